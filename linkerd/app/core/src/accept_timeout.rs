@@ -0,0 +1,161 @@
+//! Closes accepted connections on which the client sends nothing.
+//!
+//! A connection's protocol can't be detected until its client sends data, so
+//! a client that never does would otherwise hold its connection (and the
+//! state allocated to accept it) indefinitely. Connections to ports on which
+//! protocol detection is disabled are exempt, since their servers may speak
+//! first.
+
+use crate::proxy::core::Accept;
+use crate::transport::{
+    labels::Key as TransportKey, listen, metrics::CloseReason, MetricsRegistry, SkipPorts,
+};
+use futures::{try_ready, Async, Future, Poll};
+use linkerd2_error::Error;
+use std::time::Duration;
+use tokio::{clock, timer::Delay};
+use tracing::debug;
+
+#[derive(Clone, Debug)]
+pub struct AcceptTimeout<A> {
+    accept: A,
+    timeout: Option<Duration>,
+    skip_ports: SkipPorts,
+    labels: TransportKey,
+    metrics: MetricsRegistry,
+}
+
+pub enum AcceptFuture<A: Accept<listen::Connection>> {
+    // Waiting for the client to send data.
+    Idle(Option<Idle<A>>),
+    // Waiting for accept to become ready.
+    ReadyAccept(A, Option<listen::Connection>),
+    Accept(A::Future),
+}
+
+pub struct Idle<A> {
+    accept: A,
+    conn: listen::Connection,
+    timeout: Delay,
+    labels: TransportKey,
+    metrics: MetricsRegistry,
+}
+
+// === impl AcceptTimeout ===
+
+impl<A> AcceptTimeout<A> {
+    /// Closes connections on which no data is received within `timeout` of
+    /// being accepted, recording their closure under `labels`.
+    pub fn new(
+        timeout: Option<Duration>,
+        labels: TransportKey,
+        metrics: MetricsRegistry,
+        accept: A,
+    ) -> Self {
+        Self {
+            accept,
+            timeout,
+            skip_ports: SkipPorts::default(),
+            labels,
+            metrics,
+        }
+    }
+
+    pub fn with_skip_ports(self, skip_ports: SkipPorts) -> Self {
+        Self { skip_ports, ..self }
+    }
+}
+
+impl<A> tower::Service<listen::Connection> for AcceptTimeout<A>
+where
+    A: Accept<listen::Connection> + Clone,
+{
+    type Response = ();
+    type Error = Error;
+    type Future = AcceptFuture<A>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.accept.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, conn: listen::Connection) -> Self::Future {
+        let timeout = match self.timeout {
+            Some(timeout) if !self.skip_ports.contains(conn.0.target_addr().port()) => timeout,
+            _ => return AcceptFuture::Accept(self.accept.accept(conn)),
+        };
+
+        AcceptFuture::Idle(Some(Idle {
+            accept: self.accept.clone(),
+            conn,
+            timeout: Delay::new(clock::now() + timeout),
+            labels: self.labels.clone(),
+            metrics: self.metrics.clone(),
+        }))
+    }
+}
+
+// === impl Idle ===
+
+impl<A> Idle<A> {
+    /// Polls until the client sends data, returning false if the timeout
+    /// elapses first.
+    fn poll_received(&mut self) -> Async<bool> {
+        let mut byte = [0u8; 1];
+        match (self.conn.1).poll_peek(&mut byte) {
+            Ok(Async::NotReady) => {}
+            // Data, EOF, and errors are all handled by the accept stack.
+            Ok(Async::Ready(_)) | Err(_) => return Async::Ready(true),
+        }
+
+        match self.timeout.poll() {
+            Ok(Async::NotReady) => Async::NotReady,
+            // A failed timer is treated as elapsed.
+            Ok(Async::Ready(())) | Err(_) => Async::Ready(false),
+        }
+    }
+}
+
+// === impl AcceptFuture ===
+
+impl<A: Accept<listen::Connection>> Future for AcceptFuture<A> {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            *self = match self {
+                AcceptFuture::Idle(ref mut idle) => {
+                    let received = match idle
+                        .as_mut()
+                        .expect("polled after complete")
+                        .poll_received()
+                    {
+                        Async::NotReady => return Ok(Async::NotReady),
+                        Async::Ready(received) => received,
+                    };
+
+                    let Idle {
+                        accept,
+                        conn,
+                        labels,
+                        metrics,
+                        ..
+                    } = idle.take().expect("polled after complete");
+                    if !received {
+                        debug!("client sent nothing before the idle timeout; closing");
+                        let io = metrics.wrap_server_transport(labels, conn.1);
+                        io.close_reason().set(CloseReason::IdleTimeout);
+                        return Ok(Async::Ready(()));
+                    }
+                    AcceptFuture::ReadyAccept(accept, Some(conn))
+                }
+                AcceptFuture::ReadyAccept(ref mut accept, ref mut conn) => {
+                    try_ready!(accept.poll_ready().map_err(Into::into));
+                    let conn = conn.take().expect("polled after complete");
+                    AcceptFuture::Accept(accept.accept(conn))
+                }
+                AcceptFuture::Accept(ref mut fut) => return fut.poll().map_err(Into::into),
+            }
+        }
+    }
+}
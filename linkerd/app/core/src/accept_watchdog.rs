@@ -0,0 +1,325 @@
+//! Detects listeners whose accept loops have stalled.
+//!
+//! Each watched accept loop records a heartbeat whenever it polls its
+//! listener. A watchdog task checks the heartbeat periodically: if the loop
+//! has been silent for longer than `stall_timeout` while connections may be
+//! pending (i.e. it isn't waiting on its listener), or for longer than
+//! `max_silence` regardless, the loop is considered stalled. While a loop is
+//! stalled, the proxy's readiness is withheld and, if configured, its listener
+//! is rebuilt.
+//!
+//! The watchdog wakes the loop on every check, so that an idle loop beats
+//! even if no connections arrive.
+
+use super::admin::Condition;
+use super::metric_labels::Direction;
+use futures::{sync::oneshot, Async, Future, Poll, Stream};
+use linkerd2_metrics::{metrics, Counter, FmtMetrics};
+use linkerd2_proxy_core::listen::Heartbeat;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::{clock, timer::Interval};
+use tracing::{debug, info, warn};
+
+metrics! {
+    listener_accept_stall_total: Counter {
+        "Total count of times a listener's accept loop was detected to have stalled"
+    },
+    listener_accept_recover_total: Counter {
+        "Total count of attempts to rebuild a stalled listener"
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Config {
+    /// How often accept loops are checked. This should be shorter than
+    /// `max_silence`, since idle loops only beat when they're checked.
+    pub check_interval: Duration,
+    /// How long a loop may be silent while connections may be pending.
+    pub stall_timeout: Duration,
+    /// How long a loop may be silent, even if it's waiting for connections.
+    pub max_silence: Duration,
+    /// Whether stalled listeners are rebuilt.
+    pub recover: bool,
+}
+
+/// Counts stalled and recovered listeners, by direction.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    inbound: Counts,
+    outbound: Counts,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Counts(Arc<Mutex<Count>>);
+
+#[derive(Debug, Default)]
+struct Count {
+    stalls: Counter,
+    recoveries: Counter,
+}
+
+/// Watches a single accept loop.
+#[derive(Clone, Debug)]
+pub struct Watch {
+    config: Config,
+    condition: Condition,
+    counts: Counts,
+}
+
+/// Checks an accept loop's heartbeat until the loop is dropped.
+pub struct Watchdog {
+    config: Config,
+    condition: Condition,
+    counts: Counts,
+    heartbeat: Heartbeat,
+    interval: Interval,
+    started: Instant,
+    stalled: bool,
+    closed: oneshot::Receiver<()>,
+}
+
+// === impl Config ===
+
+impl Config {
+    /// Watches a loop, withholding `condition` while the loop is stalled.
+    pub fn watch(self, condition: Condition, counts: Counts) -> Watch {
+        Watch {
+            config: self,
+            condition,
+            counts,
+        }
+    }
+}
+
+// === impl Watch ===
+
+impl Watch {
+    /// Returns a watchdog for the loop that beats `heartbeat`. The watchdog
+    /// completes once the returned sender is dropped.
+    pub fn watchdog(self, heartbeat: Heartbeat) -> (oneshot::Sender<()>, Watchdog) {
+        let (tx, closed) = oneshot::channel();
+        let now = clock::now();
+        let watchdog = Watchdog {
+            interval: Interval::new(now + self.config.check_interval, self.config.check_interval),
+            config: self.config,
+            condition: self.condition,
+            counts: self.counts,
+            heartbeat,
+            started: now,
+            stalled: false,
+            closed,
+        };
+        (tx, watchdog)
+    }
+}
+
+// === impl Watchdog ===
+
+impl Watchdog {
+    fn check(&mut self) {
+        let now = clock::now();
+        // Until the loop has beaten, it's measured from when it was watched.
+        let silence = now - self.heartbeat.last_beat().unwrap_or(self.started);
+        let limit = if self.heartbeat.is_parked() {
+            self.config.max_silence
+        } else {
+            self.config.stall_timeout
+        };
+
+        if silence > limit {
+            if !self.stalled {
+                warn!(?silence, "accept loop stalled");
+                self.stalled = true;
+                self.condition.set_healthy(false);
+                self.counts.stall(self.config.recover);
+                if self.config.recover {
+                    self.heartbeat.recover();
+                }
+            }
+        } else if self.stalled {
+            info!("accept loop recovered");
+            self.stalled = false;
+            self.condition.set_healthy(true);
+        }
+
+        self.heartbeat.nudge();
+    }
+}
+
+impl Future for Watchdog {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            match self.closed.poll() {
+                Ok(Async::NotReady) => {}
+                _ => {
+                    debug!("accept loop closed");
+                    // A closed loop mustn't withhold readiness.
+                    self.condition.set_healthy(true);
+                    return Ok(Async::Ready(()));
+                }
+            }
+
+            match self.interval.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(_)) => self.check(),
+                Err(error) => {
+                    warn!(%error, "accept watchdog timer failed");
+                    return Ok(Async::Ready(()));
+                }
+            }
+        }
+    }
+}
+
+// === impl Metrics ===
+
+impl Metrics {
+    pub fn inbound(&self) -> Counts {
+        self.inbound.clone()
+    }
+
+    pub fn outbound(&self) -> Counts {
+        self.outbound.clone()
+    }
+}
+
+impl FmtMetrics for Metrics {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (inbound, outbound) = match (self.inbound.0.lock(), self.outbound.0.lock()) {
+            (Ok(i), Ok(o)) => (i, o),
+            _ => return Ok(()),
+        };
+        let scopes = [(Direction::In, &*inbound), (Direction::Out, &*outbound)];
+
+        listener_accept_stall_total.fmt_help(f)?;
+        listener_accept_stall_total
+            .fmt_scopes(f, scopes.iter().map(|(d, c)| (*d, *c)), |c| &c.stalls)?;
+
+        listener_accept_recover_total.fmt_help(f)?;
+        listener_accept_recover_total
+            .fmt_scopes(f, scopes.iter().map(|(d, c)| (*d, *c)), |c| &c.recoveries)?;
+
+        Ok(())
+    }
+}
+
+// === impl Counts ===
+
+impl Counts {
+    fn stall(&self, recover: bool) {
+        if let Ok(mut count) = self.0.lock() {
+            count.stalls.incr();
+            if recover {
+                count.recoveries.incr();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admin::Readiness;
+    use futures::future;
+    use linkerd2_error::Never;
+    use linkerd2_proxy_core::listen::Listen;
+    use std::io;
+    use tokio::runtime::current_thread::Runtime;
+    use tokio::timer::Delay;
+
+    const STALL_TIMEOUT: Duration = Duration::from_millis(50);
+
+    /// A listener that never has a connection.
+    struct Idle;
+
+    /// Accepts connections if `ready`; otherwise, it never becomes ready.
+    struct Accept {
+        ready: bool,
+    }
+
+    impl Listen for Idle {
+        type Connection = ();
+        type Error = io::Error;
+
+        fn listen_addr(&self) -> std::net::SocketAddr {
+            ([127, 0, 0, 1], 4143).into()
+        }
+
+        fn poll_accept(&mut self) -> Poll<(), io::Error> {
+            Ok(Async::NotReady)
+        }
+    }
+
+    impl tower::Service<()> for Accept {
+        type Response = ();
+        type Error = Never;
+        type Future = future::FutureResult<(), Never>;
+
+        fn poll_ready(&mut self) -> Poll<(), Never> {
+            if self.ready {
+                Ok(Async::Ready(()))
+            } else {
+                Ok(Async::NotReady)
+            }
+        }
+
+        fn call(&mut self, (): ()) -> Self::Future {
+            future::ok(())
+        }
+    }
+
+    /// Runs a watched accept loop for twice the stall timeout, returning
+    /// whether the proxy is ready afterwards.
+    fn run(accept: Accept) -> (bool, Counts) {
+        let mut rt = Runtime::new().unwrap();
+        let (ready, latch) = Readiness::new();
+        latch.release();
+        let counts = Counts::default();
+        let config = Config {
+            check_interval: Duration::from_millis(10),
+            stall_timeout: STALL_TIMEOUT,
+            max_silence: STALL_TIMEOUT * 4,
+            recover: false,
+        };
+
+        let is_ready = rt
+            .block_on(future::lazy(|| {
+                let heartbeat = Heartbeat::default();
+                let (closed, watchdog) = config
+                    .watch(ready.condition(), counts.clone())
+                    .watchdog(heartbeat.clone());
+                tokio::spawn(watchdog);
+                let serve = Idle.serve(accept).with_heartbeat(heartbeat);
+                tokio::spawn(serve.map(|n| match n {}).map_err(|_| ()));
+
+                Delay::new(clock::now() + STALL_TIMEOUT * 2).map(move |()| {
+                    let is_ready = ready.is_ready();
+                    drop(closed);
+                    is_ready
+                })
+            }))
+            .unwrap();
+        (is_ready, counts)
+    }
+
+    #[test]
+    fn stalled_accept_loops_withhold_readiness() {
+        let (is_ready, counts) = run(Accept { ready: false });
+        assert!(!is_ready, "a stalled loop must withhold readiness");
+        let stalls: u64 = counts.0.lock().unwrap().stalls.into();
+        assert_eq!(stalls, 1);
+    }
+
+    #[test]
+    fn idle_accept_loops_are_not_stalled() {
+        let (is_ready, counts) = run(Accept { ready: true });
+        assert!(is_ready, "an idle loop must not withhold readiness");
+        let stalls: u64 = counts.0.lock().unwrap().stalls.into();
+        assert_eq!(stalls, 0);
+    }
+}
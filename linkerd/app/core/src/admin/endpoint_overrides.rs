@@ -0,0 +1,111 @@
+//! Serves `PUT /dst/<authority>/endpoints/<addr>/state`, which overrides the
+//! state of one of an outbound destination's endpoints.
+//!
+//! The request body is a JSON object like `{"state": "draining"}` or
+//! `{"state": "active"}`. Draining endpoints receive no new requests until
+//! they are marked active again or are removed by discovery. Only endpoints
+//! that discovery currently returns for the destination may be drained.
+//!
+//! A draining endpoint is held unready in its balancer: requests that are
+//! already in flight to it are allowed to complete, and its connections are
+//! kept so that it may serve again as soon as it is marked active.
+
+use super::{rsp, ClientAddr, ResponseFuture};
+use crate::proxy::discover::overrides::{Overrides, SetError, State};
+use futures::{future, Future, Stream};
+use http::{Method, StatusCode};
+use hyper::{Body, Request, Response};
+use std::io;
+use std::net::SocketAddr;
+use tracing::{error, info, warn};
+
+pub(super) fn serve(overrides: Overrides, req: Request<Body>) -> ResponseFuture {
+    // Endpoint overrides can only be set from loopback IPs.
+    match req.extensions().get::<ClientAddr>() {
+        Some(addr) if addr.addr().ip().is_loopback() => {}
+        Some(addr) => {
+            let addr = addr.addr();
+            warn!(message = "denying request from non-loopback IP", %addr);
+            return Box::new(future::ok(rsp(
+                StatusCode::FORBIDDEN,
+                "access to /dst only allowed from loopback interface",
+            )));
+        }
+        None => {
+            error!(message = "ClientAddr extension should always be set");
+            return Box::new(future::ok(rsp(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Body::empty(),
+            )));
+        }
+    }
+
+    let (target, addr) = match parse_path(req.uri().path()) {
+        Some((target, addr)) => (target.to_owned(), addr),
+        None => return Box::new(future::ok(rsp(StatusCode::NOT_FOUND, Body::empty()))),
+    };
+
+    if req.method() != Method::PUT {
+        return Box::new(future::ok(
+            Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .header("allow", "PUT")
+                .body(Body::empty())
+                .expect("builder with known status code must not fail"),
+        ));
+    }
+
+    let f = req
+        .into_body()
+        .concat2()
+        .map(move |chunk| {
+            let state = match parse_state(&chunk) {
+                Ok(state) => state,
+                Err(error) => return rsp(StatusCode::BAD_REQUEST, error),
+            };
+            match overrides.set(&target, addr, state) {
+                Ok(()) => {
+                    info!(%target, %addr, ?state, "overrode endpoint state");
+                    rsp(StatusCode::NO_CONTENT, Body::empty())
+                }
+                Err(error) => {
+                    warn!(%target, %addr, %error, "failed to override endpoint state");
+                    let status = match error {
+                        SetError::NotDiscovered => StatusCode::NOT_FOUND,
+                        SetError::CapacityExceeded(_) => StatusCode::INSUFFICIENT_STORAGE,
+                    };
+                    rsp(status, format!("{}\n", error))
+                }
+            }
+        })
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e));
+    Box::new(f)
+}
+
+fn parse_path(path: &str) -> Option<(&str, SocketAddr)> {
+    let mut parts = path.trim_start_matches("/dst/").split('/');
+    match (
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+    ) {
+        (Some(target), Some("endpoints"), Some(addr), Some("state"), None)
+            if !target.is_empty() =>
+        {
+            addr.parse().ok().map(|addr| (target, addr))
+        }
+        _ => None,
+    }
+}
+
+fn parse_state(body: &[u8]) -> Result<State, String> {
+    let json: serde_json::Value =
+        serde_json::from_slice(body).map_err(|e| format!("invalid JSON: {}\n", e))?;
+    match json.get("state").and_then(|s| s.as_str()) {
+        Some("draining") => Ok(State::Draining),
+        Some("active") => Ok(State::Active),
+        _ => Err("expected {\"state\": \"draining\"} or {\"state\": \"active\"}\n".into()),
+    }
+}
@@ -0,0 +1,77 @@
+//! Serves `GET /events?target=<authority>`, which reports the state
+//! transitions recently recorded for a target as JSON.
+
+use super::{rsp, ResponseFuture};
+use crate::{events::Events, Addr};
+use futures::future;
+use http::{Method, StatusCode};
+use hyper::{Body, Request, Response};
+
+pub(super) fn serve(events: &Events, req: Request<Body>) -> ResponseFuture {
+    if req.method() != Method::GET {
+        return Box::new(future::ok(
+            Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .header("allow", "GET")
+                .body(Body::empty())
+                .expect("builder with known status code must not fail"),
+        ));
+    }
+
+    let target = match req.uri().query().and_then(parse_target) {
+        Some(target) => target,
+        None => {
+            return Box::new(future::ok(rsp(
+                StatusCode::BAD_REQUEST,
+                "a target authority must be specified, e.g. ?target=web.ns.svc.cluster.local:8080\n",
+            )));
+        }
+    };
+
+    let rsp = match events.to_json(&target) {
+        Some(json) => Response::builder()
+            .status(StatusCode::OK)
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(format!("{}\n", json).into())
+            .expect("builder with known status code must not fail"),
+        None => rsp(
+            StatusCode::NOT_FOUND,
+            format!("no events recorded for {}\n", target),
+        ),
+    };
+    Box::new(future::ok(rsp))
+}
+
+/// Parses the `target` query parameter, in which the port separator may be
+/// percent-encoded.
+fn parse_target(query: &str) -> Option<Addr> {
+    let target = query.split('&').find_map(|param| {
+        let mut kv = param.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some("target"), Some(target)) => Some(target),
+            _ => None,
+        }
+    })?;
+    let target = target.replace("%3A", ":").replace("%3a", ":");
+    Addr::from_str(&target).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_target() {
+        let web = Addr::from_str("web.ns.svc.cluster.local:8080").unwrap();
+        assert_eq!(
+            parse_target("target=web.ns.svc.cluster.local:8080"),
+            Some(web.clone())
+        );
+        assert_eq!(
+            parse_target("pretty=1&target=web.ns.svc.cluster.local%3A8080"),
+            Some(web)
+        );
+        assert_eq!(parse_target("target=web.ns.svc.cluster.local"), None);
+        assert_eq!(parse_target("dst=web.ns.svc.cluster.local:8080"), None);
+    }
+}
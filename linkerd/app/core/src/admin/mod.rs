@@ -1,7 +1,16 @@
 //! Serves an HTTP/1.1. admin server.
 //!
 //! * `/metrics` -- reports prometheus-formatted metrics.
+//! * `/metrics/summary` -- reports prometheus-formatted metrics with reduced
+//!   label sets, e.g. for long-term storage.
 //! * `/ready` -- returns 200 when the proxy is ready to participate in meshed traffic.
+//! * `/debug/stack` -- reports a JSON snapshot of the proxy's live stack state.
+//! * `/dst/<authority>/endpoints/<addr>/state` -- marks an outbound endpoint as
+//!   draining or active.
+//! * `/skip-ports/<proxy>[/<port>]` -- lists or updates the ports for which a
+//!   proxy skips protocol detection.
+//! * `/events?target=<authority>` -- reports a JSON history of a target's
+//!   recent state transitions.
 
 use crate::{svc, transport::tls::accept::Connection};
 use futures::{future, Future, Poll};
@@ -11,21 +20,28 @@ use hyper::{Body, Request, Response};
 use linkerd2_metrics::{self as metrics, FmtMetrics};
 use std::io;
 
+mod endpoint_overrides;
+mod events;
 mod readiness;
+mod skip_ports;
+mod stack_state;
 mod trace_level;
 
-pub use self::readiness::{Latch, Readiness};
+pub use self::readiness::{Condition, Latch, Readiness};
+pub use self::stack_state::StackState;
 use self::trace_level::TraceLevel;
 
 #[derive(Debug, Clone)]
-pub struct Admin<M: FmtMetrics> {
+pub struct Admin<M: FmtMetrics, S: FmtMetrics = ()> {
     metrics: metrics::Serve<M>,
+    summary: metrics::Serve<S>,
     trace_level: TraceLevel,
     ready: Readiness,
+    stack_state: StackState,
 }
 
 #[derive(Debug, Clone)]
-pub struct Accept<M: FmtMetrics>(Admin<M>, hyper::server::conn::Http);
+pub struct Accept<M: FmtMetrics, S: FmtMetrics = ()>(Admin<M, S>, hyper::server::conn::Http);
 
 #[derive(Clone, Debug)]
 pub struct ClientAddr(std::net::SocketAddr);
@@ -37,12 +53,34 @@ impl<M: FmtMetrics> Admin<M> {
     pub fn new(m: M, ready: Readiness, trace_level: TraceLevel) -> Self {
         Self {
             metrics: metrics::Serve::new(m),
+            summary: metrics::Serve::new(()),
             trace_level,
             ready,
+            stack_state: StackState::default(),
         }
     }
 
-    pub fn into_accept(self) -> Accept<M> {
+    /// Serves `summary` on `/metrics/summary`.
+    pub fn with_summary<S: FmtMetrics>(self, summary: S) -> Admin<M, S> {
+        Admin {
+            metrics: self.metrics,
+            summary: metrics::Serve::new(summary),
+            trace_level: self.trace_level,
+            ready: self.ready,
+            stack_state: self.stack_state,
+        }
+    }
+}
+
+impl<M: FmtMetrics, S: FmtMetrics> Admin<M, S> {
+    pub fn with_stack_state(self, stack_state: StackState) -> Self {
+        Self {
+            stack_state,
+            ..self
+        }
+    }
+
+    pub fn into_accept(self) -> Accept<M, S> {
         Accept(self, hyper::server::conn::Http::new())
     }
 
@@ -59,9 +97,17 @@ impl<M: FmtMetrics> Admin<M> {
                 .expect("builder with known status code must not fail")
         }
     }
+
+    fn stack_state_rsp(&self) -> Response<Body> {
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(format!("{}\n", self.stack_state.to_json()).into())
+            .expect("builder with known status code must not fail")
+    }
 }
 
-impl<M: FmtMetrics> Service for Admin<M> {
+impl<M: FmtMetrics, S: FmtMetrics> Service for Admin<M, S> {
     type ReqBody = Body;
     type ResBody = Body;
     type Error = io::Error;
@@ -70,14 +116,25 @@ impl<M: FmtMetrics> Service for Admin<M> {
     fn call(&mut self, req: Request<Body>) -> Self::Future {
         match req.uri().path() {
             "/metrics" => Box::new(self.metrics.call(req)),
+            "/metrics/summary" => Box::new(self.summary.call(req)),
             "/proxy-log-level" => self.trace_level.call(req),
             "/ready" => Box::new(future::ok(self.ready_rsp())),
+            "/debug/stack" => Box::new(future::ok(self.stack_state_rsp())),
+            path if path.starts_with("/dst/") => {
+                endpoint_overrides::serve(self.stack_state.endpoint_overrides(), req)
+            }
+            path if path.starts_with("/skip-ports/") => skip_ports::serve(&self.stack_state, req),
+            "/events" => events::serve(&self.stack_state.events(), req),
             _ => Box::new(future::ok(rsp(StatusCode::NOT_FOUND, Body::empty()))),
         }
     }
 }
 
-impl<M: FmtMetrics + Clone + Send + 'static> svc::Service<Connection> for Accept<M> {
+impl<M, S> svc::Service<Connection> for Accept<M, S>
+where
+    M: FmtMetrics + Clone + Send + 'static,
+    S: FmtMetrics + Clone + Send + 'static,
+{
     type Response = ();
     type Error = hyper::error::Error;
     type Future = Box<dyn Future<Item = Self::Response, Error = Self::Error> + Send + 'static>;
@@ -87,9 +144,9 @@ impl<M: FmtMetrics + Clone + Send + 'static> svc::Service<Connection> for Accept
     }
 
     fn call(&mut self, (meta, io): Connection) -> Self::Future {
-        // Since the `/proxy-log-level` controls access based on the
-        // client's IP address, we wrap the service with a new service
-        // that adds the remote IP as a request extension.
+        // Since `/proxy-log-level`, `/dst`, and `/skip-ports` control access
+        // based on the client's IP address, we wrap the service with a new
+        // service that adds the remote IP as a request extension.
         let peer = meta.addrs.peer();
         let mut svc = self.0.clone();
         let svc = service_fn(move |mut req| {
@@ -116,6 +173,7 @@ fn rsp(status: StatusCode, body: impl Into<Body>) -> Response<Body> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use futures::Stream;
     use http::method::Method;
     use linkerd2_test_util::BlockOnFor;
     use std::time::Duration;
@@ -150,4 +208,120 @@ mod tests {
         drop(l1);
         assert_eq!(call!().status(), StatusCode::OK);
     }
+
+    #[test]
+    fn stack_state_is_json() {
+        let (r, _l) = Readiness::new();
+        let state = StackState::default();
+        state.register_connections("outbound", || 2);
+
+        let mut rt = Runtime::new().unwrap();
+        let mut srv = Admin::new((), r, TraceLevel::dangling()).with_stack_state(state);
+        let req = Request::builder()
+            .method(Method::GET)
+            .uri("http://4.3.2.1:5678/debug/stack")
+            .body(Body::empty())
+            .unwrap();
+        let rsp = rt.block_on_for(TIMEOUT, srv.call(req)).expect("call");
+        assert_eq!(rsp.status(), StatusCode::OK);
+        assert_eq!(
+            rsp.headers().get(http::header::CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+
+        let body = rt
+            .block_on_for(TIMEOUT, rsp.into_body().concat2())
+            .expect("body");
+        let json: serde_json::Value = serde_json::from_slice(&body).expect("body must be JSON");
+        assert_eq!(json["connections"]["outbound"]["open"], 2);
+    }
+
+    #[test]
+    fn events_are_served_for_target() {
+        use crate::{events::Kind, Addr};
+
+        let (r, _l) = Readiness::new();
+        let state = StackState::default();
+        let target = Addr::from_str("web.ns.svc.cluster.local:8080").unwrap();
+        state.events().record(&target, Kind::BalancerCreated);
+        state.events().record(&target, Kind::EndpointsAdded(2));
+
+        let mut rt = Runtime::new().unwrap();
+        let mut srv = Admin::new((), r, TraceLevel::dangling()).with_stack_state(state);
+        let mut get = |query: &str| {
+            let req = Request::builder()
+                .method(Method::GET)
+                .uri(format!("http://4.3.2.1:5678/events?{}", query))
+                .body(Body::empty())
+                .unwrap();
+            let rsp = rt.block_on_for(TIMEOUT, srv.call(req)).expect("call");
+            let status = rsp.status();
+            let body = rt
+                .block_on_for(TIMEOUT, rsp.into_body().concat2())
+                .expect("body");
+            (status, body)
+        };
+
+        let (status, body) = get("target=web.ns.svc.cluster.local:8080");
+        assert_eq!(status, StatusCode::OK);
+        let json: serde_json::Value = serde_json::from_slice(&body).expect("body must be JSON");
+        assert_eq!(json["target"], "web.ns.svc.cluster.local:8080");
+        assert_eq!(json["events"][0]["event"], "balancer_created");
+        assert_eq!(json["events"][1]["event"], "endpoints_added");
+        assert_eq!(json["events"][1]["count"], 2);
+
+        let (status, _) = get("target=books.ns.svc.cluster.local:8080");
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = get("dst=web.ns.svc.cluster.local:8080");
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn endpoints_may_be_drained_from_loopback() {
+        let (r, _l) = Readiness::new();
+        let state = StackState::default();
+        let overrides = state.endpoint_overrides();
+
+        let mut rt = Runtime::new().unwrap();
+        let mut srv = Admin::new((), r, TraceLevel::dangling()).with_stack_state(state);
+        let mut put = |client: [u8; 4], state: &str| {
+            let uri = "/dst/web.ns.svc.cluster.local:8080/endpoints/10.1.1.1:8080/state";
+            let mut req = Request::builder()
+                .method(Method::PUT)
+                .uri(format!("http://4.3.2.1:5678{}", uri))
+                .body(Body::from(format!("{{\"state\": \"{}\"}}", state)))
+                .unwrap();
+            let client = ClientAddr((client, 40000).into());
+            req.extensions_mut().insert(client);
+            let rsp = rt.block_on_for(TIMEOUT, srv.call(req)).expect("call");
+            rsp.status()
+        };
+
+        assert_eq!(put([10, 1, 1, 2], "draining"), StatusCode::FORBIDDEN);
+        assert!(overrides.snapshot().is_empty());
+
+        assert_eq!(
+            put([127, 0, 0, 1], "draining"),
+            StatusCode::NOT_FOUND,
+            "undiscovered endpoints must not be drained"
+        );
+        assert!(overrides.snapshot().is_empty());
+
+        let _discovered = overrides.discover(
+            "web.ns.svc.cluster.local:8080",
+            ([10, 1, 1, 1], 8080).into(),
+        );
+        assert_eq!(put([127, 0, 0, 1], "draining"), StatusCode::NO_CONTENT);
+        assert_eq!(
+            overrides.snapshot(),
+            vec![(
+                "web.ns.svc.cluster.local:8080".to_owned(),
+                ([10, 1, 1, 1], 8080).into()
+            )]
+        );
+
+        assert_eq!(put([127, 0, 0, 1], "sleeping"), StatusCode::BAD_REQUEST);
+        assert_eq!(put([127, 0, 0, 1], "active"), StatusCode::NO_CONTENT);
+        assert!(overrides.snapshot().is_empty());
+    }
 }
@@ -1,23 +1,52 @@
-use std::sync::{Arc, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
 
 /// Tracks the processes's readiness to serve traffic.
 ///
-/// Once `is_ready()` returns true, it will never return false.
+/// Once all latches are released, `is_ready()` only returns false while a
+/// `Condition` is unhealthy.
 #[derive(Clone, Debug)]
-pub struct Readiness(Weak<()>);
+pub struct Readiness {
+    latch: Weak<()>,
+    conditions: Arc<Mutex<Vec<Condition>>>,
+}
 
 /// When all latches are dropped, the process is considered ready.
 #[derive(Clone, Debug)]
 pub struct Latch(Arc<()>);
 
+/// Withholds readiness while it's unhealthy. Unlike a `Latch`, a condition
+/// may become unhealthy again after it has recovered.
+#[derive(Clone, Debug)]
+pub struct Condition(Arc<AtomicBool>);
+
 impl Readiness {
     pub fn new() -> (Readiness, Latch) {
         let r = Arc::new(());
-        (Readiness(Arc::downgrade(&r)), Latch(r))
+        let ready = Readiness {
+            latch: Arc::downgrade(&r),
+            conditions: Arc::new(Mutex::new(Vec::new())),
+        };
+        (ready, Latch(r))
+    }
+
+    /// Returns a new, healthy condition for this readiness.
+    pub fn condition(&self) -> Condition {
+        let condition = Condition(Arc::new(AtomicBool::new(true)));
+        if let Ok(mut conditions) = self.conditions.lock() {
+            conditions.push(condition.clone());
+        }
+        condition
     }
 
     pub fn is_ready(&self) -> bool {
-        self.0.upgrade().is_none()
+        if self.latch.upgrade().is_some() {
+            return false;
+        }
+        match self.conditions.lock() {
+            Ok(conditions) => conditions.iter().all(Condition::is_healthy),
+            Err(_) => false,
+        }
     }
 }
 
@@ -34,3 +63,13 @@ impl Latch {
         drop(self);
     }
 }
+
+impl Condition {
+    pub fn set_healthy(&self, healthy: bool) {
+        self.0.store(healthy, Ordering::Release);
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
@@ -0,0 +1,136 @@
+//! Serves `/skip-ports/<proxy>` and `/skip-ports/<proxy>/<port>`, which
+//! update the ports for which the `inbound` or `outbound` proxy skips
+//! protocol detection.
+//!
+//! `GET /skip-ports/<proxy>` lists the ports. `PUT /skip-ports/<proxy>/<port>`
+//! disables protocol detection for a port and `DELETE` enables it again.
+//! Updates apply only to connections accepted after they are made.
+
+use super::{rsp, ClientAddr, ResponseFuture, StackState};
+use futures::future;
+use http::{Method, StatusCode};
+use hyper::{Body, Request, Response};
+use tracing::{error, info, warn};
+
+pub(super) fn serve(stack_state: &StackState, req: Request<Body>) -> ResponseFuture {
+    Box::new(future::ok(respond(stack_state, req)))
+}
+
+fn respond(stack_state: &StackState, req: Request<Body>) -> Response<Body> {
+    // Skip ports can only be updated from loopback IPs.
+    match req.extensions().get::<ClientAddr>() {
+        Some(addr) if addr.addr().ip().is_loopback() => {}
+        Some(addr) => {
+            let addr = addr.addr();
+            warn!(message = "denying request from non-loopback IP", %addr);
+            return rsp(
+                StatusCode::FORBIDDEN,
+                "access to /skip-ports only allowed from loopback interface",
+            );
+        }
+        None => {
+            error!(message = "ClientAddr extension should always be set");
+            return rsp(StatusCode::INTERNAL_SERVER_ERROR, Body::empty());
+        }
+    }
+
+    let (proxy, port) = match parse_path(req.uri().path()) {
+        Some(parts) => parts,
+        None => return rsp(StatusCode::NOT_FOUND, Body::empty()),
+    };
+    let skip_ports = match stack_state.skip_ports(proxy) {
+        Some(skip_ports) => skip_ports,
+        None => return rsp(StatusCode::NOT_FOUND, Body::empty()),
+    };
+
+    match (req.method(), port) {
+        (&Method::GET, None) => {
+            let ports = skip_ports
+                .ports()
+                .iter()
+                .map(|p| p.to_string())
+                .collect::<Vec<_>>();
+            rsp(StatusCode::OK, format!("{}\n", ports.join(",")))
+        }
+        (&Method::PUT, Some(port)) => {
+            if skip_ports.insert(port) {
+                info!(%proxy, %port, "disabled protocol detection");
+            }
+            rsp(StatusCode::NO_CONTENT, Body::empty())
+        }
+        (&Method::DELETE, Some(port)) => {
+            if skip_ports.remove(port) {
+                info!(%proxy, %port, "enabled protocol detection");
+            }
+            rsp(StatusCode::NO_CONTENT, Body::empty())
+        }
+        (_, port) => Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .header("allow", if port.is_some() { "PUT, DELETE" } else { "GET" })
+            .body(Body::empty())
+            .expect("builder with known status code must not fail"),
+    }
+}
+
+fn parse_path(path: &str) -> Option<(&str, Option<u16>)> {
+    let mut parts = path.trim_start_matches("/skip-ports/").split('/');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(proxy), None, None) if !proxy.is_empty() => Some((proxy, None)),
+        (Some(proxy), Some(port), None) if !proxy.is_empty() => {
+            port.parse().ok().map(|port| (proxy, Some(port)))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::SkipPorts;
+
+    fn req(method: Method, path: &str) -> Request<Body> {
+        let mut req = Request::builder()
+            .method(method)
+            .uri(path)
+            .body(Body::empty())
+            .unwrap();
+        req.extensions_mut()
+            .insert(ClientAddr(([127, 0, 0, 1], 4191).into()));
+        req
+    }
+
+    #[test]
+    fn updates_registered_ports() {
+        let state = StackState::default();
+        let ports = SkipPorts::default();
+        state.register_skip_ports("inbound", ports.clone());
+
+        let rsp = respond(&state, req(Method::PUT, "/skip-ports/inbound/5432"));
+        assert_eq!(rsp.status(), StatusCode::NO_CONTENT);
+        assert!(ports.contains(5432));
+
+        let rsp = respond(&state, req(Method::DELETE, "/skip-ports/inbound/5432"));
+        assert_eq!(rsp.status(), StatusCode::NO_CONTENT);
+        assert!(!ports.contains(5432));
+
+        let rsp = respond(&state, req(Method::PUT, "/skip-ports/outbound/5432"));
+        assert_eq!(rsp.status(), StatusCode::NOT_FOUND);
+
+        let rsp = respond(&state, req(Method::PUT, "/skip-ports/inbound/http"));
+        assert_eq!(rsp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn denies_non_loopback_clients() {
+        let state = StackState::default();
+        let ports = SkipPorts::default();
+        state.register_skip_ports("inbound", ports.clone());
+
+        let mut req = req(Method::PUT, "/skip-ports/inbound/5432");
+        req.extensions_mut()
+            .insert(ClientAddr(([10, 1, 1, 1], 4191).into()));
+        let rsp = respond(&state, req);
+        assert_eq!(rsp.status(), StatusCode::FORBIDDEN);
+        assert!(!ports.contains(5432));
+    }
+}
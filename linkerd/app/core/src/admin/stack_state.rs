@@ -0,0 +1,298 @@
+//! A read-only view of the proxy's live stack state, used for debugging.
+//!
+//! Stacks register read-only handles to their caches, balancers, and
+//! connection counts as they are built. The admin server renders a snapshot
+//! of these handles as JSON when `/debug/stack` is requested, along with any
+//! endpoints that have been overridden as draining.
+//!
+//! Each proxy also registers the ports for which it skips protocol detection,
+//! so that the admin server may update them.
+//!
+//! Stacks also record each target's state transitions, which the admin server
+//! serves on `/events`.
+
+use crate::{events::Events, profiles, proxy::discover, router, transport::SkipPorts};
+use indexmap::IndexMap;
+use serde_json::{json, Map, Value};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tokio_timer::clock;
+
+#[derive(Clone, Default)]
+pub struct StackState {
+    inner: Arc<Mutex<Inner>>,
+    profiles: profiles::Updates,
+    endpoint_overrides: discover::Overrides,
+    events: Events,
+}
+
+#[derive(Default)]
+struct Inner {
+    caches: IndexMap<&'static str, router::CacheSize>,
+    balancers: IndexMap<&'static str, discover::Endpoints>,
+    connections: IndexMap<&'static str, Box<dyn Fn() -> u64 + Send>>,
+    skip_ports: IndexMap<&'static str, SkipPorts>,
+}
+
+// === impl StackState ===
+
+impl StackState {
+    /// Registers a router's cache under `name`.
+    pub fn register_cache(&self, name: &'static str, size: router::CacheSize) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.caches.insert(name, size);
+        }
+    }
+
+    /// Registers the endpoints discovered by a balancer stack under `name`.
+    pub fn register_balancers(&self, name: &'static str, endpoints: discover::Endpoints) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.balancers.insert(name, endpoints);
+        }
+    }
+
+    /// Registers a function that counts the currently-open connections for
+    /// `name`.
+    pub fn register_connections<F>(&self, name: &'static str, open: F)
+    where
+        F: Fn() -> u64 + Send + 'static,
+    {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.connections.insert(name, Box::new(open));
+        }
+    }
+
+    /// Registers the ports for which the `name` proxy skips protocol
+    /// detection.
+    pub fn register_skip_ports(&self, name: &'static str, ports: SkipPorts) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.skip_ports.insert(name, ports);
+        }
+    }
+
+    /// Returns the ports for which the `name` proxy skips protocol detection.
+    pub(crate) fn skip_ports(&self, name: &str) -> Option<SkipPorts> {
+        self.inner
+            .lock()
+            .ok()
+            .and_then(|inner| inner.skip_ports.get(name).cloned())
+    }
+
+    /// Returns a handle that should be used to record profile updates.
+    pub fn profile_updates(&self) -> profiles::Updates {
+        self.profiles.clone()
+    }
+
+    /// Returns the registry of endpoint overrides that is managed through the
+    /// admin server.
+    pub fn endpoint_overrides(&self) -> discover::Overrides {
+        self.endpoint_overrides.clone()
+    }
+
+    /// Returns the registry in which stacks record their targets' state
+    /// transitions.
+    pub fn events(&self) -> Events {
+        self.events.clone()
+    }
+
+    /// Returns the number of services held by each registered cache.
+    pub(crate) fn cache_sizes(&self) -> Vec<(&'static str, usize)> {
+        match self.inner.lock() {
+            Ok(inner) => inner
+                .caches
+                .iter()
+                .map(|(name, size)| (*name, size.get()))
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Returns the number of resolved targets and active endpoints held by
+    /// each registered balancer stack.
+    pub(crate) fn resolution_counts(&self) -> Vec<(&'static str, (usize, usize))> {
+        match self.inner.lock() {
+            Ok(inner) => inner
+                .balancers
+                .iter()
+                .map(|(name, endpoints)| (*name, endpoints.counts()))
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let inner = self.inner.lock().expect("stack state poisoned");
+
+        let caches = inner
+            .caches
+            .iter()
+            .map(|(name, size)| (name.to_string(), json!({ "size": size.get() })))
+            .collect::<Map<_, _>>();
+
+        let balancers = inner
+            .balancers
+            .iter()
+            .map(|(name, endpoints)| {
+                let targets = endpoints
+                    .snapshot()
+                    .into_iter()
+                    .map(|(target, addrs)| {
+                        let addrs = addrs.into_iter().map(|a| a.to_string()).collect::<Vec<_>>();
+                        (target, json!({ "endpoints": addrs }))
+                    })
+                    .collect::<Map<_, _>>();
+                (name.to_string(), Value::Object(targets))
+            })
+            .collect::<Map<_, _>>();
+
+        let connections = inner
+            .connections
+            .iter()
+            .map(|(name, open)| (name.to_string(), json!({ "open": open() })))
+            .collect::<Map<_, _>>();
+
+        let skip_ports = inner
+            .skip_ports
+            .iter()
+            .map(|(name, ports)| (name.to_string(), json!(ports.ports())))
+            .collect::<Map<_, _>>();
+
+        let now = clock::now();
+        let profiles = self
+            .profiles
+            .snapshot()
+            .into_iter()
+            .map(|(dst, version)| {
+                let age_ms = version.map(|v| {
+                    let age = now - v.updated_at;
+                    age.as_secs() * 1_000 + u64::from(age.subsec_millis())
+                });
+                let hash = version.map(|v| format!("{:016x}", v.hash));
+                let profile = json!({ "last_update_age_ms": age_ms, "hash": hash });
+                (dst.to_string(), profile)
+            })
+            .collect::<Map<_, _>>();
+
+        let mut draining = Map::new();
+        for (target, addr) in self.endpoint_overrides.snapshot() {
+            if let Value::Array(addrs) = draining.entry(target).or_insert_with(|| json!([])) {
+                addrs.push(json!(addr.to_string()));
+            }
+        }
+
+        json!({
+            "caches": caches,
+            "balancers": balancers,
+            "connections": connections,
+            "skip_ports": skip_ports,
+            "profiles": profiles,
+            "draining": draining,
+        })
+    }
+}
+
+impl fmt::Debug for StackState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StackState").finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::core::resolve::{Resolution, Update};
+    use crate::{Error, NameAddr};
+    use futures::{future, Async, Future, Poll};
+    use std::collections::VecDeque;
+    use std::net::SocketAddr;
+    use tower::discover::Discover;
+
+    struct MockResolve(Option<MockResolution>);
+
+    struct MockResolution(VecDeque<Update<()>>);
+
+    impl tower::Service<&'static str> for MockResolve {
+        type Response = MockResolution;
+        type Error = Error;
+        type Future = future::FutureResult<MockResolution, Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: &'static str) -> Self::Future {
+            future::ok(self.0.take().expect("resolved more than once"))
+        }
+    }
+
+    impl Resolution for MockResolution {
+        type Endpoint = ();
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<Update<()>, Self::Error> {
+            Ok(self
+                .0
+                .pop_front()
+                .map(Async::Ready)
+                .unwrap_or(Async::NotReady))
+        }
+    }
+
+    #[test]
+    fn dump_includes_registered_state() {
+        let state = StackState::default();
+        state.register_cache("outbound.dst", router::CacheSize::default());
+        state.register_connections("inbound", || 3);
+        state.register_skip_ports(
+            "inbound",
+            SkipPorts::new(vec![25, 3306].into_iter().collect()),
+        );
+
+        let endpoints = discover::Endpoints::default();
+        state.register_balancers("outbound", endpoints.clone());
+
+        let addr0 = SocketAddr::from(([10, 1, 1, 1], 8080));
+        let addr1 = SocketAddr::from(([10, 1, 1, 2], 8080));
+        let resolution = MockResolution(vec![Update::Add(vec![(addr0, ()), (addr1, ())])].into());
+        let mut make_discover =
+            discover::from_resolve::FromResolve::new(MockResolve(Some(resolution)))
+                .with_endpoints(Some(endpoints));
+
+        let dst = NameAddr::from_str("web.example.com:8080").unwrap();
+        let updates = state.profile_updates();
+        updates.watch(&dst);
+        updates.record(&dst, 0xfeed);
+
+        let json = future::lazy(move || {
+            let mut discover = tower::Service::call(&mut make_discover, "web.example.com:8080")
+                .wait()
+                .expect("resolution must succeed");
+            while let Async::Ready(_) = discover.poll().expect("discover must not fail") {}
+
+            let json = state.to_json();
+            drop(discover);
+            Ok::<_, ()>((json, state.to_json()))
+        });
+        let (json, after_drop) = json.wait().unwrap();
+
+        assert_eq!(json["caches"]["outbound.dst"]["size"], 0);
+        assert_eq!(json["connections"]["inbound"]["open"], 3);
+        assert_eq!(json["skip_ports"]["inbound"], json!([25, 3306]));
+        assert_eq!(
+            json["balancers"]["outbound"]["web.example.com:8080"]["endpoints"],
+            json!(["10.1.1.1:8080", "10.1.1.2:8080"])
+        );
+        assert!(json["profiles"]["web.example.com:8080"]["last_update_age_ms"].is_u64());
+        assert_eq!(
+            json["profiles"]["web.example.com:8080"]["hash"],
+            "000000000000feed"
+        );
+
+        assert!(
+            after_drop["balancers"]["outbound"]
+                .get("web.example.com:8080")
+                .is_none(),
+            "balancer endpoints must be forgotten when discovery is dropped"
+        );
+    }
+}
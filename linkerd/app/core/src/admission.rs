@@ -0,0 +1,881 @@
+//! Limits the number of requests in flight across all of a proxy's
+//! connections, failing requests immediately once the limit is reached.
+//!
+//! `tower::limit::ConcurrencyLimit` acquires its permit when the service
+//! becomes ready. Because each connection holds its own clone of the admission
+//! stack, and connections poll for readiness before reading a request, every
+//! idle connection would hold a permit until it closes. Here, a permit is
+//! only taken when a request is dispatched and is owned by the request's
+//! response future, so it is released exactly once: when the response
+//! completes, fails, or is dropped.
+//!
+//! A limit is either static or adaptive. An adaptive limit is adjusted by a
+//! gradient controller that compares the latency of recent responses to a
+//! baseline: the latency of requests that were admitted while few requests
+//! were in flight. While latency stays within `tolerance` of the baseline, the
+//! limit grows by one per window of responses; once latency exceeds it, the
+//! limit is decreased in proportion to the excess.
+//!
+//! Independently of its concurrency, a limit may also shed requests while
+//! its responses are slow (a "brownout"). An exponentially-weighted moving
+//! average of response latency is maintained, in which each sample's weight
+//! decays with the time since it was recorded; while the average exceeds a
+//! threshold, new requests are shed. Because shed requests record no latency,
+//! the average decays while requests are being shed, so that admission resumes
+//! and the application's latency is measured again.
+
+use super::metric_labels::Direction;
+use futures::{Async, Future, Poll};
+use linkerd2_error::Error;
+use linkerd2_metrics::{metrics, FmtLabels, FmtMetric, FmtMetrics, Gauge};
+use std::fmt;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+use std::time::{Duration, Instant};
+use tokio::clock;
+use tracing::debug;
+
+metrics! {
+    request_concurrency_limit: Gauge {
+        "The number of requests that an adaptive limit currently admits concurrently"
+    },
+    request_concurrency_gradient: Ratio {
+        "The ratio of the tolerated latency to the latency observed by an adaptive limit, between 0.5 and 1"
+    }
+}
+
+/// How far latency may rise above its baseline before an adaptive limit is
+/// decreased, as a multiple of the baseline.
+pub const DEFAULT_ADAPTIVE_TOLERANCE: f64 = 1.5;
+
+/// The time over which a brownout's latency samples lose most of their
+/// weight.
+pub const DEFAULT_BROWNOUT_DECAY: Duration = Duration::from_secs(10);
+
+/// The fewest responses in each of an adaptive limit's windows.
+const MIN_WINDOW_SAMPLES: usize = 10;
+
+/// Bounds how much an adaptive limit may be decreased after a single window.
+const MIN_GRADIENT: f64 = 0.5;
+
+/// The weight of each new window's unloaded latency in the baseline.
+const BASELINE_WEIGHT: f64 = 0.1;
+
+/// A handle to the permits shared by all clones of an admission stack.
+#[derive(Clone, Debug)]
+pub struct Limit(Arc<Shared>);
+
+/// Configures a limit that adapts to the latency of its responses.
+#[derive(Copy, Clone, Debug)]
+pub struct Adaptive {
+    pub min: usize,
+    pub max: usize,
+    pub tolerance: f64,
+}
+
+/// Configures a limit to shed requests while response latency is high.
+#[derive(Copy, Clone, Debug)]
+pub struct Brownout {
+    /// Requests are shed while the average response latency exceeds this.
+    pub threshold: Duration,
+    /// The time constant with which latency samples' weights decay.
+    pub decay: Duration,
+}
+
+#[derive(Clone, Debug)]
+pub struct Layer(Limit);
+
+#[derive(Clone, Debug)]
+pub struct Service<S> {
+    limit: Limit,
+    inner: S,
+}
+
+pub struct ResponseFuture<F> {
+    state: Result<(F, Permit), Overloaded>,
+}
+
+/// The error returned when a request would exceed the limit, or when it
+/// arrives during a brownout.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Overloaded(Reason);
+
+/// Reports the state of each direction's adaptive limit.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    inbound: Registry,
+    outbound: Registry,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Registry(Arc<Mutex<Option<Limit>>>);
+
+#[derive(Debug)]
+struct Shared {
+    max: AtomicUsize,
+    in_flight: AtomicUsize,
+    /// Adjusts `max` if the limit is adaptive.
+    gradient: Option<Mutex<Gradient>>,
+    /// Sheds requests while latency is high, if configured.
+    brownout: Option<Mutex<Ewma>>,
+}
+
+/// Released when dropped.
+#[derive(Debug)]
+struct Permit {
+    limit: Limit,
+    /// The number of requests in flight, including this one, when it was
+    /// admitted.
+    in_flight: usize,
+    /// Set if the limit is adaptive or sheds requests on latency.
+    admitted: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct Gradient {
+    config: Adaptive,
+    limit: usize,
+    /// The latency of requests admitted while at most `config.min` requests
+    /// were in flight, in seconds.
+    baseline: Option<f64>,
+    gradient: f64,
+    window: Window,
+}
+
+#[derive(Debug, Default)]
+struct Window {
+    samples: usize,
+    total: f64,
+    max_in_flight: usize,
+    unloaded_samples: usize,
+    unloaded_total: f64,
+}
+
+/// A moving average of response latency, in which each sample's weight
+/// decays exponentially with its age.
+#[derive(Debug)]
+struct Ewma {
+    config: Brownout,
+    /// The average as of `updated`, in seconds, or `None` if no responses
+    /// have completed.
+    latency: Option<f64>,
+    updated: Instant,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Reason {
+    MaxInFlight,
+    Latency,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Ratio(f64);
+
+pub fn layer(limit: Limit) -> Layer {
+    Layer(limit)
+}
+
+// === impl Limit ===
+
+impl Limit {
+    pub fn new(max: usize) -> Self {
+        Limit(Arc::new(Shared {
+            max: AtomicUsize::new(max),
+            in_flight: AtomicUsize::new(0),
+            gradient: None,
+            brownout: None,
+        }))
+    }
+
+    /// Returns a limit that starts at `config.min` and adapts to latency.
+    pub fn adaptive(config: Adaptive) -> Self {
+        let gradient = Gradient::new(config);
+        Limit(Arc::new(Shared {
+            max: AtomicUsize::new(gradient.limit),
+            in_flight: AtomicUsize::new(0),
+            gradient: Some(Mutex::new(gradient)),
+            brownout: None,
+        }))
+    }
+
+    /// Configures the limit to shed requests while response latency exceeds
+    /// `brownout.threshold`.
+    ///
+    /// Panics if the limit has already been cloned.
+    pub fn with_brownout(mut self, brownout: Option<Brownout>) -> Self {
+        let shared =
+            Arc::get_mut(&mut self.0).expect("limit must be configured before it's shared");
+        shared.brownout = brownout.map(|b| Mutex::new(Ewma::new(b, clock::now())));
+        self
+    }
+
+    /// Returns the number of requests that may be admitted before the limit
+    /// is reached.
+    pub fn available(&self) -> usize {
+        self.max()
+            .saturating_sub(self.0.in_flight.load(Ordering::Acquire))
+    }
+
+    /// Returns the number of requests that may be in flight at once.
+    pub fn max(&self) -> usize {
+        self.0.max.load(Ordering::Acquire)
+    }
+
+    /// Returns the most recent gradient, if the limit is adaptive.
+    fn gradient(&self) -> Option<f64> {
+        let gradient = self.0.gradient.as_ref()?;
+        gradient.lock().ok().map(|g| g.gradient)
+    }
+
+    fn try_acquire(&self) -> Result<Permit, Overloaded> {
+        let now = clock::now();
+        if let Some(ref brownout) = self.0.brownout {
+            if let Ok(ewma) = brownout.lock() {
+                if ewma.exceeded(now) {
+                    return Err(Overloaded(Reason::Latency));
+                }
+            }
+        }
+
+        let prior = self.0.in_flight.fetch_add(1, Ordering::AcqRel);
+        if prior >= self.max() {
+            self.0.in_flight.fetch_sub(1, Ordering::AcqRel);
+            return Err(Overloaded(Reason::MaxInFlight));
+        }
+        let measured = self.0.gradient.is_some() || self.0.brownout.is_some();
+        Ok(Permit {
+            limit: self.clone(),
+            in_flight: prior + 1,
+            admitted: if measured { Some(now) } else { None },
+        })
+    }
+}
+
+// === impl Permit ===
+
+impl Permit {
+    /// Records the latency of the request's response, if the limit is
+    /// adaptive or sheds requests on latency.
+    fn complete(&self) {
+        let admitted = match self.admitted {
+            Some(admitted) => admitted,
+            None => return,
+        };
+        let now = clock::now();
+        let latency = now - admitted;
+
+        if let Some(ref brownout) = self.limit.0.brownout {
+            if let Ok(mut ewma) = brownout.lock() {
+                ewma.record(latency, now);
+            }
+        }
+
+        let gradient = match self.limit.0.gradient.as_ref() {
+            Some(gradient) => gradient,
+            None => return,
+        };
+        let limit = match gradient.lock() {
+            Ok(mut gradient) => gradient.record(latency, self.in_flight),
+            Err(_) => return,
+        };
+        if let Some(limit) = limit {
+            if limit != self.limit.0.max.swap(limit, Ordering::AcqRel) {
+                debug!(%limit, "adjusted concurrency limit");
+            }
+        }
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let prior = self.limit.0.in_flight.fetch_sub(1, Ordering::AcqRel);
+        debug_assert!(prior > 0, "permit released more often than acquired");
+    }
+}
+
+// === impl Gradient ===
+
+impl Gradient {
+    fn new(config: Adaptive) -> Self {
+        Self {
+            limit: config.min.max(1),
+            config,
+            baseline: None,
+            gradient: 1.0,
+            window: Window::default(),
+        }
+    }
+
+    /// Records a response's latency along with the number of requests that
+    /// were in flight when its request was admitted. Returns the new limit
+    /// whenever a window of responses completes.
+    fn record(&mut self, latency: Duration, in_flight: usize) -> Option<usize> {
+        let secs = latency.as_secs_f64();
+        let window = &mut self.window;
+        window.samples += 1;
+        window.total += secs;
+        window.max_in_flight = window.max_in_flight.max(in_flight);
+        if in_flight <= self.config.min {
+            window.unloaded_samples += 1;
+            window.unloaded_total += secs;
+        }
+        if window.samples < self.limit.max(MIN_WINDOW_SAMPLES) {
+            return None;
+        }
+
+        let window = std::mem::replace(&mut self.window, Window::default());
+        let latency = window.total / window.samples as f64;
+
+        // The baseline only learns from requests that were admitted while
+        // the proxy was lightly loaded, so that it doesn't rise along with
+        // the latency that the limit permits. It follows latency down
+        // immediately, though.
+        let baseline = match self.baseline {
+            Some(baseline) if window.unloaded_samples == 0 => baseline,
+            Some(baseline) => {
+                let unloaded = window.unloaded_total / window.unloaded_samples as f64;
+                baseline + BASELINE_WEIGHT * (unloaded - baseline)
+            }
+            None => latency,
+        }
+        .min(latency);
+        self.baseline = Some(baseline);
+
+        self.gradient = if latency > 0.0 {
+            (self.config.tolerance * baseline / latency)
+                .max(MIN_GRADIENT)
+                .min(1.0)
+        } else {
+            1.0
+        };
+
+        if self.gradient < 1.0 {
+            self.limit = (self.limit as f64 * self.gradient) as usize;
+        } else if window.max_in_flight * 2 >= self.limit {
+            // The limit only grows while it's being used; otherwise, an idle
+            // proxy's limit would creep up to the maximum.
+            self.limit += 1;
+        }
+        self.limit = self.limit.max(self.config.min).min(self.config.max);
+        Some(self.limit)
+    }
+}
+
+// === impl Ewma ===
+
+impl Ewma {
+    fn new(config: Brownout, now: Instant) -> Self {
+        Self {
+            config,
+            latency: None,
+            updated: now,
+        }
+    }
+
+    /// Returns the weight that the average as of `updated` retains at `now`.
+    fn retained(&self, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        let decay = self.config.decay.as_secs_f64().max(std::f64::EPSILON);
+        (-elapsed / decay).exp()
+    }
+
+    /// Returns the average latency at `now`, in seconds.
+    fn latency(&self, now: Instant) -> Option<f64> {
+        self.latency.map(|latency| latency * self.retained(now))
+    }
+
+    fn record(&mut self, latency: Duration, now: Instant) {
+        let sample = latency.as_secs_f64();
+        self.latency = Some(match self.latency {
+            Some(average) => {
+                let retained = self.retained(now);
+                average * retained + sample * (1.0 - retained)
+            }
+            None => sample,
+        });
+        self.updated = self.updated.max(now);
+    }
+
+    /// Indicates whether requests should be shed at `now`.
+    fn exceeded(&self, now: Instant) -> bool {
+        match self.latency(now) {
+            Some(latency) => latency > self.config.threshold.as_secs_f64(),
+            None => false,
+        }
+    }
+}
+
+// === impl Layer ===
+
+impl<S> tower::layer::Layer<S> for Layer {
+    type Service = Service<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Service {
+            limit: self.0.clone(),
+            inner,
+        }
+    }
+}
+
+// === impl Service ===
+
+impl<S, Req> tower::Service<Req> for Service<S>
+where
+    S: tower::Service<Req>,
+    S::Error: Into<Error>,
+{
+    type Response = S::Response;
+    type Error = Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        let state = match self.limit.try_acquire() {
+            Ok(permit) => Ok((self.inner.call(req), permit)),
+            Err(overloaded) => {
+                debug!(max = self.limit.max(), reason = %overloaded, "shedding request");
+                Err(overloaded)
+            }
+        };
+        ResponseFuture { state }
+    }
+}
+
+impl<F> Future for ResponseFuture<F>
+where
+    F: Future,
+    F::Error: Into<Error>,
+{
+    type Item = F::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.state {
+            Ok((ref mut f, ref permit)) => {
+                let poll = f.poll();
+                if let Ok(Async::Ready(_)) | Err(_) = poll {
+                    permit.complete();
+                }
+                poll.map_err(Into::into)
+            }
+            Err(overloaded) => Err(overloaded.into()),
+        }
+    }
+}
+
+// === impl Overloaded ===
+
+impl fmt::Display for Overloaded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Reason::MaxInFlight => write!(f, "max-in-flight reached"),
+            Reason::Latency => write!(f, "response latency exceeds brownout threshold"),
+        }
+    }
+}
+
+impl std::error::Error for Overloaded {}
+
+// === impl Metrics ===
+
+impl Metrics {
+    pub fn inbound(&self) -> Registry {
+        self.inbound.clone()
+    }
+
+    pub fn outbound(&self) -> Registry {
+        self.outbound.clone()
+    }
+}
+
+impl FmtMetrics for Metrics {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut scopes = Vec::new();
+        for (direction, registry) in &[
+            (Direction::In, &self.inbound),
+            (Direction::Out, &self.outbound),
+        ] {
+            let limit = match registry.0.lock() {
+                Ok(limit) => limit.clone(),
+                Err(_) => continue,
+            };
+            if let Some(limit) = limit {
+                if let Some(gradient) = limit.gradient() {
+                    scopes.push((
+                        *direction,
+                        (Gauge::from(limit.max() as u64), Ratio(gradient)),
+                    ));
+                }
+            }
+        }
+        if scopes.is_empty() {
+            return Ok(());
+        }
+
+        request_concurrency_limit.fmt_help(f)?;
+        request_concurrency_limit.fmt_scopes(f, scopes.iter().map(|(d, s)| (*d, s)), |s| &s.0)?;
+
+        request_concurrency_gradient.fmt_help(f)?;
+        request_concurrency_gradient
+            .fmt_scopes(f, scopes.iter().map(|(d, s)| (*d, s)), |s| &s.1)?;
+
+        Ok(())
+    }
+}
+
+// === impl Registry ===
+
+impl Registry {
+    /// Reports `limit`'s state, if it's adaptive.
+    pub fn register(&self, limit: &Limit) {
+        if let Ok(mut registered) = self.0.lock() {
+            *registered = Some(limit.clone());
+        }
+    }
+}
+
+// === impl Ratio ===
+
+impl FmtMetric for Ratio {
+    const KIND: &'static str = "gauge";
+
+    fn fmt_metric<N: fmt::Display>(&self, f: &mut fmt::Formatter<'_>, name: N) -> fmt::Result {
+        writeln!(f, "{} {}", name, self.0)
+    }
+
+    fn fmt_metric_labeled<N, L>(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        name: N,
+        labels: L,
+    ) -> fmt::Result
+    where
+        N: fmt::Display,
+        L: FmtLabels,
+    {
+        write!(f, "{}{{", name)?;
+        labels.fmt_labels(f)?;
+        writeln!(f, "}} {}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::buffer;
+    use futures::future;
+    use tokio::runtime::current_thread::Runtime;
+    use tower::layer::Layer as _;
+    use tower::retry::{budget::Budget, Policy, Retry};
+    use tower::Service as _;
+
+    const MAX: usize = 2;
+
+    /// Accepts requests but never responds to them.
+    struct Hang;
+
+    impl tower::Service<()> for Hang {
+        type Response = ();
+        type Error = Error;
+        type Future = future::Empty<(), Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: ()) -> Self::Future {
+            future::empty()
+        }
+    }
+
+    /// Never becomes ready, so that requests remain queued in a buffer.
+    struct NeverReady;
+
+    impl tower::Service<()> for NeverReady {
+        type Response = ();
+        type Error = Error;
+        type Future = future::Empty<(), Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(Async::NotReady)
+        }
+
+        fn call(&mut self, _: ()) -> Self::Future {
+            unreachable!("service never becomes ready")
+        }
+    }
+
+    /// Fails every request.
+    #[derive(Clone)]
+    struct Fail;
+
+    impl tower::Service<()> for Fail {
+        type Response = ();
+        type Error = Error;
+        type Future = future::FutureResult<(), Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: ()) -> Self::Future {
+            future::err("failed".into())
+        }
+    }
+
+    /// Retries failures for as long as the budget allows.
+    #[derive(Clone)]
+    struct RetryFailures(Arc<Budget>);
+
+    impl Policy<(), (), Error> for RetryFailures {
+        type Future = future::FutureResult<Self, ()>;
+
+        fn retry(&self, _: &(), result: Result<&(), &Error>) -> Option<Self::Future> {
+            match result {
+                Err(_) if self.0.withdraw().is_ok() => Some(future::ok(self.clone())),
+                _ => None,
+            }
+        }
+
+        fn clone_request(&self, _: &()) -> Option<()> {
+            Some(())
+        }
+    }
+
+    #[test]
+    fn idle_services_hold_no_permits() {
+        let limit = Limit::new(MAX);
+        let mut svcs = (0..2 * MAX)
+            .map(|_| Layer(limit.clone()).layer(Hang))
+            .collect::<Vec<_>>();
+        for svc in svcs.iter_mut() {
+            assert!(svc.poll_ready().expect("ready").is_ready());
+        }
+        assert_eq!(limit.available(), MAX);
+    }
+
+    #[test]
+    fn cancelling_buffered_requests_releases_permits() {
+        let limit = Limit::new(MAX);
+        let mut rt = Runtime::new().unwrap();
+
+        rt.block_on(future::lazy(|| {
+            let buffer = buffer::Enqueue::new(NeverReady, Duration::from_secs(60), MAX);
+            let mut svc = Layer(limit.clone()).layer(buffer);
+
+            let mut queued = (0..MAX)
+                .map(|_| {
+                    assert!(svc.poll_ready().expect("ready").is_ready());
+                    let mut rsp = svc.call(());
+                    assert!(rsp.poll().expect("queued").is_not_ready());
+                    rsp
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(limit.available(), 0);
+
+            queued.clear();
+            Ok::<_, ()>(())
+        }))
+        .unwrap();
+
+        assert_eq!(limit.available(), MAX);
+    }
+
+    #[test]
+    fn exhausting_a_retry_budget_releases_permits() {
+        let limit = Limit::new(MAX);
+        let budget = Arc::new(Budget::new(Duration::from_secs(10), 0, 1.0));
+        for _ in 0..3 {
+            budget.deposit();
+        }
+        let policy = RetryFailures(budget.clone());
+        let mut svc = Layer(limit.clone()).layer(Retry::new(policy, Fail));
+
+        for _ in 0..MAX + 1 {
+            svc.call(())
+                .wait()
+                .expect_err("request must fail once the budget is exhausted");
+            assert_eq!(limit.available(), MAX);
+        }
+        assert!(budget.withdraw().is_err(), "budget must be exhausted");
+    }
+
+    #[test]
+    fn shedding_releases_permits() {
+        let limit = Limit::new(MAX);
+        let mut svc = Layer(limit.clone()).layer(Hang);
+
+        let in_flight = (0..MAX).map(|_| svc.call(())).collect::<Vec<_>>();
+        assert_eq!(limit.available(), 0);
+
+        let err = svc.call(()).wait().expect_err("request must be shed");
+        assert!(err.is::<Overloaded>());
+        assert_eq!(limit.available(), 0);
+
+        drop(in_flight);
+        assert_eq!(limit.available(), MAX);
+    }
+
+    /// A scripted service whose latency is constant up to `KNEE` concurrent
+    /// requests and rises in proportion to concurrency beyond it.
+    fn scripted_latency(in_flight: usize) -> Duration {
+        const KNEE: u64 = 20;
+        let base = Duration::from_millis(10);
+        if in_flight as u64 <= KNEE {
+            base
+        } else {
+            base * in_flight as u32 / KNEE as u32
+        }
+    }
+
+    /// Drives a gradient with enough requests to fill its limit, returning
+    /// the limit after each window.
+    fn saturate(gradient: &mut Gradient, windows: usize) -> Vec<usize> {
+        let mut limits = Vec::new();
+        while limits.len() < windows {
+            let in_flight = gradient.limit;
+            if let Some(limit) = gradient.record(scripted_latency(in_flight), in_flight) {
+                limits.push(limit);
+            }
+        }
+        limits
+    }
+
+    #[test]
+    fn adaptive_limit_converges_near_knee() {
+        let mut gradient = Gradient::new(Adaptive {
+            min: 5,
+            max: 1_000,
+            tolerance: DEFAULT_ADAPTIVE_TOLERANCE,
+        });
+
+        let limits = saturate(&mut gradient, 500);
+        for &limit in &limits[limits.len() - 100..] {
+            assert!(
+                limit >= 20 && limit <= 40,
+                "limit {} must settle near the knee",
+                limit
+            );
+        }
+        assert!(gradient.gradient >= MIN_GRADIENT && gradient.gradient <= 1.0);
+    }
+
+    #[test]
+    fn adaptive_limit_only_grows_while_used() {
+        let mut gradient = Gradient::new(Adaptive {
+            min: 5,
+            max: 1_000,
+            tolerance: DEFAULT_ADAPTIVE_TOLERANCE,
+        });
+
+        // A single request at a time never fills the limit.
+        for _ in 0..1_000 {
+            gradient.record(scripted_latency(1), 1);
+        }
+        assert_eq!(gradient.limit, 5);
+    }
+
+    #[test]
+    fn adaptive_limit_sheds_beyond_current_limit() {
+        let limit = Limit::adaptive(Adaptive {
+            min: MAX,
+            max: 10 * MAX,
+            tolerance: DEFAULT_ADAPTIVE_TOLERANCE,
+        });
+        assert_eq!(limit.max(), MAX);
+        let mut svc = Layer(limit.clone()).layer(Hang);
+
+        let _in_flight = (0..MAX).map(|_| svc.call(())).collect::<Vec<_>>();
+        let err = svc.call(()).wait().expect_err("request must be shed");
+        assert!(err.is::<Overloaded>());
+    }
+
+    const BROWNOUT: Brownout = Brownout {
+        threshold: Duration::from_millis(100),
+        decay: Duration::from_secs(1),
+    };
+
+    /// Records a response with the given latency every 10ms, returning the
+    /// time of the last response.
+    fn respond(ewma: &mut Ewma, mut now: Instant, latency: Duration, n: usize) -> Instant {
+        for _ in 0..n {
+            now += Duration::from_millis(10);
+            ewma.record(latency, now);
+        }
+        now
+    }
+
+    #[test]
+    fn rising_latency_triggers_brownout() {
+        let start = Instant::now();
+        let mut ewma = Ewma::new(BROWNOUT, start);
+        assert!(!ewma.exceeded(start), "must admit before any responses");
+
+        let now = respond(&mut ewma, start, Duration::from_millis(10), 100);
+        assert!(!ewma.exceeded(now));
+
+        // A single slow response doesn't trigger a brownout...
+        let now = respond(&mut ewma, now, Duration::from_millis(500), 1);
+        assert!(!ewma.exceeded(now));
+
+        // ...but sustained slow responses do.
+        let now = respond(&mut ewma, now, Duration::from_millis(500), 100);
+        assert!(ewma.exceeded(now));
+    }
+
+    #[test]
+    fn recovering_latency_resumes_admission() {
+        let start = Instant::now();
+        let mut ewma = Ewma::new(BROWNOUT, start);
+        let now = respond(&mut ewma, start, Duration::from_millis(500), 100);
+        assert!(ewma.exceeded(now));
+
+        // While requests are shed, no responses are recorded, and the average
+        // decays until requests are admitted again.
+        assert!(ewma.exceeded(now + Duration::from_millis(500)));
+        let idle = now + BROWNOUT.decay * 3;
+        assert!(!ewma.exceeded(idle));
+
+        // If latency stays high, requests are shed again...
+        let now = respond(&mut ewma, idle, Duration::from_millis(500), 100);
+        assert!(ewma.exceeded(now));
+
+        // ...until it recovers.
+        let now = respond(&mut ewma, now, Duration::from_millis(10), 200);
+        assert!(!ewma.exceeded(now));
+    }
+
+    #[test]
+    fn brownout_sheds_requests() {
+        let limit = Limit::new(MAX).with_brownout(Some(BROWNOUT));
+        let mut svc = Layer(limit.clone()).layer(Hang);
+
+        let in_flight = svc.call(());
+        if let Some(ref brownout) = limit.0.brownout {
+            brownout
+                .lock()
+                .unwrap()
+                .record(Duration::from_secs(1), clock::now());
+        }
+
+        let err = svc.call(()).wait().expect_err("request must be shed");
+        assert_eq!(
+            err.downcast_ref::<Overloaded>(),
+            Some(&Overloaded(Reason::Latency))
+        );
+        assert_eq!(
+            limit.available(),
+            MAX - 1,
+            "shed requests must not hold permits"
+        );
+
+        drop(in_flight);
+        assert_eq!(limit.available(), MAX);
+    }
+}
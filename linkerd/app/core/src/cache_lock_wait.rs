@@ -0,0 +1,53 @@
+use crate::router;
+use indexmap::IndexMap;
+use linkerd2_metrics::{FmtLabels, FmtMetrics, Metric};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Reports the time requests spend waiting on each router cache's lock.
+#[derive(Clone, Debug, Default)]
+pub struct Registry(Arc<Mutex<IndexMap<&'static str, router::LockWait>>>);
+
+struct CacheLabel(&'static str);
+
+impl Registry {
+    pub const HELP: &'static str =
+        "A histogram of the time in microseconds that requests wait to acquire a router cache's lock.";
+    pub const NAME: &'static str = "cache_lock_wait_us";
+
+    /// Registers the lock-wait histogram of the router cache named `name`.
+    pub fn register(&self, name: &'static str, lock_wait: router::LockWait) {
+        if let Ok(mut caches) = self.0.lock() {
+            caches.insert(name, lock_wait);
+        }
+    }
+
+    fn metric(&self) -> Metric<'_, router::LockWait> {
+        Metric::new(Self::NAME, Self::HELP)
+    }
+}
+
+impl FmtMetrics for Registry {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let caches = match self.0.lock() {
+            Ok(caches) => caches,
+            Err(_) => return Ok(()),
+        };
+        if caches.is_empty() {
+            return Ok(());
+        }
+
+        let metric = self.metric();
+        metric.fmt_help(f)?;
+        let scopes = caches
+            .iter()
+            .map(|(name, lock_wait)| (CacheLabel(*name), lock_wait));
+        metric.fmt_scopes(f, scopes, |lock_wait| lock_wait)
+    }
+}
+
+impl FmtLabels for CacheLabel {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cache=\"{}\"", self.0)
+    }
+}
@@ -0,0 +1,435 @@
+//! Lets trusted callers override the retry and timeout policy of a request's
+//! route.
+//!
+//! A caller may disable retries with `l5d-retries: off`, or replace the
+//! route's timeout with `l5d-timeout: DURATION` (e.g. `120s`), which is
+//! clamped to a configured maximum. These headers are only honored when the
+//! request's source is trusted; either way, they are removed before the
+//! request is forwarded.
+
+use super::metric_labels::Direction;
+use crate::proxy::{
+    http::{retry, timeout},
+    identity,
+};
+use crate::{svc, transport::tls, L5D_RETRIES, L5D_TIMEOUT};
+use futures::{try_ready, Future, Poll};
+use indexmap::IndexMap;
+use linkerd2_metrics::{metrics, Counter, FmtLabels, FmtMetrics};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::debug;
+
+metrics! {
+    request_caller_override_total: Counter {
+        "Total count of requests on which a caller overrode the retry or timeout policy of its route"
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Config {
+    /// The longest timeout a caller may set. Longer timeouts are clamped.
+    pub max_timeout: Duration,
+}
+
+/// Determines which sources' overrides are honored.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Trust {
+    /// Only sources with a meshed peer identity are trusted.
+    Meshed,
+    /// All sources are trusted, as when they're the local application.
+    All,
+}
+
+/// Counts the overrides used by each source.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics(Arc<Mutex<IndexMap<Usage, Counter>>>);
+
+/// Records the overrides used by one direction's sources.
+#[derive(Clone, Debug)]
+pub struct Registry {
+    direction: Direction,
+    metrics: Metrics,
+}
+
+#[derive(Clone, Debug)]
+pub struct Layer {
+    config: Option<Config>,
+    trust: Trust,
+    registry: Registry,
+}
+
+#[derive(Clone, Debug)]
+pub struct Stack<M> {
+    inner: M,
+    config: Option<Config>,
+    trust: Trust,
+    registry: Registry,
+}
+
+pub struct MakeFuture<F> {
+    inner: F,
+    source: Option<Source>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Service<S> {
+    inner: S,
+    source: Source,
+}
+
+/// The overrides policy of a single source.
+#[derive(Clone, Debug)]
+struct Source {
+    config: Option<Config>,
+    trusted: bool,
+    client_id: Option<identity::Name>,
+    registry: Registry,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Usage {
+    direction: Direction,
+    client_id: Option<identity::Name>,
+    kind: Kind,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum Kind {
+    Retries,
+    Timeout,
+}
+
+/// Honors the overrides of sources permitted by `trust`. If no config is
+/// set, requests are unchanged.
+pub fn layer(config: Option<Config>, trust: Trust, registry: Registry) -> Layer {
+    Layer {
+        config,
+        trust,
+        registry,
+    }
+}
+
+/// Parses a timeout like `500ms`, `120s`, `5m`, or `1h`.
+fn parse_timeout(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let unit_at = s.find(|c: char| !c.is_ascii_digit())?;
+    let magnitude = s[..unit_at].parse::<u64>().ok()?;
+    match &s[unit_at..] {
+        "ms" => Some(Duration::from_millis(magnitude)),
+        "s" => Some(Duration::from_secs(magnitude)),
+        "m" => magnitude.checked_mul(60).map(Duration::from_secs),
+        "h" => magnitude.checked_mul(60 * 60).map(Duration::from_secs),
+        _ => None,
+    }
+}
+
+// === impl Metrics ===
+
+impl Metrics {
+    pub fn inbound(&self) -> Registry {
+        Registry {
+            direction: Direction::In,
+            metrics: self.clone(),
+        }
+    }
+
+    pub fn outbound(&self) -> Registry {
+        Registry {
+            direction: Direction::Out,
+            metrics: self.clone(),
+        }
+    }
+}
+
+impl FmtMetrics for Metrics {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let by_usage = match self.0.lock() {
+            Ok(by_usage) => by_usage,
+            Err(_) => return Ok(()),
+        };
+        if by_usage.is_empty() {
+            return Ok(());
+        }
+
+        request_caller_override_total.fmt_help(f)?;
+        request_caller_override_total.fmt_scopes(f, by_usage.iter(), |c| c)?;
+
+        Ok(())
+    }
+}
+
+// === impl Registry ===
+
+impl Registry {
+    fn incr(&self, client_id: Option<&identity::Name>, kind: Kind) {
+        let usage = Usage {
+            direction: self.direction,
+            client_id: client_id.cloned(),
+            kind,
+        };
+        if let Ok(mut by_usage) = self.metrics.0.lock() {
+            by_usage
+                .entry(usage)
+                .or_insert_with(Counter::default)
+                .incr();
+        }
+    }
+}
+
+// === impl Usage ===
+
+impl FmtLabels for Usage {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.direction.fmt_labels(f)?;
+        if let Some(ref id) = self.client_id {
+            write!(f, ",client_id=\"{}\"", id.as_ref())?;
+        }
+        match self.kind {
+            Kind::Retries => write!(f, ",override=\"retries\""),
+            Kind::Timeout => write!(f, ",override=\"timeout\""),
+        }
+    }
+}
+
+// === impl Layer ===
+
+impl<M> svc::Layer<M> for Layer {
+    type Service = Stack<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        Stack {
+            inner,
+            config: self.config,
+            trust: self.trust,
+            registry: self.registry.clone(),
+        }
+    }
+}
+
+// === impl Stack ===
+
+impl<M> svc::Service<tls::accept::Meta> for Stack<M>
+where
+    M: svc::Service<tls::accept::Meta>,
+{
+    type Response = Service<M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, meta: tls::accept::Meta) -> Self::Future {
+        let client_id = meta.peer_identity.value().cloned();
+        let trusted = match self.trust {
+            Trust::Meshed => client_id.is_some(),
+            Trust::All => true,
+        };
+        let source = Source {
+            config: self.config,
+            trusted,
+            client_id,
+            registry: self.registry.clone(),
+        };
+
+        MakeFuture {
+            inner: self.inner.call(meta),
+            source: Some(source),
+        }
+    }
+}
+
+// === impl MakeFuture ===
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = Service<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        let source = self.source.take().expect("polled after ready");
+        Ok(Service { inner, source }.into())
+    }
+}
+
+// === impl Service ===
+
+impl<S, B> svc::Service<http::Request<B>> for Service<S>
+where
+    S: svc::Service<http::Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut req: http::Request<B>) -> Self::Future {
+        if let Some(config) = self.source.config {
+            self.source.apply(config, &mut req);
+        }
+
+        self.inner.call(req)
+    }
+}
+
+// === impl Source ===
+
+impl Source {
+    fn apply<B>(&self, config: Config, req: &mut http::Request<B>) {
+        let retries = req.headers_mut().remove(L5D_RETRIES);
+        let timeout = req.headers_mut().remove(L5D_TIMEOUT);
+        if retries.is_none() && timeout.is_none() {
+            return;
+        }
+
+        if !self.trusted {
+            debug!("ignoring overrides from untrusted source");
+            return;
+        }
+
+        if let Some(retries) = retries {
+            if retries.as_bytes().eq_ignore_ascii_case(b"off") {
+                debug!("retries disabled by caller");
+                req.extensions_mut().insert(retry::Disabled);
+                self.registry.incr(self.client_id.as_ref(), Kind::Retries);
+            } else {
+                debug!(?retries, "ignoring invalid {} header", L5D_RETRIES);
+            }
+        }
+
+        if let Some(value) = timeout {
+            match value.to_str().ok().and_then(parse_timeout) {
+                Some(timeout) => {
+                    let timeout = timeout.min(config.max_timeout);
+                    debug!(?timeout, "timeout set by caller");
+                    req.extensions_mut().insert(timeout::Override(timeout));
+                    self.registry.incr(self.client_id.as_ref(), Kind::Timeout);
+                }
+                None => debug!(?value, "ignoring invalid {} header", L5D_TIMEOUT),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Conditional;
+
+    const MAX_TIMEOUT: Duration = Duration::from_secs(60);
+
+    fn source(trusted: bool, metrics: &Metrics) -> Source {
+        Source {
+            config: Some(Config {
+                max_timeout: MAX_TIMEOUT,
+            }),
+            trusted,
+            client_id: None,
+            registry: metrics.outbound(),
+        }
+    }
+
+    fn request(retries: &str, timeout: &str) -> http::Request<()> {
+        http::Request::builder()
+            .header(L5D_RETRIES, retries)
+            .header(L5D_TIMEOUT, timeout)
+            .body(())
+            .unwrap()
+    }
+
+    #[test]
+    fn trusted_overrides_are_applied_and_clamped() {
+        let metrics = Metrics::default();
+        let src = source(true, &metrics);
+
+        let mut req = request("off", "30s");
+        src.apply(src.config.unwrap(), &mut req);
+        assert!(req.extensions().get::<retry::Disabled>().is_some());
+        assert_eq!(
+            req.extensions().get::<timeout::Override>(),
+            Some(&timeout::Override(Duration::from_secs(30)))
+        );
+        assert!(req.headers().get(L5D_RETRIES).is_none());
+        assert!(req.headers().get(L5D_TIMEOUT).is_none());
+
+        let mut req = request("on", "10m");
+        src.apply(src.config.unwrap(), &mut req);
+        assert!(req.extensions().get::<retry::Disabled>().is_none());
+        assert_eq!(
+            req.extensions().get::<timeout::Override>(),
+            Some(&timeout::Override(MAX_TIMEOUT)),
+            "timeouts must be clamped"
+        );
+
+        let report = metrics.as_display().to_string();
+        assert!(report.contains(
+            "request_caller_override_total{direction=\"outbound\",override=\"retries\"} 1"
+        ));
+        assert!(report.contains(
+            "request_caller_override_total{direction=\"outbound\",override=\"timeout\"} 2"
+        ));
+    }
+
+    #[test]
+    fn untrusted_overrides_are_ignored_and_stripped() {
+        let metrics = Metrics::default();
+        let src = source(false, &metrics);
+
+        let mut req = request("off", "30s");
+        src.apply(src.config.unwrap(), &mut req);
+        assert!(req.extensions().get::<retry::Disabled>().is_none());
+        assert!(req.extensions().get::<timeout::Override>().is_none());
+        assert!(req.headers().get(L5D_RETRIES).is_none());
+        assert!(req.headers().get(L5D_TIMEOUT).is_none());
+        assert_eq!(metrics.as_display().to_string(), "");
+    }
+
+    #[test]
+    fn only_meshed_sources_are_trusted_inbound() {
+        use crate::transport::listen::Addrs;
+        use futures::future;
+        use svc::{Layer as _, Service as _};
+
+        let config = Config {
+            max_timeout: MAX_TIMEOUT,
+        };
+        let mut stack = layer(Some(config), Trust::Meshed, Metrics::default().inbound())
+            .layer(svc::mk(|_: tls::accept::Meta| future::ok::<_, ()>(())));
+
+        let meta = |peer_identity| tls::accept::Meta {
+            peer_identity,
+            addrs: Addrs::new(
+                ([10, 1, 1, 1], 33333).into(),
+                ([10, 1, 1, 2], 4143).into(),
+                None,
+            ),
+        };
+        let id =
+            identity::Name::from_hostname(b"foo.ns.serviceaccount.identity.linkerd.cluster.local")
+                .unwrap();
+
+        let meshed = stack.call(meta(Conditional::Some(id))).wait().unwrap();
+        assert!(meshed.source.trusted);
+
+        let no_identity = Conditional::None(tls::ReasonForNoIdentity::Disabled);
+        let unmeshed = stack.call(meta(no_identity)).wait().unwrap();
+        assert!(!unmeshed.source.trusted);
+    }
+
+    #[test]
+    fn parses_timeouts() {
+        assert_eq!(parse_timeout("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_timeout("120s"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_timeout("5m"), Some(Duration::from_secs(300)));
+        assert_eq!(parse_timeout("1h"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse_timeout("120"), None);
+        assert_eq!(parse_timeout("s"), None);
+        assert_eq!(parse_timeout("1.5s"), None);
+    }
+}
@@ -25,6 +25,8 @@ pub enum Eos {
     Grpc(GrpcEos),
     Profile(Class),
     Error(&'static str),
+    /// Classifies a response as a failure unless body data is received.
+    FailIfEmpty(Box<Eos>),
 }
 
 #[derive(Clone, Debug)]
@@ -50,7 +52,7 @@ pub enum SuccessOrFailure {
 
 impl From<profiles::ResponseClasses> for Request {
     fn from(classes: profiles::ResponseClasses) -> Self {
-        if classes.is_empty() {
+        if classes.is_empty() && !classes.empty_is_failure() {
             Request::Default
         } else {
             Request::Profile(classes)
@@ -136,13 +138,20 @@ impl classify::ClassifyResponse for Response {
             Response::Grpc => grpc_class(rsp.headers())
                 .map(|c| Eos::Grpc(GrpcEos::NoBody(c)))
                 .unwrap_or(Eos::Grpc(GrpcEos::Open)),
-            Response::Profile(ref classes) => Self::match_class(rsp, classes.as_ref())
-                .map(Eos::Profile)
-                .unwrap_or_else(|| {
-                    grpc_class(rsp.headers())
-                        .map(|c| Eos::Grpc(GrpcEos::NoBody(c)))
-                        .unwrap_or_else(|| Eos::Default(rsp.status()))
-                }),
+            Response::Profile(ref classes) => {
+                let eos = Self::match_class(rsp, classes.as_ref())
+                    .map(Eos::Profile)
+                    .unwrap_or_else(|| {
+                        grpc_class(rsp.headers())
+                            .map(|c| Eos::Grpc(GrpcEos::NoBody(c)))
+                            .unwrap_or_else(|| Eos::Default(rsp.status()))
+                    });
+                if classes.empty_is_failure() {
+                    eos.fail_if_empty(rsp)
+                } else {
+                    eos
+                }
+            }
         }
     }
 
@@ -153,6 +162,39 @@ impl classify::ClassifyResponse for Response {
 
 // === impl Eos ===
 
+impl Eos {
+    /// Classifies the response using only its headers, i.e. before its body
+    /// has been read.
+    pub fn headers_only(self) -> Self {
+        match self {
+            Eos::FailIfEmpty(eos) => *eos,
+            eos => eos,
+        }
+    }
+
+    fn fail_if_empty<B>(self, rsp: &http::Response<B>) -> Self {
+        // Only responses that would otherwise be successful are checked.
+        let is_success = match self {
+            Eos::Default(status) => status.is_success(),
+            Eos::Profile(ref class) => !class.is_failure(),
+            _ => false,
+        };
+        if !is_success {
+            return self;
+        }
+
+        let content_length = rsp
+            .headers()
+            .get(http::header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok());
+        match content_length {
+            Some(0) => Eos::Profile(Class::Default(SuccessOrFailure::Failure)),
+            _ => Eos::FailIfEmpty(Box::new(self)),
+        }
+    }
+}
+
 impl classify::ClassifyEos for Eos {
     type Class = Class;
 
@@ -170,6 +212,17 @@ impl classify::ClassifyEos for Eos {
                 .unwrap_or_else(|| Class::Grpc(SuccessOrFailure::Success, 0)),
             Eos::Profile(class) => class,
             Eos::Error(msg) => Class::Stream(SuccessOrFailure::Failure, msg.into()),
+            Eos::FailIfEmpty(_) => Class::Default(SuccessOrFailure::Failure),
+        }
+    }
+
+    fn data(&mut self, len: usize) {
+        // Once body data is received, the response is classified as it
+        // would be without the empty-body check.
+        if len > 0 {
+            if let Eos::FailIfEmpty(eos) = self {
+                *self = std::mem::replace(&mut **eos, Eos::Default(http::StatusCode::OK));
+            }
         }
     }
 
@@ -224,8 +277,15 @@ impl Class {
 mod tests {
     use super::{Class, SuccessOrFailure};
     use crate::proxy::http::metrics::classify::{ClassifyEos as _CE, ClassifyResponse as _CR};
+    use crate::proxy::http::profiles;
     use http::{HeaderMap, Response, StatusCode};
 
+    fn empty_is_failure() -> super::Response {
+        let mut route = profiles::Route::new(std::iter::empty(), Vec::new());
+        route.set_empty_is_failure();
+        super::Response::Profile(route.response_classes().clone())
+    }
+
     #[test]
     fn http_response_status_ok() {
         let rsp = Response::builder().status(StatusCode::OK).body(()).unwrap();
@@ -314,4 +374,43 @@ mod tests {
             .eos(Some(&trailers));
         assert_eq!(class, Class::Grpc(SuccessOrFailure::Failure, 4));
     }
+
+    #[test]
+    fn profile_empty_response_is_failure() {
+        let rsp = Response::builder()
+            .status(StatusCode::OK)
+            .header(http::header::CONTENT_LENGTH, "0")
+            .body(())
+            .unwrap();
+        let class = empty_is_failure().start(&rsp).eos(None);
+        assert_eq!(class, Class::Default(SuccessOrFailure::Failure));
+
+        let rsp = Response::builder().status(StatusCode::OK).body(()).unwrap();
+        let mut eos = empty_is_failure().start(&rsp);
+        eos.data(0);
+        let class = eos.eos(None);
+        assert_eq!(class, Class::Default(SuccessOrFailure::Failure));
+    }
+
+    #[test]
+    fn profile_nonempty_response_is_success() {
+        let rsp = Response::builder().status(StatusCode::OK).body(()).unwrap();
+        let mut eos = empty_is_failure().start(&rsp);
+        eos.data(5);
+        let class = eos.eos(None);
+        assert_eq!(class, Class::Default(SuccessOrFailure::Success));
+    }
+
+    #[test]
+    fn profile_empty_response_is_success_by_default() {
+        let rsp = Response::builder()
+            .status(StatusCode::OK)
+            .header(http::header::CONTENT_LENGTH, "0")
+            .body(())
+            .unwrap();
+        let class = super::Response::Profile(Default::default())
+            .start(&rsp)
+            .eos(None);
+        assert_eq!(class, Class::Default(SuccessOrFailure::Success));
+    }
 }
@@ -1,7 +1,14 @@
 pub use super::control::ControlAddr;
+use super::{caller_override, header_trust};
 pub use crate::exp_backoff::ExponentialBackoff;
-pub use crate::proxy::http::h2;
-pub use crate::transport::{Bind, Listen, NoOrigDstAddr, OrigDstAddr, SysOrigDstAddr};
+use crate::proxy::http::header::HeaderName;
+pub use crate::proxy::{
+    buffer::DrainPolicy,
+    http::{h2, header_limit},
+    server::{IdleTimeouts, ResetLimit},
+};
+use crate::reconnect;
+pub use crate::transport::{Bind, Listen, NoOrigDstAddr, OrigDstAddr, SkipPorts, SysOrigDstAddr};
 use indexmap::IndexSet;
 use std::sync::Arc;
 use std::time::Duration;
@@ -11,6 +18,14 @@ pub struct ServerConfig<A: OrigDstAddr = NoOrigDstAddr> {
     pub bind: Bind<A>,
     pub buffer: BufferConfig,
     pub h2_settings: h2::Settings,
+    /// If set, HTTP connections are closed once they have served this many
+    /// requests (or, for HTTP/2, streams).
+    pub max_requests_per_connection: Option<usize>,
+    /// If set, HTTP/2 connections are closed once the client resets streams
+    /// faster than this limit allows.
+    pub h2_reset_limit: Option<ResetLimit>,
+    /// Closes connections that clients leave idle.
+    pub idle_timeouts: IdleTimeouts,
 }
 
 #[derive(Clone, Debug)]
@@ -18,6 +33,12 @@ pub struct ConnectConfig {
     pub backoff: ExponentialBackoff,
     pub timeout: Duration,
     pub keepalive: Option<Duration>,
+    /// If set, clients are discarded and rebuilt once they are older than
+    /// this, regardless of whether their connections are idle.
+    pub max_lifetime: Option<Duration>,
+    /// If set, endpoints that fail to connect too many times in a row are
+    /// marked unavailable for a cooldown, rather than retried indefinitely.
+    pub retry_budget: Option<reconnect::Budget>,
     pub h2_settings: h2::Settings,
 }
 
@@ -27,7 +48,30 @@ pub struct ProxyConfig<A: OrigDstAddr = SysOrigDstAddr> {
     pub connect: ConnectConfig,
     pub router_capacity: usize,
     pub router_max_idle_age: Duration,
-    pub disable_protocol_detection_for_ports: Arc<IndexSet<u16>>,
+    /// Ports for which protocol detection is disabled. These may be updated
+    /// through the admin server.
+    pub disable_protocol_detection_for_ports: SkipPorts,
+    /// Headers that are stripped from responses, in addition to hop-by-hop
+    /// headers, before they are served to clients.
+    pub strip_response_headers: Arc<IndexSet<HeaderName>>,
+    /// If set, repeated request errors are logged at most once per window
+    /// for each class of error and target.
+    pub error_log_dedup_window: Option<Duration>,
+    /// If set, requests that lack this header are assigned a generated
+    /// correlation ID in it, and each request's ID is set on its response.
+    pub correlation_id_header: Option<HeaderName>,
+    /// If true, each request's correlation ID is recorded in its access log
+    /// and trace span, and on the spans of the logs emitted on its behalf.
+    pub correlation_id_echo: bool,
+    /// If set, trusted callers may override the retry and timeout policy of
+    /// their requests' routes.
+    pub caller_overrides: Option<caller_override::Config>,
+    /// Determines which peers may set each controlled header. Inbound, it
+    /// applies to request headers; outbound, to response headers.
+    pub header_trust: header_trust::Policy,
+    /// If set, limits the size of the response headers that upstreams may
+    /// send, as measured for HTTP/2's `SETTINGS_MAX_HEADER_LIST_SIZE`.
+    pub max_response_headers: Option<header_limit::Limit>,
 }
 
 #[derive(Clone, Debug)]
@@ -41,6 +85,10 @@ pub struct ControlConfig {
 pub struct BufferConfig {
     pub dispatch_timeout: Duration,
     pub max_in_flight: usize,
+    pub drain_policy: DrainPolicy,
+    /// If set, requests with less than this much time remaining before their
+    /// dispatch deadline fail immediately rather than being queued.
+    pub min_dispatch_budget: Option<Duration>,
 }
 
 // === impl ServerConfig ===
@@ -51,6 +99,9 @@ impl<A: OrigDstAddr> ServerConfig<A> {
             bind: self.bind.with_orig_dst_addr(orig_dst_addrs),
             buffer: self.buffer,
             h2_settings: self.h2_settings,
+            max_requests_per_connection: self.max_requests_per_connection,
+            h2_reset_limit: self.h2_reset_limit,
+            idle_timeouts: self.idle_timeouts,
         }
     }
 }
@@ -65,6 +116,13 @@ impl<A: OrigDstAddr> ProxyConfig<A> {
             router_capacity: self.router_capacity,
             router_max_idle_age: self.router_max_idle_age,
             disable_protocol_detection_for_ports: self.disable_protocol_detection_for_ports,
+            strip_response_headers: self.strip_response_headers,
+            error_log_dedup_window: self.error_log_dedup_window,
+            correlation_id_header: self.correlation_id_header,
+            correlation_id_echo: self.correlation_id_echo,
+            caller_overrides: self.caller_overrides,
+            header_trust: self.header_trust,
+            max_response_headers: self.max_response_headers,
         }
     }
 }
@@ -0,0 +1,632 @@
+//! Limits the number of connections that are open to a destination at once.
+//!
+//! Some upstreams (e.g. databases fronted by HTTP adapters) can only serve a
+//! few connections from each client. Each configured limit is enforced by a
+//! semaphore, a permit of which is held for the lifetime of each connection to
+//! a matching endpoint, from the start of its connect until it is dropped.
+//!
+//! Connects beyond a limit wait for a connection to close. If none closes
+//! within the configured wait, the connect fails with
+//! `ConnectionLimitExceeded`.
+//!
+//! Endpoints match a limit by the name of the logical destination that they
+//! serve, or by their address. The logical name is the one the application
+//! addressed, so a destination's limit also covers the endpoints of the
+//! concrete destinations that its traffic is split to. Opaque TCP forwards
+//! are only matched by address, since they have no destination name.
+//!
+//! A limit may also be applied to every destination that no configured limit
+//! matches, so that each destination's connections, across all of its
+//! endpoints, are limited independently of every other destination's. These
+//! limits are created as destinations are first connected to and are
+//! forgotten once none of their connections remain.
+
+use crate::transport::connect::HasPeerAddr;
+use crate::{svc, NameAddr};
+use bytes::Buf;
+use futures::{try_ready, Async, Future, Poll};
+use indexmap::IndexMap;
+use ipnet::{Contains, IpNet};
+use linkerd2_error::Error;
+use linkerd2_metrics::{metrics, FmtLabels, FmtMetrics, Gauge};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use std::{error, fmt, io};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_sync::semaphore::{Permit, Semaphore};
+use tokio_timer::{clock, Delay};
+use tracing::{debug, trace};
+
+metrics! {
+    connection_limit_open_connections: Gauge {
+        "Number of open connections to each destination whose connections are limited"
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The maximum number of connections to each matching destination. An
+    /// endpoint is limited by the first limit that it matches.
+    pub limits: Vec<(Match, usize)>,
+    /// If set, the maximum number of connections to each named destination
+    /// that `limits` doesn't match.
+    pub max_per_dst: Option<usize>,
+    /// The time a connect may wait for a connection to close once its
+    /// destination's limit is reached.
+    pub max_wait: Duration,
+}
+
+/// Matches the endpoints of a limited destination.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Match {
+    /// Endpoints of the named logical destination.
+    Name(NameAddr),
+    /// Endpoints whose addresses are within the network.
+    Network(IpNet),
+}
+
+/// Provides the name of the logical destination that an endpoint serves, if
+/// it was discovered.
+pub trait HasDstName {
+    fn dst_name(&self) -> Option<&NameAddr>;
+}
+
+/// Reports the open connections to each limited destination.
+#[derive(Clone, Debug, Default)]
+pub struct Registry(Arc<Mutex<Vec<Weak<Limit>>>>);
+
+/// A connect failed because its destination's connection limit was reached.
+#[derive(Debug)]
+pub struct ConnectionLimitExceeded {
+    dst: Match,
+    max: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Layer {
+    limits: Arc<Vec<Arc<Limit>>>,
+    per_dst: Option<PerDst>,
+    max_wait: Duration,
+}
+
+#[derive(Clone, Debug)]
+pub struct Service<M> {
+    inner: M,
+    limits: Arc<Vec<Arc<Limit>>>,
+    per_dst: Option<PerDst>,
+    max_wait: Duration,
+}
+
+/// Creates a limit for each destination as it's first connected to.
+#[derive(Clone, Debug)]
+struct PerDst {
+    max: usize,
+    by_dst: Arc<Mutex<IndexMap<NameAddr, Weak<Limit>>>>,
+    registry: Registry,
+}
+
+pub struct ConnectFuture<M, T>
+where
+    M: svc::Service<T>,
+{
+    state: State<M, T>,
+    permit: Option<(Arc<Limit>, Permit)>,
+}
+
+enum State<M, T>
+where
+    M: svc::Service<T>,
+{
+    Waiting(Option<(M, T)>, Delay),
+    Connecting(M::Future),
+}
+
+/// A connection that holds its destination's permit until it's dropped.
+pub struct Connection<C> {
+    io: C,
+    _held: Option<Held>,
+}
+
+#[derive(Debug)]
+struct Limit {
+    dst: Match,
+    max: usize,
+    semaphore: Semaphore,
+    open: AtomicUsize,
+}
+
+/// An established connection's permit.
+struct Held {
+    limit: Arc<Limit>,
+    permit: Permit,
+}
+
+/// Limits the connections to each of `config`'s destinations, reporting their
+/// open connections to `registry`.
+pub fn layer(config: Config, registry: Registry) -> Layer {
+    let limits = config
+        .limits
+        .into_iter()
+        .map(|(dst, max)| Arc::new(Limit::new(dst, max)))
+        .collect::<Vec<_>>();
+    registry.register(&limits);
+    let per_dst = config.max_per_dst.map(|max| PerDst {
+        max,
+        by_dst: Arc::default(),
+        registry,
+    });
+    Layer {
+        limits: Arc::new(limits),
+        per_dst,
+        max_wait: config.max_wait,
+    }
+}
+
+// === impl Match ===
+
+impl Match {
+    fn matches<T: HasPeerAddr + HasDstName>(&self, target: &T) -> bool {
+        match self {
+            Match::Name(name) => target.dst_name() == Some(name),
+            Match::Network(net) => match (net, target.peer_addr().ip()) {
+                (IpNet::V4(net), IpAddr::V4(addr)) => net.contains(&addr),
+                (IpNet::V6(net), IpAddr::V6(addr)) => net.contains(&addr),
+                _ => false,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Match {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Match::Name(name) => name.fmt(f),
+            Match::Network(net) => net.fmt(f),
+        }
+    }
+}
+
+// === impl Registry ===
+
+impl Registry {
+    fn register(&self, limits: &[Arc<Limit>]) {
+        if let Ok(mut registered) = self.0.lock() {
+            // Forget the limits that have been dropped.
+            registered.retain(|l| l.upgrade().is_some());
+            registered.extend(limits.iter().map(Arc::downgrade));
+        }
+    }
+}
+
+impl FmtMetrics for Registry {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let limits = match self.0.lock() {
+            Ok(limits) => limits.iter().filter_map(Weak::upgrade).collect::<Vec<_>>(),
+            Err(_) => return Ok(()),
+        };
+        if limits.is_empty() {
+            return Ok(());
+        }
+
+        let open = limits
+            .iter()
+            .map(|l| (&l.dst, Gauge::from(l.open() as u64)))
+            .collect::<Vec<_>>();
+        connection_limit_open_connections.fmt_help(f)?;
+        connection_limit_open_connections.fmt_scopes(
+            f,
+            open.iter().map(|(dst, open)| (*dst, open)),
+            |g| g,
+        )?;
+
+        Ok(())
+    }
+}
+
+impl FmtLabels for Match {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "dst=\"{}\"", self)
+    }
+}
+
+// === impl ConnectionLimitExceeded ===
+
+impl fmt::Display for ConnectionLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "connection limit of {} reached for {}",
+            self.max, self.dst
+        )
+    }
+}
+
+impl error::Error for ConnectionLimitExceeded {}
+
+// === impl Layer ===
+
+impl<M> svc::Layer<M> for Layer {
+    type Service = Service<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        Service {
+            inner,
+            limits: self.limits.clone(),
+            per_dst: self.per_dst.clone(),
+            max_wait: self.max_wait,
+        }
+    }
+}
+
+// === impl Service ===
+
+impl<T, M> svc::Service<T> for Service<M>
+where
+    T: HasPeerAddr + HasDstName,
+    M: svc::Service<T> + Clone,
+    M::Error: Into<Error>,
+{
+    type Response = Connection<M::Response>;
+    type Error = Error;
+    type Future = ConnectFuture<M, T>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let limit = match self.limits.iter().find(|l| l.dst.matches(&target)) {
+            Some(limit) => Some(limit.clone()),
+            None => match (self.per_dst.as_ref(), target.dst_name()) {
+                (Some(per_dst), Some(name)) => Some(per_dst.limit(name)),
+                _ => None,
+            },
+        };
+
+        match limit {
+            Some(limit) => ConnectFuture {
+                state: State::Waiting(
+                    Some((self.inner.clone(), target)),
+                    Delay::new(clock::now() + self.max_wait),
+                ),
+                permit: Some((limit, Permit::new())),
+            },
+            None => ConnectFuture {
+                state: State::Connecting(self.inner.call(target)),
+                permit: None,
+            },
+        }
+    }
+}
+
+// === impl ConnectFuture ===
+
+impl<M, T> Future for ConnectFuture<M, T>
+where
+    M: svc::Service<T>,
+    M::Error: Into<Error>,
+{
+    type Item = Connection<M::Response>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            self.state = match self.state {
+                State::Waiting(ref mut waiting, ref mut timeout) => {
+                    if let Some((ref limit, ref mut permit)) = self.permit {
+                        match permit.poll_acquire(&limit.semaphore) {
+                            // The semaphore is never closed.
+                            Ok(Async::Ready(())) | Err(_) => {}
+                            Ok(Async::NotReady) => {
+                                // Timer errors are treated as though the wait
+                                // has elapsed.
+                                if let Ok(Async::NotReady) = timeout.poll() {
+                                    trace!(dst = %limit.dst, "waiting for a connection to close");
+                                    return Ok(Async::NotReady);
+                                }
+                                return Err(limit.exceeded());
+                            }
+                        }
+                    }
+
+                    {
+                        let (inner, _) = waiting.as_mut().expect("polled after ready");
+                        try_ready!(inner.poll_ready().map_err(Into::into));
+                    }
+                    let (mut inner, target) = waiting.take().expect("polled after ready");
+                    State::Connecting(inner.call(target))
+                }
+                State::Connecting(ref mut f) => {
+                    let io = try_ready!(f.poll().map_err(Into::into));
+                    let held = self.permit.take().map(|(limit, permit)| {
+                        limit.open.fetch_add(1, Ordering::AcqRel);
+                        Held { limit, permit }
+                    });
+                    return Ok(Async::Ready(Connection { io, _held: held }));
+                }
+            };
+        }
+    }
+}
+
+impl<M, T> Drop for ConnectFuture<M, T>
+where
+    M: svc::Service<T>,
+{
+    fn drop(&mut self) {
+        // Frees the permit of a connect that failed or was abandoned.
+        if let Some((limit, mut permit)) = self.permit.take() {
+            permit.release(&limit.semaphore);
+        }
+    }
+}
+
+// === impl PerDst ===
+
+impl PerDst {
+    /// Returns the limit of `dst`'s connections, creating it if none of its
+    /// connections remain.
+    fn limit(&self, dst: &NameAddr) -> Arc<Limit> {
+        let mut by_dst = match self.by_dst.lock() {
+            Ok(by_dst) => by_dst,
+            // The limit is not shared if the lock is poisoned.
+            Err(_) => return Arc::new(Limit::new(Match::Name(dst.clone()), self.max)),
+        };
+        if let Some(limit) = by_dst.get(dst).and_then(Weak::upgrade) {
+            return limit;
+        }
+
+        trace!(%dst, max = self.max, "limiting destination");
+        let limit = Arc::new(Limit::new(Match::Name(dst.clone()), self.max));
+        by_dst.retain(|_, l| l.upgrade().is_some());
+        by_dst.insert(dst.clone(), Arc::downgrade(&limit));
+        self.registry.register(&[limit.clone()]);
+        limit
+    }
+}
+
+// === impl Limit ===
+
+impl Limit {
+    fn new(dst: Match, max: usize) -> Self {
+        let max = max.max(1);
+        Self {
+            dst,
+            max,
+            semaphore: Semaphore::new(max),
+            open: AtomicUsize::new(0),
+        }
+    }
+
+    fn open(&self) -> usize {
+        self.open.load(Ordering::Acquire)
+    }
+
+    fn exceeded(&self) -> Error {
+        debug!(dst = %self.dst, max = self.max, "connection limit reached");
+        ConnectionLimitExceeded {
+            dst: self.dst.clone(),
+            max: self.max,
+        }
+        .into()
+    }
+}
+
+// === impl Held ===
+
+impl Drop for Held {
+    fn drop(&mut self) {
+        self.limit.open.fetch_sub(1, Ordering::AcqRel);
+        self.permit.release(&self.limit.semaphore);
+    }
+}
+
+// === impl Connection ===
+
+impl<C: io::Read> io::Read for Connection<C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.io.read(buf)
+    }
+}
+
+impl<C: io::Write> io::Write for Connection<C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<C: AsyncRead> AsyncRead for Connection<C> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.io.prepare_uninitialized_buffer(buf)
+    }
+}
+
+impl<C: AsyncWrite> AsyncWrite for Connection<C> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+
+    fn write_buf<B: Buf>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
+        self.io.write_buf(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use std::net::SocketAddr;
+    use svc::{Layer as _, Service as _};
+    use tokio::runtime::current_thread::Runtime;
+
+    #[derive(Clone, Debug)]
+    struct Target(SocketAddr);
+
+    impl HasPeerAddr for Target {
+        fn peer_addr(&self) -> SocketAddr {
+            self.0
+        }
+    }
+
+    impl HasDstName for Target {
+        fn dst_name(&self) -> Option<&NameAddr> {
+            None
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    struct Named(SocketAddr, NameAddr);
+
+    impl HasPeerAddr for Named {
+        fn peer_addr(&self) -> SocketAddr {
+            self.0
+        }
+    }
+
+    impl HasDstName for Named {
+        fn dst_name(&self) -> Option<&NameAddr> {
+            Some(&self.1)
+        }
+    }
+
+    fn open_connections(registry: &Registry) -> String {
+        registry
+            .as_display()
+            .to_string()
+            .lines()
+            .find(|l| l.starts_with("connection_limit_open_connections{"))
+            .expect("gauge must be reported")
+            .to_owned()
+    }
+
+    #[test]
+    fn connections_beyond_the_limit_wait_and_fail() {
+        let registry = Registry::default();
+        let config = Config {
+            limits: vec![(Match::Network("10.1.0.0/16".parse().unwrap()), 2)],
+            max_per_dst: None,
+            max_wait: Duration::from_millis(10),
+        };
+        let mut connect =
+            layer(config, registry.clone()).layer(svc::mk(|_: Target| future::ok::<_, Error>(())));
+        let target = Target(([10, 1, 1, 1], 8080).into());
+
+        let mut rt = Runtime::new().unwrap();
+        let held = rt
+            .block_on(future::join_all(vec![
+                connect.call(target.clone()),
+                connect.call(target.clone()),
+            ]))
+            .expect("connections within the limit must be established");
+        assert_eq!(
+            open_connections(&registry),
+            "connection_limit_open_connections{dst=\"10.1.0.0/16\"} 2"
+        );
+
+        // The third connection waits for one to close...
+        let mut third = connect.call(target.clone());
+        let waiting = rt
+            .block_on(future::lazy(|| third.poll()))
+            .expect("connect must wait");
+        assert!(waiting.is_not_ready());
+
+        // ...and fails once the wait elapses.
+        let error = rt
+            .block_on(third)
+            .err()
+            .expect("connect must fail once the wait elapses");
+        assert!(error.is::<ConnectionLimitExceeded>(), "{}", error);
+        assert_eq!(
+            open_connections(&registry),
+            "connection_limit_open_connections{dst=\"10.1.0.0/16\"} 2"
+        );
+
+        // Once a connection closes, another may be established.
+        drop(held);
+        let _held = rt
+            .block_on(connect.call(target))
+            .expect("connection must be established after one closes");
+        assert_eq!(
+            open_connections(&registry),
+            "connection_limit_open_connections{dst=\"10.1.0.0/16\"} 1"
+        );
+    }
+
+    #[test]
+    fn unmatched_connections_are_unlimited() {
+        let registry = Registry::default();
+        let config = Config {
+            limits: vec![(Match::Network("10.1.0.0/16".parse().unwrap()), 1)],
+            max_per_dst: None,
+            max_wait: Duration::from_millis(10),
+        };
+        let mut connect =
+            layer(config, registry.clone()).layer(svc::mk(|_: Target| future::ok::<_, Error>(())));
+        let target = Target(([10, 2, 1, 1], 8080).into());
+
+        let conns = (0..3)
+            .map(|_| connect.call(target.clone()).wait())
+            .collect::<Result<Vec<_>, _>>()
+            .expect("unmatched connections must not be limited");
+        assert_eq!(conns.len(), 3);
+        assert_eq!(
+            open_connections(&registry),
+            "connection_limit_open_connections{dst=\"10.1.0.0/16\"} 0"
+        );
+    }
+
+    #[test]
+    fn each_destination_is_limited_independently() {
+        let registry = Registry::default();
+        let config = Config {
+            limits: vec![],
+            max_per_dst: Some(2),
+            max_wait: Duration::from_millis(10),
+        };
+        let mut connect =
+            layer(config, registry.clone()).layer(svc::mk(|_: Named| future::ok::<_, Error>(())));
+        let web = NameAddr::from_str("web.ns.svc.cluster.local:8080").unwrap();
+        let db = NameAddr::from_str("db.ns.svc.cluster.local:5432").unwrap();
+
+        // The destination's connections are limited across its endpoints.
+        let mut rt = Runtime::new().unwrap();
+        let held = rt
+            .block_on(future::join_all(vec![
+                connect.call(Named(([10, 1, 1, 1], 8080).into(), web.clone())),
+                connect.call(Named(([10, 1, 1, 2], 8080).into(), web.clone())),
+            ]))
+            .expect("connections within the limit must be established");
+        let error = rt
+            .block_on(connect.call(Named(([10, 1, 1, 3], 8080).into(), web.clone())))
+            .err()
+            .expect("connect must fail once the wait elapses");
+        assert!(error.is::<ConnectionLimitExceeded>(), "{}", error);
+
+        // Other destinations are unaffected.
+        let _db = rt
+            .block_on(future::join_all(vec![
+                connect.call(Named(([10, 2, 1, 1], 5432).into(), db.clone())),
+                connect.call(Named(([10, 2, 1, 1], 5432).into(), db.clone())),
+            ]))
+            .expect("other destinations' connections must not be limited");
+
+        let report = registry.as_display().to_string();
+        assert!(report.contains(
+            "connection_limit_open_connections{dst=\"web.ns.svc.cluster.local:8080\"} 2\n"
+        ));
+        assert!(report.contains(
+            "connection_limit_open_connections{dst=\"db.ns.svc.cluster.local:5432\"} 2\n"
+        ));
+
+        // Once its connections close, the destination's limit is forgotten.
+        drop(held);
+        let report = registry.as_display().to_string();
+        assert!(!report.contains("web.ns.svc.cluster.local"));
+    }
+}
@@ -0,0 +1,43 @@
+//! Reports the requests that buffers failed without queueing because their
+//! dispatch deadline was too soon to be met.
+
+use super::metric_labels::Direction;
+use crate::proxy::buffer::ShedCount;
+use linkerd2_metrics::{metrics, Counter, FmtMetrics};
+use std::fmt;
+
+metrics! {
+    request_deadline_shed_total: Counter {
+        "Total count of requests that were failed without being queued because their dispatch deadline was too soon to be met"
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    inbound: ShedCount,
+    outbound: ShedCount,
+}
+
+impl Metrics {
+    pub fn inbound(&self) -> ShedCount {
+        self.inbound.clone()
+    }
+
+    pub fn outbound(&self) -> ShedCount {
+        self.outbound.clone()
+    }
+}
+
+impl FmtMetrics for Metrics {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scopes = [
+            (Direction::In, Counter::from(self.inbound.value())),
+            (Direction::Out, Counter::from(self.outbound.value())),
+        ];
+
+        request_deadline_shed_total.fmt_help(f)?;
+        request_deadline_shed_total.fmt_scopes(f, scopes.iter().map(|(d, c)| (*d, c)), |c| c)?;
+
+        Ok(())
+    }
+}
@@ -3,8 +3,9 @@ use http;
 use indexmap::IndexMap;
 use linkerd2_addr::{Addr, NameAddr};
 use linkerd2_proxy_http::{
+    affinity, coalesce,
     metrics::classify::{CanClassify, Classify, ClassifyEos, ClassifyResponse},
-    profiles, retry, settings, timeout,
+    normalize_uri, profiles, retry, settings, timeout, transform,
 };
 use std::fmt;
 use std::sync::Arc;
@@ -63,6 +64,36 @@ impl timeout::HasTimeout for Route {
     }
 }
 
+impl profiles::failover::HasBackup for Route {
+    fn backup(&self) -> Option<NameAddr> {
+        self.route.backup().cloned()
+    }
+}
+
+impl coalesce::CanCoalesce for Route {
+    fn can_coalesce(&self) -> bool {
+        self.route.coalesce()
+    }
+}
+
+impl normalize_uri::ShouldPreserveScheme for Route {
+    fn should_preserve_scheme(&self) -> bool {
+        self.route.preserve_scheme()
+    }
+}
+
+impl affinity::HasAffinityCookie for Route {
+    fn affinity_cookie(&self) -> Option<&str> {
+        self.route.affinity_cookie()
+    }
+}
+
+impl transform::HasTransform for Route {
+    fn transform(&self) -> Option<transform::Transform> {
+        self.route.transform().cloned()
+    }
+}
+
 // === impl Retry ===
 
 impl retry::Retry for Retry {
@@ -71,9 +102,11 @@ impl retry::Retry for Retry {
         req: &http::Request<B1>,
         res: &http::Response<B2>,
     ) -> Result<(), retry::NoRetry> {
+        // Retries are decided before the response body is read.
         let class = classify::Request::from(self.response_classes.clone())
             .classify(req)
             .start(res)
+            .headers_only()
             .eos(None);
 
         if class.is_failure() {
@@ -87,6 +120,12 @@ impl retry::Retry for Retry {
         Err(retry::NoRetry::Success)
     }
 
+    fn retry_reset<B>(&self, _: &http::Request<B>) -> Result<(), retry::NoRetry> {
+        self.budget
+            .withdraw()
+            .map_err(|_overdrawn| retry::NoRetry::Budget)
+    }
+
     fn clone_request<B: retry::TryClone>(
         &self,
         req: &http::Request<B>,
@@ -0,0 +1,321 @@
+//! Detects requests whose destination headers disagree.
+//!
+//! Outbound requests are routed by the first destination found in the
+//! `l5d-dst-override` header, the request's authority, or its `Host` header.
+//! When more than one of these is present and they name different
+//! destinations, the lower-precedence values are silently ignored. This
+//! module can surface these conflicts by logging and counting them, or by
+//! rejecting such requests outright.
+//!
+//! Every request from a misconfigured client typically conflicts the same way,
+//! so each kind of conflict is logged at most once per `WARN_INTERVAL`, along
+//! with the number of conflicts that weren't logged since; all conflicts are
+//! counted.
+
+use crate::errors::StatusError;
+use crate::{
+    http_request_authority_addr, http_request_host_addr, http_request_l5d_override_dst_addr,
+    request_filter::RequestFilter, Addr,
+};
+use indexmap::IndexMap;
+use linkerd2_metrics::{metrics, Counter, FmtLabels, FmtMetrics};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::clock;
+use tracing::{debug, warn};
+
+metrics! {
+    request_dst_conflict_total: Counter {
+        "Total count of requests with conflicting destination headers"
+    }
+}
+
+/// Each kind of conflict is logged at most once in this interval.
+const WARN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Determines how requests with conflicting destination headers are handled.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// The highest-precedence destination is used without any signal.
+    Ignore,
+    /// The highest-precedence destination is used, but the conflict is
+    /// counted and periodically logged.
+    Log,
+    /// The request is failed with a 400 response.
+    Reject,
+}
+
+#[derive(Clone, Debug)]
+pub struct Filter {
+    policy: Policy,
+    metrics: Metrics,
+    warnings: Warnings,
+}
+
+/// Tracks when each kind of conflict was last logged.
+#[derive(Clone, Debug, Default)]
+struct Warnings(Arc<Mutex<IndexMap<Conflict, Warned>>>);
+
+#[derive(Debug)]
+struct Warned {
+    at: Option<Instant>,
+    suppressed: u64,
+}
+
+/// Counts conflicts by the pair of sources that disagreed.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics(Arc<Mutex<IndexMap<Conflict, Counter>>>);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+struct Conflict(Source, Source);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum Source {
+    Override,
+    Authority,
+    Host,
+}
+
+// === impl Policy ===
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy::Ignore
+    }
+}
+
+// === impl Filter ===
+
+impl Filter {
+    pub fn new(policy: Policy, metrics: Metrics) -> Self {
+        Self {
+            policy,
+            metrics,
+            warnings: Warnings::default(),
+        }
+    }
+}
+
+impl<B> RequestFilter<http::Request<B>> for Filter {
+    type Error = StatusError;
+
+    fn filter(&self, req: http::Request<B>) -> Result<http::Request<B>, Self::Error> {
+        if self.policy == Policy::Ignore {
+            return Ok(req);
+        }
+
+        let conflict = match find_conflict(&req) {
+            None => return Ok(req),
+            Some(conflict) => conflict,
+        };
+        self.metrics.incr(conflict);
+
+        if self.policy == Policy::Reject {
+            debug!(%conflict, "rejecting request");
+            return Err(StatusError {
+                status: http::StatusCode::BAD_REQUEST,
+                message: format!("conflicting destinations in request {}", conflict),
+            });
+        }
+
+        match self.warnings.warn(conflict, clock::now()) {
+            Some(suppressed) => {
+                warn!(%conflict, suppressed, "request has conflicting destinations")
+            }
+            None => debug!(%conflict, "request has conflicting destinations"),
+        }
+        Ok(req)
+    }
+}
+
+/// Returns the first pair of destination sources, in order of precedence,
+/// that name different destinations.
+fn find_conflict<B>(req: &http::Request<B>) -> Option<Conflict> {
+    let dsts: Vec<(Source, Addr)> = vec![
+        (Source::Override, http_request_l5d_override_dst_addr(req)),
+        (Source::Authority, http_request_authority_addr(req)),
+        (Source::Host, http_request_host_addr(req)),
+    ]
+    .into_iter()
+    .filter_map(|(src, addr)| addr.ok().map(|a| (src, a)))
+    .collect();
+
+    let (primary, primary_addr) = dsts.first()?;
+    dsts.iter()
+        .skip(1)
+        .find(|(_, addr)| addr != primary_addr)
+        .map(|(src, _)| Conflict(*primary, *src))
+}
+
+// === impl Warnings ===
+
+impl Warnings {
+    /// Returns the number of conflicts that were not logged since `conflict`
+    /// was last logged, if it should be logged at `now`.
+    fn warn(&self, conflict: Conflict, now: Instant) -> Option<u64> {
+        let mut warnings = self.0.lock().ok()?;
+        let warned = warnings.entry(conflict).or_insert(Warned {
+            at: None,
+            suppressed: 0,
+        });
+        if let Some(at) = warned.at {
+            if now.duration_since(at) < WARN_INTERVAL {
+                warned.suppressed += 1;
+                return None;
+            }
+        }
+
+        let suppressed = warned.suppressed;
+        *warned = Warned {
+            at: Some(now),
+            suppressed: 0,
+        };
+        Some(suppressed)
+    }
+}
+
+// === impl Metrics ===
+
+impl Metrics {
+    fn incr(&self, conflict: Conflict) {
+        if let Ok(mut by_conflict) = self.0.lock() {
+            by_conflict
+                .entry(conflict)
+                .or_insert_with(Counter::default)
+                .incr();
+        }
+    }
+}
+
+impl FmtMetrics for Metrics {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let by_conflict = match self.0.lock() {
+            Ok(by_conflict) => by_conflict,
+            Err(_) => return Ok(()),
+        };
+        if by_conflict.is_empty() {
+            return Ok(());
+        }
+
+        request_dst_conflict_total.fmt_help(f)?;
+        request_dst_conflict_total.fmt_scopes(f, by_conflict.iter(), |c| c)?;
+
+        Ok(())
+    }
+}
+
+// === impl Conflict ===
+
+impl FmtLabels for Conflict {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "used=\"{}\",ignored=\"{}\"", self.0, self.1)
+    }
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} conflicts with {}", self.0, self.1)
+    }
+}
+
+// === impl Source ===
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Override => f.pad("override"),
+            Source::Authority => f.pad("authority"),
+            Source::Host => f.pad("host"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DST_OVERRIDE_HEADER;
+
+    fn conflicting() -> http::Request<()> {
+        http::Request::builder()
+            .uri("http://web.example.com:8080/")
+            .header(DST_OVERRIDE_HEADER, "api.example.com:8080")
+            .body(())
+            .unwrap()
+    }
+
+    fn new_filter(policy: Policy) -> (Filter, Metrics) {
+        let metrics = Metrics::default();
+        (Filter::new(policy, metrics.clone()), metrics)
+    }
+
+    #[test]
+    fn ignores_conflicts_by_default() {
+        let (filter, metrics) = new_filter(Policy::default());
+        assert!(filter.filter(conflicting()).is_ok());
+        assert!(metrics.as_display().to_string().is_empty());
+    }
+
+    #[test]
+    fn logs_and_counts_conflicts() {
+        let (filter, metrics) = new_filter(Policy::Log);
+        assert!(filter.filter(conflicting()).is_ok());
+        assert!(metrics
+            .as_display()
+            .to_string()
+            .contains("request_dst_conflict_total{used=\"override\",ignored=\"authority\"} 1"));
+    }
+
+    #[test]
+    fn logs_each_kind_of_conflict_once_per_interval() {
+        let warnings = Warnings::default();
+        let t0 = Instant::now();
+        let by_authority = Conflict(Source::Override, Source::Authority);
+        let by_host = Conflict(Source::Authority, Source::Host);
+
+        assert_eq!(warnings.warn(by_authority, t0), Some(0));
+        assert_eq!(
+            warnings.warn(by_authority, t0 + Duration::from_secs(1)),
+            None
+        );
+        assert_eq!(
+            warnings.warn(by_authority, t0 + Duration::from_secs(2)),
+            None
+        );
+        assert_eq!(
+            warnings.warn(by_host, t0 + Duration::from_secs(2)),
+            Some(0),
+            "other kinds of conflicts must be logged independently"
+        );
+        assert_eq!(
+            warnings.warn(by_authority, t0 + WARN_INTERVAL),
+            Some(2),
+            "suppressed conflicts must be reported once the interval elapses"
+        );
+        assert_eq!(warnings.warn(by_authority, t0 + WARN_INTERVAL), None);
+    }
+
+    #[test]
+    fn rejects_conflicts() {
+        let (filter, metrics) = new_filter(Policy::Reject);
+        let err = filter.filter(conflicting()).expect_err("must be rejected");
+        assert_eq!(err.status, http::StatusCode::BAD_REQUEST);
+        assert!(metrics
+            .as_display()
+            .to_string()
+            .contains("request_dst_conflict_total{used=\"override\",ignored=\"authority\"} 1"));
+    }
+
+    #[test]
+    fn permits_consistent_destinations() {
+        let (filter, metrics) = new_filter(Policy::Reject);
+        let req = http::Request::builder()
+            .uri("http://web.example.com:8080/")
+            .header(DST_OVERRIDE_HEADER, "web.example.com:8080")
+            .header(http::header::HOST, "web.example.com:8080")
+            .body(())
+            .unwrap();
+        assert!(filter.filter(req).is_ok());
+        assert!(metrics.as_display().to_string().is_empty());
+    }
+}
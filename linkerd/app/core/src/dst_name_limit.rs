@@ -0,0 +1,210 @@
+//! Bounds the number of distinct destination names each inbound source may
+//! use.
+//!
+//! Inbound requests are routed by a destination name taken from
+//! client-controlled headers, and each distinct name is given its own
+//! profile lookup and set of metrics. Once a source has used its allotment of
+//! names within a window, requests naming further destinations are routed by
+//! their original destination address instead.
+//!
+//! Sources are distinguished by their identities. Peers without an identity
+//! are distinguished by their IP addresses, so that unauthenticated clients
+//! don't share a single allotment.
+
+use crate::{proxy::identity, transport::tls, Conditional, NameAddr};
+use indexmap::{IndexMap, IndexSet};
+use linkerd2_metrics::{metrics, Counter, FmtMetrics};
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_timer::clock;
+use tracing::warn;
+
+metrics! {
+    inbound_dst_name_collapsed_total: Counter {
+        "Total count of inbound requests whose destination name was ignored because their source exceeded its name limit"
+    }
+}
+
+/// The source to which a limit applies.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Source {
+    Identity(identity::Name),
+    /// A peer without an identity.
+    Addr(IpAddr),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Limit {
+    inner: Arc<Mutex<Inner>>,
+    config: Option<Config>,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Config {
+    max_names: usize,
+    window: Duration,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    by_src: IndexMap<Source, Window>,
+    collapsed: Counter,
+}
+
+#[derive(Debug)]
+struct Window {
+    started: Instant,
+    names: IndexSet<NameAddr>,
+    warned: bool,
+}
+
+// === impl Limit ===
+
+impl Limit {
+    /// Permits each source to use at most `max_names` distinct
+    /// destination names per `window`.
+    ///
+    /// If `max_names` is `None`, all names are permitted.
+    pub fn with_max_names(self, max_names: Option<usize>, window: Duration) -> Self {
+        Self {
+            config: max_names.map(|max_names| Config { max_names, window }),
+            ..self
+        }
+    }
+
+    /// Returns true if `src` may route a request to `dst` by name.
+    pub fn permit(&self, src: &Source, dst: &NameAddr) -> bool {
+        let Config { max_names, window } = match self.config {
+            Some(config) => config,
+            None => return true,
+        };
+        let mut inner = match self.inner.lock() {
+            Ok(inner) => inner,
+            Err(_) => return true,
+        };
+
+        let now = clock::now();
+        if !inner.by_src.contains_key(src) {
+            // Sources that have not been seen for a full window are
+            // forgotten so that the state stays small.
+            inner.by_src.retain(|_, w| now - w.started < window);
+        }
+        let w = inner.by_src.entry(src.clone()).or_insert_with(|| Window {
+            started: now,
+            names: IndexSet::new(),
+            warned: false,
+        });
+        if now - w.started >= window {
+            w.started = now;
+            w.names.clear();
+            w.warned = false;
+        }
+
+        if w.names.contains(dst) {
+            return true;
+        }
+        if w.names.len() < max_names {
+            w.names.insert(dst.clone());
+            return true;
+        }
+
+        if !w.warned {
+            w.warned = true;
+            warn!(
+                src.id = ?src,
+                %dst,
+                max_names = max_names,
+                "source exceeded its destination name limit; routing by address",
+            );
+        }
+        inner.collapsed.incr();
+        false
+    }
+}
+
+impl FmtMetrics for Limit {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = match self.inner.lock() {
+            Ok(inner) => inner,
+            Err(_) => return Ok(()),
+        };
+
+        inbound_dst_name_collapsed_total.fmt_help(f)?;
+        inbound_dst_name_collapsed_total.fmt_metric(f, inner.collapsed)?;
+
+        Ok(())
+    }
+}
+
+// === impl Source ===
+
+impl<'a> From<&'a tls::accept::Meta> for Source {
+    fn from(meta: &'a tls::accept::Meta) -> Self {
+        match meta.peer_identity {
+            Conditional::Some(ref id) => Source::Identity(id.clone()),
+            Conditional::None(_) => Source::Addr(meta.addrs.peer().ip()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn src(name: &str) -> Source {
+        Source::Identity(identity::Name::from_hostname(name.as_bytes()).unwrap())
+    }
+
+    fn dst(n: usize) -> NameAddr {
+        NameAddr::from_str(&format!("svc{}.ns.svc.cluster.local:8080", n)).unwrap()
+    }
+
+    #[test]
+    fn collapses_names_beyond_limit_per_source() {
+        let limit = Limit::default().with_max_names(Some(2), Duration::from_secs(60));
+        let noisy = src("noisy.ns.serviceaccount.identity.linkerd.cluster.local");
+        let quiet = src("quiet.ns.serviceaccount.identity.linkerd.cluster.local");
+
+        assert!(limit.permit(&noisy, &dst(0)));
+        assert!(limit.permit(&noisy, &dst(1)));
+        assert!(!limit.permit(&noisy, &dst(2)));
+        assert!(!limit.permit(&noisy, &dst(3)));
+        assert!(
+            limit.permit(&noisy, &dst(1)),
+            "names within the limit must still be permitted"
+        );
+
+        assert!(limit.permit(&quiet, &dst(2)));
+        assert!(limit.permit(&quiet, &dst(3)));
+
+        assert!(limit
+            .as_display()
+            .to_string()
+            .contains("inbound_dst_name_collapsed_total 2"));
+    }
+
+    #[test]
+    fn unidentified_peers_are_limited_by_address() {
+        let limit = Limit::default().with_max_names(Some(1), Duration::from_secs(60));
+        let a = Source::Addr([10, 1, 1, 1].into());
+        let b = Source::Addr([10, 1, 1, 2].into());
+
+        assert!(limit.permit(&a, &dst(0)));
+        assert!(!limit.permit(&a, &dst(1)));
+        assert!(
+            limit.permit(&b, &dst(1)),
+            "unidentified peers must not share an allotment"
+        );
+    }
+
+    #[test]
+    fn no_limit_by_default() {
+        let limit = Limit::default();
+        let a = Source::Addr([10, 1, 1, 1].into());
+        for n in 0..100 {
+            assert!(limit.permit(&a, &dst(n)));
+        }
+    }
+}
@@ -0,0 +1,341 @@
+//! Correlates request timeouts with the endpoints that were serving them.
+//!
+//! A route's timeout fires above its balancer, where the endpoint that was
+//! serving the request is unknown. This layer is applied to each endpoint's
+//! stack so that, when a request is abandoned after its timeout fires, the
+//! timeout is counted against the endpoint and logged with its address.
+
+use crate::metric_labels::EndpointLabels;
+use crate::proxy::http::timeout::Deadline;
+use crate::transport::connect::HasPeerAddr;
+use futures::{try_ready, Async, Future, Poll};
+use indexmap::IndexMap;
+use linkerd2_metrics::{metrics, Counter, FmtMetrics};
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+metrics! {
+    endpoint_request_timeout_total: Counter {
+        "Total count of requests that timed out while awaiting a response from an endpoint"
+    }
+}
+
+/// Counts request timeouts by endpoint.
+#[derive(Clone, Debug, Default)]
+pub struct Registry(Arc<Mutex<IndexMap<EndpointLabels, Counter>>>);
+
+#[derive(Clone, Debug)]
+pub struct Layer {
+    registry: Registry,
+}
+
+#[derive(Clone, Debug)]
+pub struct Stack<M> {
+    inner: M,
+    registry: Registry,
+}
+
+pub struct MakeFuture<F> {
+    inner: F,
+    endpoint: Option<Arc<Endpoint>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Service<S> {
+    inner: S,
+    endpoint: Arc<Endpoint>,
+}
+
+pub struct ResponseFuture<F> {
+    inner: F,
+    endpoint: Arc<Endpoint>,
+    /// Cleared once the inner future completes.
+    deadline: Option<Deadline>,
+}
+
+#[derive(Debug)]
+struct Endpoint {
+    addr: SocketAddr,
+    labels: EndpointLabels,
+    registry: Registry,
+}
+
+pub fn layer(registry: Registry) -> Layer {
+    Layer { registry }
+}
+
+// === impl Registry ===
+
+impl Registry {
+    fn incr(&self, labels: &EndpointLabels) {
+        if let Ok(mut by_endpoint) = self.0.lock() {
+            by_endpoint
+                .entry(labels.clone())
+                .or_insert_with(Counter::default)
+                .incr();
+        }
+    }
+}
+
+impl FmtMetrics for Registry {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let by_endpoint = match self.0.lock() {
+            Ok(by_endpoint) => by_endpoint,
+            Err(_) => return Ok(()),
+        };
+        if by_endpoint.is_empty() {
+            return Ok(());
+        }
+
+        endpoint_request_timeout_total.fmt_help(f)?;
+        endpoint_request_timeout_total.fmt_scopes(f, by_endpoint.iter(), |c| c)?;
+
+        Ok(())
+    }
+}
+
+// === impl Layer ===
+
+impl<M> tower::layer::Layer<M> for Layer {
+    type Service = Stack<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        Stack {
+            inner,
+            registry: self.registry.clone(),
+        }
+    }
+}
+
+// === impl Stack ===
+
+impl<T, M> tower::Service<T> for Stack<M>
+where
+    T: HasPeerAddr + Into<EndpointLabels> + Clone,
+    M: tower::Service<T>,
+{
+    type Response = Service<M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let endpoint = Endpoint {
+            addr: target.peer_addr(),
+            labels: target.clone().into(),
+            registry: self.registry.clone(),
+        };
+        let inner = self.inner.call(target);
+
+        MakeFuture {
+            inner,
+            endpoint: Some(Arc::new(endpoint)),
+        }
+    }
+}
+
+// === impl MakeFuture ===
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = Service<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        let endpoint = self.endpoint.take().expect("polled after ready");
+        Ok(Service { inner, endpoint }.into())
+    }
+}
+
+// === impl Service ===
+
+impl<S, B> tower::Service<http::Request<B>> for Service<S>
+where
+    S: tower::Service<http::Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let deadline = req.extensions().get::<Deadline>().cloned();
+        ResponseFuture {
+            inner: self.inner.call(req),
+            endpoint: self.endpoint.clone(),
+            deadline,
+        }
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl<F: Future> Future for ResponseFuture<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.inner.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            ready => {
+                self.deadline = None;
+                ready
+            }
+        }
+    }
+}
+
+impl<F> Drop for ResponseFuture<F> {
+    fn drop(&mut self) {
+        // The request was abandoned before the endpoint responded. If its
+        // timeout has fired, the endpoint is the one that timed out.
+        if let Some(deadline) = self.deadline.take() {
+            if deadline.is_expired() {
+                info!(
+                    peer.addr = %self.endpoint.addr,
+                    "request timed out awaiting endpoint response"
+                );
+                self.endpoint.registry.incr(&self.endpoint.labels);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metric_labels::Direction;
+    use crate::proxy::http::timeout::{self, HasTimeout};
+    use crate::transport::tls;
+    use crate::{svc, Conditional};
+    use futures::future;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use tokio::runtime::current_thread::Runtime;
+    use tower::{layer::Layer as _, Service as _};
+    use tracing::{field, span, Event, Metadata, Subscriber};
+
+    #[derive(Clone, Debug)]
+    struct Target(SocketAddr);
+
+    /// Never responds, so that every request times out.
+    struct Unresponsive;
+
+    /// Captures the message of each event.
+    #[derive(Clone, Default)]
+    struct Capture {
+        messages: Arc<Mutex<Vec<String>>>,
+        next_id: Arc<AtomicUsize>,
+    }
+
+    struct Message<'a>(&'a mut Option<String>);
+
+    impl HasPeerAddr for Target {
+        fn peer_addr(&self) -> SocketAddr {
+            self.0
+        }
+    }
+
+    impl Into<EndpointLabels> for Target {
+        fn into(self) -> EndpointLabels {
+            EndpointLabels {
+                direction: Direction::Out,
+                tls_id: Conditional::None(tls::ReasonForNoIdentity::Disabled),
+                dst_logical: None,
+                dst_concrete: None,
+                labels: Some(format!("pod=\"{}\"", self.0.port())),
+            }
+        }
+    }
+
+    impl HasTimeout for Target {
+        fn timeout(&self) -> Option<Duration> {
+            Some(Duration::from_millis(10))
+        }
+    }
+
+    impl tower::Service<http::Request<()>> for Unresponsive {
+        type Response = http::Response<()>;
+        type Error = crate::Error;
+        type Future = future::Empty<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: http::Request<()>) -> Self::Future {
+            future::empty()
+        }
+    }
+
+    impl Subscriber for Capture {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, _: &span::Attributes<'_>) -> span::Id {
+            span::Id::from_u64(self.next_id.fetch_add(1, Ordering::SeqCst) as u64 + 1)
+        }
+
+        fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut message = None;
+            event.record(&mut Message(&mut message));
+            self.messages.lock().unwrap().extend(message.into_iter());
+        }
+
+        fn enter(&self, _: &span::Id) {}
+
+        fn exit(&self, _: &span::Id) {}
+    }
+
+    impl<'a> field::Visit for Message<'a> {
+        fn record_debug(&mut self, field: &field::Field, value: &dyn fmt::Debug) {
+            if field.name() == "message" {
+                *self.0 = Some(format!("{:?}", value));
+            }
+        }
+    }
+
+    #[test]
+    fn endpoint_timeouts_are_counted_and_logged() {
+        let mut rt = Runtime::new().unwrap();
+        let capture = Capture::default();
+        let registry = Registry::default();
+        let target = Target(([10, 1, 1, 1], 8080).into());
+
+        let rsp = tracing::subscriber::with_default(capture.clone(), || {
+            let stack = layer(registry.clone()).layer(svc::mk(|_: Target| {
+                future::ok::<_, crate::Error>(Unresponsive)
+            }));
+            let mut stack = timeout::layer().layer(stack);
+            let target = target.clone();
+            rt.block_on(future::lazy(move || {
+                stack
+                    .call(target)
+                    .and_then(|mut svc| svc.call(http::Request::new(())))
+            }))
+        })
+        .expect("timeouts must be converted to responses");
+
+        assert_eq!(rsp.status(), http::StatusCode::GATEWAY_TIMEOUT);
+        let by_endpoint = registry.0.lock().unwrap();
+        let labels: EndpointLabels = target.into();
+        assert_eq!(by_endpoint.get(&labels).map(Counter::value), Some(1));
+        assert_eq!(
+            *capture.messages.lock().unwrap(),
+            vec!["request timed out awaiting endpoint response".to_owned()]
+        );
+    }
+}
@@ -0,0 +1,355 @@
+//! Rate-limits error logging on the data path.
+//!
+//! When an upstream fails, every request routed to it fails the same way, and
+//! logging each failure can flood the proxy's logs. When deduplication is
+//! enabled, errors are grouped by direction, error class, and target: the
+//! first error in a group is logged in full once per window, and subsequent
+//! errors are only counted. A `Daemon` periodically logs the number of errors
+//! that were suppressed in each group and forgets groups that have gone idle.
+
+use futures::{Async, Future, Poll, Stream};
+use indexmap::IndexMap;
+use linkerd2_error::Never;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::{clock, timer::Interval};
+use tracing::warn;
+
+/// Logs data path errors, deduplicating them if configured to do so.
+#[derive(Clone, Debug, Default)]
+pub struct ErrorLog(Option<Arc<Mutex<Groups>>>);
+
+/// Periodically logs suppressed error counts and expires idle groups.
+pub struct Daemon {
+    groups: Arc<Mutex<Groups>>,
+    interval: Interval,
+}
+
+#[derive(Debug)]
+struct Groups {
+    direction: &'static str,
+    window: Duration,
+    by_key: IndexMap<Key, Group>,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+struct Key {
+    class: &'static str,
+    target: String,
+}
+
+#[derive(Debug)]
+struct Group {
+    logged_at: Instant,
+    last_seen: Instant,
+    suppressed: u64,
+}
+
+// === impl ErrorLog ===
+
+impl ErrorLog {
+    /// Returns an `ErrorLog` that logs every error.
+    pub fn disabled() -> Self {
+        ErrorLog(None)
+    }
+
+    /// Returns an `ErrorLog` that logs at most one error per group in each
+    /// `window`, along with the `Daemon` that reports suppressed errors.
+    pub fn new(direction: &'static str, window: Duration) -> (Self, Daemon) {
+        let groups = Arc::new(Mutex::new(Groups {
+            direction,
+            window,
+            by_key: IndexMap::default(),
+        }));
+        let daemon = Daemon {
+            groups: groups.clone(),
+            interval: Interval::new(clock::now() + window, window),
+        };
+        (ErrorLog(Some(groups)), daemon)
+    }
+
+    /// Returns an `ErrorLog` for `direction`, deduplicating errors within
+    /// `window` if one is configured.
+    ///
+    /// Must be called on a runtime, as the `Daemon` is spawned.
+    pub fn spawn(direction: &'static str, window: Option<Duration>) -> Self {
+        match window {
+            None => Self::disabled(),
+            Some(window) => {
+                let (log, daemon) = Self::new(direction, window);
+                tokio::spawn(daemon.map_err(|never| match never {}));
+                log
+            }
+        }
+    }
+
+    /// Returns true if errors are deduplicated rather than always logged.
+    pub fn is_deduplicated(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Records an error of the given class for `target`.
+    ///
+    /// If deduplication is disabled, `log` is invoked to log the error as
+    /// usual. Otherwise, the error is logged with its detail only if no other
+    /// error in its group has been logged during the current window.
+    pub fn error<F>(&self, class: &'static str, target: &str, error: &dyn fmt::Display, log: F)
+    where
+        F: FnOnce(),
+    {
+        match self.0 {
+            None => log(),
+            Some(ref groups) => {
+                if let Ok(mut groups) = groups.lock() {
+                    groups.record(class, target, error, clock::now());
+                }
+            }
+        }
+    }
+}
+
+// === impl Groups ===
+
+impl Groups {
+    fn record(
+        &mut self,
+        class: &'static str,
+        target: &str,
+        error: &dyn fmt::Display,
+        now: Instant,
+    ) {
+        let key = Key {
+            class,
+            target: target.to_owned(),
+        };
+        match self.by_key.get_mut(&key) {
+            Some(group) => {
+                group.last_seen = now;
+                if now < group.logged_at + self.window {
+                    group.suppressed += 1;
+                    return;
+                }
+                group.logged_at = now;
+            }
+            None => {
+                self.by_key.insert(
+                    key,
+                    Group {
+                        logged_at: now,
+                        last_seen: now,
+                        suppressed: 0,
+                    },
+                );
+            }
+        }
+
+        warn!(
+            direction = self.direction,
+            error.class = %class,
+            target = %target,
+            %error,
+            "request failed"
+        );
+    }
+
+    /// Logs the number of errors suppressed in each group since the last
+    /// flush, and forgets groups that have not seen an error in a window.
+    fn flush(&mut self, now: Instant) {
+        let direction = self.direction;
+        let window = self.window;
+        self.by_key.retain(|key, group| {
+            if group.suppressed > 0 {
+                warn!(
+                    direction,
+                    error.class = %key.class,
+                    target = %key.target,
+                    suppressed = group.suppressed,
+                    "suppressed repeated errors"
+                );
+                group.suppressed = 0;
+            }
+
+            now < group.last_seen + window
+        });
+    }
+}
+
+// === impl Daemon ===
+
+impl Future for Daemon {
+    type Item = ();
+    type Error = Never;
+
+    fn poll(&mut self) -> Poll<(), Never> {
+        loop {
+            match self.interval.poll() {
+                Ok(Async::Ready(Some(now))) => {
+                    if let Ok(mut groups) = self.groups.lock() {
+                        groups.flush(now);
+                    }
+                }
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(None)) | Err(_) => return Ok(Async::Ready(())),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tracing::{field, span, Event, Metadata, Subscriber};
+
+    const WINDOW: Duration = Duration::from_secs(10);
+
+    /// Captures the fields of each event as strings.
+    #[derive(Clone, Default)]
+    struct Capture {
+        events: Arc<Mutex<Vec<Vec<(String, String)>>>>,
+        next_id: Arc<AtomicUsize>,
+    }
+
+    struct Fields<'a>(&'a mut Vec<(String, String)>);
+
+    impl Subscriber for Capture {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, _: &span::Attributes<'_>) -> span::Id {
+            span::Id::from_u64(self.next_id.fetch_add(1, Ordering::SeqCst) as u64 + 1)
+        }
+
+        fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut fields = Vec::new();
+            event.record(&mut Fields(&mut fields));
+            self.events.lock().unwrap().push(fields);
+        }
+
+        fn enter(&self, _: &span::Id) {}
+
+        fn exit(&self, _: &span::Id) {}
+    }
+
+    impl<'a> field::Visit for Fields<'a> {
+        fn record_debug(&mut self, field: &field::Field, value: &dyn fmt::Debug) {
+            self.0
+                .push((field.name().to_owned(), format!("{:?}", value)));
+        }
+    }
+
+    impl Capture {
+        fn events(&self) -> Vec<Vec<(String, String)>> {
+            self.events.lock().unwrap().clone()
+        }
+    }
+
+    fn field<'a>(event: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        event
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn groups() -> Groups {
+        Groups {
+            direction: "outbound",
+            window: WINDOW,
+            by_key: IndexMap::default(),
+        }
+    }
+
+    #[test]
+    fn identical_errors_are_logged_once_per_window() {
+        let capture = Capture::default();
+        let mut groups = groups();
+        let start = Instant::now();
+
+        tracing::subscriber::with_default(capture.clone(), || {
+            for i in 0..1000 {
+                let now = start + Duration::from_millis(i);
+                groups.record("unexpected", "foo.ns.svc:8080", &"connection refused", now);
+            }
+            groups.flush(start + WINDOW);
+        });
+
+        let events = capture.events();
+        assert_eq!(events.len(), 2, "events={:?}", events);
+
+        let detail = &events[0];
+        assert_eq!(field(detail, "message"), Some("request failed"));
+        assert_eq!(field(detail, "error"), Some("connection refused"));
+        assert_eq!(field(detail, "error.class"), Some("unexpected"));
+        assert_eq!(field(detail, "target"), Some("foo.ns.svc:8080"));
+
+        let summary = &events[1];
+        assert_eq!(
+            field(summary, "message"),
+            Some("suppressed repeated errors")
+        );
+        assert_eq!(field(summary, "suppressed"), Some("999"));
+    }
+
+    #[test]
+    fn groups_are_distinct_by_class_and_target() {
+        let capture = Capture::default();
+        let mut groups = groups();
+        let now = Instant::now();
+
+        tracing::subscriber::with_default(capture.clone(), || {
+            groups.record("unexpected", "foo:8080", &"connection refused", now);
+            groups.record("unexpected", "bar:8080", &"connection refused", now);
+            groups.record("overloaded", "foo:8080", &"overloaded", now);
+            groups.record("unexpected", "foo:8080", &"connection refused", now);
+        });
+
+        assert_eq!(capture.events().len(), 3);
+        assert_eq!(groups.by_key.len(), 3);
+    }
+
+    #[test]
+    fn errors_are_logged_again_in_later_windows() {
+        let capture = Capture::default();
+        let mut groups = groups();
+        let start = Instant::now();
+
+        tracing::subscriber::with_default(capture.clone(), || {
+            groups.record("unexpected", "foo:8080", &"connection refused", start);
+            groups.record("unexpected", "foo:8080", &"connection refused", start);
+            groups.record(
+                "unexpected",
+                "foo:8080",
+                &"connection refused",
+                start + WINDOW,
+            );
+        });
+
+        assert_eq!(capture.events().len(), 2);
+    }
+
+    #[test]
+    fn idle_groups_expire() {
+        let mut groups = groups();
+        let start = Instant::now();
+        groups.record("unexpected", "foo:8080", &"connection refused", start);
+        groups.record(
+            "unexpected",
+            "bar:8080",
+            &"connection refused",
+            start + WINDOW,
+        );
+
+        groups.flush(start + WINDOW);
+        assert_eq!(groups.by_key.len(), 1);
+        assert!(groups.by_key.keys().all(|k| k.target == "bar:8080"));
+
+        groups.flush(start + WINDOW * 2);
+        assert!(groups.by_key.is_empty());
+    }
+}
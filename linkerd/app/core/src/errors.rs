@@ -1,32 +1,72 @@
 //! Layer to map HTTP service errors into appropriate `http::Response`s.
 
+use super::metric_labels::Direction;
+use crate::error_log::ErrorLog;
 use crate::svc;
-use futures::{Future, Poll};
-use http::{header, Request, Response, StatusCode, Version};
-use linkerd2_error::Error;
-use linkerd2_proxy_http::HasH2Reason;
+use futures::{try_ready, Future, Poll};
+use http::header::HeaderValue;
+use http::{header, uri::Authority, Request, Response, StatusCode, Version};
+use indexmap::IndexMap;
+use linkerd2_error::{Error, InvariantViolation};
+use linkerd2_metrics::{metrics, Counter, FmtLabels, FmtMetrics};
+use linkerd2_proxy_http::{outcome::Outcome, HasH2Reason};
+use linkerd2_trace_context as trace_context;
+use std::fmt;
+use std::sync::{Arc, Mutex};
 use tracing::{debug, error, warn};
 
+metrics! {
+    invariant_violations_total: Counter {
+        "Total count of requests that failed because an internal invariant was violated"
+    }
+}
+
 /// Layer to map HTTP service errors into appropriate `http::Response`s.
-pub fn layer() -> Layer {
-    Layer
+///
+/// Errors are logged through `log`, which may deduplicate them. If the request
+/// is being traced, the error is also recorded as an annotation on its span.
+/// Requests that fail because an internal invariant was violated are counted
+/// in `violations`, and their responses name the site of the violation in an
+/// `l5d-proxy-error` header.
+pub fn layer(log: ErrorLog, violations: Registry) -> Layer {
+    Layer { log, violations }
 }
 
 #[derive(Clone, Debug)]
-pub struct Layer;
+pub struct Layer {
+    log: ErrorLog,
+    violations: Registry,
+}
 
 #[derive(Clone, Debug)]
 pub struct Stack<M> {
     inner: M,
+    log: ErrorLog,
+    violations: Registry,
 }
 
 #[derive(Clone, Debug)]
-pub struct Service<S>(S);
+pub struct Service<S> {
+    inner: S,
+    log: ErrorLog,
+    violations: Registry,
+}
+
+pub struct MakeFuture<F> {
+    inner: F,
+    log: Option<ErrorLog>,
+    violations: Registry,
+}
 
 #[derive(Debug)]
 pub struct ResponseFuture<F> {
     inner: F,
     is_http2: bool,
+    log: ErrorLog,
+    violations: Registry,
+    target: Option<Authority>,
+    annotations: Option<trace_context::Annotations>,
+    outcome: Option<Outcome>,
 }
 
 #[derive(Clone, Debug)]
@@ -35,11 +75,32 @@ pub struct StatusError {
     pub message: String,
 }
 
+/// Counts the invariant violations in each direction, by site.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics(Arc<Mutex<IndexMap<Violation, Counter>>>);
+
+/// Records the invariant violations in one direction.
+#[derive(Clone, Debug)]
+pub struct Registry {
+    direction: Direction,
+    metrics: Metrics,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Violation {
+    direction: Direction,
+    site: &'static str,
+}
+
 impl<M> svc::Layer<M> for Layer {
     type Service = Stack<M>;
 
     fn layer(&self, inner: M) -> Self::Service {
-        Stack { inner }
+        Stack {
+            inner,
+            log: self.log.clone(),
+            violations: self.violations.clone(),
+        }
     }
 }
 
@@ -49,13 +110,34 @@ where
 {
     type Response = Service<M::Response>;
     type Error = M::Error;
-    type Future = futures::future::Map<M::Future, fn(M::Response) -> Self::Response>;
+    type Future = MakeFuture<M::Future>;
 
     fn poll_ready(&mut self) -> Poll<(), Self::Error> {
         self.inner.poll_ready()
     }
     fn call(&mut self, target: T) -> Self::Future {
-        self.inner.call(target).map(Service)
+        MakeFuture {
+            inner: self.inner.call(target),
+            log: Some(self.log.clone()),
+            violations: self.violations.clone(),
+        }
+    }
+}
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = Service<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        let log = self.log.take().expect("polled after complete");
+        let violations = self.violations.clone();
+        Ok(Service {
+            inner,
+            log,
+            violations,
+        }
+        .into())
     }
 }
 
@@ -70,13 +152,32 @@ where
     type Future = ResponseFuture<S::Future>;
 
     fn poll_ready(&mut self) -> Poll<(), Self::Error> {
-        self.0.poll_ready().map_err(Into::into)
+        self.inner.poll_ready().map_err(Into::into)
     }
 
     fn call(&mut self, req: Request<B1>) -> Self::Future {
         let is_http2 = req.version() == Version::HTTP_2;
-        let inner = self.0.call(req);
-        ResponseFuture { inner, is_http2 }
+        // The target is only needed to group deduplicated errors.
+        let target = if self.log.is_deduplicated() {
+            target(&req)
+        } else {
+            None
+        };
+        let annotations = req
+            .extensions()
+            .get::<trace_context::Annotations>()
+            .cloned();
+        let outcome = req.extensions().get::<Outcome>().cloned();
+        let inner = self.inner.call(req);
+        ResponseFuture {
+            inner,
+            is_http2,
+            log: self.log.clone(),
+            violations: self.violations.clone(),
+            target,
+            annotations,
+            outcome,
+        }
     }
 }
 
@@ -102,11 +203,36 @@ where
                     }
                 }
 
-                let response = Response::builder()
-                    .status(map_err_to_5xx(err))
+                let target = self
+                    .target
+                    .as_ref()
+                    .map(Authority::as_str)
+                    .unwrap_or("unknown");
+                let violated = invariant_violation(&*err).map(InvariantViolation::site);
+                if let Some(site) = violated {
+                    self.violations.incr(site);
+                }
+                let (status, class) = map_err_to_5xx(err, &self.log, target);
+                if let Some(ref outcome) = self.outcome {
+                    outcome.set_error(class);
+                }
+                if let Some(ref annotations) = self.annotations {
+                    annotations.annotate(
+                        "error response",
+                        &[
+                            ("error.class", class.to_owned()),
+                            ("http.status_code", status.as_str().to_owned()),
+                        ],
+                    );
+                }
+                let mut response = Response::builder()
+                    .status(status)
                     .header(header::CONTENT_LENGTH, "0")
                     .body(B::default())
                     .expect("app::errors response is valid");
+                if let Some(site) = violated.and_then(|s| HeaderValue::from_str(s).ok()) {
+                    response.headers_mut().insert(super::L5D_PROXY_ERROR, site);
+                }
 
                 Ok(response.into())
             }
@@ -114,33 +240,122 @@ where
     }
 }
 
-fn map_err_to_5xx(e: Error) -> StatusCode {
-    use crate::proxy::buffer;
+/// Returns the authority of the request's URI or, failing that, its `Host`
+/// header.
+fn target<B>(req: &Request<B>) -> Option<Authority> {
+    req.uri().authority_part().cloned().or_else(|| {
+        req.headers()
+            .get(header::HOST)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.parse().ok())
+    })
+}
+
+/// Returns the status of the response for `e`, along with the class of the
+/// error.
+fn map_err_to_5xx(e: Error, log: &ErrorLog, target: &str) -> (StatusCode, &'static str) {
+    use crate::{admission, connection_limit::ConnectionLimitExceeded, proxy::buffer};
+    use linkerd2_proxy_http::{header_limit::HeadersTooLarge, response_reset::ResetMidResponse};
     use linkerd2_router::error as router;
     use tower::load_shed::error as shed;
 
     if let Some(ref c) = e.downcast_ref::<router::NoCapacity>() {
-        warn!("router at capacity ({})", c.0);
-        http::StatusCode::SERVICE_UNAVAILABLE
+        log.error("router_capacity", target, &e, || {
+            warn!("router at capacity ({})", c.0)
+        });
+        (http::StatusCode::SERVICE_UNAVAILABLE, "router_capacity")
     } else if let Some(_) = e.downcast_ref::<shed::Overloaded>() {
-        warn!("server overloaded, max-in-flight reached");
-        http::StatusCode::SERVICE_UNAVAILABLE
+        log.error("overloaded", target, &e, || {
+            warn!("server overloaded, max-in-flight reached")
+        });
+        (http::StatusCode::SERVICE_UNAVAILABLE, "overloaded")
+    } else if let Some(_) = e.downcast_ref::<admission::Overloaded>() {
+        log.error("overloaded", target, &e, || {
+            warn!("server overloaded, max-in-flight reached")
+        });
+        (http::StatusCode::SERVICE_UNAVAILABLE, "overloaded")
     } else if let Some(_) = e.downcast_ref::<buffer::Aborted>() {
-        warn!("request aborted because it reached the configured dispatch deadline");
-        http::StatusCode::SERVICE_UNAVAILABLE
+        log.error("dispatch_timeout", target, &e, || {
+            warn!("request aborted because it reached the configured dispatch deadline")
+        });
+        (http::StatusCode::SERVICE_UNAVAILABLE, "dispatch_timeout")
+    } else if let Some(_) = e.downcast_ref::<buffer::Draining>() {
+        log.error("draining", target, &e, || {
+            warn!("request aborted because the proxy is draining")
+        });
+        (http::StatusCode::SERVICE_UNAVAILABLE, "draining")
+    } else if caused_by::<ConnectionLimitExceeded>(&*e) {
+        log.error("connection_limit", target, &e, || {
+            warn!("request failed because its destination's connection limit was reached")
+        });
+        (http::StatusCode::SERVICE_UNAVAILABLE, "connection_limit")
     } else if let Some(_) = e.downcast_ref::<router::NotRecognized>() {
-        error!("could not recognize request");
-        http::StatusCode::BAD_GATEWAY
+        log.error("not_recognized", target, &e, || {
+            error!("could not recognize request")
+        });
+        (http::StatusCode::BAD_GATEWAY, "not_recognized")
+    } else if let Some(_) = e.downcast_ref::<ResetMidResponse>() {
+        log.error("reset_mid_response", target, &e, || {
+            warn!("response reset by upstream before it was returned")
+        });
+        (http::StatusCode::BAD_GATEWAY, "reset_mid_response")
+    } else if let Some(_) = e.downcast_ref::<HeadersTooLarge>() {
+        log.error("response_headers_too_large", target, &e, || {
+            warn!("response rejected because its headers exceeded the limit")
+        });
+        (http::StatusCode::BAD_GATEWAY, "response_headers_too_large")
+    } else if let Some(v) = invariant_violation(&*e) {
+        log.error("invariant_violation", target, &e, || {
+            error!(
+                site = v.site(),
+                details = v.details(),
+                "internal invariant violated"
+            )
+        });
+        (
+            http::StatusCode::INTERNAL_SERVER_ERROR,
+            "invariant_violation",
+        )
     } else if let Some(err) = e.downcast_ref::<StatusError>() {
-        error!(%err.status, %err.message);
-        err.status
+        log.error("status", target, &e, || error!(%err.status, %err.message));
+        (err.status, "status")
     } else {
         // we probably should have handled this before?
-        error!("unexpected error: {}", e);
-        http::StatusCode::BAD_GATEWAY
+        log.error("unexpected", target, &e, || {
+            error!("unexpected error: {}", e)
+        });
+        (http::StatusCode::BAD_GATEWAY, "unexpected")
     }
 }
 
+/// Returns the invariant violation that caused `e`, if any.
+fn invariant_violation<'e>(
+    e: &'e (dyn std::error::Error + 'static),
+) -> Option<&'e InvariantViolation> {
+    let mut e = Some(e);
+    while let Some(err) = e {
+        if let Some(v) = err.downcast_ref::<InvariantViolation>() {
+            return Some(v);
+        }
+        e = err.source();
+    }
+    None
+}
+
+/// Returns true if `e`, or any error that caused it, is an `E`.
+///
+/// Connect errors may be wrapped by the HTTP client that failed to connect.
+fn caused_by<E: std::error::Error + 'static>(e: &(dyn std::error::Error + 'static)) -> bool {
+    let mut e = Some(e);
+    while let Some(err) = e {
+        if err.is::<E>() {
+            return true;
+        }
+        e = err.source();
+    }
+    false
+}
+
 impl std::fmt::Display for StatusError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.message.fmt(f)
@@ -148,3 +363,148 @@ impl std::fmt::Display for StatusError {
 }
 
 impl std::error::Error for StatusError {}
+
+// === impl Metrics ===
+
+impl Metrics {
+    pub fn inbound(&self) -> Registry {
+        Registry {
+            direction: Direction::In,
+            metrics: self.clone(),
+        }
+    }
+
+    pub fn outbound(&self) -> Registry {
+        Registry {
+            direction: Direction::Out,
+            metrics: self.clone(),
+        }
+    }
+}
+
+impl FmtMetrics for Metrics {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let violations = match self.0.lock() {
+            Ok(violations) => violations,
+            Err(_) => return Ok(()),
+        };
+        if violations.is_empty() {
+            return Ok(());
+        }
+
+        invariant_violations_total.fmt_help(f)?;
+        invariant_violations_total.fmt_scopes(f, violations.iter(), |c| c)?;
+
+        Ok(())
+    }
+}
+
+// === impl Registry ===
+
+impl Registry {
+    fn incr(&self, site: &'static str) {
+        let violation = Violation {
+            direction: self.direction,
+            site,
+        };
+        if let Ok(mut by_site) = self.metrics.0.lock() {
+            by_site
+                .entry(violation)
+                .or_insert_with(Counter::default)
+                .incr();
+        }
+    }
+}
+
+// === impl Violation ===
+
+impl FmtLabels for Violation {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.direction.fmt_labels(f)?;
+        write!(f, ",site=\"{}\"", self.site)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{future, Async};
+    use linkerd2_error::contract;
+
+    const SITE: &str = "errors::tests::RequireReady::call";
+
+    /// Must be polled to readiness before each request.
+    #[derive(Default)]
+    struct RequireReady {
+        ready: bool,
+    }
+
+    /// Misbehaves by reporting that it's ready without polling its inner
+    /// service.
+    struct SkipReady<S>(S);
+
+    impl svc::Service<Request<()>> for RequireReady {
+        type Response = Response<()>;
+        type Error = Error;
+        type Future = contract::ResponseFuture<future::FutureResult<Response<()>, Error>>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            self.ready = true;
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: Request<()>) -> Self::Future {
+            let ready = std::mem::replace(&mut self.ready, false);
+            contract!(ready, SITE, "called before ready")
+                .map(|()| future::ok(Response::new(())))
+                .into()
+        }
+    }
+
+    impl<S: svc::Service<Request<()>>> svc::Service<Request<()>> for SkipReady<S> {
+        type Response = S::Response;
+        type Error = S::Error;
+        type Future = S::Future;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, req: Request<()>) -> Self::Future {
+            self.0.call(req)
+        }
+    }
+
+    #[test]
+    fn invariant_violations_fail_requests() {
+        let metrics = Metrics::default();
+        let mut svc = Service {
+            inner: SkipReady(RequireReady::default()),
+            log: ErrorLog::disabled(),
+            violations: metrics.inbound(),
+        };
+
+        for _ in 0..2 {
+            let ready = svc::Service::poll_ready(&mut svc).expect("service must not fail");
+            assert!(ready.is_ready());
+            let rsp = svc::Service::call(&mut svc, Request::new(()))
+                .wait()
+                .expect("violations must be answered with a response");
+            assert_eq!(rsp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+            assert_eq!(
+                rsp.headers()
+                    .get(crate::L5D_PROXY_ERROR)
+                    .and_then(|v| v.to_str().ok()),
+                Some(SITE),
+                "the response must name the site of the violation"
+            );
+        }
+
+        let report = metrics.as_display().to_string();
+        let expected = format!(
+            "invariant_violations_total{{direction=\"inbound\",site=\"{}\"}} 2\n",
+            SITE
+        );
+        assert!(report.contains(&expected), "{}", report);
+    }
+}
@@ -0,0 +1,613 @@
+//! Records a bounded history of each target's state transitions, so that
+//! flapping configuration can be debugged.
+//!
+//! Profiles being acquired, lost, or changed; endpoints being added or
+//! removed; the fallback being engaged or disengaged; and balancers being
+//! created or evicted are each recorded, with a timestamp, in a fixed-size ring
+//! for the target. The number of targets that have a history is capped as
+//! well: once the cap is reached, the target whose latest event is oldest is
+//! forgotten to make room for a new one.
+//!
+//! The admin server serves a target's history on `/events?target=<authority>`.
+//! Each event is also logged at the `debug` level.
+
+use crate::proxy::core::resolve;
+use crate::proxy::http::profiles;
+use crate::{svc, Addr, NameAddr, Never};
+use futures::{try_ready, Async, Future, Poll, Stream};
+use indexmap::IndexMap;
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio_timer::clock;
+use tracing::debug;
+
+/// The number of events retained for each target.
+pub const DEFAULT_CAPACITY: usize = 32;
+
+/// The number of targets for which events are retained.
+pub const DEFAULT_MAX_TARGETS: usize = 1_000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Kind {
+    ProfileAcquired { hash: u64 },
+    ProfileChanged { hash: u64 },
+    ProfileLost,
+    EndpointsAdded(usize),
+    EndpointsRemoved(usize),
+    FallbackEngaged,
+    FallbackDisengaged,
+    BalancerCreated,
+    BalancerEvicted,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Event {
+    pub at: Instant,
+    pub kind: Kind,
+}
+
+/// A registry of each target's recent events.
+#[derive(Clone)]
+pub struct Events(Arc<Mutex<Inner>>);
+
+struct Inner {
+    capacity: usize,
+    max_targets: usize,
+    rings: IndexMap<Addr, VecDeque<Event>>,
+}
+
+/// Records profile transitions for each destination whose routes are watched.
+#[derive(Clone, Debug)]
+pub struct GetRoutes<G> {
+    inner: G,
+    events: Events,
+}
+
+pub struct RouteStream<S> {
+    inner: S,
+    target: Addr,
+    events: Events,
+    /// The hash of the destination's current profile, if it has one.
+    profile: Option<u64>,
+}
+
+/// Records the endpoints added to and removed from each resolution.
+#[derive(Clone, Debug)]
+pub struct Resolve<R> {
+    inner: R,
+    events: Events,
+}
+
+pub struct ResolveFuture<F> {
+    inner: F,
+    target: Option<Addr>,
+    events: Events,
+}
+
+pub struct Resolution<R> {
+    inner: R,
+    target: Addr,
+    events: Events,
+    endpoints: usize,
+}
+
+/// Records one event when a target's service is built and another when it's
+/// dropped.
+#[derive(Clone, Debug)]
+pub struct Layer {
+    events: Events,
+    built: Kind,
+    dropped: Kind,
+}
+
+#[derive(Clone, Debug)]
+pub struct MakeTracked<M> {
+    inner: M,
+    layer: Layer,
+}
+
+pub struct MakeFuture<F> {
+    inner: F,
+    target: Option<Addr>,
+    layer: Layer,
+}
+
+#[derive(Clone, Debug)]
+pub struct Tracked<S> {
+    inner: S,
+    _dropped: Arc<Dropped>,
+}
+
+#[derive(Debug)]
+struct Dropped {
+    target: Addr,
+    events: Events,
+    kind: Kind,
+}
+
+/// Records `built` when each target's service is built and `dropped` once it
+/// (and all of its clones) are dropped.
+pub fn layer(events: Events, built: Kind, dropped: Kind) -> Layer {
+    Layer {
+        events,
+        built,
+        dropped,
+    }
+}
+
+// === impl Kind ===
+
+impl Kind {
+    fn name(&self) -> &'static str {
+        match self {
+            Kind::ProfileAcquired { .. } => "profile_acquired",
+            Kind::ProfileChanged { .. } => "profile_changed",
+            Kind::ProfileLost => "profile_lost",
+            Kind::EndpointsAdded(_) => "endpoints_added",
+            Kind::EndpointsRemoved(_) => "endpoints_removed",
+            Kind::FallbackEngaged => "fallback_engaged",
+            Kind::FallbackDisengaged => "fallback_disengaged",
+            Kind::BalancerCreated => "balancer_created",
+            Kind::BalancerEvicted => "balancer_evicted",
+        }
+    }
+}
+
+// === impl Events ===
+
+impl Events {
+    /// Retains up to `capacity` events for each of up to `max_targets`
+    /// targets.
+    pub fn new(capacity: usize, max_targets: usize) -> Self {
+        Events(Arc::new(Mutex::new(Inner {
+            capacity,
+            max_targets,
+            rings: IndexMap::default(),
+        })))
+    }
+
+    pub fn record(&self, target: &Addr, kind: Kind) {
+        debug!(%target, event = ?kind, "state transition");
+        let event = Event {
+            at: clock::now(),
+            kind,
+        };
+
+        let mut inner = match self.0.lock() {
+            Ok(inner) => inner,
+            Err(_) => return,
+        };
+        let inner = &mut *inner;
+        if let Some(ring) = inner.rings.get_mut(target) {
+            // The ring's slots are allocated when it's created, so recording
+            // an event doesn't allocate.
+            if ring.len() >= inner.capacity {
+                ring.pop_front();
+            }
+            ring.push_back(event);
+            return;
+        }
+
+        if inner.capacity == 0 || inner.max_targets == 0 {
+            return;
+        }
+        if inner.rings.len() >= inner.max_targets {
+            inner.forget_stalest();
+        }
+        let mut ring = VecDeque::with_capacity(inner.capacity);
+        ring.push_back(event);
+        inner.rings.insert(target.clone(), ring);
+    }
+
+    /// Returns the events recorded for `target`, oldest first.
+    pub fn history(&self, target: &Addr) -> Option<Vec<Event>> {
+        let inner = self.0.lock().ok()?;
+        let ring = inner.rings.get(target)?;
+        Some(ring.iter().cloned().collect())
+    }
+
+    pub fn to_json(&self, target: &Addr) -> Option<Value> {
+        let now = clock::now();
+        let events = self
+            .history(target)?
+            .into_iter()
+            .map(|Event { at, kind }| {
+                let age = now - at;
+                let mut event = json!({
+                    "age_ms": age.as_secs() * 1_000 + u64::from(age.subsec_millis()),
+                    "event": kind.name(),
+                });
+                match kind {
+                    Kind::ProfileAcquired { hash } | Kind::ProfileChanged { hash } => {
+                        event["hash"] = json!(format!("{:016x}", hash));
+                    }
+                    Kind::EndpointsAdded(n) | Kind::EndpointsRemoved(n) => {
+                        event["count"] = json!(n);
+                    }
+                    _ => {}
+                }
+                event
+            })
+            .collect::<Vec<_>>();
+        Some(json!({
+            "target": target.to_string(),
+            "events": events,
+        }))
+    }
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY, DEFAULT_MAX_TARGETS)
+    }
+}
+
+impl fmt::Debug for Events {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Events").finish()
+    }
+}
+
+// === impl Inner ===
+
+impl Inner {
+    /// Forgets the target whose latest event is the oldest.
+    fn forget_stalest(&mut self) {
+        let mut stalest: Option<(usize, Instant)> = None;
+        for (idx, ring) in self.rings.values().enumerate() {
+            let latest = match ring.back() {
+                Some(event) => event.at,
+                None => continue,
+            };
+            if stalest.map(|(_, at)| latest < at).unwrap_or(true) {
+                stalest = Some((idx, latest));
+            }
+        }
+        if let Some((idx, _)) = stalest {
+            self.rings.swap_remove_index(idx);
+        }
+    }
+}
+
+// === impl GetRoutes ===
+
+impl<G> GetRoutes<G> {
+    pub fn new(inner: G, events: Events) -> Self {
+        Self { inner, events }
+    }
+}
+
+impl<G: profiles::GetRoutes> profiles::GetRoutes for GetRoutes<G> {
+    type Stream = RouteStream<G::Stream>;
+
+    fn get_routes(&self, dst: &NameAddr) -> Option<Self::Stream> {
+        let inner = self.inner.get_routes(dst)?;
+        Some(RouteStream {
+            inner,
+            target: Addr::Name(dst.clone()),
+            events: self.events.clone(),
+            profile: None,
+        })
+    }
+}
+
+// === impl RouteStream ===
+
+impl<S> Stream for RouteStream<S>
+where
+    S: Stream<Item = profiles::Routes, Error = Never>,
+{
+    type Item = profiles::Routes;
+    type Error = Never;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let routes = match try_ready!(self.inner.poll()) {
+            Some(routes) => routes,
+            None => return Ok(Async::Ready(None)),
+        };
+
+        // Only profiles that were received from the control plane have a
+        // hash. An empty profile is no different from having no profile.
+        let profile = routes
+            .hash
+            .filter(|_| !(routes.routes.is_empty() && routes.dst_overrides.is_empty()));
+        let kind = match (self.profile, profile) {
+            (None, Some(hash)) => Some(Kind::ProfileAcquired { hash }),
+            (Some(_), None) => Some(Kind::ProfileLost),
+            (Some(prior), Some(hash)) if prior != hash => Some(Kind::ProfileChanged { hash }),
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            self.events.record(&self.target, kind);
+        }
+        self.profile = profile;
+
+        Ok(Async::Ready(Some(routes)))
+    }
+}
+
+// === impl Resolve ===
+
+impl<R> Resolve<R> {
+    pub fn new(inner: R, events: Events) -> Self {
+        Self { inner, events }
+    }
+}
+
+impl<T, R> tower::Service<T> for Resolve<R>
+where
+    T: AsRef<Addr>,
+    R: resolve::Resolve<T>,
+{
+    type Response = Resolution<R::Resolution>;
+    type Error = R::Error;
+    type Future = ResolveFuture<R::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        ResolveFuture {
+            target: Some(target.as_ref().clone()),
+            inner: self.inner.resolve(target),
+            events: self.events.clone(),
+        }
+    }
+}
+
+// === impl ResolveFuture ===
+
+impl<F> Future for ResolveFuture<F>
+where
+    F: Future,
+    F::Item: resolve::Resolution,
+{
+    type Item = Resolution<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        Ok(Async::Ready(Resolution {
+            inner,
+            target: self.target.take().expect("polled after ready"),
+            events: self.events.clone(),
+            endpoints: 0,
+        }))
+    }
+}
+
+// === impl Resolution ===
+
+impl<R: resolve::Resolution> Resolution<R> {
+    fn record(&mut self, update: &resolve::Update<R::Endpoint>) {
+        let (added, removed) = match update {
+            resolve::Update::Add(eps) => (eps.len(), 0),
+            resolve::Update::Remove(addrs) => (0, addrs.len().min(self.endpoints)),
+            resolve::Update::Reset(eps) => (eps.len(), self.endpoints),
+            resolve::Update::Empty | resolve::Update::DoesNotExist => (0, self.endpoints),
+        };
+        self.endpoints = self.endpoints - removed + added;
+        if removed > 0 {
+            self.events
+                .record(&self.target, Kind::EndpointsRemoved(removed));
+        }
+        if added > 0 {
+            self.events
+                .record(&self.target, Kind::EndpointsAdded(added));
+        }
+    }
+}
+
+impl<R: resolve::Resolution> resolve::Resolution for Resolution<R> {
+    type Endpoint = R::Endpoint;
+    type Error = R::Error;
+
+    fn poll(&mut self) -> Poll<resolve::Update<Self::Endpoint>, Self::Error> {
+        let update = try_ready!(self.inner.poll());
+        self.record(&update);
+        Ok(Async::Ready(update))
+    }
+
+    fn poll_tagged(&mut self) -> Poll<resolve::Tagged<Self::Endpoint>, Self::Error> {
+        let tagged = try_ready!(self.inner.poll_tagged());
+        self.record(&tagged.update);
+        Ok(Async::Ready(tagged))
+    }
+}
+
+// === impl Layer ===
+
+impl<M> svc::Layer<M> for Layer {
+    type Service = MakeTracked<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        MakeTracked {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+// === impl MakeTracked ===
+
+impl<T, M> svc::Service<T> for MakeTracked<M>
+where
+    T: AsRef<Addr>,
+    M: svc::Service<T>,
+{
+    type Response = Tracked<M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        MakeFuture {
+            target: Some(target.as_ref().clone()),
+            inner: self.inner.call(target),
+            layer: self.layer.clone(),
+        }
+    }
+}
+
+// === impl MakeFuture ===
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = Tracked<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        let target = self.target.take().expect("polled after ready");
+        let Layer {
+            ref events,
+            built,
+            dropped,
+        } = self.layer;
+        events.record(&target, built);
+        let dropped = Dropped {
+            target,
+            events: events.clone(),
+            kind: dropped,
+        };
+        Ok(Async::Ready(Tracked {
+            inner,
+            _dropped: Arc::new(dropped),
+        }))
+    }
+}
+
+// === impl Tracked ===
+
+impl<S, Req> svc::Service<Req> for Tracked<S>
+where
+    S: svc::Service<Req>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        self.inner.call(req)
+    }
+}
+
+// === impl Dropped ===
+
+impl Drop for Dropped {
+    fn drop(&mut self) {
+        self.events.record(&self.target, self.kind);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream;
+
+    /// Serves a fixed sequence of profiles for every destination.
+    #[derive(Clone)]
+    struct MockGetRoutes(Vec<profiles::Routes>);
+
+    impl profiles::GetRoutes for MockGetRoutes {
+        type Stream = stream::IterOk<std::vec::IntoIter<profiles::Routes>, Never>;
+
+        fn get_routes(&self, _: &NameAddr) -> Option<Self::Stream> {
+            Some(stream::iter_ok(self.0.clone()))
+        }
+    }
+
+    fn profile(hash: u64) -> profiles::Routes {
+        profiles::Routes {
+            dst_overrides: vec![profiles::WeightedAddr {
+                addr: NameAddr::from_str("web-v2.ns.svc.cluster.local:8080").unwrap(),
+                weight: 1,
+            }],
+            hash: Some(hash),
+            ..profiles::Routes::default()
+        }
+    }
+
+    fn kinds(events: &Events, target: &Addr) -> Vec<Kind> {
+        events
+            .history(target)
+            .expect("target must have events")
+            .into_iter()
+            .map(|e| e.kind)
+            .collect()
+    }
+
+    #[test]
+    fn profile_transitions_are_recorded_in_order() {
+        use profiles::GetRoutes as _;
+
+        let empty = profiles::Routes {
+            hash: Some(3),
+            ..profiles::Routes::default()
+        };
+        let get_routes = GetRoutes::new(
+            MockGetRoutes(vec![
+                profile(1),
+                profile(1),
+                profiles::Routes::default(),
+                profile(1),
+                profile(2),
+                empty,
+            ]),
+            Events::default(),
+        );
+        let dst = NameAddr::from_str("web.ns.svc.cluster.local:8080").unwrap();
+        let updates = get_routes
+            .get_routes(&dst)
+            .expect("routes must be watched")
+            .collect()
+            .wait()
+            .unwrap_or_else(|never| match never {});
+        assert_eq!(updates.len(), 6);
+
+        assert_eq!(
+            kinds(&get_routes.events, &Addr::Name(dst)),
+            vec![
+                Kind::ProfileAcquired { hash: 1 },
+                Kind::ProfileLost,
+                Kind::ProfileAcquired { hash: 1 },
+                Kind::ProfileChanged { hash: 2 },
+                Kind::ProfileLost,
+            ]
+        );
+    }
+
+    #[test]
+    fn events_are_capped_per_target_and_globally() {
+        let events = Events::new(2, 2);
+        let addr = |s: &str| Addr::from_str(s).unwrap();
+
+        events.record(&addr("a.example.com:80"), Kind::BalancerCreated);
+        events.record(&addr("a.example.com:80"), Kind::EndpointsAdded(3));
+        events.record(&addr("a.example.com:80"), Kind::EndpointsRemoved(1));
+        assert_eq!(
+            kinds(&events, &addr("a.example.com:80")),
+            vec![Kind::EndpointsAdded(3), Kind::EndpointsRemoved(1)],
+            "the oldest events are dropped"
+        );
+
+        events.record(&addr("b.example.com:80"), Kind::FallbackEngaged);
+        events.record(&addr("c.example.com:80"), Kind::FallbackEngaged);
+        assert!(
+            events.history(&addr("a.example.com:80")).is_none(),
+            "the stalest target is forgotten"
+        );
+        assert!(events.history(&addr("b.example.com:80")).is_some());
+        assert!(events.history(&addr("c.example.com:80")).is_some());
+    }
+}
@@ -0,0 +1,480 @@
+//! Only honors proxy-control headers set by peers with trusted identities.
+//!
+//! A `Policy` maps each controlled header to the identity suffixes of the
+//! peers that may set it. When any other peer sets a controlled header,
+//! including a peer without an identity, the header is stripped and counted
+//! before it can be honored. Headers that the policy doesn't control are
+//! unchanged, so the default (empty) policy leaves each header to be trusted
+//! as it was before, e.g. from any meshed peer.
+//!
+//! Inbound, the peer is the client that sent the request; outbound, it's the
+//! endpoint that served the response.
+
+use super::metric_labels::Direction;
+use crate::proxy::http::header::HeaderName;
+use crate::{dns, svc, transport::tls};
+use futures::{try_ready, Future, Poll};
+use indexmap::IndexMap;
+use linkerd2_error::{contract, Error};
+use linkerd2_metrics::{metrics, Counter, FmtLabels, FmtMetrics};
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tracing::debug;
+
+metrics! {
+    untrusted_header_stripped_total: Counter {
+        "Total count of controlled headers that were stripped because they were set by an untrusted peer"
+    }
+}
+
+/// Maps each controlled header to the identity suffixes of the peers that
+/// may set it.
+#[derive(Clone, Debug, Default)]
+pub struct Policy(Arc<IndexMap<HeaderName, Vec<dns::Suffix>>>);
+
+/// Counts the headers stripped in each direction.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics(Arc<Mutex<IndexMap<Stripped, Counter>>>);
+
+/// Records the headers stripped in one direction.
+#[derive(Clone, Debug)]
+pub struct Registry {
+    direction: Direction,
+    metrics: Metrics,
+}
+
+#[derive(Clone, Debug)]
+pub struct Layer {
+    policy: Policy,
+    message: Message,
+    registry: Registry,
+}
+
+#[derive(Clone, Debug)]
+pub struct Stack<M> {
+    inner: M,
+    policy: Policy,
+    message: Message,
+    registry: Registry,
+}
+
+pub struct MakeFuture<F> {
+    inner: F,
+    untrusted: Option<Untrusted>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Service<S> {
+    inner: S,
+    untrusted: Untrusted,
+}
+
+pub struct ResponseFuture<F> {
+    inner: F,
+    untrusted: Option<Untrusted>,
+}
+
+/// Whether controlled headers are validated on requests or responses.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Message {
+    Request,
+    Response,
+}
+
+/// The controlled headers that a single peer may not set.
+#[derive(Clone, Debug)]
+struct Untrusted {
+    message: Message,
+    headers: Arc<Vec<HeaderName>>,
+    registry: Registry,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Stripped {
+    direction: Direction,
+    header: HeaderName,
+}
+
+/// Strips controlled request headers sent by untrusted clients.
+pub fn request_layer(policy: Policy, registry: Registry) -> Layer {
+    Layer {
+        policy,
+        message: Message::Request,
+        registry,
+    }
+}
+
+/// Strips controlled response headers sent by untrusted servers.
+pub fn response_layer(policy: Policy, registry: Registry) -> Layer {
+    Layer {
+        policy,
+        message: Message::Response,
+        registry,
+    }
+}
+
+// === impl Policy ===
+
+impl Policy {
+    pub fn new(allowed: IndexMap<HeaderName, Vec<dns::Suffix>>) -> Self {
+        Policy(Arc::new(allowed))
+    }
+
+    /// Returns the controlled headers that a peer with the given identity
+    /// may not set.
+    fn untrusted(&self, peer_identity: &tls::PeerIdentity) -> Vec<HeaderName> {
+        let name = peer_identity
+            .value()
+            .and_then(|id| dns::Name::try_from(id.as_ref().as_bytes()).ok());
+        self.0
+            .iter()
+            .filter(|(_, suffixes)| match name {
+                Some(ref name) => !suffixes.iter().any(|sfx| sfx.contains(name)),
+                None => true,
+            })
+            .map(|(header, _)| header.clone())
+            .collect()
+    }
+}
+
+// === impl Metrics ===
+
+impl Metrics {
+    pub fn inbound(&self) -> Registry {
+        Registry {
+            direction: Direction::In,
+            metrics: self.clone(),
+        }
+    }
+
+    pub fn outbound(&self) -> Registry {
+        Registry {
+            direction: Direction::Out,
+            metrics: self.clone(),
+        }
+    }
+}
+
+impl FmtMetrics for Metrics {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stripped = match self.0.lock() {
+            Ok(stripped) => stripped,
+            Err(_) => return Ok(()),
+        };
+        if stripped.is_empty() {
+            return Ok(());
+        }
+
+        untrusted_header_stripped_total.fmt_help(f)?;
+        untrusted_header_stripped_total.fmt_scopes(f, stripped.iter(), |c| c)?;
+
+        Ok(())
+    }
+}
+
+// === impl Registry ===
+
+impl Registry {
+    fn incr(&self, header: &HeaderName) {
+        let stripped = Stripped {
+            direction: self.direction,
+            header: header.clone(),
+        };
+        if let Ok(mut by_header) = self.metrics.0.lock() {
+            by_header
+                .entry(stripped)
+                .or_insert_with(Counter::default)
+                .incr();
+        }
+    }
+}
+
+// === impl Stripped ===
+
+impl FmtLabels for Stripped {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.direction.fmt_labels(f)?;
+        write!(f, ",header=\"{}\"", self.header)
+    }
+}
+
+// === impl Layer ===
+
+impl<M> svc::Layer<M> for Layer {
+    type Service = Stack<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        Stack {
+            inner,
+            policy: self.policy.clone(),
+            message: self.message,
+            registry: self.registry.clone(),
+        }
+    }
+}
+
+// === impl Stack ===
+
+impl<T, M> svc::Service<T> for Stack<M>
+where
+    T: tls::HasPeerIdentity,
+    M: svc::Service<T>,
+    M::Error: Into<Error>,
+{
+    type Response = Service<M::Response>;
+    type Error = Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let untrusted = Untrusted {
+            message: self.message,
+            headers: Arc::new(self.policy.untrusted(&target.peer_identity())),
+            registry: self.registry.clone(),
+        };
+
+        MakeFuture {
+            inner: self.inner.call(target),
+            untrusted: Some(untrusted),
+        }
+    }
+}
+
+// === impl MakeFuture ===
+
+impl<F> Future for MakeFuture<F>
+where
+    F: Future,
+    F::Error: Into<Error>,
+{
+    type Item = Service<F::Item>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll().map_err(Into::into));
+        let untrusted = contract!(
+            self.untrusted.take(),
+            "header_trust::MakeFuture::poll",
+            "polled after ready"
+        )?;
+        Ok(Service { inner, untrusted }.into())
+    }
+}
+
+// === impl Service ===
+
+impl<S, A, B> svc::Service<http::Request<A>> for Service<S>
+where
+    S: svc::Service<http::Request<A>, Response = http::Response<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut req: http::Request<A>) -> Self::Future {
+        let untrusted = match self.untrusted.message {
+            Message::Request => {
+                self.untrusted.strip(req.headers_mut());
+                None
+            }
+            Message::Response => Some(self.untrusted.clone()),
+        };
+
+        ResponseFuture {
+            inner: self.inner.call(req),
+            untrusted,
+        }
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+{
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut rsp = try_ready!(self.inner.poll());
+        if let Some(untrusted) = self.untrusted.take() {
+            untrusted.strip(rsp.headers_mut());
+        }
+        Ok(rsp.into())
+    }
+}
+
+// === impl Untrusted ===
+
+impl Untrusted {
+    fn strip(&self, headers: &mut http::HeaderMap) {
+        for header in self.headers.iter() {
+            if headers.remove(header).is_some() {
+                debug!(%header, "stripped header set by untrusted peer");
+                self.registry.incr(header);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::{http::orig_proto::L5D_ORIG_PROTO, identity};
+    use crate::transport::listen::Addrs;
+    use crate::{Conditional, DST_OVERRIDE_HEADER};
+    use futures::future;
+    use svc::{Layer as _, Service as _};
+
+    const GATEWAY: &str = "linkerd-gateway.linkerd.serviceaccount.identity.linkerd.cluster.local";
+    const APP: &str = "app.default.serviceaccount.identity.linkerd.cluster.local";
+    const OTHER: &str = "app.other.serviceaccount.identity.linkerd.cluster.local";
+    const DEFAULT_NS: &str = "default.serviceaccount.identity.linkerd.cluster.local";
+
+    /// Only the gateway may override destinations, and only peers in the
+    /// `default` namespace may set `l5d-orig-proto`.
+    fn policy() -> Policy {
+        let suffix = |s: &str| dns::Suffix::try_from(s).unwrap();
+        let mut allowed = IndexMap::new();
+        allowed.insert(
+            HeaderName::from_static(DST_OVERRIDE_HEADER),
+            vec![suffix(GATEWAY)],
+        );
+        allowed.insert(
+            HeaderName::from_static(L5D_ORIG_PROTO),
+            vec![suffix(DEFAULT_NS)],
+        );
+        Policy::new(allowed)
+    }
+
+    fn meta(peer: Option<&str>) -> tls::accept::Meta {
+        let peer_identity = match peer {
+            Some(id) => Conditional::Some(identity::Name::from_hostname(id.as_bytes()).unwrap()),
+            None => Conditional::None(tls::ReasonForNoIdentity::Disabled),
+        };
+        tls::accept::Meta {
+            peer_identity,
+            addrs: Addrs::new(
+                ([10, 1, 1, 1], 33333).into(),
+                ([10, 1, 1, 2], 4143).into(),
+                None,
+            ),
+        }
+    }
+
+    /// Sends a request with `header` set from `peer` and returns whether it
+    /// was honored.
+    fn honored(layer: &Layer, peer: Option<&str>, header: &str) -> bool {
+        // Requests' headers are echoed on their responses.
+        let echo = svc::mk(|req: http::Request<()>| {
+            let mut rsp = http::Response::new(());
+            *rsp.headers_mut() = req.headers().clone();
+            future::ok::<_, ()>(rsp)
+        });
+        let mut stack = layer.layer(svc::mk(move |_: tls::accept::Meta| {
+            future::ok::<_, ()>(echo.clone())
+        }));
+        let mut service = stack.call(meta(peer)).wait().unwrap();
+
+        let req = http::Request::builder()
+            .header(header, "value")
+            .header("l5d-uncontrolled", "value")
+            .body(())
+            .unwrap();
+        let rsp = service.call(req).wait().unwrap();
+        assert!(
+            rsp.headers().contains_key("l5d-uncontrolled"),
+            "uncontrolled headers must not be stripped"
+        );
+        rsp.headers().contains_key(header)
+    }
+
+    fn stripped(metrics: &Metrics, direction: &str, header: &str) -> bool {
+        let report = metrics.as_display().to_string();
+        let line = format!(
+            "untrusted_header_stripped_total{{direction=\"{}\",header=\"{}\"}} ",
+            direction, header
+        );
+        report.lines().any(|l| l.starts_with(&line))
+    }
+
+    #[test]
+    fn request_headers_from_untrusted_peers_are_stripped() {
+        let metrics = Metrics::default();
+        let layer = request_layer(policy(), metrics.inbound());
+
+        assert!(honored(&layer, Some(GATEWAY), DST_OVERRIDE_HEADER));
+        assert!(honored(&layer, Some(APP), L5D_ORIG_PROTO));
+        assert_eq!(metrics.as_display().to_string(), "");
+
+        assert!(!honored(&layer, Some(APP), DST_OVERRIDE_HEADER));
+        assert!(!honored(&layer, Some(OTHER), L5D_ORIG_PROTO));
+        assert!(!honored(&layer, None, DST_OVERRIDE_HEADER));
+        assert!(!honored(&layer, None, L5D_ORIG_PROTO));
+
+        let report = metrics.as_display().to_string();
+        assert!(report.contains(
+            "untrusted_header_stripped_total{direction=\"inbound\",header=\"l5d-dst-override\"} 2"
+        ));
+        assert!(report.contains(
+            "untrusted_header_stripped_total{direction=\"inbound\",header=\"l5d-orig-proto\"} 2"
+        ));
+    }
+
+    #[test]
+    fn response_headers_from_untrusted_peers_are_stripped() {
+        let metrics = Metrics::default();
+        let layer = response_layer(policy(), metrics.outbound());
+
+        assert!(honored(&layer, Some(GATEWAY), DST_OVERRIDE_HEADER));
+        assert!(honored(&layer, Some(APP), L5D_ORIG_PROTO));
+        assert!(!stripped(&metrics, "outbound", L5D_ORIG_PROTO));
+
+        assert!(!honored(&layer, Some(OTHER), L5D_ORIG_PROTO));
+        assert!(stripped(&metrics, "outbound", L5D_ORIG_PROTO));
+        assert!(!honored(&layer, None, DST_OVERRIDE_HEADER));
+        assert!(stripped(&metrics, "outbound", DST_OVERRIDE_HEADER));
+    }
+
+    #[test]
+    fn default_policy_strips_nothing() {
+        let metrics = Metrics::default();
+        let layer = request_layer(Policy::default(), metrics.inbound());
+
+        for peer in &[Some(GATEWAY), Some(OTHER), None] {
+            assert!(honored(&layer, *peer, DST_OVERRIDE_HEADER));
+            assert!(honored(&layer, *peer, L5D_ORIG_PROTO));
+        }
+        assert_eq!(metrics.as_display().to_string(), "");
+    }
+
+    #[test]
+    fn suffixes_match_whole_labels() {
+        let policy = policy();
+
+        let peer =
+            |id: &str| Conditional::Some(identity::Name::from_hostname(id.as_bytes()).unwrap());
+        assert_eq!(
+            policy.untrusted(&peer(APP)),
+            vec![HeaderName::from_static(DST_OVERRIDE_HEADER)]
+        );
+        assert_eq!(
+            policy.untrusted(&peer(
+                "app.notdefault.serviceaccount.identity.linkerd.cluster.local"
+            )),
+            vec![
+                HeaderName::from_static(DST_OVERRIDE_HEADER),
+                HeaderName::from_static(L5D_ORIG_PROTO),
+            ]
+        );
+    }
+}
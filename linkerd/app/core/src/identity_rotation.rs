@@ -0,0 +1,138 @@
+//! Retires outbound clients when the proxy's identity certificate rotates.
+//!
+//! Connections that were established with the old certificate continue to
+//! present it until they close, and some peers reject it before it expires.
+//! Each time the certificate is replaced, every outbound client is retired:
+//! requests that are in flight complete, but new requests are dispatched to a
+//! client that is rebuilt, and so handshakes, with the new certificate.
+//!
+//! Inbound handshakes read the local identity's configuration as they're
+//! accepted, so they present the new certificate as soon as it's provisioned.
+
+use crate::proxy::identity;
+use futures::{Future, Stream};
+use linkerd2_metrics::{metrics, Counter, FmtMetrics, Gauge};
+use linkerd2_reconnect::Retire;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+use tracing::info;
+
+metrics! {
+    identity_cert_expiration_timestamp_seconds: Gauge {
+        "The time at which the proxy's current identity certificate expires, in seconds since the Unix epoch"
+    },
+    identity_rotation_retired_clients_total: Counter {
+        "Total count of outbound clients that were rebuilt because the proxy's identity certificate rotated"
+    }
+}
+
+/// Retires outbound clients when the local identity's certificate rotates,
+/// and reports the certificate's expiry.
+#[derive(Clone, Debug, Default)]
+pub struct Rotation {
+    local: Arc<Mutex<Option<identity::Local>>>,
+    retire: Retire,
+}
+
+impl Rotation {
+    /// Returns the handle with which outbound clients are retired.
+    pub fn retire(&self) -> Retire {
+        self.retire.clone()
+    }
+
+    /// Returns a task that retires all outbound clients each time `local`'s
+    /// certificate rotates, and reports the certificate's expiry.
+    pub fn watch(
+        &self,
+        local: identity::Local,
+    ) -> impl Future<Item = (), Error = ()> + Send + 'static {
+        let rotations = local.rotations();
+        if let Ok(mut l) = self.local.lock() {
+            *l = Some(local);
+        }
+        retire_on(rotations, self.retire.clone())
+    }
+}
+
+/// Retires all services built with `retire` each time `rotations` yields.
+fn retire_on<S>(rotations: S, retire: Retire) -> impl Future<Item = (), Error = ()>
+where
+    S: Stream<Item = ()>,
+{
+    rotations
+        .for_each(move |()| {
+            info!("Identity certificate rotated; retiring outbound clients");
+            retire.retire_all();
+            Ok(())
+        })
+        .map_err(|_| ())
+}
+
+impl FmtMetrics for Rotation {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let expiry = match self.local.lock() {
+            Ok(local) => local.as_ref().and_then(identity::Local::crt_expiry),
+            Err(_) => return Ok(()),
+        };
+        if let Some(expiry) = expiry {
+            let secs = expiry
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            identity_cert_expiration_timestamp_seconds.fmt_help(f)?;
+            identity_cert_expiration_timestamp_seconds.fmt_metric(f, Gauge::from(secs))?;
+        }
+
+        identity_rotation_retired_clients_total.fmt_help(f)?;
+        identity_rotation_retired_clients_total
+            .fmt_metric(f, Counter::from(self.retire.retired() as u64))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::svc;
+    use futures::{future, sync::mpsc};
+    use linkerd2_error::{recover::Immediately, Never};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn rotations_retire_clients() {
+        let rotation = Rotation::default();
+
+        // Each client responds with the order in which it was built.
+        let built = Arc::new(AtomicUsize::new(0));
+        let make = svc::mk(move |()| {
+            let n = built.fetch_add(1, Ordering::SeqCst);
+            future::ok::<_, Never>(svc::mk(move |()| future::ok::<_, Never>(n)))
+        });
+        let mut client = linkerd2_reconnect::Service::new((), make, Immediately::new())
+            .with_retire(Some(rotation.retire()));
+        let mut send = move || {
+            let ready = svc::Service::<()>::poll_ready(&mut client).expect("must not fail");
+            assert!(ready.is_ready(), "client must be ready");
+            svc::Service::<()>::call(&mut client, ())
+                .wait()
+                .expect("request must succeed")
+        };
+        assert_eq!(send(), 0);
+        assert_eq!(send(), 0);
+
+        let (rotate, rotations) = mpsc::unbounded();
+        rotate.unbounded_send(()).unwrap();
+        drop(rotate);
+        retire_on(rotations, rotation.retire()).wait().unwrap();
+
+        assert_eq!(send(), 1, "client must be rebuilt after a rotation");
+        assert_eq!(send(), 1);
+
+        let metrics = rotation.as_display().to_string();
+        assert!(metrics.contains("identity_rotation_retired_clients_total 1\n"));
+        // No certificate has been provisioned.
+        assert!(!metrics.contains("identity_cert_expiration_timestamp_seconds"));
+    }
+}
@@ -22,21 +22,40 @@ pub use linkerd2_router as router;
 pub use linkerd2_trace_context as trace_context;
 
 pub mod accept_error;
+pub mod accept_timeout;
+pub mod accept_watchdog;
 pub mod admin;
+pub mod admission;
+pub mod cache_lock_wait;
+pub mod caller_override;
 pub mod classify;
 pub mod config;
+pub mod connection_limit;
 pub mod control;
+pub mod deadline_shed;
 pub mod dns;
 pub mod dst;
+pub mod dst_conflict;
+pub mod dst_name_limit;
+pub mod endpoint_timeout;
+pub mod error_log;
 pub mod errors;
+pub mod events;
 pub mod handle_time;
+pub mod header_trust;
+pub mod identity_rotation;
 pub mod metric_labels;
+pub mod orig_proto_coverage;
 pub mod profiles;
 pub mod proxy;
+pub mod route_backend;
 pub mod serve;
 pub mod spans;
+pub mod stack_stage;
+pub mod startup;
 pub mod svc;
 pub mod telemetry;
+pub mod tls_passthrough;
 pub mod trace;
 pub mod transport;
 
@@ -46,6 +65,9 @@ pub const L5D_REMOTE_IP: &'static str = "l5d-remote-ip";
 pub const L5D_SERVER_ID: &'static str = "l5d-server-id";
 pub const L5D_CLIENT_ID: &'static str = "l5d-client-id";
 pub const L5D_REQUIRE_ID: &'static str = "l5d-require-id";
+pub const L5D_RETRIES: &'static str = "l5d-retries";
+pub const L5D_TIMEOUT: &'static str = "l5d-timeout";
+pub const L5D_PROXY_ERROR: &'static str = "l5d-proxy-error";
 
 const DEFAULT_PORT: u16 = 80;
 
@@ -91,6 +113,18 @@ impl DispatchDeadline {
     pub fn extract<A>(req: &http::Request<A>) -> Option<std::time::Instant> {
         req.extensions().get::<DispatchDeadline>().map(|d| d.0)
     }
+
+    /// Ensures that the request is dispatched within `allowance`, keeping its
+    /// existing deadline if that is sooner.
+    pub fn limit<A>(req: &mut http::Request<A>, allowance: std::time::Duration) {
+        let limit = tokio_timer::clock::now() + allowance;
+        match req.extensions().get::<DispatchDeadline>() {
+            Some(&DispatchDeadline(deadline)) if deadline <= limit => {}
+            _ => {
+                req.extensions_mut().insert(DispatchDeadline(limit));
+            }
+        }
+    }
 }
 
 pub type ControlHttpMetricsRegistry =
@@ -104,9 +138,33 @@ pub type HttpRouteMetricsRegistry =
 
 #[derive(Clone)]
 pub struct ProxyMetrics {
+    pub accept_watchdog: accept_watchdog::Counts,
+    pub admission: admission::Registry,
+    pub cache_lock_wait: cache_lock_wait::Registry,
+    pub caller_override: caller_override::Registry,
+    pub connection_limit: connection_limit::Registry,
+    pub dns_canonicalize: proxy::http::canonicalize::Metrics,
+    pub deadline_shed: proxy::buffer::ShedCount,
+    pub dry_run: proxy::http::dry_run::Metrics,
+    pub header_trust: header_trust::Registry,
+    pub identity_rotation: identity_rotation::Rotation,
+    pub invariant_violations: errors::Registry,
     pub http_handle_time: proxy::http::metrics::handle_time::Scope,
     pub http_route: HttpRouteMetricsRegistry,
     pub http_route_retry: HttpRouteMetricsRegistry,
+    pub http_route_backend: route_backend::Registry,
     pub http_endpoint: HttpEndpointMetricsRegistry,
+    pub http_endpoint_pool: proxy::http::pool::Registry<metric_labels::EndpointLabels>,
+    pub orig_proto_coverage: orig_proto_coverage::Coverage,
+    pub response_reset: proxy::http::response_reset::Metrics,
+    pub body_checksum: proxy::http::checksum::Metrics,
+    pub endpoint_timeout: endpoint_timeout::Registry,
+    pub fallback_hops: proxy::fallback::Hops,
+    pub dst_conflict: dst_conflict::Metrics,
+    pub dst_name_limit: dst_name_limit::Limit,
+    pub route_unmatched: proxy::http::profiles::Unmatched,
+    pub tls_passthrough: proxy::server::TlsPassthroughCount,
     pub transport: transport::MetricsRegistry,
+    pub stack_state: admin::StackState,
+    pub stack_stage: stack_stage::Registry,
 }
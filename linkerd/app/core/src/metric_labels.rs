@@ -1,5 +1,6 @@
-use crate::proxy::identity;
+use crate::proxy::{http::profiles::DefaultRoute, identity};
 use crate::transport::{labels::TlsStatus, tls};
+use indexmap::IndexSet;
 use linkerd2_addr::{Addr, NameAddr};
 use linkerd2_conditional::Conditional;
 use linkerd2_metrics::FmtLabels;
@@ -22,10 +23,18 @@ pub struct EndpointLabels {
     pub labels: Option<String>,
 }
 
+/// Labels endpoint metrics by destination, rather than by each endpoint.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DstLabels {
+    direction: Direction,
+    dst_logical: Option<NameAddr>,
+    dst_concrete: Option<NameAddr>,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct RouteLabels {
     dst: dst::DstAddr,
-    labels: Option<String>,
+    labels: Vec<(String, String)>,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
@@ -67,9 +76,37 @@ impl FmtLabels for ControlLabels {
 
 impl From<dst::Route> for RouteLabels {
     fn from(r: dst::Route) -> Self {
+        // Default routes are labeled so that requests which matched no
+        // route may be distinguished from those to destinations without a
+        // profile.
+        let labels = match r.route.default_route() {
+            Some(DefaultRoute::NoProfile) => vec![("no_profile".to_string(), "true".to_string())],
+            Some(DefaultRoute::Unmatched) => vec![("default".to_string(), "true".to_string())],
+            None => r
+                .route
+                .labels()
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+        };
         RouteLabels {
             dst: r.dst_addr,
-            labels: prefix_labels("rt", r.route.labels().as_ref().into_iter()),
+            labels,
+        }
+    }
+}
+
+impl RouteLabels {
+    /// Returns these labels without any of the route labels named in `drop`.
+    pub fn without(&self, drop: &IndexSet<String>) -> Self {
+        RouteLabels {
+            dst: self.dst.clone(),
+            labels: self
+                .labels
+                .iter()
+                .filter(|(k, _)| !drop.contains(k))
+                .cloned()
+                .collect(),
         }
     }
 }
@@ -78,8 +115,8 @@ impl FmtLabels for RouteLabels {
     fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         self.dst.fmt_labels(f)?;
 
-        if let Some(labels) = self.labels.as_ref() {
-            write!(f, ",{}", labels)?;
+        for (k, v) in &self.labels {
+            write!(f, ",rt_{}=\"{}\"", k, v)?;
         }
 
         Ok(())
@@ -109,6 +146,31 @@ impl FmtLabels for EndpointLabels {
     }
 }
 
+// === impl DstLabels ===
+
+impl<'a> From<&'a EndpointLabels> for DstLabels {
+    fn from(ep: &'a EndpointLabels) -> Self {
+        DstLabels {
+            direction: ep.direction,
+            dst_logical: ep.dst_logical.clone(),
+            dst_concrete: ep.dst_concrete.clone(),
+        }
+    }
+}
+
+impl FmtLabels for DstLabels {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let authority = self.dst_logical.as_ref().map(Authority);
+        (authority, &self.direction).fmt_labels(f)?;
+
+        if let Some(concrete) = self.dst_concrete.as_ref() {
+            write!(f, ",dst_concrete=\"{}\"", concrete)?;
+        }
+
+        Ok(())
+    }
+}
+
 impl FmtLabels for Direction {
     fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -186,3 +248,69 @@ where
     }
     Some(out)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::http::profiles::{self, WithRoute};
+    use crate::proxy::http::settings::Settings;
+
+    struct Fmt<L>(L);
+
+    impl<L: FmtLabels> fmt::Display for Fmt<L> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            self.0.fmt_labels(f)
+        }
+    }
+
+    fn route_labels(route: profiles::Route) -> String {
+        let addr = Addr::from_str("web.example.com:8080").unwrap();
+        let dst = dst::DstAddr::outbound(addr, Settings::Http2);
+        Fmt(RouteLabels::from(dst.with_route(route))).to_string()
+    }
+
+    #[test]
+    fn route_labels_distinguish_default_routes() {
+        let configured = profiles::Route::new(
+            vec![("route".to_string(), "GET /books".to_string())].into_iter(),
+            vec![],
+        );
+        let configured = route_labels(configured);
+        let unmatched = route_labels(profiles::Route::new_default(DefaultRoute::Unmatched));
+        let no_profile = route_labels(profiles::Route::new_default(DefaultRoute::NoProfile));
+
+        assert_eq!(
+            configured,
+            "direction=\"outbound\",dst=\"web.example.com:8080\",rt_route=\"GET /books\""
+        );
+        assert_eq!(
+            unmatched,
+            "direction=\"outbound\",dst=\"web.example.com:8080\",rt_default=\"true\""
+        );
+        assert_eq!(
+            no_profile,
+            "direction=\"outbound\",dst=\"web.example.com:8080\",rt_no_profile=\"true\""
+        );
+    }
+
+    #[test]
+    fn route_labels_without_dropped_labels() {
+        let route = profiles::Route::new(
+            vec![
+                ("route".to_string(), "GET /books".to_string()),
+                ("pod".to_string(), "web-1234".to_string()),
+            ]
+            .into_iter(),
+            vec![],
+        );
+        let addr = Addr::from_str("web.example.com:8080").unwrap();
+        let dst = dst::DstAddr::outbound(addr, Settings::Http2);
+        let labels = RouteLabels::from(dst.with_route(route));
+
+        let drop = Some("pod".to_string()).into_iter().collect();
+        assert_eq!(
+            Fmt(labels.without(&drop)).to_string(),
+            "direction=\"outbound\",dst=\"web.example.com:8080\",rt_route=\"GET /books\""
+        );
+    }
+}
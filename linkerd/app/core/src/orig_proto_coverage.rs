@@ -0,0 +1,132 @@
+//! Reports the fraction of the outbound proxy's resolved endpoints to which
+//! HTTP/1 requests may be upgraded with the `l5d-orig-proto` header, i.e. how
+//! much of its traffic may be carried between meshed proxies.
+
+use linkerd2_metrics::{metrics, FmtLabels, FmtMetric, FmtMetrics};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+metrics! {
+    endpoint_orig_proto_capable_ratio: Ratio {
+        "The fraction of currently-resolved outbound endpoints that can be sent upgraded HTTP/1 requests"
+    }
+}
+
+/// Counts the endpoints that are currently resolved, and how many of them are
+/// orig-proto capable.
+#[derive(Clone, Debug, Default)]
+pub struct Coverage(Arc<Mutex<Counts>>);
+
+#[derive(Debug, Default)]
+struct Counts {
+    resolved: u64,
+    capable: u64,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Ratio(f64);
+
+// === impl Coverage ===
+
+impl Coverage {
+    /// Records that an endpoint has been resolved.
+    pub fn add(&self, capable: bool) {
+        if let Ok(mut counts) = self.0.lock() {
+            counts.resolved += 1;
+            if capable {
+                counts.capable += 1;
+            }
+        }
+    }
+
+    /// Records that a resolved endpoint has been removed.
+    pub fn remove(&self, capable: bool) {
+        if let Ok(mut counts) = self.0.lock() {
+            counts.resolved = counts.resolved.saturating_sub(1);
+            if capable {
+                counts.capable = counts.capable.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Returns the fraction of resolved endpoints that are capable, if any
+    /// endpoints are resolved.
+    pub fn ratio(&self) -> Option<f64> {
+        let counts = self.0.lock().ok()?;
+        if counts.resolved == 0 {
+            return None;
+        }
+        Some(counts.capable as f64 / counts.resolved as f64)
+    }
+}
+
+impl FmtMetrics for Coverage {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ratio = match self.ratio() {
+            Some(ratio) => ratio,
+            None => return Ok(()),
+        };
+
+        endpoint_orig_proto_capable_ratio.fmt_help(f)?;
+        endpoint_orig_proto_capable_ratio.fmt_metric(f, Ratio(ratio))?;
+
+        Ok(())
+    }
+}
+
+// === impl Ratio ===
+
+impl FmtMetric for Ratio {
+    const KIND: &'static str = "gauge";
+
+    fn fmt_metric<N: fmt::Display>(&self, f: &mut fmt::Formatter<'_>, name: N) -> fmt::Result {
+        writeln!(f, "{} {}", name, self.0)
+    }
+
+    fn fmt_metric_labeled<N, L>(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        name: N,
+        labels: L,
+    ) -> fmt::Result
+    where
+        N: fmt::Display,
+        L: FmtLabels,
+    {
+        write!(f, "{}{{", name)?;
+        labels.fmt_labels(f)?;
+        writeln!(f, "}} {}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ratio_tracks_resolved_endpoints() {
+        let coverage = Coverage::default();
+        assert_eq!(coverage.ratio(), None);
+        assert_eq!(coverage.as_display().to_string(), "");
+
+        coverage.add(true);
+        assert_eq!(coverage.ratio(), Some(1.0));
+
+        coverage.add(false);
+        coverage.add(false);
+        coverage.add(true);
+        assert_eq!(coverage.ratio(), Some(0.5));
+        assert!(coverage
+            .as_display()
+            .to_string()
+            .contains("endpoint_orig_proto_capable_ratio 0.5\n"));
+
+        coverage.remove(false);
+        coverage.remove(false);
+        assert_eq!(coverage.ratio(), Some(1.0));
+
+        coverage.remove(true);
+        coverage.remove(true);
+        assert_eq!(coverage.ratio(), None);
+    }
+}
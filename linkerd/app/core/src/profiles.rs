@@ -1,25 +1,139 @@
 use crate::dns;
+use crate::exp_backoff::{ExponentialBackoff, ExponentialBackoffStream};
 use crate::proxy::http::{profiles, retry::Budget};
 use futures::{Async, Future, Poll, Stream};
 use http;
+use indexmap::IndexMap;
 use linkerd2_addr::NameAddr;
 use linkerd2_error::Never;
+use linkerd2_metrics::{metrics, Counter, FmtLabels, FmtMetrics, Gauge};
 use linkerd2_proxy_api::destination as api;
 use regex::Regex;
-use std::sync::Arc;
-use std::time::Duration;
+use std::fmt::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::sync::{oneshot, watch};
-use tokio_timer::{clock, Delay};
+use tokio_sync::semaphore::{Permit, Semaphore};
+use tokio_timer::clock;
 use tower_grpc::{self as grpc, generic::client::GrpcService, Body, BoxBody};
 use tracing::{debug, error, trace, warn};
 use tracing_futures::Instrument;
 
+metrics! {
+    route_profile_info: Gauge {
+        "Identifies the version of each watched destination's profile by a hash of its content"
+    },
+    profile_rebuilds_in_flight: Gauge {
+        "The number of profile lookups that have been started but not yet answered"
+    },
+    profile_rebuilds_queued: Gauge {
+        "The number of profile lookups waiting for the concurrent rebuild limit"
+    },
+    profile_routes_truncated_total: Counter {
+        "Total count of profiles whose routes were truncated to the maximum number of routes"
+    }
+}
+
+/// By default, no more than this many profile lookups may be in flight.
+pub const DEFAULT_MAX_CONCURRENT_REBUILDS: usize = 100;
+
 #[derive(Clone, Debug)]
 pub struct Client<T> {
     service: api::client::Destination<T>,
-    backoff: Duration,
+    retry: Retry,
     context_token: String,
     suffixes: Vec<dns::Suffix>,
+    updates: Updates,
+    rebuild_limit: Arc<Semaphore>,
+    rebuilds: Rebuilds,
+    route_limit: RouteLimit,
+}
+
+/// Configures how failed profile lookups are retried.
+#[derive(Copy, Clone, Debug)]
+pub struct Retry {
+    /// The number of consecutive failed lookups that are retried before a
+    /// destination's profile falls back to the default routes.
+    ///
+    /// Until then, the destination's last profile continues to be used.
+    pub max_retries: usize,
+    pub backoff: ExponentialBackoff,
+}
+
+/// Records the time at which each watched destination's profile was last
+/// updated, along with the version of the profile.
+///
+/// Destinations are forgotten once their profile is no longer watched by any
+/// daemon. A destination's router may be rebuilt while its previous daemon is
+/// still shutting down, so each destination counts its daemons rather than
+/// letting the previous daemon forget the rebuilt one's destination.
+#[derive(Clone, Debug, Default)]
+pub struct Updates(Arc<Mutex<IndexMap<NameAddr, Watch>>>);
+
+#[derive(Debug, Default)]
+struct Watch {
+    daemons: usize,
+    version: Option<Version>,
+}
+
+/// Records the number of profile lookups that are in flight or queued.
+///
+/// A lookup is started whenever a destination's profile is (re)built, e.g.
+/// when its router cache entry expired and a request rebuilt it. Lookups are
+/// limited so that many destinations expiring together don't flood the
+/// controller; excess lookups are queued until one completes.
+#[derive(Clone, Debug, Default)]
+pub struct Rebuilds(Arc<Mutex<RebuildGauges>>);
+
+#[derive(Debug, Default)]
+struct RebuildGauges {
+    in_flight: Gauge,
+    queued: Gauge,
+}
+
+/// Counts the profiles whose routes were truncated.
+#[derive(Clone, Debug, Default)]
+pub struct Truncations(Arc<Mutex<Counter>>);
+
+/// Bounds the number of routes applied from each profile, since a service is
+/// built for each of a profile's routes.
+#[derive(Clone, Debug, Default)]
+struct RouteLimit {
+    max: Option<usize>,
+    truncations: Truncations,
+}
+
+/// A daemon's claim on one of the limited concurrent profile lookups.
+struct Rebuild {
+    limit: Arc<Semaphore>,
+    permit: Permit,
+    state: RebuildState,
+    rebuilds: Rebuilds,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum RebuildState {
+    Idle,
+    Queued,
+    InFlight,
+}
+
+/// Identifies the profile that was applied to a destination.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Version {
+    pub updated_at: Instant,
+    /// A hash of the profile's content, which is identical across proxies for
+    /// identical profiles.
+    pub hash: u64,
+}
+
+/// A 64-bit FNV-1a hash, which (unlike the standard library's hashers) is
+/// guaranteed to be stable across builds.
+struct Fnv(u64);
+
+struct ProfileLabels<'a> {
+    dst: &'a NameAddr,
+    hash: u64,
 }
 
 pub struct Rx {
@@ -31,12 +145,16 @@ struct Daemon<T>
 where
     T: GrpcService<BoxBody>,
 {
-    backoff: Duration,
+    failures: Failures,
     service: api::client::Destination<T>,
     state: State<T>,
     tx: watch::Sender<profiles::Routes>,
     hangup: oneshot::Receiver<Never>,
     request: api::GetDestination,
+    updates: Updates,
+    rebuild: Rebuild,
+    route_limit: RouteLimit,
+    dst: NameAddr,
 }
 
 enum State<T>
@@ -44,11 +162,18 @@ where
     T: GrpcService<BoxBody>,
 {
     Disconnected,
-    Backoff(Delay),
+    Backoff,
     Waiting(grpc::client::server_streaming::ResponseFuture<api::DestinationProfile, T::Future>),
     Streaming(grpc::Streaming<api::DestinationProfile, T::ResponseBody>),
 }
 
+/// Tracks a destination's consecutive failed profile lookups.
+struct Failures {
+    retry: Retry,
+    failures: usize,
+    backoff: ExponentialBackoffStream,
+}
+
 // === impl Client ===
 
 impl<T> Client<T>
@@ -63,15 +188,44 @@ where
 {
     pub fn new(
         service: T,
-        backoff: Duration,
+        retry: Retry,
         context_token: String,
         suffixes: impl IntoIterator<Item = dns::Suffix>,
     ) -> Self {
         Self {
             service: api::client::Destination::new(service),
-            backoff,
+            retry,
             context_token,
             suffixes: suffixes.into_iter().collect(),
+            updates: Updates::default(),
+            rebuild_limit: Arc::new(Semaphore::new(DEFAULT_MAX_CONCURRENT_REBUILDS)),
+            rebuilds: Rebuilds::default(),
+            route_limit: RouteLimit::default(),
+        }
+    }
+
+    /// Records profile update times in `updates`.
+    pub fn with_updates(self, updates: Updates) -> Self {
+        Self { updates, ..self }
+    }
+
+    /// Limits the number of profile lookups that may be in flight at once to
+    /// `max` (at least one), recording in-flight and queued lookups in
+    /// `rebuilds`.
+    pub fn with_rebuild_limit(self, max: usize, rebuilds: Rebuilds) -> Self {
+        Self {
+            rebuild_limit: Arc::new(Semaphore::new(max.max(1))),
+            rebuilds,
+            ..self
+        }
+    }
+
+    /// Applies at most `max` routes from each profile, if set, ignoring the
+    /// rest and recording the truncation in `truncations`.
+    pub fn with_max_routes(self, max: Option<usize>, truncations: Truncations) -> Self {
+        Self {
+            route_limit: RouteLimit { max, truncations },
+            ..self
         }
     }
 }
@@ -96,17 +250,22 @@ where
         // is dropped.
         let (hangup_tx, hangup_rx) = oneshot::channel();
         let (tx, rx) = watch::channel(profiles::Routes::default());
+        self.updates.watch(dst);
         let daemon = Daemon {
             tx,
             hangup: hangup_rx,
             state: State::Disconnected,
             service: self.service.clone(),
-            backoff: self.backoff,
+            failures: Failures::new(self.retry),
             request: api::GetDestination {
                 path: format!("{}", dst),
                 context_token: self.context_token.clone(),
                 ..Default::default()
             },
+            updates: self.updates.clone(),
+            rebuild: Rebuild::new(self.rebuild_limit.clone(), self.rebuilds.clone()),
+            route_limit: self.route_limit.clone(),
+            dst: dst.clone(),
         };
 
         tokio::spawn(daemon.in_current_span().map_err(|never| match never {}));
@@ -117,6 +276,282 @@ where
     }
 }
 
+// === impl Updates ===
+
+impl Updates {
+    /// Returns each watched destination along with the version of its
+    /// profile, if it has been updated at all.
+    pub fn snapshot(&self) -> Vec<(NameAddr, Option<Version>)> {
+        self.0
+            .lock()
+            .expect("profile updates poisoned")
+            .iter()
+            .map(|(dst, watch)| (dst.clone(), watch.version))
+            .collect()
+    }
+
+    /// Returns the number of destinations whose profiles are watched.
+    pub fn watched(&self) -> usize {
+        self.0.lock().map(|updates| updates.len()).unwrap_or(0)
+    }
+
+    pub(crate) fn watch(&self, dst: &NameAddr) {
+        if let Ok(mut updates) = self.0.lock() {
+            updates
+                .entry(dst.clone())
+                .or_insert_with(Watch::default)
+                .daemons += 1;
+        }
+    }
+
+    pub(crate) fn record(&self, dst: &NameAddr, hash: u64) {
+        if let Ok(mut updates) = self.0.lock() {
+            let version = Version {
+                updated_at: clock::now(),
+                hash,
+            };
+            if let Some(watch) = updates.get_mut(dst) {
+                watch.version = Some(version);
+            }
+        }
+    }
+
+    fn forget(&self, dst: &NameAddr) {
+        if let Ok(mut updates) = self.0.lock() {
+            let unwatched = match updates.get_mut(dst) {
+                Some(watch) => {
+                    watch.daemons = watch.daemons.saturating_sub(1);
+                    watch.daemons == 0
+                }
+                None => false,
+            };
+            if unwatched {
+                updates.swap_remove(dst);
+            }
+        }
+    }
+}
+
+impl FmtMetrics for Updates {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let updates = match self.0.lock() {
+            Ok(updates) => updates,
+            Err(_) => return Ok(()),
+        };
+        let versions = updates
+            .iter()
+            .filter_map(|(dst, watch)| {
+                let hash = watch.version.as_ref()?.hash;
+                Some((ProfileLabels { dst, hash }, Gauge::from(1)))
+            })
+            .collect::<Vec<_>>();
+        if versions.is_empty() {
+            return Ok(());
+        }
+
+        route_profile_info.fmt_help(f)?;
+        route_profile_info.fmt_scopes(f, versions.iter().map(|(l, g)| (l, g)), |g| g)?;
+
+        Ok(())
+    }
+}
+
+impl<'a> FmtLabels for ProfileLabels<'a> {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "dst=\"{}\",hash=\"{:016x}\"", self.dst, self.hash)
+    }
+}
+
+// === impl Rebuilds ===
+
+impl Rebuilds {
+    fn update(&self, f: impl FnOnce(&mut RebuildGauges)) {
+        if let Ok(mut gauges) = self.0.lock() {
+            f(&mut *gauges);
+        }
+    }
+}
+
+impl FmtMetrics for Rebuilds {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let gauges = match self.0.lock() {
+            Ok(gauges) => gauges,
+            Err(_) => return Ok(()),
+        };
+
+        profile_rebuilds_in_flight.fmt_help(f)?;
+        profile_rebuilds_in_flight.fmt_metric(f, gauges.in_flight)?;
+
+        profile_rebuilds_queued.fmt_help(f)?;
+        profile_rebuilds_queued.fmt_metric(f, gauges.queued)?;
+
+        Ok(())
+    }
+}
+
+// === impl Truncations ===
+
+impl Truncations {
+    fn incr(&self) {
+        if let Ok(mut truncations) = self.0.lock() {
+            truncations.incr();
+        }
+    }
+}
+
+impl FmtMetrics for Truncations {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let truncations = match self.0.lock() {
+            Ok(truncations) => *truncations,
+            Err(_) => return Ok(()),
+        };
+
+        profile_routes_truncated_total.fmt_help(f)?;
+        profile_routes_truncated_total.fmt_metric(f, truncations)?;
+
+        Ok(())
+    }
+}
+
+// === impl RouteLimit ===
+
+impl RouteLimit {
+    /// Truncates a profile's routes to the maximum. Routes are matched in
+    /// order, so the first routes are kept.
+    fn apply<R>(&self, dst: &NameAddr, routes: &mut Vec<R>) {
+        let max = match self.max {
+            Some(max) if routes.len() > max => max,
+            _ => return,
+        };
+        warn!(
+            %dst,
+            routes = routes.len(),
+            max = max,
+            "profile has too many routes; ignoring the excess routes"
+        );
+        routes.truncate(max);
+        self.truncations.incr();
+    }
+}
+
+// === impl Rebuild ===
+
+impl Rebuild {
+    fn new(limit: Arc<Semaphore>, rebuilds: Rebuilds) -> Self {
+        Self {
+            limit,
+            permit: Permit::new(),
+            state: RebuildState::Idle,
+            rebuilds,
+        }
+    }
+
+    /// Polls for permission to start a lookup, queueing it if the limit has
+    /// been reached.
+    fn poll_start(&mut self) -> Async<()> {
+        if self.state == RebuildState::InFlight {
+            return Async::Ready(());
+        }
+
+        match self.permit.poll_acquire(&self.limit) {
+            Ok(Async::NotReady) => {
+                if self.state == RebuildState::Idle {
+                    self.state = RebuildState::Queued;
+                    self.rebuilds.update(|g| g.queued.incr());
+                }
+                Async::NotReady
+            }
+            // The limit is never closed.
+            Ok(Async::Ready(())) | Err(_) => {
+                if self.state == RebuildState::Queued {
+                    self.rebuilds.update(|g| g.queued.decr());
+                }
+                self.state = RebuildState::InFlight;
+                self.rebuilds.update(|g| g.in_flight.incr());
+                Async::Ready(())
+            }
+        }
+    }
+
+    /// Completes (or abandons) a lookup so that a queued lookup may start.
+    fn finish(&mut self) {
+        match self.state {
+            RebuildState::Idle => return,
+            RebuildState::Queued => self.rebuilds.update(|g| g.queued.decr()),
+            RebuildState::InFlight => self.rebuilds.update(|g| g.in_flight.decr()),
+        }
+        self.state = RebuildState::Idle;
+        self.permit.release(&self.limit);
+    }
+}
+
+impl Drop for Rebuild {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+// === impl Fnv ===
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Write for Fnv {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for b in s.bytes() {
+            self.0 ^= u64::from(b);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+        Ok(())
+    }
+}
+
+// === impl Failures ===
+
+impl Failures {
+    fn new(retry: Retry) -> Self {
+        Self {
+            retry,
+            failures: 0,
+            backoff: retry.backoff.stream(),
+        }
+    }
+
+    /// Restores the retry budget once a profile has been received.
+    fn reset(&mut self) {
+        if self.failures > 0 {
+            self.failures = 0;
+            self.backoff = self.retry.backoff.stream();
+        }
+    }
+
+    /// Records a failed lookup, falling back to the default routes once the
+    /// retry budget is exhausted.
+    ///
+    /// Returns false if the profile is no longer watched.
+    fn record(&mut self, tx: &mut watch::Sender<profiles::Routes>) -> bool {
+        self.failures += 1;
+        if self.failures == self.retry.max_retries + 1 {
+            warn!(
+                failures = self.failures,
+                "profile lookups failed; falling back to default routes"
+            );
+            return tx.broadcast(profiles::Routes::default()).is_ok();
+        }
+        true
+    }
+
+    fn poll_backoff(&mut self) -> Async<()> {
+        match self.backoff.poll() {
+            Ok(Async::NotReady) => Async::NotReady,
+            Ok(Async::Ready(_)) | Err(_) => Async::Ready(()),
+        }
+    }
+}
+
 // === impl Rx ===
 
 impl Stream for Rx {
@@ -143,6 +578,10 @@ where
         rx: &mut grpc::Streaming<api::DestinationProfile, T::ResponseBody>,
         tx: &mut watch::Sender<profiles::Routes>,
         hangup: &mut oneshot::Receiver<Never>,
+        failures: &mut Failures,
+        updates: &Updates,
+        route_limit: &RouteLimit,
+        dst: &NameAddr,
     ) -> Async<StreamState> {
         loop {
             match rx.poll() {
@@ -161,25 +600,18 @@ where
                 },
                 Ok(Async::Ready(None)) => return StreamState::RecvDone.into(),
                 Ok(Async::Ready(Some(proto))) => {
-                    debug!("profile received: {:?}", proto);
-                    let retry_budget = proto.retry_budget.and_then(convert_retry_budget);
-                    let routes = proto
-                        .routes
-                        .into_iter()
-                        .filter_map(move |orig| convert_route(orig, retry_budget.as_ref()))
-                        .collect();
-                    let dst_overrides = proto
-                        .dst_overrides
-                        .into_iter()
-                        .filter_map(convert_dst_override)
-                        .collect();
-                    let profile = profiles::Routes {
-                        routes,
-                        dst_overrides,
-                    };
+                    let hash = profile_hash(&proto);
+                    debug!(
+                        profile.hash = %format!("{:016x}", hash),
+                        "profile received: {:?}",
+                        proto
+                    );
+                    let profile = convert_profile(proto, hash, route_limit, dst);
                     if tx.broadcast(profile).is_err() {
                         return StreamState::SendLost.into();
                     }
+                    failures.reset();
+                    updates.record(dst, hash);
                 }
                 Err(e) => {
                     warn!("profile stream failed: {:?}", e);
@@ -201,6 +633,10 @@ where
         loop {
             self.state = match self.state {
                 State::Disconnected => {
+                    if self.rebuild.poll_start().is_not_ready() {
+                        trace!("waiting for the rebuild limit");
+                        return Ok(Async::NotReady);
+                    }
                     match self.service.poll_ready() {
                         Ok(Async::NotReady) => return Ok(Async::NotReady),
                         Ok(Async::Ready(())) => {}
@@ -219,31 +655,104 @@ where
                     Ok(Async::NotReady) => return Ok(Async::NotReady),
                     Ok(Async::Ready(rsp)) => {
                         trace!("response received");
+                        self.rebuild.finish();
                         State::Streaming(rsp.into_inner())
                     }
                     Err(e) => {
                         warn!("error fetching profile: {:?}", e);
-                        State::Backoff(Delay::new(clock::now() + self.backoff))
+                        self.rebuild.finish();
+                        if !self.failures.record(&mut self.tx) {
+                            return Ok(().into());
+                        }
+                        State::Backoff
                     }
                 },
                 State::Streaming(ref mut s) => {
-                    match Self::proxy_stream(s, &mut self.tx, &mut self.hangup) {
+                    match Self::proxy_stream(
+                        s,
+                        &mut self.tx,
+                        &mut self.hangup,
+                        &mut self.failures,
+                        &self.updates,
+                        &self.route_limit,
+                        &self.dst,
+                    ) {
                         Async::NotReady => return Ok(Async::NotReady),
                         Async::Ready(StreamState::SendLost) => return Ok(().into()),
                         Async::Ready(StreamState::RecvDone) => {
-                            State::Backoff(Delay::new(clock::now() + self.backoff))
+                            if !self.failures.record(&mut self.tx) {
+                                return Ok(().into());
+                            }
+                            State::Backoff
                         }
                     }
                 }
-                State::Backoff(ref mut f) => match f.poll() {
-                    Ok(Async::NotReady) => return Ok(Async::NotReady),
-                    Err(_) | Ok(Async::Ready(())) => State::Disconnected,
+                State::Backoff => match self.failures.poll_backoff() {
+                    Async::NotReady => return Ok(Async::NotReady),
+                    Async::Ready(()) => State::Disconnected,
                 },
             };
         }
     }
 }
 
+impl<T> Drop for Daemon<T>
+where
+    T: GrpcService<BoxBody>,
+{
+    fn drop(&mut self) {
+        self.updates.forget(&self.dst);
+    }
+}
+
+/// Hashes the content of a profile.
+///
+/// Messages are hashed by their `Debug` representations, which are
+/// deterministic except for maps, so route labels are hashed in sorted order.
+fn profile_hash(proto: &api::DestinationProfile) -> u64 {
+    let mut hash = Fnv::default();
+    for route in &proto.routes {
+        let mut labels = route.metrics_labels.iter().collect::<Vec<_>>();
+        labels.sort();
+        let _ = write!(
+            hash,
+            "route({:?},{:?},{:?},{:?},{:?})",
+            route.condition, route.response_classes, labels, route.is_retryable, route.timeout,
+        );
+    }
+    let _ = write!(
+        hash,
+        "budget({:?}),overrides({:?})",
+        proto.retry_budget, proto.dst_overrides
+    );
+    hash.0
+}
+
+fn convert_profile(
+    proto: api::DestinationProfile,
+    hash: u64,
+    route_limit: &RouteLimit,
+    dst: &NameAddr,
+) -> profiles::Routes {
+    let retry_budget = proto.retry_budget.and_then(convert_retry_budget);
+    let mut routes = proto
+        .routes
+        .into_iter()
+        .filter_map(move |orig| convert_route(orig, retry_budget.as_ref()))
+        .collect();
+    route_limit.apply(dst, &mut routes);
+    let dst_overrides = proto
+        .dst_overrides
+        .into_iter()
+        .filter_map(convert_dst_override)
+        .collect();
+    profiles::Routes {
+        routes,
+        dst_overrides,
+        hash: Some(hash),
+    }
+}
+
 fn convert_route(
     orig: api::Route,
     retry_budget: Option<&Arc<Budget>>,
@@ -419,7 +928,225 @@ fn convert_retry_budget(orig: api::RetryBudget) -> Option<Arc<Budget>> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use futures::future;
     use quickcheck::*;
+    use tokio::runtime::current_thread::Runtime;
+
+    fn retry(max_retries: usize) -> Retry {
+        Retry {
+            max_retries,
+            backoff: ExponentialBackoff {
+                min: Duration::from_millis(1),
+                max: Duration::from_millis(1),
+                jitter: 0.0,
+            },
+        }
+    }
+
+    fn profile() -> profiles::Routes {
+        let route = profiles::Route::new(std::iter::empty(), Vec::new());
+        profiles::Routes {
+            routes: vec![(profiles::RequestMatch::Method(http::Method::GET), route)],
+            dst_overrides: Vec::new(),
+            hash: None,
+        }
+    }
+
+    fn proto(paths: &[&str]) -> api::DestinationProfile {
+        let routes = paths
+            .iter()
+            .map(|path| {
+                let path = api::request_match::Match::Path(api::PathMatch {
+                    regex: path.to_string(),
+                });
+                let labels = vec![("route", *path), ("team", "web")]
+                    .into_iter()
+                    .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                    .collect();
+                api::Route {
+                    condition: Some(api::RequestMatch {
+                        r#match: Some(path),
+                    }),
+                    metrics_labels: labels,
+                    ..Default::default()
+                }
+            })
+            .collect();
+        api::DestinationProfile {
+            routes,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn profile_versions_are_reported() {
+        let dst = NameAddr::from_str("web.ns.svc.cluster.local:8080").unwrap();
+        let updates = Updates::default();
+        updates.watch(&dst);
+        assert!(updates.as_display().to_string().is_empty());
+
+        let v1 = profile_hash(&proto(&["/users"]));
+        assert_eq!(
+            v1,
+            profile_hash(&proto(&["/users"])),
+            "identical profiles must have the same hash"
+        );
+        updates.record(&dst, v1);
+        let report = updates.as_display().to_string();
+        assert!(report.contains(&format!(
+            "route_profile_info{{dst=\"{}\",hash=\"{:016x}\"}} 1",
+            dst, v1
+        )));
+
+        let v2 = profile_hash(&proto(&["/users", "/books"]));
+        assert_ne!(v1, v2, "changed profiles must have a new hash");
+        updates.record(&dst, v2);
+        let report = updates.as_display().to_string();
+        assert!(report.contains(&format!("hash=\"{:016x}\"", v2)));
+        assert!(!report.contains(&format!("hash=\"{:016x}\"", v1)));
+    }
+
+    #[test]
+    fn rebuilt_destinations_are_not_forgotten_by_previous_daemons() {
+        let dst = NameAddr::from_str("web.ns.svc.cluster.local:8080").unwrap();
+        let updates = Updates::default();
+
+        // The destination is rebuilt before its previous daemon is dropped.
+        updates.watch(&dst);
+        updates.watch(&dst);
+        updates.forget(&dst);
+        assert_eq!(updates.watched(), 1);
+
+        updates.record(&dst, 0xfeed);
+        assert_eq!(updates.snapshot()[0].1.map(|v| v.hash), Some(0xfeed));
+
+        updates.forget(&dst);
+        assert_eq!(updates.watched(), 0);
+    }
+
+    #[test]
+    fn excess_routes_are_truncated() {
+        let dst = NameAddr::from_str("web.ns.svc.cluster.local:8080").unwrap();
+        let truncations = Truncations::default();
+        let route_limit = RouteLimit {
+            max: Some(2),
+            truncations: truncations.clone(),
+        };
+
+        let profile = convert_profile(proto(&["/a", "/b", "/c"]), 1, &route_limit, &dst);
+        let names = profile
+            .routes
+            .iter()
+            .map(|(_, route)| route.labels()["route"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["/a", "/b"], "the first routes must be kept");
+        assert!(truncations
+            .as_display()
+            .to_string()
+            .contains("profile_routes_truncated_total 1\n"));
+    }
+
+    #[test]
+    fn routes_within_the_limit_are_applied() {
+        let dst = NameAddr::from_str("web.ns.svc.cluster.local:8080").unwrap();
+        let truncations = Truncations::default();
+        let route_limit = RouteLimit {
+            max: Some(3),
+            truncations: truncations.clone(),
+        };
+
+        let profile = convert_profile(proto(&["/a", "/b", "/c"]), 1, &route_limit, &dst);
+        assert_eq!(profile.routes.len(), 3);
+        assert!(truncations
+            .as_display()
+            .to_string()
+            .contains("profile_routes_truncated_total 0\n"));
+
+        let profile = convert_profile(proto(&["/a", "/b", "/c"]), 1, &RouteLimit::default(), &dst);
+        assert_eq!(profile.routes.len(), 3, "routes are unlimited by default");
+    }
+
+    #[test]
+    fn transient_failures_are_retried() {
+        let mut rt = Runtime::new().unwrap();
+        let (mut tx, rx) = watch::channel(profile());
+        let mut failures = Failures::new(retry(2));
+
+        for _ in 0..2 {
+            assert!(failures.record(&mut tx));
+            rt.block_on(future::poll_fn(|| Ok::<_, ()>(failures.poll_backoff())))
+                .unwrap();
+        }
+        assert_eq!(rx.get_ref().routes.len(), 1, "profile must be retained");
+
+        // The lookup is retried and succeeds, restoring the retry budget.
+        failures.reset();
+        for _ in 0..2 {
+            assert!(failures.record(&mut tx));
+        }
+        assert_eq!(rx.get_ref().routes.len(), 1, "profile must be retained");
+    }
+
+    #[test]
+    fn persistent_failures_fall_back_to_defaults() {
+        let (mut tx, rx) = watch::channel(profile());
+        let mut failures = Failures::new(retry(2));
+
+        for _ in 0..2 {
+            assert!(failures.record(&mut tx));
+        }
+        assert_eq!(rx.get_ref().routes.len(), 1, "profile must be retained");
+
+        assert!(failures.record(&mut tx));
+        assert!(rx.get_ref().routes.is_empty(), "must fall back to defaults");
+    }
+
+    #[test]
+    fn simultaneous_rebuilds_are_limited() {
+        let mut rt = Runtime::new().unwrap();
+        let limit = Arc::new(Semaphore::new(2));
+        let rebuilds = Rebuilds::default();
+        let gauges = |rebuilds: &Rebuilds| -> (u64, u64) {
+            let g = rebuilds.0.lock().unwrap();
+            (g.in_flight.into(), g.queued.into())
+        };
+
+        rt.block_on(future::lazy(|| {
+            // Many destinations' profiles expire at once.
+            let mut expired = (0..5)
+                .map(|_| Rebuild::new(limit.clone(), rebuilds.clone()))
+                .collect::<Vec<_>>();
+            let mut started = 0;
+            for r in expired.iter_mut() {
+                if r.poll_start().is_ready() {
+                    started += 1;
+                }
+            }
+            assert_eq!(started, 2, "rebuilds must not exceed the limit");
+            assert_eq!(gauges(&rebuilds), (2, 3));
+
+            // Polling again doesn't start more rebuilds.
+            for r in expired.iter_mut() {
+                r.poll_start();
+            }
+            assert_eq!(gauges(&rebuilds), (2, 3));
+
+            // Once a lookup completes, a queued rebuild may start.
+            expired[0].finish();
+            assert_eq!(gauges(&rebuilds), (1, 3));
+            assert!(expired[2].poll_start().is_ready());
+            assert!(expired[3].poll_start().is_not_ready());
+            assert_eq!(gauges(&rebuilds), (2, 2));
+
+            // Dropped destinations leave the queue and release their permits.
+            drop(expired);
+            assert_eq!(gauges(&rebuilds), (0, 0));
+            assert_eq!(limit.available_permits(), 2);
+
+            Ok::<_, ()>(())
+        }))
+        .unwrap();
+    }
 
     quickcheck! {
         fn retry_budget_from_proto(
@@ -1,13 +1,15 @@
-use crate::svc;
-use futures::{try_ready, Async, Future, Poll};
-use linkerd2_error::Error;
+use crate::{drain, svc};
+use futures::{future, try_ready, Async, Future, Poll};
+use linkerd2_error::{contract, Error};
 use linkerd2_router as rt;
 use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex, Weak};
 use std::time::{Duration, Instant};
 use std::{error, fmt};
 use tokio_timer::{clock, Delay};
 use tower::buffer;
+use tracing::debug;
 use tracing_futures::Instrument;
 
 /// Determines the dispatch deadline for a request.
@@ -15,11 +17,39 @@ pub trait Deadline<Req>: Clone {
     fn deadline(&self, req: &Req) -> Option<Instant>;
 }
 
+/// Determines how requests that are still queued when the proxy begins to
+/// drain are handled.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DrainPolicy {
+    /// Queued requests are dispatched as usual.
+    Complete,
+    /// Queued requests fail immediately, so that no new work is dispatched.
+    FailFast,
+}
+
+/// Fails requests without queueing them when their dispatch deadline is too
+/// soon for them to be dispatched in time.
+///
+/// A request that is queued with less than `min_budget` remaining before its
+/// deadline is unlikely to be dispatched before it is aborted, and it would
+/// only hold a place in the queue ahead of requests that could be.
+#[derive(Clone, Debug)]
+pub struct Shed {
+    min_budget: Duration,
+    shed: ShedCount,
+}
+
+/// Counts the requests that were failed by a `Shed`.
+#[derive(Clone, Debug, Default)]
+pub struct ShedCount(Arc<AtomicU64>);
+
 /// Produces `MakeService`s where the output `Service` is wrapped with a `Buffer`
 #[derive(Debug)]
 pub struct Layer<D, Req> {
     capacity: usize,
     deadline: D,
+    drain: Option<drain::Signaled>,
+    shed: Option<Shed>,
     _marker: PhantomData<fn(Req)>,
 }
 
@@ -28,6 +58,8 @@ pub struct Layer<D, Req> {
 pub struct Make<M, D, Req> {
     capacity: usize,
     deadline: D,
+    drain: Option<drain::Signaled>,
+    shed: Option<Shed>,
     inner: M,
     _marker: PhantomData<fn(Req)>,
 }
@@ -41,6 +73,8 @@ where
     S::Error: Into<Error>,
 {
     deadline: D,
+    drain: Option<drain::Signaled>,
+    shed: Option<Shed>,
     inner: buffer::Buffer<Dequeue<S>, Stealer<Req>>,
 }
 
@@ -50,6 +84,7 @@ pub struct EnqueueFuture<F, Req> {
     holder: Holder<Req>,
     inner: buffer::future::ResponseFuture<DequeueFuture<F>>,
     timeout: Option<Delay>,
+    drain: Option<drain::Signaled>,
 }
 
 pub enum DequeueFuture<F> {
@@ -60,9 +95,15 @@ pub enum DequeueFuture<F> {
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Aborted;
 
+/// Indicates that a queued request was failed because the proxy is draining.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Draining;
+
 pub struct MakeFuture<F, D, Req> {
     capacity: usize,
     deadline: D,
+    drain: Option<drain::Signaled>,
+    shed: Option<Shed>,
     inner: F,
     _marker: PhantomData<fn(Req)>,
 }
@@ -77,15 +118,38 @@ where
     Layer {
         capacity,
         deadline,
+        drain: None,
+        shed: None,
         _marker: PhantomData,
     }
 }
 
+impl<D, Req> Layer<D, Req> {
+    /// Fails requests that are still queued once `drain` is signaled.
+    pub fn with_drain(self, drain: drain::Signaled) -> Self {
+        Self {
+            drain: Some(drain),
+            ..self
+        }
+    }
+
+    /// Fails requests without queueing them when `shed` determines that
+    /// their dispatch deadline can't be met.
+    pub fn with_shed(self, shed: Shed) -> Self {
+        Self {
+            shed: Some(shed),
+            ..self
+        }
+    }
+}
+
 impl<D: Clone, Req> Clone for Layer<D, Req> {
     fn clone(&self) -> Self {
         Self {
             capacity: self.capacity,
             deadline: self.deadline.clone(),
+            drain: self.drain.clone(),
+            shed: self.shed.clone(),
             _marker: PhantomData,
         }
     }
@@ -101,6 +165,8 @@ where
         Self::Service {
             capacity: self.capacity,
             deadline: self.deadline.clone(),
+            drain: self.drain.clone(),
+            shed: self.shed.clone(),
             inner,
             _marker: PhantomData,
         }
@@ -114,6 +180,8 @@ impl<M: Clone, D: Clone, Req> Clone for Make<M, D, Req> {
         Self {
             capacity: self.capacity,
             deadline: self.deadline.clone(),
+            drain: self.drain.clone(),
+            shed: self.shed.clone(),
             inner: self.inner.clone(),
             _marker: PhantomData,
         }
@@ -145,6 +213,8 @@ where
         Self::Future {
             capacity: self.capacity,
             deadline: self.deadline.clone(),
+            drain: self.drain.clone(),
+            shed: self.shed.clone(),
             inner,
             _marker: PhantomData,
         }
@@ -169,6 +239,8 @@ where
             self.deadline.clone(),
             self.capacity,
         )
+        .with_drain(self.drain.clone())
+        .with_shed(self.shed.clone())
     }
 }
 
@@ -189,6 +261,8 @@ impl<M, D, Req> Make<M, D, Req> {
             self.deadline.clone(),
             self.capacity,
         )
+        .with_drain(self.drain.clone())
+        .with_shed(self.shed.clone())
     }
 }
 
@@ -209,7 +283,9 @@ where
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         let svc = try_ready!(self.inner.poll().map_err(Into::into));
-        let enq = Enqueue::new(svc, self.deadline.clone(), self.capacity);
+        let enq = Enqueue::new(svc, self.deadline.clone(), self.capacity)
+            .with_drain(self.drain.clone())
+            .with_shed(self.shed.clone());
         Ok(enq.into())
     }
 }
@@ -230,7 +306,20 @@ where
     pub fn new(svc: S, deadline: D, capacity: usize) -> Self {
         let mut exec = tokio::executor::DefaultExecutor::current().in_current_span();
         let inner = buffer::Buffer::with_executor(Dequeue(svc), capacity, &mut exec);
-        Self { deadline, inner }
+        Self {
+            deadline,
+            drain: None,
+            shed: None,
+            inner,
+        }
+    }
+
+    fn with_drain(self, drain: Option<drain::Signaled>) -> Self {
+        Self { drain, ..self }
+    }
+
+    fn with_shed(self, shed: Option<Shed>) -> Self {
+        Self { shed, ..self }
     }
 }
 
@@ -244,22 +333,33 @@ where
 {
     type Response = S::Response;
     type Error = Error;
-    type Future = EnqueueFuture<S::Future, Req>;
+    type Future = future::Either<
+        EnqueueFuture<S::Future, Req>,
+        future::FutureResult<S::Response, Self::Error>,
+    >;
 
     fn poll_ready(&mut self) -> Poll<(), Self::Error> {
         self.inner.poll_ready().map_err(Into::into)
     }
 
     fn call(&mut self, req: Req) -> Self::Future {
-        let timeout = self.deadline.deadline(&req).map(Delay::new);
+        let deadline = self.deadline.deadline(&req);
+        if let (Some(shed), Some(deadline)) = (self.shed.as_ref(), deadline) {
+            if shed.should_shed(deadline) {
+                return future::Either::B(future::err(Aborted.into()));
+            }
+        }
+
+        let timeout = deadline.map(Delay::new);
         let holder = Arc::new(Mutex::new(Some(req)));
         let stealer = Arc::downgrade(&holder);
 
-        EnqueueFuture {
+        future::Either::A(EnqueueFuture {
             holder,
             timeout,
+            drain: self.drain.clone(),
             inner: self.inner.call(stealer),
-        }
+        })
     }
 }
 
@@ -272,6 +372,8 @@ where
     fn clone(&self) -> Self {
         Self {
             deadline: self.deadline.clone(),
+            drain: self.drain.clone(),
+            shed: self.shed.clone(),
             inner: self.inner.clone(),
         }
     }
@@ -293,8 +395,12 @@ where
         }
 
         // If the request hasn't been consumed by `Dequeue`, then steal it and
-        // drop it when the timeout fires.
-        let mut h = self.holder.lock().expect("inner service panicked");
+        // drop it when the timeout fires or when the proxy begins to drain.
+        let mut h = contract!(
+            self.holder.lock().ok(),
+            "buffer::EnqueueFuture::poll",
+            "request holder poisoned by a panicked service"
+        )?;
         if h.is_some() {
             if let Some(t) = self.timeout.as_mut() {
                 if t.poll().map_err(Error::from)?.is_ready() {
@@ -302,9 +408,17 @@ where
                     return Err(Aborted.into());
                 }
             }
+            if let Some(d) = self.drain.as_mut() {
+                if d.poll().map_err(Error::from)?.is_ready() {
+                    drop(h.take());
+                    return Err(Draining.into());
+                }
+            }
         } else {
-            // Drop the timeout future so the timer doesn't need to track it.
+            // Drop the timeout and drain futures so that they needn't be
+            // tracked once the request has been dispatched.
             drop(self.timeout.take());
+            drop(self.drain.take());
         }
 
         return Ok(Async::NotReady);
@@ -362,6 +476,56 @@ impl fmt::Display for Aborted {
 
 impl error::Error for Aborted {}
 
+// === Draining ===
+
+impl fmt::Display for Draining {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the request was not dispatched before the proxy began to drain"
+        )
+    }
+}
+
+impl error::Error for Draining {}
+
+// === impl Shed ===
+
+impl Shed {
+    pub fn new(min_budget: Duration, shed: ShedCount) -> Self {
+        Self { min_budget, shed }
+    }
+
+    /// Returns true (and counts the request) if less than `min_budget`
+    /// remains before `deadline`.
+    fn should_shed(&self, deadline: Instant) -> bool {
+        let now = clock::now();
+        if deadline > now && deadline - now >= self.min_budget {
+            return false;
+        }
+
+        debug!(min_budget = ?self.min_budget, "shedding request before its deadline");
+        self.shed.0.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+}
+
+// === impl ShedCount ===
+
+impl ShedCount {
+    pub fn value(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// === impl DrainPolicy ===
+
+impl Default for DrainPolicy {
+    fn default() -> Self {
+        DrainPolicy::Complete
+    }
+}
+
 // === impl Deadline ===
 
 impl<Req> Deadline<Req> for () {
@@ -438,6 +602,22 @@ mod tests {
         }
     }
 
+    /// Isn't ready until its delay elapses.
+    struct Busy(Delay);
+    impl svc::Service<Duration> for Busy {
+        type Response = ();
+        type Error = Error;
+        type Future = future::FutureResult<(), Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            self.0.poll().map_err(Into::into)
+        }
+
+        fn call(&mut self, _: Duration) -> Self::Future {
+            future::ok(())
+        }
+    }
+
     #[test]
     fn request_aborted_with_idle_service() {
         tokio::run(future::lazy(|| {
@@ -495,4 +675,76 @@ mod tests {
             })
         }));
     }
+
+    #[test]
+    fn request_shed_when_deadline_cannot_be_met() {
+        tokio::run(future::lazy(|| {
+            let count = ShedCount::default();
+            let busy = Busy(Delay::new(clock::now() + Duration::from_millis(200)));
+            // Each request is its remaining budget.
+            let deadline = |budget: &Duration| Some(clock::now() + *budget);
+            let mut svc = Enqueue::new(busy, deadline, 10)
+                .with_shed(Some(Shed::new(Duration::from_millis(100), count.clone())));
+
+            svc.poll_ready().expect("service must be ready");
+            let queued = svc.call(Duration::from_secs(1));
+
+            svc.poll_ready().expect("service must be ready");
+            let start = clock::now();
+            svc.call(Duration::from_millis(50)).then(move |r| {
+                match r {
+                    Ok(_) => panic!("unexpected response from busy service"),
+                    Err(e) => {
+                        e.downcast::<Aborted>().expect("request must be aborted");
+                    }
+                }
+                assert!(
+                    clock::now() - start < Duration::from_millis(50),
+                    "request must be rejected without waiting for the service"
+                );
+                assert_eq!(count.value(), 1);
+
+                queued.map_err(|_| panic!("queued request must complete"))
+            })
+        }));
+    }
+
+    #[test]
+    fn queued_request_fails_fast_when_draining() {
+        tokio::run(future::lazy(|| {
+            let (signal, watch) = drain::channel();
+            let mut svc =
+                Enqueue::new(Idle(Arc::new(())), (), 1).with_drain(Some(watch.signaled()));
+
+            assert!(svc.poll_ready().ok().map(|r| r.is_ready()).unwrap_or(false));
+            let call = svc.call(());
+            let _ = signal.drain();
+
+            call.then(|r| match r {
+                Ok(_) => panic!("unexpected response from idle service"),
+                Err(e) => {
+                    e.downcast::<Draining>().expect("request must fail fast");
+                    future::ok(())
+                }
+            })
+        }));
+    }
+
+    #[test]
+    fn dispatched_request_completes_when_draining() {
+        tokio::run(future::lazy(|| {
+            let (signal, watch) = drain::channel();
+            let (tx, rx) = oneshot::channel();
+            let mut svc = Enqueue::new(Active(Some(tx)), (), 1).with_drain(Some(watch.signaled()));
+
+            svc.poll_ready().expect("service must be ready");
+
+            let call = svc.call(());
+            rx.map_err(|_| ()).and_then(move |rsp_tx| {
+                let _ = signal.drain();
+                rsp_tx.send(()).expect("service lost");
+                call.map_err(|_| panic!("dispatched request must complete"))
+            })
+        }));
+    }
 }
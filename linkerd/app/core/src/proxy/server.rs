@@ -8,32 +8,59 @@ use crate::{
             h2::Settings as H2Settings,
             upgrade, Version as HttpVersion,
         },
+        identity,
     },
     svc::{MakeService, Service, ServiceExt},
-    transport::{self, io::BoxedIo, labels::Key as TransportKey, metrics::TransportLabels, tls},
+    transport::{
+        self,
+        io::BoxedIo,
+        labels::Key as TransportKey,
+        metrics::{CloseReason, SetCloseReason, TransportLabels},
+        tls, SkipPorts,
+    },
     Error, Never,
 };
-use futures::{future::Either, Future, Poll};
+use futures::{future::Either, sync::oneshot, task::AtomicTask, try_ready, Async, Future, Poll};
 use http;
 use hyper;
-use indexmap::IndexSet;
-use std::sync::Arc;
-use tracing::{info_span, trace};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::{clock, timer::Delay};
+use tracing::{debug, debug_span, info_span, trace, warn};
 use tracing_futures::Instrument;
 
 #[derive(Clone, Debug)]
 pub struct Protocol {
     pub http: Option<HttpVersion>,
     pub tls: tls::accept::Meta,
+    /// Set when the client began the connection with a TLS ClientHello, so
+    /// that it is forwarded without attempting HTTP detection.
+    pub client_hello: Option<ClientHello>,
+    /// Set when the client sent nothing before the accept idle timeout
+    /// elapsed, so that the connection is closed rather than served.
+    pub idle: bool,
+}
+
+/// Describes a TLS ClientHello sent by the application.
+#[derive(Clone, Debug)]
+pub struct ClientHello {
+    pub sni: Option<identity::Name>,
 }
 
 pub type Connection = (Protocol, BoxedIo);
 
 #[derive(Clone, Debug)]
 pub struct ProtocolDetect {
-    skip_ports: Arc<IndexSet<u16>>,
+    skip_ports: SkipPorts,
+    tls_passthrough: TlsPassthroughCount,
 }
 
+/// Counts the connections that were forwarded because they began with a TLS
+/// ClientHello.
+#[derive(Clone, Debug, Default)]
+pub struct TlsPassthroughCount(Arc<AtomicU64>);
+
 impl detect::Detect<tls::accept::Meta> for ProtocolDetect {
     type Target = Protocol;
 
@@ -42,21 +69,64 @@ impl detect::Detect<tls::accept::Meta> for ProtocolDetect {
         tls: tls::accept::Meta,
     ) -> Result<Self::Target, tls::accept::Meta> {
         let port = tls.addrs.target_addr().port();
-        if self.skip_ports.contains(&port) {
-            return Ok(Protocol { tls, http: None });
+        if self.skip_ports.contains(port) {
+            return Ok(Protocol {
+                tls,
+                http: None,
+                client_hello: None,
+                idle: false,
+            });
         }
 
         Err(tls)
     }
 
     fn detect_peeked_prefix(&self, tls: tls::accept::Meta, prefix: &[u8]) -> Self::Target {
+        // The application is speaking TLS itself, so there's no point in
+        // trying to parse its ClientHello as HTTP.
+        if tls::is_client_hello(prefix) {
+            self.tls_passthrough.incr();
+            let client_hello = ClientHello {
+                sni: tls::client_hello_sni(prefix),
+            };
+            return Protocol {
+                tls,
+                http: None,
+                client_hello: Some(client_hello),
+                idle: false,
+            };
+        }
+
         Protocol {
             tls,
             http: HttpVersion::from_prefix(prefix),
+            client_hello: None,
+            idle: false,
+        }
+    }
+
+    fn detect_timeout(&self, tls: tls::accept::Meta) -> Self::Target {
+        Protocol {
+            tls,
+            http: None,
+            client_hello: None,
+            idle: true,
         }
     }
 }
 
+// === impl TlsPassthroughCount ===
+
+impl TlsPassthroughCount {
+    pub fn value(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn incr(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
 /// A protocol-transparent Server!
 ///
 /// As TCP streams are passed to `Server::serve`, the following occurs:
@@ -68,6 +138,9 @@ impl detect::Detect<tls::accept::Meta> for ProtocolDetect {
 ///    buffered until the server can determine whether the streams begins with a
 ///    HTTP/1 or HTTP/2 preamble.
 ///
+/// *  If the stream begins with a TLS ClientHello, it cannot be HTTP, so it is
+///    forwarded without further detection.
+///
 /// *  If the stream is not determined to be HTTP, then the original destination
 ///    address is used to transparently forward the TCP stream. A `C`-typed
 ///    `Connect` `Stack` is used to build a connection to the destination (i.e.,
@@ -75,6 +148,11 @@ impl detect::Detect<tls::accept::Meta> for ProtocolDetect {
 ///
 /// *  Otherwise, an `H`-typed `Service` is used to build a service that
 ///    can route HTTP  requests for the `tls::accept::Meta`.
+///
+/// Informational (1xx) responses never reach the `H`-typed service: hyper
+/// answers `Expect: 100-continue` itself when the request body is first read,
+/// and the client discards interim responses sent by the destination. Only
+/// final responses (and `101 Switching Protocols`) pass through the stack.
 pub struct Server<L, F, H, B>
 where
     // Used when forwarding a TCP stream (e.g. with telemetry, timeouts).
@@ -95,6 +173,112 @@ where
     forward_tcp: F,
     make_http: H,
     drain: drain::Watch,
+    max_requests_per_connection: Option<usize>,
+    h2_reset_limit: Option<ResetLimit>,
+    idle_timeouts: IdleTimeouts,
+}
+
+/// Closes connections that clients leave idle.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct IdleTimeouts {
+    /// Connections on which the client sends no request (or, if the
+    /// connection isn't HTTP, no data) within this timeout of being accepted
+    /// are closed.
+    pub accept: Option<Duration>,
+    /// HTTP connections are closed once they have had no requests in flight
+    /// for this long after serving a request.
+    pub keep_alive: Option<Duration>,
+}
+
+/// Limits the rate at which a client may reset HTTP/2 streams.
+///
+/// Resetting streams immediately after opening them lets a client impose work
+/// on the proxy without being bound by the concurrent stream limit.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ResetLimit {
+    /// The number of streams that may be reset within a window.
+    pub max_resets: usize,
+    pub window: Duration,
+}
+
+/// Counts the requests served on a single connection, closing the connection
+/// once `max_requests_per_connection` have been served.
+///
+/// HTTP/1 connections are closed by setting `Connection: close` on the final
+/// response. HTTP/2 connections are sent a GOAWAY once the final stream's
+/// response is ready, allowing in-flight streams to complete.
+struct MaxRequests<S> {
+    inner: S,
+    remaining: Option<usize>,
+    close: Option<Close>,
+}
+
+struct MaxRequestsFuture<F> {
+    inner: F,
+    close: Option<Close>,
+}
+
+enum Close {
+    Http1(SetCloseReason),
+    H2(SetCloseReason, oneshot::Sender<()>),
+}
+
+/// Counts the HTTP/2 streams that the client resets before their responses
+/// are ready, shutting down the connection once more than
+/// `ResetLimit::max_resets` are reset within a window.
+struct ResetTracking<S> {
+    inner: S,
+    resets: Option<Arc<Mutex<Resets>>>,
+}
+
+/// Records a reset if dropped before the response is ready.
+struct ResetTrackingFuture<F> {
+    inner: F,
+    resets: Option<Arc<Mutex<Resets>>>,
+}
+
+struct Resets {
+    limit: ResetLimit,
+    window_start: Instant,
+    count: usize,
+    close: Option<(SetCloseReason, oneshot::Sender<()>)>,
+}
+
+/// Tracks the requests in flight on an HTTP connection so that the
+/// connection can be closed once it has been idle for too long.
+struct TrackIdle<S> {
+    inner: S,
+    idle: Option<Arc<Mutex<Idle>>>,
+}
+
+struct TrackIdleFuture<F> {
+    inner: F,
+    _in_flight: Option<InFlight>,
+}
+
+/// Marks a request as in flight until dropped.
+struct InFlight(Arc<Mutex<Idle>>);
+
+struct Idle {
+    timeouts: IdleTimeouts,
+    in_flight: usize,
+    served: bool,
+    since: Instant,
+    task: AtomicTask,
+}
+
+/// Completes once the connection has been idle for longer than its timeout.
+struct IdleTimer {
+    idle: Arc<Mutex<Idle>>,
+    delay: Option<Delay>,
+    reason: SetCloseReason,
+}
+
+/// Gracefully shuts down a connection once signaled.
+struct ShutdownOn<C, F, S = oneshot::Receiver<()>> {
+    conn: C,
+    signal: Option<S>,
+    shutdown: F,
 }
 
 impl<L, F, H, B> Server<L, F, H, B>
@@ -117,10 +301,17 @@ where
         make_http: H,
         h2_settings: H2Settings,
         drain: drain::Watch,
-        skip_ports: Arc<IndexSet<u16>>,
+        skip_ports: SkipPorts,
+        tls_passthrough: TlsPassthroughCount,
+        max_requests_per_connection: Option<usize>,
+        h2_reset_limit: Option<ResetLimit>,
+        idle_timeouts: IdleTimeouts,
     ) -> detect::Accept<ProtocolDetect, Self> {
         detect::Accept::new(
-            ProtocolDetect { skip_ports },
+            ProtocolDetect {
+                skip_ports,
+                tls_passthrough,
+            },
             Self {
                 http: hyper::server::conn::Http::new(),
                 h2_settings,
@@ -129,8 +320,12 @@ where
                 forward_tcp,
                 make_http,
                 drain,
+                max_requests_per_connection,
+                h2_reset_limit,
+                idle_timeouts,
             },
         )
+        .with_timeout(idle_timeouts.accept)
     }
 }
 
@@ -174,17 +369,37 @@ where
             self.transport_metrics.wrap_server_transport(labels, io)
         };
 
+        let close_reason = io.close_reason();
+        if proto.idle {
+            debug!("client sent nothing before the idle timeout; closing connection");
+            close_reason.set(CloseReason::IdleTimeout);
+            return Box::new(futures::future::ok::<(), Error>(()));
+        }
+
+        let max_requests = self.max_requests_per_connection;
+        let reset_limit = self.h2_reset_limit;
+        let idle_timeouts = self.idle_timeouts;
         let drain = self.drain.clone();
         let http_version = match proto.http {
             Some(http) => http,
             None => {
-                trace!("did not detect protocol; forwarding TCP");
+                let span = match proto.client_hello {
+                    Some(ClientHello { sni: Some(ref sni) }) => {
+                        debug_span!("tls_passthrough", %sni)
+                    }
+                    Some(ClientHello { sni: None }) => debug_span!("tls_passthrough"),
+                    None => {
+                        trace!("did not detect protocol; forwarding TCP");
+                        tracing::Span::none()
+                    }
+                };
                 let fwd = self
                     .forward_tcp
                     .clone()
                     .into_service()
                     .oneshot((proto.tls, io));
-                return Box::new(drain.watch(fwd.map_err(Into::into), |_| {}));
+                let fwd = drain.watch(fwd.map_err(Into::into), |_| {});
+                return Box::new(fwd.instrument(span));
             }
         };
 
@@ -200,6 +415,8 @@ where
             HttpVersion::Http1 => {
                 // Enable support for HTTP upgrades (CONNECT and websockets).
                 let svc = upgrade::Service::new(http_svc, drain.clone());
+                let svc = MaxRequests::http1(svc, max_requests, close_reason.clone());
+                let (svc, idle) = TrackIdle::new(svc, idle_timeouts, close_reason);
                 let exec =
                     tokio::executor::DefaultExecutor::current().instrument(info_span!("http1"));
                 let conn = http
@@ -207,25 +424,37 @@ where
                     .http1_only(true)
                     .serve_connection(io, HyperServerSvc::new(svc))
                     .with_upgrades();
+                // Hyper closes idle HTTP/1 connections immediately when
+                // shutdown gracefully.
+                let conn = ShutdownOn::new(conn, idle, |conn| conn.graceful_shutdown());
                 Either::A(
                     drain
-                        .watch(conn, |conn| conn.graceful_shutdown())
+                        .watch(conn, |conn| conn.shutdown())
                         .map(|_| ())
                         .map_err(Into::into),
                 )
             }
 
             HttpVersion::H2 => {
+                let (svc, resets_exceeded) =
+                    ResetTracking::new(http_svc, reset_limit, close_reason.clone());
+                let (svc, max_requests_reached) =
+                    MaxRequests::h2(svc, max_requests, close_reason.clone());
+                let (svc, idle) = TrackIdle::new(svc, idle_timeouts, close_reason);
                 let exec = tokio::executor::DefaultExecutor::current().instrument(info_span!("h2"));
                 let conn = http
                     .with_executor(exec)
                     .http2_only(true)
                     .http2_initial_stream_window_size(initial_stream_window_size)
                     .http2_initial_connection_window_size(initial_conn_window_size)
-                    .serve_connection(io, HyperServerSvc::new(http_svc));
+                    .serve_connection(io, HyperServerSvc::new(svc));
+                let conn =
+                    ShutdownOn::new(conn, max_requests_reached, |conn| conn.graceful_shutdown());
+                let conn = ShutdownOn::new(conn, resets_exceeded, ShutdownOn::shutdown);
+                let conn = ShutdownOn::new(conn, idle, ShutdownOn::shutdown);
                 Either::B(
                     drain
-                        .watch(conn, |conn| conn.graceful_shutdown())
+                        .watch(conn, |conn| conn.shutdown())
                         .map(|_| ())
                         .map_err(Into::into),
                 )
@@ -255,6 +484,529 @@ where
             forward_tcp: self.forward_tcp.clone(),
             make_http: self.make_http.clone(),
             drain: self.drain.clone(),
+            max_requests_per_connection: self.max_requests_per_connection,
+            h2_reset_limit: self.h2_reset_limit,
+            idle_timeouts: self.idle_timeouts,
+        }
+    }
+}
+
+// === impl MaxRequests ===
+
+impl<S> MaxRequests<S> {
+    fn http1(inner: S, max: Option<usize>, reason: SetCloseReason) -> Self {
+        Self {
+            inner,
+            remaining: max,
+            close: Some(Close::Http1(reason)),
+        }
+    }
+
+    fn h2(
+        inner: S,
+        max: Option<usize>,
+        reason: SetCloseReason,
+    ) -> (Self, Option<oneshot::Receiver<()>>) {
+        let (tx, rx) = match max {
+            Some(_) => {
+                let (tx, rx) = oneshot::channel();
+                (Some(Close::H2(reason, tx)), Some(rx))
+            }
+            None => (None, None),
+        };
+        let svc = Self {
+            inner,
+            remaining: max,
+            close: tx,
+        };
+        (svc, rx)
+    }
+}
+
+impl<S, A, B> Service<http::Request<A>> for MaxRequests<S>
+where
+    S: Service<http::Request<A>, Response = http::Response<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = MaxRequestsFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: http::Request<A>) -> Self::Future {
+        let close = match self.remaining.as_mut() {
+            Some(remaining) if *remaining > 0 => {
+                *remaining -= 1;
+                if *remaining == 0 {
+                    self.close.take()
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+
+        MaxRequestsFuture {
+            inner: self.inner.call(req),
+            close,
+        }
+    }
+}
+
+impl<F, B> Future for MaxRequestsFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+{
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut rsp = try_ready!(self.inner.poll());
+
+        match self.close.take() {
+            None => {}
+            Some(Close::Http1(reason)) => {
+                debug!("max requests served; closing connection");
+                reason.set(CloseReason::MaxRequests);
+                rsp.headers_mut().insert(
+                    http::header::CONNECTION,
+                    http::HeaderValue::from_static("close"),
+                );
+            }
+            Some(Close::H2(reason, shutdown)) => {
+                debug!("max streams served; shutting down connection");
+                reason.set(CloseReason::MaxRequests);
+                let _ = shutdown.send(());
+            }
+        }
+
+        Ok(Async::Ready(rsp))
+    }
+}
+
+// === impl ResetTracking ===
+
+impl<S> ResetTracking<S> {
+    fn new(
+        inner: S,
+        limit: Option<ResetLimit>,
+        reason: SetCloseReason,
+    ) -> (Self, Option<oneshot::Receiver<()>>) {
+        match limit {
+            None => (
+                Self {
+                    inner,
+                    resets: None,
+                },
+                None,
+            ),
+            Some(limit) => {
+                let (tx, rx) = oneshot::channel();
+                let resets = Resets {
+                    limit,
+                    window_start: clock::now(),
+                    count: 0,
+                    close: Some((reason, tx)),
+                };
+                let svc = Self {
+                    inner,
+                    resets: Some(Arc::new(Mutex::new(resets))),
+                };
+                (svc, Some(rx))
+            }
+        }
+    }
+}
+
+impl<S, Req> Service<Req> for ResetTracking<S>
+where
+    S: Service<Req>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResetTrackingFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        ResetTrackingFuture {
+            inner: self.inner.call(req),
+            resets: self.resets.clone(),
+        }
+    }
+}
+
+impl<F: Future> Future for ResetTrackingFuture<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.inner.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            ready => {
+                self.resets = None;
+                ready
+            }
+        }
+    }
+}
+
+impl<F> Drop for ResetTrackingFuture<F> {
+    fn drop(&mut self) {
+        // The server only drops a stream's response future before it
+        // completes when the stream has been reset.
+        if let Some(resets) = self.resets.take() {
+            if let Ok(mut resets) = resets.lock() {
+                resets.record(clock::now());
+            }
         }
     }
 }
+
+// === impl Resets ===
+
+impl Resets {
+    fn record(&mut self, now: Instant) {
+        if now >= self.window_start + self.limit.window {
+            self.window_start = now;
+            self.count = 0;
+        }
+        self.count += 1;
+
+        if self.count > self.limit.max_resets {
+            if let Some((reason, close)) = self.close.take() {
+                warn!(
+                    resets = self.count,
+                    window = ?self.limit.window,
+                    "client reset too many streams; closing connection"
+                );
+                reason.set(CloseReason::StreamResets);
+                let _ = close.send(());
+            }
+        }
+    }
+}
+
+// === impl TrackIdle ===
+
+impl<S> TrackIdle<S> {
+    fn new(inner: S, timeouts: IdleTimeouts, reason: SetCloseReason) -> (Self, Option<IdleTimer>) {
+        if timeouts.accept.is_none() && timeouts.keep_alive.is_none() {
+            return (Self { inner, idle: None }, None);
+        }
+
+        let idle = Arc::new(Mutex::new(Idle {
+            timeouts,
+            in_flight: 0,
+            served: false,
+            since: clock::now(),
+            task: AtomicTask::new(),
+        }));
+        let timer = IdleTimer {
+            idle: idle.clone(),
+            delay: None,
+            reason,
+        };
+        let svc = Self {
+            inner,
+            idle: Some(idle),
+        };
+        (svc, Some(timer))
+    }
+}
+
+impl<S, Req> Service<Req> for TrackIdle<S>
+where
+    S: Service<Req>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = TrackIdleFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        TrackIdleFuture {
+            _in_flight: self.idle.clone().map(InFlight::new),
+            inner: self.inner.call(req),
+        }
+    }
+}
+
+impl<F: Future> Future for TrackIdleFuture<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.inner.poll()
+    }
+}
+
+// === impl InFlight ===
+
+impl InFlight {
+    fn new(idle: Arc<Mutex<Idle>>) -> Self {
+        if let Ok(mut idle) = idle.lock() {
+            idle.in_flight += 1;
+            idle.served = true;
+        }
+        InFlight(idle)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if let Ok(mut idle) = self.0.lock() {
+            idle.in_flight -= 1;
+            if idle.in_flight == 0 {
+                // The connection is idle again, so the timer must be reset.
+                idle.since = clock::now();
+                idle.task.notify();
+            }
+        }
+    }
+}
+
+// === impl Idle ===
+
+impl Idle {
+    /// Returns the time at which the connection will have been idle for too
+    /// long, if it has no requests in flight.
+    fn deadline(&self) -> Option<Instant> {
+        if self.in_flight > 0 {
+            return None;
+        }
+
+        let timeout = if self.served {
+            self.timeouts.keep_alive
+        } else {
+            self.timeouts.accept
+        };
+        timeout.map(|t| self.since + t)
+    }
+}
+
+// === impl IdleTimer ===
+
+impl Future for IdleTimer {
+    type Item = ();
+    type Error = Never;
+
+    fn poll(&mut self) -> Poll<(), Never> {
+        let deadline = match self.idle.lock() {
+            Ok(idle) => {
+                idle.task.register();
+                idle.deadline()
+            }
+            Err(_) => None,
+        };
+
+        let deadline = match deadline {
+            Some(deadline) => deadline,
+            None => {
+                self.delay = None;
+                return Ok(Async::NotReady);
+            }
+        };
+        match self.delay {
+            Some(ref delay) if delay.deadline() == deadline => {}
+            Some(ref mut delay) => delay.reset(deadline),
+            None => self.delay = Some(Delay::new(deadline)),
+        }
+
+        match self.delay.as_mut().expect("delay must be set").poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            // A failed timer is treated as elapsed.
+            Ok(Async::Ready(())) | Err(_) => {
+                debug!("connection idle; closing");
+                self.reason.set(CloseReason::IdleTimeout);
+                Ok(Async::Ready(()))
+            }
+        }
+    }
+}
+
+// === impl ShutdownOn ===
+
+impl<C, F: FnMut(&mut C), S> ShutdownOn<C, F, S> {
+    fn new(conn: C, signal: Option<S>, shutdown: F) -> Self {
+        Self {
+            conn,
+            signal,
+            shutdown,
+        }
+    }
+
+    fn shutdown(&mut self) {
+        self.signal = None;
+        (self.shutdown)(&mut self.conn);
+    }
+}
+
+impl<C: Future, F: FnMut(&mut C), S: Future<Item = ()>> Future for ShutdownOn<C, F, S> {
+    type Item = C::Item;
+    type Error = C::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let signaled = match self.signal.as_mut().map(Future::poll) {
+            Some(Ok(Async::NotReady)) | None => false,
+            // The signal is only sent once the limit is reached, so a dropped
+            // sender indicates that the connection can continue.
+            Some(Ok(Async::Ready(()))) => true,
+            Some(Err(_)) => {
+                self.signal = None;
+                false
+            }
+        };
+        if signaled {
+            self.shutdown();
+        }
+
+        self.conn.poll()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::svc;
+    use futures::future;
+
+    const LIMIT: ResetLimit = ResetLimit {
+        max_resets: 2,
+        window: Duration::from_secs(10),
+    };
+
+    #[test]
+    fn skip_ports_apply_to_new_connections() {
+        use crate::{proxy::detect::Detect, transport::listen::Addrs, Conditional};
+
+        let skip_ports = SkipPorts::default();
+        let detect = ProtocolDetect {
+            skip_ports: skip_ports.clone(),
+            tls_passthrough: TlsPassthroughCount::default(),
+        };
+        let meta = || tls::accept::Meta {
+            peer_identity: Conditional::None(tls::ReasonForNoPeerName::Loopback.into()),
+            addrs: Addrs::new(
+                ([127, 0, 0, 1], 4140).into(),
+                ([10, 1, 1, 1], 33333).into(),
+                Some(([10, 1, 1, 2], 5432).into()),
+            ),
+        };
+
+        let accepted = detect.detect_before_peek(meta());
+        assert!(accepted.is_err(), "protocol must be detected");
+
+        skip_ports.insert(5432);
+        let accepted = detect
+            .detect_before_peek(meta())
+            .expect("protocol detection must be skipped");
+        assert!(accepted.http.is_none(), "connection must be opaque");
+
+        skip_ports.remove(5432);
+        assert!(detect.detect_before_peek(meta()).is_err());
+    }
+
+    #[test]
+    fn client_hellos_are_not_detected_as_http() {
+        use crate::{proxy::detect::Detect, transport::listen::Addrs, Conditional};
+
+        let tls_passthrough = TlsPassthroughCount::default();
+        let detect = ProtocolDetect {
+            skip_ports: SkipPorts::default(),
+            tls_passthrough: tls_passthrough.clone(),
+        };
+        let meta = || tls::accept::Meta {
+            peer_identity: Conditional::None(tls::ReasonForNoPeerName::Loopback.into()),
+            addrs: Addrs::new(
+                ([127, 0, 0, 1], 4140).into(),
+                ([10, 1, 1, 1], 33333).into(),
+                Some(([10, 1, 1, 2], 8443).into()),
+            ),
+        };
+
+        let hello = include_bytes!(
+            "../../../../proxy/transport/src/tls/testdata/example-com-client-hello.bin"
+        );
+        let proto = detect.detect_peeked_prefix(meta(), &hello[..]);
+        assert!(proto.http.is_none(), "connection must be opaque");
+        let sni = proto
+            .client_hello
+            .expect("client hello must be detected")
+            .sni
+            .expect("client hello must have an SNI");
+        assert_eq!(sni.as_ref(), "example.com");
+        assert_eq!(tls_passthrough.value(), 1);
+
+        let proto = detect.detect_peeked_prefix(meta(), b"GET / HTTP/1.1\r\nHost: example.com\r\n");
+        assert!(proto.client_hello.is_none());
+        match proto.http {
+            Some(HttpVersion::Http1) => {}
+            http => panic!("expected HTTP/1 to be detected; got {:?}", http),
+        }
+        assert_eq!(tls_passthrough.value(), 1);
+    }
+
+    #[test]
+    fn closes_connections_that_reset_too_many_streams() {
+        let reason = SetCloseReason::default();
+        let inner = svc::mk(|()| future::empty::<(), Never>());
+        let (mut svc, closed) = ResetTracking::new(inner, Some(LIMIT), reason.clone());
+
+        // Each stream is reset before its response is ready.
+        for _ in 0..3 {
+            drop(svc.call(()));
+        }
+
+        closed
+            .expect("limit must be configured")
+            .wait()
+            .expect("connection must be closed");
+        assert_eq!(reason.get(), Some(CloseReason::StreamResets));
+    }
+
+    #[test]
+    fn completed_streams_are_not_resets() {
+        let reason = SetCloseReason::default();
+        let inner = svc::mk(|()| future::ok::<(), Never>(()));
+        let (mut svc, closed) = ResetTracking::new(inner, Some(LIMIT), reason.clone());
+
+        for _ in 0..10 {
+            svc.call(()).wait().expect("stream must complete");
+        }
+        drop(svc);
+
+        let closed = closed.expect("limit must be configured").wait();
+        assert!(closed.is_err(), "connection must not be closed");
+        assert_eq!(reason.get(), None);
+    }
+
+    #[test]
+    fn resets_are_counted_per_window() {
+        let start = Instant::now();
+        let (close, mut closed) = oneshot::channel();
+        let mut resets = Resets {
+            limit: LIMIT,
+            window_start: start,
+            count: 0,
+            close: Some((SetCloseReason::default(), close)),
+        };
+
+        for i in 0..10 {
+            resets.record(start + LIMIT.window * i);
+            resets.record(start + LIMIT.window * i);
+        }
+        assert!(resets.close.is_some(), "limit must not be exceeded");
+
+        resets.record(start + LIMIT.window * 9);
+        assert!(resets.close.is_none(), "limit must be exceeded");
+        assert!(closed.try_recv().unwrap().is_some());
+    }
+}
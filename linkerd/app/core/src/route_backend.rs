@@ -0,0 +1,236 @@
+//! Counts each route's responses by the traffic split backend that served
+//! them.
+//!
+//! Route metrics are recorded above a destination's traffic split, so they
+//! aggregate over all of its backends. This layer is applied directly above
+//! the split so that, e.g., a canary's failure rate on a route may be
+//! compared with the primary's. Only requests that a split dispatches are
+//! counted, so the number of series is bounded by the number of backends.
+
+use crate::classify::classify::{ClassifyEos, ClassifyResponse};
+use crate::classify::{Class, Response as Classify};
+use crate::metric_labels::RouteLabels;
+use crate::proxy::http::profiles::recognize::SplitBackend;
+use futures::{try_ready, Async, Future, Poll};
+use indexmap::IndexMap;
+use linkerd2_addr::NameAddr;
+use linkerd2_error::Error;
+use linkerd2_metrics::{metrics, Counter, FmtLabels, FmtMetrics};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+metrics! {
+    route_backend_response_total: Counter {
+        "Total count of responses to requests on a route, by the traffic split backend that served them"
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Registry(Arc<Mutex<IndexMap<Key, Counter>>>);
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Key {
+    route: RouteLabels,
+    backend: NameAddr,
+    class: Class,
+}
+
+#[derive(Clone, Debug)]
+pub struct Layer {
+    registry: Registry,
+}
+
+#[derive(Clone, Debug)]
+pub struct Stack<M> {
+    inner: M,
+    registry: Registry,
+}
+
+pub struct MakeFuture<F> {
+    inner: F,
+    route: Option<RouteLabels>,
+    registry: Registry,
+}
+
+#[derive(Clone, Debug)]
+pub struct Service<S> {
+    inner: S,
+    route: RouteLabels,
+    registry: Registry,
+}
+
+pub struct ResponseFuture<F> {
+    inner: F,
+    route: RouteLabels,
+    backend: SplitBackend,
+    classify: Option<Classify>,
+    registry: Registry,
+}
+
+pub fn layer(registry: Registry) -> Layer {
+    Layer { registry }
+}
+
+// === impl Registry ===
+
+impl Registry {
+    fn incr(&self, key: Key) {
+        if let Ok(mut by_backend) = self.0.lock() {
+            by_backend
+                .entry(key)
+                .or_insert_with(Counter::default)
+                .incr();
+        }
+    }
+}
+
+impl FmtMetrics for Registry {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let by_backend = match self.0.lock() {
+            Ok(by_backend) => by_backend,
+            Err(_) => return Ok(()),
+        };
+        if by_backend.is_empty() {
+            return Ok(());
+        }
+
+        route_backend_response_total.fmt_help(f)?;
+        route_backend_response_total.fmt_scopes(f, by_backend.iter(), |c| c)?;
+
+        Ok(())
+    }
+}
+
+impl FmtLabels for Key {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.route.fmt_labels(f)?;
+        write!(f, ",backend=\"{}\",", self.backend)?;
+        self.class.fmt_labels(f)
+    }
+}
+
+// === impl Layer ===
+
+impl<M> tower::layer::Layer<M> for Layer {
+    type Service = Stack<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        Stack {
+            inner,
+            registry: self.registry.clone(),
+        }
+    }
+}
+
+// === impl Stack ===
+
+impl<T, M> tower::Service<T> for Stack<M>
+where
+    T: Into<RouteLabels> + Clone,
+    M: tower::Service<T>,
+{
+    type Response = Service<M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        MakeFuture {
+            route: Some(target.clone().into()),
+            inner: self.inner.call(target),
+            registry: self.registry.clone(),
+        }
+    }
+}
+
+// === impl MakeFuture ===
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = Service<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        let route = self.route.take().expect("polled after ready");
+        Ok(Service {
+            inner,
+            route,
+            registry: self.registry.clone(),
+        }
+        .into())
+    }
+}
+
+// === impl Service ===
+
+impl<S, A, B> tower::Service<http::Request<A>> for Service<S>
+where
+    S: tower::Service<http::Request<A>, Response = http::Response<B>>,
+    S::Error: Into<Error>,
+{
+    type Response = S::Response;
+    type Error = Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, mut req: http::Request<A>) -> Self::Future {
+        let backend = SplitBackend::default();
+        req.extensions_mut().insert(backend.clone());
+        let classify = req.extensions().get::<Classify>().cloned();
+
+        ResponseFuture {
+            inner: self.inner.call(req),
+            route: self.route.clone(),
+            backend,
+            classify,
+            registry: self.registry.clone(),
+        }
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+    F::Error: Into<Error>,
+{
+    type Item = F::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let classify = self.classify.take().unwrap_or(Classify::Default);
+        let (result, class) = match self.inner.poll() {
+            Ok(Async::NotReady) => {
+                self.classify = Some(classify);
+                return Ok(Async::NotReady);
+            }
+            Ok(Async::Ready(rsp)) => {
+                // Responses are classified before their bodies are read.
+                let class = classify.start(&rsp).headers_only().eos(None);
+                (Ok(Async::Ready(rsp)), class)
+            }
+            Err(e) => {
+                let error = e.into();
+                let class = classify.error(&error);
+                (Err(error), class)
+            }
+        };
+
+        if let Some(backend) = self.backend.get() {
+            self.registry.incr(Key {
+                route: self.route.clone(),
+                backend,
+                class,
+            });
+        }
+
+        result
+    }
+}
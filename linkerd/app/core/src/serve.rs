@@ -1,10 +1,11 @@
 use super::accept_error::AcceptError;
-use futures::{future, try_ready, Future, Poll};
+use super::accept_watchdog;
+use futures::{future, sync::oneshot, try_ready, Future, Poll};
 use linkerd2_drain as drain;
 use linkerd2_error::Error;
-use linkerd2_proxy_core::listen::{Accept, Listen, Serve};
+use linkerd2_proxy_core::listen::{Accept, Bind, Heartbeat, Listen, Serve};
 use linkerd2_proxy_transport::listen::Addrs;
-use tracing::{debug, info_span, Span};
+use tracing::{debug, info_span, warn, Span};
 use tracing_futures::{Instrument, Instrumented};
 
 pub type Task = Box<dyn Future<Item = (), Error = Error> + Send + 'static>;
@@ -36,9 +37,55 @@ where
     }))
 }
 
-struct ServeAndSpawnUntilCancel<L: Listen, A: Accept<L::Connection>>(
-    Option<Serve<L, TraceAccept<AcceptError<A>>, Instrumented<tokio::executor::DefaultExecutor>>>,
-);
+/// Like `serve`, but the accept loop is watched so that a stall is detected.
+///
+/// If the watchdog asks for the listener to be rebuilt, the current listener
+/// is closed and `bind` is bound again. If the listener can't be rebound, the
+/// task fails.
+pub fn serve_watched<B, A>(
+    listen: B::Listen,
+    bind: B,
+    accept: A,
+    watch: accept_watchdog::Watch,
+    drain: drain::Watch,
+) -> Task
+where
+    B: Bind + Clone + Send + 'static,
+    B::Listen: Send + 'static,
+    B::Connection: HasSpan,
+    <B::Listen as Listen>::Error: std::error::Error + Send + 'static,
+    A: Accept<B::Connection> + Send + 'static,
+    A::Error: 'static,
+    A::Future: Send + 'static,
+{
+    Box::new(future::lazy(move || {
+        debug!(listen.addr = %listen.listen_addr(), "serving");
+        let heartbeat = Heartbeat::default();
+        let (closed, watchdog) = watch.watchdog(heartbeat.clone());
+        tokio::spawn(watchdog.in_current_span());
+
+        let serve = ServeAndSpawnUntilCancel::new(listen, accept).watched(Watched {
+            heartbeat,
+            rebind: Box::new(move || bind.clone().bind()),
+            _closed: closed,
+        });
+        drain.watch(serve, |s| s.cancel())
+    }))
+}
+
+struct ServeAndSpawnUntilCancel<L: Listen, A: Accept<L::Connection>> {
+    serve: Option<
+        Serve<L, TraceAccept<AcceptError<A>>, Instrumented<tokio::executor::DefaultExecutor>>,
+    >,
+    watched: Option<Watched<L>>,
+}
+
+struct Watched<L> {
+    heartbeat: Heartbeat,
+    rebind: Box<dyn Fn() -> std::io::Result<L> + Send>,
+    /// Stops the watchdog when the loop is dropped.
+    _closed: oneshot::Sender<()>,
+}
 
 impl<L, A> ServeAndSpawnUntilCancel<L, A>
 where
@@ -55,11 +102,48 @@ where
             span: Span::current(),
         };
         let serve = listen.serve(accept).with_executor(exec);
-        ServeAndSpawnUntilCancel(Some(serve))
+        ServeAndSpawnUntilCancel {
+            serve: Some(serve),
+            watched: None,
+        }
+    }
+
+    fn watched(self, watched: Watched<L>) -> Self {
+        let serve = self
+            .serve
+            .map(|s| s.with_heartbeat(watched.heartbeat.clone()));
+        ServeAndSpawnUntilCancel {
+            serve,
+            watched: Some(watched),
+        }
     }
 
     fn cancel(&mut self) {
-        self.0 = None;
+        self.serve = None;
+        self.watched = None;
+    }
+
+    /// Replaces the listener with a newly-bound one.
+    fn rebind(&mut self) -> Result<(), Error> {
+        let watched = match (self.serve.is_some(), self.watched.as_ref()) {
+            (true, Some(w)) => w,
+            _ => return Ok(()),
+        };
+        let (listen, accept, exec) = self.serve.take().expect("must be serving").into_parts();
+        let addr = listen.listen_addr();
+        // The old listener is closed first so that its address may be reused.
+        drop(listen);
+        let listen = (watched.rebind)().map_err(|error| {
+            warn!(listen.addr = %addr, %error, "failed to rebuild stalled listener");
+            error
+        })?;
+        warn!(listen.addr = %listen.listen_addr(), "rebuilt stalled listener");
+        let serve = listen
+            .serve(accept)
+            .with_executor(exec)
+            .with_heartbeat(watched.heartbeat.clone());
+        self.serve = Some(serve);
+        Ok(())
     }
 }
 
@@ -75,7 +159,10 @@ where
     type Error = Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        match self.0.as_mut() {
+        if self.watched.as_ref().map(|w| w.heartbeat.take_recover()) == Some(true) {
+            self.rebind()?;
+        }
+        match self.serve.as_mut() {
             Some(ref mut serve) => match try_ready!(serve.poll()) {},
             None => Ok(().into()),
         }
@@ -76,6 +76,11 @@ impl SpanConverter {
                 },
             );
         }
+        let time_events = if span.annotations.is_empty() {
+            None
+        } else {
+            Some(time_events(span.annotations))
+        };
         Ok(oc::Span {
             trace_id: into_bytes(span.trace_id, 16)?,
             span_id: into_bytes(span.span_id, 8)?,
@@ -90,7 +95,7 @@ impl SpanConverter {
                 dropped_attributes_count: 0,
             }),
             stack_trace: None,
-            time_events: None,
+            time_events,
             links: None,
             status: None, // TODO: this is gRPC status; we must read response trailers to populate this
             resource: None,
@@ -121,6 +126,41 @@ fn into_bytes(id: trace_context::Id, size: usize) -> Result<Vec<u8>, IdLengthErr
     }
 }
 
+fn time_events(annotations: Vec<trace_context::Annotation>) -> oc::span::TimeEvents {
+    let time_event = annotations
+        .into_iter()
+        .map(|annotation| {
+            let time = annotation.time;
+            let attribute_map = annotation
+                .attributes
+                .into_iter()
+                .map(|(k, v)| {
+                    let v = oc::AttributeValue {
+                        value: Some(oc::attribute_value::Value::StringValue(truncatable(v))),
+                    };
+                    (k, v)
+                })
+                .collect();
+            let value = oc::span::time_event::Annotation {
+                description: Some(truncatable(annotation.description)),
+                attributes: Some(oc::span::Attributes {
+                    attribute_map,
+                    dropped_attributes_count: 0,
+                }),
+            };
+            oc::span::TimeEvent {
+                time: Some(time.into()),
+                value: Some(oc::span::time_event::Value::Annotation(value)),
+            }
+        })
+        .collect();
+    oc::span::TimeEvents {
+        time_event,
+        dropped_annotations_count: 0,
+        dropped_message_events_count: 0,
+    }
+}
+
 fn truncatable(value: String) -> oc::TruncatableString {
     oc::TruncatableString {
         value,
@@ -0,0 +1,340 @@
+//! Samples the time that requests spend in each named stage of the proxy's
+//! stacks.
+//!
+//! This is a debugging aid for finding the layers that are responsible for
+//! the proxy's CPU use. When it is enabled, one in every `sample` requests
+//! is timed in each stage: the time spent calling the stage's service and
+//! polling its response future is recorded once the response is ready.
+//! Time spent waiting to be notified isn't counted.
+//!
+//! Stages are nested, so each stage's time includes that of the stages
+//! below it. When it is disabled, stages are not registered and their
+//! services do nothing but check that they are disabled.
+
+use futures::{try_ready, Future, Poll};
+use indexmap::IndexMap;
+use linkerd2_metrics::{latency, FmtLabels, FmtMetric, FmtMetrics, Histogram, Metric};
+use std::fmt;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
+use tokio_timer::clock;
+
+/// Reports the time that sampled requests spend in each registered stage.
+#[derive(Clone, Debug, Default)]
+pub struct Registry {
+    sample: Option<usize>,
+    stages: Arc<Mutex<IndexMap<&'static str, Stage>>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Layer {
+    stage: Option<Stage>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Stack<M> {
+    inner: M,
+    stage: Option<Stage>,
+}
+
+pub struct MakeFuture<F> {
+    inner: F,
+    stage: Option<Stage>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Service<S> {
+    inner: S,
+    stage: Option<Stage>,
+}
+
+pub struct ResponseFuture<F> {
+    inner: F,
+    timing: Option<Timing>,
+}
+
+#[derive(Clone, Debug)]
+struct Stage(Arc<Shared>);
+
+#[derive(Debug)]
+struct Shared {
+    sample: usize,
+    requests: AtomicUsize,
+    durations: Mutex<Histogram<latency::Us>>,
+}
+
+/// The time spent in a stage by a sampled request.
+struct Timing {
+    stage: Stage,
+    elapsed: Duration,
+}
+
+struct StageLabel(&'static str);
+
+// === impl Registry ===
+
+impl Registry {
+    pub const HELP: &'static str =
+        "A histogram of the time in microseconds that sampled requests spend in each stack stage.";
+    pub const NAME: &'static str = "stack_stage_duration_us";
+
+    /// Times one in every `sample` requests, if set. Otherwise, stages are
+    /// not timed.
+    pub fn new(sample: Option<usize>) -> Self {
+        Self {
+            sample: sample.filter(|n| *n > 0),
+            stages: Arc::default(),
+        }
+    }
+
+    /// Returns a layer that times requests in the stage named `name`.
+    pub fn layer(&self, name: &'static str) -> Layer {
+        let sample = match self.sample {
+            Some(sample) => sample,
+            None => return Layer { stage: None },
+        };
+
+        let mut stages = match self.stages.lock() {
+            Ok(stages) => stages,
+            Err(_) => return Layer { stage: None },
+        };
+        let stage = stages
+            .entry(name)
+            .or_insert_with(|| {
+                Stage(Arc::new(Shared {
+                    sample,
+                    requests: AtomicUsize::new(0),
+                    durations: Mutex::new(Histogram::default()),
+                }))
+            })
+            .clone();
+        Layer { stage: Some(stage) }
+    }
+
+    fn metric(&self) -> Metric<'_, Stage> {
+        Metric::new(Self::NAME, Self::HELP)
+    }
+}
+
+impl FmtMetrics for Registry {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stages = match self.stages.lock() {
+            Ok(stages) => stages,
+            Err(_) => return Ok(()),
+        };
+        if stages.is_empty() {
+            return Ok(());
+        }
+
+        let metric = self.metric();
+        metric.fmt_help(f)?;
+        let scopes = stages
+            .iter()
+            .map(|(name, stage)| (StageLabel(*name), stage));
+        metric.fmt_scopes(f, scopes, |stage| stage)
+    }
+}
+
+impl FmtLabels for StageLabel {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "stage=\"{}\"", self.0)
+    }
+}
+
+// === impl Stage ===
+
+impl Stage {
+    fn is_sampled(&self) -> bool {
+        self.0.requests.fetch_add(1, Ordering::Relaxed) % self.0.sample == 0
+    }
+
+    fn record(&self, elapsed: Duration) {
+        if let Ok(mut durations) = self.0.durations.lock() {
+            durations.add(elapsed);
+        }
+    }
+}
+
+impl FmtMetric for Stage {
+    const KIND: &'static str = <Histogram<latency::Us> as FmtMetric>::KIND;
+
+    fn fmt_metric<N: fmt::Display>(&self, f: &mut fmt::Formatter<'_>, name: N) -> fmt::Result {
+        if let Ok(durations) = self.0.durations.lock() {
+            durations.fmt_metric(f, name)?;
+        }
+        Ok(())
+    }
+
+    fn fmt_metric_labeled<N, L>(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        name: N,
+        labels: L,
+    ) -> fmt::Result
+    where
+        N: fmt::Display,
+        L: FmtLabels,
+    {
+        if let Ok(durations) = self.0.durations.lock() {
+            durations.fmt_metric_labeled(f, name, labels)?;
+        }
+        Ok(())
+    }
+}
+
+// === impl Layer ===
+
+impl<M> tower::layer::Layer<M> for Layer {
+    type Service = Stack<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        Stack {
+            inner,
+            stage: self.stage.clone(),
+        }
+    }
+}
+
+// === impl Stack ===
+
+impl<T, M> tower::Service<T> for Stack<M>
+where
+    M: tower::Service<T>,
+{
+    type Response = Service<M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        MakeFuture {
+            inner: self.inner.call(target),
+            stage: self.stage.clone(),
+        }
+    }
+}
+
+// === impl MakeFuture ===
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = Service<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        Ok(Service {
+            inner,
+            stage: self.stage.clone(),
+        }
+        .into())
+    }
+}
+
+// === impl Service ===
+
+impl<S, Req> tower::Service<Req> for Service<S>
+where
+    S: tower::Service<Req>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        let stage = match self.stage {
+            Some(ref stage) if stage.is_sampled() => stage.clone(),
+            _ => {
+                return ResponseFuture {
+                    inner: self.inner.call(req),
+                    timing: None,
+                }
+            }
+        };
+
+        let start = clock::now();
+        let inner = self.inner.call(req);
+        let elapsed = clock::now() - start;
+        ResponseFuture {
+            inner,
+            timing: Some(Timing { stage, elapsed }),
+        }
+    }
+}
+
+impl<F: Future> Future for ResponseFuture<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut timing = match self.timing.take() {
+            Some(timing) => timing,
+            None => return self.inner.poll(),
+        };
+
+        let start = clock::now();
+        let poll = self.inner.poll();
+        timing.elapsed += clock::now() - start;
+        match poll {
+            Ok(ref ready) if ready.is_not_ready() => {
+                self.timing = Some(timing);
+            }
+            _ => timing.stage.record(timing.elapsed),
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use tower::Service as _;
+
+    fn service(
+        registry: &Registry,
+        name: &'static str,
+    ) -> Service<impl tower::Service<(), Response = (), Error = ()>> {
+        let inner = tower::service_fn(|()| future::ok::<(), ()>(()));
+        Service {
+            inner,
+            stage: registry.layer(name).stage,
+        }
+    }
+
+    fn observations(registry: &Registry, name: &'static str) -> u64 {
+        let stages = registry.stages.lock().unwrap();
+        let durations = stages[name].0.durations.lock().unwrap();
+        durations
+            .into_iter()
+            .map(|(_, &count)| -> u64 { count.into() })
+            .sum()
+    }
+
+    #[test]
+    fn samples_one_in_n_requests() {
+        let registry = Registry::new(Some(3));
+        let mut svc = service(&registry, "test");
+        for _ in 0..6 {
+            svc.call(()).wait().unwrap();
+        }
+        assert_eq!(observations(&registry, "test"), 2);
+    }
+
+    #[test]
+    fn disabled_stages_are_not_registered() {
+        let registry = Registry::new(None);
+        let mut svc = service(&registry, "test");
+        svc.call(()).wait().unwrap();
+        assert!(registry.stages.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,312 @@
+//! Holds accepted connections until the proxy's stack has been constructed.
+//!
+//! The listener is bound (so that peers and probes can connect) before the
+//! stack that serves it is built. While the stack is being constructed, a
+//! bounded number of accepted connections are held; once it's constructed,
+//! they're released to it. Connections accepted while the queue is full fail
+//! with an `Overloaded` error rather than racing the stack's construction.
+//!
+//! Readiness is withheld until the stack is constructed.
+
+use super::admin::Condition;
+use futures::{sync::oneshot, try_ready, Future, Poll};
+use linkerd2_error::Error;
+use linkerd2_proxy_core::listen::Accept;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tracing::debug;
+
+/// Signals that the stack has been constructed, providing the `A`-typed
+/// accept service that serves connections from then on.
+pub struct Constructed<A> {
+    shared: Arc<Mutex<State<A>>>,
+    condition: Condition,
+}
+
+/// Accepts connections with the constructed stack, holding them until it's
+/// constructed.
+pub struct Gate<A> {
+    accept: Option<A>,
+    shared: Arc<Mutex<State<A>>>,
+    capacity: usize,
+}
+
+pub enum AcceptFuture<A, C>
+where
+    A: Accept<C>,
+{
+    Waiting(oneshot::Receiver<A>, Option<C>),
+    ReadyAccept(A, Option<C>),
+    Accept(A::Future),
+    Failed(Option<Error>),
+}
+
+/// Indicates that a connection was refused because too many connections
+/// were already held while the stack was being constructed.
+#[derive(Debug)]
+pub struct Overloaded(());
+
+/// Indicates that the stack was never constructed.
+#[derive(Debug)]
+pub struct Unconstructed(());
+
+enum State<A> {
+    Constructing(Vec<oneshot::Sender<A>>),
+    Constructed(A),
+    Unconstructed,
+}
+
+/// Returns a `Gate` that holds at most `capacity` connections until its
+/// `Constructed` is completed. `condition` is unhealthy until then.
+pub fn gate<A>(capacity: usize, condition: Condition) -> (Constructed<A>, Gate<A>) {
+    condition.set_healthy(false);
+    let shared = Arc::new(Mutex::new(State::Constructing(Vec::new())));
+    let constructed = Constructed {
+        shared: shared.clone(),
+        condition,
+    };
+    let gate = Gate {
+        accept: None,
+        shared,
+        capacity,
+    };
+    (constructed, gate)
+}
+
+// === impl Constructed ===
+
+impl<A: Clone> Constructed<A> {
+    /// Releases held connections to `accept`.
+    pub fn complete(self, accept: A) {
+        let mut state = self.shared.lock().expect("startup lock poisoned");
+        if let State::Constructing(ref mut waiting) = *state {
+            debug!(held = waiting.len(), "stack constructed");
+            for tx in waiting.drain(..) {
+                let _ = tx.send(accept.clone());
+            }
+        }
+        // Connections accepted from now on are served directly.
+        *state = State::Constructed(accept);
+        self.condition.set_healthy(true);
+    }
+}
+
+impl<A> Drop for Constructed<A> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.shared.lock() {
+            // If the stack wasn't constructed, held connections fail.
+            if let State::Constructing(_) = *state {
+                *state = State::Unconstructed;
+            }
+        }
+    }
+}
+
+// === impl Gate ===
+
+impl<A: Clone> Gate<A> {
+    /// Returns the constructed accept service, if it has been constructed.
+    fn constructed(&mut self) -> Option<&mut A> {
+        if self.accept.is_none() {
+            if let Ok(state) = self.shared.lock() {
+                if let State::Constructed(ref accept) = *state {
+                    self.accept = Some(accept.clone());
+                }
+            }
+        }
+        self.accept.as_mut()
+    }
+}
+
+impl<A, C> tower::Service<C> for Gate<A>
+where
+    A: Accept<C> + Clone,
+{
+    type Response = ();
+    type Error = Error;
+    type Future = AcceptFuture<A, C>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        match self.constructed() {
+            Some(accept) => accept.poll_ready().map_err(Into::into),
+            // Connections are accepted (and held) until the stack is
+            // constructed.
+            None => Ok(().into()),
+        }
+    }
+
+    fn call(&mut self, conn: C) -> Self::Future {
+        if let Some(accept) = self.constructed() {
+            return AcceptFuture::Accept(accept.accept(conn));
+        }
+
+        let mut state = self.shared.lock().expect("startup lock poisoned");
+        match *state {
+            // The stack was constructed since it was checked.
+            State::Constructed(ref accept) => AcceptFuture::ReadyAccept(accept.clone(), Some(conn)),
+            State::Unconstructed => AcceptFuture::Failed(Some(Unconstructed(()).into())),
+            State::Constructing(ref mut waiting) => {
+                // Connections that have since been closed don't count.
+                waiting.retain(|tx| !tx.is_canceled());
+                if waiting.len() >= self.capacity {
+                    debug!(
+                        capacity = self.capacity,
+                        "refusing connection during startup"
+                    );
+                    return AcceptFuture::Failed(Some(Overloaded(()).into()));
+                }
+                let (tx, rx) = oneshot::channel();
+                waiting.push(tx);
+                AcceptFuture::Waiting(rx, Some(conn))
+            }
+        }
+    }
+}
+
+// === impl AcceptFuture ===
+
+impl<A, C> Future for AcceptFuture<A, C>
+where
+    A: Accept<C>,
+{
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            *self = match self {
+                AcceptFuture::Waiting(ref mut rx, ref mut conn) => {
+                    let accept = try_ready!(rx.poll().map_err(|_| Unconstructed(())));
+                    AcceptFuture::ReadyAccept(accept, conn.take())
+                }
+                AcceptFuture::ReadyAccept(ref mut accept, ref mut conn) => {
+                    try_ready!(accept.poll_ready().map_err(Into::into));
+                    let conn = conn.take().expect("polled after ready");
+                    AcceptFuture::Accept(accept.accept(conn))
+                }
+                AcceptFuture::Accept(ref mut fut) => return fut.poll().map_err(Into::into),
+                AcceptFuture::Failed(ref mut e) => {
+                    return Err(e.take().expect("polled after failure"));
+                }
+            }
+        }
+    }
+}
+
+// === impl Overloaded ===
+
+impl fmt::Display for Overloaded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "proxy is starting and too many connections are pending")
+    }
+}
+
+impl std::error::Error for Overloaded {}
+
+// === impl Unconstructed ===
+
+impl fmt::Display for Unconstructed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "proxy stack was not constructed")
+    }
+}
+
+impl std::error::Error for Unconstructed {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admin::Readiness;
+    use futures::future;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    /// Counts the connections it accepts.
+    #[derive(Clone, Default)]
+    struct Accepted(Arc<AtomicUsize>);
+
+    impl tower::Service<usize> for Accepted {
+        type Response = ();
+        type Error = Error;
+        type Future = future::FutureResult<(), Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: usize) -> Self::Future {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            future::ok(())
+        }
+    }
+
+    #[test]
+    fn holds_connections_until_constructed() {
+        let (readiness, latch) = Readiness::new();
+        latch.release();
+        let (constructed, mut gate) = gate(2, readiness.condition());
+        assert!(!readiness.is_ready(), "must not be ready until constructed");
+
+        let accepted = Accepted::default();
+        let construct = {
+            let accepted = accepted.clone();
+            thread::spawn(move || {
+                // Construction is slow, so connections arrive while it's
+                // underway.
+                thread::sleep(Duration::from_millis(100));
+                constructed.complete(accepted);
+            })
+        };
+
+        let conns = (0..5)
+            .map(|conn| {
+                future::poll_fn(|| tower::Service::<usize>::poll_ready(&mut gate))
+                    .wait()
+                    .expect("gate must be ready");
+                let fut = tower::Service::call(&mut gate, conn);
+                thread::spawn(move || fut.wait())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(accepted.0.load(Ordering::SeqCst), 0, "connections are held");
+
+        let mut overloaded = 0;
+        for conn in conns {
+            match conn.join().expect("connection must not panic") {
+                Ok(()) => {}
+                Err(e) => {
+                    assert!(e.is::<Overloaded>(), "unexpected error: {}", e);
+                    overloaded += 1;
+                }
+            }
+        }
+        construct.join().unwrap();
+        assert_eq!(
+            accepted.0.load(Ordering::SeqCst),
+            2,
+            "held connections are released"
+        );
+        assert_eq!(overloaded, 3, "connections beyond capacity are refused");
+        assert!(readiness.is_ready(), "must be ready once constructed");
+
+        // Once constructed, connections are accepted directly.
+        tower::Service::call(&mut gate, 5)
+            .wait()
+            .expect("connection must be accepted");
+        assert_eq!(accepted.0.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn held_connections_fail_if_never_constructed() {
+        let (constructed, mut gate) = gate::<Accepted>(1, Readiness::default().condition());
+        let held = tower::Service::<usize>::call(&mut gate, 0);
+        drop(constructed);
+        let error = held.wait().expect_err("connection must fail");
+        assert!(error.is::<Unconstructed>());
+
+        let error = tower::Service::<usize>::call(&mut gate, 1)
+            .wait()
+            .expect_err("connection must fail");
+        assert!(error.is::<Unconstructed>());
+    }
+}
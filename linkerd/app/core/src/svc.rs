@@ -1,5 +1,5 @@
 use crate::proxy::{buffer, http, pending};
-use crate::Error;
+use crate::{drain, Error};
 pub use linkerd2_router::Make;
 pub use linkerd2_stack::{self as stack, layer, map_target, Layer, LayerExt, Shared};
 pub use linkerd2_timeout::stack as timeout;
@@ -26,6 +26,26 @@ pub fn stack<S>(inner: S) -> Stack<S> {
     Stack(inner)
 }
 
+fn buffer_layer<D, Req>(
+    bound: usize,
+    d: D,
+    drain: Option<drain::Signaled>,
+    shed: Option<buffer::Shed>,
+) -> buffer::Layer<D, Req>
+where
+    D: buffer::Deadline<Req>,
+    Req: Send + 'static,
+{
+    let mut layer = buffer::layer(bound, d);
+    if let Some(drain) = drain {
+        layer = layer.with_drain(drain);
+    }
+    if let Some(shed) = shed {
+        layer = layer.with_shed(shed);
+    }
+    layer
+}
+
 // Possibly unused, but useful during development.
 #[allow(dead_code)]
 impl<L> Layers<L> {
@@ -51,6 +71,25 @@ impl<L> Layers<L> {
         self.push_pending().push(buffer::layer(bound, d))
     }
 
+    /// Buffer requests when the next layer is out of capacity. If `drain`
+    /// is set, requests that are still queued when it is signaled fail. If
+    /// `shed` is set, requests whose deadline is too soon to be met fail
+    /// without being queued.
+    pub fn push_buffer_pending_with_shedding<D, Req>(
+        self,
+        bound: usize,
+        d: D,
+        drain: Option<drain::Signaled>,
+        shed: Option<buffer::Shed>,
+    ) -> Layers<Pair<Pair<L, pending::Layer>, buffer::Layer<D, Req>>>
+    where
+        D: buffer::Deadline<Req>,
+        Req: Send + 'static,
+    {
+        self.push_pending()
+            .push(buffer_layer(bound, d, drain, shed))
+    }
+
     pub fn push_spawn_ready(self) -> Layers<Pair<L, SpawnReadyLayer>> {
         self.push(SpawnReadyLayer::new())
     }
@@ -97,6 +136,25 @@ impl<S> Stack<S> {
         self.push_pending().push(buffer::layer(bound, d))
     }
 
+    /// Buffer requests when the next layer is out of capacity. If `drain`
+    /// is set, requests that are still queued when it is signaled fail. If
+    /// `shed` is set, requests whose deadline is too soon to be met fail
+    /// without being queued.
+    pub fn push_buffer_pending_with_shedding<D, Req>(
+        self,
+        bound: usize,
+        d: D,
+        drain: Option<drain::Signaled>,
+        shed: Option<buffer::Shed>,
+    ) -> Stack<buffer::Make<pending::MakePending<S>, D, Req>>
+    where
+        D: buffer::Deadline<Req>,
+        Req: Send + 'static,
+    {
+        self.push_pending()
+            .push(buffer_layer(bound, d, drain, shed))
+    }
+
     pub fn push_spawn_ready(self) -> Stack<tower_spawn_ready::MakeSpawnReady<S>> {
         self.push(SpawnReadyLayer::new())
     }
@@ -0,0 +1,173 @@
+//! Reports the resources held by the proxy's own stacks and registries.
+//!
+//! Values are gathered when metrics are scraped, from handles that only hold
+//! their locks long enough to read a length.
+
+use crate::admin::StackState;
+use crate::proxy::http::metrics::SharedRegistry;
+use linkerd2_metrics::{metrics, FmtLabels, FmtMetrics, Gauge, Metric};
+use std::fmt;
+use std::hash::Hash;
+use std::sync::Arc;
+
+metrics! {
+    proxy_cache_entries: Gauge { "Number of services held by each router cache" },
+    proxy_resolutions: Gauge { "Number of destinations that are actively resolved" },
+    proxy_resolved_endpoints: Gauge { "Number of endpoints held by active resolutions" },
+    proxy_profile_watches: Gauge { "Number of destinations whose profiles are watched" },
+    proxy_metrics_series: Gauge { "Number of targets held by each HTTP metrics registry" }
+}
+
+#[derive(Clone)]
+pub struct Report {
+    stack_state: StackState,
+    series: Vec<(&'static str, Arc<dyn Fn() -> usize + Send + Sync>)>,
+}
+
+struct Label(&'static str, &'static str);
+
+// === impl Report ===
+
+impl Report {
+    pub fn new(stack_state: StackState) -> Self {
+        Self {
+            stack_state,
+            series: Vec::new(),
+        }
+    }
+
+    /// Reports the number of targets held by an HTTP metrics registry.
+    pub fn with_http_registry<T, C>(
+        mut self,
+        name: &'static str,
+        registry: SharedRegistry<T, C>,
+    ) -> Self
+    where
+        T: Hash + Eq + Send + 'static,
+        C: Hash + Eq + Send + 'static,
+    {
+        let count = move || registry.lock().map(|r| r.target_count()).unwrap_or(0);
+        self.series.push((name, Arc::new(count)));
+        self
+    }
+}
+
+fn fmt_gauges(
+    f: &mut fmt::Formatter<'_>,
+    metric: &Metric<'_, Gauge>,
+    key: &'static str,
+    values: Vec<(&'static str, usize)>,
+) -> fmt::Result {
+    if values.is_empty() {
+        return Ok(());
+    }
+
+    let gauges = values
+        .into_iter()
+        .map(|(name, n)| (Label(key, name), Gauge::from(n as u64)))
+        .collect::<Vec<_>>();
+    metric.fmt_help(f)?;
+    metric.fmt_scopes(f, gauges.iter().map(|(l, g)| (l, g)), |g| g)
+}
+
+impl FmtMetrics for Report {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_gauges(
+            f,
+            &proxy_cache_entries,
+            "cache",
+            self.stack_state.cache_sizes(),
+        )?;
+
+        let resolutions = self.stack_state.resolution_counts();
+        fmt_gauges(
+            f,
+            &proxy_resolutions,
+            "stack",
+            resolutions.iter().map(|(n, (t, _))| (*n, *t)).collect(),
+        )?;
+        fmt_gauges(
+            f,
+            &proxy_resolved_endpoints,
+            "stack",
+            resolutions.iter().map(|(n, (_, e))| (*n, *e)).collect(),
+        )?;
+
+        let watches = self.stack_state.profile_updates().watched();
+        proxy_profile_watches.fmt_help(f)?;
+        proxy_profile_watches.fmt_metric(f, Gauge::from(watches as u64))?;
+
+        fmt_gauges(
+            f,
+            &proxy_metrics_series,
+            "registry",
+            self.series.iter().map(|(n, count)| (*n, count())).collect(),
+        )?;
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Report").finish()
+    }
+}
+
+// === impl Label ===
+
+impl FmtLabels for Label {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}=\"{}\"", self.0, self.1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{router, Error};
+    use futures::{future, Future, Poll};
+    use std::time::Duration;
+
+    #[derive(Clone)]
+    struct Svc;
+
+    impl tower::Service<usize> for Svc {
+        type Response = ();
+        type Error = Error;
+        type Future = future::FutureResult<(), Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: usize) -> Self::Future {
+            future::ok(())
+        }
+    }
+
+    #[test]
+    fn reports_one_cache_entry_per_target() {
+        const N: usize = 5;
+        let stack_state = StackState::default();
+        let report = Report::new(stack_state.clone());
+
+        let (mut router, _purge) = router::Router::new(
+            |n: &usize| Some(*n),
+            |_: &usize| Svc,
+            2 * N,
+            Duration::from_secs(60),
+        );
+        stack_state.register_cache("outbound.dst", router.cache_size());
+
+        for n in 0..N {
+            tower::Service::call(&mut router, n)
+                .wait()
+                .expect("request must be routed");
+        }
+
+        let report = report.as_display().to_string();
+        let expected = format!("proxy_cache_entries{{cache=\"outbound.dst\"}} {}\n", N);
+        assert!(report.contains(&expected), "{}", report);
+    }
+}
@@ -0,0 +1,43 @@
+//! Reports the connections that were forwarded without HTTP detection because
+//! the application began them with a TLS ClientHello.
+
+use super::metric_labels::Direction;
+use crate::proxy::server::TlsPassthroughCount;
+use linkerd2_metrics::{metrics, Counter, FmtMetrics};
+use std::fmt;
+
+metrics! {
+    tcp_tls_passthrough_total: Counter {
+        "Total count of connections that were forwarded opaquely because they began with a TLS ClientHello"
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    inbound: TlsPassthroughCount,
+    outbound: TlsPassthroughCount,
+}
+
+impl Metrics {
+    pub fn inbound(&self) -> TlsPassthroughCount {
+        self.inbound.clone()
+    }
+
+    pub fn outbound(&self) -> TlsPassthroughCount {
+        self.outbound.clone()
+    }
+}
+
+impl FmtMetrics for Metrics {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scopes = [
+            (Direction::In, Counter::from(self.inbound.value())),
+            (Direction::Out, Counter::from(self.outbound.value())),
+        ];
+
+        tcp_tls_passthrough_total.fmt_help(f)?;
+        tcp_tls_passthrough_total.fmt_scopes(f, scopes.iter().map(|(d, c)| (*d, c)), |c| c)?;
+
+        Ok(())
+    }
+}
@@ -303,6 +303,249 @@ pub mod layer {
     }
 }
 
+/// Instruments each accepted `(T, I)` connection with a span for its lifetime.
+///
+/// This is intended for opaque TCP forwarding, which isn't otherwise traced:
+/// `tcp::Forward` records `bytes.sent` and `bytes.received` on the span when
+/// the connection closes, so spans should declare those fields (e.g. as `0`)
+/// when they're created.
+pub mod accept {
+    use super::GetSpan;
+    use futures::Poll;
+    use linkerd2_proxy_core::listen;
+    use tracing_futures::{Instrument, Instrumented};
+
+    pub fn layer<T, G: GetSpan<T> + Clone>(get_span: G) -> Layer<T, G> {
+        Layer {
+            get_span,
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    pub struct Layer<T, G: GetSpan<T>> {
+        get_span: G,
+        _marker: std::marker::PhantomData<fn(T)>,
+    }
+
+    pub struct Accept<T, G: GetSpan<T>, A> {
+        get_span: G,
+        accept: A,
+        _marker: std::marker::PhantomData<fn(T)>,
+    }
+
+    impl<T, G: GetSpan<T> + Clone> Clone for Layer<T, G> {
+        fn clone(&self) -> Self {
+            Self {
+                get_span: self.get_span.clone(),
+                _marker: std::marker::PhantomData,
+            }
+        }
+    }
+
+    impl<T, G: GetSpan<T> + Clone, A> tower::layer::Layer<A> for Layer<T, G> {
+        type Service = Accept<T, G, A>;
+
+        fn layer(&self, accept: A) -> Self::Service {
+            Self::Service {
+                accept,
+                get_span: self.get_span.clone(),
+                _marker: std::marker::PhantomData,
+            }
+        }
+    }
+
+    impl<T, G: GetSpan<T> + Clone, A: Clone> Clone for Accept<T, G, A> {
+        fn clone(&self) -> Self {
+            Self {
+                accept: self.accept.clone(),
+                get_span: self.get_span.clone(),
+                _marker: std::marker::PhantomData,
+            }
+        }
+    }
+
+    impl<T, I, G, A> tower::Service<(T, I)> for Accept<T, G, A>
+    where
+        G: GetSpan<T>,
+        A: listen::Accept<(T, I)>,
+    {
+        type Response = ();
+        type Error = A::Error;
+        type Future = Instrumented<A::Future>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            self.accept.poll_ready()
+        }
+
+        fn call(&mut self, (meta, io): (T, I)) -> Self::Future {
+            let span = self.get_span.get_span(&meta);
+            let _enter = span.enter();
+            self.accept.accept((meta, io)).instrument(span.clone())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::proxy::tcp;
+        use futures::{future, Async, Future};
+        use std::collections::HashMap;
+        use std::io::{self, Read, Write};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::{Arc, Mutex};
+        use std::{cmp, fmt};
+        use tokio::io::{AsyncRead, AsyncWrite};
+        use tower::layer::Layer as _;
+        use tracing::{field, info_span, span, Event, Metadata, Subscriber};
+
+        /// Records the fields of each span, so that they can be checked by
+        /// span name.
+        #[derive(Clone, Default)]
+        struct Capture {
+            next_id: Arc<AtomicUsize>,
+            spans: Arc<Mutex<HashMap<u64, (&'static Metadata<'static>, Fields)>>>,
+            entered: Arc<Mutex<Vec<span::Id>>>,
+        }
+
+        type Fields = HashMap<String, String>;
+
+        struct Visit<'a>(&'a mut Fields);
+
+        /// Reads `rx` and then EOF; discards writes.
+        struct Io(&'static [u8]);
+
+        impl Capture {
+            fn fields(&self, name: &str) -> Vec<Fields> {
+                self.spans
+                    .lock()
+                    .unwrap()
+                    .values()
+                    .filter(|(meta, _)| meta.name() == name)
+                    .map(|(_, fields)| fields.clone())
+                    .collect()
+            }
+        }
+
+        impl Subscriber for Capture {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, attrs: &span::Attributes<'_>) -> span::Id {
+                let id = self.next_id.fetch_add(1, Ordering::SeqCst) as u64 + 1;
+                let mut fields = HashMap::new();
+                attrs.record(&mut Visit(&mut fields));
+                self.spans
+                    .lock()
+                    .unwrap()
+                    .insert(id, (attrs.metadata(), fields));
+                span::Id::from_u64(id)
+            }
+
+            fn record(&self, id: &span::Id, values: &span::Record<'_>) {
+                if let Some((_, fields)) = self.spans.lock().unwrap().get_mut(&id.into_u64()) {
+                    values.record(&mut Visit(fields));
+                }
+            }
+
+            fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+            fn event(&self, _: &Event<'_>) {}
+
+            fn enter(&self, id: &span::Id) {
+                self.entered.lock().unwrap().push(id.clone());
+            }
+
+            fn exit(&self, _: &span::Id) {
+                self.entered.lock().unwrap().pop();
+            }
+
+            // `tcp::Forward` records byte counts on the current span.
+            fn current_span(&self) -> span::Current {
+                let entered = self.entered.lock().unwrap();
+                let spans = self.spans.lock().unwrap();
+                entered
+                    .last()
+                    .and_then(|id| {
+                        let (meta, _) = spans.get(&id.into_u64())?;
+                        Some(span::Current::new(id.clone(), meta))
+                    })
+                    .unwrap_or_else(span::Current::none)
+            }
+        }
+
+        impl<'a> field::Visit for Visit<'a> {
+            fn record_debug(&mut self, field: &field::Field, value: &dyn fmt::Debug) {
+                self.0
+                    .insert(field.name().to_owned(), format!("{:?}", value));
+            }
+        }
+
+        impl Read for Io {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let n = cmp::min(buf.len(), self.0.len());
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+
+        impl AsyncRead for Io {}
+
+        impl Write for Io {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        impl AsyncWrite for Io {
+            fn shutdown(&mut self) -> Poll<(), io::Error> {
+                Ok(Async::Ready(()))
+            }
+        }
+
+        #[test]
+        fn forwarded_connections_are_spanned() {
+            let capture = Capture::default();
+
+            tracing::subscriber::with_default(capture.clone(), || {
+                let forward = tcp::Forward::new(crate::svc::mk(|_: &'static str| {
+                    future::ok::<_, crate::Error>(Io(b"hello from the endpoint"))
+                }));
+                let mut accept = layer(|addr: &&'static str| {
+                    info_span!(
+                        "forward",
+                        endpoint.addr = %addr,
+                        peer.id = "foo.ns.serviceaccount.identity.linkerd.cluster.local",
+                        bytes.sent = 0u64,
+                        bytes.received = 0u64,
+                    )
+                })
+                .layer(forward);
+                tower::Service::call(&mut accept, ("10.1.1.1:4143", Io(b"ping")))
+                    .wait()
+                    .expect("forward must succeed");
+            });
+
+            let spans = capture.fields("forward");
+            assert_eq!(spans.len(), 1, "one span per connection");
+            let fields = &spans[0];
+            let field = |name: &str| fields.get(name).map(String::as_str);
+            assert_eq!(field("endpoint.addr"), Some("10.1.1.1:4143"));
+            assert_eq!(
+                field("peer.id"),
+                Some("\"foo.ns.serviceaccount.identity.linkerd.cluster.local\"")
+            );
+            assert_eq!(field("bytes.sent"), Some("4"));
+            assert_eq!(field("bytes.received"), Some("23"));
+        }
+    }
+}
+
 pub use self::layer::Layer;
 
 pub fn layer<T, G: GetSpan<T> + Clone>(get_span: G) -> Layer<T, G> {
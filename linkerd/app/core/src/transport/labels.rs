@@ -27,7 +27,17 @@ enum Peer {
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
-pub struct TlsStatus(tls::Conditional<()>);
+pub struct TlsStatus(Tls);
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+enum Tls {
+    Mesh(tls::Conditional<()>),
+    /// Mesh TLS was established with a peer whose certificate was verified
+    /// against a URI SAN rather than its identity.
+    UriSan,
+    /// TLS was originated to a server outside of the mesh.
+    Originated,
+}
 
 // ===== impl Key =====
 
@@ -35,7 +45,7 @@ impl Key {
     pub fn accept<T>(direction: &'static str, tls: tls::Conditional<T>) -> Self {
         Self {
             direction: Direction(direction),
-            tls_status: TlsStatus(tls.map(|_| ())),
+            tls_status: tls.into(),
             peer: Peer::Src,
         }
     }
@@ -43,10 +53,34 @@ impl Key {
     pub fn connect<T>(direction: &'static str, tls: tls::Conditional<T>) -> Self {
         Self {
             direction: Direction(direction),
-            tls_status: TlsStatus(tls.map(|_| ())),
+            tls_status: tls.into(),
+            peer: Peer::Dst,
+        }
+    }
+
+    /// Describes connections on which TLS is originated to a server outside
+    /// of the mesh.
+    pub fn originate(direction: &'static str) -> Self {
+        Self {
+            direction: Direction(direction),
+            tls_status: TlsStatus(Tls::Originated),
             peer: Peer::Dst,
         }
     }
+
+    /// Describes connections on which mesh TLS is established with peers
+    /// whose certificates are verified against a URI SAN.
+    pub fn connect_uri_san(direction: &'static str) -> Self {
+        Self {
+            direction: Direction(direction),
+            tls_status: TlsStatus(Tls::UriSan),
+            peer: Peer::Dst,
+        }
+    }
+
+    pub fn direction(&self) -> &'static str {
+        self.direction.0
+    }
 }
 
 impl FmtLabels for Key {
@@ -78,27 +112,34 @@ impl FmtLabels for Peer {
 
 impl<T> From<tls::Conditional<T>> for TlsStatus {
     fn from(inner: tls::Conditional<T>) -> Self {
-        TlsStatus(inner.map(|_| ()))
+        TlsStatus(Tls::Mesh(inner.map(|_| ())))
     }
 }
 
 impl Into<tls::Conditional<()>> for TlsStatus {
     fn into(self) -> tls::Conditional<()> {
-        self.0
+        match self.0 {
+            Tls::Mesh(tls) => tls,
+            Tls::UriSan | Tls::Originated => Conditional::Some(()),
+        }
     }
 }
 
 impl TlsStatus {
     pub fn no_tls_reason(&self) -> Option<tls::ReasonForNoIdentity> {
-        self.0.reason()
+        match self.0 {
+            Tls::Mesh(ref tls) => tls.reason(),
+            Tls::UriSan | Tls::Originated => None,
+        }
     }
 }
 
 impl fmt::Display for TlsStatus {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self.0 {
-            Conditional::Some(()) => write!(f, "true"),
-            Conditional::None(r) => fmt::Display::fmt(&r, f),
+            Tls::Mesh(Conditional::Some(())) | Tls::UriSan => write!(f, "true"),
+            Tls::Mesh(Conditional::None(r)) => fmt::Display::fmt(&r, f),
+            Tls::Originated => write!(f, "origination"),
         }
     }
 }
@@ -109,6 +150,10 @@ impl FmtLabels for TlsStatus {
             return write!(f, "tls=\"no_identity\",no_tls_reason=\"{}\"", why);
         }
 
+        if let Tls::UriSan = self.0 {
+            return write!(f, "tls=\"{}\",tls_verification=\"uri_san\"", self);
+        }
+
         write!(f, "tls=\"{}\"", self)
     }
 }
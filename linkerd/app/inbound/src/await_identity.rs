@@ -0,0 +1,314 @@
+//! Determines how inbound connections are accepted before the local identity
+//! has been certified.
+//!
+//! Until the identity service issues a certificate, the inbound proxy cannot
+//! terminate mTLS: TLS handshakes fail and only plaintext connections can be
+//! served. `IdentityStartup` makes this behavior explicit, either holding
+//! connections until a certificate is available or accepting them without
+//! mTLS in the meantime.
+
+use futures::{try_ready, Async, Future, Poll};
+use linkerd2_app_core::{proxy::identity, svc, Error};
+use std::time::Duration;
+use std::{error, fmt};
+use tokio::{clock, timer::Delay};
+use tracing::debug;
+
+/// Configures how connections are handled while the local identity is not
+/// yet certified.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum IdentityStartup {
+    /// Holds each connection until the local identity is certified, failing
+    /// it if no certificate is issued within the timeout.
+    WaitForIdentity { timeout: Duration },
+
+    /// Accepts connections immediately. Plaintext connections are served
+    /// normally, but TLS handshakes fail until the identity is certified.
+    ServePlaintext,
+}
+
+/// Exposes whether the local identity has been certified.
+pub trait Certified: Clone {
+    type Future: Future<Item = (), Error = Error>;
+
+    fn is_certified(&self) -> bool;
+
+    /// Returns a future that completes once the identity is certified.
+    fn await_certified(self) -> Self::Future;
+}
+
+#[derive(Clone, Debug)]
+pub struct AwaitIdentity<C, S> {
+    certified: Option<C>,
+    startup: IdentityStartup,
+    inner: S,
+}
+
+pub enum ResponseFuture<F, S, T>
+where
+    F: Future<Item = (), Error = Error>,
+    S: svc::Service<T>,
+{
+    Waiting {
+        certified: F,
+        timeout: Duration,
+        expired: Delay,
+        ready: Option<(S, T)>,
+    },
+    Ready(Option<(S, T)>),
+    Inner(S::Future),
+}
+
+/// Produced when the local identity is not certified before a held connection
+/// times out.
+#[derive(Debug)]
+pub struct UncertifiedTimeout(Duration);
+
+pub struct AwaitCrt(identity::AwaitCrt);
+
+// === impl AwaitIdentity ===
+
+impl<C, S> AwaitIdentity<C, S> {
+    /// Wraps `inner` so that connections are accepted according to
+    /// `startup`. When `certified` is `None`, identity is disabled and
+    /// connections are always passed through.
+    pub fn new(certified: Option<C>, startup: IdentityStartup, inner: S) -> Self {
+        Self {
+            certified,
+            startup,
+            inner,
+        }
+    }
+}
+
+impl<C, S, T> svc::Service<T> for AwaitIdentity<C, S>
+where
+    C: Certified,
+    S: svc::Service<T, Response = ()> + Clone,
+    S::Error: Into<Error>,
+{
+    type Response = ();
+    type Error = Error;
+    type Future = ResponseFuture<C::Future, S, T>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, conn: T) -> Self::Future {
+        let certified = match self.certified {
+            Some(ref c) if !c.is_certified() => c.clone(),
+            _ => return ResponseFuture::Inner(self.inner.call(conn)),
+        };
+
+        match self.startup {
+            IdentityStartup::ServePlaintext => {
+                debug!("identity not certified; accepting connection without mTLS");
+                ResponseFuture::Inner(self.inner.call(conn))
+            }
+            IdentityStartup::WaitForIdentity { timeout } => {
+                debug!(?timeout, "identity not certified; holding connection");
+                ResponseFuture::Waiting {
+                    certified: certified.await_certified(),
+                    timeout,
+                    expired: Delay::new(clock::now() + timeout),
+                    ready: Some((self.inner.clone(), conn)),
+                }
+            }
+        }
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl<F, S, T> Future for ResponseFuture<F, S, T>
+where
+    F: Future<Item = (), Error = Error>,
+    S: svc::Service<T, Response = ()>,
+    S::Error: Into<Error>,
+{
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<(), Error> {
+        loop {
+            *self = match self {
+                ResponseFuture::Waiting {
+                    ref mut certified,
+                    timeout,
+                    ref mut expired,
+                    ref mut ready,
+                } => match certified.poll()? {
+                    Async::Ready(()) => {
+                        debug!("identity certified; accepting held connection");
+                        ResponseFuture::Ready(ready.take())
+                    }
+                    Async::NotReady => {
+                        try_ready!(expired.poll().map_err(Error::from));
+                        return Err(UncertifiedTimeout(*timeout).into());
+                    }
+                },
+                ResponseFuture::Ready(ref mut ready) => {
+                    let (svc, _) = ready.as_mut().expect("polled after complete");
+                    try_ready!(svc.poll_ready().map_err(Into::into));
+                    let (mut svc, conn) = ready.take().expect("polled after complete");
+                    ResponseFuture::Inner(svc.call(conn))
+                }
+                ResponseFuture::Inner(ref mut f) => return f.poll().map_err(Into::into),
+            };
+        }
+    }
+}
+
+// === impl UncertifiedTimeout ===
+
+impl fmt::Display for UncertifiedTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "identity was not certified within {}ms",
+            self.0.as_millis()
+        )
+    }
+}
+
+impl error::Error for UncertifiedTimeout {}
+
+// === impl Certified for identity::Local ===
+
+impl Certified for identity::Local {
+    type Future = AwaitCrt;
+
+    fn is_certified(&self) -> bool {
+        identity::Local::is_certified(self)
+    }
+
+    fn await_certified(self) -> Self::Future {
+        AwaitCrt(self.await_crt())
+    }
+}
+
+impl Future for AwaitCrt {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<(), Error> {
+        try_ready!(self.0.poll());
+        Ok(Async::Ready(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{future, Stream};
+    use std::sync::{Arc, Mutex};
+    use tokio::runtime::current_thread::Runtime;
+    use tokio::sync::watch;
+
+    #[derive(Clone)]
+    struct Cert(watch::Receiver<bool>);
+
+    impl Certified for Cert {
+        type Future = Box<dyn Future<Item = (), Error = Error>>;
+
+        fn is_certified(&self) -> bool {
+            *self.0.get_ref()
+        }
+
+        fn await_certified(self) -> Self::Future {
+            let f = self
+                .0
+                .skip_while(|c| Ok(!*c))
+                .into_future()
+                .map(|_| ())
+                .map_err(|(e, _)| e.into());
+            Box::new(f)
+        }
+    }
+
+    /// Records the connections that it accepts.
+    #[derive(Clone, Default)]
+    struct Accepted(Arc<Mutex<Vec<usize>>>);
+
+    impl svc::Service<usize> for Accepted {
+        type Response = ();
+        type Error = Error;
+        type Future = future::FutureResult<(), Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, conn: usize) -> Self::Future {
+            self.0.lock().unwrap().push(conn);
+            future::ok(())
+        }
+    }
+
+    impl Accepted {
+        fn get(&self) -> Vec<usize> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    #[test]
+    fn wait_for_identity_holds_connections_until_certified() {
+        let mut rt = Runtime::new().unwrap();
+        let (tx, rx) = watch::channel(false);
+        let accepted = Accepted::default();
+        let startup = IdentityStartup::WaitForIdentity {
+            timeout: Duration::from_secs(60),
+        };
+        let mut svc = AwaitIdentity::new(Some(Cert(rx)), startup, accepted.clone());
+
+        let mut held = rt
+            .block_on(future::lazy(|| {
+                let mut f = svc.call(1);
+                assert!(f.poll().unwrap().is_not_ready(), "must be held");
+                Ok::<_, ()>(f)
+            }))
+            .unwrap();
+        assert!(accepted.get().is_empty());
+
+        tx.broadcast(true).unwrap();
+        rt.block_on(future::poll_fn(|| held.poll()))
+            .expect("held connection must be accepted");
+        assert_eq!(accepted.get(), vec![1]);
+
+        rt.block_on(future::lazy(|| svc.call(2))).unwrap();
+        assert_eq!(accepted.get(), vec![1, 2]);
+    }
+
+    #[test]
+    fn wait_for_identity_times_out() {
+        let mut rt = Runtime::new().unwrap();
+        let (_tx, rx) = watch::channel(false);
+        let accepted = Accepted::default();
+        let startup = IdentityStartup::WaitForIdentity {
+            timeout: Duration::from_millis(10),
+        };
+        let mut svc = AwaitIdentity::new(Some(Cert(rx)), startup, accepted.clone());
+
+        let err = rt
+            .block_on(future::lazy(|| svc.call(1)))
+            .expect_err("held connection must time out");
+        assert!(err.is::<UncertifiedTimeout>());
+        assert!(accepted.get().is_empty());
+    }
+
+    #[test]
+    fn serve_plaintext_accepts_connections_before_certified() {
+        let mut rt = Runtime::new().unwrap();
+        let (_tx, rx) = watch::channel(false);
+        let accepted = Accepted::default();
+        let mut svc = AwaitIdentity::new(
+            Some(Cert(rx)),
+            IdentityStartup::ServePlaintext,
+            accepted.clone(),
+        );
+
+        rt.block_on(future::lazy(|| svc.call(1))).unwrap();
+        assert_eq!(accepted.get(), vec![1]);
+    }
+}
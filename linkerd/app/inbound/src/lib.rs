@@ -6,19 +6,21 @@
 #![deny(warnings, rust_2018_idioms)]
 
 use futures::future;
+use indexmap::IndexMap;
 use linkerd2_app_core::{
-    self as core, classify,
-    config::{ProxyConfig, ServerConfig},
+    self as core, accept_timeout, accept_watchdog, admin, admission, caller_override, classify,
+    config::{DrainPolicy, ProxyConfig, ServerConfig},
     drain,
-    dst::DstAddr,
-    errors, http_request_authority_addr, http_request_host_addr,
-    http_request_l5d_override_dst_addr, http_request_orig_dst_addr,
+    dst::{self, DstAddr},
+    error_log::ErrorLog,
+    errors, header_trust, http_request_orig_dst_addr,
     opencensus::proto::trace::v1 as oc,
     proxy::{
         self,
         http::{
-            client, insert, metrics as http_metrics, normalize_uri, profiles, settings,
-            strip_header,
+            checksum, client, correlation_id, expect_continue, header_limit, insert,
+            metrics as http_metrics, normalize_headers, normalize_uri, outcome, pool, profiles,
+            sanitize_response, settings, strip_header, transform,
         },
         identity,
         server::{Protocol as ServerProtocol, Server},
@@ -26,30 +28,68 @@ use linkerd2_app_core::{
     },
     reconnect, router, serve,
     spans::SpanConverter,
-    svc, trace, trace_context,
+    startup, svc, trace, trace_context,
     transport::{self, connect, tls, OrigDstAddr, SysOrigDstAddr},
-    Addr, DispatchDeadline, Error, ProxyMetrics, CANONICAL_DST_HEADER, DST_OVERRIDE_HEADER,
-    L5D_CLIENT_ID, L5D_REMOTE_IP, L5D_SERVER_ID,
+    DispatchDeadline, Error, NameAddr, ProxyMetrics, DST_OVERRIDE_HEADER, L5D_CLIENT_ID,
+    L5D_REMOTE_IP, L5D_SERVER_ID,
 };
 use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc;
 use tower_grpc::{self as grpc, generic::client::GrpcService};
 use tracing::{debug, info, info_span};
 
+mod await_identity;
 mod endpoint;
+mod limit_dst_names;
 mod orig_proto_downgrade;
 mod rewrite_loopback_addr;
 #[allow(dead_code)] // TODO #2597
 mod set_client_id_on_req;
 #[allow(dead_code)] // TODO #2597
 mod set_remote_ip_on_req;
+mod startup_shield;
 
+pub use self::await_identity::IdentityStartup;
 pub use self::endpoint::{Endpoint, RecognizeEndpoint};
+pub use self::rewrite_loopback_addr::ForwardAddrs;
 
 #[derive(Clone, Debug)]
 pub struct Config<A: OrigDstAddr = SysOrigDstAddr> {
     pub proxy: ProxyConfig<A>,
+    pub dst_name_limit: Option<usize>,
+    pub dst_name_limit_window: Duration,
+    pub identity_startup: IdentityStartup,
+    /// If set, requests that fail because the application refuses connections
+    /// receive a 503 with this `Retry-After` until the application first
+    /// accepts a connection. Readiness is withheld until then.
+    pub startup_shield_retry_after: Option<Duration>,
+    /// Request headers that are collapsed into a single value when a request
+    /// repeats them.
+    pub collapse_request_headers: normalize_headers::Policy,
+    /// If set, the admission limit adapts to the latency of responses rather
+    /// than being fixed at `max_in_flight`.
+    pub adaptive_concurrency: Option<admission::Adaptive>,
+    /// If set, requests are shed while the latency of responses exceeds a
+    /// threshold.
+    pub brownout: Option<admission::Brownout>,
+    /// The transformations applied to requests and responses on each
+    /// destination's routes, by route name.
+    pub route_transforms: IndexMap<NameAddr, IndexMap<String, transform::Transform>>,
+    /// If set, the listener's accept loop is watched so that a stall is
+    /// detected (and, optionally, recovered from).
+    pub accept_watchdog: Option<accept_watchdog::Config>,
+    /// If set, TLS connections that don't present an SNI are rejected rather
+    /// than being forwarded without being terminated.
+    pub require_sni: bool,
+    /// The maximum number of connections held while the stack is being
+    /// constructed. Further connections are refused until it has been.
+    pub startup_queue_capacity: usize,
+    /// The addresses on which the local application is reached. By default,
+    /// connections are forwarded to `127.0.0.1`.
+    pub forward_addrs: ForwardAddrs,
 }
 
 pub struct Inbound {
@@ -61,17 +101,31 @@ impl<A: OrigDstAddr> Config<A> {
     pub fn with_orig_dst_addr<B: OrigDstAddr>(self, orig_dst_addr: B) -> Config<B> {
         Config {
             proxy: self.proxy.with_orig_dst_addr(orig_dst_addr),
+            dst_name_limit: self.dst_name_limit,
+            dst_name_limit_window: self.dst_name_limit_window,
+            identity_startup: self.identity_startup,
+            startup_shield_retry_after: self.startup_shield_retry_after,
+            collapse_request_headers: self.collapse_request_headers,
+            adaptive_concurrency: self.adaptive_concurrency,
+            brownout: self.brownout,
+            route_transforms: self.route_transforms,
+            accept_watchdog: self.accept_watchdog,
+            require_sni: self.require_sni,
+            startup_queue_capacity: self.startup_queue_capacity,
+            forward_addrs: self.forward_addrs,
         }
     }
 
     pub fn build<P>(
         self,
         local_identity: tls::Conditional<identity::Local>,
-        profiles_client: core::profiles::Client<P>,
+        profiles_client: profiles::negative_cache::GetRoutes<core::profiles::Client<P>>,
         tap_layer: tap::Layer,
         metrics: ProxyMetrics,
         span_sink: Option<mpsc::Sender<oc::Span>>,
         drain: drain::Watch,
+        latch: admin::Latch,
+        readiness: admin::Readiness,
     ) -> Result<Inbound, Error>
     where
         A: Send + 'static,
@@ -82,6 +136,18 @@ impl<A: OrigDstAddr> Config<A> {
     {
         use proxy::core::listen::{Bind, Listen};
         let Config {
+            dst_name_limit,
+            dst_name_limit_window,
+            identity_startup,
+            startup_shield_retry_after,
+            collapse_request_headers,
+            adaptive_concurrency,
+            brownout,
+            route_transforms,
+            accept_watchdog,
+            require_sni,
+            startup_queue_capacity,
+            forward_addrs,
             proxy:
                 ProxyConfig {
                     server:
@@ -89,33 +155,76 @@ impl<A: OrigDstAddr> Config<A> {
                             bind,
                             buffer,
                             h2_settings,
+                            max_requests_per_connection,
+                            h2_reset_limit,
+                            idle_timeouts,
                         },
                     connect,
                     router_capacity,
                     router_max_idle_age,
                     disable_protocol_detection_for_ports,
+                    strip_response_headers,
+                    error_log_dedup_window,
+                    correlation_id_header,
+                    correlation_id_echo,
+                    caller_overrides,
+                    header_trust,
+                    max_response_headers,
                 },
         } = self;
 
-        let listen = bind.bind().map_err(Error::from)?;
+        let listen = bind.clone().bind().map_err(Error::from)?;
         let listen_addr = listen.listen_addr();
+        // While the accept loop is stalled, the proxy isn't ready.
+        let accept_watch = accept_watchdog
+            .map(|c| c.watch(readiness.condition(), metrics.accept_watchdog.clone()));
 
         // The stack is served lazily since some layers (notably buffer) spawn
         // tasks from their constructor. This helps to ensure that tasks are
         // spawned on the same runtime as the proxy.
-        let serve = Box::new(future::lazy(move || {
+        // Requests that are still queued in buffers when the proxy begins to
+        // drain fail immediately if the drain policy requires it.
+        let buffer_drain = match buffer.drain_policy {
+            DrainPolicy::FailFast => Some(drain.signaled()),
+            DrainPolicy::Complete => None,
+        };
+        // Requests whose dispatch deadline is too soon to be met fail without
+        // being queued, if a minimum dispatch budget is configured.
+        let buffer_shed = buffer
+            .min_dispatch_budget
+            .map(|min| proxy::buffer::Shed::new(min, metrics.deadline_shed.clone()));
+
+        // Until the application first accepts a connection, the proxy is not
+        // ready and refused connections are reported as 503s.
+        let startup_shield = match startup_shield_retry_after {
+            Some(retry_after) => startup_shield::Shield::new(retry_after, latch),
+            None => startup_shield::Shield::lowered(),
+        };
+
+        // Connections are accepted while the stack is constructed, but they're
+        // held until it has been, so that they don't race its construction.
+        let (constructed, gate) = startup::gate(startup_queue_capacity, readiness.condition());
+        let serve_drain = drain.clone();
+
+        let construct = future::lazy(move || {
+            let error_log = ErrorLog::spawn("inbound", error_log_dedup_window);
+
             // Establishes connections to the local application (for both
             // TCP forwarding and HTTP proxying).
             let connect_stack = svc::stack(connect::svc(connect.keepalive))
                 .push(tls::client::layer(local_identity.clone()))
                 .push_timeout(connect.timeout)
                 .push(metrics.transport.layer_connect(TransportLabels))
-                .push(rewrite_loopback_addr::layer());
+                .push(startup_shield.connect_layer())
+                .push(rewrite_loopback_addr::layer(Arc::new(forward_addrs)));
 
-            // Instantiates an HTTP client for a `client::Config`
+            // Instantiates an HTTP client for a `client::Config`, reporting
+            // the state of each endpoint's connection pool.
             let client_stack = connect_stack
                 .clone()
+                .push(pool::connect_layer(metrics.http_endpoint_pool.clone()))
                 .push(client::layer(connect.h2_settings))
+                .push(pool::layer(metrics.http_endpoint_pool))
                 .push(reconnect::layer({
                     let backoff = connect.backoff.clone();
                     move |_| Ok(backoff.stream())
@@ -123,20 +232,35 @@ impl<A: OrigDstAddr> Config<A> {
                 .push(trace_context::layer(span_sink.clone().map(|span_sink| {
                     SpanConverter::client(span_sink, trace_labels())
                 })))
-                .push(normalize_uri::layer());
+                .push(normalize_uri::layer())
+                .push(startup_shield.http_layer());
 
             // A stack configured by `router::Config`, responsible for building
             // a router made of route stacks configured by `inbound::Endpoint`.
             let endpoint_router = client_stack
+                .push(header_limit::layer(max_response_headers))
                 .push(tap_layer)
                 .push(http_metrics::layer::<_, classify::Response>(
                     metrics.http_endpoint,
                 ))
+                .push(outcome::record::layer(
+                    |endpoint: &Endpoint, outcome: &outcome::Outcome| {
+                        outcome.set_endpoint(endpoint.addr)
+                    },
+                ))
                 .serves::<Endpoint>()
+                // When enabled for debugging, sampled requests are timed in
+                // each of the stack's major stages.
+                .push(metrics.stack_stage.layer("inbound.endpoint"))
                 .push(trace::layer(
                     |endpoint: &Endpoint| info_span!("endpoint", peer.addr = %endpoint.addr),
                 ))
-                .push_buffer_pending(buffer.max_in_flight, DispatchDeadline::extract)
+                .push_buffer_pending_with_shedding(
+                    buffer.max_in_flight,
+                    DispatchDeadline::extract,
+                    buffer_drain.clone(),
+                    buffer_shed.clone(),
+                )
                 .makes::<Endpoint>()
                 .push(router::Layer::new(
                     router::Config::new(router_capacity, router_max_idle_age),
@@ -144,6 +268,12 @@ impl<A: OrigDstAddr> Config<A> {
                 ))
                 .into_inner()
                 .spawn();
+            metrics
+                .stack_state
+                .register_cache("inbound.endpoint", endpoint_router.cache_size());
+            metrics
+                .cache_lock_wait
+                .register("inbound.endpoint", endpoint_router.lock_wait());
 
             // A per-`dst::Route` layer that uses profile data to configure
             // a per-route layer.
@@ -151,13 +281,28 @@ impl<A: OrigDstAddr> Config<A> {
             // The `classify` module installs a `classify::Response`
             // extension into each request so that all lower metrics
             // implementations can use the route-specific configuration.
+            // Requests and responses are optionally transformed, as
+            // configured for the route, before anything else sees them.
             let dst_route_layer = svc::layers()
                 .push(insert::target::layer())
-                .push(http_metrics::layer::<_, classify::Response>(
-                    metrics.http_route,
+                .push(
+                    http_metrics::layer::<_, classify::Response>(metrics.http_route)
+                        .with_outcome(true),
+                )
+                .push(outcome::record::layer(
+                    |route: &dst::Route, outcome: &outcome::Outcome| {
+                        outcome.set_route_labels(route.route.labels())
+                    },
                 ))
                 .push(classify::layer())
-                .push_buffer_pending(buffer.max_in_flight, DispatchDeadline::extract);
+                .push_buffer_pending_with_shedding(
+                    buffer.max_in_flight,
+                    DispatchDeadline::extract,
+                    buffer_drain.clone(),
+                    buffer_shed.clone(),
+                )
+                .push(transform::layer())
+                .push(metrics.stack_stage.layer("inbound.profile"));
 
             // A per-`DstAddr` stack that does the following:
             //
@@ -167,57 +312,40 @@ impl<A: OrigDstAddr> Config<A> {
             //    `RecognizeEndpoint` can use the value.
             let dst_stack = svc::stack(svc::Shared::new(endpoint_router))
                 .push(insert::target::layer())
-                .push_buffer_pending(buffer.max_in_flight, DispatchDeadline::extract)
-                .push(profiles::router::layer(profiles_client, dst_route_layer))
+                .push_buffer_pending_with_shedding(
+                    buffer.max_in_flight,
+                    DispatchDeadline::extract,
+                    buffer_drain.clone(),
+                    buffer_shed.clone(),
+                )
+                .push(
+                    profiles::router::layer(profiles_client, dst_route_layer)
+                        .with_transforms(Arc::new(route_transforms)),
+                )
                 .push(strip_header::request::layer(DST_OVERRIDE_HEADER))
+                .push(metrics.stack_stage.layer("inbound.logical"))
                 .push(trace::layer(
                     |dst: &DstAddr| info_span!("logical", dst = %dst.dst_logical()),
                 ));
 
             // Routes requests to a `DstAddr`.
             //
-            // 1. If the CANONICAL_DST_HEADER is set by the remote peer,
-            // this value is used to construct a DstAddr.
-            //
-            // 2. If the OVERRIDE_DST_HEADER is set by the remote peer,
-            // this value is used.
-            //
-            // 3. If the request is HTTP/2 and has an :authority, this value
-            // is used.
-            //
-            // 4. If the request is absolute-form HTTP/1, the URI's
-            // authority is used.
-            //
-            // 5. If the request has an HTTP/1 Host header, it is used.
-            //
-            // 6. Finally, if the tls::accept::Meta had an SO_ORIGINAL_DST, this TCP
-            // address is used.
+            // The destination requested by the client's headers is used
+            // unless the source has exceeded its destination name limit.
+            // Otherwise, if the tls::accept::Meta had an SO_ORIGINAL_DST,
+            // this TCP address is used.
             let dst_router = dst_stack
-                .push_buffer_pending(buffer.max_in_flight, DispatchDeadline::extract)
+                .push_buffer_pending_with_shedding(
+                    buffer.max_in_flight,
+                    DispatchDeadline::extract,
+                    buffer_drain.clone(),
+                    buffer_shed.clone(),
+                )
                 .push(router::Layer::new(
                     router::Config::new(router_capacity, router_max_idle_age),
                     |req: &http::Request<_>| {
-                        let dst = req
-                            .headers()
-                            .get(CANONICAL_DST_HEADER)
-                            .and_then(|dst| {
-                                dst.to_str().ok().and_then(|d| {
-                                    Addr::from_str(d).ok().map(|a| {
-                                        debug!("using {}", CANONICAL_DST_HEADER);
-                                        a
-                                    })
-                                })
-                            })
-                            .or_else(|| {
-                                http_request_l5d_override_dst_addr(req)
-                                    .ok()
-                                    .map(|override_addr| {
-                                        debug!("using {}", DST_OVERRIDE_HEADER);
-                                        override_addr
-                                    })
-                            })
-                            .or_else(|| http_request_authority_addr(req).ok())
-                            .or_else(|| http_request_host_addr(req).ok())
+                        let dst = limit_dst_names::requested_dst(req)
+                            .filter(|_| !limit_dst_names::is_collapsed(req))
                             .or_else(|| http_request_orig_dst_addr(req).ok())
                             .map(|addr| {
                                 DstAddr::inbound(addr, settings::Settings::from_request(req))
@@ -228,12 +356,23 @@ impl<A: OrigDstAddr> Config<A> {
                 ))
                 .into_inner()
                 .spawn();
+            metrics
+                .stack_state
+                .register_cache("inbound.dst", dst_router.cache_size());
+            metrics
+                .cache_lock_wait
+                .register("inbound.dst", dst_router.lock_wait());
 
-            // Share a single semaphore across all requests to signal when
-            // the proxy is overloaded.
-            let admission_control = svc::stack(dst_router)
-                .push_concurrency_limit(buffer.max_in_flight)
-                .push_load_shed();
+            // Share a single admission limit across all requests so that they
+            // are shed when the proxy is overloaded or the application is
+            // responding slowly.
+            let limit = match adaptive_concurrency {
+                Some(adaptive) => admission::Limit::adaptive(adaptive),
+                None => admission::Limit::new(buffer.max_in_flight),
+            }
+            .with_brownout(brownout);
+            metrics.admission.register(&limit);
+            let admission_control = svc::stack(dst_router).push(admission::layer(limit));
 
             // As HTTP requests are accepted, the `tls::accept::Meta` connection
             // metadata is stored on each request's extensions.
@@ -243,18 +382,49 @@ impl<A: OrigDstAddr> Config<A> {
             // the router need not detect whether a request _will be_ downgraded.
             let source_stack = svc::stack(svc::Shared::new(admission_control))
                 .serves::<tls::accept::Meta>()
+                .push(limit_dst_names::layer(
+                    metrics
+                        .dst_name_limit
+                        .with_max_names(dst_name_limit, dst_name_limit_window),
+                ))
                 .push(orig_proto_downgrade::layer())
+                // Bodies that the outbound proxy checksummed are verified,
+                // and their responses checksummed in turn. Requests aren't
+                // checksummed here, since they aren't upgraded.
+                .push(checksum::layer(
+                    |_: &tls::accept::Meta| false,
+                    metrics.body_checksum.clone(),
+                ))
                 .push(insert::target::layer())
+                // Only meshed peers may override retries and timeouts.
+                .push(caller_override::layer(
+                    caller_overrides,
+                    caller_override::Trust::Meshed,
+                    metrics.caller_override.clone(),
+                ))
+                // Proxy-control headers are only honored from the peers that
+                // the policy trusts to set them.
+                .push(header_trust::request_layer(
+                    header_trust,
+                    metrics.header_trust.clone(),
+                ))
                 // disabled due to information leagkage
                 //.push(set_remote_ip_on_req::layer())
                 //.push(set_client_id_on_req::layer())
+                .push(normalize_headers::layer(collapse_request_headers))
                 .push(strip_header::request::layer(L5D_REMOTE_IP))
                 .push(strip_header::request::layer(L5D_CLIENT_ID))
                 .push(strip_header::response::layer(L5D_SERVER_ID))
                 .push(insert::layer(move || {
                     DispatchDeadline::after(buffer.dispatch_timeout)
                 }))
-                .push(errors::layer())
+                .push(errors::layer(
+                    error_log,
+                    metrics.invariant_violations.clone(),
+                ))
+                .push(sanitize_response::layer(strip_response_headers))
+                .push(expect_continue::layer())
+                .push(correlation_id::layer(correlation_id_header).with_echo(correlation_id_echo))
                 .push(trace::layer(|src: &tls::accept::Meta| {
                     info_span!(
                         "source",
@@ -262,35 +432,81 @@ impl<A: OrigDstAddr> Config<A> {
                         target.addr = %src.addrs.target_addr(),
                     )
                 }))
+                .push(outcome::layer())
                 .push(trace_context::layer(span_sink.map(|span_sink| {
                     SpanConverter::server(span_sink, trace_labels())
                 })))
                 .push(metrics.http_handle_time.layer())
+                .push(metrics.stack_stage.layer("inbound.server"))
                 .serves::<tls::accept::Meta>();
 
-            let forward_tcp = tcp::Forward::new(
+            // Opaque TCP connections are forwarded within a connection-scoped
+            // span, on which the forwarded byte counts are recorded once the
+            // connection closes.
+            let forward_tcp = svc::stack(tcp::Forward::new(
                 svc::stack(connect_stack)
                     .push(svc::map_target::layer(|meta: tls::accept::Meta| {
                         Endpoint::from(meta.addrs.target_addr())
                     }))
                     .into_inner(),
-            );
+            ))
+            .push(trace::accept::layer(|meta: &tls::accept::Meta| {
+                info_span!(
+                    "forward",
+                    endpoint.addr = %meta.addrs.target_addr(),
+                    peer.id = ?meta.peer_identity,
+                    bytes.sent = 0u64,
+                    bytes.received = 0u64,
+                )
+            }))
+            .into_inner();
 
+            metrics
+                .stack_state
+                .register_skip_ports("inbound", disable_protocol_detection_for_ports.clone());
             let server = Server::new(
                 TransportLabels,
-                metrics.transport,
+                metrics.transport.clone(),
                 forward_tcp,
                 source_stack,
                 h2_settings,
                 drain.clone(),
                 disable_protocol_detection_for_ports.clone(),
+                metrics.tls_passthrough,
+                max_requests_per_connection,
+                h2_reset_limit,
+                idle_timeouts,
             );
 
+            let certified = local_identity.value().cloned();
             let accept = tls::AcceptTls::new(local_identity, server)
-                .with_skip_ports(disable_protocol_detection_for_ports);
+                .with_skip_ports(disable_protocol_detection_for_ports.clone())
+                .with_require_sni(require_sni);
+            // Clients that never send data can't be identified, so their
+            // closures are labeled as plaintext.
+            let accept = accept_timeout::AcceptTimeout::new(
+                idle_timeouts.accept,
+                transport::labels::Key::accept::<()>(
+                    "inbound",
+                    tls::Conditional::None(tls::ReasonForNoPeerName::NotProvidedByRemote.into()),
+                ),
+                metrics.transport,
+                accept,
+            )
+            .with_skip_ports(disable_protocol_detection_for_ports);
+            let accept = await_identity::AwaitIdentity::new(certified, identity_startup, accept);
+
+            constructed.complete(accept);
+            Ok(())
+        });
 
+        let serve = Box::new(future::lazy(move || {
             info!(listen.addr = %listen.listen_addr(), "serving");
-            serve::serve(listen, accept, drain)
+            tokio::spawn(construct);
+            match accept_watch {
+                Some(watch) => serve::serve_watched(listen, bind, gate, watch, serve_drain),
+                None => serve::serve(listen, gate, serve_drain),
+            }
         }));
 
         Ok(Inbound { listen_addr, serve })
@@ -0,0 +1,243 @@
+//! Applies a `dst_name_limit::Limit` to each source's requests.
+//!
+//! Requests whose source has exceeded its limit are marked as `Collapsed` so
+//! that the destination router ignores their requested name.
+
+use futures::{try_ready, Future, Poll};
+use http;
+use linkerd2_app_core::{
+    dst_name_limit::{Limit, Source},
+    http_request_authority_addr, http_request_host_addr, http_request_l5d_override_dst_addr, svc,
+    transport::tls,
+    Addr, CANONICAL_DST_HEADER, DST_OVERRIDE_HEADER,
+};
+use tracing::debug;
+
+/// Marks a request whose destination name must be ignored because its source
+/// has exceeded its destination name limit.
+#[derive(Copy, Clone, Debug)]
+pub struct Collapsed(());
+
+#[derive(Clone, Debug)]
+pub struct Layer(Limit);
+
+#[derive(Clone, Debug)]
+pub struct Stack<M> {
+    limit: Limit,
+    inner: M,
+}
+
+pub struct MakeFuture<F> {
+    src: Option<Source>,
+    limit: Limit,
+    inner: F,
+}
+
+#[derive(Clone, Debug)]
+pub struct Service<S> {
+    src: Source,
+    limit: Limit,
+    inner: S,
+}
+
+/// Determines the destination requested by a client's headers.
+///
+/// 1. If the CANONICAL_DST_HEADER is set by the remote peer,
+/// this value is used to construct a DstAddr.
+///
+/// 2. If the OVERRIDE_DST_HEADER is set by the remote peer,
+/// this value is used.
+///
+/// 3. If the request is HTTP/2 and has an :authority, this value
+/// is used.
+///
+/// 4. If the request is absolute-form HTTP/1, the URI's
+/// authority is used.
+///
+/// 5. If the request has an HTTP/1 Host header, it is used.
+pub fn requested_dst<B>(req: &http::Request<B>) -> Option<Addr> {
+    req.headers()
+        .get(CANONICAL_DST_HEADER)
+        .and_then(|dst| {
+            dst.to_str().ok().and_then(|d| {
+                Addr::from_str(d).ok().map(|a| {
+                    debug!("using {}", CANONICAL_DST_HEADER);
+                    a
+                })
+            })
+        })
+        .or_else(|| {
+            http_request_l5d_override_dst_addr(req)
+                .ok()
+                .map(|override_addr| {
+                    debug!("using {}", DST_OVERRIDE_HEADER);
+                    override_addr
+                })
+        })
+        .or_else(|| http_request_authority_addr(req).ok())
+        .or_else(|| http_request_host_addr(req).ok())
+}
+
+pub fn is_collapsed<B>(req: &http::Request<B>) -> bool {
+    req.extensions().get::<Collapsed>().is_some()
+}
+
+pub fn layer(limit: Limit) -> Layer {
+    Layer(limit)
+}
+
+// === impl Layer ===
+
+impl<M> svc::Layer<M> for Layer {
+    type Service = Stack<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        Stack {
+            limit: self.0.clone(),
+            inner,
+        }
+    }
+}
+
+// === impl Stack ===
+
+impl<M> svc::Service<tls::accept::Meta> for Stack<M>
+where
+    M: svc::Service<tls::accept::Meta>,
+{
+    type Response = Service<M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, meta: tls::accept::Meta) -> Self::Future {
+        MakeFuture {
+            src: Some(Source::from(&meta)),
+            limit: self.limit.clone(),
+            inner: self.inner.call(meta),
+        }
+    }
+}
+
+// === impl MakeFuture ===
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = Service<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        let src = self.src.take().expect("polled after ready");
+        Ok(Service {
+            src,
+            limit: self.limit.clone(),
+            inner,
+        }
+        .into())
+    }
+}
+
+// === impl Service ===
+
+impl<S, B> svc::Service<http::Request<B>> for Service<S>
+where
+    S: svc::Service<http::Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut req: http::Request<B>) -> Self::Future {
+        if let Some(Addr::Name(dst)) = requested_dst(&req) {
+            if !self.limit.permit(&self.src, &dst) {
+                req.extensions_mut().insert(Collapsed(()));
+            }
+        }
+
+        self.inner.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use linkerd2_app_core::{transport::listen::Addrs, Conditional, Never};
+    use std::time::Duration;
+
+    fn meta(peer: [u8; 4]) -> tls::accept::Meta {
+        tls::accept::Meta {
+            peer_identity: Conditional::None(tls::ReasonForNoIdentity::Disabled),
+            addrs: Addrs::new(([10, 1, 1, 1], 4143).into(), (peer, 33333).into(), None),
+        }
+    }
+
+    /// Builds a service for `meta` that returns whether each request's
+    /// destination name was collapsed.
+    fn serve(
+        limit: &Limit,
+        meta: tls::accept::Meta,
+    ) -> impl svc::Service<http::Request<()>, Response = bool> {
+        let make = svc::mk(|_: tls::accept::Meta| {
+            future::ok::<_, Never>(svc::mk(|req: http::Request<()>| {
+                future::ok::<_, Never>(is_collapsed(&req))
+            }))
+        });
+        let mut stack = svc::Layer::layer(&layer(limit.clone()), make);
+        svc::Service::call(&mut stack, meta)
+            .wait()
+            .expect("service must be built")
+    }
+
+    fn collapsed<S>(svc: &mut S, dst: &str) -> bool
+    where
+        S: svc::Service<http::Request<()>, Response = bool>,
+        S::Error: std::fmt::Debug,
+    {
+        let req = http::Request::builder()
+            .uri(format!("http://{}:8080/", dst))
+            .body(())
+            .unwrap();
+        svc::Service::call(svc, req)
+            .wait()
+            .expect("request must succeed")
+    }
+
+    #[test]
+    fn unidentified_sources_are_limited_by_address() {
+        let limit = Limit::default().with_max_names(Some(2), Duration::from_secs(60));
+
+        let mut a = serve(&limit, meta([10, 2, 2, 1]));
+        assert!(!collapsed(&mut a, "svc0.ns.svc.cluster.local"));
+        assert!(!collapsed(&mut a, "svc1.ns.svc.cluster.local"));
+        assert!(collapsed(&mut a, "svc2.ns.svc.cluster.local"));
+
+        // A new connection from the same address shares its allotment...
+        let mut a = serve(&limit, meta([10, 2, 2, 1]));
+        assert!(collapsed(&mut a, "svc3.ns.svc.cluster.local"));
+
+        // ...but other unidentified peers have their own.
+        let mut b = serve(&limit, meta([10, 2, 2, 2]));
+        assert!(!collapsed(&mut b, "svc2.ns.svc.cluster.local"));
+        assert!(!collapsed(&mut b, "svc3.ns.svc.cluster.local"));
+    }
+
+    #[test]
+    fn unlimited_unless_configured() {
+        let limit = Limit::default().with_max_names(None, Duration::from_secs(60));
+        let mut a = serve(&limit, meta([10, 2, 2, 1]));
+        for n in 0..100 {
+            assert!(!collapsed(
+                &mut a,
+                &format!("svc{}.ns.svc.cluster.local", n)
+            ));
+        }
+    }
+}
@@ -1,15 +1,93 @@
-//! Rewrites connect `SocketAddr`s IP address to the loopback address (`127.0.0.1`),
-//! with the same port still set.
+//! Rewrites connect `SocketAddr`s IP address to the address on which the
+//! local application listens, with the same port still set.
+//!
+//! By default, this is the loopback address (`127.0.0.1`). Applications that
+//! only listen on another address (e.g. on the pod IP or, with host
+//! networking, on another interface) may be reached by configuring a
+//! `ForwardAddrs`.
 
 use super::Endpoint;
+use indexmap::IndexMap;
 use linkerd2_app_core::svc::map_target;
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
 use tracing::debug;
 
-pub fn layer() -> map_target::Layer<impl Fn(Endpoint) -> Endpoint + Copy> {
-    map_target::layer(|mut ep: Endpoint| {
-        debug!("rewriting inbound address to loopback; addr={:?}", ep.addr);
-        ep.addr = SocketAddr::from(([127, 0, 0, 1], ep.addr.port()));
+const DEFAULT_FORWARD_ADDR: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+/// Determines the address to which inbound connections are forwarded.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ForwardAddrs {
+    /// If set, connections are forwarded to this address rather than to
+    /// `127.0.0.1`.
+    pub addr: Option<IpAddr>,
+    /// Overrides `addr` for connections to specific ports.
+    pub ports: IndexMap<u16, IpAddr>,
+}
+
+pub fn layer(
+    forward: Arc<ForwardAddrs>,
+) -> map_target::Layer<impl Fn(Endpoint) -> Endpoint + Clone> {
+    map_target::layer(move |mut ep: Endpoint| {
+        let addr = forward.forward_addr(ep.addr);
+        debug!(
+            "rewriting inbound address; addr={:?}; forward={:?}",
+            ep.addr, addr
+        );
+        ep.addr = addr;
         ep
     })
 }
+
+// === impl ForwardAddrs ===
+
+impl ForwardAddrs {
+    /// Returns the address to which connections to `addr` are forwarded.
+    pub fn forward_addr(&self, addr: SocketAddr) -> SocketAddr {
+        let port = addr.port();
+        let ip = self
+            .ports
+            .get(&port)
+            .cloned()
+            .or(self.addr)
+            .unwrap_or(DEFAULT_FORWARD_ADDR);
+        SocketAddr::new(ip, port)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn orig_dst() -> SocketAddr {
+        SocketAddr::from(([10, 1, 2, 3], 8080))
+    }
+
+    #[test]
+    fn forwards_to_loopback_by_default() {
+        let forward = ForwardAddrs::default();
+        assert_eq!(
+            forward.forward_addr(orig_dst()),
+            SocketAddr::from(([127, 0, 0, 1], 8080))
+        );
+    }
+
+    #[test]
+    fn forwards_to_configured_addr() {
+        let mut forward = ForwardAddrs {
+            addr: Some([127, 0, 0, 2].into()),
+            ports: IndexMap::default(),
+        };
+        assert_eq!(
+            forward.forward_addr(orig_dst()),
+            SocketAddr::from(([127, 0, 0, 2], 8080))
+        );
+
+        forward.ports.insert(8080, [10, 1, 2, 3].into());
+        assert_eq!(forward.forward_addr(orig_dst()), orig_dst());
+        assert_eq!(
+            forward.forward_addr(SocketAddr::from(([10, 1, 2, 3], 9090))),
+            SocketAddr::from(([127, 0, 0, 2], 9090))
+        );
+    }
+}
@@ -0,0 +1,387 @@
+//! Shields callers from the application's startup.
+//!
+//! While the application is starting, its socket refuses connections, so
+//! every request proxied to it would fail with a 502. While the shield is
+//! raised, these requests instead fail with a 503 and a `Retry-After` header,
+//! and the proxy reports that it is not ready. The shield is lowered for good
+//! once a connection to the application succeeds; refused connections are
+//! ordinary errors thereafter.
+
+use futures::{try_ready, Future, Poll};
+use http::{header, HeaderValue, Response, StatusCode};
+use linkerd2_app_core::{admin, svc, Error};
+use std::io;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
+use tracing::{debug, info};
+
+/// Tracks whether the application has ever accepted a connection.
+#[derive(Clone, Debug)]
+pub struct Shield(Arc<Inner>);
+
+#[derive(Debug)]
+struct Inner {
+    lowered: AtomicBool,
+    retry_after: HeaderValue,
+    /// Held until the shield is lowered, so that the proxy is not ready.
+    latch: Mutex<Option<admin::Latch>>,
+}
+
+/// Lowers the shield once a connection succeeds.
+#[derive(Clone, Debug)]
+pub struct ConnectLayer(Shield);
+
+#[derive(Clone, Debug)]
+pub struct Connect<C> {
+    inner: C,
+    shield: Shield,
+}
+
+pub struct ConnectFuture<F> {
+    inner: F,
+    shield: Shield,
+}
+
+/// Responds with a 503 when a request fails because the application refused a
+/// connection while the shield is raised.
+#[derive(Clone, Debug)]
+pub struct HttpLayer(Shield);
+
+#[derive(Clone, Debug)]
+pub struct Stack<M> {
+    inner: M,
+    shield: Shield,
+}
+
+pub struct MakeFuture<F> {
+    inner: F,
+    shield: Option<Shield>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Service<S> {
+    inner: S,
+    shield: Shield,
+}
+
+pub struct ResponseFuture<F> {
+    inner: F,
+    shield: Shield,
+}
+
+// === impl Shield ===
+
+impl Shield {
+    /// Raises a shield that holds `latch` until it is lowered.
+    pub fn new(retry_after: Duration, latch: admin::Latch) -> Self {
+        let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        Shield(Arc::new(Inner {
+            lowered: AtomicBool::new(false),
+            retry_after: HeaderValue::from(secs),
+            latch: Mutex::new(Some(latch)),
+        }))
+    }
+
+    /// Returns a shield that is already lowered.
+    pub fn lowered() -> Self {
+        Shield(Arc::new(Inner {
+            lowered: AtomicBool::new(true),
+            retry_after: HeaderValue::from(0u64),
+            latch: Mutex::new(None),
+        }))
+    }
+
+    pub fn connect_layer(&self) -> ConnectLayer {
+        ConnectLayer(self.clone())
+    }
+
+    pub fn http_layer(&self) -> HttpLayer {
+        HttpLayer(self.clone())
+    }
+
+    fn is_raised(&self) -> bool {
+        !self.0.lowered.load(Ordering::Acquire)
+    }
+
+    fn lower(&self) {
+        if !self.0.lowered.swap(true, Ordering::AcqRel) {
+            info!("application accepted a connection; lowering startup shield");
+            if let Ok(mut latch) = self.0.latch.lock() {
+                latch.take();
+            }
+        }
+    }
+
+    fn unavailable<B: Default>(&self) -> Response<B> {
+        Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header(header::RETRY_AFTER, self.0.retry_after.clone())
+            .header(header::CONTENT_LENGTH, "0")
+            .body(B::default())
+            .expect("startup shield response is valid")
+    }
+}
+
+/// Returns true if `error` was caused by a refused connection.
+fn is_connection_refused(error: &(dyn std::error::Error + 'static)) -> bool {
+    let mut cause = Some(error);
+    while let Some(e) = cause {
+        if let Some(e) = e.downcast_ref::<io::Error>() {
+            if e.kind() == io::ErrorKind::ConnectionRefused {
+                return true;
+            }
+        }
+        cause = e.source();
+    }
+    false
+}
+
+// === impl ConnectLayer ===
+
+impl<C> svc::Layer<C> for ConnectLayer {
+    type Service = Connect<C>;
+
+    fn layer(&self, inner: C) -> Self::Service {
+        Connect {
+            inner,
+            shield: self.0.clone(),
+        }
+    }
+}
+
+impl<T, C> svc::Service<T> for Connect<C>
+where
+    C: svc::Service<T>,
+{
+    type Response = C::Response;
+    type Error = C::Error;
+    type Future = ConnectFuture<C::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        ConnectFuture {
+            inner: self.inner.call(target),
+            shield: self.shield.clone(),
+        }
+    }
+}
+
+impl<F: Future> Future for ConnectFuture<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let conn = try_ready!(self.inner.poll());
+        self.shield.lower();
+        Ok(conn.into())
+    }
+}
+
+// === impl HttpLayer ===
+
+impl<M> svc::Layer<M> for HttpLayer {
+    type Service = Stack<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        Stack {
+            inner,
+            shield: self.0.clone(),
+        }
+    }
+}
+
+impl<T, M> svc::Service<T> for Stack<M>
+where
+    M: svc::Service<T>,
+{
+    type Response = Service<M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        MakeFuture {
+            inner: self.inner.call(target),
+            shield: Some(self.shield.clone()),
+        }
+    }
+}
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = Service<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        let shield = self.shield.take().expect("polled after complete");
+        Ok(Service { inner, shield }.into())
+    }
+}
+
+impl<S, A, B> svc::Service<http::Request<A>> for Service<S>
+where
+    S: svc::Service<http::Request<A>, Response = Response<B>>,
+    S::Error: Into<Error>,
+    B: Default,
+{
+    type Response = Response<B>;
+    type Error = Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, req: http::Request<A>) -> Self::Future {
+        ResponseFuture {
+            inner: self.inner.call(req),
+            shield: self.shield.clone(),
+        }
+    }
+}
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = Response<B>>,
+    F::Error: Into<Error>,
+    B: Default,
+{
+    type Item = Response<B>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.inner.poll() {
+            Ok(ready) => Ok(ready),
+            Err(e) => {
+                let error: Error = e.into();
+                if self.shield.is_raised() && is_connection_refused(&*error) {
+                    debug!(%error, "application is not yet accepting connections");
+                    return Ok(self.shield.unavailable().into());
+                }
+                Err(error)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use linkerd2_app_core::svc::{Layer, Service as _};
+    use tokio::runtime::current_thread::Runtime;
+
+    /// Succeeds or fails to connect according to `listening`.
+    #[derive(Clone)]
+    struct App {
+        listening: Arc<AtomicBool>,
+    }
+
+    impl svc::Service<()> for App {
+        type Response = ();
+        type Error = io::Error;
+        type Future = future::FutureResult<(), io::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), io::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, (): ()) -> Self::Future {
+            if self.listening.load(Ordering::SeqCst) {
+                future::ok(())
+            } else {
+                future::err(io::ErrorKind::ConnectionRefused.into())
+            }
+        }
+    }
+
+    /// An HTTP client that connects to the `App` for each request, as the
+    /// HTTP/1 client does when it has no idle connections.
+    struct Client<C>(C);
+
+    impl<C> svc::Service<http::Request<()>> for Client<C>
+    where
+        C: svc::Service<(), Response = (), Error = io::Error>,
+        C::Future: 'static,
+    {
+        type Response = Response<()>;
+        type Error = Error;
+        type Future = Box<dyn Future<Item = Response<()>, Error = Error>>;
+
+        fn poll_ready(&mut self) -> Poll<(), Error> {
+            self.0.poll_ready().map_err(Into::into)
+        }
+
+        fn call(&mut self, _: http::Request<()>) -> Self::Future {
+            let rsp = self
+                .0
+                .call(())
+                .map(|()| Response::new(()))
+                .map_err(Error::from);
+            Box::new(rsp)
+        }
+    }
+
+    fn get() -> http::Request<()> {
+        http::Request::new(())
+    }
+
+    #[test]
+    fn shields_until_the_application_accepts_a_connection() {
+        let mut rt = Runtime::new().unwrap();
+        let (ready, latch) = admin::Readiness::new();
+        let shield = Shield::new(Duration::from_millis(1500), latch);
+        let listening = Arc::new(AtomicBool::new(false));
+
+        let connect = shield.connect_layer().layer(App {
+            listening: listening.clone(),
+        });
+        let mut svc = Service {
+            inner: Client(connect),
+            shield: shield.clone(),
+        };
+
+        let rsp = rt.block_on(svc.call(get())).expect("must respond");
+        assert_eq!(rsp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(rsp.headers().get(header::RETRY_AFTER).unwrap(), "2");
+        assert!(!ready.is_ready(), "must not be ready while shielded");
+
+        listening.store(true, Ordering::SeqCst);
+        let rsp = rt.block_on(svc.call(get())).expect("must respond");
+        assert_eq!(rsp.status(), StatusCode::OK);
+        assert!(ready.is_ready(), "must be ready once connected");
+
+        listening.store(false, Ordering::SeqCst);
+        let err = rt
+            .block_on(svc.call(get()))
+            .expect_err("refusals must fail once the shield is lowered");
+        assert!(is_connection_refused(&*err));
+    }
+
+    #[test]
+    fn does_not_shield_other_errors() {
+        let mut rt = Runtime::new().unwrap();
+        let (_, latch) = admin::Readiness::new();
+        let shield = Shield::new(Duration::from_secs(1), latch);
+
+        let client = svc::mk(|_: http::Request<()>| {
+            future::err::<Response<()>, Error>(io::Error::from(io::ErrorKind::TimedOut).into())
+        });
+        let mut svc = Service {
+            inner: client,
+            shield,
+        };
+
+        rt.block_on(svc.call(get()))
+            .expect_err("timeouts must not be shielded");
+    }
+}
@@ -3,6 +3,7 @@ use futures::future::Either;
 use rustls::ServerConfig;
 use std::collections::HashMap;
 use std::io;
+use std::net::IpAddr;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::thread;
@@ -37,6 +38,7 @@ pub struct Server {
     routes: HashMap<String, Route>,
     version: Run,
     tls: Option<Arc<ServerConfig>>,
+    ip: IpAddr,
 }
 
 pub struct Listening {
@@ -63,6 +65,7 @@ impl Server {
             routes: HashMap::new(),
             version: run,
             tls,
+            ip: [127, 0, 0, 1].into(),
         }
     }
     fn http1() -> Self {
@@ -81,6 +84,12 @@ impl Server {
         Server::new(Run::Http2, Some(tls))
     }
 
+    /// Listens on `ip` rather than on `127.0.0.1`.
+    pub fn ip(mut self, ip: IpAddr) -> Self {
+        self.ip = ip;
+        self
+    }
+
     /// Return a string body as a 200 OK response, with the string as
     /// the response body.
     pub fn route(mut self, path: &str, resp: &str) -> Self {
@@ -145,7 +154,7 @@ impl Server {
         let version = self.version;
         let tname = format!("support {:?} server (test={})", version, thread_name(),);
 
-        let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+        let addr = SocketAddr::from((self.ip, 0));
         let listener = net2::TcpBuilder::new_v4().expect("Tcp::new_v4");
         listener.bind(addr).expect("Tcp::bind");
         let addr = listener.local_addr().expect("Tcp::local_addr");
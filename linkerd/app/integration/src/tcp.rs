@@ -2,7 +2,7 @@ use super::*;
 use futures::sync::{mpsc, oneshot};
 use std::collections::VecDeque;
 use std::io;
-use std::net::TcpListener as StdTcpListener;
+use std::net::{IpAddr, TcpListener as StdTcpListener};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use tokio::net::TcpStream;
@@ -21,6 +21,7 @@ pub fn client(addr: SocketAddr) -> TcpClient {
 pub fn server() -> TcpServer {
     TcpServer {
         accepts: VecDeque::new(),
+        ip: [127, 0, 0, 1].into(),
     }
 }
 
@@ -45,6 +46,7 @@ impl<F: FnOnce(TcpStream) -> Box<dyn Future<Item = (), Error = ()>> + Send + 'st
 
 pub struct TcpServer {
     accepts: VecDeque<Handler>,
+    ip: IpAddr,
 }
 
 pub struct TcpConn {
@@ -69,6 +71,12 @@ impl TcpClient {
 }
 
 impl TcpServer {
+    /// Listens on `ip` rather than on `127.0.0.1`.
+    pub fn ip(mut self, ip: IpAddr) -> Self {
+        self.ip = ip;
+        self
+    }
+
     pub fn accept<F, U>(self, cb: F) -> Self
     where
         F: FnOnce(Vec<u8>) -> U + Send + 'static,
@@ -225,7 +233,7 @@ fn run_server(tcp: TcpServer) -> server::Listening {
     let (started_tx, started_rx) = oneshot::channel();
     let conn_count = Arc::new(AtomicUsize::from(0));
     let srv_conn_count = Arc::clone(&conn_count);
-    let any_port = SocketAddr::from(([127, 0, 0, 1], 0));
+    let any_port = SocketAddr::from((tcp.ip, 0));
     let std_listener = StdTcpListener::bind(&any_port).expect("bind");
     let addr = std_listener.local_addr().expect("local_addr");
     let tname = format!("support tcp server (addr={})", addr);
@@ -373,6 +373,117 @@ macro_rules! generate_tests {
             }
         }
 
+        mod label_headers {
+            use super::super::*;
+
+            const ZONE_HEADER: &'static str = "x-dst-zone";
+            const DST: &'static str = "labeled.test.svc.cluster.local";
+
+            /// Responds with the server's own zone and the value of the zone
+            /// header that it received.
+            fn zone_server(zone: &'static str) -> server::Listening {
+                $make_server()
+                    .route_fn("/", move |req| {
+                        let header = req
+                            .headers()
+                            .get(ZONE_HEADER)
+                            .and_then(|v| v.to_str().ok())
+                            .unwrap_or("none");
+                        Response::builder()
+                            .status(200)
+                            .body(Bytes::from(format!("{} {}", zone, header)))
+                            .unwrap()
+                    })
+                    .run()
+            }
+
+            fn run_proxy(
+                srvs: &[(&server::Listening, &str)],
+                env: TestEnv,
+            ) -> (proxy::Listening, controller::DstSender) {
+                let ctrl = controller::new();
+                let dst = ctrl.destination_tx(DST);
+                for (srv, zone) in srvs {
+                    let mut labels = HashMap::new();
+                    labels.insert("zone".to_owned(), zone.to_string());
+                    dst.send_labeled(srv.addr, labels, HashMap::new());
+                }
+                let proxy = proxy::new().controller(ctrl.run()).run_with_test_env(env);
+                (proxy, dst)
+            }
+
+            fn get_with_zone(client: &client::Client, zone: &str) -> String {
+                let rsp = client.request(
+                    client.request_builder("/").header(ZONE_HEADER, zone),
+                );
+                assert_eq!(rsp.status(), 200);
+                let body = rsp.into_body().concat2().wait().expect("response body");
+                String::from_utf8(body.to_vec()).expect("response body must be UTF-8")
+            }
+
+            #[test]
+            fn sets_header_from_chosen_endpoint() {
+                let _ = trace_init();
+                let east = zone_server("east");
+                let west = zone_server("west");
+
+                let mut env = TestEnv::new();
+                env.put(
+                    app::env::ENV_OUTBOUND_LABEL_HEADERS,
+                    format!("zone={}", ZONE_HEADER),
+                );
+                let (proxy, _dst) = run_proxy(&[(&east, "east"), (&west, "west")], env);
+                let client = $make_client(proxy.outbound, DST);
+
+                for _ in 0..10 {
+                    let rsp = client.get("/");
+                    let mut parts = rsp.split(' ');
+                    let (zone, header) = (parts.next(), parts.next());
+                    assert!(zone.is_some());
+                    assert_eq!(
+                        zone, header,
+                        "header must match the chosen endpoint's label"
+                    );
+                }
+            }
+
+            #[test]
+            fn preserves_client_header_by_default() {
+                let _ = trace_init();
+                let east = zone_server("east");
+
+                let mut env = TestEnv::new();
+                env.put(
+                    app::env::ENV_OUTBOUND_LABEL_HEADERS,
+                    format!("zone={}", ZONE_HEADER),
+                );
+                let (proxy, _dst) = run_proxy(&[(&east, "east")], env);
+                let client = $make_client(proxy.outbound, DST);
+
+                assert_eq!(get_with_zone(&client, "client"), "east client");
+            }
+
+            #[test]
+            fn overwrites_client_header_if_configured() {
+                let _ = trace_init();
+                let east = zone_server("east");
+
+                let mut env = TestEnv::new();
+                env.put(
+                    app::env::ENV_OUTBOUND_LABEL_HEADERS,
+                    format!("zone={}", ZONE_HEADER),
+                );
+                env.put(
+                    app::env::ENV_OUTBOUND_LABEL_HEADERS_OVERWRITE,
+                    "true".to_owned(),
+                );
+                let (proxy, _dst) = run_proxy(&[(&east, "east")], env);
+                let client = $make_client(proxy.outbound, DST);
+
+                assert_eq!(get_with_zone(&client, "client"), "east east");
+            }
+        }
+
         mod override_header {
             use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
             use super::super::*;
@@ -191,6 +191,39 @@ fn http2_outbound_tls_works_before_identity_is_certified() {
     }
 }
 
+#[test]
+fn outbound_originates_tls_to_unresolvable_external_names() {
+    let _ = trace_init();
+
+    // The server is outside of the mesh, so its name is never resolved by
+    // the destination service and requests fall back to the original
+    // destination address.
+    let server_name = "bar.ns1.serviceaccount.identity.linkerd.cluster.local";
+    let server_identity = identity::Identity::new("bar-ns1", server_name.to_string());
+    let srv = server::http1_tls(server_identity.server_config)
+        .route("/", "hello over tls")
+        .run();
+
+    let mut env = TestEnv::new();
+    env.put(
+        app::env::ENV_OUTBOUND_TLS_ORIGINATION,
+        format!(
+            "external.example.com;ca={}/src/data/ca1.pem;sni={}",
+            env!("CARGO_MANIFEST_DIR"),
+            server_name
+        ),
+    );
+    let proxy = proxy::new()
+        .controller(controller::new().no_more_destinations().run())
+        .outbound(srv)
+        .run_with_test_env(env);
+
+    // The application sends plaintext, which the server would reject unless
+    // the proxy originated TLS to it.
+    let client = client::http1(proxy.outbound, "api.external.example.com");
+    assert_eq!(client.get("/"), "hello over tls");
+}
+
 #[test]
 fn ready() {
     let _ = trace_init();
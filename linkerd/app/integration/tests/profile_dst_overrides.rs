@@ -14,6 +14,15 @@ struct Service {
 
 impl Service {
     fn new(name: &'static str) -> Self {
+        Self::with_status(name, 200)
+    }
+
+    /// Returns a service that fails every request to `/`.
+    fn failing(name: &'static str) -> Self {
+        Self::with_status(name, 500)
+    }
+
+    fn with_status(name: &'static str, status: u16) -> Self {
         let response_counter = Arc::new(AtomicUsize::new(0));
         let counter = response_counter.clone();
         let svc = server::http1()
@@ -22,7 +31,10 @@ impl Service {
             })
             .route_fn("/", move |_req| {
                 counter.fetch_add(1, Ordering::SeqCst);
-                Response::builder().status(200).body(name.into()).unwrap()
+                Response::builder()
+                    .status(status)
+                    .body(name.into())
+                    .unwrap()
             })
             .run();
         Service {
@@ -333,3 +345,70 @@ fn remove_a_dst_override() {
     assert_eq!(apex_svc.response_counter.load(Ordering::SeqCst), n);
     assert_eq!(leaf_svc.response_counter.load(Ordering::SeqCst), 0);
 }
+
+#[test]
+fn route_responses_are_counted_per_split_backend() {
+    let _ = trace_init();
+    let ctrl = controller::new_unordered();
+
+    let apex = "apex";
+    let apex_svc = Service::new(apex);
+    let ctrl = ctrl.destination_and_close(&apex_svc.authority(), apex_svc.svc.addr);
+
+    let leaf_a = "leaf-a";
+    let leaf_a_svc = Service::new(leaf_a);
+    let ctrl = ctrl.destination_and_close(&leaf_a_svc.authority(), leaf_a_svc.svc.addr);
+    let leaf_b = "leaf-b";
+    let leaf_b_svc = Service::failing(leaf_b);
+    let ctrl = ctrl.destination_and_close(&leaf_b_svc.authority(), leaf_b_svc.svc.addr);
+
+    let profile_tx = ctrl.profile_tx(&apex_svc.authority());
+
+    let ctrl = ctrl.run();
+    let proxy = proxy::new().controller(ctrl).run();
+
+    let client = client::http1(proxy.outbound, apex_svc.authority());
+    let metrics = client::http1(proxy.metrics, "localhost");
+
+    // 1. Split traffic evenly between a healthy and a failing backend.
+    profile_tx.send(profile(
+        "split",
+        vec![
+            controller::dst_override(leaf_a_svc.authority(), 5000),
+            controller::dst_override(leaf_b_svc.authority(), 5000),
+        ],
+    ));
+    wait_for_profile_stage(&client, &metrics, "split");
+
+    // 2. Send `n` requests on the same route.
+    let n = 100;
+    for _ in 0..n {
+        let rsp = client.request(&mut client.request_builder("/"));
+        assert!(rsp.status() == 200 || rsp.status() == 500);
+    }
+    let a = leaf_a_svc.response_counter.load(Ordering::SeqCst);
+    let b = leaf_b_svc.response_counter.load(Ordering::SeqCst);
+    assert_eq!(a + b, n);
+    assert!(a > 0 && b > 0, "both backends must be used");
+
+    // 3. Each backend's responses are counted separately.
+    let labels = |leaf: &Service, class: &str| {
+        format!(
+            "route_backend_response_total{{direction=\"outbound\",dst=\"{}\",backend=\"{}\",classification=\"{}\"}}",
+            apex_svc.authority(),
+            leaf.authority(),
+            class,
+        )
+    };
+    assert_eventually_contains!(
+        metrics.get("/metrics"),
+        &format!("{} {}", labels(&leaf_a_svc, "success"), a)
+    );
+    assert_eventually_contains!(
+        metrics.get("/metrics"),
+        &format!("{} {}", labels(&leaf_b_svc, "failure"), b)
+    );
+    let scrape = metrics.get("/metrics");
+    assert!(!scrape.contains(&labels(&leaf_a_svc, "failure")));
+    assert!(!scrape.contains(&labels(&leaf_b_svc, "success")));
+}
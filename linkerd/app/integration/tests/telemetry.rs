@@ -9,6 +9,8 @@
 use bytes::IntoBuf;
 use linkerd2_app_integration::*;
 use std::io::Read;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 
 struct Fixture {
     client: client::Client,
@@ -123,6 +125,43 @@ fn metrics_endpoint_inbound_request_count() {
     assert_eventually_contains!(metrics.get("/metrics"), "request_total{authority=\"tele.test.svc.cluster.local\",direction=\"inbound\",tls=\"disabled\"} 1");
 }
 
+#[test]
+fn metrics_endpoint_is_served_while_the_proxy_runtime_is_saturated() {
+    let _ = trace_init();
+
+    // The shutdown signal is first polled on the proxy's main runtime once the
+    // proxy is running, so it spawns a task there that occupies the runtime's
+    // only thread, starving every other task until the scrape completes.
+    let saturated = Arc::new(AtomicBool::new(true));
+    let saturate = {
+        let saturated = saturated.clone();
+        future::lazy(move || {
+            tokio::spawn(future::lazy(move || {
+                let deadline = Instant::now() + Duration::from_secs(10);
+                while saturated.load(Ordering::Acquire) && Instant::now() < deadline {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                Ok(())
+            }));
+            future::empty::<(), ()>()
+        })
+    };
+    let proxy = proxy::new().shutdown_signal(saturate).run();
+    let metrics = client::http1(proxy.metrics, "localhost");
+
+    let start = Instant::now();
+    let scrape = metrics.get("/metrics");
+    let elapsed = start.elapsed();
+    saturated.store(false, Ordering::Release);
+
+    assert!(scrape.contains("process_start_time_seconds"));
+    assert!(
+        elapsed < Duration::from_secs(2),
+        "scrape took {:?} while the proxy runtime was saturated",
+        elapsed
+    );
+}
+
 #[test]
 fn metrics_endpoint_outbound_request_count() {
     let _ = trace_init();
@@ -143,6 +182,56 @@ fn metrics_endpoint_outbound_request_count() {
     assert_eventually_contains!(metrics.get("/metrics"), "request_total{authority=\"tele.test.svc.cluster.local\",direction=\"outbound\",tls=\"no_identity\",no_tls_reason=\"not_provided_by_service_discovery\"} 1");
 }
 
+#[test]
+fn metrics_endpoint_inbound_stack_stage_durations() {
+    let _ = trace_init();
+    let srv = server::new().route("/", "hello").run();
+    let mut env = TestEnv::new();
+    env.put(app::env::ENV_METRICS_STACK_STAGE_SAMPLE, "1".into());
+    let proxy = proxy::new().inbound(srv).run_with_test_env(env);
+    let client = client::new(proxy.inbound, "tele.test.svc.cluster.local");
+    let metrics = client::http1(proxy.metrics, "localhost");
+
+    assert_eq!(client.get("/"), "hello");
+    for stage in &["server", "logical", "profile", "endpoint"] {
+        assert_eventually_contains!(
+            metrics.get("/metrics"),
+            &format!(
+                "stack_stage_duration_us_count{{stage=\"inbound.{}\"}} 1",
+                stage
+            )
+        );
+    }
+}
+
+#[test]
+fn metrics_endpoint_outbound_stack_stage_durations() {
+    let _ = trace_init();
+    let srv = server::new().route("/", "hello").run();
+    let ctrl = controller::new()
+        .destination_and_close("tele.test.svc.cluster.local", srv.addr)
+        .run();
+    let mut env = TestEnv::new();
+    env.put(app::env::ENV_METRICS_STACK_STAGE_SAMPLE, "1".into());
+    let proxy = proxy::new()
+        .controller(ctrl)
+        .outbound(srv)
+        .run_with_test_env(env);
+    let client = client::new(proxy.outbound, "tele.test.svc.cluster.local");
+    let metrics = client::http1(proxy.metrics, "localhost");
+
+    assert_eq!(client.get("/"), "hello");
+    for stage in &["server", "logical", "profile", "concrete", "endpoint"] {
+        assert_eventually_contains!(
+            metrics.get("/metrics"),
+            &format!(
+                "stack_stage_duration_us_count{{stage=\"outbound.{}\"}} 1",
+                stage
+            )
+        );
+    }
+}
+
 mod response_classification {
     use super::Fixture;
     use linkerd2_app_integration::*;
@@ -771,6 +860,51 @@ mod transport {
         );
     }
 
+    #[test]
+    fn inbound_http1_max_requests_per_connection() {
+        let _ = trace_init();
+        let srv = server::http1().route("/", "hello").run();
+        let mut env = TestEnv::new();
+        env.put(app::env::ENV_SERVER_MAX_REQUESTS_PER_CONNECTION, "3".into());
+        let proxy = proxy::new().inbound(srv).run_with_test_env(env);
+        let client = client::http1(proxy.inbound, "tele.test.svc.cluster.local");
+        let metrics = client::http1(proxy.metrics, "localhost");
+
+        for _ in 0..3 {
+            assert_eq!(client.get("/"), "hello");
+        }
+        assert_eventually_contains!(
+            metrics.get("/metrics"),
+            "tcp_open_total{direction=\"inbound\",peer=\"src\",tls=\"disabled\"} 1"
+        );
+        // The third response closes the connection.
+        assert_eventually_contains!(metrics.get("/metrics"), "close_reason=\"max_requests\"} 1");
+
+        // The remaining requests are served on a new connection.
+        for _ in 0..2 {
+            assert_eq!(client.get("/"), "hello");
+        }
+        assert_eventually_contains!(
+            metrics.get("/metrics"),
+            "tcp_open_total{direction=\"inbound\",peer=\"src\",tls=\"disabled\"} 2"
+        );
+    }
+
+    #[test]
+    fn inbound_accept_idle_timeout() {
+        let _ = trace_init();
+        let srv = server::http1().route("/", "hello").run();
+        let mut env = TestEnv::new();
+        env.put(app::env::ENV_SERVER_ACCEPT_IDLE_TIMEOUT, "100ms".into());
+        let proxy = proxy::new().inbound(srv).run_with_test_env(env);
+        let client = tcp::client(proxy.inbound);
+        let metrics = client::http1(proxy.metrics, "localhost");
+
+        // The client never sends anything, so the proxy closes the connection.
+        let _conn = client.connect();
+        assert_eventually_contains!(metrics.get("/metrics"), "close_reason=\"idle_timeout\"} 1");
+    }
+
     #[test]
     fn inbound_http_connect() {
         let _ = trace_init();
@@ -54,6 +54,37 @@ fn outbound_tcp() {
     assert_eq!(tcp_client.read(), msg2.as_bytes());
 }
 
+#[test]
+fn outbound_tcp_tls_client_hello() {
+    let _ = trace_init();
+
+    // From `cargo run --example tlsclient -- --http example.com`
+    let hello: &'static [u8] =
+        include_bytes!("../../../proxy/transport/src/tls/testdata/example-com-client-hello.bin");
+    let msg2 = "custom tls bye";
+
+    let srv = server::tcp()
+        .accept(move |read| {
+            assert_eq!(read, hello);
+            msg2
+        })
+        .run();
+    let proxy = proxy::new().outbound(srv).run();
+
+    let client = client::tcp(proxy.outbound);
+
+    let tcp_client = client.connect();
+
+    tcp_client.write(hello);
+    assert_eq!(tcp_client.read(), msg2.as_bytes());
+
+    let metrics = client::http1(proxy.metrics, "localhost");
+    assert_eventually_contains!(
+        metrics.get("/metrics"),
+        "tcp_tls_passthrough_total{direction=\"outbound\"} 1"
+    );
+}
+
 #[test]
 fn inbound_tcp() {
     let _ = trace_init();
@@ -77,6 +108,48 @@ fn inbound_tcp() {
     assert_eq!(tcp_client.read(), msg2.as_bytes());
 }
 
+#[test]
+fn inbound_http1_forward_addr() {
+    let _ = trace_init();
+
+    let srv = server::http1()
+        .ip([127, 0, 0, 2].into())
+        .route("/", "hello h1")
+        .run();
+    let mut env = TestEnv::new();
+    env.put(app::env::ENV_INBOUND_FORWARD_ADDR, "127.0.0.2".to_owned());
+    let proxy = proxy::new().inbound(srv).run_with_test_env(env);
+    let client = client::http1(proxy.inbound, "transparency.test.svc.cluster.local");
+
+    assert_eq!(client.get("/"), "hello h1");
+}
+
+#[test]
+fn inbound_tcp_forward_addr() {
+    let _ = trace_init();
+
+    let msg1 = "custom tcp hello";
+    let msg2 = "custom tcp bye";
+
+    let srv = server::tcp()
+        .ip([127, 0, 0, 2].into())
+        .accept(move |read| {
+            assert_eq!(read, msg1.as_bytes());
+            msg2
+        })
+        .run();
+    let mut env = TestEnv::new();
+    env.put(app::env::ENV_INBOUND_FORWARD_ADDR, "127.0.0.2".to_owned());
+    let proxy = proxy::new().inbound(srv).run_with_test_env(env);
+
+    let client = client::tcp(proxy.inbound);
+
+    let tcp_client = client.connect();
+
+    tcp_client.write(msg1);
+    assert_eq!(tcp_client.read(), msg2.as_bytes());
+}
+
 fn test_server_speaks_first(env: TestEnv) {
     const TIMEOUT: Duration = Duration::from_secs(5);
 
@@ -277,6 +350,79 @@ macro_rules! http1_tests {
             assert!(!res.headers().contains_key("x-server-quux"));
         }
 
+        #[test]
+        fn http1_removes_hop_by_hop_headers() {
+            let _ = trace_init();
+
+            // Each of these is hop-by-hop, whether or not it's nominated by
+            // a `Connection` header. `Upgrade` is only tested on responses,
+            // since requests that set it are proxied as upgrades.
+            let hop_by_hop = &[
+                "keep-alive",
+                "proxy-connection",
+                "proxy-authenticate",
+                "proxy-authorization",
+                "te",
+                "trailer",
+            ];
+
+            let srv = server::http1()
+                .route_fn("/", move |req| {
+                    let stripped = hop_by_hop
+                        .iter()
+                        .chain(&["connection", "x-client-hop"])
+                        .all(|name| !req.headers().contains_key(*name));
+                    let e2e = req
+                        .headers()
+                        .get("x-client-e2e")
+                        .map_or(false, |v| v == "1");
+                    let status = if stripped && e2e {
+                        StatusCode::OK
+                    } else {
+                        StatusCode::BAD_REQUEST
+                    };
+                    let mut rsp = Response::builder();
+                    rsp.status(status)
+                        .header("connection", "x-server-hop")
+                        .header("x-server-hop", "1")
+                        .header("upgrade", "foo/2")
+                        .header("x-server-e2e", "1");
+                    for name in hop_by_hop {
+                        rsp.header(*name, "1");
+                    }
+                    rsp.body("".into()).unwrap()
+                })
+                .run();
+            let proxy = $proxy(srv);
+            let client = client::http1(proxy.inbound, "transparency.test.svc.cluster.local");
+
+            let mut req = client.request_builder("/");
+            req.header("connection", "x-client-hop")
+                .header("x-client-hop", "1")
+                .header("x-client-e2e", "1");
+            for name in hop_by_hop {
+                req.header(*name, "1");
+            }
+            let res = client.request(&mut req);
+
+            assert_eq!(
+                res.status(),
+                StatusCode::OK,
+                "request headers must be stripped"
+            );
+            for name in hop_by_hop
+                .iter()
+                .chain(&["connection", "upgrade", "x-server-hop"])
+            {
+                assert!(
+                    !res.headers().contains_key(*name),
+                    "{} must be stripped",
+                    name
+                );
+            }
+            assert_eq!(res.headers()["x-server-e2e"], "1");
+        }
+
         #[test]
         fn http10_with_host() {
             let _ = trace_init();
@@ -306,6 +452,97 @@ macro_rules! http1_tests {
             assert_eq!(res.version(), http::Version::HTTP_10);
         }
 
+        #[test]
+        fn http10_response_is_framed_for_client() {
+            let _ = trace_init();
+
+            let host = "transparency.test.svc.cluster.local";
+            let srv = server::http1()
+                .route_fn("/", move |req| {
+                    assert_eq!(req.version(), http::Version::HTTP_10);
+                    Response::builder().body("hello".into()).unwrap()
+                })
+                .run();
+            let proxy = $proxy(srv);
+            let client = client::tcp(proxy.inbound);
+
+            let tcp_client = client.connect();
+            tcp_client.write(format!("GET / HTTP/1.0\r\nHost: {}\r\n\r\n", host));
+
+            let rsp = s(&tcp_client.read()).to_ascii_lowercase();
+            assert!(rsp.starts_with("http/1.0 200 ok\r\n"), "{}", rsp);
+            assert!(!rsp.contains("transfer-encoding"), "{}", rsp);
+            assert!(rsp.contains("content-length: 5\r\n"), "{}", rsp);
+        }
+
+        #[test]
+        fn http11_te_trailers_is_preserved() {
+            let _ = trace_init();
+
+            let host = "transparency.test.svc.cluster.local";
+            let srv = server::http1()
+                .route_fn("/", move |req| {
+                    assert_eq!(req.headers()["te"], "trailers");
+                    Response::new("".into())
+                })
+                .route_fn("/no-trailers", move |req| {
+                    assert!(!req.headers().contains_key("te"));
+                    Response::new("".into())
+                })
+                .run();
+            let proxy = $proxy(srv);
+            let client = client::http1(proxy.inbound, host);
+
+            let res = client.request(
+                client
+                    .request_builder("/")
+                    .header("te", "trailers")
+                    .header("host", host),
+            );
+            assert_eq!(res.status(), http::StatusCode::OK);
+
+            let res = client.request(client.request_builder("/no-trailers").header("host", host));
+            assert_eq!(res.status(), http::StatusCode::OK);
+        }
+
+        #[test]
+        fn http11_expect_continue_is_answered_locally() {
+            let _ = trace_init();
+
+            let srv = server::http1()
+                .route_async("/", |req| {
+                    assert!(!req.headers().contains_key("expect"));
+                    req.into_body()
+                        .concat2()
+                        .map_err(|()| "req concat error")
+                        .map(|body| {
+                            assert_eq!(body, "hello");
+                            Response::new("".into())
+                        })
+                })
+                .run();
+            let proxy = $proxy(srv);
+
+            let client = client::tcp(proxy.inbound);
+            let tcp_client = client.connect();
+
+            tcp_client.write(
+                "\
+                 POST / HTTP/1.1\r\n\
+                 Host: transparency.test.svc.cluster.local\r\n\
+                 Expect: 100-continue\r\n\
+                 Content-Length: 5\r\n\
+                 \r\n\
+                 ",
+            );
+            let expected = "HTTP/1.1 100 Continue\r\n\r\n";
+            assert_eq!(s(&tcp_client.read()), expected);
+
+            tcp_client.write("hello");
+            let expected = "HTTP/1.1 200 OK\r\n";
+            assert_eq!(s(&tcp_client.read()[..expected.len()]), expected);
+        }
+
         #[test]
         fn http11_absolute_uri_differs_from_host() {
             let _ = trace_init();
@@ -926,6 +1163,64 @@ mod proxy_to_proxy {
     } }
 }
 
+#[test]
+fn http1_proxy_to_proxy_sanitizes_response_headers() {
+    let _ = trace_init();
+
+    let srv = server::http1()
+        .route_fn("/", |_| {
+            Response::builder()
+                .header("connection", "x-hop")
+                .header("x-hop", "1")
+                .header("keep-alive", "timeout=5")
+                .header("upgrade", "foo/2")
+                .header("x-inbound-debug", "1")
+                .header("x-outbound-debug", "1")
+                .header("x-normal", "1")
+                .body("".into())
+                .unwrap()
+        })
+        .run();
+
+    let mut env = TestEnv::new();
+    env.put(
+        app::env::ENV_INBOUND_STRIP_RESPONSE_HEADERS,
+        "x-inbound-debug".to_owned(),
+    );
+    let inbound = proxy::new().inbound(srv).run_with_test_env(env);
+
+    let ctrl = controller::new();
+    let dst = ctrl.destination_tx("transparency.test.svc.cluster.local");
+    dst.send_h2_hinted(inbound.inbound);
+
+    let mut env = TestEnv::new();
+    env.put(
+        app::env::ENV_OUTBOUND_STRIP_RESPONSE_HEADERS,
+        "x-outbound-debug".to_owned(),
+    );
+    let outbound = proxy::new().controller(ctrl.run()).run_with_test_env(env);
+
+    let client = client::http1(outbound.outbound, "transparency.test.svc.cluster.local");
+    let res = client.request(&mut client.request_builder("/"));
+
+    assert_eq!(res.status(), http::StatusCode::OK);
+    for name in &[
+        "connection",
+        "x-hop",
+        "keep-alive",
+        "upgrade",
+        "x-inbound-debug",
+        "x-outbound-debug",
+    ] {
+        assert!(
+            !res.headers().contains_key(*name),
+            "{} must be stripped",
+            name
+        );
+    }
+    assert_eq!(res.headers()["x-normal"], "1");
+}
+
 #[test]
 fn http10_without_host() {
     // Without a host or authority, there's no way to route this test,
@@ -0,0 +1,571 @@
+//! Dispatches requests that are pinned by an affinity cookie to the endpoint
+//! the cookie names, rather than to the balancer.
+//!
+//! A cookie only pins a request to an endpoint that is currently resolved for
+//! the request's concrete destination, so that a client can't use it to reach
+//! arbitrary addresses. Requests whose cookie names any other address are
+//! balanced as usual.
+//!
+//! For debugging and canary pinning, a pin header may also be configured. A
+//! request carrying it is pinned to the endpoint it names, taking precedence
+//! over its cookie, if that endpoint is resolved and isn't draining.
+
+use crate::Endpoint;
+use futures::{try_ready, Async, Future, Poll};
+use indexmap::IndexMap;
+use linkerd2_app_core::{
+    dst::DstAddr,
+    proxy::{core::resolve, discover::overrides::Overrides, http::affinity},
+    svc, Error,
+};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, Weak};
+use tracing::debug;
+
+type Active = Arc<Mutex<IndexMap<SocketAddr, Endpoint>>>;
+
+/// The endpoints that are currently resolved for each concrete destination.
+#[derive(Clone, Debug, Default)]
+pub struct Endpoints(Arc<Mutex<IndexMap<DstAddr, Weak<Mutex<IndexMap<SocketAddr, Endpoint>>>>>>);
+
+/// Records each resolution's endpoints in `Endpoints`.
+#[derive(Clone, Debug)]
+pub struct Resolve<R> {
+    inner: R,
+    endpoints: Endpoints,
+}
+
+pub struct ResolveFuture<F> {
+    inner: F,
+    target: Option<DstAddr>,
+    endpoints: Endpoints,
+}
+
+pub struct Resolution<R> {
+    inner: R,
+    active: Active,
+}
+
+/// A request extension holding the endpoint to which a request is pinned.
+#[derive(Clone, Debug)]
+struct Pinned(Endpoint);
+
+/// Pins requests to the endpoint that a header names, unless the endpoint
+/// has been overridden as draining.
+#[derive(Clone, Debug)]
+pub struct PinHeader {
+    name: http::header::HeaderName,
+    overrides: Overrides,
+}
+
+/// Builds a `balanced` service and a `pinned` service for each destination,
+/// dispatching requests to the latter only when they are pinned to one of the
+/// destination's endpoints.
+#[derive(Clone, Debug)]
+pub struct Layer<A, B> {
+    balanced: A,
+    pinned: B,
+    endpoints: Endpoints,
+    pin_header: Option<PinHeader>,
+}
+
+#[derive(Clone, Debug)]
+pub struct MakeSvc<A, B> {
+    balanced: A,
+    pinned: B,
+    endpoints: Endpoints,
+    pin_header: Option<PinHeader>,
+}
+
+pub struct MakeFuture<A, B: Future> {
+    balanced: A,
+    pinned: B,
+    pinned_svc: Option<B::Item>,
+    target: Option<DstAddr>,
+    endpoints: Endpoints,
+    pin_header: Option<PinHeader>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Service<A, B> {
+    balanced: A,
+    pinned: B,
+    target: DstAddr,
+    endpoints: Endpoints,
+    pin_header: Option<PinHeader>,
+}
+
+pub enum ResponseFuture<A, B> {
+    Balanced(A),
+    Pinned(B),
+}
+
+pub fn layer<A, B>(balanced: A, pinned: B, endpoints: Endpoints) -> Layer<A, B> {
+    Layer {
+        balanced,
+        pinned,
+        endpoints,
+        pin_header: None,
+    }
+}
+
+/// Returns the endpoint to which a request has been pinned.
+///
+/// Used to route pinned requests over the endpoint stack.
+pub fn pinned_endpoint<B>(req: &http::Request<B>) -> Option<Endpoint> {
+    req.extensions()
+        .get::<Pinned>()
+        .map(|Pinned(ep)| ep.clone())
+}
+
+// === impl PinHeader ===
+
+impl PinHeader {
+    pub fn new(name: http::header::HeaderName, overrides: Overrides) -> Self {
+        Self { name, overrides }
+    }
+
+    /// Returns the endpoint named by the request's pin header, if it's
+    /// resolved for `target` and isn't draining.
+    ///
+    /// The header's value must be a socket address, i.e. `IP:PORT`.
+    fn pinned<B>(
+        &self,
+        req: &http::Request<B>,
+        target: &DstAddr,
+        endpoints: &Endpoints,
+    ) -> Option<Endpoint> {
+        let value = req.headers().get(&self.name)?;
+        let addr = match value.to_str().ok().and_then(|v| v.trim().parse().ok()) {
+            Some(addr) => addr,
+            None => {
+                debug!(header = %self.name, ?value, "ignoring invalid pin header");
+                return None;
+            }
+        };
+
+        let ep = endpoints.get(target, addr);
+        if ep.is_none() {
+            debug!(header = %self.name, %addr, "ignoring pin header for unresolved endpoint");
+            return None;
+        }
+        if self.overrides.is_draining(&target.to_string(), addr) {
+            debug!(header = %self.name, %addr, "ignoring pin header for draining endpoint");
+            return None;
+        }
+        ep
+    }
+}
+
+// === impl Endpoints ===
+
+impl Endpoints {
+    /// Returns the endpoint at `addr`, if it's currently resolved for
+    /// `target`.
+    fn get(&self, target: &DstAddr, addr: SocketAddr) -> Option<Endpoint> {
+        let active = self.0.lock().ok()?.get(target)?.upgrade()?;
+        let eps = active.lock().ok()?;
+        eps.get(&addr).cloned()
+    }
+
+    /// Publishes a new resolution's endpoints for `target`, replacing those of
+    /// any previous resolution.
+    fn register(&self, target: DstAddr) -> Active {
+        let active = Active::default();
+        if let Ok(mut targets) = self.0.lock() {
+            targets.retain(|_, active| active.upgrade().is_some());
+            targets.insert(target, Arc::downgrade(&active));
+        }
+        active
+    }
+}
+
+// === impl Resolve ===
+
+impl<R> Resolve<R> {
+    pub fn new(inner: R, endpoints: Endpoints) -> Self {
+        Self { inner, endpoints }
+    }
+}
+
+impl<R> tower::Service<DstAddr> for Resolve<R>
+where
+    R: resolve::Resolve<DstAddr, Endpoint = Endpoint>,
+{
+    type Response = Resolution<R::Resolution>;
+    type Error = R::Error;
+    type Future = ResolveFuture<R::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: DstAddr) -> Self::Future {
+        ResolveFuture {
+            target: Some(target.clone()),
+            inner: self.inner.resolve(target),
+            endpoints: self.endpoints.clone(),
+        }
+    }
+}
+
+// === impl ResolveFuture ===
+
+impl<F> Future for ResolveFuture<F>
+where
+    F: Future,
+    F::Item: resolve::Resolution<Endpoint = Endpoint>,
+{
+    type Item = Resolution<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        let target = self.target.take().expect("polled after ready");
+        Ok(Async::Ready(Resolution {
+            inner,
+            active: self.endpoints.register(target),
+        }))
+    }
+}
+
+// === impl Resolution ===
+
+impl<R: resolve::Resolution<Endpoint = Endpoint>> Resolution<R> {
+    fn record(&self, update: &resolve::Update<Endpoint>) {
+        let mut active = match self.active.lock() {
+            Ok(active) => active,
+            Err(_) => return,
+        };
+        match update {
+            resolve::Update::Add(eps) => {
+                active.extend(eps.iter().cloned());
+            }
+            resolve::Update::Remove(addrs) => {
+                for addr in addrs.iter() {
+                    active.remove(addr);
+                }
+            }
+            resolve::Update::Reset(eps) => {
+                active.clear();
+                active.extend(eps.iter().cloned());
+            }
+            resolve::Update::Empty | resolve::Update::DoesNotExist => {
+                active.clear();
+            }
+        }
+    }
+}
+
+impl<R: resolve::Resolution<Endpoint = Endpoint>> resolve::Resolution for Resolution<R> {
+    type Endpoint = Endpoint;
+    type Error = R::Error;
+
+    fn poll(&mut self) -> Poll<resolve::Update<Self::Endpoint>, Self::Error> {
+        let update = try_ready!(self.inner.poll());
+        self.record(&update);
+        Ok(Async::Ready(update))
+    }
+
+    fn poll_tagged(&mut self) -> Poll<resolve::Tagged<Self::Endpoint>, Self::Error> {
+        let tagged = try_ready!(self.inner.poll_tagged());
+        self.record(&tagged.update);
+        Ok(Async::Ready(tagged))
+    }
+}
+
+// === impl Layer ===
+
+impl<A, B> Layer<A, B> {
+    /// Pins requests that carry the header to the endpoint it names.
+    pub fn with_pin_header(self, pin_header: Option<PinHeader>) -> Self {
+        Self { pin_header, ..self }
+    }
+}
+
+impl<A, B, M> svc::Layer<M> for Layer<A, B>
+where
+    A: svc::Layer<M>,
+    B: svc::Layer<M>,
+    M: Clone,
+{
+    type Service = MakeSvc<A::Service, B::Service>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        MakeSvc {
+            balanced: self.balanced.layer(inner.clone()),
+            pinned: self.pinned.layer(inner),
+            endpoints: self.endpoints.clone(),
+            pin_header: self.pin_header.clone(),
+        }
+    }
+}
+
+// === impl MakeSvc ===
+
+impl<A, B> svc::Service<DstAddr> for MakeSvc<A, B>
+where
+    A: svc::Service<DstAddr>,
+    A::Error: Into<Error>,
+    B: svc::Service<DstAddr>,
+    B::Error: Into<Error>,
+{
+    type Response = Service<A::Response, B::Response>;
+    type Error = Error;
+    type Future = MakeFuture<A::Future, B::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        try_ready!(self.balanced.poll_ready().map_err(Into::into));
+        self.pinned.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, target: DstAddr) -> Self::Future {
+        MakeFuture {
+            balanced: self.balanced.call(target.clone()),
+            pinned: self.pinned.call(target.clone()),
+            pinned_svc: None,
+            target: Some(target),
+            endpoints: self.endpoints.clone(),
+            pin_header: self.pin_header.clone(),
+        }
+    }
+}
+
+// === impl MakeFuture ===
+
+impl<A, B> Future for MakeFuture<A, B>
+where
+    A: Future,
+    A::Error: Into<Error>,
+    B: Future,
+    B::Error: Into<Error>,
+{
+    type Item = Service<A::Item, B::Item>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.pinned_svc.is_none() {
+            let pinned = try_ready!(self.pinned.poll().map_err(Into::into));
+            self.pinned_svc = Some(pinned);
+        }
+        let balanced = try_ready!(self.balanced.poll().map_err(Into::into));
+
+        Ok(Async::Ready(Service {
+            balanced,
+            pinned: self.pinned_svc.take().expect("polled after ready"),
+            target: self.target.take().expect("polled after ready"),
+            endpoints: self.endpoints.clone(),
+            pin_header: self.pin_header.take(),
+        }))
+    }
+}
+
+// === impl Service ===
+
+impl<A, B, Req> svc::Service<http::Request<Req>> for Service<A, B>
+where
+    A: svc::Service<http::Request<Req>>,
+    A::Error: Into<Error>,
+    B: svc::Service<http::Request<Req>, Response = A::Response>,
+    B::Error: Into<Error>,
+{
+    type Response = A::Response;
+    type Error = Error;
+    type Future = ResponseFuture<A::Future, B::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        try_ready!(self.balanced.poll_ready().map_err(Into::into));
+        self.pinned.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, mut req: http::Request<Req>) -> Self::Future {
+        let by_header = self
+            .pin_header
+            .as_ref()
+            .and_then(|h| h.pinned(&req, &self.target, &self.endpoints));
+        let pinned = match by_header {
+            Some(ep) => {
+                debug!(endpoint = %ep.addr, "pinned by header");
+                Some(ep)
+            }
+            None => affinity::pinned_addr(&req)
+                .and_then(|addr| self.endpoints.get(&self.target, addr))
+                .map(|ep| {
+                    debug!(endpoint = %ep.addr, "pinned by affinity cookie");
+                    ep
+                }),
+        };
+        match pinned {
+            Some(ep) => {
+                req.extensions_mut().insert(Pinned(ep));
+                ResponseFuture::Pinned(self.pinned.call(req))
+            }
+            None => ResponseFuture::Balanced(self.balanced.call(req)),
+        }
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl<A, B> Future for ResponseFuture<A, B>
+where
+    A: Future,
+    A::Error: Into<Error>,
+    B: Future<Item = A::Item>,
+    B::Error: Into<Error>,
+{
+    type Item = A::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self {
+            ResponseFuture::Balanced(f) => f.poll().map_err(Into::into),
+            ResponseFuture::Pinned(f) => f.poll().map_err(Into::into),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use linkerd2_app_core::{
+        proxy::{
+            discover::overrides,
+            http::{affinity::HasAffinityCookie, Settings},
+        },
+        svc::{Layer as _, Service as _},
+        Addr,
+    };
+
+    const COOKIE: &str = "l5d-affinity";
+    const PIN_HEADER: &str = "l5d-pin-endpoint";
+
+    /// A route that honors the affinity cookie.
+    struct Route;
+
+    impl HasAffinityCookie for Route {
+        fn affinity_cookie(&self) -> Option<&str> {
+            Some(COOKIE)
+        }
+    }
+
+    fn target() -> DstAddr {
+        let addr = Addr::from_str("web.ns.svc.cluster.local:8080").unwrap();
+        DstAddr::outbound(addr, Settings::Http2)
+    }
+
+    /// Builds a route service over a destination with two resolved endpoints,
+    /// which remain resolved while the returned `Active` is held. Responses
+    /// name the service that served them.
+    fn route_service(
+        pin_header: Option<PinHeader>,
+    ) -> (
+        impl svc::Service<http::Request<()>, Response = String, Error = Error>,
+        Active,
+    ) {
+        let endpoints = Endpoints::default();
+        let active = endpoints.register(target());
+        for addr in &["10.1.1.1:8080", "10.1.1.2:8080"] {
+            let addr = addr.parse::<SocketAddr>().unwrap();
+            active.lock().unwrap().insert(addr, Endpoint::from(addr));
+        }
+
+        let balanced =
+            svc::mk(|_: http::Request<()>| future::ok::<_, Error>("balanced".to_string()));
+        let pinned = svc::mk(|req: http::Request<()>| {
+            let ep = pinned_endpoint(&req).expect("request must be pinned");
+            future::ok::<_, Error>(ep.addr.to_string())
+        });
+        let dst = Service {
+            balanced,
+            pinned,
+            target: target(),
+            endpoints,
+            pin_header,
+        };
+
+        let svc = affinity::layer()
+            .layer(svc::mk(move |_: Route| future::ok::<_, Error>(dst.clone())))
+            .call(Route)
+            .wait()
+            .expect("route service must be built");
+        (svc, active)
+    }
+
+    fn request(cookie: Option<&str>) -> http::Request<()> {
+        let mut req = http::Request::builder();
+        if let Some(cookie) = cookie {
+            req.header(http::header::COOKIE, cookie);
+        }
+        req.body(()).unwrap()
+    }
+
+    fn pinned_request(addr: &str) -> http::Request<()> {
+        http::Request::builder()
+            .header(PIN_HEADER, addr)
+            .body(())
+            .unwrap()
+    }
+
+    #[test]
+    fn request_with_cookie_routes_to_named_endpoint() {
+        let (mut svc, _active) = route_service(None);
+        let cookie = format!("session=abc; {}=10.1.1.2:8080", COOKIE);
+        let rsp = svc.call(request(Some(&cookie))).wait().unwrap();
+        assert_eq!(rsp, "10.1.1.2:8080");
+    }
+
+    #[test]
+    fn request_without_cookie_is_balanced() {
+        let (mut svc, _active) = route_service(None);
+        let rsp = svc.call(request(None)).wait().unwrap();
+        assert_eq!(rsp, "balanced");
+
+        // Cookies that name endpoints that aren't resolved are ignored.
+        let cookie = format!("{}=10.1.1.3:8080", COOKIE);
+        let rsp = svc.call(request(Some(&cookie))).wait().unwrap();
+        assert_eq!(rsp, "balanced");
+    }
+
+    #[test]
+    fn pin_header_routes_to_named_endpoint() {
+        let overrides = Overrides::default();
+        let pin = PinHeader::new(PIN_HEADER.parse().unwrap(), overrides);
+        let (mut svc, _active) = route_service(Some(pin));
+        let rsp = svc.call(pinned_request("10.1.1.2:8080")).wait().unwrap();
+        assert_eq!(rsp, "10.1.1.2:8080");
+
+        // The header takes precedence over the affinity cookie.
+        let mut req = pinned_request("10.1.1.2:8080");
+        let cookie = format!("{}=10.1.1.1:8080", COOKIE);
+        req.headers_mut()
+            .insert(http::header::COOKIE, cookie.parse().unwrap());
+        let rsp = svc.call(req).wait().unwrap();
+        assert_eq!(rsp, "10.1.1.2:8080");
+    }
+
+    #[test]
+    fn invalid_pin_header_is_balanced() {
+        let overrides = Overrides::default();
+        let addr = "10.1.1.1:8080".parse().unwrap();
+        let _discovered = overrides.discover(&target().to_string(), addr);
+        overrides
+            .set(&target().to_string(), addr, overrides::State::Draining)
+            .unwrap();
+        let pin = PinHeader::new(PIN_HEADER.parse().unwrap(), overrides);
+        let (mut svc, _active) = route_service(Some(pin));
+
+        for addr in &["10.1.1.3:8080", "10.1.1.1:8080", "10.1.1.2"] {
+            let rsp = svc.call(pinned_request(addr)).wait().unwrap();
+            assert_eq!(rsp, "balanced", "{} must not be pinned", addr);
+        }
+    }
+
+    #[test]
+    fn pin_header_is_ignored_unless_configured() {
+        let (mut svc, _active) = route_service(None);
+        let rsp = svc.call(pinned_request("10.1.1.2:8080")).wait().unwrap();
+        assert_eq!(rsp, "balanced");
+    }
+}
@@ -0,0 +1,240 @@
+//! Limits the number of outbound connections that are established at once.
+//!
+//! A burst of requests to new destinations may otherwise start a connect (and
+//! TLS handshake) to each of their endpoints at once, saturating the event
+//! loop. Connects beyond the limit are queued until an in-flight connect
+//! completes or fails.
+//!
+//! Connects are frequently initiated without polling the connect service for
+//! readiness (e.g. by hyper's connection pool), so a permit is acquired by each
+//! connect's future rather than when the service becomes ready. The inner
+//! service is not called until the permit is acquired.
+
+use futures::{try_ready, Async, Future, Poll};
+use linkerd2_app_core::svc;
+use std::sync::Arc;
+use tokio_sync::semaphore::{Permit, Semaphore};
+use tracing::trace;
+
+#[derive(Clone, Debug)]
+pub struct Layer {
+    limit: Option<Arc<Semaphore>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Service<M> {
+    inner: M,
+    limit: Option<Arc<Semaphore>>,
+}
+
+pub struct ConnectFuture<M, T>
+where
+    M: svc::Service<T>,
+{
+    state: State<M, T>,
+    permit: Option<(Arc<Semaphore>, Permit)>,
+}
+
+enum State<M, T>
+where
+    M: svc::Service<T>,
+{
+    Queued(Option<(M, T)>),
+    Connecting(M::Future),
+}
+
+/// Limits the number of connects in flight to `max`, if set.
+pub fn layer(max: Option<usize>) -> Layer {
+    Layer {
+        limit: max.map(|max| Arc::new(Semaphore::new(max.max(1)))),
+    }
+}
+
+// === impl Layer ===
+
+impl<M> svc::Layer<M> for Layer {
+    type Service = Service<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        Service {
+            inner,
+            limit: self.limit.clone(),
+        }
+    }
+}
+
+// === impl Service ===
+
+impl<T, M> svc::Service<T> for Service<M>
+where
+    M: svc::Service<T> + Clone,
+{
+    type Response = M::Response;
+    type Error = M::Error;
+    type Future = ConnectFuture<M, T>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        match self.limit {
+            Some(ref limit) => ConnectFuture {
+                state: State::Queued(Some((self.inner.clone(), target))),
+                permit: Some((limit.clone(), Permit::new())),
+            },
+            None => ConnectFuture {
+                state: State::Connecting(self.inner.call(target)),
+                permit: None,
+            },
+        }
+    }
+}
+
+// === impl ConnectFuture ===
+
+impl<M, T> ConnectFuture<M, T>
+where
+    M: svc::Service<T>,
+{
+    /// Frees the connect's permit so that a queued connect may start.
+    fn release(&mut self) {
+        if let Some((limit, mut permit)) = self.permit.take() {
+            permit.release(&limit);
+        }
+    }
+}
+
+impl<M, T> Future for ConnectFuture<M, T>
+where
+    M: svc::Service<T>,
+{
+    type Item = M::Response;
+    type Error = M::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            self.state = match self.state {
+                State::Queued(ref mut queued) => {
+                    if let Some((ref limit, ref mut permit)) = self.permit {
+                        match permit.poll_acquire(limit) {
+                            Ok(Async::NotReady) => {
+                                trace!(available = %limit.available_permits(), "queued");
+                                return Ok(Async::NotReady);
+                            }
+                            // The limit is never closed.
+                            Ok(Async::Ready(())) | Err(_) => {}
+                        }
+                    }
+
+                    {
+                        let (inner, _) = queued.as_mut().expect("polled after ready");
+                        try_ready!(inner.poll_ready());
+                    }
+                    let (mut inner, target) = queued.take().expect("polled after ready");
+                    State::Connecting(inner.call(target))
+                }
+                State::Connecting(ref mut f) => {
+                    let poll = f.poll();
+                    if let Ok(Async::Ready(_)) | Err(_) = poll {
+                        self.release();
+                    }
+                    return poll;
+                }
+            };
+        }
+    }
+}
+
+impl<M, T> Drop for ConnectFuture<M, T>
+where
+    M: svc::Service<T>,
+{
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{future, sync::oneshot};
+    use linkerd2_app_core::svc::{Layer as _, Service as _};
+    use std::sync::Mutex;
+    use tokio::runtime::current_thread::Runtime;
+
+    /// Starts a connect that completes when its sender is notified.
+    #[derive(Clone, Default)]
+    struct MockConnect(Arc<Mutex<Vec<oneshot::Sender<()>>>>);
+
+    impl svc::Service<()> for MockConnect {
+        type Response = ();
+        type Error = oneshot::Canceled;
+        type Future = oneshot::Receiver<()>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: ()) -> Self::Future {
+            let (tx, rx) = oneshot::channel();
+            self.0.lock().unwrap().push(tx);
+            rx
+        }
+    }
+
+    impl MockConnect {
+        fn started(&self) -> usize {
+            self.0.lock().unwrap().len()
+        }
+
+        /// Completes the `i`th connect that was started.
+        fn complete(&self, i: usize) {
+            let (tx, _) = oneshot::channel();
+            let tx = std::mem::replace(&mut self.0.lock().unwrap()[i], tx);
+            tx.send(()).unwrap();
+        }
+    }
+
+    #[test]
+    fn burst_of_connects_is_limited() {
+        let mock = MockConnect::default();
+        let mut connect = layer(Some(2)).layer(mock.clone());
+
+        Runtime::new()
+            .unwrap()
+            .block_on(future::lazy(move || {
+                let mut connects = (0..5).map(|_| connect.call(())).collect::<Vec<_>>();
+                for c in connects.iter_mut() {
+                    assert!(c.poll().unwrap().is_not_ready());
+                }
+                assert_eq!(mock.started(), 2, "connects must not exceed the limit");
+
+                // Once a connect completes, a queued connect starts.
+                mock.complete(0);
+                assert!(connects[0].poll().unwrap().is_ready());
+                for c in connects[1..].iter_mut() {
+                    assert!(c.poll().unwrap().is_not_ready());
+                }
+                assert_eq!(mock.started(), 3);
+
+                // Connects that are abandoned free their permits.
+                connects.truncate(2);
+                connects.push(connect.call(()));
+                assert!(connects[2].poll().unwrap().is_not_ready());
+                assert_eq!(mock.started(), 4);
+
+                Ok::<_, ()>(())
+            }))
+            .unwrap();
+    }
+
+    #[test]
+    fn connects_are_unlimited_by_default() {
+        let mock = MockConnect::default();
+        let mut connect = layer(None).layer(mock.clone());
+
+        let _connects = (0..5).map(|_| connect.call(())).collect::<Vec<_>>();
+        assert_eq!(mock.started(), 5);
+    }
+}
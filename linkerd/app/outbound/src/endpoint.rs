@@ -1,5 +1,6 @@
 use indexmap::IndexMap;
 use linkerd2_app_core::{
+    connection_limit,
     dst::{DstAddr, Route},
     metric_labels::{prefix_labels, EndpointLabels},
     proxy::{
@@ -23,10 +24,24 @@ pub struct Endpoint {
     pub identity: tls::PeerIdentity,
     pub metadata: Metadata,
     pub http_settings: http::Settings,
+    /// Set if TLS is originated to this endpoint because it is outside of the
+    /// mesh.
+    pub tls_origination: Option<tls::originate::Origination>,
+    /// Set if the endpoint's certificate must present this URI SAN rather
+    /// than be valid for its identity.
+    pub tls_uri_san: Option<tls::peer_name::UriSan>,
 }
 
-#[derive(Copy, Clone, Debug)]
-pub struct FromMetadata;
+/// Builds endpoints from discovery metadata, originating TLS to those that
+/// have no identity and whose logical name is configured for origination.
+///
+/// Endpoints whose logical name or identity matches a peer name rule are
+/// identified as the rule describes.
+#[derive(Clone, Debug)]
+pub struct FromMetadata {
+    tls_origination: tls::originate::Config,
+    tls_peer_names: tls::peer_name::Config,
+}
 
 impl Endpoint {
     pub fn can_use_orig_proto(&self) -> bool {
@@ -37,10 +52,15 @@ impl Endpoint {
 
         match self.http_settings {
             http::Settings::Http2 => false,
+            // The request's version and whether it accepts trailers are
+            // carried in its `l5d-orig-proto` header, so that the
+            // downgrading proxy restores them.
             http::Settings::Http1 {
                 keep_alive: _,
                 wants_h1_upgrade,
                 was_absolute_form: _,
+                is_http10: _,
+                wants_trailers: _,
             } => !wants_h1_upgrade,
             http::Settings::NotHttp => {
                 unreachable!(
@@ -51,7 +71,15 @@ impl Endpoint {
         }
     }
 
-    pub fn from_request<B>(req: &http::Request<B>) -> Option<Self> {
+    /// Builds an endpoint for a request's original destination address.
+    ///
+    /// Because such destinations aren't resolved by the destination service,
+    /// TLS is originated according to the name that the request targets,
+    /// unless the request requires a mesh identity.
+    pub fn from_request<B>(
+        req: &http::Request<B>,
+        tls_origination: &tls::originate::Config,
+    ) -> Option<Self> {
         let addr = req
             .extensions()
             .get::<tls::accept::Meta>()?
@@ -66,6 +94,15 @@ impl Endpoint {
             }
         };
 
+        let tls_origination = match identity {
+            Conditional::None(_) => req
+                .extensions()
+                .get::<Addr>()
+                .and_then(Addr::name_addr)
+                .and_then(|dst| tls_origination.origination(dst.name())),
+            Conditional::Some(_) => None,
+        };
+
         Some(Self {
             addr,
             dst_logical: None,
@@ -73,6 +110,8 @@ impl Endpoint {
             identity,
             metadata: Metadata::empty(),
             http_settings,
+            tls_origination,
+            tls_uri_san: None,
         })
     }
 }
@@ -86,6 +125,8 @@ impl From<SocketAddr> for Endpoint {
             identity: Conditional::None(tls::ReasonForNoPeerName::NotHttp.into()),
             metadata: Metadata::empty(),
             http_settings: http::Settings::NotHttp,
+            tls_origination: None,
+            tls_uri_san: None,
         }
     }
 }
@@ -103,6 +144,7 @@ impl std::hash::Hash for Endpoint {
         self.addr.hash(state);
         self.identity.hash(state);
         self.http_settings.hash(state);
+        self.tls_uri_san.hash(state);
         // Ignore metadata.
     }
 }
@@ -111,6 +153,16 @@ impl tls::HasPeerIdentity for Endpoint {
     fn peer_identity(&self) -> tls::PeerIdentity {
         self.identity.clone()
     }
+
+    fn peer_uri_san(&self) -> Option<tls::peer_name::UriSan> {
+        self.tls_uri_san.clone()
+    }
+}
+
+impl tls::originate::HasOrigination for Endpoint {
+    fn tls_origination(&self) -> Option<tls::originate::Origination> {
+        self.tls_origination.clone()
+    }
 }
 
 impl connect::HasPeerAddr for Endpoint {
@@ -119,6 +171,12 @@ impl connect::HasPeerAddr for Endpoint {
     }
 }
 
+impl connection_limit::HasDstName for Endpoint {
+    fn dst_name(&self) -> Option<&NameAddr> {
+        self.dst_logical.as_ref()
+    }
+}
+
 impl http::normalize_uri::ShouldNormalizeUri for Endpoint {
     fn should_normalize_uri(&self) -> Option<http::uri::Authority> {
         if let http::Settings::Http1 {
@@ -181,24 +239,61 @@ impl tap::Inspect for Endpoint {
     }
 }
 
+impl FromMetadata {
+    pub fn new(
+        tls_origination: tls::originate::Config,
+        tls_peer_names: tls::peer_name::Config,
+    ) -> Self {
+        Self {
+            tls_origination,
+            tls_peer_names,
+        }
+    }
+}
+
 impl MapEndpoint<DstAddr, Metadata> for FromMetadata {
     type Out = Endpoint;
 
     fn map_endpoint(&self, target: &DstAddr, addr: SocketAddr, metadata: Metadata) -> Endpoint {
-        let identity = metadata
-            .identity()
-            .cloned()
-            .map(Conditional::Some)
-            .unwrap_or_else(|| {
-                Conditional::None(tls::ReasonForNoPeerName::NotProvidedByServiceDiscovery.into())
-            });
+        let dst_logical = target.dst_logical().name_addr().cloned();
+        let peer_name = self.tls_peer_names.peer_name(
+            dst_logical.as_ref().map(|dst| dst.name()),
+            metadata.identity(),
+        );
+        let (identity, tls_uri_san) = match peer_name {
+            Some(peer_name) => (
+                Conditional::Some(peer_name.name().clone()),
+                peer_name.uri().cloned(),
+            ),
+            None => {
+                let identity = metadata
+                    .identity()
+                    .cloned()
+                    .map(Conditional::Some)
+                    .unwrap_or_else(|| {
+                        Conditional::None(
+                            tls::ReasonForNoPeerName::NotProvidedByServiceDiscovery.into(),
+                        )
+                    });
+                (identity, None)
+            }
+        };
+
+        // Meshed endpoints are always secured by their identity.
+        let tls_origination = match (&identity, &dst_logical) {
+            (Conditional::None(_), Some(dst)) => self.tls_origination.origination(dst.name()),
+            _ => None,
+        };
+
         Endpoint {
             addr,
             identity,
             metadata,
-            dst_logical: target.dst_logical().name_addr().cloned(),
+            dst_logical,
             dst_concrete: target.dst_concrete().name_addr().cloned(),
             http_settings: target.http_settings.clone(),
+            tls_origination,
+            tls_uri_san,
         }
     }
 }
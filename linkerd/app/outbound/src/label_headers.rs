@@ -0,0 +1,151 @@
+//! Sets request headers from the discovery metadata labels of the endpoint
+//! that each request is dispatched to.
+//!
+//! This is applied per-endpoint, below the balancer, so that the headers
+//! describe the endpoint that was actually selected.
+
+use super::Endpoint;
+use futures::{try_ready, Future, Poll};
+use indexmap::IndexMap;
+use linkerd2_app_core::{
+    proxy::http::header::{HeaderName, HeaderValue},
+    svc,
+};
+use std::sync::Arc;
+use tracing::{debug, trace};
+
+#[derive(Clone, Debug)]
+pub struct Layer {
+    headers: Arc<IndexMap<String, HeaderName>>,
+    overwrite: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct MakeSvc<M> {
+    inner: M,
+    headers: Arc<IndexMap<String, HeaderName>>,
+    overwrite: bool,
+}
+
+pub struct MakeFuture<F> {
+    inner: F,
+    values: Option<Arc<Vec<(HeaderName, HeaderValue)>>>,
+    overwrite: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Service<S> {
+    inner: S,
+    values: Arc<Vec<(HeaderName, HeaderValue)>>,
+    overwrite: bool,
+}
+
+// === impl Layer ===
+
+/// Sets each header in `headers` to the value of the endpoint label that
+/// maps to it. Headers that are already set on a request are only replaced
+/// if `overwrite` is true.
+pub fn layer(headers: IndexMap<String, HeaderName>, overwrite: bool) -> Layer {
+    Layer {
+        headers: Arc::new(headers),
+        overwrite,
+    }
+}
+
+impl<M> svc::Layer<M> for Layer {
+    type Service = MakeSvc<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        MakeSvc {
+            inner,
+            headers: self.headers.clone(),
+            overwrite: self.overwrite,
+        }
+    }
+}
+
+// === impl MakeSvc ===
+
+impl<M> svc::Service<Endpoint> for MakeSvc<M>
+where
+    M: svc::Service<Endpoint>,
+{
+    type Response = Service<M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, endpoint: Endpoint) -> Self::Future {
+        let labels = endpoint.metadata.labels();
+        let values = self
+            .headers
+            .iter()
+            .filter_map(|(label, header)| {
+                let value = labels.get(label)?;
+                match HeaderValue::from_str(value) {
+                    Ok(value) => Some((header.clone(), value)),
+                    Err(_) => {
+                        debug!(label = %label, "label is not a valid header value");
+                        None
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+        trace!(
+            headers = values.len(),
+            "setting headers from endpoint labels"
+        );
+
+        MakeFuture {
+            values: Some(Arc::new(values)),
+            overwrite: self.overwrite,
+            inner: self.inner.call(endpoint),
+        }
+    }
+}
+
+// === impl MakeFuture ===
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = Service<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        let values = self.values.take().expect("polled after ready");
+        Ok(Service {
+            inner,
+            values,
+            overwrite: self.overwrite,
+        }
+        .into())
+    }
+}
+
+// === impl Service ===
+
+impl<S, B> svc::Service<http::Request<B>> for Service<S>
+where
+    S: svc::Service<http::Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut req: http::Request<B>) -> Self::Future {
+        for (name, value) in self.values.iter() {
+            if self.overwrite || !req.headers().contains_key(name) {
+                req.headers_mut().insert(name.clone(), value.clone());
+            }
+        }
+
+        self.inner.call(req)
+    }
+}
@@ -6,27 +6,32 @@
 #![deny(warnings, rust_2018_idioms)]
 
 use futures::future;
+use indexmap::{IndexMap, IndexSet};
 use linkerd2_app_core::{
-    self as core, classify,
-    config::{ProxyConfig, ServerConfig},
-    dns, drain,
-    dst::DstAddr,
-    errors, http_request_authority_addr, http_request_host_addr,
+    self as core, accept_timeout, accept_watchdog, admin, admission, caller_override, classify,
+    config::{DrainPolicy, ProxyConfig, ServerConfig},
+    connection_limit, dns, drain,
+    dst::{self, DstAddr},
+    dst_conflict, endpoint_timeout,
+    error_log::ErrorLog,
+    errors, events, header_trust, http_request_authority_addr, http_request_host_addr,
     http_request_l5d_override_dst_addr, http_request_orig_dst_addr,
     opencensus::proto::trace::v1 as oc,
     proxy::{
         self, core::resolve::Resolve, discover, fallback, http, identity, resolve::map_endpoint,
         tap, tcp, Server,
     },
-    reconnect, router, serve,
+    reconnect, request_filter, route_backend, router, serve,
     spans::SpanConverter,
-    svc, trace, trace_context,
+    startup, svc, trace, trace_context,
     transport::{self, connect, tls, OrigDstAddr, SysOrigDstAddr},
-    Addr, Conditional, DispatchDeadline, Error, ProxyMetrics, CANONICAL_DST_HEADER,
+    Addr, Conditional, DispatchDeadline, Error, NameAddr, ProxyMetrics, CANONICAL_DST_HEADER,
     DST_OVERRIDE_HEADER, L5D_CLIENT_ID, L5D_REMOTE_IP, L5D_REQUIRE_ID, L5D_SERVER_ID,
 };
+use rand::{rngs::SmallRng, SeedableRng};
 use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio::sync::mpsc;
 use tower_grpc::{self as grpc, generic::client::GrpcService};
@@ -36,7 +41,11 @@ use tracing::{debug, info_span};
 mod add_remote_ip_on_rsp;
 #[allow(dead_code)] // TODO #2597
 mod add_server_id_on_rsp;
+mod affinity;
+mod connect_limit;
 mod endpoint;
+mod label_headers;
+mod max_queue_time;
 mod orig_proto_upgrade;
 mod require_identity_on_endpoint;
 
@@ -49,6 +58,110 @@ const EWMA_DECAY: Duration = Duration::from_secs(10);
 pub struct Config<A: OrigDstAddr = SysOrigDstAddr> {
     pub proxy: ProxyConfig<A>,
     pub canonicalize_timeout: Duration,
+    pub canonicalize_freshness: Option<Duration>,
+    pub route_unmatched_log_interval: Option<Duration>,
+    pub dst_conflict_policy: dst_conflict::Policy,
+    /// The backup destination of each named route of each destination.
+    pub route_backups: IndexMap<NameAddr, IndexMap<String, NameAddr>>,
+    pub empty_response_failures: IndexMap<NameAddr, IndexSet<String>>,
+    /// The names of each destination's routes on which concurrent identical
+    /// requests are coalesced.
+    pub coalesced_routes: IndexMap<NameAddr, IndexSet<String>>,
+    pub coalesce: http::coalesce::Config,
+    /// The names of each destination's routes on which requests keep their
+    /// original scheme when their URIs are normalized.
+    pub preserve_scheme_routes: IndexMap<NameAddr, IndexSet<String>>,
+    /// The name of the cookie that pins requests to an endpoint on each
+    /// destination's routes, by route name.
+    pub affinity_cookie_routes: IndexMap<NameAddr, IndexMap<String, String>>,
+    /// If set, requests carrying this header are dispatched to the endpoint
+    /// whose `IP:PORT` it names, rather than balanced.
+    pub pin_endpoint_header: Option<http::header::HeaderName>,
+    /// The transformations applied to requests and responses on each
+    /// destination's routes, by route name.
+    pub route_transforms: IndexMap<NameAddr, IndexMap<String, http::transform::Transform>>,
+    /// The maximum time that requests to each destination may spend queued
+    /// before they fail.
+    pub max_queue_times: IndexMap<NameAddr, Duration>,
+    pub label_headers: IndexMap<String, http::header::HeaderName>,
+    pub label_headers_overwrite: bool,
+    /// Determines which destinations outside of the mesh TLS is originated to.
+    pub tls_origination: tls::originate::Config,
+    /// Determines the names that endpoints' certificates are verified
+    /// against, e.g. for peers in an adjacent SPIFFE-based mesh.
+    pub tls_peer_names: tls::peer_name::Config,
+    /// Seeds all of the RNGs used to balance and split traffic, so that these
+    /// decisions are reproducible. If unset, entropy is used.
+    pub rng_seed: Option<u64>,
+    /// If set, balancers don't serve requests until enough of their endpoints
+    /// are ready.
+    pub balancer_min_ready: Option<http::min_ready::Config>,
+    /// If set, each balancer builds at most this many endpoint services at
+    /// once.
+    pub balancer_max_concurrent_builds: Option<usize>,
+    /// The EWMA parameters of concrete destinations whose balancers don't use
+    /// the defaults.
+    pub balancer_ewma_overrides: IndexMap<NameAddr, http::balance::Ewma>,
+    /// If set, at most this many connections are established at once; further
+    /// connects are queued.
+    pub max_concurrent_connects: Option<usize>,
+    /// Limits the connections that are open to each matching destination at
+    /// once.
+    pub connection_limits: connection_limit::Config,
+    /// If set, responses of up to this many bytes to idempotent requests on
+    /// retryable routes are buffered, so that they're retried if they are
+    /// reset before they complete.
+    pub retry_reset_max_body_bytes: Option<usize>,
+    /// The destinations whose upgraded requests' bodies are checksummed.
+    pub body_checksum: BodyChecksumConfig,
+    /// If set, the listener's accept loop is watched so that a stall is
+    /// detected (and, optionally, recovered from).
+    pub accept_watchdog: Option<accept_watchdog::Config>,
+    /// Policies evaluated alongside those in effect, without acting on them.
+    pub dry_run: DryRunConfig,
+    /// The maximum number of connections held while the stack is being
+    /// constructed. Further connections are refused until it has been.
+    pub startup_queue_capacity: usize,
+    /// The number of shards into which the logical and authority routers'
+    /// caches are split.
+    pub router_shards: usize,
+}
+
+/// Configures the policies that are evaluated in a dry run. Only the
+/// configured policies are evaluated.
+#[derive(Clone, Debug, Default)]
+pub struct DryRunConfig {
+    /// If set, the balancer's choices are compared with least-request's.
+    pub balancer: bool,
+    /// If set, each retry decision is compared with this condition's.
+    pub retry: Option<http::dry_run::retry::Condition>,
+}
+
+/// Configures the destinations to which the bodies of requests that are
+/// upgraded to HTTP/2 are sent with checksums, so that the inbound proxy
+/// verifies them (and checksums its responses in turn).
+#[derive(Clone, Debug, Default)]
+pub struct BodyChecksumConfig {
+    /// Logical or concrete names whose requests are checksummed.
+    pub dsts: IndexSet<NameAddr>,
+    /// Ports whose requests are checksummed, whatever their destination.
+    pub ports: IndexSet<u16>,
+}
+
+impl BodyChecksumConfig {
+    fn matches(&self, endpoint: &Endpoint) -> bool {
+        let port = endpoint
+            .dst_logical
+            .as_ref()
+            .map(|dst| dst.port())
+            .unwrap_or_else(|| endpoint.addr.port());
+        self.ports.contains(&port)
+            || endpoint
+                .dst_logical
+                .iter()
+                .chain(endpoint.dst_concrete.iter())
+                .any(|dst| self.dsts.contains(dst))
+    }
 }
 
 pub struct Outbound {
@@ -61,6 +174,34 @@ impl<A: OrigDstAddr> Config<A> {
         Config {
             proxy: self.proxy.with_orig_dst_addr(orig_dst_addr),
             canonicalize_timeout: self.canonicalize_timeout,
+            canonicalize_freshness: self.canonicalize_freshness,
+            route_unmatched_log_interval: self.route_unmatched_log_interval,
+            dst_conflict_policy: self.dst_conflict_policy,
+            route_backups: self.route_backups,
+            empty_response_failures: self.empty_response_failures,
+            coalesced_routes: self.coalesced_routes,
+            coalesce: self.coalesce,
+            preserve_scheme_routes: self.preserve_scheme_routes,
+            affinity_cookie_routes: self.affinity_cookie_routes,
+            pin_endpoint_header: self.pin_endpoint_header,
+            route_transforms: self.route_transforms,
+            max_queue_times: self.max_queue_times,
+            label_headers: self.label_headers,
+            label_headers_overwrite: self.label_headers_overwrite,
+            tls_origination: self.tls_origination,
+            tls_peer_names: self.tls_peer_names,
+            rng_seed: self.rng_seed,
+            balancer_min_ready: self.balancer_min_ready,
+            balancer_max_concurrent_builds: self.balancer_max_concurrent_builds,
+            balancer_ewma_overrides: self.balancer_ewma_overrides,
+            max_concurrent_connects: self.max_concurrent_connects,
+            connection_limits: self.connection_limits,
+            retry_reset_max_body_bytes: self.retry_reset_max_body_bytes,
+            body_checksum: self.body_checksum,
+            accept_watchdog: self.accept_watchdog,
+            dry_run: self.dry_run,
+            startup_queue_capacity: self.startup_queue_capacity,
+            router_shards: self.router_shards,
         }
     }
 
@@ -69,11 +210,13 @@ impl<A: OrigDstAddr> Config<A> {
         local_identity: tls::Conditional<identity::Local>,
         resolve: R,
         dns_resolver: dns::Resolver,
-        profiles_client: core::profiles::Client<P>,
+        refinements: http::canonicalize::Refinements,
+        profiles_client: http::profiles::negative_cache::GetRoutes<core::profiles::Client<P>>,
         tap_layer: tap::Layer,
         metrics: ProxyMetrics,
         span_sink: Option<mpsc::Sender<oc::Span>>,
         drain: drain::Watch,
+        readiness: admin::Readiness,
     ) -> Result<Outbound, Error>
     where
         A: Send + 'static,
@@ -92,6 +235,34 @@ impl<A: OrigDstAddr> Config<A> {
         use proxy::core::listen::{Bind, Listen};
         let Config {
             canonicalize_timeout,
+            canonicalize_freshness,
+            route_unmatched_log_interval,
+            dst_conflict_policy,
+            route_backups,
+            empty_response_failures,
+            coalesced_routes,
+            coalesce,
+            preserve_scheme_routes,
+            affinity_cookie_routes,
+            pin_endpoint_header,
+            route_transforms,
+            max_queue_times,
+            label_headers,
+            label_headers_overwrite,
+            tls_origination,
+            tls_peer_names,
+            rng_seed,
+            balancer_min_ready,
+            balancer_max_concurrent_builds,
+            balancer_ewma_overrides,
+            max_concurrent_connects,
+            connection_limits,
+            retry_reset_max_body_bytes,
+            body_checksum,
+            accept_watchdog,
+            dry_run,
+            startup_queue_capacity,
+            router_shards,
             proxy:
                 ProxyConfig {
                     server:
@@ -99,36 +270,118 @@ impl<A: OrigDstAddr> Config<A> {
                             bind,
                             buffer,
                             h2_settings,
+                            max_requests_per_connection,
+                            h2_reset_limit,
+                            idle_timeouts,
                         },
                     connect,
                     router_capacity,
                     router_max_idle_age,
                     disable_protocol_detection_for_ports,
+                    strip_response_headers,
+                    error_log_dedup_window,
+                    correlation_id_header,
+                    correlation_id_echo,
+                    caller_overrides,
+                    header_trust,
+                    max_response_headers,
                 },
         } = self;
 
-        let listen = bind.bind().map_err(Error::from)?;
+        let listen = bind.clone().bind().map_err(Error::from)?;
         let listen_addr = listen.listen_addr();
+        // While the accept loop is stalled, the proxy isn't ready.
+        let accept_watch = accept_watchdog
+            .map(|c| c.watch(readiness.condition(), metrics.accept_watchdog.clone()));
 
         // The stack is served lazily since some layers (notably buffer) spawn
         // tasks from their constructor. This helps to ensure that tasks are
         // spawned on the same runtime as the proxy.
-        let serve = Box::new(future::lazy(move || {
+        // Requests that are still queued in buffers when the proxy begins to
+        // drain fail immediately if the drain policy requires it.
+        let buffer_drain = match buffer.drain_policy {
+            DrainPolicy::FailFast => Some(drain.signaled()),
+            DrainPolicy::Complete => None,
+        };
+        // Requests whose dispatch deadline is too soon to be met fail without
+        // being queued, if a minimum dispatch budget is configured.
+        let buffer_shed = buffer
+            .min_dispatch_budget
+            .map(|min| proxy::buffer::Shed::new(min, metrics.deadline_shed.clone()));
+
+        // Every RNG in the stack is derived from a single RNG, so that a seed
+        // determines all of the stack's balancing and splitting decisions.
+        let mut rng = match rng_seed {
+            Some(seed) => SmallRng::seed_from_u64(seed),
+            None => SmallRng::from_entropy(),
+        };
+        let backoff_rng = Arc::new(Mutex::new(fork_rng(&mut rng)));
+        let balance_rng = fork_rng(&mut rng);
+        let split_rng = fork_rng(&mut rng);
+
+        // Connections are accepted while the stack is constructed, but they're
+        // held until it has been, so that they don't race its construction.
+        let (constructed, gate) = startup::gate(startup_queue_capacity, readiness.condition());
+        let serve_drain = drain.clone();
+
+        let construct = future::lazy(move || {
+            let error_log = ErrorLog::spawn("outbound", error_log_dedup_window);
+
+            // When the local identity's certificate rotates, clients are
+            // rebuilt so that their connections present the new certificate.
+            if let Conditional::Some(ref local) = local_identity {
+                tokio::spawn(metrics.identity_rotation.watch(local.clone()));
+            }
+
             // Establishes connections to remote peers (for both TCP
-            // forwarding and HTTP proxying).
+            // forwarding and HTTP proxying). Meshed peers are secured by
+            // their identity; TLS may be originated to peers outside of the
+            // mesh.
             let connect_stack = svc::stack(connect::svc(connect.keepalive))
-                .push(tls::client::layer(local_identity))
+                // The time taken to connect is recorded separately from the
+                // time taken by the mesh TLS handshake.
+                .push(
+                    tls::client::layer(local_identity)
+                        .with_observe(metrics.transport.observe_connect(TransportLabels)),
+                )
+                .push(tls::originate::layer())
                 .push_timeout(connect.timeout)
-                .push(metrics.transport.layer_connect(TransportLabels));
+                .push(metrics.transport.layer_connect(TransportLabels))
+                // Connects that are queued by the limit aren't subject to the
+                // connect timeout until they start.
+                .push(connect_limit::layer(max_concurrent_connects))
+                // Connects that wait for a destination's connection to close
+                // don't hold one of the concurrent connects while they wait.
+                .push(connection_limit::layer(
+                    connection_limits,
+                    metrics.connection_limit,
+                ));
 
-            // Instantiates an HTTP client for for a `client::Config`
+            // Instantiates an HTTP client for for a `client::Config`.
+            //
+            // If a max lifetime is configured, the client (and its pool of
+            // connections) is rebuilt once it exceeds that age. It's also
+            // rebuilt when the local identity's certificate rotates. The state
+            // of each endpoint's pool is reported.
             let client_stack = connect_stack
                 .clone()
+                .push(http::pool::connect_layer(
+                    metrics.http_endpoint_pool.clone(),
+                ))
                 .push(http::client::layer(connect.h2_settings))
-                .push(reconnect::layer({
-                    let backoff = connect.backoff.clone();
-                    move |_| Ok(backoff.stream())
-                }))
+                .push(http::pool::layer(metrics.http_endpoint_pool))
+                .push(
+                    reconnect::layer({
+                        let backoff = connect.backoff.clone();
+                        move |_| {
+                            let mut rng = backoff_rng.lock().expect("backoff rng lock");
+                            Ok(backoff.stream_with_rng(fork_rng(&mut rng)))
+                        }
+                    })
+                    .with_max_age(connect.max_lifetime)
+                    .with_budget(connect.retry_budget)
+                    .with_retire(Some(metrics.identity_rotation.retire())),
+                )
                 .push(trace_context::layer(span_sink.clone().map(|span_sink| {
                     SpanConverter::client(span_sink, trace_labels())
                 })))
@@ -136,30 +389,61 @@ impl<A: OrigDstAddr> Config<A> {
 
             // A per-`outbound::Endpoint` stack that:
             //
-            // 1. Records http metrics  with per-endpoint labels.
-            // 2. Instruments `tap` inspection.
-            // 3. Changes request/response versions when the endpoint
+            // 1. Correlates route timeouts with the endpoint that was serving
+            //    the request when the timeout fired.
+            // 2. Records http metrics  with per-endpoint labels.
+            // 3. Instruments `tap` inspection.
+            // 4. Changes request/response versions when the endpoint
             //    supports protocol upgrade (and the request may be upgraded).
-            // 4. Appends `l5d-server-id` to responses coming back iff meshed
+            // 5. Appends `l5d-server-id` to responses coming back iff meshed
             //    TLS was used on the connection.
-            // 5. Routes requests to the correct client (based on the
+            // 6. Routes requests to the correct client (based on the
             //    request version and headers).
-            // 6. Strips any `l5d-server-id` that may have been received from
+            // 7. Strips any `l5d-server-id` that may have been received from
             //    the server, before we apply our own.
+            // 8. Strips controlled response headers set by servers whose
+            //    identities aren't trusted to set them.
+            // 9. Sets request headers from the endpoint's discovery labels.
+            // 10. Limits the size of response headers, if configured, before
+            //     any other layer sees the response.
+            // 11. Checksums the bodies of upgraded requests to configured
+            //     destinations, and verifies the checksums of their responses.
             let endpoint_stack = client_stack
                 .serves::<Endpoint>()
+                .push(http::header_limit::layer(max_response_headers))
+                .push(label_headers::layer(label_headers, label_headers_overwrite))
                 .push(http::strip_header::response::layer(L5D_REMOTE_IP))
                 .push(http::strip_header::response::layer(L5D_SERVER_ID))
                 .push(http::strip_header::request::layer(L5D_REQUIRE_ID))
+                .push(header_trust::response_layer(
+                    header_trust,
+                    metrics.header_trust.clone(),
+                ))
                 // disabled due to information leagkage
                 //.push(add_remote_ip_on_rsp::layer())
                 //.push(add_server_id_on_rsp::layer())
+                .push(http::checksum::layer(
+                    {
+                        let body_checksum = Arc::new(body_checksum);
+                        move |ep: &Endpoint| body_checksum.matches(ep)
+                    },
+                    metrics.body_checksum,
+                ))
                 .push(orig_proto_upgrade::layer())
                 .push(tap_layer.clone())
                 .push(http::metrics::layer::<_, classify::Response>(
                     metrics.http_endpoint,
                 ))
+                .push(http::outcome::record::layer(
+                    |endpoint: &Endpoint, outcome: &http::outcome::Outcome| {
+                        outcome.set_endpoint(endpoint.addr)
+                    },
+                ))
+                .push(endpoint_timeout::layer(metrics.endpoint_timeout))
                 .push(require_identity_on_endpoint::layer())
+                // When enabled for debugging, sampled requests are timed in
+                // each of the stack's major stages.
+                .push(metrics.stack_stage.layer("outbound.endpoint"))
                 .push(trace::layer(|endpoint: &Endpoint| {
                     info_span!("endpoint", peer.addr = %endpoint.addr, peer.id = ?endpoint.identity)
                 }))
@@ -175,57 +459,193 @@ impl<A: OrigDstAddr> Config<A> {
             //    specifies a timeout. This goes before `retry` to cap
             //    retries.
             // 3. Retries are optionally enabled depending on if the route
-            //    is retryable.
+            //    is retryable. Responses that are reset before they complete
+            //    fail distinctly, so that they may be retried if they were
+            //    buffered.
+            // 4. Requests that fail without a response are retried once
+            //    against the route's backup destination, if it has one.
+            // 5. Concurrent identical requests are optionally coalesced into
+            //    a single request, depending on if the route allows it.
+            // 6. Requests are optionally marked so that each endpoint's
+            //    `normalize_uri` layer preserves their scheme.
+            // 7. Requests are optionally marked with the route's affinity
+            //    cookie, so that the distributor may pin them to the endpoint
+            //    the cookie names.
+            // 8. Responses are counted by the traffic split backend that
+            //    served them, when the destination's traffic is split.
+            // 9. Requests and responses are optionally transformed, as
+            //    configured for the route, before anything else sees them.
             let dst_route_layer = svc::layers()
+                .push(route_backend::layer(metrics.http_route_backend))
+                .push(http::normalize_uri::preserve_scheme::layer())
+                .push(http::affinity::layer())
                 .push(http::insert::target::layer())
                 .push(http::metrics::layer::<_, classify::Response>(
                     metrics.http_route_retry.clone(),
                 ))
-                .push(http::retry::layer(metrics.http_route_retry))
+                .push(http::response_reset::layer(
+                    retry_reset_max_body_bytes,
+                    metrics.response_reset,
+                ))
+                .push(
+                    http::retry::layer(metrics.http_route_retry).with_dry_run(
+                        dry_run
+                            .retry
+                            .map(|c| http::dry_run::retry::DryRun::new(c, metrics.dry_run.clone())),
+                    ),
+                )
                 .push(http::timeout::layer())
-                .push(http::metrics::layer::<_, classify::Response>(
-                    metrics.http_route,
+                .push(
+                    http::metrics::layer::<_, classify::Response>(metrics.http_route)
+                        .with_outcome(true),
+                )
+                .push(http::outcome::record::layer(
+                    |route: &dst::Route, outcome: &http::outcome::Outcome| {
+                        outcome.set_route_labels(route.route.labels())
+                    },
                 ))
                 .push(classify::layer())
-                .push_buffer_pending(buffer.max_in_flight, DispatchDeadline::extract);
+                .push_buffer_pending_with_shedding(
+                    buffer.max_in_flight,
+                    DispatchDeadline::extract,
+                    buffer_drain.clone(),
+                    buffer_shed.clone(),
+                )
+                .push(http::profiles::failover::layer(
+                    move || DispatchDeadline::after(buffer.dispatch_timeout),
+                    is_undispatched,
+                ))
+                .push(http::coalesce::layer(coalesce, coalesce_client))
+                .push(http::transform::layer())
+                .push(metrics.stack_stage.layer("outbound.profile"));
 
             // Routes requests to their original destination endpoints. Used as
             // a fallback when service discovery has no endpoints for a destination.
             //
             // If the `l5d-require-id` header is present, then that identity is
             // used as the server name when connecting to the endpoint.
+            // Otherwise, TLS is originated to the endpoint if the request's
+            // destination name is configured for origination, since names
+            // outside of the mesh are never resolved by service discovery.
+            let orig_dst_tls_origination = tls_origination.clone();
             let orig_dst_router_layer = svc::layers()
-                .push_buffer_pending(buffer.max_in_flight, DispatchDeadline::extract)
+                .push_buffer_pending_with_shedding(
+                    buffer.max_in_flight,
+                    DispatchDeadline::extract,
+                    buffer_drain.clone(),
+                    buffer_shed.clone(),
+                )
                 .push(router::Layer::new(
                     router::Config::new(router_capacity, router_max_idle_age),
-                    Endpoint::from_request,
+                    move |req: &http::Request<_>| {
+                        Endpoint::from_request(req, &orig_dst_tls_origination)
+                    },
+                ))
+                .push(trace_context::annotate::layer(
+                    "fallback",
+                    vec![("reason", "unresolvable destination".to_owned())],
+                ))
+                .push(events::layer(
+                    metrics.stack_state.events(),
+                    events::Kind::FallbackEngaged,
+                    events::Kind::FallbackDisengaged,
+                ));
+
+            // Routes requests that are pinned by an affinity cookie or by the
+            // pin header directly to the endpoint that it names.
+            let affinity_endpoints = affinity::Endpoints::default();
+            let pin_header = pin_endpoint_header.map(|name| {
+                affinity::PinHeader::new(name, metrics.stack_state.endpoint_overrides())
+            });
+            let pinned_router_layer = svc::layers()
+                .push_buffer_pending_with_shedding(
+                    buffer.max_in_flight,
+                    DispatchDeadline::extract,
+                    buffer_drain.clone(),
+                    buffer_shed.clone(),
+                )
+                .push(router::Layer::new(
+                    router::Config::new(router_capacity, router_max_idle_age),
+                    affinity::pinned_endpoint,
                 ));
 
             // Resolves the target via the control plane and balances requests
-            // over all endpoints returned from the destination service.
+            // over all endpoints returned from the destination service. Endpoint
+            // updates and the balancer's lifetime are recorded in the target's
+            // event history, and the fraction of resolved endpoints that can
+            // use orig-proto is reported.
             const DISCOVER_UPDATE_BUFFER_CAPACITY: usize = 10;
+            let balancer_endpoints = discover::Endpoints::default();
+            metrics
+                .stack_state
+                .register_balancers("outbound", balancer_endpoints.clone());
             let balancer_layer = svc::layers()
                 .push_spawn_ready()
-                .push(discover::Layer::new(
-                    DISCOVER_UPDATE_BUFFER_CAPACITY,
-                    router_max_idle_age,
-                    map_endpoint::Resolve::new(endpoint::FromMetadata, resolve.clone()),
-                ))
-                .push(http::balance::layer(EWMA_DEFAULT_RTT, EWMA_DECAY));
+                .push(
+                    discover::Layer::new(
+                        DISCOVER_UPDATE_BUFFER_CAPACITY,
+                        router_max_idle_age,
+                        affinity::Resolve::new(
+                            orig_proto_upgrade::Resolve::new(
+                                map_endpoint::Resolve::new(
+                                    endpoint::FromMetadata::new(tls_origination, tls_peer_names),
+                                    events::Resolve::new(
+                                        resolve.clone(),
+                                        metrics.stack_state.events(),
+                                    ),
+                                ),
+                                metrics.orig_proto_coverage,
+                            ),
+                            affinity_endpoints.clone(),
+                        ),
+                    )
+                    .with_endpoints(balancer_endpoints)
+                    .with_overrides(metrics.stack_state.endpoint_overrides())
+                    .with_max_concurrent_builds(balancer_max_concurrent_builds),
+                )
+                .push(
+                    http::balance::layer(EWMA_DEFAULT_RTT, EWMA_DECAY, balance_rng)
+                        .with_ewma_overrides(balancer_ewma_overrides)
+                        .with_min_ready(balancer_min_ready)
+                        .with_dry_run(if dry_run.balancer {
+                            Some(metrics.dry_run.clone())
+                        } else {
+                            None
+                        }),
+                )
+                .push(events::layer(
+                    metrics.stack_state.events(),
+                    events::Kind::BalancerCreated,
+                    events::Kind::BalancerEvicted,
+                ));
 
             // If the balancer fails to be created, i.e., because it is unresolvable,
             // fall back to using a router that dispatches request to the
-            // application-selected original destination.
+            // application-selected original destination. Requests that are
+            // pinned to one of the balancer's endpoints bypass it.
             let distributor = endpoint_stack
                 .serves::<Endpoint>()
-                .push(fallback::layer(
-                    balancer_layer.boxed(),
-                    orig_dst_router_layer.boxed(),
-                ))
+                .push(
+                    affinity::layer(
+                        fallback::layer(balancer_layer.boxed(), orig_dst_router_layer.boxed())
+                            .with_hops(metrics.fallback_hops.clone()),
+                        pinned_router_layer.boxed(),
+                        affinity_endpoints,
+                    )
+                    .with_pin_header(pin_header),
+                )
+                .push(metrics.stack_stage.layer("outbound.concrete"))
                 .push(trace::layer(
                     |dst: &DstAddr| info_span!("concrete", dst.concrete = %dst.dst_concrete()),
                 ));
 
+            // Counts (and optionally logs) requests that match none of their
+            // destination profile's routes.
+            let route_unmatched = metrics
+                .route_unmatched
+                .clone()
+                .with_warn_interval(route_unmatched_log_interval);
+
             // A per-`DstAddr` stack that does the following:
             //
             // 1. Adds the `CANONICAL_DST_HEADER` from the `DstAddr`.
@@ -235,13 +655,29 @@ impl<A: OrigDstAddr> Config<A> {
             //   `DstAddr` with a resolver.
             let dst_stack = distributor
                 .serves::<DstAddr>()
-                .push_buffer_pending(buffer.max_in_flight, DispatchDeadline::extract)
+                .push_buffer_pending_with_shedding(
+                    buffer.max_in_flight,
+                    DispatchDeadline::extract,
+                    buffer_drain.clone(),
+                    buffer_shed.clone(),
+                )
                 .makes::<DstAddr>()
-                .push(http::profiles::router::layer(
-                    profiles_client,
-                    dst_route_layer,
-                ))
-                .push(http::header_from_target::layer(CANONICAL_DST_HEADER));
+                .push(
+                    http::profiles::router::layer(
+                        events::GetRoutes::new(profiles_client, metrics.stack_state.events()),
+                        dst_route_layer,
+                    )
+                    .with_unmatched(route_unmatched)
+                    .with_backups(Arc::new(route_backups))
+                    .with_empty_failures(Arc::new(empty_response_failures))
+                    .with_coalesced(Arc::new(coalesced_routes))
+                    .with_preserved_schemes(Arc::new(preserve_scheme_routes))
+                    .with_affinity_cookies(Arc::new(affinity_cookie_routes))
+                    .with_transforms(Arc::new(route_transforms))
+                    .with_rng(split_rng),
+                )
+                .push(http::header_from_target::layer(CANONICAL_DST_HEADER))
+                .push(metrics.stack_stage.layer("outbound.logical"));
 
             // Routes request using the `DstAddr` extension.
             //
@@ -251,9 +687,15 @@ impl<A: OrigDstAddr> Config<A> {
                 .push(trace::layer(
                     |dst: &DstAddr| info_span!("logical", dst.logical = %dst.dst_logical()),
                 ))
-                .push_buffer_pending(buffer.max_in_flight, DispatchDeadline::extract)
+                .push_buffer_pending_with_shedding(
+                    buffer.max_in_flight,
+                    DispatchDeadline::extract,
+                    buffer_drain.clone(),
+                    buffer_shed.clone(),
+                )
                 .push(router::Layer::new(
-                    router::Config::new(router_capacity, router_max_idle_age),
+                    router::Config::new(router_capacity, router_max_idle_age)
+                        .with_shards(router_shards),
                     |req: &http::Request<_>| {
                         req.extensions().get::<Addr>().cloned().map(|addr| {
                             DstAddr::outbound(addr, http::settings::Settings::from_request(req))
@@ -262,12 +704,37 @@ impl<A: OrigDstAddr> Config<A> {
                 ))
                 .into_inner()
                 .spawn();
+            metrics
+                .stack_state
+                .register_cache("outbound.dst", dst_router.cache_size());
+            metrics
+                .cache_lock_wait
+                .register("outbound.dst", dst_router.lock_wait());
+
+            let evict_dsts = dst_router.evict_handle();
+
+            // Requests to destinations with a maximum queue time fail if they
+            // are not dispatched in time by the buffers in the dst-stack.
+            let dst_router = svc::stack(dst_router)
+                .push(max_queue_time::layer(max_queue_times))
+                .into_inner();
 
             // Canonicalizes the request-specified `Addr` via DNS, and
             // annotates each request with a refined `Addr` so that it may be
             // routed by the dst_router.
+            //
+            // When a name's canonical form changes, the dst-stacks for the
+            // prior name are evicted so that they are not retained until they
+            // become idle.
             let addr_stack = svc::stack(svc::Shared::new(dst_router)).push(
-                http::canonicalize::layer(dns_resolver, canonicalize_timeout),
+                http::canonicalize::layer(dns_resolver, canonicalize_timeout)
+                    .with_freshness(canonicalize_freshness)
+                    .with_metrics(metrics.dns_canonicalize.clone())
+                    .with_refinements(refinements)
+                    .with_invalidate(move |prior: &NameAddr| {
+                        let prior = Addr::Name(prior.clone());
+                        evict_dsts.evict(move |dst: &DstAddr| *dst.dst_logical() == prior);
+                    }),
             );
 
             // Routes requests to an `Addr`:
@@ -290,9 +757,15 @@ impl<A: OrigDstAddr> Config<A> {
                 .push(http::strip_header::request::layer(DST_OVERRIDE_HEADER))
                 .push(http::insert::target::layer())
                 .push(trace::layer(|addr: &Addr| info_span!("addr", %addr)))
-                .push_buffer_pending(buffer.max_in_flight, DispatchDeadline::extract)
+                .push_buffer_pending_with_shedding(
+                    buffer.max_in_flight,
+                    DispatchDeadline::extract,
+                    buffer_drain.clone(),
+                    buffer_shed.clone(),
+                )
                 .push(router::Layer::new(
-                    router::Config::new(router_capacity, router_max_idle_age),
+                    router::Config::new(router_capacity, router_max_idle_age)
+                        .with_shards(router_shards),
                     |req: &http::Request<_>| {
                         http_request_l5d_override_dst_addr(req)
                             .map(|override_addr| {
@@ -307,12 +780,25 @@ impl<A: OrigDstAddr> Config<A> {
                 ))
                 .into_inner()
                 .spawn();
-
-            // Share a single semaphore across all requests to signal when
-            // the proxy is overloaded.
+            metrics
+                .stack_state
+                .register_cache("outbound.addr", addr_router.cache_size());
+            metrics
+                .cache_lock_wait
+                .register("outbound.addr", addr_router.lock_wait());
+
+            // Conflicts between the destination headers used above are
+            // detected before the request is routed, so that rejected
+            // requests never consume router capacity.
+            let dst_conflict_filter =
+                dst_conflict::Filter::new(dst_conflict_policy, metrics.dst_conflict.clone());
+
+            // Share a single admission limit across all requests so that they
+            // are shed when the proxy is overloaded.
+            let limit = admission::Limit::new(buffer.max_in_flight);
             let admission_control = svc::stack(addr_router)
-                .push_concurrency_limit(buffer.max_in_flight)
-                .push_load_shed();
+                .push(request_filter::layer(dst_conflict_filter))
+                .push(admission::layer(limit));
 
             // Instantiates an HTTP service for each `tls::accept::Meta` using the
             // shared `addr_router`. The `tls::accept::Meta` is stored in the request's
@@ -322,39 +808,96 @@ impl<A: OrigDstAddr> Config<A> {
                     DispatchDeadline::after(buffer.dispatch_timeout)
                 }))
                 .push(http::insert::target::layer())
-                .push(errors::layer())
+                // The local application may override its routes' retries and
+                // timeouts, if configured.
+                .push(caller_override::layer(
+                    caller_overrides,
+                    caller_override::Trust::All,
+                    metrics.caller_override.clone(),
+                ))
+                .push(errors::layer(
+                    error_log,
+                    metrics.invariant_violations.clone(),
+                ))
+                .push(http::sanitize_response::layer(strip_response_headers))
+                .push(http::expect_continue::layer())
+                .push(
+                    http::correlation_id::layer(correlation_id_header)
+                        .with_echo(correlation_id_echo),
+                )
                 .push(trace::layer(
                     |src: &tls::accept::Meta| info_span!("source", target.addr = %src.addrs.target_addr()),
                 ))
+                .push(http::outcome::layer())
                 .push(trace_context::layer(span_sink.map(|span_sink| {
                     SpanConverter::server(span_sink, trace_labels())
                 })))
-                .push(metrics.http_handle_time.layer());
+                .push(metrics.http_handle_time.layer())
+                .push(metrics.stack_stage.layer("outbound.server"));
 
-            let forward_tcp = tcp::Forward::new(
+            // Opaque TCP connections are forwarded within a connection-scoped
+            // span, on which the forwarded byte counts are recorded once the
+            // connection closes.
+            let forward_tcp = svc::stack(tcp::Forward::new(
                 svc::stack(connect_stack)
                     .push(svc::map_target::layer(|meta: tls::accept::Meta| {
                         Endpoint::from(meta.addrs.target_addr())
                     }))
                     .into_inner(),
-            );
-
+            ))
+            .push(trace::accept::layer(|meta: &tls::accept::Meta| {
+                info_span!(
+                    "forward",
+                    endpoint.addr = %meta.addrs.target_addr(),
+                    peer.id = ?meta.peer_identity,
+                    bytes.sent = 0u64,
+                    bytes.received = 0u64,
+                )
+            }))
+            .into_inner();
+
+            metrics
+                .stack_state
+                .register_skip_ports("outbound", disable_protocol_detection_for_ports.clone());
             let proxy = Server::new(
                 TransportLabels,
-                metrics.transport,
+                metrics.transport.clone(),
                 forward_tcp,
                 server_stack,
                 h2_settings,
                 drain.clone(),
                 disable_protocol_detection_for_ports.clone(),
+                metrics.tls_passthrough,
+                max_requests_per_connection,
+                h2_reset_limit,
+                idle_timeouts,
             );
 
             let no_tls: tls::Conditional<identity::Local> =
                 Conditional::None(tls::ReasonForNoPeerName::Loopback.into());
             let accept = tls::AcceptTls::new(no_tls, proxy)
-                .with_skip_ports(disable_protocol_detection_for_ports);
+                .with_skip_ports(disable_protocol_detection_for_ports.clone());
+            let accept = accept_timeout::AcceptTimeout::new(
+                idle_timeouts.accept,
+                transport::labels::Key::accept::<()>(
+                    "outbound",
+                    Conditional::None(tls::ReasonForNoPeerName::Loopback.into()),
+                ),
+                metrics.transport,
+                accept,
+            )
+            .with_skip_ports(disable_protocol_detection_for_ports);
+
+            constructed.complete(accept);
+            Ok(())
+        });
 
-            serve::serve(listen, accept, drain)
+        let serve = Box::new(future::lazy(move || {
+            tokio::spawn(construct);
+            match accept_watch {
+                Some(watch) => serve::serve_watched(listen, bind, gate, watch, serve_drain),
+                None => serve::serve(listen, gate, serve_drain),
+            }
         }));
 
         Ok(Outbound { listen_addr, serve })
@@ -368,6 +911,12 @@ impl transport::metrics::TransportLabels<Endpoint> for TransportLabels {
     type Labels = transport::labels::Key;
 
     fn transport_labels(&self, endpoint: &Endpoint) -> Self::Labels {
+        if endpoint.tls_origination.is_some() {
+            return transport::labels::Key::originate("outbound");
+        }
+        if endpoint.tls_uri_san.is_some() {
+            return transport::labels::Key::connect_uri_san("outbound");
+        }
         transport::labels::Key::connect("outbound", endpoint.identity.as_ref())
     }
 }
@@ -385,3 +934,29 @@ pub fn trace_labels() -> HashMap<String, String> {
     l.insert("direction".to_string(), "outbound".to_string());
     l
 }
+
+/// Identifies the client of a request by its peer identity or, if it has none,
+/// by its IP address.
+fn coalesce_client(extensions: &::http::Extensions) -> Option<String> {
+    let meta = extensions.get::<tls::accept::Meta>()?;
+    match meta.peer_identity {
+        Conditional::Some(ref id) => Some(id.to_string()),
+        Conditional::None(_) => Some(meta.addrs.peer().ip().to_string()),
+    }
+}
+
+/// Indicates whether a request failed because the balancer could not
+/// dispatch it, either because it had no available endpoints before the
+/// request's dispatch deadline or because it shed the request while
+/// unavailable. Only such requests may fail over to a backup.
+fn is_undispatched(e: &Error) -> bool {
+    use linkerd2_app_core::proxy::buffer;
+    use tower::load_shed::error::Overloaded;
+
+    e.is::<buffer::Aborted>() || e.is::<Overloaded>()
+}
+
+/// Derives a new RNG from `rng`.
+fn fork_rng(rng: &mut SmallRng) -> SmallRng {
+    SmallRng::from_rng(rng).expect("SmallRng must not fail to seed")
+}
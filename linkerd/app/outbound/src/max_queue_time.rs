@@ -0,0 +1,225 @@
+//! Limits the time that requests to a logical destination may spend queued.
+//!
+//! Requests are queued in several buffers on their way through a
+//! destination's stack. When a destination has a maximum queue time, each of
+//! its requests' dispatch deadlines is limited to that time, so that the
+//! request is failed with a 503 by whichever buffer holds it once the time
+//! elapses. This is applied before requests are routed to their destination
+//! stack, so that the time spent queued for the destination's stack counts.
+//!
+//! Unlike a request timeout, this only bounds the time a request waits to be
+//! dispatched; a request that has been dispatched is not affected.
+
+use indexmap::IndexMap;
+use linkerd2_app_core::{svc, Addr, DispatchDeadline, NameAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::trace;
+
+#[derive(Clone, Debug)]
+pub struct Layer {
+    limits: Arc<IndexMap<NameAddr, Duration>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Service<S> {
+    inner: S,
+    limits: Arc<IndexMap<NameAddr, Duration>>,
+}
+
+// === impl Layer ===
+
+/// Limits the queue time of requests to each destination in `limits`.
+pub fn layer(limits: IndexMap<NameAddr, Duration>) -> Layer {
+    Layer {
+        limits: Arc::new(limits),
+    }
+}
+
+impl<S> svc::Layer<S> for Layer {
+    type Service = Service<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Service {
+            inner,
+            limits: self.limits.clone(),
+        }
+    }
+}
+
+// === impl Service ===
+
+impl<S, B> svc::Service<http::Request<B>> for Service<S>
+where
+    S: svc::Service<http::Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> futures::Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut req: http::Request<B>) -> Self::Future {
+        let limit = req
+            .extensions()
+            .get::<Addr>()
+            .and_then(Addr::name_addr)
+            .and_then(|dst| self.limits.get(dst))
+            .cloned();
+        if let Some(limit) = limit {
+            trace!(?limit, "limiting queue time");
+            DispatchDeadline::limit(&mut req, limit);
+        }
+
+        self.inner.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{future, Async, Future, Poll};
+    use linkerd2_app_core::{proxy::buffer, svc::Service as _, Error};
+    use std::time::Instant;
+    use tokio::runtime::current_thread::Runtime;
+
+    type Deadline = fn(&http::Request<()>) -> Option<Instant>;
+
+    type Buffer<S> = buffer::Enqueue<S, Deadline, http::Request<()>>;
+
+    /// A target that never becomes ready, so that its requests stay queued.
+    struct Slow;
+
+    /// A target that serves requests immediately.
+    struct Fast;
+
+    /// Dispatches requests to the target named by their `Addr`.
+    struct Targets {
+        slow: Buffer<Slow>,
+        fast: Buffer<Fast>,
+    }
+
+    impl svc::Service<http::Request<()>> for Slow {
+        type Response = http::Response<()>;
+        type Error = Error;
+        type Future = future::FutureResult<Self::Response, Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Error> {
+            Ok(Async::NotReady)
+        }
+
+        fn call(&mut self, _: http::Request<()>) -> Self::Future {
+            unreachable!("slow target is never ready")
+        }
+    }
+
+    impl svc::Service<http::Request<()>> for Fast {
+        type Response = http::Response<()>;
+        type Error = Error;
+        type Future = future::FutureResult<Self::Response, Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: http::Request<()>) -> Self::Future {
+            future::ok(http::Response::new(()))
+        }
+    }
+
+    impl svc::Service<http::Request<()>> for Targets {
+        type Response = http::Response<()>;
+        type Error = Error;
+        type Future = Box<dyn Future<Item = Self::Response, Error = Error>>;
+
+        fn poll_ready(&mut self) -> Poll<(), Error> {
+            let slow = self.slow.poll_ready()?.is_ready();
+            let fast = self.fast.poll_ready()?.is_ready();
+            Ok(if slow && fast {
+                Async::Ready(())
+            } else {
+                Async::NotReady
+            })
+        }
+
+        fn call(&mut self, req: http::Request<()>) -> Self::Future {
+            let addr = req.extensions().get::<Addr>().expect("must have addr");
+            if addr.to_string().starts_with("slow.") {
+                Box::new(self.slow.call(req))
+            } else {
+                Box::new(self.fast.call(req))
+            }
+        }
+    }
+
+    fn req(dst: &str) -> http::Request<()> {
+        let mut req = http::Request::new(());
+        req.extensions_mut()
+            .insert(Addr::from_str(dst).expect("must be a valid address"));
+        req
+    }
+
+    #[test]
+    fn slow_target_is_shed_while_fast_target_is_served() {
+        let mut rt = Runtime::new().unwrap();
+        let mut limits = IndexMap::new();
+        limits.insert(
+            NameAddr::from_str("slow.ns.svc.cluster.local:8080").unwrap(),
+            Duration::from_millis(50),
+        );
+        limits.insert(
+            NameAddr::from_str("fast.ns.svc.cluster.local:8080").unwrap(),
+            Duration::from_millis(50),
+        );
+
+        let (slow, fast) = rt
+            .block_on(future::lazy(move || {
+                let extract: Deadline = DispatchDeadline::extract;
+                let targets = Targets {
+                    slow: buffer::Enqueue::new(Slow, extract, 10),
+                    fast: buffer::Enqueue::new(Fast, extract, 10),
+                };
+                let mut svc = svc::Layer::layer(&layer(limits), targets);
+
+                svc.poll_ready().expect("must be ready");
+                let slow = svc.call(req("slow.ns.svc.cluster.local:8080"));
+                svc.poll_ready().expect("must be ready");
+                let fast = svc.call(req("fast.ns.svc.cluster.local:8080"));
+                slow.then(Ok::<_, Error>).join(fast.then(Ok))
+            }))
+            .expect("requests must complete");
+
+        let err = slow.expect_err("slow target's request must be shed");
+        assert!(err.is::<buffer::Aborted>(), "unexpected error: {}", err);
+        fast.expect("fast target's request must be served");
+    }
+
+    #[test]
+    fn requests_to_other_targets_keep_their_deadline() {
+        let mut limits = IndexMap::new();
+        limits.insert(
+            NameAddr::from_str("slow.ns.svc.cluster.local:8080").unwrap(),
+            Duration::from_millis(50),
+        );
+        let mut svc = svc::Layer::layer(
+            &layer(limits),
+            svc::mk(|req: http::Request<()>| {
+                future::ok::<_, Error>(DispatchDeadline::extract(&req))
+            }),
+        );
+
+        let deadline = svc
+            .call(req("other.ns.svc.cluster.local:8080"))
+            .wait()
+            .unwrap();
+        assert!(deadline.is_none(), "other targets must not be limited");
+
+        let deadline = svc
+            .call(req("slow.ns.svc.cluster.local:8080"))
+            .wait()
+            .unwrap();
+        assert!(deadline.is_some(), "limited targets must have a deadline");
+    }
+}
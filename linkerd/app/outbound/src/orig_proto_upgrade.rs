@@ -1,9 +1,13 @@
 use super::Endpoint;
+use crate::core::orig_proto_coverage::Coverage;
+use crate::proxy::core::resolve;
 use crate::proxy::http::{orig_proto, settings::Settings};
 use crate::svc;
-use futures::{try_ready, Future, Poll};
+use futures::{try_ready, Async, Future, Poll};
 use http;
+use indexmap::IndexMap;
 use std::marker::PhantomData;
+use std::net::SocketAddr;
 use tracing::trace;
 
 #[derive(Debug)]
@@ -21,6 +25,25 @@ pub struct MakeFuture<F, A, B> {
     _marker: PhantomData<fn(A) -> B>,
 }
 
+/// Records whether each resolved endpoint can use orig-proto in `Coverage`.
+#[derive(Clone, Debug)]
+pub struct Resolve<R> {
+    inner: R,
+    coverage: Coverage,
+}
+
+pub struct ResolveFuture<F> {
+    inner: F,
+    coverage: Coverage,
+}
+
+/// Removes its endpoints from `Coverage` when dropped.
+pub struct Resolution<R> {
+    inner: R,
+    coverage: Coverage,
+    resolved: IndexMap<SocketAddr, bool>,
+}
+
 pub fn layer<A, B>() -> Layer<A, B> {
     Layer(PhantomData)
 }
@@ -109,3 +132,194 @@ where
         }
     }
 }
+
+// === impl Resolve ===
+
+impl<R> Resolve<R> {
+    pub fn new(inner: R, coverage: Coverage) -> Self {
+        Self { inner, coverage }
+    }
+}
+
+impl<T, R> tower::Service<T> for Resolve<R>
+where
+    R: resolve::Resolve<T, Endpoint = Endpoint>,
+{
+    type Response = Resolution<R::Resolution>;
+    type Error = R::Error;
+    type Future = ResolveFuture<R::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        ResolveFuture {
+            inner: self.inner.resolve(target),
+            coverage: self.coverage.clone(),
+        }
+    }
+}
+
+// === impl ResolveFuture ===
+
+impl<F> Future for ResolveFuture<F>
+where
+    F: Future,
+    F::Item: resolve::Resolution<Endpoint = Endpoint>,
+{
+    type Item = Resolution<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        Ok(Async::Ready(Resolution {
+            inner,
+            coverage: self.coverage.clone(),
+            resolved: IndexMap::default(),
+        }))
+    }
+}
+
+// === impl Resolution ===
+
+impl<R> Resolution<R> {
+    fn record(&mut self, update: &resolve::Update<Endpoint>) {
+        match update {
+            resolve::Update::Add(eps) => self.add(eps),
+            resolve::Update::Remove(addrs) => {
+                for addr in addrs.iter() {
+                    if let Some(capable) = self.resolved.remove(addr) {
+                        self.coverage.remove(capable);
+                    }
+                }
+            }
+            resolve::Update::Reset(eps) => {
+                self.clear();
+                self.add(eps);
+            }
+            resolve::Update::Empty | resolve::Update::DoesNotExist => self.clear(),
+        }
+    }
+
+    fn add(&mut self, eps: &[(SocketAddr, Endpoint)]) {
+        for (addr, ep) in eps.iter() {
+            // Endpoints aren't resolved for non-HTTP targets, but
+            // `can_use_orig_proto` must not be asked about them.
+            let capable = match ep.http_settings {
+                Settings::NotHttp => false,
+                _ => ep.can_use_orig_proto(),
+            };
+            // An endpoint that is added again replaces its prior record.
+            if let Some(prior) = self.resolved.insert(*addr, capable) {
+                self.coverage.remove(prior);
+            }
+            self.coverage.add(capable);
+        }
+    }
+
+    fn clear(&mut self) {
+        for (_, capable) in self.resolved.drain(..) {
+            self.coverage.remove(capable);
+        }
+    }
+}
+
+impl<R: resolve::Resolution<Endpoint = Endpoint>> resolve::Resolution for Resolution<R> {
+    type Endpoint = Endpoint;
+    type Error = R::Error;
+
+    fn poll(&mut self) -> Poll<resolve::Update<Self::Endpoint>, Self::Error> {
+        let update = try_ready!(self.inner.poll());
+        self.record(&update);
+        Ok(Async::Ready(update))
+    }
+
+    fn poll_tagged(&mut self) -> Poll<resolve::Tagged<Self::Endpoint>, Self::Error> {
+        let tagged = try_ready!(self.inner.poll_tagged());
+        self.record(&tagged.update);
+        Ok(Async::Ready(tagged))
+    }
+}
+
+impl<R> Drop for Resolution<R> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::api_resolve::{Metadata, ProtocolHint};
+    use crate::proxy::core::resolve::{Resolution as _, Update};
+    use futures::future;
+    use linkerd2_app_core::Error;
+    use std::collections::VecDeque;
+
+    /// Yields each of its updates, once.
+    struct Updates(VecDeque<Update<Endpoint>>);
+
+    impl resolve::Resolution for Updates {
+        type Endpoint = Endpoint;
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<Update<Endpoint>, Error> {
+            match self.0.pop_front() {
+                Some(update) => Ok(Async::Ready(update)),
+                None => Ok(Async::NotReady),
+            }
+        }
+    }
+
+    fn endpoint(addr: &str, hint: ProtocolHint) -> (SocketAddr, Endpoint) {
+        let addr = addr.parse::<SocketAddr>().unwrap();
+        let mut ep = Endpoint::from(addr);
+        ep.metadata = Metadata::new(Default::default(), hint, None, 10_000);
+        ep.http_settings = Settings::Http1 {
+            keep_alive: true,
+            wants_h1_upgrade: false,
+            was_absolute_form: false,
+            is_http10: false,
+            wants_trailers: false,
+        };
+        (addr, ep)
+    }
+
+    #[test]
+    fn coverage_tracks_resolved_endpoints() {
+        let coverage = Coverage::default();
+        let updates = vec![
+            Update::Add(vec![endpoint("10.1.1.1:8080", ProtocolHint::Http2)]),
+            Update::Add(vec![
+                endpoint("10.1.1.2:8080", ProtocolHint::Unknown),
+                endpoint("10.1.1.3:8080", ProtocolHint::Unknown),
+                endpoint("10.1.1.4:8080", ProtocolHint::Http2),
+            ]),
+            Update::Remove(vec!["10.1.1.2:8080".parse().unwrap()]),
+            Update::Reset(vec![endpoint("10.1.1.5:8080", ProtocolHint::Unknown)]),
+        ];
+        let mut resolve = Resolve::new(
+            svc::mk(move |_: ()| future::ok::<_, Error>(Updates(updates.clone().into()))),
+            coverage.clone(),
+        );
+        let mut resolution = tower::Service::call(&mut resolve, ())
+            .wait()
+            .expect("resolution");
+
+        resolution.poll().unwrap();
+        assert_eq!(coverage.ratio(), Some(1.0));
+
+        resolution.poll().unwrap();
+        assert_eq!(coverage.ratio(), Some(0.5));
+
+        resolution.poll().unwrap();
+        assert_eq!(coverage.ratio(), Some(2.0 / 3.0));
+
+        resolution.poll().unwrap();
+        assert_eq!(coverage.ratio(), Some(0.0));
+
+        drop(resolution);
+        assert_eq!(coverage.ratio(), None);
+    }
+}
@@ -1,33 +1,44 @@
 use crate::identity::LocalIdentity;
+use indexmap::IndexSet;
 use linkerd2_app_core::{
     admin, config::ServerConfig, drain, metrics::FmtMetrics, serve, trace::LevelHandle,
     transport::tls, Error,
 };
 use std::net::SocketAddr;
+use std::sync::Arc;
 use std::time::Duration;
 
 #[derive(Clone, Debug)]
 pub struct Config {
     pub server: ServerConfig,
     pub metrics_retain_idle: Duration,
+    pub metrics_rate_window: Duration,
+    /// Route labels that are omitted from the summary metrics report.
+    pub metrics_summary_drop_route_labels: Arc<IndexSet<String>>,
+    /// If set, one in every N requests is timed in each stack stage.
+    pub metrics_stack_stage_sample: Option<usize>,
 }
 
 pub struct Admin {
     pub listen_addr: SocketAddr,
     pub latch: admin::Latch,
+    pub readiness: admin::Readiness,
     pub serve: serve::Task,
 }
 
 impl Config {
-    pub fn build<R>(
+    pub fn build<R, S>(
         self,
         identity: LocalIdentity,
         report: R,
+        summary: S,
+        stack_state: admin::StackState,
         log_level: LevelHandle,
         drain: drain::Watch,
     ) -> Result<Admin, Error>
     where
         R: FmtMetrics + Clone + Send + 'static,
+        S: FmtMetrics + Clone + Send + 'static,
     {
         use linkerd2_app_core::proxy::core::listen::{Bind, Listen};
 
@@ -35,12 +46,15 @@ impl Config {
         let listen_addr = listen.listen_addr();
 
         let (ready, latch) = admin::Readiness::new();
-        let admin = admin::Admin::new(report, ready, log_level);
+        let admin = admin::Admin::new(report, ready.clone(), log_level)
+            .with_summary(summary)
+            .with_stack_state(stack_state);
         let accept = tls::AcceptTls::new(identity, admin.into_accept());
         let serve = serve::serve(listen, accept, drain);
         Ok(Admin {
             listen_addr,
             latch,
+            readiness: ready,
             serve,
         })
     }
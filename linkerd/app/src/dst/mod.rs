@@ -1,9 +1,12 @@
 mod resolve;
+pub mod static_endpoints;
 
 use indexmap::IndexSet;
 use linkerd2_app_core::{
     config::{ControlAddr, ControlConfig},
-    dns, profiles, Error,
+    dns, profiles,
+    proxy::http::profiles::negative_cache,
+    Error,
 };
 use std::time::Duration;
 use tower_grpc::{generic::client::GrpcService, Body, BoxBody};
@@ -15,6 +18,14 @@ pub struct Config {
     pub get_suffixes: IndexSet<dns::Suffix>,
     pub get_networks: IndexSet<ipnet::IpNet>,
     pub profile_suffixes: IndexSet<dns::Suffix>,
+    pub profile_retry: profiles::Retry,
+    pub profile_max_concurrent_rebuilds: usize,
+    /// If set, at most this many routes are applied from each profile.
+    pub profile_max_routes: Option<usize>,
+    /// If set, destinations that have no profile are not looked up again for
+    /// this long.
+    pub profile_negative_cache_ttl: Option<Duration>,
+    pub static_endpoints: Option<static_endpoints::Config>,
 }
 
 /// Handles to destination service clients.
@@ -22,13 +33,22 @@ pub struct Config {
 /// The addr is preserved for logging.
 pub struct Dst<S> {
     pub addr: ControlAddr,
-    pub profiles: profiles::Client<S>,
-    pub resolve: resolve::Resolve<S>,
+    pub profiles: negative_cache::GetRoutes<profiles::Client<S>>,
+    pub resolve: static_endpoints::Resolve<resolve::Resolve<S>>,
+    /// Reloads the static endpoint table, if its file is watched.
+    pub static_endpoints: Option<static_endpoints::Watch>,
 }
 
 impl Config {
     // XXX This is unfortunate -- the service should be built here, but it's annoying to name.
-    pub fn build<S>(self, svc: S) -> Result<Dst<S>, Error>
+    pub fn build<S>(
+        self,
+        svc: S,
+        profile_updates: profiles::Updates,
+        profile_rebuilds: profiles::Rebuilds,
+        profile_route_truncations: profiles::Truncations,
+        profile_negative_cache: negative_cache::NegativeCache,
+    ) -> Result<Dst<S>, Error>
     where
         S: GrpcService<BoxBody> + Clone + Send + 'static,
         S::ResponseBody: Send,
@@ -42,18 +62,25 @@ impl Config {
             &self.context,
             self.control.connect.backoff,
         );
+        // Names in the static endpoint table are never resolved by the
+        // destination service.
+        let (resolve, static_endpoints) = self.static_endpoints.unwrap_or_default().build(resolve);
 
-        const DUMB_PROFILE_BACKOFF: Duration = Duration::from_secs(3);
-        let profiles = profiles::Client::new(
-            svc,
-            DUMB_PROFILE_BACKOFF,
-            self.context,
-            self.profile_suffixes,
+        let profiles =
+            profiles::Client::new(svc, self.profile_retry, self.context, self.profile_suffixes)
+                .with_updates(profile_updates)
+                .with_rebuild_limit(self.profile_max_concurrent_rebuilds, profile_rebuilds)
+                .with_max_routes(self.profile_max_routes, profile_route_truncations);
+        let profiles = negative_cache::GetRoutes::new(
+            profiles,
+            profile_negative_cache,
+            self.profile_negative_cache_ttl,
         );
 
         Ok(Dst {
             addr: self.control.addr,
             resolve,
+            static_endpoints,
             profiles,
         })
     }
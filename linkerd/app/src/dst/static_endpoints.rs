@@ -0,0 +1,494 @@
+//! Resolves names from a static endpoint table, read from a file.
+//!
+//! This allows specific names to be resolved where the destination service
+//! isn't available (e.g. while the control plane itself is bootstrapped).
+//! Each non-empty line of the file that isn't a `#` comment maps an authority
+//! to its endpoints:
+//!
+//! ```text
+//! web.ns.svc.cluster.local:8080 10.1.1.1:8080 10.1.1.2:8080;identity=web.ns.serviceaccount.identity.linkerd.cluster.local;weight=20000
+//! ```
+//!
+//! Each endpoint is an address, optionally followed by the `identity` that its
+//! TLS certificate is verified against and by its relative `weight`.
+//!
+//! Names in the table are resolved from it; all others are resolved by the
+//! inner resolver. If the file is watched, edits replace the table and each
+//! active resolution of a name in the table is reset to the name's new
+//! endpoints. A resolution of a name that is removed from the table becomes
+//! empty; it isn't handed to the inner resolver.
+
+use futures::{try_ready, Async, Future, Poll, Stream};
+use indexmap::IndexMap;
+use linkerd2_app_core::{
+    dst::DstAddr,
+    proxy::{
+        api_resolve::{Metadata, ProtocolHint},
+        core::resolve::{self, Update},
+        identity,
+    },
+    svc, Addr, Error, NameAddr,
+};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use std::{fmt, fs};
+use tokio::{
+    sync::watch,
+    timer::{self, Interval},
+};
+use tracing::{debug, info, warn};
+
+/// The weight of endpoints that don't specify one.
+const DEFAULT_WEIGHT: u32 = 10_000;
+
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    pub path: PathBuf,
+    /// The table, as it was read when the proxy was configured.
+    pub table: Table,
+    /// If set, the file is re-read on this interval.
+    pub watch_interval: Option<Duration>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Table(IndexMap<NameAddr, Vec<(SocketAddr, Metadata)>>);
+
+#[derive(Debug)]
+pub struct InvalidTable {
+    line: usize,
+    reason: &'static str,
+}
+
+/// Re-reads the file on an interval, publishing each new table.
+pub struct Watch {
+    path: PathBuf,
+    interval: Interval,
+    tx: watch::Sender<Arc<Table>>,
+    current: Arc<Table>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Resolve<R> {
+    table: watch::Receiver<Arc<Table>>,
+    inner: R,
+}
+
+pub enum ResolveFuture<F> {
+    Static(Option<Static>),
+    Inner(F),
+}
+
+pub enum Resolution<R> {
+    Static(Static),
+    Inner(R),
+}
+
+/// Resolves a name in the table.
+pub struct Static {
+    name: NameAddr,
+    table: watch::Receiver<Arc<Table>>,
+    pending: Option<Update<Metadata>>,
+}
+
+// === impl Config ===
+
+impl Config {
+    /// Returns a resolver that resolves names in the table, along with a task
+    /// that must be spawned if the file is watched.
+    pub fn build<R>(self, inner: R) -> (Resolve<R>, Option<Watch>) {
+        let current = Arc::new(self.table);
+        let (tx, rx) = watch::channel(current.clone());
+        let watch = self.watch_interval.map(|interval| Watch {
+            path: self.path,
+            interval: Interval::new_interval(interval),
+            tx,
+            current,
+        });
+        (Resolve { table: rx, inner }, watch)
+    }
+}
+
+// === impl Table ===
+
+impl Table {
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        let contents = fs::read_to_string(path)?;
+        Ok(contents.parse()?)
+    }
+
+    /// Returns the name's endpoints, or `None` if the name isn't in the table.
+    fn resolve(&self, name: &NameAddr) -> Option<Update<Metadata>> {
+        self.0.get(name).map(|endpoints| {
+            if endpoints.is_empty() {
+                Update::Empty
+            } else {
+                Update::Reset(endpoints.clone())
+            }
+        })
+    }
+}
+
+impl FromStr for Table {
+    type Err = InvalidTable;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut table = IndexMap::new();
+        for (i, line) in s.lines().enumerate() {
+            let invalid = |reason| InvalidTable {
+                line: i + 1,
+                reason,
+            };
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let name = fields
+                .next()
+                .and_then(|n| NameAddr::from_str(n).ok())
+                .ok_or_else(|| invalid("expected an authority (NAME:PORT)"))?;
+            let mut endpoints = Vec::new();
+            for endpoint in fields {
+                endpoints.push(parse_endpoint(endpoint).map_err(invalid)?);
+            }
+            table.insert(name, endpoints);
+        }
+        Ok(Table(table))
+    }
+}
+
+fn parse_endpoint(s: &str) -> Result<(SocketAddr, Metadata), &'static str> {
+    let mut parts = s.split(';');
+    let addr = parts
+        .next()
+        .and_then(|a| a.parse::<SocketAddr>().ok())
+        .ok_or("expected an endpoint address (IP:PORT)")?;
+
+    let mut identity = None;
+    let mut weight = DEFAULT_WEIGHT;
+    for opt in parts {
+        let mut kv = opt.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some("identity"), Some(name)) => {
+                let name = identity::Name::from_hostname(name.as_bytes())
+                    .map_err(|_| "invalid identity")?;
+                identity = Some(name);
+            }
+            (Some("weight"), Some(w)) => {
+                weight = w.parse().map_err(|_| "invalid weight")?;
+            }
+            _ => return Err("expected identity=NAME or weight=WEIGHT"),
+        }
+    }
+
+    let meta = Metadata::new(IndexMap::new(), ProtocolHint::Unknown, identity, weight);
+    Ok((addr, meta))
+}
+
+// === impl InvalidTable ===
+
+impl fmt::Display for InvalidTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+impl std::error::Error for InvalidTable {}
+
+// === impl Watch ===
+
+impl Watch {
+    /// Re-reads the table, returning true if it changed.
+    fn reload(&mut self) -> bool {
+        let table = match Table::read(&self.path) {
+            Ok(table) => table,
+            Err(error) => {
+                // The current table is kept until the file is valid again.
+                warn!(path = %self.path.display(), %error, "failed to read endpoints");
+                return false;
+            }
+        };
+        if table == *self.current {
+            return false;
+        }
+
+        info!(path = %self.path.display(), names = %table.0.len(), "updated endpoints");
+        self.current = Arc::new(table);
+        true
+    }
+}
+
+impl Future for Watch {
+    type Item = ();
+    type Error = timer::Error;
+
+    fn poll(&mut self) -> Poll<(), timer::Error> {
+        loop {
+            if try_ready!(self.interval.poll()).is_none() {
+                return Ok(Async::Ready(()));
+            }
+
+            if self.reload() && self.tx.broadcast(self.current.clone()).is_err() {
+                debug!("resolver dropped");
+                return Ok(Async::Ready(()));
+            }
+        }
+    }
+}
+
+// === impl Resolve ===
+
+impl<R> svc::Service<DstAddr> for Resolve<R>
+where
+    R: resolve::Resolve<DstAddr, Endpoint = Metadata>,
+    R::Error: Into<Error>,
+{
+    type Response = Resolution<R::Resolution>;
+    type Error = Error;
+    type Future = ResolveFuture<R::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, dst: DstAddr) -> Self::Future {
+        if let Addr::Name(ref name) = dst.dst_concrete() {
+            // The receiver is cloned before the table is read so that the
+            // resolution observes every later change to the table.
+            let table = self.table.clone();
+            let update = table.get_ref().resolve(name);
+            if let Some(update) = update {
+                debug!(%name, "resolving from static endpoints");
+                return ResolveFuture::Static(Some(Static {
+                    name: name.clone(),
+                    table,
+                    pending: Some(update),
+                }));
+            }
+        }
+
+        ResolveFuture::Inner(self.inner.resolve(dst))
+    }
+}
+
+// === impl ResolveFuture ===
+
+impl<F> Future for ResolveFuture<F>
+where
+    F: Future,
+    F::Error: Into<Error>,
+{
+    type Item = Resolution<F::Item>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self {
+            ResolveFuture::Static(resolution) => {
+                let resolution = resolution.take().expect("polled after ready");
+                Ok(Async::Ready(Resolution::Static(resolution)))
+            }
+            ResolveFuture::Inner(f) => {
+                let inner = try_ready!(f.poll().map_err(Into::into));
+                Ok(Async::Ready(Resolution::Inner(inner)))
+            }
+        }
+    }
+}
+
+// === impl Resolution ===
+
+impl<R> resolve::Resolution for Resolution<R>
+where
+    R: resolve::Resolution<Endpoint = Metadata>,
+{
+    type Endpoint = Metadata;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Update<Metadata>, Self::Error> {
+        match self {
+            Resolution::Static(s) => Ok(s.poll()),
+            Resolution::Inner(r) => r.poll().map_err(Into::into),
+        }
+    }
+}
+
+// === impl Static ===
+
+impl Static {
+    fn poll(&mut self) -> Async<Update<Metadata>> {
+        if let Some(update) = self.pending.take() {
+            return Async::Ready(update);
+        }
+
+        match self.table.poll() {
+            Ok(Async::Ready(Some(table))) => {
+                debug!(name = %self.name, "static endpoints changed");
+                Async::Ready(table.resolve(&self.name).unwrap_or(Update::Empty))
+            }
+            // If the file isn't watched, the table never changes.
+            Ok(Async::Ready(None)) | Ok(Async::NotReady) | Err(_) => Async::NotReady,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use linkerd2_app_core::{proxy::http::Settings, svc::Service};
+    use tokio::runtime::current_thread::Runtime;
+
+    const TABLE: &str = "
+        # Bootstrap endpoints.
+        web.ns.svc.cluster.local:8080 10.1.1.1:8080 \
+            10.1.1.2:8080;identity=web.ns.serviceaccount.identity.linkerd.cluster.local;weight=20000
+    ";
+
+    /// An inner resolver that resolves every name to a single endpoint.
+    #[derive(Clone)]
+    struct Inner;
+
+    struct InnerResolution(Option<Update<Metadata>>);
+
+    impl Service<DstAddr> for Inner {
+        type Response = InnerResolution;
+        type Error = Error;
+        type Future = future::FutureResult<InnerResolution, Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: DstAddr) -> Self::Future {
+            let ep = ("10.2.2.2:80".parse().unwrap(), Metadata::empty());
+            future::ok(InnerResolution(Some(Update::Add(vec![ep]))))
+        }
+    }
+
+    impl resolve::Resolution for InnerResolution {
+        type Endpoint = Metadata;
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<Update<Metadata>, Error> {
+            Ok(self.0.take().map(Async::Ready).unwrap_or(Async::NotReady))
+        }
+    }
+
+    fn dst(name: &str) -> DstAddr {
+        DstAddr::outbound(Addr::from_str(name).unwrap(), Settings::Http2)
+    }
+
+    fn addrs(update: &Update<Metadata>) -> Vec<SocketAddr> {
+        match update {
+            Update::Reset(eps) | Update::Add(eps) => eps.iter().map(|(a, _)| *a).collect(),
+            _ => vec![],
+        }
+    }
+
+    fn poll_update<R: resolve::Resolution>(r: &mut R) -> Option<Update<R::Endpoint>> {
+        match r.poll().ok().expect("must not fail") {
+            Async::Ready(update) => Some(update),
+            Async::NotReady => None,
+        }
+    }
+
+    #[test]
+    fn resolves_names_in_table() {
+        let table = TABLE.parse::<Table>().expect("table must parse");
+        let config = Config {
+            path: PathBuf::new(),
+            table,
+            watch_interval: None,
+        };
+        let (mut resolver, watch) = config.build(Inner);
+        assert!(watch.is_none());
+
+        let mut rt = Runtime::new().unwrap();
+        let mut res = rt
+            .block_on(resolver.call(dst("web.ns.svc.cluster.local:8080")))
+            .unwrap();
+        let update = rt.block_on(future::lazy(|| Ok::<_, ()>(poll_update(&mut res))));
+        let update = update.unwrap().expect("must be reset");
+        assert_eq!(
+            addrs(&update),
+            vec![
+                "10.1.1.1:8080".parse::<SocketAddr>().unwrap(),
+                "10.1.1.2:8080".parse().unwrap()
+            ]
+        );
+        match update {
+            Update::Reset(eps) => {
+                assert_eq!(eps[0].1.identity(), None);
+                assert_eq!(
+                    eps[1].1.identity().map(|id| id.as_ref().to_string()),
+                    Some("web.ns.serviceaccount.identity.linkerd.cluster.local".to_string())
+                );
+            }
+            update => panic!("unexpected update: {:?}", update),
+        }
+
+        // Other names are resolved by the inner resolver.
+        let mut res = rt
+            .block_on(resolver.call(dst("api.ns.svc.cluster.local:8080")))
+            .unwrap();
+        let update = rt.block_on(future::lazy(|| Ok::<_, ()>(poll_update(&mut res))));
+        assert_eq!(
+            addrs(&update.unwrap().expect("must be added")),
+            vec!["10.2.2.2:80".parse::<SocketAddr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_tables() {
+        for invalid in &[
+            "web.ns.svc.cluster.local 10.1.1.1:8080",
+            "web.ns.svc.cluster.local:8080 10.1.1.1",
+            "web.ns.svc.cluster.local:8080 10.1.1.1:8080;weight=heavy",
+            "web.ns.svc.cluster.local:8080 10.1.1.1:8080;zone=west",
+        ] {
+            assert!(
+                invalid.parse::<Table>().is_err(),
+                "{} must not parse",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn file_edits_update_active_resolutions() {
+        let path = std::env::temp_dir().join(format!("static-endpoints-{}", std::process::id()));
+        fs::write(&path, TABLE).unwrap();
+        let config = Config {
+            table: Table::read(&path).unwrap(),
+            path: path.clone(),
+            watch_interval: Some(Duration::from_millis(10)),
+        };
+        let (mut resolver, watch) = config.build(Inner);
+
+        let mut rt = Runtime::new().unwrap();
+        rt.spawn(watch.expect("must watch").map_err(|_| ()));
+        let mut res = rt
+            .block_on(resolver.call(dst("web.ns.svc.cluster.local:8080")))
+            .unwrap();
+        let update = rt.block_on(future::lazy(|| Ok::<_, ()>(poll_update(&mut res))));
+        assert_eq!(addrs(&update.unwrap().unwrap()).len(), 2);
+
+        fs::write(&path, "web.ns.svc.cluster.local:8080 10.1.1.3:8080\n").unwrap();
+        let update = rt
+            .block_on(future::poll_fn(|| {
+                resolve::Resolution::poll(&mut res).map_err(|_| ())
+            }))
+            .unwrap();
+        assert_eq!(
+            addrs(&update),
+            vec!["10.1.1.3:8080".parse::<SocketAddr>().unwrap()]
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,15 +1,21 @@
 use crate::core::{
-    addr,
+    accept_watchdog, addr, admission, caller_override,
     config::*,
-    proxy::http::h2,
+    connection_limit, dst_conflict, header_trust, profiles,
+    proxy::http::{
+        balance, coalesce, dry_run, h2,
+        header::{HeaderName, HeaderValue},
+        min_ready, normalize_headers, transform,
+    },
+    reconnect,
     transport::{listen, tls},
-    Addr,
+    Addr, NameAddr,
 };
-use crate::{dns, identity, inbound, oc_collector, outbound};
-use indexmap::IndexSet;
+use crate::{dns, dst::static_endpoints, identity, inbound, oc_collector, outbound};
+use indexmap::{IndexMap, IndexSet};
 use std::convert::TryFrom;
 use std::iter::FromIterator;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::time::Duration;
@@ -45,6 +51,29 @@ pub enum ParseError {
     NameError,
     InvalidTokenSource,
     InvalidTrustAnchors,
+    InvalidDstConflictPolicy,
+    InvalidRouteBackup,
+    InvalidEmptyResponseFailure,
+    InvalidCoalescedRoute,
+    InvalidPreserveSchemeRoute,
+    InvalidAffinityCookieRoute,
+    InvalidRouteTransform,
+    InvalidMaxQueueTime,
+    InvalidLabelHeader,
+    InvalidTlsOrigination,
+    InvalidTlsPeerNames,
+    InvalidHeaderName,
+    InvalidCollapseHeader,
+    InvalidBufferDrainPolicy,
+    InvalidIdentityStartup,
+    InvalidStaticEndpoints,
+    InvalidDryRunRetryCondition,
+    InvalidHeaderTrust,
+    InvalidEwmaOverride,
+    InvalidResponseHeaderLimitPolicy,
+    InvalidConnectionLimit,
+    InvalidForwardPortAddrs,
+    ForwardAddrNotLocal,
 }
 
 // Environment variables to look at when loading the configuration
@@ -53,6 +82,17 @@ pub const ENV_INBOUND_LISTEN_ADDR: &str = "LINKERD2_PROXY_INBOUND_LISTEN_ADDR";
 pub const ENV_CONTROL_LISTEN_ADDR: &str = "LINKERD2_PROXY_CONTROL_LISTEN_ADDR";
 pub const ENV_ADMIN_LISTEN_ADDR: &str = "LINKERD2_PROXY_ADMIN_LISTEN_ADDR";
 pub const ENV_METRICS_RETAIN_IDLE: &str = "LINKERD2_PROXY_METRICS_RETAIN_IDLE";
+const ENV_METRICS_RATE_WINDOW: &str = "LINKERD2_PROXY_METRICS_RATE_WINDOW";
+
+/// A comma-separated list of route labels that are omitted from route metrics
+/// served on `/metrics/summary`.
+const ENV_METRICS_SUMMARY_DROP_ROUTE_LABELS: &str =
+    "LINKERD2_PROXY_METRICS_SUMMARY_DROP_ROUTE_LABELS";
+
+/// If set to N, one in every N requests is timed in each of the proxy's stack
+/// stages and reported as `stack_stage_duration_us`. This is a debugging aid;
+/// by default, stages are not timed.
+pub const ENV_METRICS_STACK_STAGE_SAMPLE: &str = "LINKERD2_PROXY_METRICS_STACK_STAGE_SAMPLE";
 const ENV_INBOUND_DISPATCH_TIMEOUT: &str = "LINKERD2_PROXY_INBOUND_DISPATCH_TIMEOUT";
 const ENV_OUTBOUND_DISPATCH_TIMEOUT: &str = "LINKERD2_PROXY_OUTBOUND_DISPATCH_TIMEOUT";
 const ENV_INBOUND_CONNECT_TIMEOUT: &str = "LINKERD2_PROXY_INBOUND_CONNECT_TIMEOUT";
@@ -63,6 +103,22 @@ const ENV_OUTBOUND_ACCEPT_KEEPALIVE: &str = "LINKERD2_PROXY_OUTBOUND_ACCEPT_KEEP
 const ENV_INBOUND_CONNECT_KEEPALIVE: &str = "LINKERD2_PROXY_INBOUND_CONNECT_KEEPALIVE";
 const ENV_OUTBOUND_CONNECT_KEEPALIVE: &str = "LINKERD2_PROXY_OUTBOUND_CONNECT_KEEPALIVE";
 
+/// Configures the maximum amount of time that an outbound HTTP client (and
+/// its pooled connections) may be reused before it is re-established.
+///
+/// If unspecified, clients are reused until they fail or become idle.
+const ENV_OUTBOUND_CONNECT_MAX_LIFETIME: &str = "LINKERD2_PROXY_OUTBOUND_CONNECT_MAX_LIFETIME";
+
+/// If set, limits the number of times in a row that the proxy retries
+/// connecting to an outbound endpoint. An endpoint that exhausts its budget
+/// is marked unavailable, so that it receives no requests and no connections
+/// are attempted, until `LINKERD2_PROXY_OUTBOUND_CONNECT_RETRY_COOLDOWN`
+/// elapses. Request retries are unaffected.
+///
+/// If unspecified, connections are retried indefinitely, with backoff.
+const ENV_OUTBOUND_CONNECT_RETRY_BUDGET: &str = "LINKERD2_PROXY_OUTBOUND_CONNECT_RETRY_BUDGET";
+const ENV_OUTBOUND_CONNECT_RETRY_COOLDOWN: &str = "LINKERD2_PROXY_OUTBOUND_CONNECT_RETRY_COOLDOWN";
+
 // Limits the number of HTTP routes that may be active in the proxy at any time. There is
 // an inbound route for each local port that receives connections. There is an outbound
 // route for each protocol and authority.
@@ -72,9 +128,415 @@ pub const ENV_OUTBOUND_ROUTER_CAPACITY: &str = "LINKERD2_PROXY_OUTBOUND_ROUTER_C
 pub const ENV_INBOUND_ROUTER_MAX_IDLE_AGE: &str = "LINKERD2_PROXY_INBOUND_ROUTER_MAX_IDLE_AGE";
 pub const ENV_OUTBOUND_ROUTER_MAX_IDLE_AGE: &str = "LINKERD2_PROXY_OUTBOUND_ROUTER_MAX_IDLE_AGE";
 
+/// The number of independently locked shards into which each outbound router's
+/// cache is split, rounded up to a power of two.
+pub const ENV_OUTBOUND_ROUTER_SHARDS: &str = "LINKERD2_PROXY_OUTBOUND_ROUTER_SHARDS";
+
+/// When set, a warning describing a request that matched none of its
+/// destination profile's routes is logged at most once per this interval for
+/// each destination.
+///
+/// If unspecified, unmatched requests are only counted.
+const ENV_OUTBOUND_ROUTE_UNMATCHED_LOG_INTERVAL: &str =
+    "LINKERD2_PROXY_OUTBOUND_ROUTE_UNMATCHED_LOG_INTERVAL";
+
+/// Determines how outbound requests are handled when the `l5d-dst-override`
+/// header, the request's authority, and its `Host` header name different
+/// destinations. One of `ignore`, `log`, or `reject`.
+///
+/// If unspecified, conflicts are ignored and the highest-precedence
+/// destination is used.
+const ENV_OUTBOUND_DST_CONFLICT_POLICY: &str = "LINKERD2_PROXY_OUTBOUND_DST_CONFLICT_POLICY";
+
+/// A comma-separated list of `DST=ROUTE;BACKUP` entries, where `DST` and
+/// `BACKUP` are each a `NAME:PORT` and `ROUTE` is the name of one of `DST`'s
+/// profile's routes. Requests on each `ROUTE` that its balancer cannot
+/// dispatch (e.g. because it has no available endpoints) are retried once
+/// against `BACKUP`.
+const ENV_OUTBOUND_ROUTE_BACKUPS: &str = "LINKERD2_PROXY_OUTBOUND_ROUTE_BACKUPS";
+
+/// A comma-separated list of `DST=ROUTE` pairs, where `DST` is a `NAME:PORT`
+/// and `ROUTE` is the name of one of its profile's routes. Successful
+/// responses with an empty body are classified as failures on each `ROUTE`.
+const ENV_OUTBOUND_EMPTY_RESPONSE_FAILURES: &str =
+    "LINKERD2_PROXY_OUTBOUND_EMPTY_RESPONSE_FAILURES";
+
+/// A comma-separated list of `DST=ROUTE` pairs, where `DST` is a `NAME:PORT`
+/// and `ROUTE` is the name of one of its profile's routes. Concurrent
+/// identical `GET` requests on each `ROUTE` are coalesced into a single
+/// request whose response is shared.
+const ENV_OUTBOUND_COALESCED_ROUTES: &str = "LINKERD2_PROXY_OUTBOUND_COALESCED_ROUTES";
+
+/// A comma-separated list of `DST=ROUTE` pairs, where `DST` is a `NAME:PORT`
+/// and `ROUTE` is the name of one of its profile's routes. Requests on each
+/// `ROUTE` keep their original scheme when their URIs are normalized.
+const ENV_OUTBOUND_PRESERVE_SCHEME_ROUTES: &str = "LINKERD2_PROXY_OUTBOUND_PRESERVE_SCHEME_ROUTES";
+
+/// A comma-separated list of `DST=ROUTE;COOKIE` entries, where `DST` is a
+/// `NAME:PORT` and `ROUTE` is the name of one of its profile's routes.
+/// Requests on each `ROUTE` that carry the cookie named `COOKIE`, set by the
+/// backend to its `IP:PORT`, are dispatched to that endpoint rather than
+/// balanced, as long as it is still resolved for `DST`.
+const ENV_OUTBOUND_AFFINITY_COOKIE_ROUTES: &str = "LINKERD2_PROXY_OUTBOUND_AFFINITY_COOKIE_ROUTES";
+
+/// The name of a header that pins an outbound request to the endpoint whose
+/// `IP:PORT` it carries, for debugging and canary pinning. The request is
+/// dispatched to that endpoint rather than balanced, as long as the endpoint
+/// is resolved for the request's destination and isn't draining. If unset,
+/// requests are never pinned by a header.
+const ENV_OUTBOUND_PIN_ENDPOINT_HEADER: &str = "LINKERD2_PROXY_OUTBOUND_PIN_ENDPOINT_HEADER";
+
+/// A comma-separated list of `DST=ROUTE;OP;...` entries, where `DST` is a
+/// `NAME:PORT` and `ROUTE` is the name of one of its profile's routes. Each
+/// `OP` transforms the route's requests or responses, in order, and is one of:
+///
+/// - `request.add:NAME=VALUE` or `response.add:NAME=VALUE`, which append a
+///   value to the header;
+/// - `request.set:NAME=VALUE` or `response.set:NAME=VALUE`, which replace any
+///   values of the header;
+/// - `request.remove:NAME` or `response.remove:NAME`;
+/// - `request.rename:FROM=TO` or `response.rename:FROM=TO`;
+/// - `path:PATTERN=REPLACEMENT`, which rewrites the first match of the
+///   regular expression `PATTERN` in the request's path; `REPLACEMENT` may
+///   refer to capture groups, as in `$1`;
+/// - `bucket:NAME=BUCKET:WEIGHT|...`, which sets the request header `NAME` to
+///   one of the `BUCKET`s, chosen at random in proportion to their weights;
+/// - `bucket:NAME@KEY=BUCKET:WEIGHT|...`, which chooses the bucket by hashing
+///   the request header `KEY`, so that requests with the same key are assigned
+///   the same bucket. Requests without the header are assigned at random.
+///
+/// Values and patterns may not contain `,` or `;`, and patterns may not
+/// contain `=`.
+const ENV_OUTBOUND_ROUTE_TRANSFORMS: &str = "LINKERD2_PROXY_OUTBOUND_ROUTE_TRANSFORMS";
+
+/// Like `ENV_OUTBOUND_ROUTE_TRANSFORMS`, for the routes of inbound
+/// destinations' profiles.
+const ENV_INBOUND_ROUTE_TRANSFORMS: &str = "LINKERD2_PROXY_INBOUND_ROUTE_TRANSFORMS";
+
+/// Responses with larger bodies are not shared by coalesced requests.
+const ENV_OUTBOUND_COALESCE_MAX_BODY_BYTES: &str =
+    "LINKERD2_PROXY_OUTBOUND_COALESCE_MAX_BODY_BYTES";
+
+/// If set, responses are only shared by coalesced requests from the same
+/// client.
+const ENV_OUTBOUND_COALESCE_ISOLATE_CLIENTS: &str =
+    "LINKERD2_PROXY_OUTBOUND_COALESCE_ISOLATE_CLIENTS";
+
+/// A comma-separated list of `DST=DURATION` pairs, where `DST` is a
+/// `NAME:PORT`. Requests to `DST` that are not dispatched within `DURATION`
+/// fail with a 503, even if their dispatch timeout has not elapsed.
+const ENV_OUTBOUND_MAX_QUEUE_TIMES: &str = "LINKERD2_PROXY_OUTBOUND_MAX_QUEUE_TIMES";
+
+/// If set, a destination's balancer does not serve requests until this many
+/// of its endpoints are ready, or until
+/// `ENV_OUTBOUND_BALANCER_MIN_READY_TIMEOUT` elapses.
+const ENV_OUTBOUND_BALANCER_MIN_READY_ENDPOINTS: &str =
+    "LINKERD2_PROXY_OUTBOUND_BALANCER_MIN_READY_ENDPOINTS";
+const ENV_OUTBOUND_BALANCER_MIN_READY_TIMEOUT: &str =
+    "LINKERD2_PROXY_OUTBOUND_BALANCER_MIN_READY_TIMEOUT";
+
+/// A comma-separated list of `DST=RTT/DECAY` entries, where `DST` is a
+/// `NAME:PORT`. The balancer for `DST` estimates its endpoints' load assuming
+/// an RTT of `RTT` until responses are observed, decaying observed RTTs over
+/// `DECAY`, rather than using the defaults.
+const ENV_OUTBOUND_BALANCER_EWMA_OVERRIDES: &str =
+    "LINKERD2_PROXY_OUTBOUND_BALANCER_EWMA_OVERRIDES";
+
+/// If set, each balancer builds at most this many endpoint services at once,
+/// so that a large set of endpoints is built incrementally.
+const ENV_OUTBOUND_BALANCER_MAX_CONCURRENT_BUILDS: &str =
+    "LINKERD2_PROXY_OUTBOUND_BALANCER_MAX_CONCURRENT_BUILDS";
+
+/// If set, at most this many outbound connections are established at once.
+/// Additional connects wait until an in-flight connect completes.
+const ENV_OUTBOUND_MAX_CONCURRENT_CONNECTS: &str =
+    "LINKERD2_PROXY_OUTBOUND_MAX_CONCURRENT_CONNECTS";
+
+/// A comma-separated list of `DST=MAX` entries, where `DST` is either a
+/// `NAME:PORT` or a network in CIDR notation. At most `MAX` outbound
+/// connections are open at once to the endpoints of `NAME:PORT`, or to the
+/// addresses within the network. `NAME:PORT` is the logical destination, so
+/// its limit includes the endpoints of destinations that its traffic is split
+/// to. Opaque TCP forwards are only limited by network. Each endpoint is
+/// limited by the first entry that it matches.
+const ENV_OUTBOUND_CONNECTION_LIMITS: &str = "LINKERD2_PROXY_OUTBOUND_CONNECTION_LIMITS";
+
+/// If set, at most this many outbound connections are open at once to the
+/// endpoints of each destination that `LINKERD2_PROXY_OUTBOUND_CONNECTION_LIMITS`
+/// doesn't limit. Each destination is limited independently.
+const ENV_OUTBOUND_CONNECTION_LIMIT_PER_DST: &str =
+    "LINKERD2_PROXY_OUTBOUND_CONNECTION_LIMIT_PER_DST";
+
+/// The time that an outbound connect waits for a connection to close once its
+/// destination's connection limit is reached. Requests whose connects don't
+/// start within it fail with a 503.
+const ENV_OUTBOUND_CONNECTION_LIMIT_MAX_WAIT: &str =
+    "LINKERD2_PROXY_OUTBOUND_CONNECTION_LIMIT_MAX_WAIT";
+
+/// If set, outbound responses of up to this many bytes to idempotent requests
+/// on retryable routes are buffered so that they may be retried if the
+/// upstream resets them before they complete.
+const ENV_OUTBOUND_RETRY_RESET_MAX_BODY_BYTES: &str =
+    "LINKERD2_PROXY_OUTBOUND_RETRY_RESET_MAX_BODY_BYTES";
+
+/// A comma-separated list of `NAME:PORT` destinations. The bodies of requests
+/// that are upgraded to HTTP/2 for these destinations are sent with checksums,
+/// which the inbound proxy verifies; their responses are checksummed in turn.
+const ENV_OUTBOUND_BODY_CHECKSUM_DSTS: &str = "LINKERD2_PROXY_OUTBOUND_BODY_CHECKSUM_DSTS";
+
+/// A comma-separated list of ports whose upgraded requests are checksummed,
+/// as with `LINKERD2_PROXY_OUTBOUND_BODY_CHECKSUM_DSTS`.
+const ENV_OUTBOUND_BODY_CHECKSUM_PORTS: &str = "LINKERD2_PROXY_OUTBOUND_BODY_CHECKSUM_PORTS";
+
+/// If set, each outbound balancer's choices are compared with those a
+/// least-request balancer would have made, without affecting which endpoint
+/// is chosen.
+const ENV_OUTBOUND_DRY_RUN_BALANCER: &str = "LINKERD2_PROXY_OUTBOUND_DRY_RUN_BALANCER";
+
+/// A comma-separated list of response statuses and status ranges (e.g.
+/// `500-599,429`). If set, each outbound retry decision is compared with
+/// whether a response with one of these statuses would have been retried,
+/// without affecting which responses are retried.
+const ENV_OUTBOUND_DRY_RUN_RETRY_STATUSES: &str = "LINKERD2_PROXY_OUTBOUND_DRY_RUN_RETRY_STATUSES";
+
+/// A comma-separated list of DNS suffixes of destinations outside of the mesh
+/// to which TLS is originated. Each suffix may be followed by `;ca=PATH`, a
+/// PEM bundle of the roots that servers' certificates are verified against,
+/// and by `;sni=NAME`, the name that is verified instead of the destination's
+/// name. Destinations with a mesh identity are unaffected.
+///
+/// Names that service discovery doesn't resolve, e.g. external HTTPS APIs,
+/// are matched by the name that each request targets and are sent to the
+/// request's original destination address.
+pub const ENV_OUTBOUND_TLS_ORIGINATION: &str = "LINKERD2_PROXY_OUTBOUND_TLS_ORIGINATION";
+
+/// A comma-separated list of rules that determine the names that endpoints'
+/// certificates are verified against, e.g. for workloads in an adjacent mesh
+/// that identifies them by SPIFFE URI SANs.
+///
+/// Each rule has the form `authority:SUFFIX=FORM` or `identity:SUFFIX=FORM`,
+/// matching endpoints by their destination's name or by their discovered
+/// identity. `FORM` is either `dns`, in which case the certificate must be
+/// valid for the matched name, or a URI template such as
+/// `spiffe://cluster.local/ns/{2}/sa/{1}`, in which `{name}` is replaced by
+/// the matched name and `{N}` by its `N`th label. The matched name is sent as
+/// the SNI. The first matching rule is used.
+const ENV_OUTBOUND_TLS_PEER_NAMES: &str = "LINKERD2_PROXY_OUTBOUND_TLS_PEER_NAMES";
+
+/// The system's CA bundle, which is used when a destination's bundle is not
+/// configured.
+const DEFAULT_OUTBOUND_TLS_ORIGINATION_CA_BUNDLE: &str = "/etc/ssl/certs/ca-certificates.crt";
+
+/// A comma-separated list of `LABEL=HEADER` pairs. Each outbound request is
+/// sent with `HEADER` set to the value of `LABEL` in the discovery metadata
+/// of the endpoint it is dispatched to, if the endpoint has that label.
+pub const ENV_OUTBOUND_LABEL_HEADERS: &str = "LINKERD2_PROXY_OUTBOUND_LABEL_HEADERS";
+
+/// If set, headers configured by `LINKERD2_PROXY_OUTBOUND_LABEL_HEADERS`
+/// replace any values already set on a request. Otherwise, they are only set
+/// on requests that do not already have them.
+pub const ENV_OUTBOUND_LABEL_HEADERS_OVERWRITE: &str =
+    "LINKERD2_PROXY_OUTBOUND_LABEL_HEADERS_OVERWRITE";
+
+/// Seeds the RNGs that the outbound proxy uses to balance and split traffic,
+/// so that these decisions are reproducible. Entropy is used if unset.
+const ENV_OUTBOUND_RNG_SEED: &str = "LINKERD2_PROXY_OUTBOUND_RNG_SEED";
+
+/// Comma-separated lists of header names that are stripped from responses
+/// before they are served to clients. Hop-by-hop headers are always stripped.
+pub const ENV_INBOUND_STRIP_RESPONSE_HEADERS: &str =
+    "LINKERD2_PROXY_INBOUND_STRIP_RESPONSE_HEADERS";
+pub const ENV_OUTBOUND_STRIP_RESPONSE_HEADERS: &str =
+    "LINKERD2_PROXY_OUTBOUND_STRIP_RESPONSE_HEADERS";
+
+/// The name of a header, like `x-request-id`, that carries a correlation ID.
+/// Requests that lack it are assigned a generated ID, and each request's ID is
+/// set on its response.
+///
+/// If unspecified, correlation IDs are not generated.
+pub const ENV_INBOUND_CORRELATION_ID_HEADER: &str = "LINKERD2_PROXY_INBOUND_CORRELATION_ID_HEADER";
+pub const ENV_OUTBOUND_CORRELATION_ID_HEADER: &str =
+    "LINKERD2_PROXY_OUTBOUND_CORRELATION_ID_HEADER";
+
+/// If set, each request's correlation ID is echoed in its access log, in its
+/// trace span's annotations, and in the logs emitted while it's served.
+pub const ENV_CORRELATION_ID_ECHO: &str = "LINKERD2_PROXY_CORRELATION_ID_ECHO";
+
+/// If set, trusted callers may disable retries with an `l5d-retries: off`
+/// header, or replace their route's timeout with an `l5d-timeout` header.
+/// Inbound, only meshed peers are trusted; outbound, the local application
+/// is.
+pub const ENV_INBOUND_CALLER_OVERRIDES: &str = "LINKERD2_PROXY_INBOUND_CALLER_OVERRIDES";
+pub const ENV_OUTBOUND_CALLER_OVERRIDES: &str = "LINKERD2_PROXY_OUTBOUND_CALLER_OVERRIDES";
+
+/// The longest timeout that a caller may set with an `l5d-timeout` header.
+pub const ENV_CALLER_OVERRIDE_MAX_TIMEOUT: &str = "LINKERD2_PROXY_CALLER_OVERRIDE_MAX_TIMEOUT";
+
+/// A comma-separated list of `HEADER=SUFFIX|SUFFIX...` rules, each of which
+/// only honors a proxy-control header when it's set by a peer whose identity
+/// is within one of the given suffixes, e.g.
+/// `l5d-dst-override=linkerd-gateway.linkerd.serviceaccount.identity.linkerd.cluster.local`.
+/// A controlled header set by any other peer is stripped.
+///
+/// Inbound, rules apply to request headers sent by clients; outbound, to
+/// response headers sent by servers. Headers without a rule are unchanged.
+const ENV_INBOUND_HEADER_TRUST: &str = "LINKERD2_PROXY_INBOUND_HEADER_TRUST";
+const ENV_OUTBOUND_HEADER_TRUST: &str = "LINKERD2_PROXY_OUTBOUND_HEADER_TRUST";
+
+/// If set, limits the size, in bytes, of the response headers that upstreams
+/// may send, both inbound and outbound. Each header counts its name and value
+/// plus 32 bytes, as HTTP/2 counts `SETTINGS_MAX_HEADER_LIST_SIZE`.
+const ENV_MAX_RESPONSE_HEADER_BYTES: &str = "LINKERD2_PROXY_MAX_RESPONSE_HEADER_BYTES";
+
+/// Either `reject`, so that responses whose headers exceed the limit fail with
+/// a 502, or `truncate`, so that the headers that don't fit are removed.
+/// Defaults to `reject`.
+const ENV_RESPONSE_HEADER_LIMIT_POLICY: &str = "LINKERD2_PROXY_RESPONSE_HEADER_LIMIT_POLICY";
+
+/// A comma-separated list of `NAME[=first|last|join]` request headers that
+/// are collapsed into a single value when an inbound request repeats them.
+/// Values are joined by default. `Set-Cookie` is never collapsed.
+const ENV_INBOUND_COLLAPSE_REQUEST_HEADERS: &str =
+    "LINKERD2_PROXY_INBOUND_COLLAPSE_REQUEST_HEADERS";
+
+/// Limits the number of distinct destination names that each inbound source
+/// may route to within `LINKERD2_PROXY_INBOUND_DST_NAME_LIMIT_WINDOW`.
+/// Requests naming further destinations are routed by their original
+/// destination address. Sources are identified by their TLS identity or, when
+/// they have none, by their IP address.
+///
+/// If unspecified, destination names are not limited.
+const ENV_INBOUND_DST_NAME_LIMIT: &str = "LINKERD2_PROXY_INBOUND_DST_NAME_LIMIT";
+const ENV_INBOUND_DST_NAME_LIMIT_WINDOW: &str = "LINKERD2_PROXY_INBOUND_DST_NAME_LIMIT_WINDOW";
+
+/// Determines how inbound connections are handled before the local identity
+/// has been certified: `wait-for-identity` holds each connection until a
+/// certificate is issued (failing it after
+/// `LINKERD2_PROXY_INBOUND_IDENTITY_STARTUP_TIMEOUT`), while `serve-plaintext`
+/// accepts connections without mTLS in the meantime.
+///
+/// If unspecified, connections are served in plaintext.
+const ENV_INBOUND_IDENTITY_STARTUP: &str = "LINKERD2_PROXY_INBOUND_IDENTITY_STARTUP";
+const ENV_INBOUND_IDENTITY_STARTUP_TIMEOUT: &str =
+    "LINKERD2_PROXY_INBOUND_IDENTITY_STARTUP_TIMEOUT";
+
+/// If set, the inbound proxy shields callers from the application's startup:
+/// until a connection to the application first succeeds, requests that fail
+/// because it refuses connections receive a 503 with a `Retry-After` of this
+/// duration, and the proxy does not report that it is ready.
+///
+/// If unspecified, refused connections fail requests with a 502.
+const ENV_INBOUND_STARTUP_SHIELD_RETRY_AFTER: &str =
+    "LINKERD2_PROXY_INBOUND_STARTUP_SHIELD_RETRY_AFTER";
+
+/// If set, inbound TLS connections whose ClientHello doesn't include an SNI
+/// are rejected. Otherwise, they're forwarded to the application without
+/// being terminated.
+const ENV_INBOUND_REQUIRE_SNI: &str = "LINKERD2_PROXY_INBOUND_REQUIRE_SNI";
+
+/// If set, the inbound and outbound accept loops are watched: a loop that
+/// hasn't polled its listener for this long while connections may be pending
+/// is considered stalled, and the proxy isn't ready until it recovers.
+const ENV_ACCEPT_WATCHDOG_STALL_TIMEOUT: &str = "LINKERD2_PROXY_ACCEPT_WATCHDOG_STALL_TIMEOUT";
+
+/// How long a watched accept loop may go without polling its listener, even
+/// if no connections are pending, before it's considered stalled.
+const ENV_ACCEPT_WATCHDOG_MAX_SILENCE: &str = "LINKERD2_PROXY_ACCEPT_WATCHDOG_MAX_SILENCE";
+
+/// If set, a stalled accept loop's listener is closed and bound again.
+const ENV_ACCEPT_WATCHDOG_RECOVER: &str = "LINKERD2_PROXY_ACCEPT_WATCHDOG_RECOVER";
+
+/// The maximum number of connections each listener holds while the proxy's
+/// stack is being constructed at startup. Further connections are refused
+/// until the stack has been constructed.
+const ENV_STARTUP_QUEUE_CAPACITY: &str = "LINKERD2_PROXY_STARTUP_QUEUE_CAPACITY";
+
+/// If set, inbound connections are forwarded to the local application on
+/// this IP address rather than on `127.0.0.1`.
+pub const ENV_INBOUND_FORWARD_ADDR: &str = "LINKERD2_PROXY_INBOUND_FORWARD_ADDR";
+
+/// A comma-separated list of `PORT=IP` entries that override the address to
+/// which inbound connections are forwarded for specific ports.
+const ENV_INBOUND_FORWARD_PORT_ADDRS: &str = "LINKERD2_PROXY_INBOUND_FORWARD_PORT_ADDRS";
+
+/// A comma-separated list of non-loopback IP addresses to which inbound
+/// connections may be forwarded (e.g. the host's address, when the proxy
+/// runs with host networking). Loopback addresses are always permitted.
+const ENV_INBOUND_FORWARD_ADDR_ALLOWLIST: &str = "LINKERD2_PROXY_INBOUND_FORWARD_ADDR_ALLOWLIST";
+
 pub const ENV_INBOUND_MAX_IN_FLIGHT: &str = "LINKERD2_PROXY_INBOUND_MAX_IN_FLIGHT";
 pub const ENV_OUTBOUND_MAX_IN_FLIGHT: &str = "LINKERD2_PROXY_OUTBOUND_MAX_IN_FLIGHT";
 
+/// If set, the inbound proxy's limit on requests in flight adapts to the
+/// latency of the application's responses, starting at this minimum.
+///
+/// The limit never exceeds `LINKERD2_PROXY_INBOUND_ADAPTIVE_CONCURRENCY_MAX`,
+/// which defaults to `LINKERD2_PROXY_INBOUND_MAX_IN_FLIGHT`. If unspecified,
+/// the limit is fixed at `LINKERD2_PROXY_INBOUND_MAX_IN_FLIGHT`.
+const ENV_INBOUND_ADAPTIVE_CONCURRENCY_MIN: &str =
+    "LINKERD2_PROXY_INBOUND_ADAPTIVE_CONCURRENCY_MIN";
+const ENV_INBOUND_ADAPTIVE_CONCURRENCY_MAX: &str =
+    "LINKERD2_PROXY_INBOUND_ADAPTIVE_CONCURRENCY_MAX";
+
+/// If set, the inbound proxy sheds new requests while the moving average of
+/// the application's response latency exceeds this duration.
+const ENV_INBOUND_BROWNOUT_LATENCY_THRESHOLD: &str =
+    "LINKERD2_PROXY_INBOUND_BROWNOUT_LATENCY_THRESHOLD";
+
+/// If set, inbound and outbound HTTP server connections are closed once they
+/// have served this many requests (or, for HTTP/2, streams), so that clients
+/// reconnect and rebalance across proxies.
+///
+/// If unspecified, connections may serve any number of requests.
+pub const ENV_SERVER_MAX_REQUESTS_PER_CONNECTION: &str =
+    "LINKERD2_PROXY_SERVER_MAX_REQUESTS_PER_CONNECTION";
+
+/// If set, inbound HTTP/2 connections are closed once the client resets more
+/// than this many streams, before their responses are ready, within
+/// `LINKERD2_PROXY_INBOUND_H2_MAX_RESETS_WINDOW`. This bounds the work that a
+/// client can impose by rapidly opening and resetting streams.
+///
+/// If unspecified, stream resets are not limited.
+const ENV_INBOUND_H2_MAX_RESETS: &str = "LINKERD2_PROXY_INBOUND_H2_MAX_RESETS";
+const ENV_INBOUND_H2_MAX_RESETS_WINDOW: &str = "LINKERD2_PROXY_INBOUND_H2_MAX_RESETS_WINDOW";
+
+/// If set, inbound and outbound connections on which the client sends no
+/// request (or, if the connection isn't HTTP, no data) within this timeout of
+/// being accepted are closed. Connections to ports on which protocol
+/// detection is disabled are exempt, since their servers may speak first.
+///
+/// If unspecified, accepted connections may remain unused indefinitely.
+pub const ENV_SERVER_ACCEPT_IDLE_TIMEOUT: &str = "LINKERD2_PROXY_SERVER_ACCEPT_IDLE_TIMEOUT";
+
+/// If set, inbound and outbound HTTP connections are closed once they have
+/// had no requests in flight for this long after serving a request.
+///
+/// If unspecified, keep-alive connections may remain idle indefinitely.
+pub const ENV_SERVER_KEEP_ALIVE_IDLE_TIMEOUT: &str =
+    "LINKERD2_PROXY_SERVER_KEEP_ALIVE_IDLE_TIMEOUT";
+
+/// If set, errors that fail inbound and outbound requests are logged at most
+/// once per window for each class of error and target. Errors that are not
+/// logged are counted, and the counts are logged at the end of each window.
+///
+/// If unspecified, every error is logged.
+const ENV_ERROR_LOG_DEDUP_WINDOW: &str = "LINKERD2_PROXY_ERROR_LOG_DEDUP_WINDOW";
+
+/// Determines how requests that are still queued when the proxy begins to
+/// drain are handled: `complete` dispatches them as usual, while `fail-fast`
+/// fails them immediately.
+///
+/// If unspecified, queued requests are completed.
+pub const ENV_BUFFER_DRAIN_POLICY: &str = "LINKERD2_PROXY_BUFFER_DRAIN_POLICY";
+
+/// If set, requests that have less than this much time remaining before their
+/// dispatch deadline fail immediately instead of being queued, since they're
+/// unlikely to be dispatched in time.
+///
+/// If unspecified, requests are always queued.
+const ENV_INBOUND_MIN_DISPATCH_BUDGET: &str = "LINKERD2_PROXY_INBOUND_MIN_DISPATCH_BUDGET";
+const ENV_OUTBOUND_MIN_DISPATCH_BUDGET: &str = "LINKERD2_PROXY_OUTBOUND_MIN_DISPATCH_BUDGET";
+
 /// Constrains which destination names are resolved through the destination
 /// service.
 ///
@@ -109,6 +571,50 @@ pub const ENV_DESTINATION_GET_NETWORKS: &str = "LINKERD2_PROXY_DESTINATION_GET_N
 /// If unspecified, a default value is used.
 pub const ENV_DESTINATION_PROFILE_SUFFIXES: &str = "LINKERD2_PROXY_DESTINATION_PROFILE_SUFFIXES";
 
+/// The number of consecutive failed profile lookups that are retried, with
+/// backoff, before a destination's routes fall back to the defaults.
+///
+/// The backoff may be configured with the `LINKERD2_PROXY_DESTINATION_PROFILE_EXP_BACKOFF_*`
+/// variables.
+const ENV_DESTINATION_PROFILE_MAX_RETRIES: &str = "LINKERD2_PROXY_DESTINATION_PROFILE_MAX_RETRIES";
+
+/// The maximum number of profile lookups that may be in flight at once.
+///
+/// When many destinations' profiles are rebuilt together, e.g. because their
+/// cache entries expired at the same time, lookups beyond this limit are
+/// queued until earlier lookups are answered.
+const ENV_DESTINATION_PROFILE_MAX_CONCURRENT_REBUILDS: &str =
+    "LINKERD2_PROXY_DESTINATION_PROFILE_MAX_CONCURRENT_REBUILDS";
+
+/// The maximum number of routes applied from each profile.
+///
+/// Routes beyond this limit are ignored and the truncation is logged. If
+/// unspecified, all of a profile's routes are applied.
+const ENV_DESTINATION_PROFILE_MAX_ROUTES: &str = "LINKERD2_PROXY_DESTINATION_PROFILE_MAX_ROUTES";
+
+/// How long a destination that has no profile is remembered as such.
+///
+/// While a destination is remembered, rebuilding its routes uses the default
+/// routes without looking up its profile. If unspecified, profiles are always
+/// looked up.
+const ENV_DESTINATION_PROFILE_NEGATIVE_CACHE_TTL: &str =
+    "LINKERD2_PROXY_DESTINATION_PROFILE_NEGATIVE_CACHE_TTL";
+
+/// The path of a file to which DNS canonicalizations and the destinations
+/// that have no profile are written when the proxy shuts down, and from which
+/// they're restored when it starts.
+const ENV_CACHE_PERSIST_PATH: &str = "LINKERD2_PROXY_CACHE_PERSIST_PATH";
+
+/// The path of a file that maps names to static endpoints.
+///
+/// Names in the file are resolved from it rather than by the destination
+/// service. The file is read once, at startup, unless
+/// `LINKERD2_PROXY_DESTINATION_STATIC_ENDPOINTS_WATCH_INTERVAL` is set, in
+/// which case it is re-read on that interval.
+const ENV_DESTINATION_STATIC_ENDPOINTS: &str = "LINKERD2_PROXY_DESTINATION_STATIC_ENDPOINTS";
+const ENV_DESTINATION_STATIC_ENDPOINTS_WATCH_INTERVAL: &str =
+    "LINKERD2_PROXY_DESTINATION_STATIC_ENDPOINTS_WATCH_INTERVAL";
+
 // These *disable* our protocol detection for connections whose SO_ORIGINAL_DST
 // has a port in the provided list.
 pub const ENV_INBOUND_PORTS_DISABLE_PROTOCOL_DETECTION: &str =
@@ -151,6 +657,13 @@ const ENV_DNS_MAX_TTL: &str = "LINKERD2_PROXY_DNS_MAX_TTL";
 /// an uncanonicalized address.
 const ENV_DNS_CANONICALIZE_TIMEOUT: &str = "LINKERD2_PROXY_DNS_CANONICALIZE_TIMEOUT";
 
+/// The maximum amount of time a canonicalized name is used before it is
+/// refined again, even if its DNS TTL has not expired. Stale names continue to
+/// be used while they are refined in the background.
+///
+/// If unspecified, names are refined again only when their TTL expires.
+const ENV_DNS_CANONICALIZE_FRESHNESS: &str = "LINKERD2_PROXY_DNS_CANONICALIZE_FRESHNESS";
+
 /// Configure the stream or connection level flow control setting for HTTP2.
 ///
 /// If unspecified, the default value of 65,535 is used.
@@ -158,12 +671,23 @@ const ENV_INITIAL_STREAM_WINDOW_SIZE: &str = "LINKERD2_PROXY_HTTP2_INITIAL_STREA
 const ENV_INITIAL_CONNECTION_WINDOW_SIZE: &str =
     "LINKERD2_PROXY_HTTP2_INITIAL_CONNECTION_WINDOW_SIZE";
 
+/// If set, newly established outbound HTTP/2 connections limit their
+/// concurrent streams for this long after they're established. The limit
+/// starts at `LINKERD2_PROXY_OUTBOUND_HTTP2_WARMUP_INITIAL_STREAMS` and
+/// doubles each tenth of the window.
+///
+/// If unspecified, connections are not warmed up.
+const ENV_OUTBOUND_HTTP2_WARMUP_WINDOW: &str = "LINKERD2_PROXY_OUTBOUND_HTTP2_WARMUP_WINDOW";
+const ENV_OUTBOUND_HTTP2_WARMUP_INITIAL_STREAMS: &str =
+    "LINKERD2_PROXY_OUTBOUND_HTTP2_WARMUP_INITIAL_STREAMS";
+
 // Default values for various configuration fields
 const DEFAULT_OUTBOUND_LISTEN_ADDR: &str = "127.0.0.1:4140";
 const DEFAULT_INBOUND_LISTEN_ADDR: &str = "0.0.0.0:4143";
 const DEFAULT_CONTROL_LISTEN_ADDR: &str = "0.0.0.0:4190";
 const DEFAULT_ADMIN_LISTEN_ADDR: &str = "127.0.0.1:4191";
 const DEFAULT_METRICS_RETAIN_IDLE: Duration = Duration::from_secs(10 * 60);
+const DEFAULT_METRICS_RATE_WINDOW: Duration = Duration::from_secs(10);
 const DEFAULT_INBOUND_DISPATCH_TIMEOUT: Duration = Duration::from_secs(1);
 const DEFAULT_INBOUND_CONNECT_TIMEOUT: Duration = Duration::from_millis(100);
 const DEFAULT_INBOUND_CONNECT_BACKOFF: ExponentialBackoff = ExponentialBackoff {
@@ -172,17 +696,30 @@ const DEFAULT_INBOUND_CONNECT_BACKOFF: ExponentialBackoff = ExponentialBackoff {
     jitter: 0.1,
 };
 const DEFAULT_OUTBOUND_DISPATCH_TIMEOUT: Duration = Duration::from_secs(3);
+const DEFAULT_OUTBOUND_CONNECTION_LIMIT_MAX_WAIT: Duration = Duration::from_secs(1);
+const DEFAULT_OUTBOUND_BALANCER_MIN_READY_TIMEOUT: Duration = Duration::from_secs(1);
+const DEFAULT_ACCEPT_WATCHDOG_MAX_SILENCE: Duration = Duration::from_secs(60);
+const DEFAULT_STARTUP_QUEUE_CAPACITY: usize = 100;
+const DEFAULT_CALLER_OVERRIDE_MAX_TIMEOUT: Duration = Duration::from_secs(5 * 60);
 const DEFAULT_OUTBOUND_CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+const DEFAULT_OUTBOUND_CONNECT_RETRY_COOLDOWN: Duration = Duration::from_secs(10);
 const DEFAULT_OUTBOUND_CONNECT_BACKOFF: ExponentialBackoff = ExponentialBackoff {
     min: Duration::from_millis(100),
     max: Duration::from_millis(500),
     jitter: 0.1,
 };
+const DEFAULT_DESTINATION_PROFILE_BACKOFF: ExponentialBackoff = ExponentialBackoff {
+    min: Duration::from_millis(500),
+    max: Duration::from_secs(3),
+    jitter: 0.1,
+};
+const DEFAULT_DESTINATION_PROFILE_MAX_RETRIES: usize = 5;
 const DEFAULT_DNS_CANONICALIZE_TIMEOUT: Duration = Duration::from_millis(100);
 const DEFAULT_RESOLV_CONF: &str = "/etc/resolv.conf";
 
 const DEFAULT_INITIAL_STREAM_WINDOW_SIZE: u32 = 65_535; // Protocol default
 const DEFAULT_INITIAL_CONNECTION_WINDOW_SIZE: u32 = 1048576; // 1MB ~ 16 streams at capacity
+const DEFAULT_OUTBOUND_HTTP2_WARMUP_INITIAL_STREAMS: usize = 10;
 
 /// It's assumed that a typical proxy can serve inbound traffic for up to 100 pod-local
 /// HTTP services and may communicate with up to 10K external HTTP domains.
@@ -192,6 +729,19 @@ const DEFAULT_OUTBOUND_ROUTER_CAPACITY: usize = 10_000;
 const DEFAULT_INBOUND_ROUTER_MAX_IDLE_AGE: Duration = Duration::from_secs(60);
 const DEFAULT_OUTBOUND_ROUTER_MAX_IDLE_AGE: Duration = Duration::from_secs(60);
 
+/// Many outbound authorities are routed concurrently, so outbound routers'
+/// caches are sharded so that requests don't queue on a single lock.
+const DEFAULT_OUTBOUND_ROUTER_SHARDS: usize = 8;
+
+/// A fraction of the inbound router's capacity, so that a single source cannot
+/// exhaust it.
+const DEFAULT_INBOUND_DST_NAME_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+const DEFAULT_INBOUND_H2_MAX_RESETS_WINDOW: Duration = Duration::from_secs(10);
+
+const DEFAULT_INBOUND_IDENTITY_STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+const DEFAULT_OUTBOUND_COALESCE_MAX_BODY_BYTES: usize = 64 * 1024;
+
 // 10_000 is arbitrarily chosen for now...
 const DEFAULT_INBOUND_MAX_IN_FLIGHT: usize = 10_000;
 const DEFAULT_OUTBOUND_MAX_IN_FLIGHT: usize = 10_000;
@@ -213,6 +763,7 @@ const DEFAULT_PORTS_DISABLE_PROTOCOL_DETECTION: &[u16] = &[
 
 const INBOUND_CONNECT_BASE: &str = "INBOUND_CONNECT";
 const OUTBOUND_CONNECT_BASE: &str = "OUTBOUND_CONNECT";
+const DESTINATION_PROFILE_BASE: &str = "DESTINATION_PROFILE";
 
 /// Load a `App` by reading ENV variables.
 pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError> {
@@ -226,6 +777,11 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
     let inbound_connect_timeout = parse(strings, ENV_INBOUND_CONNECT_TIMEOUT, parse_duration);
 
     let outbound_dispatch_timeout = parse(strings, ENV_OUTBOUND_DISPATCH_TIMEOUT, parse_duration);
+
+    let inbound_min_dispatch_budget =
+        parse(strings, ENV_INBOUND_MIN_DISPATCH_BUDGET, parse_duration);
+    let outbound_min_dispatch_budget =
+        parse(strings, ENV_OUTBOUND_MIN_DISPATCH_BUDGET, parse_duration);
     let outbound_connect_timeout = parse(strings, ENV_OUTBOUND_CONNECT_TIMEOUT, parse_duration);
 
     let inbound_accept_keepalive = parse(strings, ENV_INBOUND_ACCEPT_KEEPALIVE, parse_duration);
@@ -234,6 +790,13 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
     let inbound_connect_keepalive = parse(strings, ENV_INBOUND_CONNECT_KEEPALIVE, parse_duration);
     let outbound_connect_keepalive = parse(strings, ENV_OUTBOUND_CONNECT_KEEPALIVE, parse_duration);
 
+    let outbound_connect_max_lifetime =
+        parse(strings, ENV_OUTBOUND_CONNECT_MAX_LIFETIME, parse_duration);
+    let outbound_connect_retry_budget =
+        parse(strings, ENV_OUTBOUND_CONNECT_RETRY_BUDGET, parse_number);
+    let outbound_connect_retry_cooldown =
+        parse(strings, ENV_OUTBOUND_CONNECT_RETRY_COOLDOWN, parse_duration);
+
     let inbound_disable_ports = parse(
         strings,
         ENV_INBOUND_PORTS_DISABLE_PROTOCOL_DETECTION,
@@ -245,6 +808,30 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
         parse_port_set,
     );
 
+    let inbound_strip_response_headers = parse(
+        strings,
+        ENV_INBOUND_STRIP_RESPONSE_HEADERS,
+        parse_header_names,
+    );
+    let outbound_strip_response_headers = parse(
+        strings,
+        ENV_OUTBOUND_STRIP_RESPONSE_HEADERS,
+        parse_header_names,
+    );
+    let inbound_correlation_id_header = parse(
+        strings,
+        ENV_INBOUND_CORRELATION_ID_HEADER,
+        parse_header_name,
+    );
+    let outbound_correlation_id_header = parse(
+        strings,
+        ENV_OUTBOUND_CORRELATION_ID_HEADER,
+        parse_header_name,
+    );
+    let correlation_id_echo = strings
+        .get(ENV_CORRELATION_ID_ECHO)
+        .map(|v| v.map(|v| !v.is_empty()).unwrap_or(false));
+
     let inbound_router_capacity = parse(strings, ENV_INBOUND_ROUTER_CAPACITY, parse_number);
     let outbound_router_capacity = parse(strings, ENV_OUTBOUND_ROUTER_CAPACITY, parse_number);
 
@@ -252,11 +839,205 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
         parse(strings, ENV_INBOUND_ROUTER_MAX_IDLE_AGE, parse_duration);
     let outbound_router_max_idle_age =
         parse(strings, ENV_OUTBOUND_ROUTER_MAX_IDLE_AGE, parse_duration);
+    let outbound_router_shards = parse(strings, ENV_OUTBOUND_ROUTER_SHARDS, parse_number);
+    let outbound_route_unmatched_log_interval = parse(
+        strings,
+        ENV_OUTBOUND_ROUTE_UNMATCHED_LOG_INTERVAL,
+        parse_duration,
+    );
+    let outbound_dst_conflict_policy = parse(
+        strings,
+        ENV_OUTBOUND_DST_CONFLICT_POLICY,
+        parse_dst_conflict_policy,
+    );
+    let outbound_route_backups = parse(strings, ENV_OUTBOUND_ROUTE_BACKUPS, parse_route_backups);
+    let outbound_empty_response_failures = parse(
+        strings,
+        ENV_OUTBOUND_EMPTY_RESPONSE_FAILURES,
+        parse_empty_response_failures,
+    );
+    let outbound_coalesced_routes = parse(
+        strings,
+        ENV_OUTBOUND_COALESCED_ROUTES,
+        parse_coalesced_routes,
+    );
+    let outbound_preserve_scheme_routes = parse(
+        strings,
+        ENV_OUTBOUND_PRESERVE_SCHEME_ROUTES,
+        parse_preserve_scheme_routes,
+    );
+    let outbound_affinity_cookie_routes = parse(
+        strings,
+        ENV_OUTBOUND_AFFINITY_COOKIE_ROUTES,
+        parse_affinity_cookie_routes,
+    );
+    let outbound_pin_endpoint_header =
+        parse(strings, ENV_OUTBOUND_PIN_ENDPOINT_HEADER, parse_header_name);
+    let outbound_route_transforms = parse(
+        strings,
+        ENV_OUTBOUND_ROUTE_TRANSFORMS,
+        parse_route_transforms,
+    );
+    let inbound_route_transforms = parse(
+        strings,
+        ENV_INBOUND_ROUTE_TRANSFORMS,
+        parse_route_transforms,
+    );
+    let outbound_coalesce_max_body_bytes =
+        parse(strings, ENV_OUTBOUND_COALESCE_MAX_BODY_BYTES, parse_number);
+    let outbound_coalesce_isolate_clients = strings
+        .get(ENV_OUTBOUND_COALESCE_ISOLATE_CLIENTS)
+        .map(|v| v.map(|v| !v.is_empty()).unwrap_or(false));
+    let outbound_max_queue_times =
+        parse(strings, ENV_OUTBOUND_MAX_QUEUE_TIMES, parse_max_queue_times);
+    let outbound_label_headers = parse(strings, ENV_OUTBOUND_LABEL_HEADERS, parse_label_headers);
+    let outbound_label_headers_overwrite = strings
+        .get(ENV_OUTBOUND_LABEL_HEADERS_OVERWRITE)
+        .map(|v| v.map(|v| !v.is_empty()).unwrap_or(false));
+    let outbound_tls_origination =
+        parse(strings, ENV_OUTBOUND_TLS_ORIGINATION, parse_tls_origination);
+    let outbound_tls_peer_names = parse(strings, ENV_OUTBOUND_TLS_PEER_NAMES, parse_tls_peer_names);
+    let outbound_rng_seed = parse(strings, ENV_OUTBOUND_RNG_SEED, parse_number);
+    let outbound_balancer_min_ready_endpoints = parse(
+        strings,
+        ENV_OUTBOUND_BALANCER_MIN_READY_ENDPOINTS,
+        parse_number,
+    );
+    let outbound_balancer_min_ready_timeout = parse(
+        strings,
+        ENV_OUTBOUND_BALANCER_MIN_READY_TIMEOUT,
+        parse_duration,
+    );
+    let outbound_balancer_max_concurrent_builds = parse(
+        strings,
+        ENV_OUTBOUND_BALANCER_MAX_CONCURRENT_BUILDS,
+        parse_number,
+    );
+    let outbound_balancer_ewma_overrides = parse(
+        strings,
+        ENV_OUTBOUND_BALANCER_EWMA_OVERRIDES,
+        parse_ewma_overrides,
+    );
+    let outbound_max_concurrent_connects =
+        parse(strings, ENV_OUTBOUND_MAX_CONCURRENT_CONNECTS, parse_number);
+    let outbound_connection_limits = parse(
+        strings,
+        ENV_OUTBOUND_CONNECTION_LIMITS,
+        parse_connection_limits,
+    );
+    let outbound_connection_limit_per_dst =
+        parse(strings, ENV_OUTBOUND_CONNECTION_LIMIT_PER_DST, parse_number);
+    let outbound_connection_limit_max_wait = parse(
+        strings,
+        ENV_OUTBOUND_CONNECTION_LIMIT_MAX_WAIT,
+        parse_duration,
+    );
+    let outbound_retry_reset_max_body_bytes = parse(
+        strings,
+        ENV_OUTBOUND_RETRY_RESET_MAX_BODY_BYTES,
+        parse_number,
+    );
+    let outbound_body_checksum_dsts = parse(
+        strings,
+        ENV_OUTBOUND_BODY_CHECKSUM_DSTS,
+        parse_name_addr_set,
+    );
+    let outbound_body_checksum_ports =
+        parse(strings, ENV_OUTBOUND_BODY_CHECKSUM_PORTS, parse_port_set);
+    let outbound_dry_run_balancer = strings
+        .get(ENV_OUTBOUND_DRY_RUN_BALANCER)
+        .map(|v| v.map(|v| !v.is_empty()).unwrap_or(false));
+    let outbound_dry_run_retry = parse(
+        strings,
+        ENV_OUTBOUND_DRY_RUN_RETRY_STATUSES,
+        parse_dry_run_retry_condition,
+    );
+
+    let inbound_dst_name_limit = parse(strings, ENV_INBOUND_DST_NAME_LIMIT, parse_number);
+    let inbound_dst_name_limit_window =
+        parse(strings, ENV_INBOUND_DST_NAME_LIMIT_WINDOW, parse_duration);
+    let inbound_collapse_request_headers = parse(
+        strings,
+        ENV_INBOUND_COLLAPSE_REQUEST_HEADERS,
+        parse_collapse_headers,
+    );
+    let inbound_startup_shield_retry_after = parse(
+        strings,
+        ENV_INBOUND_STARTUP_SHIELD_RETRY_AFTER,
+        parse_duration,
+    );
+    let inbound_identity_startup = parse(
+        strings,
+        ENV_INBOUND_IDENTITY_STARTUP,
+        parse_identity_startup,
+    );
+    let inbound_identity_startup_timeout = parse(
+        strings,
+        ENV_INBOUND_IDENTITY_STARTUP_TIMEOUT,
+        parse_duration,
+    );
 
     let inbound_max_in_flight = parse(strings, ENV_INBOUND_MAX_IN_FLIGHT, parse_number);
+    let inbound_adaptive_concurrency_min =
+        parse(strings, ENV_INBOUND_ADAPTIVE_CONCURRENCY_MIN, parse_number);
+    let inbound_adaptive_concurrency_max =
+        parse(strings, ENV_INBOUND_ADAPTIVE_CONCURRENCY_MAX, parse_number);
+    let inbound_brownout_latency_threshold = parse(
+        strings,
+        ENV_INBOUND_BROWNOUT_LATENCY_THRESHOLD,
+        parse_duration,
+    );
     let outbound_max_in_flight = parse(strings, ENV_OUTBOUND_MAX_IN_FLIGHT, parse_number);
+    let buffer_drain_policy = parse(strings, ENV_BUFFER_DRAIN_POLICY, parse_buffer_drain_policy);
+    let server_max_requests_per_connection = parse(
+        strings,
+        ENV_SERVER_MAX_REQUESTS_PER_CONNECTION,
+        parse_number::<usize>,
+    );
+    let inbound_h2_max_resets = parse(strings, ENV_INBOUND_H2_MAX_RESETS, parse_number);
+    let inbound_h2_max_resets_window =
+        parse(strings, ENV_INBOUND_H2_MAX_RESETS_WINDOW, parse_duration);
+    let server_accept_idle_timeout = parse(strings, ENV_SERVER_ACCEPT_IDLE_TIMEOUT, parse_duration);
+    let server_keep_alive_idle_timeout =
+        parse(strings, ENV_SERVER_KEEP_ALIVE_IDLE_TIMEOUT, parse_duration);
+    let error_log_dedup_window = parse(strings, ENV_ERROR_LOG_DEDUP_WINDOW, parse_duration);
+    let inbound_caller_overrides = strings
+        .get(ENV_INBOUND_CALLER_OVERRIDES)
+        .map(|v| v.map(|v| !v.is_empty()).unwrap_or(false));
+    let inbound_require_sni = strings
+        .get(ENV_INBOUND_REQUIRE_SNI)
+        .map(|v| v.map(|v| !v.is_empty()).unwrap_or(false));
+    let accept_watchdog_stall_timeout =
+        parse(strings, ENV_ACCEPT_WATCHDOG_STALL_TIMEOUT, parse_duration);
+    let accept_watchdog_max_silence =
+        parse(strings, ENV_ACCEPT_WATCHDOG_MAX_SILENCE, parse_duration);
+    let accept_watchdog_recover = strings
+        .get(ENV_ACCEPT_WATCHDOG_RECOVER)
+        .map(|v| v.map(|v| !v.is_empty()).unwrap_or(false));
+    let startup_queue_capacity = parse(strings, ENV_STARTUP_QUEUE_CAPACITY, parse_number);
+    let inbound_forward_addrs = parse_inbound_forward_addrs(strings);
+    let outbound_caller_overrides = strings
+        .get(ENV_OUTBOUND_CALLER_OVERRIDES)
+        .map(|v| v.map(|v| !v.is_empty()).unwrap_or(false));
+    let caller_override_max_timeout =
+        parse(strings, ENV_CALLER_OVERRIDE_MAX_TIMEOUT, parse_duration);
+    let inbound_header_trust = parse(strings, ENV_INBOUND_HEADER_TRUST, parse_header_trust);
+    let outbound_header_trust = parse(strings, ENV_OUTBOUND_HEADER_TRUST, parse_header_trust);
+    let max_response_header_bytes = parse(strings, ENV_MAX_RESPONSE_HEADER_BYTES, parse_number);
+    let response_header_limit_policy = parse(
+        strings,
+        ENV_RESPONSE_HEADER_LIMIT_POLICY,
+        parse_response_header_limit_policy,
+    );
 
     let metrics_retain_idle = parse(strings, ENV_METRICS_RETAIN_IDLE, parse_duration);
+    let metrics_rate_window = parse(strings, ENV_METRICS_RATE_WINDOW, parse_duration);
+    let metrics_summary_drop_route_labels = parse(
+        strings,
+        ENV_METRICS_SUMMARY_DROP_ROUTE_LABELS,
+        parse_label_names,
+    );
+    let metrics_stack_stage_sample = parse(strings, ENV_METRICS_STACK_STAGE_SAMPLE, parse_number);
 
     // DNS
 
@@ -266,6 +1047,7 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
     let dns_max_ttl = parse(strings, ENV_DNS_MAX_TTL, parse_duration);
 
     let dns_canonicalize_timeout = parse(strings, ENV_DNS_CANONICALIZE_TIMEOUT, parse_duration);
+    let dns_canonicalize_freshness = parse(strings, ENV_DNS_CANONICALIZE_FRESHNESS, parse_duration);
 
     let identity_config = parse_identity_config(strings);
 
@@ -297,10 +1079,39 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
         ENV_DESTINATION_PROFILE_SUFFIXES,
         parse_dns_suffixes,
     );
+    let dst_profile_max_retries = parse(strings, ENV_DESTINATION_PROFILE_MAX_RETRIES, parse_number);
+    let dst_profile_max_concurrent_rebuilds = parse(
+        strings,
+        ENV_DESTINATION_PROFILE_MAX_CONCURRENT_REBUILDS,
+        parse_number,
+    );
+    let dst_profile_max_routes = parse(strings, ENV_DESTINATION_PROFILE_MAX_ROUTES, parse_number);
+    let dst_profile_negative_cache_ttl = parse(
+        strings,
+        ENV_DESTINATION_PROFILE_NEGATIVE_CACHE_TTL,
+        parse_duration,
+    );
+    let dst_static_endpoints = parse(
+        strings,
+        ENV_DESTINATION_STATIC_ENDPOINTS,
+        parse_static_endpoints,
+    );
+    let dst_static_endpoints_watch_interval = parse(
+        strings,
+        ENV_DESTINATION_STATIC_ENDPOINTS_WATCH_INTERVAL,
+        parse_duration,
+    );
 
     let initial_stream_window_size = parse(strings, ENV_INITIAL_STREAM_WINDOW_SIZE, parse_number);
     let initial_connection_window_size =
         parse(strings, ENV_INITIAL_CONNECTION_WINDOW_SIZE, parse_number);
+    let outbound_h2_warmup_window =
+        parse(strings, ENV_OUTBOUND_HTTP2_WARMUP_WINDOW, parse_duration);
+    let outbound_h2_warmup_initial_streams = parse(
+        strings,
+        ENV_OUTBOUND_HTTP2_WARMUP_INITIAL_STREAMS,
+        parse_number,
+    );
 
     let tap = parse_tap_config(strings, id_disabled);
 
@@ -311,6 +1122,32 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
         initial_connection_window_size: Some(
             initial_connection_window_size?.unwrap_or(DEFAULT_INITIAL_CONNECTION_WINDOW_SIZE),
         ),
+        warmup: None,
+    };
+
+    let caller_override = caller_override::Config {
+        max_timeout: caller_override_max_timeout?.unwrap_or(DEFAULT_CALLER_OVERRIDE_MAX_TIMEOUT),
+    };
+
+    let max_response_headers = {
+        let policy = response_header_limit_policy?.unwrap_or(header_limit::Policy::Reject);
+        max_response_header_bytes?.map(|max_bytes| header_limit::Limit { max_bytes, policy })
+    };
+
+    let startup_queue_capacity = startup_queue_capacity?.unwrap_or(DEFAULT_STARTUP_QUEUE_CAPACITY);
+
+    let accept_watchdog = {
+        let max_silence =
+            accept_watchdog_max_silence?.unwrap_or(DEFAULT_ACCEPT_WATCHDOG_MAX_SILENCE);
+        let recover = accept_watchdog_recover?;
+        accept_watchdog_stall_timeout?.map(|stall_timeout| accept_watchdog::Config {
+            // Idle loops only beat when they're checked, so loops are checked
+            // well within the stall timeout.
+            check_interval: stall_timeout / 2,
+            stall_timeout,
+            max_silence: max_silence.max(stall_timeout),
+            recover,
+        })
     };
 
     let outbound = {
@@ -325,22 +1162,103 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
                 dispatch_timeout: outbound_dispatch_timeout?
                     .unwrap_or(DEFAULT_OUTBOUND_DISPATCH_TIMEOUT),
                 max_in_flight: outbound_max_in_flight?.unwrap_or(DEFAULT_OUTBOUND_MAX_IN_FLIGHT),
+                drain_policy: buffer_drain_policy.clone()?.unwrap_or_default(),
+                min_dispatch_budget: outbound_min_dispatch_budget?,
             },
             h2_settings,
+            max_requests_per_connection: server_max_requests_per_connection
+                .clone()?
+                .filter(|n| *n > 0),
+            h2_reset_limit: None,
+            idle_timeouts: IdleTimeouts {
+                accept: server_accept_idle_timeout.clone()?,
+                keep_alive: server_keep_alive_idle_timeout.clone()?,
+            },
+        };
+        let outbound_h2_warmup = {
+            let initial_streams = outbound_h2_warmup_initial_streams?
+                .unwrap_or(DEFAULT_OUTBOUND_HTTP2_WARMUP_INITIAL_STREAMS);
+            outbound_h2_warmup_window?.map(|window| h2::Warmup {
+                initial_streams,
+                window,
+            })
         };
         let connect = ConnectConfig {
             keepalive: outbound_connect_keepalive?,
+            max_lifetime: outbound_connect_max_lifetime?,
+            retry_budget: {
+                let cooldown = outbound_connect_retry_cooldown?
+                    .unwrap_or(DEFAULT_OUTBOUND_CONNECT_RETRY_COOLDOWN);
+                outbound_connect_retry_budget?.map(|max_retries| reconnect::Budget {
+                    max_retries,
+                    cooldown,
+                })
+            },
             timeout: outbound_connect_timeout?.unwrap_or(DEFAULT_OUTBOUND_CONNECT_TIMEOUT),
             backoff: parse_backoff(
                 strings,
                 OUTBOUND_CONNECT_BASE,
                 DEFAULT_OUTBOUND_CONNECT_BACKOFF,
             )?,
-            h2_settings,
+            h2_settings: h2::Settings {
+                warmup: outbound_h2_warmup,
+                ..h2_settings
+            },
         };
         outbound::Config {
             canonicalize_timeout: dns_canonicalize_timeout?
                 .unwrap_or(DEFAULT_DNS_CANONICALIZE_TIMEOUT),
+            canonicalize_freshness: dns_canonicalize_freshness?,
+            route_unmatched_log_interval: outbound_route_unmatched_log_interval?,
+            dst_conflict_policy: outbound_dst_conflict_policy?.unwrap_or_default(),
+            route_backups: outbound_route_backups?.unwrap_or_default(),
+            empty_response_failures: outbound_empty_response_failures?.unwrap_or_default(),
+            coalesced_routes: outbound_coalesced_routes?.unwrap_or_default(),
+            coalesce: coalesce::Config {
+                max_body_bytes: outbound_coalesce_max_body_bytes?
+                    .unwrap_or(DEFAULT_OUTBOUND_COALESCE_MAX_BODY_BYTES),
+                isolate_clients: outbound_coalesce_isolate_clients?,
+            },
+            preserve_scheme_routes: outbound_preserve_scheme_routes?.unwrap_or_default(),
+            affinity_cookie_routes: outbound_affinity_cookie_routes?.unwrap_or_default(),
+            pin_endpoint_header: outbound_pin_endpoint_header?,
+            route_transforms: outbound_route_transforms?.unwrap_or_default(),
+            max_queue_times: outbound_max_queue_times?.unwrap_or_default(),
+            label_headers: outbound_label_headers?.unwrap_or_default(),
+            label_headers_overwrite: outbound_label_headers_overwrite?,
+            tls_origination: outbound_tls_origination?.unwrap_or_default(),
+            tls_peer_names: outbound_tls_peer_names?.unwrap_or_default(),
+            rng_seed: outbound_rng_seed?,
+            balancer_min_ready: {
+                let timeout = outbound_balancer_min_ready_timeout?
+                    .unwrap_or(DEFAULT_OUTBOUND_BALANCER_MIN_READY_TIMEOUT);
+                outbound_balancer_min_ready_endpoints?.map(|min_endpoints| min_ready::Config {
+                    min_endpoints,
+                    timeout,
+                })
+            },
+            balancer_max_concurrent_builds: outbound_balancer_max_concurrent_builds?
+                .filter(|n| *n > 0),
+            balancer_ewma_overrides: outbound_balancer_ewma_overrides?.unwrap_or_default(),
+            max_concurrent_connects: outbound_max_concurrent_connects?,
+            connection_limits: connection_limit::Config {
+                limits: outbound_connection_limits?.unwrap_or_default(),
+                max_per_dst: outbound_connection_limit_per_dst?.filter(|n| *n > 0),
+                max_wait: outbound_connection_limit_max_wait?
+                    .unwrap_or(DEFAULT_OUTBOUND_CONNECTION_LIMIT_MAX_WAIT),
+            },
+            retry_reset_max_body_bytes: outbound_retry_reset_max_body_bytes?,
+            body_checksum: outbound::BodyChecksumConfig {
+                dsts: outbound_body_checksum_dsts?.unwrap_or_default(),
+                ports: outbound_body_checksum_ports?.unwrap_or_default(),
+            },
+            accept_watchdog,
+            dry_run: outbound::DryRunConfig {
+                balancer: outbound_dry_run_balancer?,
+                retry: outbound_dry_run_retry?,
+            },
+            startup_queue_capacity,
+            router_shards: outbound_router_shards?.unwrap_or(DEFAULT_OUTBOUND_ROUTER_SHARDS),
             proxy: ProxyConfig {
                 server,
                 connect,
@@ -351,6 +1269,19 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
                     .unwrap_or(DEFAULT_OUTBOUND_ROUTER_MAX_IDLE_AGE),
                 router_capacity: outbound_router_capacity?
                     .unwrap_or(DEFAULT_OUTBOUND_ROUTER_CAPACITY),
+                strip_response_headers: outbound_strip_response_headers?.unwrap_or_default().into(),
+                error_log_dedup_window: error_log_dedup_window
+                    .clone()?
+                    .filter(|w| *w > Duration::from_secs(0)),
+                correlation_id_header: outbound_correlation_id_header?,
+                correlation_id_echo: correlation_id_echo.clone()?,
+                caller_overrides: if outbound_caller_overrides? {
+                    Some(caller_override)
+                } else {
+                    None
+                },
+                header_trust: outbound_header_trust?.unwrap_or_default(),
+                max_response_headers,
             },
         }
     };
@@ -361,17 +1292,40 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
                 .unwrap_or_else(|| parse_socket_addr(DEFAULT_INBOUND_LISTEN_ADDR).unwrap()),
             inbound_accept_keepalive?,
         );
+        let max_in_flight = inbound_max_in_flight?.unwrap_or(DEFAULT_INBOUND_MAX_IN_FLIGHT);
+        let adaptive_concurrency = {
+            let max = inbound_adaptive_concurrency_max?.unwrap_or(max_in_flight);
+            inbound_adaptive_concurrency_min?.map(|min| admission::Adaptive {
+                min,
+                max: max.max(min),
+                tolerance: admission::DEFAULT_ADAPTIVE_TOLERANCE,
+            })
+        };
         let server = ServerConfig {
             bind: bind.with_sys_orig_dst_addr(),
             buffer: BufferConfig {
                 dispatch_timeout: inbound_dispatch_timeout?
                     .unwrap_or(DEFAULT_INBOUND_DISPATCH_TIMEOUT),
-                max_in_flight: inbound_max_in_flight?.unwrap_or(DEFAULT_INBOUND_MAX_IN_FLIGHT),
+                max_in_flight,
+                drain_policy: buffer_drain_policy?.unwrap_or_default(),
+                min_dispatch_budget: inbound_min_dispatch_budget?,
             },
             h2_settings,
+            max_requests_per_connection: server_max_requests_per_connection?.filter(|n| *n > 0),
+            h2_reset_limit: {
+                let window =
+                    inbound_h2_max_resets_window?.unwrap_or(DEFAULT_INBOUND_H2_MAX_RESETS_WINDOW);
+                inbound_h2_max_resets?.map(|max_resets| ResetLimit { max_resets, window })
+            },
+            idle_timeouts: IdleTimeouts {
+                accept: server_accept_idle_timeout?,
+                keep_alive: server_keep_alive_idle_timeout?,
+            },
         };
         let connect = ConnectConfig {
             keepalive: inbound_connect_keepalive?,
+            max_lifetime: None,
+            retry_budget: None,
             timeout: inbound_connect_timeout?.unwrap_or(DEFAULT_INBOUND_CONNECT_TIMEOUT),
             backoff: parse_backoff(
                 strings,
@@ -381,6 +1335,30 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
             h2_settings,
         };
         inbound::Config {
+            dst_name_limit: inbound_dst_name_limit?,
+            dst_name_limit_window: inbound_dst_name_limit_window?
+                .unwrap_or(DEFAULT_INBOUND_DST_NAME_LIMIT_WINDOW),
+            identity_startup: match inbound_identity_startup? {
+                Some(inbound::IdentityStartup::WaitForIdentity { .. }) => {
+                    inbound::IdentityStartup::WaitForIdentity {
+                        timeout: inbound_identity_startup_timeout?
+                            .unwrap_or(DEFAULT_INBOUND_IDENTITY_STARTUP_TIMEOUT),
+                    }
+                }
+                _ => inbound::IdentityStartup::ServePlaintext,
+            },
+            startup_shield_retry_after: inbound_startup_shield_retry_after?,
+            collapse_request_headers: inbound_collapse_request_headers?.unwrap_or_default().into(),
+            adaptive_concurrency,
+            brownout: inbound_brownout_latency_threshold?.map(|threshold| admission::Brownout {
+                threshold,
+                decay: admission::DEFAULT_BROWNOUT_DECAY,
+            }),
+            route_transforms: inbound_route_transforms?.unwrap_or_default(),
+            accept_watchdog,
+            require_sni: inbound_require_sni?,
+            startup_queue_capacity,
+            forward_addrs: inbound_forward_addrs?,
             proxy: ProxyConfig {
                 server,
                 connect,
@@ -391,6 +1369,18 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
                     .unwrap_or(DEFAULT_INBOUND_ROUTER_MAX_IDLE_AGE),
                 router_capacity: inbound_router_capacity?
                     .unwrap_or(DEFAULT_INBOUND_ROUTER_CAPACITY),
+                strip_response_headers: inbound_strip_response_headers?.unwrap_or_default().into(),
+                error_log_dedup_window: error_log_dedup_window?
+                    .filter(|w| *w > Duration::from_secs(0)),
+                correlation_id_header: inbound_correlation_id_header?,
+                correlation_id_echo: correlation_id_echo?,
+                caller_overrides: if inbound_caller_overrides? {
+                    Some(caller_override)
+                } else {
+                    None
+                },
+                header_trust: inbound_header_trust?.unwrap_or_default(),
+                max_response_headers,
             },
         }
     };
@@ -414,6 +1404,27 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
             get_networks: dst_get_networks?.unwrap_or_default(),
             profile_suffixes: dst_profile_suffixes?
                 .unwrap_or(parse_dns_suffixes(DEFAULT_DESTINATION_PROFILE_SUFFIXES).unwrap()),
+            profile_retry: profiles::Retry {
+                max_retries: dst_profile_max_retries?
+                    .unwrap_or(DEFAULT_DESTINATION_PROFILE_MAX_RETRIES),
+                backoff: parse_backoff(
+                    strings,
+                    DESTINATION_PROFILE_BASE,
+                    DEFAULT_DESTINATION_PROFILE_BACKOFF,
+                )?,
+            },
+            profile_max_concurrent_rebuilds: dst_profile_max_concurrent_rebuilds?
+                .unwrap_or(profiles::DEFAULT_MAX_CONCURRENT_REBUILDS),
+            profile_max_routes: dst_profile_max_routes?,
+            profile_negative_cache_ttl: dst_profile_negative_cache_ttl?,
+            static_endpoints: {
+                let watch_interval = dst_static_endpoints_watch_interval?;
+                dst_static_endpoints?.map(|(path, table)| static_endpoints::Config {
+                    path,
+                    table,
+                    watch_interval,
+                })
+            },
             control: ControlConfig {
                 addr,
                 connect,
@@ -424,6 +1435,11 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
 
     let admin = super::admin::Config {
         metrics_retain_idle: metrics_retain_idle?.unwrap_or(DEFAULT_METRICS_RETAIN_IDLE),
+        metrics_rate_window: metrics_rate_window?.unwrap_or(DEFAULT_METRICS_RATE_WINDOW),
+        metrics_summary_drop_route_labels: metrics_summary_drop_route_labels?
+            .unwrap_or_default()
+            .into(),
+        metrics_stack_stage_sample: metrics_stack_stage_sample?,
         server: ServerConfig {
             bind: listen::Bind::new(
                 admin_listener_addr?
@@ -432,6 +1448,9 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
             ),
             buffer: inbound.proxy.server.buffer,
             h2_settings,
+            max_requests_per_connection: None,
+            h2_reset_limit: None,
+            idle_timeouts: IdleTimeouts::default(),
         },
     };
 
@@ -469,6 +1488,9 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
                 bind: listen::Bind::new(addr, inbound.proxy.server.bind.keepalive()),
                 buffer: inbound.proxy.server.buffer,
                 h2_settings,
+                max_requests_per_connection: None,
+                h2_reset_limit: None,
+                idle_timeouts: IdleTimeouts::default(),
             },
         })
         .unwrap_or(super::tap::Config::Disabled);
@@ -497,6 +1519,11 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
         })
         .unwrap_or(identity::Config::Disabled);
 
+    let persist = strings
+        .get(ENV_CACHE_PERSIST_PATH)?
+        .filter(|path| !path.is_empty())
+        .map(|path| super::persist::Config { path: path.into() });
+
     Ok(super::Config {
         admin,
         dns,
@@ -506,6 +1533,24 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
         identity,
         outbound,
         inbound,
+        persist,
+    })
+}
+
+/// Loads the addresses to which inbound connections are forwarded, ensuring
+/// that each is either a loopback address or explicitly allowed.
+fn parse_inbound_forward_addrs(strings: &dyn Strings) -> Result<inbound::ForwardAddrs, EnvError> {
+    let allowlist =
+        parse(strings, ENV_INBOUND_FORWARD_ADDR_ALLOWLIST, parse_ip_set)?.unwrap_or_default();
+    let addr = parse(strings, ENV_INBOUND_FORWARD_ADDR, |s| {
+        parse_forward_addr(s, &allowlist)
+    })?;
+    let ports = parse(strings, ENV_INBOUND_FORWARD_PORT_ADDRS, |s| {
+        parse_forward_port_addrs(s, &allowlist)
+    })?;
+    Ok(inbound::ForwardAddrs {
+        addr,
+        ports: ports.unwrap_or_default(),
     })
 }
 
@@ -612,6 +1657,46 @@ fn parse_duration(s: &str) -> Result<Duration, ParseError> {
     }
 }
 
+fn parse_dst_conflict_policy(s: &str) -> Result<dst_conflict::Policy, ParseError> {
+    match s.to_ascii_lowercase().as_str() {
+        "ignore" => Ok(dst_conflict::Policy::Ignore),
+        "log" => Ok(dst_conflict::Policy::Log),
+        "reject" => Ok(dst_conflict::Policy::Reject),
+        _ => Err(ParseError::InvalidDstConflictPolicy),
+    }
+}
+
+fn parse_buffer_drain_policy(s: &str) -> Result<DrainPolicy, ParseError> {
+    match s.to_ascii_lowercase().as_str() {
+        "complete" => Ok(DrainPolicy::Complete),
+        "fail-fast" => Ok(DrainPolicy::FailFast),
+        _ => Err(ParseError::InvalidBufferDrainPolicy),
+    }
+}
+
+fn parse_response_header_limit_policy(s: &str) -> Result<header_limit::Policy, ParseError> {
+    match s.to_ascii_lowercase().as_str() {
+        "reject" => Ok(header_limit::Policy::Reject),
+        "truncate" => Ok(header_limit::Policy::Truncate),
+        _ => Err(ParseError::InvalidResponseHeaderLimitPolicy),
+    }
+}
+
+fn parse_dry_run_retry_condition(s: &str) -> Result<dry_run::retry::Condition, ParseError> {
+    s.parse()
+        .map_err(|_| ParseError::InvalidDryRunRetryCondition)
+}
+
+fn parse_identity_startup(s: &str) -> Result<inbound::IdentityStartup, ParseError> {
+    match s.to_ascii_lowercase().as_str() {
+        "wait-for-identity" => Ok(inbound::IdentityStartup::WaitForIdentity {
+            timeout: DEFAULT_INBOUND_IDENTITY_STARTUP_TIMEOUT,
+        }),
+        "serve-plaintext" => Ok(inbound::IdentityStartup::ServePlaintext),
+        _ => Err(ParseError::InvalidIdentityStartup),
+    }
+}
+
 fn parse_socket_addr(s: &str) -> Result<SocketAddr, ParseError> {
     match parse_addr(s)? {
         Addr::Socket(a) => Ok(a),
@@ -629,83 +1714,643 @@ fn parse_addr(s: &str) -> Result<Addr, ParseError> {
     })
 }
 
-fn parse_port_set(s: &str) -> Result<IndexSet<u16>, ParseError> {
-    let mut set = IndexSet::new();
-    for num in s.split(',') {
-        set.insert(parse_number::<u16>(num)?);
+fn parse_name_addr(s: &str) -> Result<NameAddr, ParseError> {
+    match parse_addr(s)? {
+        Addr::Name(n) => Ok(n),
+        Addr::Socket(_) => {
+            error!("Expected NAME:PORT; found: {}", s);
+            Err(ParseError::NameError)
+        }
     }
-    Ok(set)
 }
 
-pub(super) fn parse_identity(s: &str) -> Result<identity::Name, ParseError> {
-    identity::Name::from_hostname(s.as_bytes()).map_err(|identity::InvalidName| {
-        error!("Not a valid identity name: {}", s);
-        ParseError::NameError
-    })
+fn parse_name_addr_set(s: &str) -> Result<IndexSet<NameAddr>, ParseError> {
+    let mut set = IndexSet::new();
+    for dst in s.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+        set.insert(parse_name_addr(dst)?);
+    }
+    Ok(set)
 }
 
-pub(super) fn parse<T, Parse>(
-    strings: &dyn Strings,
-    name: &str,
-    parse: Parse,
-) -> Result<Option<T>, EnvError>
-where
-    Parse: FnOnce(&str) -> Result<T, ParseError>,
-{
-    match strings.get(name)? {
-        Some(ref s) => {
-            let r = parse(s).map_err(|parse_error| {
-                error!("{}={:?} is not valid: {:?}", name, s, parse_error);
-                EnvError::InvalidEnvVar
-            })?;
-            Ok(Some(r))
+fn parse_route_backups(
+    s: &str,
+) -> Result<IndexMap<NameAddr, IndexMap<String, NameAddr>>, ParseError> {
+    let mut backups = IndexMap::new();
+    for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let mut parts = entry.splitn(2, ';');
+        let mut dst_route = parts.next().unwrap_or_default().splitn(2, '=');
+        let dst_route = (dst_route.next(), dst_route.next().map(str::trim));
+        match (dst_route, parts.next().map(str::trim)) {
+            ((Some(dst), Some(route)), Some(backup)) if !route.is_empty() => {
+                let backup = parse_name_addr(backup)?;
+                backups
+                    .entry(parse_name_addr(dst.trim())?)
+                    .or_insert_with(IndexMap::new)
+                    .insert(route.to_owned(), backup);
+            }
+            _ => {
+                error!("Expected DST=ROUTE;BACKUP; found: {}", entry);
+                return Err(ParseError::InvalidRouteBackup);
+            }
         }
-        None => Ok(None),
     }
+    Ok(backups)
 }
 
-#[allow(dead_code)]
-fn parse_deprecated<T, Parse>(
-    strings: &dyn Strings,
-    name: &str,
-    deprecated_name: &str,
-    f: Parse,
-) -> Result<Option<T>, EnvError>
-where
-    Parse: Copy,
-    Parse: Fn(&str) -> Result<T, ParseError>,
-{
-    match parse(strings, name, f)? {
-        Some(v) => Ok(Some(v)),
-        None => {
-            let v = parse(strings, deprecated_name, f)?;
-            if v.is_some() {
-                warn!("{} has been deprecated; use {}", deprecated_name, name);
+fn parse_empty_response_failures(
+    s: &str,
+) -> Result<IndexMap<NameAddr, IndexSet<String>>, ParseError> {
+    parse_dst_routes(s, ParseError::InvalidEmptyResponseFailure)
+}
+
+fn parse_coalesced_routes(s: &str) -> Result<IndexMap<NameAddr, IndexSet<String>>, ParseError> {
+    parse_dst_routes(s, ParseError::InvalidCoalescedRoute)
+}
+
+fn parse_preserve_scheme_routes(
+    s: &str,
+) -> Result<IndexMap<NameAddr, IndexSet<String>>, ParseError> {
+    parse_dst_routes(s, ParseError::InvalidPreserveSchemeRoute)
+}
+
+fn parse_affinity_cookie_routes(
+    s: &str,
+) -> Result<IndexMap<NameAddr, IndexMap<String, String>>, ParseError> {
+    let mut routes = IndexMap::new();
+    for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let mut parts = entry.splitn(2, ';');
+        let mut dst_route = parts.next().unwrap_or_default().splitn(2, '=');
+        let dst_route = (dst_route.next(), dst_route.next().map(str::trim));
+        match (dst_route, parts.next().map(str::trim)) {
+            ((Some(dst), Some(route)), Some(cookie))
+                if !route.is_empty() && is_cookie_name(cookie) =>
+            {
+                routes
+                    .entry(parse_name_addr(dst.trim())?)
+                    .or_insert_with(IndexMap::new)
+                    .insert(route.to_owned(), cookie.to_owned());
+            }
+            _ => {
+                error!("Expected DST=ROUTE;COOKIE; found: {}", entry);
+                return Err(ParseError::InvalidAffinityCookieRoute);
             }
-            Ok(v)
         }
     }
+    Ok(routes)
 }
 
-fn parse_dns_suffixes(list: &str) -> Result<IndexSet<dns::Suffix>, ParseError> {
-    let mut suffixes = IndexSet::new();
-    for item in list.split(',') {
-        let item = item.trim();
-        if !item.is_empty() {
-            let sfx = parse_dns_suffix(item)?;
-            suffixes.insert(sfx);
+/// Returns true if `s` is a valid cookie name, i.e. an RFC 7230 token.
+fn is_cookie_name(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+fn parse_route_transforms(
+    s: &str,
+) -> Result<IndexMap<NameAddr, IndexMap<String, transform::Transform>>, ParseError> {
+    let mut ops_by_dst = IndexMap::new();
+    for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let mut parts = entry.split(';').map(str::trim);
+        let mut dst_route = parts.next().unwrap_or_default().splitn(2, '=');
+        let (dst, route) = match (dst_route.next(), dst_route.next().map(str::trim)) {
+            (Some(dst), Some(route)) if !route.is_empty() => (dst.trim(), route),
+            _ => {
+                error!("Expected DST=ROUTE;OP...; found: {}", entry);
+                return Err(ParseError::InvalidRouteTransform);
+            }
+        };
+        let ops = ops_by_dst
+            .entry(parse_name_addr(dst)?)
+            .or_insert_with(IndexMap::new)
+            .entry(route.to_owned())
+            .or_insert_with(Vec::new);
+        for op in parts.filter(|op| !op.is_empty()) {
+            ops.push(parse_transform_op(op)?);
         }
     }
 
-    Ok(suffixes)
+    let mut transforms = IndexMap::with_capacity(ops_by_dst.len());
+    for (dst, routes) in ops_by_dst.into_iter() {
+        let mut by_route = IndexMap::with_capacity(routes.len());
+        for (route, ops) in routes.into_iter() {
+            let transform = transform::Transform::new(ops).map_err(|error| {
+                error!(%dst, %route, %error, "Invalid route transform");
+                ParseError::InvalidRouteTransform
+            })?;
+            by_route.insert(route, transform);
+        }
+        transforms.insert(dst, by_route);
+    }
+    Ok(transforms)
 }
 
-fn parse_dns_suffix(s: &str) -> Result<dns::Suffix, ParseError> {
-    if s == "." {
-        return Ok(dns::Suffix::Root);
-    }
+fn parse_transform_op(s: &str) -> Result<transform::Op, ParseError> {
+    use transform::Op;
 
-    dns::Name::try_from(s.as_bytes())
+    let mut parts = s.splitn(2, ':').map(str::trim);
+    let (kind, arg) = match (parts.next(), parts.next()) {
+        (Some(kind), Some(arg)) => (kind, arg),
+        _ => {
+            error!("Expected OP:ARGS; found: {}", s);
+            return Err(ParseError::InvalidRouteTransform);
+        }
+    };
+
+    if kind == "path" {
+        let (pattern, replacement) = parse_transform_pair(arg)?;
+        let rewrite = transform::Rewrite::new(pattern, replacement).map_err(|error| {
+            error!(%pattern, %error, "Invalid path pattern");
+            ParseError::InvalidRouteTransform
+        })?;
+        return Ok(Op::RewritePath(rewrite));
+    }
+
+    if kind == "bucket" {
+        return parse_transform_bucket(arg).map(Op::Bucket);
+    }
+
+    let mut target_op = kind.splitn(2, '.');
+    match (target_op.next(), target_op.next()) {
+        (Some("request"), Some(op)) => parse_header_op(op, arg).map(Op::Request),
+        (Some("response"), Some(op)) => parse_header_op(op, arg).map(Op::Response),
+        _ => {
+            error!("Unknown route transform: {}", s);
+            Err(ParseError::InvalidRouteTransform)
+        }
+    }
+}
+
+fn parse_header_op(op: &str, arg: &str) -> Result<transform::HeaderOp, ParseError> {
+    use transform::HeaderOp;
+
+    let name = |s: &str| {
+        HeaderName::from_bytes(s.as_bytes()).map_err(|_| {
+            error!("Invalid header name: {}", s);
+            ParseError::InvalidRouteTransform
+        })
+    };
+    let header_value = |s: &str| -> Result<(HeaderName, HeaderValue), ParseError> {
+        let (header, value) = parse_transform_pair(s)?;
+        let value = value.parse().map_err(|_| {
+            error!("Invalid header value: {}", value);
+            ParseError::InvalidRouteTransform
+        })?;
+        Ok((name(header)?, value))
+    };
+    match op {
+        "add" => header_value(arg).map(|(h, v)| HeaderOp::Add(h, v)),
+        "set" => header_value(arg).map(|(h, v)| HeaderOp::Set(h, v)),
+        "remove" => Ok(HeaderOp::Remove(name(arg)?)),
+        "rename" => {
+            let (from, to) = parse_transform_pair(arg)?;
+            Ok(HeaderOp::Rename(name(from)?, name(to)?))
+        }
+        _ => {
+            error!("Unknown header transform: {}", op);
+            Err(ParseError::InvalidRouteTransform)
+        }
+    }
+}
+
+fn parse_transform_bucket(s: &str) -> Result<transform::Bucket, ParseError> {
+    let invalid = |error: &dyn std::fmt::Display| {
+        error!(%error, "Invalid experiment buckets: {}", s);
+        ParseError::InvalidRouteTransform
+    };
+
+    let (header, buckets) = parse_transform_pair(s)?;
+    let mut header_key = header.splitn(2, '@').map(str::trim);
+    let header = header_key.next().unwrap_or_default();
+    let header = HeaderName::from_bytes(header.as_bytes()).map_err(|e| invalid(&e))?;
+    let key = match header_key.next() {
+        Some(key) => Some(HeaderName::from_bytes(key.as_bytes()).map_err(|e| invalid(&e))?),
+        None => None,
+    };
+
+    let mut weights = Vec::new();
+    for bucket in buckets.split('|').map(str::trim) {
+        let mut parts = bucket.rsplitn(2, ':').map(str::trim);
+        let (weight, bucket) = match (parts.next(), parts.next()) {
+            (Some(weight), Some(bucket)) => (weight, bucket),
+            _ => return Err(invalid(&"expected BUCKET:WEIGHT")),
+        };
+        let bucket = HeaderValue::from_str(bucket).map_err(|e| invalid(&e))?;
+        let weight = weight.parse::<u32>().map_err(|e| invalid(&e))?;
+        weights.push((bucket, weight));
+    }
+
+    transform::Bucket::new(header, key, weights).map_err(|e| invalid(&e))
+}
+
+fn parse_transform_pair(s: &str) -> Result<(&str, &str), ParseError> {
+    let mut parts = s.splitn(2, '=').map(str::trim);
+    match (parts.next(), parts.next()) {
+        (Some(k), Some(v)) if !k.is_empty() => Ok((k, v)),
+        _ => {
+            error!("Expected KEY=VALUE; found: {}", s);
+            Err(ParseError::InvalidRouteTransform)
+        }
+    }
+}
+
+/// Parses `DST=ROUTE` pairs, grouping route names by destination.
+fn parse_dst_routes(
+    s: &str,
+    invalid: ParseError,
+) -> Result<IndexMap<NameAddr, IndexSet<String>>, ParseError> {
+    let mut routes = IndexMap::new();
+    for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let mut parts = pair.splitn(2, '=');
+        match (parts.next(), parts.next().map(str::trim)) {
+            (Some(dst), Some(route)) if !route.is_empty() => {
+                let dst = parse_name_addr(dst.trim())?;
+                routes
+                    .entry(dst)
+                    .or_insert_with(IndexSet::new)
+                    .insert(route.to_owned());
+            }
+            _ => {
+                error!("Expected DST=ROUTE; found: {}", pair);
+                return Err(invalid);
+            }
+        }
+    }
+    Ok(routes)
+}
+
+fn parse_max_queue_times(s: &str) -> Result<IndexMap<NameAddr, Duration>, ParseError> {
+    let mut times = IndexMap::new();
+    for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let mut parts = pair.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(dst), Some(time)) => {
+                let dst = parse_name_addr(dst.trim())?;
+                times.insert(dst, parse_duration(time.trim())?);
+            }
+            _ => {
+                error!("Expected DST=DURATION; found: {}", pair);
+                return Err(ParseError::InvalidMaxQueueTime);
+            }
+        }
+    }
+    Ok(times)
+}
+
+fn parse_ewma_overrides(s: &str) -> Result<IndexMap<NameAddr, balance::Ewma>, ParseError> {
+    let mut overrides = IndexMap::new();
+    for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let mut parts = entry.splitn(2, '=');
+        let (dst, ewma) = match (parts.next(), parts.next()) {
+            (Some(dst), Some(ewma)) => (parse_name_addr(dst.trim())?, ewma),
+            _ => {
+                error!("Expected DST=RTT/DECAY; found: {}", entry);
+                return Err(ParseError::InvalidEwmaOverride);
+            }
+        };
+        let mut params = ewma.splitn(2, '/');
+        match (params.next(), params.next()) {
+            (Some(rtt), Some(decay)) => {
+                let ewma = balance::Ewma {
+                    default_rtt: parse_duration(rtt.trim())?,
+                    decay: parse_duration(decay.trim())?,
+                };
+                overrides.insert(dst, ewma);
+            }
+            _ => {
+                error!("Expected DST=RTT/DECAY; found: {}", entry);
+                return Err(ParseError::InvalidEwmaOverride);
+            }
+        }
+    }
+    Ok(overrides)
+}
+
+fn parse_connection_limits(s: &str) -> Result<Vec<(connection_limit::Match, usize)>, ParseError> {
+    let mut limits = Vec::new();
+    for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let mut parts = entry.splitn(2, '=').map(str::trim);
+        let (dst, max) = match (parts.next(), parts.next()) {
+            (Some(dst), Some(max)) => (dst, max),
+            _ => {
+                error!("Expected DST=MAX; found: {}", entry);
+                return Err(ParseError::InvalidConnectionLimit);
+            }
+        };
+        let dst = if dst.contains('/') {
+            let net = ipnet::IpNet::from_str(dst).map_err(|error| {
+                error!(%dst, %error, "Invalid network");
+                ParseError::InvalidConnectionLimit
+            })?;
+            connection_limit::Match::Network(net)
+        } else {
+            connection_limit::Match::Name(parse_name_addr(dst)?)
+        };
+        match parse_number::<usize>(max) {
+            Ok(max) if max > 0 => limits.push((dst, max)),
+            _ => {
+                error!("Expected a positive connection limit; found: {}", entry);
+                return Err(ParseError::InvalidConnectionLimit);
+            }
+        }
+    }
+    Ok(limits)
+}
+
+fn parse_tls_origination(s: &str) -> Result<tls::originate::Config, ParseError> {
+    let mut rules = Vec::new();
+    for item in s.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+        let mut parts = item.split(';').map(str::trim);
+        let suffix = parse_dns_suffix(parts.next().unwrap_or_default())?;
+
+        let mut ca_bundle = DEFAULT_OUTBOUND_TLS_ORIGINATION_CA_BUNDLE;
+        let mut server_name = None;
+        for opt in parts {
+            let mut kv = opt.splitn(2, '=').map(str::trim);
+            match (kv.next(), kv.next()) {
+                (Some("ca"), Some(path)) if !path.is_empty() => ca_bundle = path,
+                (Some("sni"), Some(name)) => server_name = Some(parse_identity(name)?),
+                _ => {
+                    error!("Expected ca=PATH or sni=NAME; found: {}", opt);
+                    return Err(ParseError::InvalidTlsOrigination);
+                }
+            }
+        }
+
+        let roots = fs::read(ca_bundle).map_err(|error| {
+            error!(%ca_bundle, %error, "Failed to read CA bundle");
+            ParseError::InvalidTlsOrigination
+        })?;
+        let rule = tls::originate::Rule::new(suffix, &roots, server_name).ok_or_else(|| {
+            error!(%ca_bundle, "CA bundle contains no roots");
+            ParseError::InvalidTlsOrigination
+        })?;
+        rules.push(rule);
+    }
+    Ok(tls::originate::Config::new(rules))
+}
+
+fn parse_tls_peer_names(s: &str) -> Result<tls::peer_name::Config, ParseError> {
+    use tls::peer_name::{Form, Match, Rule, Template};
+
+    let mut rules = Vec::new();
+    for item in s.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+        let mut parts = item.splitn(2, '=').map(str::trim);
+        let mut matches = parts.next().unwrap_or_default().splitn(2, ':');
+        let matches = match (matches.next(), matches.next()) {
+            (Some("authority"), Some(suffix)) => Match::Authority(parse_dns_suffix(suffix)?),
+            (Some("identity"), Some(suffix)) => Match::Identity(parse_dns_suffix(suffix)?),
+            _ => {
+                error!(
+                    "Expected authority:SUFFIX or identity:SUFFIX; found: {}",
+                    item
+                );
+                return Err(ParseError::InvalidTlsPeerNames);
+            }
+        };
+        let san = match parts.next() {
+            Some("dns") => Form::Dns,
+            Some(template) => Form::Uri(Template::parse(template).map_err(|error| {
+                error!(%template, %error, "Invalid URI template");
+                ParseError::InvalidTlsPeerNames
+            })?),
+            None => {
+                error!("Expected SUFFIX=dns or SUFFIX=TEMPLATE; found: {}", item);
+                return Err(ParseError::InvalidTlsPeerNames);
+            }
+        };
+        rules.push(Rule::new(matches, san));
+    }
+    Ok(tls::peer_name::Config::new(rules))
+}
+
+fn parse_static_endpoints(s: &str) -> Result<(PathBuf, static_endpoints::Table), ParseError> {
+    let path = PathBuf::from(s);
+    let table = static_endpoints::Table::read(&path).map_err(|error| {
+        error!(path = %s, %error, "Failed to read static endpoints");
+        ParseError::InvalidStaticEndpoints
+    })?;
+    Ok((path, table))
+}
+
+fn parse_label_headers(s: &str) -> Result<IndexMap<String, HeaderName>, ParseError> {
+    let mut headers = IndexMap::new();
+    for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let mut parts = pair.splitn(2, '=').map(str::trim);
+        match (parts.next(), parts.next()) {
+            (Some(label), Some(header)) if !label.is_empty() => {
+                let header = HeaderName::from_bytes(header.as_bytes()).map_err(|_| {
+                    error!("Invalid header name: {}", header);
+                    ParseError::InvalidLabelHeader
+                })?;
+                headers.insert(label.to_owned(), header);
+            }
+            _ => {
+                error!("Expected LABEL=HEADER; found: {}", pair);
+                return Err(ParseError::InvalidLabelHeader);
+            }
+        }
+    }
+    Ok(headers)
+}
+
+fn parse_header_names(s: &str) -> Result<IndexSet<HeaderName>, ParseError> {
+    let mut names = IndexSet::new();
+    for name in s.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+        names.insert(parse_header_name(name)?);
+    }
+    Ok(names)
+}
+
+fn parse_header_name(s: &str) -> Result<HeaderName, ParseError> {
+    HeaderName::from_bytes(s.trim().as_bytes()).map_err(|_| {
+        error!("Invalid header name: {}", s);
+        ParseError::InvalidHeaderName
+    })
+}
+
+fn parse_collapse_headers(
+    s: &str,
+) -> Result<IndexMap<HeaderName, normalize_headers::Collapse>, ParseError> {
+    use normalize_headers::Collapse;
+
+    let mut headers = IndexMap::new();
+    for spec in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let mut parts = spec.splitn(2, '=').map(str::trim);
+        let name = parts.next().expect("splitn must return at least one part");
+        let collapse = match parts.next() {
+            None | Some("join") => Collapse::Join,
+            Some("first") => Collapse::First,
+            Some("last") => Collapse::Last,
+            Some(_) => {
+                error!("Invalid header collapse policy: {}", spec);
+                return Err(ParseError::InvalidCollapseHeader);
+            }
+        };
+        let name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| {
+            error!("Invalid header name: {}", name);
+            ParseError::InvalidCollapseHeader
+        })?;
+        headers.insert(name, collapse);
+    }
+    Ok(headers)
+}
+
+fn parse_header_trust(s: &str) -> Result<header_trust::Policy, ParseError> {
+    let mut allowed = IndexMap::new();
+    for rule in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let mut parts = rule.splitn(2, '=').map(str::trim);
+        let name = parts.next().expect("splitn must return at least one part");
+        let name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| {
+            error!("Invalid header name: {}", name);
+            ParseError::InvalidHeaderTrust
+        })?;
+        let mut suffixes = Vec::new();
+        for suffix in parts.next().unwrap_or("").split('|').map(str::trim) {
+            let suffix = parse_dns_suffix(suffix).map_err(|_| {
+                error!("Invalid identity suffix: {}", rule);
+                ParseError::InvalidHeaderTrust
+            })?;
+            suffixes.push(suffix);
+        }
+        allowed.insert(name, suffixes);
+    }
+    Ok(header_trust::Policy::new(allowed))
+}
+
+fn parse_label_names(s: &str) -> Result<IndexSet<String>, ParseError> {
+    Ok(s.split(',')
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+        .map(str::to_owned)
+        .collect())
+}
+
+fn parse_ip_set(s: &str) -> Result<IndexSet<IpAddr>, ParseError> {
+    let mut set = IndexSet::new();
+    for ip in s.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+        let ip = IpAddr::from_str(ip).map_err(|_| {
+            error!("Not a valid IP address: {}", ip);
+            ParseError::HostIsNotAnIpAddress
+        })?;
+        set.insert(ip);
+    }
+    Ok(set)
+}
+
+fn parse_forward_addr(s: &str, allowlist: &IndexSet<IpAddr>) -> Result<IpAddr, ParseError> {
+    let ip = IpAddr::from_str(s.trim()).map_err(|_| {
+        error!("Not a valid IP address: {}", s);
+        ParseError::HostIsNotAnIpAddress
+    })?;
+    if !ip.is_loopback() && !allowlist.contains(&ip) {
+        error!(
+            "{} is neither a loopback address nor listed in {}",
+            ip, ENV_INBOUND_FORWARD_ADDR_ALLOWLIST
+        );
+        return Err(ParseError::ForwardAddrNotLocal);
+    }
+    Ok(ip)
+}
+
+fn parse_forward_port_addrs(
+    s: &str,
+    allowlist: &IndexSet<IpAddr>,
+) -> Result<IndexMap<u16, IpAddr>, ParseError> {
+    let mut ports = IndexMap::new();
+    for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let mut parts = entry.splitn(2, '=').map(str::trim);
+        match (parts.next(), parts.next()) {
+            (Some(port), Some(ip)) => {
+                let port = parse_number::<u16>(port)?;
+                ports.insert(port, parse_forward_addr(ip, allowlist)?);
+            }
+            _ => {
+                error!("Expected PORT=IP; found: {}", entry);
+                return Err(ParseError::InvalidForwardPortAddrs);
+            }
+        }
+    }
+    Ok(ports)
+}
+
+fn parse_port_set(s: &str) -> Result<IndexSet<u16>, ParseError> {
+    let mut set = IndexSet::new();
+    for num in s.split(',') {
+        set.insert(parse_number::<u16>(num)?);
+    }
+    Ok(set)
+}
+
+pub(super) fn parse_identity(s: &str) -> Result<identity::Name, ParseError> {
+    identity::Name::from_hostname(s.as_bytes()).map_err(|identity::InvalidName| {
+        error!("Not a valid identity name: {}", s);
+        ParseError::NameError
+    })
+}
+
+pub(super) fn parse<T, Parse>(
+    strings: &dyn Strings,
+    name: &str,
+    parse: Parse,
+) -> Result<Option<T>, EnvError>
+where
+    Parse: FnOnce(&str) -> Result<T, ParseError>,
+{
+    match strings.get(name)? {
+        Some(ref s) => {
+            let r = parse(s).map_err(|parse_error| {
+                error!("{}={:?} is not valid: {:?}", name, s, parse_error);
+                EnvError::InvalidEnvVar
+            })?;
+            Ok(Some(r))
+        }
+        None => Ok(None),
+    }
+}
+
+#[allow(dead_code)]
+fn parse_deprecated<T, Parse>(
+    strings: &dyn Strings,
+    name: &str,
+    deprecated_name: &str,
+    f: Parse,
+) -> Result<Option<T>, EnvError>
+where
+    Parse: Copy,
+    Parse: Fn(&str) -> Result<T, ParseError>,
+{
+    match parse(strings, name, f)? {
+        Some(v) => Ok(Some(v)),
+        None => {
+            let v = parse(strings, deprecated_name, f)?;
+            if v.is_some() {
+                warn!("{} has been deprecated; use {}", deprecated_name, name);
+            }
+            Ok(v)
+        }
+    }
+}
+
+fn parse_dns_suffixes(list: &str) -> Result<IndexSet<dns::Suffix>, ParseError> {
+    let mut suffixes = IndexSet::new();
+    for item in list.split(',') {
+        let item = item.trim();
+        if !item.is_empty() {
+            let sfx = parse_dns_suffix(item)?;
+            suffixes.insert(sfx);
+        }
+    }
+
+    Ok(suffixes)
+}
+
+fn parse_dns_suffix(s: &str) -> Result<dns::Suffix, ParseError> {
+    if s == "." {
+        return Ok(dns::Suffix::Root);
+    }
+
+    dns::Name::try_from(s.as_bytes())
         .map(dns::Suffix::Name)
         .map_err(|_| ParseError::NotADomainSuffix)
 }
@@ -1047,4 +2692,622 @@ mod tests {
             "names are coerced to lowercase"
         );
     }
+
+    #[test]
+    fn route_backups() {
+        fn p(s: &str) -> Result<Vec<(String, String, String)>, ParseError> {
+            let mut backups = Vec::new();
+            for (dst, routes) in parse_route_backups(s)? {
+                for (route, backup) in routes {
+                    backups.push((dst.to_string(), route, backup.to_string()));
+                }
+            }
+            Ok(backups)
+        }
+
+        assert_eq!(p(""), Ok(vec![]), "empty string");
+        assert_eq!(
+            p(
+                " web.ns.svc.cluster.local:80 = GET /books ; web.ns.svc.east.example.com:80 ,\
+               web.ns.svc.cluster.local:80=GET /login;web.ns.svc.west.example.com:80"
+            ),
+            Ok(vec![
+                (
+                    "web.ns.svc.cluster.local:80".to_owned(),
+                    "GET /books".to_owned(),
+                    "web.ns.svc.east.example.com:80".to_owned()
+                ),
+                (
+                    "web.ns.svc.cluster.local:80".to_owned(),
+                    "GET /login".to_owned(),
+                    "web.ns.svc.west.example.com:80".to_owned()
+                ),
+            ]),
+            "each route of a destination has its own backup"
+        );
+        assert_eq!(
+            p("web.ns.svc.cluster.local:80=web.ns.svc.east.example.com:80"),
+            Err(ParseError::InvalidRouteBackup),
+            "a route is required"
+        );
+        assert_eq!(
+            p("web.ns.svc.cluster.local:80=GET /books"),
+            Err(ParseError::InvalidRouteBackup),
+            "a backup is required"
+        );
+        assert_eq!(
+            p("web.ns.svc.cluster.local:80=GET /books;10.1.1.1:80"),
+            Err(ParseError::NameError),
+            "backups must be names"
+        );
+    }
+
+    #[test]
+    fn ewma_overrides() {
+        fn p(s: &str) -> Result<Vec<(String, Duration, Duration)>, ParseError> {
+            let overrides = parse_ewma_overrides(s)?
+                .into_iter()
+                .map(|(dst, ewma)| (dst.to_string(), ewma.default_rtt, ewma.decay))
+                .collect();
+
+            Ok(overrides)
+        }
+
+        assert_eq!(p(""), Ok(vec![]), "empty string");
+        assert_eq!(
+            p(" web.ns.svc.cluster.local:80 = 100ms / 30s , books.ns.svc.cluster.local:80=5ms/1s,"),
+            Ok(vec![
+                (
+                    "web.ns.svc.cluster.local:80".to_owned(),
+                    Duration::from_millis(100),
+                    Duration::from_secs(30)
+                ),
+                (
+                    "books.ns.svc.cluster.local:80".to_owned(),
+                    Duration::from_millis(5),
+                    Duration::from_secs(1)
+                ),
+            ]),
+            "whitespace and empty components are ignored"
+        );
+        assert_eq!(
+            p("web.ns.svc.cluster.local:80=100ms"),
+            Err(ParseError::InvalidEwmaOverride),
+            "a decay is required"
+        );
+        assert_eq!(
+            p("web.ns.svc.cluster.local:80"),
+            Err(ParseError::InvalidEwmaOverride),
+            "parameters are required"
+        );
+        assert_eq!(
+            p("web.ns.svc.cluster.local:80=fast/10s"),
+            Err(ParseError::NotADuration),
+            "durations must be valid"
+        );
+    }
+
+    #[test]
+    fn name_addr_set() {
+        fn p(s: &str) -> Result<Vec<String>, ParseError> {
+            let set = parse_name_addr_set(s)?;
+            Ok(set.iter().map(ToString::to_string).collect())
+        }
+
+        assert_eq!(p(""), Ok(vec![]), "empty string");
+        assert_eq!(
+            p(" web.ns.svc.cluster.local:8080 ,, db.ns.svc.cluster.local:5432"),
+            Ok(vec![
+                "web.ns.svc.cluster.local:8080".to_owned(),
+                "db.ns.svc.cluster.local:5432".to_owned(),
+            ]),
+            "whitespace and empty components are ignored"
+        );
+        assert_eq!(
+            p("10.1.2.3:8080"),
+            Err(ParseError::NameError),
+            "addresses must be names"
+        );
+        assert!(p("web.ns.svc.cluster.local").is_err(), "a port is required");
+    }
+
+    #[test]
+    fn connection_limits() {
+        fn p(s: &str) -> Result<Vec<(String, usize)>, ParseError> {
+            let limits = parse_connection_limits(s)?
+                .into_iter()
+                .map(|(dst, max)| (dst.to_string(), max))
+                .collect();
+            Ok(limits)
+        }
+
+        assert_eq!(p(""), Ok(vec![]), "empty string");
+        assert_eq!(
+            p(" db.ns.svc.cluster.local:5432 = 2 , 10.1.0.0/16=10,"),
+            Ok(vec![
+                ("db.ns.svc.cluster.local:5432".to_owned(), 2),
+                ("10.1.0.0/16".to_owned(), 10),
+            ]),
+            "whitespace and empty components are ignored"
+        );
+        assert_eq!(
+            p("db.ns.svc.cluster.local:5432"),
+            Err(ParseError::InvalidConnectionLimit),
+            "a limit is required"
+        );
+        assert_eq!(
+            p("db.ns.svc.cluster.local:5432=0"),
+            Err(ParseError::InvalidConnectionLimit),
+            "limits must be positive"
+        );
+        assert_eq!(
+            p("10.1.0.0/33=2"),
+            Err(ParseError::InvalidConnectionLimit),
+            "networks must be valid"
+        );
+    }
+
+    #[test]
+    fn max_queue_times() {
+        fn p(s: &str) -> Result<Vec<(String, Duration)>, ParseError> {
+            let times = parse_max_queue_times(s)?
+                .into_iter()
+                .map(|(dst, time)| (dst.to_string(), time))
+                .collect();
+
+            Ok(times)
+        }
+
+        assert_eq!(p(""), Ok(vec![]), "empty string");
+        assert_eq!(
+            p(" web.ns.svc.cluster.local:80 = 500ms , books.ns.svc.cluster.local:80=2s,"),
+            Ok(vec![
+                (
+                    "web.ns.svc.cluster.local:80".to_owned(),
+                    Duration::from_millis(500)
+                ),
+                (
+                    "books.ns.svc.cluster.local:80".to_owned(),
+                    Duration::from_secs(2)
+                ),
+            ]),
+            "whitespace and empty components are ignored"
+        );
+        assert_eq!(
+            p("web.ns.svc.cluster.local:80"),
+            Err(ParseError::InvalidMaxQueueTime),
+            "a duration is required"
+        );
+        assert_eq!(
+            p("web.ns.svc.cluster.local:80=fast"),
+            Err(ParseError::NotADuration),
+            "durations must be valid"
+        );
+        assert_eq!(
+            p("10.1.1.1:80=1s"),
+            Err(ParseError::NameError),
+            "destinations must be names"
+        );
+    }
+
+    #[test]
+    fn empty_response_failures() {
+        fn p(s: &str) -> Result<Vec<(String, Vec<String>)>, ParseError> {
+            let failures = parse_empty_response_failures(s)?
+                .into_iter()
+                .map(|(dst, routes)| (dst.to_string(), routes.into_iter().collect()))
+                .collect();
+
+            Ok(failures)
+        }
+
+        assert_eq!(p(""), Ok(vec![]), "empty string");
+        assert_eq!(
+            p("web.ns.svc.cluster.local:80=GET /users, web.ns.svc.cluster.local:80 = GET /books,"),
+            Ok(vec![(
+                "web.ns.svc.cluster.local:80".to_owned(),
+                vec!["GET /users".to_owned(), "GET /books".to_owned()]
+            )]),
+            "routes are grouped by destination"
+        );
+        assert_eq!(
+            p("web.ns.svc.cluster.local:80="),
+            Err(ParseError::InvalidEmptyResponseFailure),
+            "a route is required"
+        );
+        assert_eq!(
+            p("10.1.1.1:80=GET /users"),
+            Err(ParseError::NameError),
+            "destinations must be names"
+        );
+    }
+
+    #[test]
+    fn coalesced_routes() {
+        let routes =
+            parse_coalesced_routes("web.ns.svc.cluster.local:80=GET /users").expect("must parse");
+        let dst = NameAddr::from_str("web.ns.svc.cluster.local:80").unwrap();
+        assert!(routes[&dst].contains("GET /users"));
+
+        assert_eq!(
+            parse_coalesced_routes("web.ns.svc.cluster.local:80"),
+            Err(ParseError::InvalidCoalescedRoute),
+            "a route is required"
+        );
+    }
+
+    #[test]
+    fn preserve_scheme_routes() {
+        let routes = parse_preserve_scheme_routes(
+            "web.ns.svc.cluster.local:80=GET /users, web.ns.svc.cluster.local:80=GET /posts",
+        )
+        .expect("must parse");
+        let dst = NameAddr::from_str("web.ns.svc.cluster.local:80").unwrap();
+        assert_eq!(routes.len(), 1);
+        assert!(routes[&dst].contains("GET /users"));
+        assert!(routes[&dst].contains("GET /posts"));
+
+        assert_eq!(
+            parse_preserve_scheme_routes("web.ns.svc.cluster.local:80="),
+            Err(ParseError::InvalidPreserveSchemeRoute),
+            "a route is required"
+        );
+    }
+
+    #[test]
+    fn affinity_cookie_routes() {
+        let routes = parse_affinity_cookie_routes(
+            "web.ns.svc.cluster.local:80=GET /cart;cart-endpoint, \
+             web.ns.svc.cluster.local:80=POST /cart;cart-endpoint",
+        )
+        .expect("must parse");
+        let dst = NameAddr::from_str("web.ns.svc.cluster.local:80").unwrap();
+        assert_eq!(routes.len(), 1);
+        assert_eq!(routes[&dst]["GET /cart"], "cart-endpoint");
+        assert_eq!(routes[&dst]["POST /cart"], "cart-endpoint");
+
+        assert_eq!(
+            parse_affinity_cookie_routes("web.ns.svc.cluster.local:80=GET /cart"),
+            Err(ParseError::InvalidAffinityCookieRoute),
+            "a cookie is required"
+        );
+        assert_eq!(
+            parse_affinity_cookie_routes("web.ns.svc.cluster.local:80=GET /cart;a=b"),
+            Err(ParseError::InvalidAffinityCookieRoute),
+            "the cookie must be a valid name"
+        );
+    }
+
+    #[test]
+    fn route_transforms() {
+        let transforms = parse_route_transforms(
+            "web.ns.svc.cluster.local:80=GET /users/{id};request.rename:x-old=x-new;\
+             path:^/users/([0-9]+)$=/v2/users/$1, \
+             web.ns.svc.cluster.local:80=GET /posts, \
+             web.ns.svc.cluster.local:80=GET /feed;response.set:cache-control=no-store",
+        )
+        .expect("must parse");
+        let dst = NameAddr::from_str("web.ns.svc.cluster.local:80").unwrap();
+        assert_eq!(transforms[&dst].len(), 3);
+        assert!(!transforms[&dst]["GET /users/{id}"].is_noop());
+        assert!(transforms[&dst]["GET /posts"].is_noop());
+        assert!(!transforms[&dst]["GET /feed"].is_noop());
+
+        let p = parse_route_transforms;
+        assert_eq!(
+            p("web.ns.svc.cluster.local:80=GET /users;request.rename:x-old"),
+            Err(ParseError::InvalidRouteTransform),
+            "a rename requires a new name"
+        );
+        assert_eq!(
+            p("web.ns.svc.cluster.local:80=GET /users;response.set:x-frame-options"),
+            Err(ParseError::InvalidRouteTransform),
+            "a set requires a value"
+        );
+        assert_eq!(
+            p("web.ns.svc.cluster.local:80=GET /users;path:(=/"),
+            Err(ParseError::InvalidRouteTransform),
+            "patterns must be valid"
+        );
+        assert_eq!(
+            p("web.ns.svc.cluster.local:80=GET /users;eval:1"),
+            Err(ParseError::InvalidRouteTransform),
+            "only known operations are supported"
+        );
+        assert_eq!(
+            p("web.ns.svc.cluster.local:80;request.remove:x-old"),
+            Err(ParseError::InvalidRouteTransform),
+            "a route is required"
+        );
+    }
+
+    #[test]
+    fn route_transform_buckets() {
+        let transforms = parse_route_transforms(
+            "web.ns.svc.cluster.local:80=GET /users;bucket:x-experiment=control:90|treatment:10, \
+             web.ns.svc.cluster.local:80=GET /feed;bucket:x-experiment@x-user-id=a:1|b:1",
+        )
+        .expect("must parse");
+        let dst = NameAddr::from_str("web.ns.svc.cluster.local:80").unwrap();
+        assert!(!transforms[&dst]["GET /users"].is_noop());
+        assert!(!transforms[&dst]["GET /feed"].is_noop());
+
+        let p =
+            |op: &str| parse_route_transforms(&format!("web.ns.svc.cluster.local:80=GET /;{}", op));
+        assert_eq!(
+            p("bucket:x-experiment=control|treatment:10"),
+            Err(ParseError::InvalidRouteTransform),
+            "each bucket requires a weight"
+        );
+        assert_eq!(
+            p("bucket:x-experiment=control:-1"),
+            Err(ParseError::InvalidRouteTransform),
+            "weights must be unsigned"
+        );
+        assert_eq!(
+            p("bucket:x-experiment=control:0|treatment:0"),
+            Err(ParseError::InvalidRouteTransform),
+            "buckets must have weight"
+        );
+        assert_eq!(
+            p("bucket:x experiment@x-user-id=control:1"),
+            Err(ParseError::InvalidRouteTransform),
+            "the header must be a valid name"
+        );
+        assert_eq!(
+            p("bucket:x-experiment@x user=control:1"),
+            Err(ParseError::InvalidRouteTransform),
+            "the key must be a valid header name"
+        );
+    }
+
+    #[test]
+    fn header_trust() {
+        assert!(parse_header_trust(
+            "l5d-dst-override=linkerd-gateway.linkerd.serviceaccount.identity.linkerd.cluster.local, \
+             l5d-orig-proto=default.serviceaccount.identity.linkerd.cluster.local|linkerd.serviceaccount.identity.linkerd.cluster.local"
+        )
+        .is_ok());
+        assert!(parse_header_trust("").is_ok());
+
+        for invalid in &[
+            "l5d-dst-override",
+            "l5d-dst-override=",
+            "l5d-dst-override=a.example.com|",
+            "l5d dst override=a.example.com",
+        ] {
+            assert_eq!(
+                parse_header_trust(invalid).err(),
+                Some(ParseError::InvalidHeaderTrust),
+                "{:?} must not parse",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn response_header_limit_policy() {
+        assert_eq!(
+            parse_response_header_limit_policy("Reject"),
+            Ok(header_limit::Policy::Reject)
+        );
+        assert_eq!(
+            parse_response_header_limit_policy("truncate"),
+            Ok(header_limit::Policy::Truncate)
+        );
+        assert_eq!(
+            parse_response_header_limit_policy("drop"),
+            Err(ParseError::InvalidResponseHeaderLimitPolicy)
+        );
+    }
+
+    #[test]
+    fn forward_addrs() {
+        let allowlist = parse_ip_set("10.1.2.3").unwrap();
+        assert_eq!(
+            parse_forward_addr("127.0.0.2", &IndexSet::new()),
+            Ok([127, 0, 0, 2].into())
+        );
+        assert_eq!(
+            parse_forward_addr("::1", &IndexSet::new()),
+            Ok(IpAddr::from_str("::1").unwrap())
+        );
+        assert_eq!(
+            parse_forward_addr("10.1.2.3", &IndexSet::new()),
+            Err(ParseError::ForwardAddrNotLocal)
+        );
+        assert_eq!(
+            parse_forward_addr("10.1.2.3", &allowlist),
+            Ok([10, 1, 2, 3].into())
+        );
+        assert_eq!(
+            parse_forward_addr("localhost", &allowlist),
+            Err(ParseError::HostIsNotAnIpAddress)
+        );
+
+        let ports = parse_forward_port_addrs("8080=127.0.0.2, 9090=10.1.2.3", &allowlist).unwrap();
+        assert_eq!(ports.get(&8080), Some(&[127, 0, 0, 2].into()));
+        assert_eq!(ports.get(&9090), Some(&[10, 1, 2, 3].into()));
+        assert_eq!(
+            parse_forward_port_addrs("8080=10.1.2.4", &allowlist),
+            Err(ParseError::ForwardAddrNotLocal)
+        );
+        assert_eq!(
+            parse_forward_port_addrs("8080", &allowlist),
+            Err(ParseError::InvalidForwardPortAddrs)
+        );
+    }
+
+    #[test]
+    fn tls_origination() {
+        let ca = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../identity/src/testdata/ca1.pem"
+        );
+        let config = parse_tls_origination(&format!(
+            "example.com;ca={}, api.test;ca={};sni=api.example.com",
+            ca, ca
+        ))
+        .expect("must parse");
+        let name = |s: &str| dns::Name::try_from(s.as_bytes()).unwrap();
+
+        let www = config
+            .origination(&name("www.example.com"))
+            .expect("suffix must match");
+        assert_eq!(www.server_name().as_ref(), "www.example.com");
+        let api = config
+            .origination(&name("v1.api.test"))
+            .expect("suffix must match");
+        assert_eq!(api.server_name().as_ref(), "api.example.com");
+        assert!(config.origination(&name("example.org")).is_none());
+
+        assert_eq!(
+            parse_tls_origination("example.com;ca=/does/not/exist").err(),
+            Some(ParseError::InvalidTlsOrigination),
+            "the CA bundle must be readable"
+        );
+        assert_eq!(
+            parse_tls_origination(&format!("example.com;ca={};alpn=h2", ca)).err(),
+            Some(ParseError::InvalidTlsOrigination),
+            "options must be known"
+        );
+    }
+
+    #[test]
+    fn tls_peer_names() {
+        let config = parse_tls_peer_names(
+            "authority:legacy.test=dns, identity:cluster.local=spiffe://cluster.local/ns/{2}/sa/{1}",
+        )
+        .expect("must parse");
+        let name = |s: &str| dns::Name::try_from(s.as_bytes()).unwrap();
+        let id = |s: &str| identity::Name::from_hostname(s.as_bytes()).unwrap();
+
+        let legacy = config
+            .peer_name(Some(&name("web.legacy.test")), None)
+            .expect("authority must match");
+        assert_eq!(legacy.name().as_ref(), "web.legacy.test");
+        assert!(legacy.uri().is_none());
+        let spiffe = config
+            .peer_name(None, Some(&id("foo.ns1.serviceaccount.cluster.local")))
+            .expect("identity must match");
+        assert_eq!(
+            spiffe.uri().map(AsRef::as_ref),
+            Some("spiffe://cluster.local/ns/ns1/sa/foo")
+        );
+        assert!(config.peer_name(Some(&name("example.com")), None).is_none());
+
+        for invalid in &[
+            "legacy.test=dns",
+            "service:legacy.test=dns",
+            "authority:legacy.test",
+            "identity:cluster.local=spiffe://{ns}",
+        ] {
+            assert_eq!(
+                parse_tls_peer_names(invalid).err(),
+                Some(ParseError::InvalidTlsPeerNames),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn static_endpoints() {
+        assert_eq!(
+            parse_static_endpoints("/does/not/exist").err(),
+            Some(ParseError::InvalidStaticEndpoints),
+            "the file must be readable"
+        );
+    }
+
+    #[test]
+    fn label_headers() {
+        fn p(s: &str) -> Result<Vec<(String, String)>, ParseError> {
+            let headers = parse_label_headers(s)?
+                .into_iter()
+                .map(|(label, header)| (label, header.as_str().to_owned()))
+                .collect();
+
+            Ok(headers)
+        }
+
+        assert_eq!(p(""), Ok(vec![]), "empty string");
+        assert_eq!(
+            p(" zone = X-Dst-Zone , tenant=x-tenant-id,"),
+            Ok(vec![
+                ("zone".to_owned(), "x-dst-zone".to_owned()),
+                ("tenant".to_owned(), "x-tenant-id".to_owned()),
+            ]),
+            "whitespace and empty components are ignored"
+        );
+        assert_eq!(
+            p("zone"),
+            Err(ParseError::InvalidLabelHeader),
+            "a header is required"
+        );
+        assert_eq!(
+            p("zone=x dst zone"),
+            Err(ParseError::InvalidLabelHeader),
+            "headers must be valid header names"
+        );
+    }
+
+    #[test]
+    fn collapse_headers() {
+        use normalize_headers::Collapse;
+
+        fn p(s: &str) -> Result<Vec<(String, Collapse)>, ParseError> {
+            let headers = parse_collapse_headers(s)?
+                .into_iter()
+                .map(|(name, collapse)| (name.as_str().to_owned(), collapse))
+                .collect();
+
+            Ok(headers)
+        }
+
+        assert_eq!(p(""), Ok(vec![]), "empty string");
+        assert_eq!(
+            p("X-Request-Id=first, x-legacy = last,forwarded"),
+            Ok(vec![
+                ("x-request-id".to_owned(), Collapse::First),
+                ("x-legacy".to_owned(), Collapse::Last),
+                ("forwarded".to_owned(), Collapse::Join),
+            ]),
+            "names are lowercased and values are joined by default"
+        );
+        assert_eq!(
+            p("x-legacy=merge"),
+            Err(ParseError::InvalidCollapseHeader),
+            "policies must be known"
+        );
+        assert_eq!(
+            p("x legacy"),
+            Err(ParseError::InvalidCollapseHeader),
+            "names must be valid header names"
+        );
+    }
+
+    #[test]
+    fn header_names() {
+        fn p(s: &str) -> Result<Vec<String>, ParseError> {
+            let names = parse_header_names(s)?
+                .into_iter()
+                .map(|name| name.as_str().to_owned())
+                .collect();
+
+            Ok(names)
+        }
+
+        assert_eq!(p(""), Ok(vec![]), "empty string");
+        assert_eq!(
+            p(" X-Debug , x-internal-id,,x-debug"),
+            Ok(vec!["x-debug".to_owned(), "x-internal-id".to_owned()]),
+            "whitespace, empty components and duplicates are ignored"
+        );
+        assert_eq!(
+            p("x debug"),
+            Err(ParseError::InvalidHeaderName),
+            "names must be valid header names"
+        );
+    }
 }
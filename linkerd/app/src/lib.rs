@@ -8,6 +8,7 @@ pub mod env;
 pub mod identity;
 pub mod metrics;
 pub mod oc_collector;
+pub mod persist;
 pub mod tap;
 
 use self::metrics::Metrics;
@@ -48,6 +49,8 @@ pub struct Config<A: OrigDstAddr = SysOrigDstAddr> {
     pub admin: admin::Config,
     pub tap: tap::Config,
     pub oc_collector: oc_collector::Config,
+    /// If set, caches are persisted across restarts.
+    pub persist: Option<persist::Config>,
 }
 
 pub struct App {
@@ -59,6 +62,8 @@ pub struct App {
     inbound: inbound::Inbound,
     oc_collector: oc_collector::OcCollector,
     outbound: outbound::Outbound,
+    persist: Option<persist::Task>,
+    static_endpoints: Option<dst::static_endpoints::Watch>,
     tap: tap::Tap,
 }
 
@@ -79,6 +84,7 @@ impl<A: OrigDstAddr + Send + 'static> Config<A> {
             admin: self.admin,
             tap: self.tap,
             oc_collector: self.oc_collector,
+            persist: self.persist,
         }
     }
 
@@ -95,10 +101,16 @@ impl<A: OrigDstAddr + Send + 'static> Config<A> {
             inbound,
             oc_collector,
             outbound,
+            persist,
             tap,
         } = self;
         debug!("building app");
-        let (metrics, report) = Metrics::new(admin.metrics_retain_idle);
+        let (metrics, report, summary) = Metrics::new(
+            admin.metrics_retain_idle,
+            admin.metrics_rate_window,
+            admin.metrics_summary_drop_route_labels.clone(),
+            admin.metrics_stack_stage_sample,
+        );
 
         let dns = info_span!("dns").in_scope(|| dns.build())?;
 
@@ -107,6 +119,11 @@ impl<A: OrigDstAddr + Send + 'static> Config<A> {
 
         let (drain_tx, drain_rx) = drain::channel();
 
+        let caches = persist::Caches::default();
+        if let Some(ref persist) = persist {
+            info_span!("persist").in_scope(|| persist.restore(&caches));
+        }
+
         let tap = info_span!("tap").in_scope(|| tap.build(identity.local(), drain_rx.clone()))?;
 
         let dst = {
@@ -118,6 +135,10 @@ impl<A: OrigDstAddr + Send + 'static> Config<A> {
                 transport::{connect, tls},
             };
 
+            let profile_updates = metrics.stack_state.profile_updates();
+            let profile_rebuilds = metrics.profile_rebuilds.clone();
+            let profile_route_truncations = metrics.profile_route_truncations.clone();
+            let no_profiles = caches.no_profiles.clone();
             let metrics = metrics.control.clone();
             let dns = dns.resolver.clone();
             info_span!("dst").in_scope(|| {
@@ -143,7 +164,13 @@ impl<A: OrigDstAddr + Send + 'static> Config<A> {
                     )
                     .into_inner()
                     .make(dst.control.addr.clone());
-                dst.build(svc)
+                dst.build(
+                    svc,
+                    profile_updates,
+                    profile_rebuilds,
+                    profile_route_truncations,
+                    no_profiles,
+                )
             })
         }?;
 
@@ -157,10 +184,16 @@ impl<A: OrigDstAddr + Send + 'static> Config<A> {
         let admin = {
             let identity = identity.local();
             let drain = drain_rx.clone();
-            info_span!("admin").in_scope(move || admin.build(identity, report, log_level, drain))?
+            let stack_state = metrics.stack_state.clone();
+            info_span!("admin").in_scope(move || {
+                admin.build(identity, report, summary, stack_state, log_level, drain)
+            })?
         };
 
+        let persist = persist.map(|p| p.build(caches.clone(), drain_rx.clone()));
+
         let dst_addr = dst.addr.clone();
+        let static_endpoints = dst.static_endpoints;
         let inbound = {
             let inbound = inbound;
             let identity = identity.local();
@@ -169,25 +202,34 @@ impl<A: OrigDstAddr + Send + 'static> Config<A> {
             let metrics = metrics.inbound;
             let oc = oc_collector.span_sink();
             let drain = drain_rx.clone();
-            info_span!("inbound")
-                .in_scope(move || inbound.build(identity, profiles, tap, metrics, oc, drain))?
+            let latch = admin.latch.clone();
+            let readiness = admin.readiness.clone();
+            info_span!("inbound").in_scope(move || {
+                inbound.build(
+                    identity, profiles, tap, metrics, oc, drain, latch, readiness,
+                )
+            })?
         };
         let outbound = {
             let identity = identity.local();
             let dns = dns.resolver;
+            let refinements = caches.refinements;
             let tap = tap.layer();
             let metrics = metrics.outbound;
             let oc = oc_collector.span_sink();
+            let readiness = admin.readiness.clone();
             info_span!("outbound").in_scope(move || {
                 outbound.build(
                     identity,
                     dst.resolve,
                     dns,
+                    refinements,
                     dst.profiles,
                     tap,
                     metrics,
                     oc,
                     drain_rx,
+                    readiness,
                 )
             })?
         };
@@ -201,6 +243,8 @@ impl<A: OrigDstAddr + Send + 'static> Config<A> {
             inbound,
             oc_collector,
             outbound,
+            persist,
+            static_endpoints,
             tap,
         })
     }
@@ -260,12 +304,19 @@ impl App {
             inbound,
             oc_collector,
             outbound,
+            persist,
+            static_endpoints,
             tap,
             ..
         } = self;
 
         // Run a daemon thread for all administative tasks.
         //
+        // The admin server runs on its own runtime so that scrapes and
+        // readiness checks are served even while the main runtime is
+        // saturated with traffic. It shares only the metrics registries, the
+        // readiness latch, and other `Arc`'d state with the data path.
+        //
         // The main reactor holds `admin_shutdown_tx` until the reactor drops
         // the task. This causes the daemon reactor to stop.
         let (admin_shutdown_tx, admin_shutdown_rx) = tokio::sync::oneshot::channel::<()>();
@@ -338,6 +389,14 @@ impl App {
                                 );
                             }
 
+                            if let Some(watch) = static_endpoints {
+                                tokio::spawn(
+                                    watch
+                                        .map_err(|error| error!(%error, "watch failed"))
+                                        .instrument(info_span!("static_endpoints")),
+                                );
+                            }
+
                             admin_shutdown_rx.map_err(|_| ())
                         })
                         .instrument(info_span!("daemon")),
@@ -359,6 +418,16 @@ impl App {
                 .instrument(info_span!("inbound")),
         );
 
+        // The caches are persisted on the main runtime, which outlives the
+        // drain.
+        if let Some(persist) = persist {
+            tokio::spawn(
+                persist
+                    .map_err(|never| match never {})
+                    .instrument(info_span!("persist")),
+            );
+        }
+
         drain
     }
 }
@@ -1,10 +1,17 @@
+use indexmap::IndexSet;
 pub use linkerd2_app_core::{
+    accept_watchdog,
+    admin::StackState,
+    admission, cache_lock_wait, caller_override,
     classify::Class,
-    handle_time,
-    metric_labels::{ControlLabels, EndpointLabels, RouteLabels},
+    connection_limit, deadline_shed, dst_conflict, dst_name_limit, endpoint_timeout, errors,
+    handle_time, header_trust, identity_rotation,
+    metric_labels::{ControlLabels, DstLabels, EndpointLabels, RouteLabels},
     metrics::FmtMetrics,
-    opencensus, proxy, telemetry, transport, ControlHttpMetricsRegistry, ProxyMetrics,
+    opencensus, orig_proto_coverage, profiles, proxy, route_backend, stack_stage, telemetry,
+    tls_passthrough, transport, ControlHttpMetricsRegistry, ProxyMetrics,
 };
+use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
 pub struct Metrics {
@@ -12,66 +19,245 @@ pub struct Metrics {
     pub outbound: ProxyMetrics,
     pub control: ControlHttpMetricsRegistry,
     pub opencensus: opencensus::metrics::Registry,
+    pub profile_rebuilds: profiles::Rebuilds,
+    pub profile_route_truncations: profiles::Truncations,
+    pub stack_state: StackState,
 }
 
 impl Metrics {
-    pub fn new(retain_idle: Duration) -> (Self, impl FmtMetrics + Clone + Send + 'static) {
+    /// Returns the proxy's metrics along with two reports of them: one that
+    /// reports all metrics and a summary that reports HTTP metrics per
+    /// destination rather than per endpoint, omitting the route labels in
+    /// `drop_route_labels`.
+    ///
+    /// If `stack_stage_sample` is set, one in every `stack_stage_sample`
+    /// requests is timed in each stack stage.
+    pub fn new(
+        retain_idle: Duration,
+        rate_window: Duration,
+        drop_route_labels: Arc<IndexSet<String>>,
+        stack_stage_sample: Option<usize>,
+    ) -> (
+        Self,
+        impl FmtMetrics + Clone + Send + 'static,
+        impl FmtMetrics + Clone + Send + 'static,
+    ) {
         let process = telemetry::process::Report::new(SystemTime::now());
 
         let (control, control_report) = {
-            let (m, r) = proxy::http::metrics::new::<ControlLabels, Class>(retain_idle);
+            let (m, r) =
+                proxy::http::metrics::new::<ControlLabels, Class>(retain_idle, rate_window);
             (m, r.with_prefix("control"))
         };
 
         let (http_endpoint, endpoint_report) =
-            proxy::http::metrics::new::<EndpointLabels, Class>(retain_idle);
+            proxy::http::metrics::new::<EndpointLabels, Class>(retain_idle, rate_window);
 
         let (http_route, route_report) = {
-            let (m, r) = proxy::http::metrics::new::<RouteLabels, Class>(retain_idle);
+            let (m, r) = proxy::http::metrics::new::<RouteLabels, Class>(retain_idle, rate_window);
             (m, r.with_prefix("route"))
         };
 
         let (http_route_retry, retry_report) = {
-            let (m, r) = proxy::http::metrics::new::<RouteLabels, Class>(retain_idle);
+            let (m, r) = proxy::http::metrics::new::<RouteLabels, Class>(retain_idle, rate_window);
             (m, r.with_prefix("route_actual"))
         };
 
+        let http_route_backend = route_backend::Registry::default();
+
+        let http_endpoint_pool = proxy::http::pool::Registry::<EndpointLabels>::default();
+
+        let endpoint_timeout = endpoint_timeout::Registry::default();
+
+        let orig_proto_coverage = orig_proto_coverage::Coverage::default();
+
+        let dns_canonicalize = proxy::http::canonicalize::Metrics::default();
+
+        let route_unmatched =
+            proxy::http::profiles::Unmatched::default().with_retain_idle(retain_idle);
+
+        let response_reset = proxy::http::response_reset::Metrics::default();
+
+        let body_checksum = proxy::http::checksum::Metrics::default();
+
+        let dry_run = proxy::http::dry_run::Metrics::default();
+
+        let dst_conflict = dst_conflict::Metrics::default();
+
+        let deadline_shed = deadline_shed::Metrics::default();
+
+        let admission = admission::Metrics::default();
+
+        let accept_watchdog = accept_watchdog::Metrics::default();
+
+        let caller_override = caller_override::Metrics::default();
+
+        let connection_limit = connection_limit::Registry::default();
+
+        let header_trust = header_trust::Metrics::default();
+
+        let identity_rotation = identity_rotation::Rotation::default();
+
+        let invariant_violations = errors::Metrics::default();
+
+        let dst_name_limit = dst_name_limit::Limit::default();
+
+        let tls_passthrough = tls_passthrough::Metrics::default();
+
+        let cache_lock_wait = cache_lock_wait::Registry::default();
+
+        let fallback_hops = proxy::fallback::Hops::default();
+
+        let stack_stage = stack_stage::Registry::new(stack_stage_sample);
+
         let handle_time_report = handle_time::Metrics::new();
         let inbound_handle_time = handle_time_report.inbound();
         let outbound_handle_time = handle_time_report.outbound();
 
         let (transport, transport_report) = transport::metrics::new();
 
+        let stack_state = StackState::default();
+        for direction in &["inbound", "outbound"] {
+            let direction: &'static str = direction;
+            let report = transport_report.clone();
+            stack_state.register_connections(direction, move || {
+                report.open_connections(|key| key.direction() == direction)
+            });
+        }
+
+        let profile_updates = stack_state.profile_updates();
+        let profile_rebuilds = profiles::Rebuilds::default();
+        let profile_route_truncations = profiles::Truncations::default();
+
         let (opencensus, opencensus_report) = opencensus::metrics::new();
 
+        let resources = telemetry::resources::Report::new(stack_state.clone())
+            .with_http_registry("endpoint", http_endpoint.clone())
+            .with_http_registry("route", http_route.clone())
+            .with_http_registry("route_actual", http_route_retry.clone())
+            .with_http_registry("control", control.clone());
+
         let metrics = Metrics {
             inbound: ProxyMetrics {
+                accept_watchdog: accept_watchdog.inbound(),
+                admission: admission.inbound(),
+                cache_lock_wait: cache_lock_wait.clone(),
+                caller_override: caller_override.inbound(),
+                connection_limit: connection_limit.clone(),
+                dns_canonicalize: dns_canonicalize.clone(),
+                deadline_shed: deadline_shed.inbound(),
+                dry_run: dry_run.clone(),
+                header_trust: header_trust.inbound(),
+                identity_rotation: identity_rotation.clone(),
+                invariant_violations: invariant_violations.inbound(),
                 http_handle_time: inbound_handle_time,
                 http_endpoint: http_endpoint.clone(),
+                http_endpoint_pool: http_endpoint_pool.clone(),
+                orig_proto_coverage: orig_proto_coverage.clone(),
+                endpoint_timeout: endpoint_timeout.clone(),
+                fallback_hops: fallback_hops.clone(),
                 http_route: http_route.clone(),
                 http_route_retry: http_route_retry.clone(),
+                http_route_backend: http_route_backend.clone(),
+                dst_conflict: dst_conflict.clone(),
+                dst_name_limit: dst_name_limit.clone(),
+                response_reset: response_reset.clone(),
+                body_checksum: body_checksum.clone(),
+                route_unmatched: route_unmatched.clone(),
+                tls_passthrough: tls_passthrough.inbound(),
                 transport: transport.clone(),
+                stack_state: stack_state.clone(),
+                stack_stage: stack_stage.clone(),
             },
             outbound: ProxyMetrics {
+                accept_watchdog: accept_watchdog.outbound(),
+                admission: admission.outbound(),
+                cache_lock_wait: cache_lock_wait.clone(),
+                caller_override: caller_override.outbound(),
+                connection_limit: connection_limit.clone(),
+                dns_canonicalize: dns_canonicalize.clone(),
+                deadline_shed: deadline_shed.outbound(),
+                dry_run: dry_run.clone(),
+                header_trust: header_trust.outbound(),
+                identity_rotation: identity_rotation.clone(),
+                invariant_violations: invariant_violations.outbound(),
                 http_handle_time: outbound_handle_time,
                 http_endpoint,
+                http_endpoint_pool: http_endpoint_pool.clone(),
+                orig_proto_coverage: orig_proto_coverage.clone(),
+                endpoint_timeout: endpoint_timeout.clone(),
+                fallback_hops: fallback_hops.clone(),
                 http_route,
                 http_route_retry,
+                http_route_backend: http_route_backend.clone(),
+                dst_conflict: dst_conflict.clone(),
+                dst_name_limit: dst_name_limit.clone(),
+                response_reset: response_reset.clone(),
+                body_checksum: body_checksum.clone(),
+                route_unmatched: route_unmatched.clone(),
+                tls_passthrough: tls_passthrough.outbound(),
                 transport,
+                stack_state: stack_state.clone(),
+                stack_stage: stack_stage.clone(),
             },
             control,
             opencensus,
+            profile_rebuilds: profile_rebuilds.clone(),
+            profile_route_truncations: profile_route_truncations.clone(),
+            stack_state,
+        };
+
+        let summary = {
+            let endpoint = endpoint_report
+                .clone()
+                .summarize(|ep: &EndpointLabels| DstLabels::from(ep));
+            let drop = drop_route_labels.clone();
+            let route = route_report
+                .clone()
+                .summarize(move |rt: &RouteLabels| rt.without(&drop));
+            let drop = drop_route_labels;
+            let retry = retry_report
+                .clone()
+                .summarize(move |rt: &RouteLabels| rt.without(&drop));
+            endpoint.and_then(route).and_then(retry)
         };
 
         let report = endpoint_report
             .and_then(route_report)
             .and_then(retry_report)
+            .and_then(http_route_backend)
+            .and_then(endpoint_timeout)
+            .and_then(http_endpoint_pool)
+            .and_then(orig_proto_coverage)
+            .and_then(profile_updates)
+            .and_then(profile_rebuilds)
+            .and_then(profile_route_truncations)
+            .and_then(route_unmatched)
+            .and_then(response_reset)
+            .and_then(body_checksum)
+            .and_then(dry_run)
+            .and_then(dst_conflict)
+            .and_then(deadline_shed)
+            .and_then(admission)
+            .and_then(accept_watchdog)
+            .and_then(caller_override)
+            .and_then(connection_limit)
+            .and_then(header_trust)
+            .and_then(identity_rotation)
+            .and_then(invariant_violations)
+            .and_then(dst_name_limit)
+            .and_then(tls_passthrough)
+            .and_then(cache_lock_wait)
+            .and_then(fallback_hops)
+            .and_then(stack_stage)
             .and_then(control_report)
             .and_then(handle_time_report)
+            .and_then(dns_canonicalize)
             .and_then(transport_report)
             .and_then(opencensus_report)
+            .and_then(resources)
             .and_then(process);
 
-        (metrics, report)
+        (metrics, report, summary)
     }
 }
@@ -0,0 +1,344 @@
+//! Persists the DNS canonicalization and profile negative caches across
+//! restarts.
+//!
+//! Without this, every restarted proxy refines each name and looks up each
+//! profile anew, so a rolling restart causes a burst of DNS and control plane
+//! lookups across the fleet. Instead, the caches are written to a file when
+//! the proxy drains and unexpired entries are restored when it starts.
+//! Restored entries are used until they expire, when they're revalidated as
+//! if they had been cached by the new process.
+//!
+//! The file is versioned. Each line after the version records a cache entry
+//! and the UNIX time (in seconds) at which it expires:
+//!
+//! ```text
+//! linkerd2-proxy-caches v1
+//! refine web:8080 web.ns.svc.cluster.local.:8080 1577836800
+//! no-profile web.ns.svc.cluster.local:8080 1577836800
+//! ```
+//!
+//! Files that are missing, corrupt, or of another version are ignored.
+
+use futures::{try_ready, Async, Future, Poll};
+use linkerd2_app_core::{
+    drain,
+    proxy::http::{canonicalize, profiles::negative_cache::NegativeCache},
+    NameAddr, Never,
+};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{fmt, fs};
+use tracing::{debug, info, warn};
+
+const VERSION: &str = "linkerd2-proxy-caches v1";
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub path: PathBuf,
+}
+
+/// The caches that are persisted.
+#[derive(Clone, Debug, Default)]
+pub struct Caches {
+    pub refinements: canonicalize::Refinements,
+    pub no_profiles: NegativeCache,
+}
+
+/// Writes the caches to the file once a drain is signaled.
+///
+/// The drain doesn't complete until the file has been written.
+pub struct Task {
+    config: Config,
+    caches: Caches,
+    signaled: drain::Signaled,
+    _watch: drain::Watch,
+}
+
+/// Cache entries, with wall-clock expiries so that they are meaningful to
+/// another process.
+#[derive(Debug, Default, PartialEq)]
+struct Entries {
+    refinements: Vec<(NameAddr, NameAddr, SystemTime)>,
+    no_profiles: Vec<(NameAddr, SystemTime)>,
+}
+
+#[derive(Debug)]
+struct InvalidEntries;
+
+// === impl Config ===
+
+impl Config {
+    /// Restores the unexpired entries from the file into `caches`.
+    pub fn restore(&self, caches: &Caches) {
+        let entries = match fs::read_to_string(&self.path) {
+            Ok(contents) => match contents.parse::<Entries>() {
+                Ok(entries) => entries,
+                Err(InvalidEntries) => {
+                    debug!(path = %self.path.display(), "ignoring invalid cache file");
+                    return;
+                }
+            },
+            Err(error) => {
+                debug!(path = %self.path.display(), %error, "no cache file");
+                return;
+            }
+        };
+
+        let (now, sys_now) = (tokio::clock::now(), SystemTime::now());
+        caches
+            .refinements
+            .restore(
+                entries
+                    .refinements
+                    .into_iter()
+                    .filter_map(|(original, name, until)| {
+                        Some((original, name, to_instant(until, now, sys_now)?))
+                    }),
+            );
+        caches.no_profiles.restore(
+            entries
+                .no_profiles
+                .into_iter()
+                .filter_map(|(dst, until)| Some((dst, to_instant(until, now, sys_now)?))),
+        );
+        info!(path = %self.path.display(), "restored caches");
+    }
+
+    /// Writes the unexpired entries in `caches` to the file.
+    ///
+    /// The file is replaced atomically, so that a proxy that is killed while
+    /// writing doesn't leave a partial file behind.
+    pub fn persist(&self, caches: &Caches) {
+        let (now, sys_now) = (tokio::clock::now(), SystemTime::now());
+        let entries = Entries {
+            refinements: caches
+                .refinements
+                .snapshot()
+                .into_iter()
+                .filter_map(|(original, name, until)| {
+                    Some((original, name, to_system_time(until, now, sys_now)?))
+                })
+                .collect(),
+            no_profiles: caches
+                .no_profiles
+                .snapshot()
+                .into_iter()
+                .filter_map(|(dst, until)| Some((dst, to_system_time(until, now, sys_now)?)))
+                .collect(),
+        };
+
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let written =
+            fs::write(&tmp, entries.to_string()).and_then(|()| fs::rename(&tmp, &self.path));
+        match written {
+            Ok(()) => info!(path = %self.path.display(), "persisted caches"),
+            Err(error) => warn!(path = %self.path.display(), %error, "failed to persist caches"),
+        }
+    }
+
+    /// Returns a task that persists `caches` when `drain` is signaled.
+    pub fn build(self, caches: Caches, drain: drain::Watch) -> Task {
+        Task {
+            config: self,
+            caches,
+            signaled: drain.signaled(),
+            _watch: drain,
+        }
+    }
+}
+
+// === impl Task ===
+
+impl Future for Task {
+    type Item = ();
+    type Error = Never;
+
+    fn poll(&mut self) -> Poll<(), Never> {
+        try_ready!(self.signaled.poll());
+        self.config.persist(&self.caches);
+        Ok(Async::Ready(()))
+    }
+}
+
+// === impl Entries ===
+
+impl fmt::Display for Entries {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", VERSION)?;
+        for (original, refined, until) in &self.refinements {
+            writeln!(
+                f,
+                "refine {} {} {}",
+                fmt_addr(original),
+                fmt_addr(refined),
+                unix_secs(*until)
+            )?;
+        }
+        for (dst, until) in &self.no_profiles {
+            writeln!(f, "no-profile {} {}", fmt_addr(dst), unix_secs(*until))?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Entries {
+    type Err = InvalidEntries;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines();
+        if lines.next() != Some(VERSION) {
+            return Err(InvalidEntries);
+        }
+
+        let addr = |s: Option<&str>| {
+            s.and_then(|s| NameAddr::from_str(s).ok())
+                .ok_or(InvalidEntries)
+        };
+        let time = |s: Option<&str>| {
+            s.and_then(|s| s.parse::<u64>().ok())
+                .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+                .ok_or(InvalidEntries)
+        };
+
+        let mut entries = Entries::default();
+        for line in lines.filter(|l| !l.trim().is_empty()) {
+            let mut parts = line.split_whitespace();
+            match parts.next() {
+                Some("refine") => {
+                    let entry = (
+                        addr(parts.next())?,
+                        addr(parts.next())?,
+                        time(parts.next())?,
+                    );
+                    entries.refinements.push(entry);
+                }
+                Some("no-profile") => {
+                    let entry = (addr(parts.next())?, time(parts.next())?);
+                    entries.no_profiles.push(entry);
+                }
+                _ => return Err(InvalidEntries),
+            }
+            if parts.next().is_some() {
+                return Err(InvalidEntries);
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// Formats an address with its name's trailing dot, if it has one, so that
+/// fully-qualified names are restored as such.
+fn fmt_addr(addr: &NameAddr) -> String {
+    format!("{}:{}", addr.name(), addr.port())
+}
+
+fn unix_secs(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Converts an expiry to wall-clock time, unless it has passed.
+fn to_system_time(until: Instant, now: Instant, sys_now: SystemTime) -> Option<SystemTime> {
+    if until > now {
+        Some(sys_now + (until - now))
+    } else {
+        None
+    }
+}
+
+/// Converts a wall-clock expiry to this process's clock, unless it has
+/// passed.
+fn to_instant(until: SystemTime, now: Instant, sys_now: SystemTime) -> Option<Instant> {
+    until.duration_since(sys_now).ok().map(|d| now + d)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> NameAddr {
+        NameAddr::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn caches_round_trip_through_file() {
+        let path = std::env::temp_dir().join(format!("proxy-caches-{}", std::process::id()));
+        let config = Config { path: path.clone() };
+        let in_an_hour = tokio::clock::now() + Duration::from_secs(60 * 60);
+
+        let caches = Caches::default();
+        caches.refinements.restore(vec![(
+            addr("web:8080"),
+            addr("web.ns.svc.cluster.local.:8080"),
+            in_an_hour,
+        )]);
+        caches
+            .no_profiles
+            .restore(vec![(addr("web.ns.svc.cluster.local:8080"), in_an_hour)]);
+        // Expired entries aren't persisted.
+        caches.no_profiles.restore(vec![(
+            addr("old.ns.svc.cluster.local:8080"),
+            tokio::clock::now(),
+        )]);
+        config.persist(&caches);
+
+        let restored = Caches::default();
+        config.restore(&restored);
+        fs::remove_file(&path).unwrap();
+
+        let refinements = restored.refinements.snapshot();
+        assert_eq!(refinements.len(), 1);
+        let (original, refined, until) = &refinements[0];
+        assert_eq!(*original, addr("web:8080"));
+        assert_eq!(
+            refined.name().to_string(),
+            "web.ns.svc.cluster.local.",
+            "fully-qualified names must be restored"
+        );
+        assert!(*until > tokio::clock::now() + Duration::from_secs(60 * 59));
+
+        let no_profiles = restored.no_profiles.snapshot();
+        assert_eq!(no_profiles.len(), 1);
+        assert_eq!(no_profiles[0].0, addr("web.ns.svc.cluster.local:8080"));
+    }
+
+    #[test]
+    fn expired_entries_are_not_restored() {
+        let entries = format!(
+            "{}\nrefine web:8080 web.ns.svc.cluster.local.:8080 {}\n",
+            VERSION,
+            unix_secs(SystemTime::now()) - 1
+        );
+        let entries = entries.parse::<Entries>().expect("entries must parse");
+        let (now, sys_now) = (tokio::clock::now(), SystemTime::now());
+        assert_eq!(to_instant(entries.refinements[0].2, now, sys_now), None);
+    }
+
+    #[test]
+    fn invalid_files_are_ignored() {
+        for invalid in &[
+            "",
+            "linkerd2-proxy-caches v0\nno-profile web.ns.svc.cluster.local:8080 1",
+            "linkerd2-proxy-caches v1\nno-profile web.ns.svc.cluster.local 1",
+            "linkerd2-proxy-caches v1\nno-profile web.ns.svc.cluster.local:8080 soon",
+            "linkerd2-proxy-caches v1\nrefine web:8080 1",
+            "linkerd2-proxy-caches v1\nresolve web:8080 10.1.1.1:8080 1",
+        ] {
+            assert!(
+                invalid.parse::<Entries>().is_err(),
+                "{:?} must not parse",
+                invalid
+            );
+        }
+
+        let config = Config {
+            path: std::env::temp_dir().join("proxy-caches-missing"),
+        };
+        let caches = Caches::default();
+        config.restore(&caches);
+        assert!(caches.refinements.snapshot().is_empty());
+    }
+}
@@ -55,6 +55,13 @@ enum State<F> {
     Draining,
 }
 
+/// A future that resolves when a drain is signaled.
+///
+/// Unlike a `Watch`, a `Signaled` does not delay the completion of a drain,
+/// so it may be held by long-lived resources.
+#[derive(Clone, Debug)]
+pub struct Signaled(Shared<oneshot::Receiver<()>>);
+
 /// A future that resolves when all `Watch`ers have been dropped (drained).
 pub struct Drained {
     drained_rx: mpsc::Receiver<Never>,
@@ -94,6 +101,11 @@ impl Watch {
             watch: self,
         }
     }
+
+    /// Returns a future that resolves when a drain is signaled.
+    pub fn signaled(&self) -> Signaled {
+        Signaled(self.rx.clone())
+    }
 }
 
 // ===== impl Watching =====
@@ -129,6 +141,21 @@ where
     }
 }
 
+// ===== impl Signaled =====
+
+impl Future for Signaled {
+    type Item = ();
+    type Error = Never;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.0.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            // As with `Watching`, a dropped `Signal` is treated as a drain.
+            Ok(Async::Ready(_)) | Err(_) => Ok(Async::Ready(())),
+        }
+    }
+}
+
 // ===== impl Drained =====
 
 impl Future for Drained {
@@ -263,4 +290,24 @@ mod tests {
         .wait()
         .unwrap();
     }
+
+    #[test]
+    fn signaled() {
+        future::lazy(|| {
+            let (tx, rx) = channel();
+            let mut signaled = rx.signaled();
+            assert!(signaled.poll().unwrap().is_not_ready());
+
+            drop(rx);
+            let mut draining = tx.drain();
+
+            // Holding a `Signaled` does not prevent the drain from completing.
+            assert!(draining.poll().unwrap().is_ready());
+            assert!(signaled.poll().unwrap().is_ready());
+
+            Ok::<_, ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
 }
@@ -1,12 +1,23 @@
 #![deny(warnings, rust_2018_idioms)]
 
 use bytes::{Buf, BufMut};
-use futures::{try_ready, Async, Future, Poll};
+use futures::{task, try_ready, Async, Future, Poll};
 use std::io;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tracing::trace;
 
+/// The size of each half's copy buffer, unless otherwise configured.
+pub const DEFAULT_BUFFER_SIZE: usize = 4096;
+
+/// The number of reads each half may perform in a single poll before it
+/// yields, so that a fast producer in one direction can't starve the other.
+const MAX_READS_PER_POLL: usize = 16;
+
 /// A future piping data bi-directionally to In and Out.
+///
+/// When one side reaches EOF, the bytes it sent are flushed and the other
+/// side's write half is shut down; the opposite direction continues to be
+/// copied until it also reaches EOF.
 pub struct Duplex<In, Out> {
     half_in: HalfDuplex<In>,
     half_out: HalfDuplex<Out>,
@@ -16,6 +27,8 @@ struct HalfDuplex<T> {
     // None means socket met eof, and bytes have been drained into other half.
     buf: Option<CopyBuf>,
     is_shutdown: bool,
+    // The number of bytes read from `io` and written into the other half.
+    copied: u64,
     io: T,
 }
 
@@ -39,10 +52,35 @@ where
 {
     pub fn new(in_io: In, out_io: Out) -> Self {
         Duplex {
-            half_in: HalfDuplex::new(in_io),
-            half_out: HalfDuplex::new(out_io),
+            half_in: HalfDuplex::new(in_io, DEFAULT_BUFFER_SIZE),
+            half_out: HalfDuplex::new(out_io, DEFAULT_BUFFER_SIZE),
+        }
+    }
+
+    /// Sets the size of the buffer used to copy data in each direction.
+    ///
+    /// This must be set before the duplex is polled.
+    pub fn with_buffer_size(self, size: usize) -> Self {
+        assert!(size > 0, "copy buffer size must be non-zero");
+        debug_assert!(
+            self.half_in.copied == 0 && self.half_out.copied == 0,
+            "buffer size changed after copying"
+        );
+        Duplex {
+            half_in: HalfDuplex::new(self.half_in.io, size),
+            half_out: HalfDuplex::new(self.half_out.io, size),
         }
     }
+
+    /// Returns the number of bytes copied from `In` to `Out`, so far.
+    pub fn bytes_in_to_out(&self) -> u64 {
+        self.half_in.copied
+    }
+
+    /// Returns the number of bytes copied from `Out` to `In`, so far.
+    pub fn bytes_out_to_in(&self) -> u64 {
+        self.half_out.copied
+    }
 }
 
 impl<In, Out> Future for Duplex<In, Out>
@@ -72,10 +110,11 @@ impl<T> HalfDuplex<T>
 where
     T: AsyncRead,
 {
-    fn new(io: T) -> Self {
+    fn new(io: T, buffer_size: usize) -> Self {
         Self {
-            buf: Some(CopyBuf::new()),
+            buf: Some(CopyBuf::new(buffer_size)),
             is_shutdown: false,
+            copied: 0,
             io,
         }
     }
@@ -93,10 +132,21 @@ where
             trace!("already shutdown");
             return Ok(Async::Ready(()));
         }
-        loop {
-            try_ready!(self.read());
+        for _ in 0..MAX_READS_PER_POLL {
+            if self.read()?.is_not_ready() {
+                // Nothing more can be read for now, so ensure that everything
+                // written so far actually reaches the destination.
+                try_ready!(dst.io.poll_flush());
+                return Ok(Async::NotReady);
+            }
             try_ready!(self.write_into(dst));
             if self.buf.is_none() {
+                // The destination may buffer writes internally (e.g. TLS), so
+                // the final bytes must be flushed before the shutdown is
+                // propagated; otherwise the peer may observe a truncated
+                // stream.
+                trace!("flushing");
+                try_ready!(dst.io.poll_flush());
                 trace!("shutting down");
                 debug_assert!(!dst.is_shutdown, "attempted to shut down destination twice");
                 try_ready!(dst.io.shutdown());
@@ -105,6 +155,14 @@ where
                 return Ok(Async::Ready(()));
             }
         }
+
+        // This half has exhausted its budget but may still be readable. Yield
+        // so that the other half (and other tasks) may make progress, and
+        // ensure that this task is polled again.
+        trace!("yielding");
+        try_ready!(dst.io.poll_flush());
+        task::current().notify();
+        Ok(Async::NotReady)
     }
 
     fn read(&mut self) -> Poll<(), io::Error> {
@@ -140,6 +198,7 @@ where
                 if n == 0 {
                     return Err(write_zero());
                 }
+                self.copied += n as u64;
             }
         }
 
@@ -156,9 +215,9 @@ fn write_zero() -> io::Error {
 }
 
 impl CopyBuf {
-    fn new() -> Self {
+    fn new(size: usize) -> Self {
         CopyBuf {
-            buf: Box::new([0; 4096]),
+            buf: vec![0; size].into_boxed_slice(),
             read_pos: 0,
             write_pos: 0,
         }
@@ -203,10 +262,12 @@ impl BufMut for CopyBuf {
 
 #[cfg(test)]
 mod tests {
-    use std::io::{Error, Read, Result, Write};
-    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::io::{Error, ErrorKind, Read, Result, Write};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
 
     use super::*;
+    use futures::executor::{self, Notify};
     use futures::{Async, Poll};
     use tokio::io::{AsyncRead, AsyncWrite};
 
@@ -258,4 +319,192 @@ mod tests {
         assert_eq!(duplex.poll().unwrap(), Async::NotReady);
         assert_eq!(duplex.poll().unwrap(), Async::Ready(()));
     }
+
+    /// A mock IO whose state is shared with the test.
+    #[derive(Clone, Debug, Default)]
+    struct MockIo(Arc<Mutex<MockState>>);
+
+    #[derive(Debug, Default)]
+    struct MockState {
+        /// The number of bytes that may be read before the read would block.
+        readable: usize,
+        /// If set, reads return EOF once `readable` is exhausted.
+        eof: bool,
+        /// If set, reads never block or end.
+        flood: bool,
+        /// Bytes that have been written but not yet flushed.
+        unflushed: usize,
+        /// Bytes that have been written and flushed.
+        flushed: usize,
+        is_shutdown: bool,
+    }
+
+    impl MockIo {
+        fn readable(n: usize, eof: bool) -> Self {
+            let io = Self::default();
+            {
+                let mut state = io.0.lock().unwrap();
+                state.readable = n;
+                state.eof = eof;
+            }
+            io
+        }
+
+        fn flood() -> Self {
+            let io = Self::default();
+            io.0.lock().unwrap().flood = true;
+            io
+        }
+
+        fn state(&self) -> std::sync::MutexGuard<'_, MockState> {
+            self.0.lock().unwrap()
+        }
+    }
+
+    impl Read for MockIo {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let mut state = self.state();
+            if state.flood {
+                return Ok(buf.len());
+            }
+            if state.readable > 0 {
+                let n = buf.len().min(state.readable);
+                state.readable -= n;
+                return Ok(n);
+            }
+            if state.eof {
+                return Ok(0);
+            }
+            Err(ErrorKind::WouldBlock.into())
+        }
+    }
+
+    impl AsyncRead for MockIo {}
+
+    impl Write for MockIo {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            let mut state = self.state();
+            assert!(!state.is_shutdown, "write after shutdown");
+            state.unflushed += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            let mut state = self.state();
+            state.flushed += state.unflushed;
+            state.unflushed = 0;
+            Ok(())
+        }
+    }
+
+    impl AsyncWrite for MockIo {
+        fn shutdown(&mut self) -> Poll<(), Error> {
+            let mut state = self.state();
+            assert_eq!(state.unflushed, 0, "shutdown before flush");
+            state.is_shutdown = true;
+            Ok(Async::Ready(()))
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct CountNotify(AtomicUsize);
+
+    impl Notify for CountNotify {
+        fn notify(&self, _: usize) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn half_close_in_to_out() {
+        let io_in = MockIo::readable(10_000, true);
+        let io_out = MockIo::readable(0, false);
+        let notify = Arc::new(CountNotify::default());
+        let mut duplex =
+            executor::spawn(Duplex::new(io_in.clone(), io_out.clone()).with_buffer_size(1024));
+
+        // `In` finishes, so `Out`'s write half is shut down once everything
+        // has been flushed; `Out` may still send data.
+        assert!(duplex
+            .poll_future_notify(&notify, 0)
+            .unwrap()
+            .is_not_ready());
+        assert_eq!(duplex.get_ref().bytes_in_to_out(), 10_000);
+        assert_eq!(io_out.state().flushed, 10_000);
+        assert!(io_out.state().is_shutdown);
+        assert!(!io_in.state().is_shutdown);
+
+        {
+            let mut state = io_out.state();
+            state.readable = 5_000;
+            state.eof = true;
+        }
+        assert!(duplex.poll_future_notify(&notify, 0).unwrap().is_ready());
+        assert_eq!(duplex.get_ref().bytes_in_to_out(), 10_000);
+        assert_eq!(duplex.get_ref().bytes_out_to_in(), 5_000);
+        assert_eq!(io_in.state().flushed, 5_000);
+        assert!(io_in.state().is_shutdown);
+    }
+
+    #[test]
+    fn half_close_out_to_in() {
+        let io_in = MockIo::readable(0, false);
+        let io_out = MockIo::readable(10_000, true);
+        let notify = Arc::new(CountNotify::default());
+        let mut duplex =
+            executor::spawn(Duplex::new(io_in.clone(), io_out.clone()).with_buffer_size(1024));
+
+        assert!(duplex
+            .poll_future_notify(&notify, 0)
+            .unwrap()
+            .is_not_ready());
+        assert_eq!(duplex.get_ref().bytes_out_to_in(), 10_000);
+        assert_eq!(io_in.state().flushed, 10_000);
+        assert!(io_in.state().is_shutdown);
+        assert!(!io_out.state().is_shutdown);
+
+        {
+            let mut state = io_in.state();
+            state.readable = 5_000;
+            state.eof = true;
+        }
+        assert!(duplex.poll_future_notify(&notify, 0).unwrap().is_ready());
+        assert_eq!(duplex.get_ref().bytes_in_to_out(), 5_000);
+        assert_eq!(duplex.get_ref().bytes_out_to_in(), 10_000);
+        assert_eq!(io_out.state().flushed, 5_000);
+        assert!(io_out.state().is_shutdown);
+    }
+
+    #[test]
+    fn one_sided_flood_does_not_starve_other_direction() {
+        const BUFFER_SIZE: usize = 1024;
+        let io_in = MockIo::flood();
+        let io_out = MockIo::readable(0, false);
+        let notify = Arc::new(CountNotify::default());
+        let mut duplex = executor::spawn(
+            Duplex::new(io_in.clone(), io_out.clone()).with_buffer_size(BUFFER_SIZE),
+        );
+
+        let mut sent = 0;
+        for i in 1..=10 {
+            io_out.state().readable = 100;
+            assert!(duplex
+                .poll_future_notify(&notify, 0)
+                .unwrap()
+                .is_not_ready());
+
+            // Each poll copies a bounded amount of the flood, and the task is
+            // notified so that it's polled again.
+            let copied = duplex.get_ref().bytes_in_to_out();
+            assert!(copied > sent, "flood must make progress");
+            assert!(copied - sent <= (MAX_READS_PER_POLL * BUFFER_SIZE) as u64);
+            assert_eq!(io_out.state().flushed as u64, copied);
+            sent = copied;
+            assert_eq!(notify.0.load(Ordering::SeqCst), i);
+
+            // Meanwhile, the other direction is copied as well.
+            assert_eq!(duplex.get_ref().bytes_out_to_in(), 100 * i as u64);
+            assert_eq!(io_in.state().flushed, 100 * i);
+        }
+    }
 }
@@ -0,0 +1,161 @@
+//! Checks internal invariants on request paths.
+//!
+//! Services rely on their callers to uphold tower's contracts: e.g. that a
+//! service is only called once it has become ready, and that a future is not
+//! polled after it completes. If a composed layer violates one of these
+//! contracts, an `expect` deep in the stack would kill the connection's task
+//! without any indication of what went wrong. Instead, request paths check
+//! these invariants with `contract!`, so that the request fails with an
+//! `InvariantViolation` naming the site at which it was detected.
+//!
+//! When the `strict-contracts` feature is enabled, violations panic instead,
+//! so that they are caught during development.
+
+use super::Error;
+use futures::{Future, Poll};
+use std::fmt;
+
+/// Evaluates to the value of an `Option`, or to `()` if a `bool` is true;
+/// otherwise, evaluates to an `InvariantViolation` at `$site`.
+///
+/// The result is a `Result<_, InvariantViolation>`, so that it may be
+/// propagated with `?`.
+#[macro_export]
+macro_rules! contract {
+    ($check:expr, $site:expr, $($details:tt)+) => {
+        $crate::contract::Check::check($check)
+            .ok_or_else(|| $crate::contract::InvariantViolation::new($site, format!($($details)+)))
+    };
+}
+
+/// Indicates that an internal invariant was violated, e.g. because a service
+/// was called before it was ready.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvariantViolation {
+    site: &'static str,
+    details: String,
+}
+
+/// A value that upholds an invariant if it is present.
+pub trait Check {
+    type Value;
+
+    fn check(self) -> Option<Self::Value>;
+}
+
+/// A response future that fails with an `InvariantViolation` if its service
+/// was called in violation of its contract.
+#[derive(Debug)]
+pub enum ResponseFuture<F> {
+    Inner(F),
+    Violated(InvariantViolation),
+}
+
+// === impl InvariantViolation ===
+
+impl InvariantViolation {
+    /// Panics if the `strict-contracts` feature is enabled.
+    pub fn new(site: &'static str, details: String) -> Self {
+        if cfg!(feature = "strict-contracts") {
+            panic!("invariant violated at {}: {}", site, details);
+        }
+        Self { site, details }
+    }
+
+    /// Returns the name of the site at which the violation was detected.
+    pub fn site(&self) -> &'static str {
+        self.site
+    }
+
+    pub fn details(&self) -> &str {
+        &self.details
+    }
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invariant violated at {}: {}", self.site, self.details)
+    }
+}
+
+impl std::error::Error for InvariantViolation {}
+
+// === impl Check ===
+
+impl<T> Check for Option<T> {
+    type Value = T;
+
+    fn check(self) -> Option<T> {
+        self
+    }
+}
+
+impl Check for bool {
+    type Value = ();
+
+    fn check(self) -> Option<()> {
+        if self {
+            Some(())
+        } else {
+            None
+        }
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl<F> From<Result<F, InvariantViolation>> for ResponseFuture<F> {
+    fn from(result: Result<F, InvariantViolation>) -> Self {
+        match result {
+            Ok(f) => ResponseFuture::Inner(f),
+            Err(violation) => ResponseFuture::Violated(violation),
+        }
+    }
+}
+
+impl<F> Future for ResponseFuture<F>
+where
+    F: Future,
+    F::Error: Into<Error>,
+{
+    type Item = F::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self {
+            ResponseFuture::Inner(ref mut f) => f.poll().map_err(Into::into),
+            ResponseFuture::Violated(ref violation) => Err(violation.clone().into()),
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "strict-contracts")))]
+mod tests {
+    use super::*;
+    use futures::future;
+
+    #[test]
+    fn violations_name_their_site() {
+        let present: Result<usize, InvariantViolation> = contract!(Some(1), "test", "absent");
+        assert_eq!(present, Ok(1));
+        assert_eq!(contract!(true, "test", "false"), Ok(()));
+
+        let err = contract!(
+            None::<usize>,
+            "test::site",
+            "{} before {}",
+            "called",
+            "ready"
+        )
+        .expect_err("must be violated");
+        assert_eq!(err.site(), "test::site");
+        assert_eq!(err.details(), "called before ready");
+
+        let rsp = ResponseFuture::<future::FutureResult<(), Error>>::from(Err(err));
+        let err = rsp.wait().expect_err("must fail");
+        let violation = err
+            .downcast_ref::<InvariantViolation>()
+            .expect("must be a violation");
+        assert_eq!(violation.site(), "test::site");
+    }
+}
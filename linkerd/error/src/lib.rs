@@ -1,8 +1,10 @@
 #![deny(warnings, rust_2018_idioms)]
 
+pub mod contract;
 mod never;
 pub mod recover;
 
+pub use self::contract::InvariantViolation;
 pub use self::never::Never;
 pub use self::recover::Recover;
 
@@ -37,9 +37,14 @@ pub struct InvalidBackoff(&'static str);
 
 impl ExponentialBackoff {
     pub fn stream(&self) -> ExponentialBackoffStream {
+        self.stream_with_rng(SmallRng::from_entropy())
+    }
+
+    /// Returns a backoff stream that jitters its delays with `rng`.
+    pub fn stream_with_rng(&self, rng: SmallRng) -> ExponentialBackoffStream {
         ExponentialBackoffStream {
             backoff: self.clone(),
-            rng: SmallRng::from_entropy(),
+            rng,
             iterations: 0,
             delay: None,
         }
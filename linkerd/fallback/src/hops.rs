@@ -0,0 +1,83 @@
+use linkerd2_metrics::{Bounds, Bucket, FmtMetric, FmtMetrics, Histogram, Metric};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Most fallback chains are only a few builders deep.
+const BOUNDS: &Bounds = &Bounds(&[
+    Bucket::Le(0),
+    Bucket::Le(1),
+    Bucket::Le(2),
+    Bucket::Le(3),
+    Bucket::Le(5),
+    Bucket::Inf,
+]);
+
+/// A histogram of the number of fallbacks taken before a fallback chain
+/// built a service (or failed to).
+///
+/// Each `Hops` records from a position in a chain: the first layer in a
+/// chain records with the `Hops` it was given, and a fallback layer nested
+/// within it records with that `Hops`'s `next()`.
+#[derive(Clone, Debug)]
+pub struct Hops {
+    histogram: Arc<Mutex<Histogram<u64>>>,
+    hop: u64,
+}
+
+// === impl Hops ===
+
+impl Hops {
+    pub const HELP: &'static str =
+        "A histogram of the number of fallbacks taken before a service was built.";
+    pub const NAME: &'static str = "fallback_hops";
+
+    /// Returns the recorder for the layer following this one in a chain.
+    pub fn next(&self) -> Self {
+        Self {
+            histogram: self.histogram.clone(),
+            hop: self.hop + 1,
+        }
+    }
+
+    /// Records that the chain resolved `fallbacks` hops past this position.
+    pub(crate) fn record(&self, fallbacks: u64) {
+        if let Ok(mut hist) = self.histogram.lock() {
+            hist.add(self.hop + fallbacks);
+        }
+    }
+
+    fn metric(&self) -> Metric<'_, Histogram<u64>> {
+        Metric::new(Self::NAME, Self::HELP)
+    }
+}
+
+impl Default for Hops {
+    fn default() -> Self {
+        Self {
+            histogram: Arc::new(Mutex::new(Histogram::new(BOUNDS))),
+            hop: 0,
+        }
+    }
+}
+
+impl FmtMetrics for Hops {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hist = match self.histogram.lock() {
+            Ok(hist) => hist,
+            Err(_) => return Ok(()),
+        };
+
+        self.metric().fmt_help(f)?;
+        hist.fmt_metric(f, Self::NAME)
+    }
+}
+
+#[cfg(test)]
+impl Hops {
+    pub(crate) fn assert_recorded(&self, hops: u64, count: u64) {
+        self.histogram
+            .lock()
+            .unwrap()
+            .assert_bucket_exactly(hops, count);
+    }
+}
@@ -1,9 +1,13 @@
 #![deny(warnings, rust_2018_idioms)]
 
-use futures::{try_ready, Future, Poll};
+use futures::{try_ready, Async, Future, Poll};
 use linkerd2_error::Error;
 use tracing::trace;
 
+mod hops;
+
+pub use self::hops::Hops;
+
 /// A fallback layer composing two service builders.
 ///
 /// If the future returned by the primary builder's `MakeService` fails with
@@ -14,6 +18,10 @@ pub struct Layer<A, B, P = fn(&Error) -> bool> {
     primary: A,
     fallback: B,
     predicate: P,
+    hops: Option<Hops>,
+    /// Whether `fallback` is itself a fallback layer that records its own
+    /// hops.
+    chained: bool,
 }
 
 #[derive(Clone, Debug)]
@@ -21,6 +29,8 @@ pub struct MakeSvc<A, B, P> {
     primary: A,
     fallback: B,
     predicate: P,
+    hops: Option<Hops>,
+    chained: bool,
 }
 
 pub struct MakeFuture<A, B, P, T>
@@ -32,6 +42,8 @@ where
     fallback: B,
     target: Option<T>,
     predicate: P,
+    hops: Option<Hops>,
+    chained: bool,
     state: FallbackState<A, B::Future, T>,
 }
 
@@ -50,6 +62,8 @@ pub fn layer<A, B>(primary: A, fallback: B) -> Layer<A, B> {
         primary,
         fallback,
         predicate,
+        hops: None,
+        chained: false,
     }
 }
 
@@ -66,6 +80,8 @@ impl<A, B> Layer<A, B> {
             primary: self.primary,
             fallback: self.fallback,
             predicate,
+            hops: self.hops,
+            chained: self.chained,
         }
     }
 
@@ -79,6 +95,31 @@ impl<A, B> Layer<A, B> {
     }
 }
 
+impl<A, B, P> Layer<A, B, P> {
+    /// Returns a `Layer` that records, in `hops`, the number of fallbacks
+    /// taken before each service is built (or fails to be built).
+    pub fn with_hops(self, hops: Hops) -> Self {
+        Self {
+            hops: Some(hops),
+            chained: false,
+            ..self
+        }
+    }
+
+    /// Like `with_hops`, for a `Layer` whose fallback is itself a fallback
+    /// layer recording with `hops.next()`.
+    ///
+    /// When this layer falls back, the number of hops is left to the chained
+    /// layer to record.
+    pub fn with_chained_hops(self, hops: Hops) -> Self {
+        Self {
+            hops: Some(hops),
+            chained: true,
+            ..self
+        }
+    }
+}
+
 impl<A, B, P, M> tower::layer::Layer<M> for Layer<A, B, P>
 where
     A: tower::layer::Layer<M>,
@@ -93,6 +134,8 @@ where
             primary: self.primary.layer(inner.clone()),
             fallback: self.fallback.layer(inner),
             predicate: self.predicate.clone(),
+            hops: self.hops.clone(),
+            chained: self.chained,
         }
     }
 }
@@ -122,6 +165,8 @@ where
             fallback: self.fallback.clone(),
             predicate: self.predicate.clone(),
             target: Some(target.clone()),
+            hops: self.hops.clone(),
+            chained: self.chained,
             state: FallbackState::Primary(self.primary.call(target)),
         }
     }
@@ -145,7 +190,11 @@ where
                 // We've called the primary service and are waiting for its
                 // future to complete.
                 FallbackState::Primary(ref mut f) => match f.poll() {
-                    Ok(r) => return Ok(r),
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(svc)) => {
+                        self.record(0);
+                        return Ok(Async::Ready(svc));
+                    }
                     Err(error) => {
                         let error = error.into();
                         if (self.predicate)(&error) {
@@ -153,6 +202,7 @@ where
                             FallbackState::Waiting(self.target.take())
                         } else {
                             trace!("{} does not match; not falling back", error);
+                            self.record(0);
                             return Err(error);
                         }
                     }
@@ -160,16 +210,113 @@ where
                 // The primary service has returned an error matching the
                 // predicate, and we are waiting for the fallback service to be ready.
                 FallbackState::Waiting(ref mut target) => {
-                    try_ready!(self.fallback.poll_ready().map_err(Into::into));
+                    let ready = self.fallback.poll_ready().map_err(Into::into);
+                    if ready.is_err() {
+                        // The fallback was never called, so a chained layer
+                        // can't have recorded anything.
+                        if let Some(ref hops) = self.hops {
+                            hops.record(1);
+                        }
+                    }
+                    try_ready!(ready);
                     let target = target.take().expect("target should only be taken once");
                     FallbackState::Fallback(self.fallback.call(target))
                 }
                 // We've called the fallback service and are waiting for its
                 // future to complete.
                 FallbackState::Fallback(ref mut f) => {
-                    return f.poll().map(|a| a.map(Into::into)).map_err(Into::into);
+                    let poll = f.poll().map(|a| a.map(Into::into)).map_err(Into::into);
+                    let resolved = match poll {
+                        Ok(Async::NotReady) => false,
+                        _ => true,
+                    };
+                    // A chained fallback layer records the hops it took.
+                    if resolved && !self.chained {
+                        self.record(1);
+                    }
+                    return poll;
                 }
             }
         }
     }
 }
+
+impl<A, B, P, T> MakeFuture<A, B, P, T>
+where
+    A: Future,
+    A::Error: Into<Error>,
+    B: tower::Service<T>,
+{
+    /// Records that this future resolved after `fallbacks` hops.
+    fn record(&self, fallbacks: u64) {
+        if let Some(ref hops) = self.hops {
+            hops.record(fallbacks);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use tower::{layer::Layer as _, Service as _};
+
+    /// Builds the named service, or fails to if it has no name.
+    #[derive(Clone, Debug)]
+    struct Builder(Option<&'static str>);
+
+    impl tower::layer::Layer<()> for Builder {
+        type Service = Self;
+
+        fn layer(&self, _: ()) -> Self::Service {
+            self.clone()
+        }
+    }
+
+    impl tower::Service<()> for Builder {
+        type Response = &'static str;
+        type Error = Error;
+        type Future = future::FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: ()) -> Self::Future {
+            future::result(self.0.ok_or_else(|| "unbuildable".into()))
+        }
+    }
+
+    fn chain(first: Builder, second: Builder, third: Builder, hops: &Hops) -> &'static str {
+        let fallback = layer(second, third).with_hops(hops.next());
+        let mut make = layer(first, fallback)
+            .with_chained_hops(hops.clone())
+            .layer(());
+        make.call(()).wait().expect("chain must build a service")
+    }
+
+    #[test]
+    fn records_no_hops_when_the_first_builder_succeeds() {
+        let hops = Hops::default();
+        let svc = chain(
+            Builder(Some("first")),
+            Builder(Some("second")),
+            Builder(Some("third")),
+            &hops,
+        );
+        assert_eq!(svc, "first");
+        hops.assert_recorded(0, 1);
+        hops.assert_recorded(1, 0);
+        hops.assert_recorded(2, 0);
+    }
+
+    #[test]
+    fn records_two_hops_when_the_third_builder_succeeds() {
+        let hops = Hops::default();
+        let svc = chain(Builder(None), Builder(None), Builder(Some("third")), &hops);
+        assert_eq!(svc, "third");
+        hops.assert_recorded(0, 0);
+        hops.assert_recorded(1, 0);
+        hops.assert_recorded(2, 1);
+    }
+}
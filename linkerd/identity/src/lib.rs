@@ -323,6 +323,11 @@ impl CrtKey {
     pub fn tls_server_config(&self) -> Arc<rustls::ServerConfig> {
         self.server_config.clone()
     }
+
+    /// Returns the time after which the certificate is no longer valid.
+    pub fn expiry(&self) -> SystemTime {
+        self.expiry
+    }
 }
 
 impl fmt::Debug for CrtKey {
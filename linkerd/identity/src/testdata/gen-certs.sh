@@ -24,10 +24,26 @@ ee() {
   cp_ns=$4
 
   hostname="${ee_name}.${ee_ns}.serviceaccount.identity.${cp_ns}.cluster.local"
+  gen_ee "${ca_name}" "${ee_name}-${ee_ns}-${ca_name}" "${hostname}"
+}
+
+# An end entity identified by a SPIFFE URI SAN rather than a DNS name.
+ee_spiffe() {
+  ca_name=$1
+  ee_name=$2
+  ee_ns=$3
+
+  uri="spiffe://cluster.local/ns/${ee_ns}/sa/${ee_name}"
+  gen_ee "${ca_name}" "${ee_name}-${ee_ns}-${ca_name}-spiffe" "${uri}"
+}
+
+gen_ee() {
+  ca_name=$1
+  ee=$2
+  san=$3
 
-  ee="${ee_name}-${ee_ns}-${ca_name}"
   echo '{}' \
-    | cfssl gencert -ca "${ca_name}.pem" -ca-key "${ca_name}-key.pem" -hostname "${hostname}" - \
+    | cfssl gencert -ca "${ca_name}.pem" -ca-key "${ca_name}-key.pem" -hostname "${san}" - \
     | cfssljson -bare "${ee}"
   mkdir -p "${ee}"
 
@@ -57,3 +73,4 @@ ee ca1 controller linkerd linkerd
 ee ca1 foo ns1 linkerd
 ee ca2 foo ns1 linkerd # Same, but different CA
 ee ca1 bar ns1 linkerd # Different service.
+ee_spiffe ca1 foo ns1 # Same service, identified by a SPIFFE URI.
@@ -23,6 +23,13 @@ impl<T: AsyncRead + AsyncWrite> Peek<T> {
         let buf = BytesMut::with_capacity(capacity);
         Peek(Some(Inner { buf, io }))
     }
+
+    /// Stops peeking, returning the transport along with whatever has been
+    /// read from it.
+    pub fn into_io(mut self) -> PrefixedIo<T> {
+        let Inner { buf, io } = self.0.take().expect("polled after complete");
+        PrefixedIo::new(buf.freeze(), io)
+    }
 }
 
 impl<T: AsyncRead + AsyncWrite> Future for Peek<T> {
@@ -56,11 +56,11 @@ struct Label<K: fmt::Display, V: fmt::Display>(K, V);
 impl<V: Into<u64>> Histogram<V> {
     pub fn new(bounds: &'static Bounds) -> Self {
         let mut buckets = Vec::with_capacity(bounds.0.len());
-        let mut prior = &Bucket::Le(0);
+        let mut prior: Option<&Bucket> = None;
         for bound in bounds.0.iter() {
-            assert!(prior < bound);
+            assert!(prior.map(|p| p < bound).unwrap_or(true));
             buckets.push(Counter::default());
-            prior = bound;
+            prior = Some(bound);
         }
 
         Self {
@@ -88,6 +88,20 @@ impl<V: Into<u64>> Histogram<V> {
         self.buckets[idx].incr();
         self.sum += value;
     }
+
+    /// Adds all of the values observed by `other` into this histogram.
+    ///
+    /// Both histograms must have the same bounds.
+    pub fn merge(&mut self, other: &Self) {
+        assert_eq!(
+            self.bounds.0, other.bounds.0,
+            "only histograms with the same bounds may be merged"
+        );
+        for (bucket, count) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *bucket += *count;
+        }
+        self.sum += other.sum;
+    }
 }
 
 #[cfg(any(test, feature = "test_util"))]
@@ -403,5 +417,22 @@ mod tests {
             }
             true
         }
+
+        fn merge_equals_combined_observations(a: Vec<u64>, b: Vec<u64>) -> bool {
+            let mut combined = Histogram::<u64>::new(&BOUNDS);
+            let mut hist_a = Histogram::<u64>::new(&BOUNDS);
+            for obs in &a {
+                hist_a.add(*obs);
+                combined.add(*obs);
+            }
+            let mut hist_b = Histogram::<u64>::new(&BOUNDS);
+            for obs in &b {
+                hist_b.add(*obs);
+                combined.add(*obs);
+            }
+
+            hist_a.merge(&hist_b);
+            hist_a.buckets == combined.buckets && hist_a.sum == combined.sum
+        }
     }
 }
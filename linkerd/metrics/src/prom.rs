@@ -1,7 +1,16 @@
+use std::borrow::Cow;
 use std::fmt;
 use std::marker::{PhantomData, Sized};
 
+/// Units that are declared in OpenMetrics output for metrics whose names end
+/// with them.
+const UNITS: &[&str] = &["seconds", "bytes", "ms", "us"];
+
 /// Writes a block of metrics in prometheus-formatted output.
+///
+/// When formatted with the alternate flag (i.e. `{:#}`), metrics are written
+/// in the OpenMetrics text format instead. The caller is responsible for
+/// terminating OpenMetrics output with `# EOF`.
 pub trait FmtMetrics {
     fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result;
 
@@ -83,15 +92,52 @@ impl<'a, M: FmtMetric> Metric<'a, M> {
     }
 
     /// Formats help messages for this metric.
+    ///
+    /// In OpenMetrics output, a counter's family is named without its
+    /// `_total` suffix, and the family's unit is declared if its name ends
+    /// with one.
     pub fn fmt_help(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "# HELP {} {}", self.name, self.help)?;
-        writeln!(f, "# TYPE {} {}", self.name, M::KIND)?;
+        if !f.alternate() {
+            writeln!(f, "# HELP {} {}", self.name, self.help)?;
+            writeln!(f, "# TYPE {} {}", self.name, M::KIND)?;
+            return Ok(());
+        }
+
+        let family = self.family();
+        writeln!(f, "# TYPE {} {}", family, M::KIND)?;
+        let unit = UNITS
+            .iter()
+            .find(|u| family.ends_with(*u) && family[..family.len() - u.len()].ends_with('_'));
+        if let Some(unit) = unit {
+            writeln!(f, "# UNIT {} {}", family, unit)?;
+        }
+        writeln!(f, "# HELP {} {}", family, self.help)?;
         Ok(())
     }
 
+    /// Returns the name of this metric's samples.
+    ///
+    /// In OpenMetrics output, counters' samples are always named with a
+    /// `_total` suffix.
+    pub fn sample_name(&self, f: &fmt::Formatter<'_>) -> Cow<'a, str> {
+        if f.alternate() && M::KIND == "counter" && !self.name.ends_with("_total") {
+            return Cow::Owned(format!("{}_total", self.name));
+        }
+        Cow::Borrowed(self.name)
+    }
+
+    /// The name of this metric's family in OpenMetrics output.
+    fn family(&self) -> &'a str {
+        if M::KIND == "counter" && self.name.ends_with("_total") {
+            return &self.name[..self.name.len() - "_total".len()];
+        }
+        self.name
+    }
+
     /// Formats a single metric without labels.
     pub fn fmt_metric(&self, f: &mut fmt::Formatter<'_>, metric: M) -> fmt::Result {
-        metric.fmt_metric(f, self.name)
+        let name = self.sample_name(f);
+        metric.fmt_metric(f, name)
     }
 
     /// Formats a single metric across labeled scopes.
@@ -106,8 +152,9 @@ impl<'a, M: FmtMetric> Metric<'a, M> {
         I: IntoIterator<Item = (L, &'s S)>,
         F: Fn(&S) -> &M,
     {
+        let name = self.sample_name(f);
         for (labels, scope) in scopes {
-            to_metric(scope).fmt_metric_labeled(f, self.name, labels)?;
+            to_metric(scope).fmt_metric_labeled(f, &name, labels)?;
         }
 
         Ok(())
@@ -178,3 +225,129 @@ impl FmtMetrics for () {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Bounds, Bucket, Counter, Gauge, Histogram};
+
+    static BOUNDS: Bounds = Bounds(&[Bucket::Le(10), Bucket::Le(100), Bucket::Inf]);
+
+    struct Direction(&'static str);
+
+    struct Sample {
+        requests: Vec<(Direction, Counter)>,
+        open_connections: Gauge,
+        span_exports: Counter,
+        latencies: Histogram<u64>,
+        start_time: Gauge,
+    }
+
+    impl FmtLabels for Direction {
+        fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "direction=\"{}\"", self.0)
+        }
+    }
+
+    impl FmtMetrics for Sample {
+        fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let requests = Metric::<Counter>::new("request_total", "Total count of requests.");
+            requests.fmt_help(f)?;
+            requests.fmt_scopes(f, self.requests.iter().map(|(d, c)| (d, c)), |c| c)?;
+
+            let open = Metric::<Gauge>::new("open_connections", "Number of open connections.");
+            open.fmt_help(f)?;
+            open.fmt_metric(f, self.open_connections)?;
+
+            let spans = Metric::<Counter>::new("span_exports", "Total count of spans exported.");
+            spans.fmt_help(f)?;
+            spans.fmt_metric(f, self.span_exports)?;
+
+            let latency =
+                Metric::<Histogram<u64>>::new("response_latency_ms", "Response latencies.");
+            latency.fmt_help(f)?;
+            latency.fmt_metric(f, self.latencies.clone())?;
+
+            let start = Metric::<Gauge>::new(
+                "process_start_time_seconds",
+                "Time that the process started.",
+            );
+            start.fmt_help(f)?;
+            start.fmt_metric(f, self.start_time)?;
+
+            Ok(())
+        }
+    }
+
+    fn sample() -> Sample {
+        let mut latencies = Histogram::new(&BOUNDS);
+        latencies.add(5u64);
+        latencies.add(50u64);
+        Sample {
+            requests: vec![
+                (Direction("inbound"), Counter::from(2)),
+                (Direction("outbound"), Counter::from(3)),
+            ],
+            open_connections: Gauge::from(4),
+            span_exports: Counter::from(5),
+            latencies,
+            start_time: Gauge::from(1),
+        }
+    }
+
+    #[test]
+    fn fmt_prometheus() {
+        let expected = "\
+# HELP request_total Total count of requests.
+# TYPE request_total counter
+request_total{direction=\"inbound\"} 2
+request_total{direction=\"outbound\"} 3
+# HELP open_connections Number of open connections.
+# TYPE open_connections gauge
+open_connections 4
+# HELP span_exports Total count of spans exported.
+# TYPE span_exports counter
+span_exports 5
+# HELP response_latency_ms Response latencies.
+# TYPE response_latency_ms histogram
+response_latency_ms_bucket{le=\"10\"} 1
+response_latency_ms_bucket{le=\"100\"} 2
+response_latency_ms_bucket{le=\"+Inf\"} 2
+response_latency_ms_count 2
+response_latency_ms_sum 55
+# HELP process_start_time_seconds Time that the process started.
+# TYPE process_start_time_seconds gauge
+process_start_time_seconds 1
+";
+        assert_eq!(sample().as_display().to_string(), expected);
+    }
+
+    #[test]
+    fn fmt_open_metrics() {
+        let expected = "\
+# TYPE request counter
+# HELP request Total count of requests.
+request_total{direction=\"inbound\"} 2
+request_total{direction=\"outbound\"} 3
+# TYPE open_connections gauge
+# HELP open_connections Number of open connections.
+open_connections 4
+# TYPE span_exports counter
+# HELP span_exports Total count of spans exported.
+span_exports_total 5
+# TYPE response_latency_ms histogram
+# UNIT response_latency_ms ms
+# HELP response_latency_ms Response latencies.
+response_latency_ms_bucket{le=\"10\"} 1
+response_latency_ms_bucket{le=\"100\"} 2
+response_latency_ms_bucket{le=\"+Inf\"} 2
+response_latency_ms_count 2
+response_latency_ms_sum 55
+# TYPE process_start_time_seconds gauge
+# UNIT process_start_time_seconds seconds
+# HELP process_start_time_seconds Time that the process started.
+process_start_time_seconds 1
+";
+        assert_eq!(format!("{:#}", sample().as_display()), expected);
+    }
+}
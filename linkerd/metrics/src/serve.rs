@@ -10,6 +10,9 @@ use tracing::{error, trace};
 
 use super::FmtMetrics;
 
+const OPEN_METRICS_CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
 /// Serve Prometheues metrics.
 #[derive(Debug, Clone)]
 pub struct Serve<M: FmtMetrics> {
@@ -30,16 +33,28 @@ impl<M: FmtMetrics> Serve<M> {
     }
 
     fn is_gzip<B>(req: &Request<B>) -> bool {
+        Self::accepts(req, header::ACCEPT_ENCODING, "gzip")
+    }
+
+    /// Metrics are served in the OpenMetrics format to clients that accept it.
+    fn is_open_metrics<B>(req: &Request<B>) -> bool {
+        Self::accepts(req, header::ACCEPT, "application/openmetrics-text")
+    }
+
+    fn accepts<B>(req: &Request<B>, name: header::HeaderName, value: &str) -> bool {
         req.headers()
-            .get_all(header::ACCEPT_ENCODING)
+            .get_all(name)
             .iter()
-            .any(|value| {
-                value
-                    .to_str()
-                    .ok()
-                    .map(|value| value.contains("gzip"))
-                    .unwrap_or(false)
-            })
+            .any(|v| v.to_str().ok().map(|v| v.contains(value)).unwrap_or(false))
+    }
+
+    fn write_metrics<W: Write>(&self, writer: &mut W, open_metrics: bool) -> io::Result<()> {
+        if open_metrics {
+            write!(writer, "{:#}", self.metrics.as_display())?;
+            writeln!(writer, "# EOF")
+        } else {
+            write!(writer, "{}", self.metrics.as_display())
+        }
     }
 }
 
@@ -50,34 +65,33 @@ impl<M: FmtMetrics> Service for Serve<M> {
     type Future = FutureResult<Response<Body>, Self::Error>;
 
     fn call(&mut self, req: Request<Body>) -> Self::Future {
-        if req.uri().path() != "/metrics" {
-            let rsp = Response::builder()
-                .status(StatusCode::NOT_FOUND)
-                .body(Body::empty())
-                .expect("builder with known status code should not fail");
-            return future::ok(rsp);
-        }
+        let open_metrics = Self::is_open_metrics(&req);
+        let content_type = if open_metrics {
+            OPEN_METRICS_CONTENT_TYPE
+        } else {
+            "text/plain"
+        };
 
         let resp = if Self::is_gzip(&req) {
             trace!("gzipping metrics");
             let mut writer = GzEncoder::new(Vec::<u8>::new(), CompressionOptions::fast());
-            write!(&mut writer, "{}", self.metrics.as_display())
+            self.write_metrics(&mut writer, open_metrics)
                 .and_then(|_| writer.finish())
                 .map_err(ServeError::from)
                 .and_then(|body| {
                     Response::builder()
                         .header(header::CONTENT_ENCODING, "gzip")
-                        .header(header::CONTENT_TYPE, "text/plain")
+                        .header(header::CONTENT_TYPE, content_type)
                         .body(Body::from(body))
                         .map_err(ServeError::from)
                 })
         } else {
             let mut writer = Vec::<u8>::new();
-            write!(&mut writer, "{}", self.metrics.as_display())
+            self.write_metrics(&mut writer, open_metrics)
                 .map_err(ServeError::from)
                 .and_then(|_| {
                     Response::builder()
-                        .header(header::CONTENT_TYPE, "text/plain")
+                        .header(header::CONTENT_TYPE, content_type)
                         .body(Body::from(writer))
                         .map_err(ServeError::from)
                 })
@@ -134,3 +148,41 @@ impl Error for ServeError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{Future, Stream};
+
+    fn get(accept: Option<&str>) -> Response<Body> {
+        let mut req = Request::get("/metrics");
+        if let Some(accept) = accept {
+            req.header(header::ACCEPT, accept);
+        }
+        Serve::new(())
+            .call(req.body(Body::empty()).unwrap())
+            .wait()
+            .unwrap()
+    }
+
+    fn body(rsp: Response<Body>) -> String {
+        let body = rsp.into_body().concat2().wait().unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn negotiates_open_metrics() {
+        let rsp = get(Some(
+            "application/openmetrics-text; version=1.0.0,text/plain;q=0.5",
+        ));
+        assert_eq!(
+            rsp.headers()[header::CONTENT_TYPE],
+            OPEN_METRICS_CONTENT_TYPE
+        );
+        assert_eq!(body(rsp), "# EOF\n");
+
+        let rsp = get(None);
+        assert_eq!(rsp.headers()[header::CONTENT_TYPE], "text/plain");
+        assert_eq!(body(rsp), "");
+    }
+}
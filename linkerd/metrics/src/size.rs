@@ -0,0 +1,39 @@
+use super::histogram::{Bounds, Bucket, Histogram};
+
+/// The maximum value (inclusive) for each size bucket in bytes.
+pub const BOUNDS: &Bounds = &Bounds(&[
+    Bucket::Le(64),
+    Bucket::Le(256),
+    Bucket::Le(1_024),
+    Bucket::Le(4_096),
+    Bucket::Le(16_384),
+    Bucket::Le(65_536),
+    Bucket::Le(262_144),
+    Bucket::Le(1_048_576),
+    Bucket::Le(4_194_304),
+    Bucket::Le(16_777_216),
+    // A final upper bound.
+    Bucket::Inf,
+]);
+
+/// A size in bytes.
+#[derive(Debug, Default, Clone)]
+pub struct Bytes(u64);
+
+impl Into<u64> for Bytes {
+    fn into(self) -> u64 {
+        self.0
+    }
+}
+
+impl From<u64> for Bytes {
+    fn from(n: u64) -> Self {
+        Bytes(n)
+    }
+}
+
+impl Default for Histogram<Bytes> {
+    fn default() -> Self {
+        Histogram::new(BOUNDS)
+    }
+}
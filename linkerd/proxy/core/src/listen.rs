@@ -1,5 +1,8 @@
-use futures::{try_ready, Future, Poll};
+use futures::{task::AtomicTask, try_ready, Future, Poll};
 use linkerd2_error::{Error, Never};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use tokio;
 use tower::Service;
 
@@ -30,6 +33,7 @@ pub trait Listen {
             listen: self,
             accept,
             executor: tokio::executor::DefaultExecutor::current(),
+            heartbeat: None,
         }
     }
 }
@@ -89,6 +93,24 @@ pub struct Serve<L, A, E> {
     listen: L,
     accept: A,
     executor: E,
+    heartbeat: Option<Heartbeat>,
+}
+
+/// Records the progress of an accept loop, so that a stalled loop can be
+/// detected by another task.
+#[derive(Clone, Debug, Default)]
+pub struct Heartbeat(Arc<Beats>);
+
+#[derive(Debug, Default)]
+struct Beats {
+    /// The last time the loop polled its listener.
+    last: Mutex<Option<Instant>>,
+    /// Set while the loop waits for a connection, i.e. while no connections
+    /// are pending.
+    parked: AtomicBool,
+    /// Set when the listener should be rebuilt.
+    recover: AtomicBool,
+    task: AtomicTask,
 }
 
 impl<L, A> Serve<L, A, tokio::executor::DefaultExecutor>
@@ -102,10 +124,25 @@ where
             listen: self.listen,
             accept: self.accept,
             executor,
+            heartbeat: self.heartbeat,
         }
     }
 }
 
+impl<L, A, E> Serve<L, A, E> {
+    /// Records the loop's progress in `heartbeat`.
+    pub fn with_heartbeat(self, heartbeat: Heartbeat) -> Self {
+        Self {
+            heartbeat: Some(heartbeat),
+            ..self
+        }
+    }
+
+    pub fn into_parts(self) -> (L, A, E) {
+        (self.listen, self.accept, self.executor)
+    }
+}
+
 impl<L, A, E> Future for Serve<L, A, E>
 where
     L: Listen,
@@ -117,11 +154,61 @@ where
     type Error = Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(ref heartbeat) = self.heartbeat {
+            heartbeat.polled();
+        }
         loop {
             try_ready!(self.accept.poll_ready());
-            let conn = try_ready!(self.listen.poll_accept().map_err(Into::into));
+            let poll = self.listen.poll_accept().map_err(Into::into);
+            if let Some(ref heartbeat) = self.heartbeat {
+                heartbeat.beat(poll.as_ref().map(|p| p.is_not_ready()).unwrap_or(false));
+            }
+            let conn = try_ready!(poll);
             let accept = self.accept.accept(conn).map_err(|e| match e {});
             self.executor.spawn(Box::new(accept)).map_err(Error::from)?;
         }
     }
 }
+
+// === impl Heartbeat ===
+
+impl Heartbeat {
+    /// Returns the last time the loop polled its listener.
+    pub fn last_beat(&self) -> Option<Instant> {
+        self.0.last.lock().ok().and_then(|last| *last)
+    }
+
+    /// Indicates whether the loop is waiting for a connection. If it isn't,
+    /// connections may be pending on its listener.
+    pub fn is_parked(&self) -> bool {
+        self.0.parked.load(Ordering::Acquire)
+    }
+
+    /// Wakes the loop, so that it beats if it's able to make progress.
+    pub fn nudge(&self) {
+        self.0.task.notify();
+    }
+
+    /// Asks the loop's owner to rebuild its listener.
+    pub fn recover(&self) {
+        self.0.recover.store(true, Ordering::Release);
+        self.nudge();
+    }
+
+    /// Returns true if the listener should be rebuilt.
+    pub fn take_recover(&self) -> bool {
+        self.0.recover.swap(false, Ordering::AcqRel)
+    }
+
+    fn polled(&self) {
+        self.0.task.register();
+        self.0.parked.store(false, Ordering::Release);
+    }
+
+    fn beat(&self, parked: bool) {
+        if let Ok(mut last) = self.0.last.lock() {
+            *last = Some(tokio::clock::now());
+        }
+        self.0.parked.store(parked, Ordering::Release);
+    }
+}
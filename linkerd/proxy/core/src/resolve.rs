@@ -1,4 +1,4 @@
-use futures::{Future, Poll};
+use futures::{try_ready, Future, Poll};
 use linkerd2_error::Error;
 use std::net::SocketAddr;
 
@@ -27,6 +27,21 @@ pub trait Resolution {
     type Error: Into<Error>;
 
     fn poll(&mut self) -> Poll<Update<Self::Endpoint>, Self::Error>;
+
+    /// Polls for the next update, tagged with its generation.
+    ///
+    /// A resolution that is re-established begins a new generation with a
+    /// `Reset` snapshot, so consumers may discard any update that is tagged
+    /// with an older generation than the latest snapshot they applied.
+    /// Resolutions that are never re-established have a single generation.
+    fn poll_tagged(&mut self) -> Poll<Tagged<Self::Endpoint>, Self::Error> {
+        let update = try_ready!(self.poll());
+        Ok(Tagged {
+            generation: 0,
+            update,
+        }
+        .into())
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -36,10 +51,19 @@ pub struct Service<S>(S);
 pub enum Update<T> {
     Add(Vec<(SocketAddr, T)>),
     Remove(Vec<SocketAddr>),
+    /// Replaces all endpoints with the given snapshot.
+    Reset(Vec<(SocketAddr, T)>),
     Empty,
     DoesNotExist,
 }
 
+/// An update, along with the generation of the resolution that produced it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tagged<T> {
+    pub generation: u64,
+    pub update: Update<T>,
+}
+
 // === impl Resolve ===
 
 impl<S, T, R> Resolve<T> for S
@@ -1,7 +1,9 @@
-use futures::{try_ready, Future, Poll};
+use futures::{try_ready, Async, Future, Poll};
 use linkerd2_error::Error;
 use linkerd2_io::{BoxedIo, Peek};
 use linkerd2_proxy_core as core;
+use std::time::Duration;
+use tokio::{clock, timer::Delay};
 
 /// A strategy for detecting values out of a client transport.
 pub trait Detect<T>: Clone {
@@ -15,6 +17,10 @@ pub trait Detect<T>: Clone {
     /// If the target could not be determined without peeking, then used the
     /// peeked prefix to determine the protocol.
     fn detect_peeked_prefix(&self, target: T, prefix: &[u8]) -> Self::Target;
+
+    /// If the client sent nothing before the detection timeout elapsed, then
+    /// determines the target without a prefix.
+    fn detect_timeout(&self, target: T) -> Self::Target;
 }
 
 #[derive(Debug, Clone)]
@@ -22,6 +28,7 @@ pub struct Accept<D, A> {
     detect: D,
     accept: A,
     peek_capacity: usize,
+    timeout: Option<Duration>,
 }
 
 pub enum AcceptFuture<T, D, A>
@@ -41,7 +48,7 @@ pub enum PeekAndDetect<T, D: Detect<T>> {
     // Waiting for accept to become ready.
     Detected(Option<(D::Target, BoxedIo)>),
     // Waiting for the prefix to be read.
-    Peek(Option<T>, Peek<BoxedIo>),
+    Peek(Option<T>, Option<Peek<BoxedIo>>, Option<Delay>),
 }
 
 impl<D, A> Accept<D, A> {
@@ -53,6 +60,7 @@ impl<D, A> Accept<D, A> {
             detect,
             accept,
             peek_capacity: Self::DEFAULT_CAPACITY,
+            timeout: None,
         }
     }
 
@@ -60,6 +68,13 @@ impl<D, A> Accept<D, A> {
         self.peek_capacity = capacity;
         self
     }
+
+    /// Stops waiting for a prefix once `timeout` has elapsed, so that clients
+    /// that never send data don't hold their connections open indefinitely.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
 }
 
 impl<T, D, A> tower::Service<(T, BoxedIo)> for Accept<D, A>
@@ -83,7 +98,8 @@ where
                 accept: self.accept.clone(),
                 inner: PeekAndDetect::Peek(
                     Some(target),
-                    Peek::with_capacity(self.peek_capacity, io),
+                    Some(Peek::with_capacity(self.peek_capacity, io)),
+                    self.timeout.map(|t| Delay::new(clock::now() + t)),
                 ),
             },
         }
@@ -107,13 +123,33 @@ where
                     ref mut accept,
                     ref mut inner,
                 } => match inner {
-                    PeekAndDetect::Peek(ref mut target, ref mut peek) => {
-                        let io = try_ready!(peek.poll().map_err(Error::from));
-                        let target = detect.detect_peeked_prefix(
-                            target.take().expect("polled after complete"),
-                            io.prefix().as_ref(),
-                        );
-                        *inner = PeekAndDetect::Detected(Some((target, BoxedIo::new(io))));
+                    PeekAndDetect::Peek(ref mut target, ref mut peek, ref mut timeout) => {
+                        let peeked = peek
+                            .as_mut()
+                            .expect("polled after complete")
+                            .poll()
+                            .map_err(Error::from)?;
+                        let detected = match peeked {
+                            Async::Ready(io) => {
+                                let target = detect.detect_peeked_prefix(
+                                    target.take().expect("polled after complete"),
+                                    io.prefix().as_ref(),
+                                );
+                                (target, BoxedIo::new(io))
+                            }
+                            Async::NotReady => {
+                                match timeout.as_mut().map(Future::poll) {
+                                    None | Some(Ok(Async::NotReady)) => return Ok(Async::NotReady),
+                                    // A failed timer is treated as elapsed.
+                                    Some(Ok(Async::Ready(()))) | Some(Err(_)) => {}
+                                }
+                                let target = detect
+                                    .detect_timeout(target.take().expect("polled after complete"));
+                                let io = peek.take().expect("polled after complete").into_io();
+                                (target, BoxedIo::new(io))
+                            }
+                        };
+                        *inner = PeekAndDetect::Detected(Some(detected));
                     }
                     PeekAndDetect::Detected(ref mut io) => {
                         try_ready!(accept.poll_ready().map_err(Into::into));
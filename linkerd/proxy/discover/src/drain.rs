@@ -0,0 +1,178 @@
+//! Holds draining endpoints' services unready.
+//!
+//! A draining endpoint's service stays in its balancer, so that its
+//! connections are kept, but it reports that it is not ready, so that the
+//! balancer sends it no new requests. Once the endpoint is restored, its
+//! service is notified and becomes ready again.
+
+use futures::{task::AtomicTask, try_ready, Async, Future, Poll};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// Controls whether an endpoint's service is held unready.
+#[derive(Clone, Debug, Default)]
+pub struct Gate(Arc<Inner>);
+
+/// An endpoint target along with the gate that controls its service.
+#[derive(Clone, Debug)]
+pub struct Target<T> {
+    pub target: T,
+    pub gate: Gate,
+}
+
+/// Builds `Service`s that are held unready while their gates are draining.
+#[derive(Clone, Debug)]
+pub struct MakeGated<M>(M);
+
+#[derive(Debug)]
+pub struct MakeFuture<F> {
+    inner: F,
+    gate: Option<Gate>,
+}
+
+#[derive(Debug)]
+pub struct Service<S> {
+    inner: S,
+    gate: Gate,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    draining: AtomicBool,
+    task: AtomicTask,
+}
+
+// === impl Gate ===
+
+impl Gate {
+    /// Returns true if the gate's service is held unready.
+    pub fn is_draining(&self) -> bool {
+        self.0.draining.load(Ordering::Acquire)
+    }
+
+    /// Holds or releases the gate's service, returning true if its state
+    /// changed.
+    pub(crate) fn set_draining(&self, draining: bool) -> bool {
+        let changed = self.0.draining.swap(draining, Ordering::AcqRel) != draining;
+        if changed && !draining {
+            self.0.task.notify();
+        }
+        changed
+    }
+
+    fn poll_open(&self) -> Async<()> {
+        if !self.is_draining() {
+            return Async::Ready(());
+        }
+
+        // The gate may have been released before the task was registered.
+        self.0.task.register();
+        if self.is_draining() {
+            Async::NotReady
+        } else {
+            Async::Ready(())
+        }
+    }
+}
+
+// === impl MakeGated ===
+
+impl<M> MakeGated<M> {
+    pub fn new(inner: M) -> Self {
+        MakeGated(inner)
+    }
+}
+
+impl<T, M> tower::Service<Target<T>> for MakeGated<M>
+where
+    M: tower::Service<T>,
+{
+    type Response = Service<M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.0.poll_ready()
+    }
+
+    fn call(&mut self, Target { target, gate }: Target<T>) -> Self::Future {
+        MakeFuture {
+            inner: self.0.call(target),
+            gate: Some(gate),
+        }
+    }
+}
+
+// === impl MakeFuture ===
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = Service<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        let gate = self.gate.take().expect("polled after ready");
+        Ok(Async::Ready(Service { inner, gate }))
+    }
+}
+
+// === impl Service ===
+
+impl<S, Req> tower::Service<Req> for Service<S>
+where
+    S: tower::Service<Req>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        if self.gate.poll_open().is_not_ready() {
+            return Ok(Async::NotReady);
+        }
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        self.inner.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use tower::Service as _;
+    use tower_util::service_fn;
+
+    #[test]
+    fn draining_services_are_unready_until_restored() {
+        future::lazy(|| {
+            let gate = Gate::default();
+            let mut make = MakeGated::new(service_fn(|()| {
+                future::ok::<_, ()>(service_fn(|()| future::ok::<_, ()>(())))
+            }));
+            let mut svc = make
+                .call(Target {
+                    target: (),
+                    gate: gate.clone(),
+                })
+                .wait()
+                .expect("make must succeed");
+            assert!(svc.poll_ready().unwrap().is_ready());
+
+            assert!(gate.set_draining(true));
+            assert!(!gate.set_draining(true), "the gate is already draining");
+            assert!(svc.poll_ready().unwrap().is_not_ready());
+
+            assert!(gate.set_draining(false));
+            assert!(svc.poll_ready().unwrap().is_ready());
+
+            Ok::<_, ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+}
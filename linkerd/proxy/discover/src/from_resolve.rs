@@ -1,26 +1,78 @@
-use futures::{try_ready, Async, Future, Poll};
-use indexmap::IndexSet;
-use linkerd2_proxy_core::resolve::{Resolution, Resolve, Update};
+use crate::drain::{Gate, Target};
+use crate::overrides::{Discovered, Overrides};
+use futures::{task::AtomicTask, try_ready, Async, Future, Poll};
+use indexmap::{IndexMap, IndexSet};
+use linkerd2_proxy_core::resolve::{Resolution, Resolve, Tagged, Update};
 use std::collections::VecDeque;
+use std::fmt;
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
 use tower::discover::Change;
+use tracing::{debug, trace};
 
 #[derive(Clone, Debug)]
 pub struct FromResolve<R> {
     resolve: R,
+    endpoints: Option<Endpoints>,
+    overrides: Option<Overrides>,
 }
 
 #[derive(Debug)]
 pub struct DiscoverFuture<F> {
     future: F,
+    observe: Option<Observe>,
 }
 
 /// Observes an `R`-typed resolution stream, using an `M`-typed endpoint stack to
 /// build a service for each endpoint.
+///
+/// Updates are applied in order and at most once per generation: updates
+/// from a generation older than the latest snapshot are discarded, and each
+/// snapshot is reconciled against the active endpoints, so that the balancer
+/// holds exactly the endpoints of the latest snapshot and the updates that
+/// followed it.
+///
+/// Each endpoint is yielded with a `Gate`, which holds its service unready
+/// while the endpoint is overridden as draining.
 pub struct Discover<R: Resolution> {
     resolution: R,
-    active: IndexSet<SocketAddr>,
-    pending: VecDeque<Change<SocketAddr, R::Endpoint>>,
+    /// The generation of the latest snapshot that was applied.
+    generation: Option<u64>,
+    active: IndexMap<SocketAddr, R::Endpoint>,
+    /// The gate of each active endpoint's service.
+    gates: IndexMap<SocketAddr, Gate>,
+    /// Registers each active endpoint with the overrides, if there are any.
+    discovered: IndexMap<SocketAddr, Discovered>,
+    pending: VecDeque<Change<SocketAddr, Target<R::Endpoint>>>,
+    /// The endpoints that have been inserted into the balancer.
+    applied: IndexSet<SocketAddr>,
+    observe: Option<Observe>,
+}
+
+/// A read-only view of the endpoints that are currently active for each
+/// resolved target.
+///
+/// A target may be resolved again while its previous resolution is still
+/// being dropped, so each resolution publishes its endpoints separately and
+/// removes only its own when it is dropped. The latest resolution's endpoints
+/// are reported for each target.
+#[derive(Clone, Debug, Default)]
+pub struct Endpoints(Arc<Mutex<Published>>);
+
+#[derive(Debug, Default)]
+struct Published {
+    next_instance: u64,
+    /// Each target's active endpoints, by the resolution that published them.
+    targets: IndexMap<String, IndexMap<u64, IndexSet<SocketAddr>>>,
+}
+
+/// Ties a resolution to its target's published endpoints and overrides.
+#[derive(Debug)]
+struct Observe {
+    target: String,
+    endpoints: Option<(Endpoints, u64)>,
+    overrides: Option<(Overrides, Arc<AtomicTask>)>,
+    overrides_version: Option<u64>,
 }
 
 // === impl FromResolve ===
@@ -30,12 +82,28 @@ impl<R> FromResolve<R> {
     where
         R: Resolve<T>,
     {
-        Self { resolve }
+        Self {
+            resolve,
+            endpoints: None,
+            overrides: None,
+        }
+    }
+
+    /// Publishes the active endpoints of each resolution into `endpoints`.
+    pub fn with_endpoints(self, endpoints: Option<Endpoints>) -> Self {
+        Self { endpoints, ..self }
+    }
+
+    /// Holds endpoints that are overridden as draining unready in each
+    /// resolution.
+    pub fn with_overrides(self, overrides: Option<Overrides>) -> Self {
+        Self { overrides, ..self }
     }
 }
 
 impl<T, R> tower::Service<T> for FromResolve<R>
 where
+    T: fmt::Display,
     R: Resolve<T> + Clone,
 {
     type Response = Discover<R::Resolution>;
@@ -49,8 +117,19 @@ where
 
     #[inline]
     fn call(&mut self, target: T) -> Self::Future {
+        let observe = if self.endpoints.is_some() || self.overrides.is_some() {
+            Some(Observe {
+                target: target.to_string(),
+                endpoints: self.endpoints.as_ref().map(|e| (e.clone(), e.instance())),
+                overrides: self.overrides.as_ref().map(|o| (o.clone(), o.watch())),
+                overrides_version: None,
+            })
+        } else {
+            None
+        };
         Self::Future {
             future: self.resolve.resolve(target),
+            observe,
         }
     }
 }
@@ -67,7 +146,9 @@ where
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         let resolution = try_ready!(self.future.poll());
-        Ok(Async::Ready(Discover::new(resolution)))
+        let mut discover = Discover::new(resolution);
+        discover.observe = self.observe.take();
+        Ok(Async::Ready(discover))
     }
 }
 
@@ -77,42 +158,478 @@ impl<R: Resolution> Discover<R> {
     pub fn new(resolution: R) -> Self {
         Self {
             resolution,
-            active: IndexSet::default(),
+            generation: None,
+            active: IndexMap::default(),
+            gates: IndexMap::default(),
+            discovered: IndexMap::default(),
             pending: VecDeque::new(),
+            applied: IndexSet::default(),
+            observe: None,
+        }
+    }
+
+    /// Returns true if the balancer holds exactly the active endpoints.
+    fn is_consistent(&self) -> bool {
+        self.applied.len() == self.active.len()
+            && self
+                .applied
+                .iter()
+                .all(|addr| self.active.contains_key(addr))
+    }
+
+    fn publish(&self) {
+        if let Some(Observe {
+            ref target,
+            endpoints: Some((ref endpoints, instance)),
+            ..
+        }) = self.observe
+        {
+            endpoints.set(target, instance, self.active.keys().cloned().collect());
+        }
+    }
+
+    /// Registers a newly-active endpoint with the overrides, so that it may
+    /// be drained.
+    fn discover_override(&mut self, addr: SocketAddr) {
+        if let Some(Observe {
+            ref target,
+            overrides: Some((ref overrides, _)),
+            ..
+        }) = self.observe
+        {
+            self.discovered
+                .insert(addr, overrides.discover(target, addr));
+        }
+    }
+
+    /// Holds the services of endpoints that have been overridden as draining
+    /// unready and releases those whose overrides have been cleared.
+    fn apply_overrides(&mut self) {
+        let draining = match self.observe {
+            Some(Observe {
+                ref target,
+                overrides: Some((ref overrides, ref task)),
+                ref mut overrides_version,
+                ..
+            }) => {
+                task.register();
+                let (version, draining) = overrides.draining(target);
+                if *overrides_version == Some(version) {
+                    return;
+                }
+                *overrides_version = Some(version);
+                draining
+            }
+            _ => return,
+        };
+
+        for (addr, gate) in self.gates.iter() {
+            let drain = draining.contains(addr);
+            if gate.set_draining(drain) {
+                if drain {
+                    debug!(%addr, "draining endpoint");
+                } else {
+                    debug!(%addr, "restoring endpoint");
+                }
+            }
+        }
+    }
+}
+
+impl<R: Resolution> Drop for Discover<R> {
+    fn drop(&mut self) {
+        if let Some(Observe {
+            ref target,
+            endpoints: Some((ref endpoints, instance)),
+            ..
+        }) = self.observe
+        {
+            endpoints.remove(target, instance);
         }
     }
 }
 
-impl<R: Resolution> tower::discover::Discover for Discover<R> {
+impl<R> Discover<R>
+where
+    R: Resolution,
+    R::Endpoint: Clone + PartialEq,
+{
+    /// Returns false if `generation` has been superseded by a snapshot.
+    fn is_current(&mut self, generation: u64, is_snapshot: bool) -> bool {
+        match self.generation {
+            Some(current) if generation < current => false,
+            Some(current) if generation == current || !is_snapshot => true,
+            _ => {
+                self.generation = Some(generation);
+                true
+            }
+        }
+    }
+
+    fn insert(&mut self, addr: SocketAddr, endpoint: R::Endpoint) {
+        if self.active.insert(addr, endpoint.clone()).is_none() {
+            self.discover_override(addr);
+        }
+        // A rebuilt endpoint's service gets a new gate, which is closed by
+        // `apply_overrides` if the endpoint is draining.
+        let gate = Gate::default();
+        self.gates.insert(addr, gate.clone());
+        self.pending.push_back(Change::Insert(
+            addr,
+            Target {
+                target: endpoint,
+                gate,
+            },
+        ));
+    }
+
+    fn remove(&mut self, addr: SocketAddr) {
+        if self.active.remove(&addr).is_some() {
+            self.gates.remove(&addr);
+            // Dropping the registration forgets any override once no other
+            // resolution discovers the endpoint.
+            self.discovered.remove(&addr);
+            self.pending.push_back(Change::Remove(addr));
+        }
+    }
+
+    /// Reconciles the active endpoints with a snapshot, removing endpoints
+    /// that are not in the snapshot and inserting those that are new or
+    /// have changed.
+    fn reset(&mut self, endpoints: Vec<(SocketAddr, R::Endpoint)>) {
+        let snapshot = endpoints.into_iter().collect::<IndexMap<_, _>>();
+        let removed = self
+            .active
+            .keys()
+            .filter(|addr| !snapshot.contains_key(*addr))
+            .cloned()
+            .collect::<Vec<_>>();
+        for addr in removed.into_iter() {
+            self.remove(addr);
+        }
+        for (addr, endpoint) in snapshot.into_iter() {
+            if self.active.get(&addr) != Some(&endpoint) {
+                self.insert(addr, endpoint);
+            }
+        }
+    }
+
+    fn apply(&mut self, update: Update<R::Endpoint>) {
+        match update {
+            Update::Add(endpoints) => {
+                for (addr, endpoint) in endpoints.into_iter() {
+                    self.insert(addr, endpoint);
+                }
+            }
+            Update::Remove(addrs) => {
+                for addr in addrs.into_iter() {
+                    self.remove(addr);
+                }
+            }
+            Update::Reset(endpoints) => self.reset(endpoints),
+            Update::DoesNotExist | Update::Empty => self.reset(Vec::new()),
+        }
+        self.publish();
+        // New endpoints may already be overridden as draining.
+        if let Some(ref mut observe) = self.observe {
+            observe.overrides_version = None;
+        }
+        self.apply_overrides();
+    }
+}
+
+impl<R> tower::discover::Discover for Discover<R>
+where
+    R: Resolution,
+    R::Endpoint: Clone + PartialEq,
+{
     type Key = SocketAddr;
-    type Service = R::Endpoint;
+    type Service = Target<R::Endpoint>;
     type Error = R::Error;
 
     fn poll(&mut self) -> Poll<Change<Self::Key, Self::Service>, Self::Error> {
+        self.apply_overrides();
+
         loop {
             if let Some(change) = self.pending.pop_front() {
+                match change {
+                    Change::Insert(addr, _) => {
+                        self.applied.insert(addr);
+                    }
+                    Change::Remove(addr) => {
+                        self.applied.remove(&addr);
+                    }
+                }
                 return Ok(change.into());
             }
+            debug_assert!(
+                self.is_consistent(),
+                "balancer endpoints must match the resolution"
+            );
 
-            match try_ready!(self.resolution.poll()) {
-                Update::Add(endpoints) => {
-                    for (addr, endpoint) in endpoints.into_iter() {
-                        self.active.insert(addr);
-                        self.pending.push_back(Change::Insert(addr, endpoint));
-                    }
+            let Tagged { generation, update } = try_ready!(self.resolution.poll_tagged());
+            let is_snapshot = match update {
+                Update::Add(_) | Update::Remove(_) => false,
+                Update::Reset(_) | Update::Empty | Update::DoesNotExist => true,
+            };
+            if !self.is_current(generation, is_snapshot) {
+                debug!(generation, "discarding update from a superseded generation");
+                continue;
+            }
+            trace!(generation, "applying update");
+            self.apply(update);
+        }
+    }
+}
+
+// === impl Endpoints ===
+
+impl Endpoints {
+    /// Returns each resolved target along with its active endpoints.
+    pub fn snapshot(&self) -> Vec<(String, Vec<SocketAddr>)> {
+        let published = self.0.lock().expect("endpoints poisoned");
+        published
+            .targets
+            .keys()
+            .map(|target| {
+                let addrs = published.latest(target).into_iter().flatten();
+                (target.clone(), addrs.cloned().collect())
+            })
+            .collect()
+    }
+
+    /// Returns the number of resolved targets and the total number of their
+    /// active endpoints.
+    pub fn counts(&self) -> (usize, usize) {
+        match self.0.lock() {
+            Ok(published) => (
+                published.targets.len(),
+                published
+                    .targets
+                    .keys()
+                    .filter_map(|target| published.latest(target))
+                    .map(|addrs| addrs.len())
+                    .sum(),
+            ),
+            Err(_) => (0, 0),
+        }
+    }
+
+    /// Identifies a new resolution, so that it publishes its endpoints
+    /// separately from other resolutions of the same target.
+    fn instance(&self) -> u64 {
+        match self.0.lock() {
+            Ok(mut published) => {
+                published.next_instance += 1;
+                published.next_instance
+            }
+            Err(_) => 0,
+        }
+    }
+
+    fn set(&self, target: &str, instance: u64, active: IndexSet<SocketAddr>) {
+        if let Ok(mut published) = self.0.lock() {
+            published
+                .targets
+                .entry(target.to_owned())
+                .or_insert_with(IndexMap::new)
+                .insert(instance, active);
+        }
+    }
+
+    fn remove(&self, target: &str, instance: u64) {
+        if let Ok(mut published) = self.0.lock() {
+            let unresolved = match published.targets.get_mut(target) {
+                Some(instances) => {
+                    instances.swap_remove(&instance);
+                    instances.is_empty()
                 }
-                Update::Remove(addrs) => {
-                    for addr in addrs.into_iter() {
-                        if self.active.remove(&addr) {
-                            self.pending.push_back(Change::Remove(addr));
-                        }
+                None => false,
+            };
+            if unresolved {
+                published.targets.swap_remove(target);
+            }
+        }
+    }
+}
+
+// === impl Published ===
+
+impl Published {
+    /// Returns the endpoints of the latest resolution of `target`.
+    fn latest(&self, target: &str) -> Option<&IndexSet<SocketAddr>> {
+        self.targets
+            .get(target)?
+            .iter()
+            .max_by_key(|(instance, _)| *instance)
+            .map(|(_, addrs)| addrs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use linkerd2_error::Error;
+    use tower::discover::Discover as _;
+
+    /// Yields each of its updates once, with the given generation.
+    struct MockResolution(VecDeque<Tagged<usize>>);
+
+    impl Resolution for MockResolution {
+        type Endpoint = usize;
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<Update<usize>, Self::Error> {
+            let Tagged { update, .. } = try_ready!(self.poll_tagged());
+            Ok(update.into())
+        }
+
+        fn poll_tagged(&mut self) -> Poll<Tagged<usize>, Self::Error> {
+            Ok(self
+                .0
+                .pop_front()
+                .map(Async::Ready)
+                .unwrap_or(Async::NotReady))
+        }
+    }
+
+    fn addr(n: u8) -> SocketAddr {
+        SocketAddr::from(([10, 1, 1, n], 8080))
+    }
+
+    fn tagged(generation: u64, update: Update<usize>) -> Tagged<usize> {
+        Tagged { generation, update }
+    }
+
+    /// Applies all pending changes to `balanced`, the endpoints that would
+    /// receive requests.
+    fn poll_changes(
+        discover: &mut Discover<MockResolution>,
+        balanced: &mut IndexMap<SocketAddr, usize>,
+    ) {
+        future::lazy(|| {
+            while let Async::Ready(change) = discover.poll().expect("discover must not fail") {
+                match change {
+                    Change::Insert(addr, ep) => {
+                        balanced.insert(addr, ep.target);
+                    }
+                    Change::Remove(addr) => {
+                        balanced.remove(&addr);
                     }
-                }
-                Update::DoesNotExist | Update::Empty => {
-                    self.pending
-                        .extend(self.active.drain(..).map(Change::Remove));
                 }
             }
-        }
+            Ok::<_, ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+
+    fn endpoints(eps: &[(SocketAddr, usize)]) -> IndexMap<SocketAddr, usize> {
+        eps.iter().cloned().collect()
+    }
+
+    #[test]
+    fn stale_updates_are_discarded_after_a_snapshot() {
+        let updates = vec![
+            tagged(1, Update::Reset(vec![(addr(1), 0), (addr(2), 0)])),
+            tagged(2, Update::Reset(vec![(addr(2), 0), (addr(3), 0)])),
+            // An update from the first generation arrives after the second
+            // generation's snapshot.
+            tagged(1, Update::Add(vec![(addr(4), 0)])),
+            tagged(1, Update::Remove(vec![addr(3)])),
+            tagged(2, Update::Add(vec![(addr(5), 0)])),
+        ];
+        let mut discover = Discover::new(MockResolution(updates.into()));
+        let mut balanced = IndexMap::new();
+
+        poll_changes(&mut discover, &mut balanced);
+        assert_eq!(
+            balanced,
+            endpoints(&[(addr(2), 0), (addr(3), 0), (addr(5), 0)]),
+            "the stale add must be ignored"
+        );
+    }
+
+    #[test]
+    fn snapshots_are_reconciled_exactly() {
+        let updates = vec![
+            tagged(1, Update::Add(vec![(addr(1), 0), (addr(2), 0)])),
+            tagged(1, Update::Add(vec![(addr(3), 0)])),
+            tagged(
+                2,
+                Update::Reset(vec![(addr(2), 0), (addr(3), 1), (addr(4), 0)]),
+            ),
+        ];
+        let mut discover = Discover::new(MockResolution(updates.into()));
+        let mut balanced = IndexMap::new();
+        poll_changes(&mut discover, &mut balanced);
+        assert_eq!(
+            balanced,
+            endpoints(&[(addr(2), 0), (addr(3), 1), (addr(4), 0)])
+        );
+
+        // Unchanged endpoints are not rebuilt.
+        discover
+            .resolution
+            .0
+            .push_back(tagged(3, Update::Reset(vec![(addr(2), 0), (addr(3), 1)])));
+        let mut changes = Vec::new();
+        future::lazy(|| {
+            while let Async::Ready(change) = discover.poll().expect("discover must not fail") {
+                changes.push(match change {
+                    Change::Insert(addr, _) => (addr, true),
+                    Change::Remove(addr) => (addr, false),
+                });
+            }
+            Ok::<_, ()>(())
+        })
+        .wait()
+        .unwrap();
+        assert_eq!(changes, vec![(addr(4), false)]);
+    }
+
+    #[test]
+    fn empty_resolutions_remove_all_endpoints() {
+        let updates = vec![
+            tagged(1, Update::Add(vec![(addr(1), 0), (addr(2), 0)])),
+            tagged(1, Update::DoesNotExist),
+        ];
+        let mut discover = Discover::new(MockResolution(updates.into()));
+        let mut balanced = IndexMap::new();
+        poll_changes(&mut discover, &mut balanced);
+        assert!(balanced.is_empty());
+    }
+
+    #[test]
+    fn rebuilt_resolutions_are_not_unpublished_by_previous_ones() {
+        let published = Endpoints::default();
+        let resolve = |eps: Vec<(SocketAddr, usize)>| {
+            let updates = vec![tagged(1, Update::Reset(eps))];
+            let mut discover = Discover::new(MockResolution(updates.into()));
+            discover.observe = Some(Observe {
+                target: "web.ns.svc.cluster.local:8080".to_owned(),
+                endpoints: Some((published.clone(), published.instance())),
+                overrides: None,
+                overrides_version: None,
+            });
+            poll_changes(&mut discover, &mut IndexMap::new());
+            discover
+        };
+
+        let previous = resolve(vec![(addr(1), 0)]);
+        // The target is resolved again before its previous resolution is
+        // dropped.
+        let rebuilt = resolve(vec![(addr(2), 0)]);
+        assert_eq!(published.snapshot()[0].1, vec![addr(2)]);
+
+        drop(previous);
+        assert_eq!(
+            published.snapshot(),
+            vec![("web.ns.svc.cluster.local:8080".to_owned(), vec![addr(2)])]
+        );
+
+        drop(rebuilt);
+        assert_eq!(published.counts(), (0, 0));
     }
 }
@@ -6,18 +6,26 @@ use std::fmt;
 use std::time::Duration;
 
 pub mod buffer;
+pub mod drain;
 pub mod from_resolve;
 pub mod make_endpoint;
+pub mod overrides;
 
 use self::buffer::Buffer;
+use self::drain::MakeGated;
+pub use self::from_resolve::Endpoints;
 use self::from_resolve::FromResolve;
 use self::make_endpoint::MakeEndpoint;
+pub use self::overrides::Overrides;
 
 #[derive(Clone, Debug)]
 pub struct Layer<T, R> {
     capacity: usize,
     watchdog: Duration,
     resolve: R,
+    endpoints: Option<Endpoints>,
+    overrides: Option<Overrides>,
+    max_concurrent_builds: Option<usize>,
     _marker: std::marker::PhantomData<fn(T)>,
 }
 
@@ -33,9 +41,38 @@ impl<T, R> Layer<T, R> {
             capacity,
             watchdog,
             resolve,
+            endpoints: None,
+            overrides: None,
+            max_concurrent_builds: None,
             _marker: std::marker::PhantomData,
         }
     }
+
+    /// Publishes the endpoints discovered for each target into `endpoints`.
+    pub fn with_endpoints(self, endpoints: Endpoints) -> Self {
+        Self {
+            endpoints: Some(endpoints),
+            ..self
+        }
+    }
+
+    /// Holds endpoints that are overridden as draining in `overrides`
+    /// unready, so that they receive no new requests.
+    pub fn with_overrides(self, overrides: Overrides) -> Self {
+        Self {
+            overrides: Some(overrides),
+            ..self
+        }
+    }
+
+    /// Builds at most `max_concurrent_builds` of each target's endpoint
+    /// services at once, if set.
+    pub fn with_max_concurrent_builds(self, max_concurrent_builds: Option<usize>) -> Self {
+        Self {
+            max_concurrent_builds,
+            ..self
+        }
+    }
 }
 
 impl<T, R, M> tower::layer::Layer<M> for Layer<T, R>
@@ -51,11 +88,14 @@ where
     M::Response: Send + 'static,
     M::Future: Send + 'static,
 {
-    type Service = Buffer<MakeEndpoint<FromResolve<R>, M>>;
+    type Service = Buffer<MakeEndpoint<FromResolve<R>, MakeGated<M>>>;
 
     fn layer(&self, make_endpoint: M) -> Self::Service {
-        let make_discover =
-            MakeEndpoint::new(make_endpoint, FromResolve::new(self.resolve.clone()));
+        let from_resolve = FromResolve::new(self.resolve.clone())
+            .with_endpoints(self.endpoints.clone())
+            .with_overrides(self.overrides.clone());
+        let make_discover = MakeEndpoint::new(MakeGated::new(make_endpoint), from_resolve)
+            .with_max_concurrent_builds(self.max_concurrent_builds);
         Buffer::new(self.capacity, self.watchdog, make_discover)
     }
 }
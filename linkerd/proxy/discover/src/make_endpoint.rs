@@ -1,6 +1,7 @@
-use futures::{stream::FuturesUnordered, try_ready, Async, Future, Poll, Stream};
+use futures::{stream::FuturesUnordered, task, try_ready, Async, Future, Poll, Stream};
 use indexmap::IndexMap;
 use linkerd2_error::Error;
+use std::collections::VecDeque;
 use std::hash::Hash;
 use tokio::sync::oneshot;
 use tower::discover::{self, Change};
@@ -9,21 +10,32 @@ use tower::discover::{self, Change};
 pub struct MakeEndpoint<D, E> {
     make_discover: D,
     make_endpoint: E,
+    max_concurrent_builds: Option<usize>,
 }
 
 #[derive(Debug)]
 pub struct DiscoverFuture<F, M> {
     future: F,
     make_endpoint: Option<M>,
+    max_concurrent_builds: Option<usize>,
 }
 
 /// Observes an `R`-typed resolution stream, using an `M`-typed endpoint stack to
 /// build a service for each endpoint.
+///
+/// If `max_concurrent_builds` is set, at most that many endpoint services are
+/// built at once, and at most that many are started each time the discover
+/// is polled; further endpoints are queued until they can be built. This
+/// prevents a large set of endpoints from being built all at once.
 pub struct Discover<D: discover::Discover, E: tower::Service<D::Service>> {
     discover: D,
     make_endpoint: E,
     make_futures: MakeFutures<D::Key, E::Future>,
     pending_removals: Vec<D::Key>,
+    pending_inserts: VecDeque<(D::Key, D::Service)>,
+    max_concurrent_builds: Option<usize>,
+    /// The number of builds started since the discover last yielded.
+    started: usize,
 }
 
 struct MakeFutures<K, F> {
@@ -57,6 +69,15 @@ impl<D, E> MakeEndpoint<D, E> {
         Self {
             make_discover,
             make_endpoint,
+            max_concurrent_builds: None,
+        }
+    }
+
+    /// Limits the number of endpoint services each discover builds at once.
+    pub fn with_max_concurrent_builds(self, max_concurrent_builds: Option<usize>) -> Self {
+        Self {
+            max_concurrent_builds,
+            ..self
         }
     }
 }
@@ -85,6 +106,7 @@ where
         DiscoverFuture {
             future,
             make_endpoint: Some(self.make_endpoint.clone()),
+            max_concurrent_builds: self.max_concurrent_builds,
         }
     }
 }
@@ -106,7 +128,9 @@ where
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         let resolution = try_ready!(self.future.poll());
         let make_endpoint = self.make_endpoint.take().expect("polled after ready");
-        Ok(Async::Ready(Discover::new(resolution, make_endpoint)))
+        let discover = Discover::new(resolution, make_endpoint)
+            .with_max_concurrent_builds(self.max_concurrent_builds);
+        Ok(Async::Ready(discover))
     }
 }
 
@@ -126,6 +150,16 @@ where
             make_endpoint,
             make_futures: MakeFutures::new(),
             pending_removals: Vec::new(),
+            pending_inserts: VecDeque::new(),
+            max_concurrent_builds: None,
+            started: 0,
+        }
+    }
+
+    pub fn with_max_concurrent_builds(self, max_concurrent_builds: Option<usize>) -> Self {
+        Self {
+            max_concurrent_builds,
+            ..self
         }
     }
 }
@@ -147,10 +181,23 @@ where
             return Ok(Async::Ready(Change::Remove(key)));
         }
 
+        self.start_pending_inserts()?;
+
         if let Async::Ready(Some((key, svc))) = self.make_futures.poll().map_err(Into::into)? {
             return Ok(Async::Ready(Change::Insert(key, svc)));
         }
 
+        // If this poll started as many builds as it may, queued endpoints are
+        // started when the discover is polled again.
+        let exhausted = self
+            .max_concurrent_builds
+            .map(|max| self.started >= max)
+            .unwrap_or(false);
+        self.started = 0;
+        if exhausted && !self.pending_inserts.is_empty() {
+            task::current().notify();
+        }
+
         Ok(Async::NotReady)
     }
 }
@@ -178,16 +225,53 @@ where
             match try_ready!(self.discover.poll().map_err(Into::into)) {
                 Change::Insert(key, target) => {
                     // Start building the service and continue. If a pending
-                    // service exists for this addr, it will be canceled.
-                    let fut = self.make_endpoint.call(target);
-                    self.make_futures.push(key, fut);
+                    // service exists for this addr, it will be canceled. If
+                    // too many services are being built, the endpoint is
+                    // queued until one completes.
+                    self.pending_inserts.retain(|(k, _)| *k != key);
+                    if self.can_start() {
+                        self.start(key, target);
+                    } else {
+                        self.pending_inserts.push_back((key, target));
+                    }
                 }
                 Change::Remove(key) => {
+                    self.pending_inserts.retain(|(k, _)| *k != key);
                     self.pending_removals.push(key);
                 }
             }
         }
     }
+
+    /// Starts building queued endpoints' services, while more may be built.
+    fn start_pending_inserts(&mut self) -> Result<(), Error> {
+        while !self.pending_inserts.is_empty() && self.can_start() {
+            if self
+                .make_endpoint
+                .poll_ready()
+                .map_err(Into::into)?
+                .is_not_ready()
+            {
+                return Ok(());
+            }
+            let (key, target) = self.pending_inserts.pop_front().expect("must not be empty");
+            self.start(key, target);
+        }
+        Ok(())
+    }
+
+    fn can_start(&self) -> bool {
+        match self.max_concurrent_builds {
+            Some(max) => self.make_futures.futures.len() < max && self.started < max,
+            None => true,
+        }
+    }
+
+    fn start(&mut self, key: D::Key, target: D::Service) {
+        let fut = self.make_endpoint.call(target);
+        self.make_futures.push(key, fut);
+        self.started += 1;
+    }
 }
 
 // === impl MakeFutures ===
@@ -523,6 +607,76 @@ mod tests {
         });
     }
 
+    #[test]
+    fn builds_are_limited() {
+        with_task(move || {
+            let (mut tx, reso_rx) = mpsc::channel(5);
+            let (makes, mut make_rxs): (Vec<_>, Vec<_>) =
+                (0..5).map(|_| oneshot::channel::<()>()).unzip();
+            // Services are built in the order their endpoints are inserted.
+            make_rxs.reverse();
+            let mut discover =
+                Discover::new(Dx(reso_rx), Svc(make_rxs)).with_max_concurrent_builds(Some(2));
+
+            for port in 0..5 {
+                let addr = SocketAddr::from(([127, 0, 0, 1], port));
+                tx.try_send(Change::Insert(addr, ())).ok().unwrap();
+            }
+            assert!(
+                discover.poll().expect("discover can't fail").is_not_ready(),
+                "ready without service being made"
+            );
+            assert_eq!(discover.make_futures.futures.len(), 2, "too many builds");
+            assert_eq!(
+                discover.pending_inserts.len(),
+                3,
+                "endpoints must be queued"
+            );
+
+            // As each build completes, another is started.
+            for (port, make) in makes.into_iter().enumerate() {
+                make.send(()).unwrap();
+                match discover.poll().expect("discover can't fail") {
+                    Async::Ready(Change::Insert(addr, ())) => assert_eq!(addr.port(), port as u16),
+                    _ => panic!("expected insert"),
+                }
+                assert!(discover.poll().expect("discover can't fail").is_not_ready());
+                assert!(discover.make_futures.futures.len() <= 2, "too many builds");
+            }
+            assert!(discover.pending_inserts.is_empty());
+        });
+    }
+
+    #[test]
+    fn builds_are_started_incrementally() {
+        with_task(move || {
+            let (mut tx, reso_rx) = mpsc::channel(5);
+            let mut discover =
+                Discover::new(Dx(reso_rx), service_fn(|()| future::ok::<(), Error>(())))
+                    .with_max_concurrent_builds(Some(2));
+
+            for port in 0..5 {
+                let addr = SocketAddr::from(([127, 0, 0, 1], port));
+                tx.try_send(Change::Insert(addr, ())).ok().unwrap();
+            }
+
+            // Even though each build completes immediately, at most two are
+            // started before the discover yields.
+            let mut batches = Vec::new();
+            for _ in 0..3 {
+                let mut inserts = 0;
+                while let Async::Ready(change) = discover.poll().expect("discover can't fail") {
+                    match change {
+                        Change::Insert(..) => inserts += 1,
+                        Change::Remove(..) => panic!("unexpected remove"),
+                    }
+                }
+                batches.push(inserts);
+            }
+            assert_eq!(batches, vec![2, 2, 1]);
+        });
+    }
+
     fn with_task<F: FnOnce() -> U, U>(f: F) -> U {
         future::lazy(|| Ok::<_, ()>(f())).wait().unwrap()
     }
@@ -0,0 +1,433 @@
+//! Operator-driven overrides of discovered endpoints' states.
+//!
+//! An operator may know that an endpoint is unhealthy before discovery or
+//! failure accrual react. Endpoints that are marked as draining are held
+//! unready in their balancer, keeping their connections, until the override
+//! is cleared or the endpoint is removed by discovery, at which point the
+//! override is forgotten.
+//!
+//! Only endpoints that are currently discovered may be drained, so that
+//! overrides for addresses that discovery never returns can't fill the
+//! registry.
+
+use futures::task::AtomicTask;
+use indexmap::{IndexMap, IndexSet};
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, Weak};
+
+/// The maximum number of endpoints that may be draining at once, by default.
+const DEFAULT_CAPACITY: usize = 100;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum State {
+    /// The endpoint receives requests as discovered.
+    Active,
+    /// The endpoint receives no new requests.
+    Draining,
+}
+
+/// A bounded registry of endpoints that have been marked as draining.
+#[derive(Clone, Debug)]
+pub struct Overrides(Arc<Mutex<Inner>>);
+
+/// Registers an endpoint as discovered until it is dropped.
+#[derive(Debug)]
+pub struct Discovered {
+    overrides: Overrides,
+    key: (String, SocketAddr),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SetError {
+    /// The endpoint is not currently discovered for its target.
+    NotDiscovered,
+    /// The registry already holds as many draining endpoints as it may.
+    CapacityExceeded(usize),
+}
+
+#[derive(Debug)]
+struct Inner {
+    capacity: usize,
+    draining: IndexSet<(String, SocketAddr)>,
+    /// The number of resolutions that currently discover each endpoint.
+    discovered: IndexMap<(String, SocketAddr), usize>,
+    /// Incremented whenever `draining` changes so that watchers need only
+    /// check for overrides when they may have changed.
+    version: u64,
+    watchers: Vec<Weak<AtomicTask>>,
+}
+
+// === impl Overrides ===
+
+impl Overrides {
+    pub fn new(capacity: usize) -> Self {
+        Overrides(Arc::new(Mutex::new(Inner {
+            capacity,
+            draining: IndexSet::new(),
+            discovered: IndexMap::new(),
+            version: 0,
+            watchers: Vec::new(),
+        })))
+    }
+
+    /// Sets the state of the `addr` endpoint of `target`.
+    ///
+    /// Fails if the endpoint would be draining but it is not discovered or
+    /// the registry is full.
+    pub fn set(&self, target: &str, addr: SocketAddr, state: State) -> Result<(), SetError> {
+        let mut inner = self.0.lock().expect("overrides poisoned");
+        let key = (target.to_owned(), addr);
+        let changed = match state {
+            State::Active => inner.draining.remove(&key),
+            State::Draining => {
+                if !inner.discovered.contains_key(&key) {
+                    return Err(SetError::NotDiscovered);
+                }
+                if !inner.draining.contains(&key) && inner.draining.len() >= inner.capacity {
+                    return Err(SetError::CapacityExceeded(inner.capacity));
+                }
+                inner.draining.insert(key)
+            }
+        };
+
+        if changed {
+            inner.changed();
+        }
+
+        Ok(())
+    }
+
+    /// Registers the `addr` endpoint as discovered for `target` until the
+    /// returned handle is dropped.
+    ///
+    /// An endpoint may be discovered by several resolutions at once; its
+    /// override is forgotten once none of them discover it.
+    pub fn discover(&self, target: &str, addr: SocketAddr) -> Discovered {
+        let key = (target.to_owned(), addr);
+        if let Ok(mut inner) = self.0.lock() {
+            *inner.discovered.entry(key.clone()).or_insert(0) += 1;
+        }
+        Discovered {
+            overrides: self.clone(),
+            key,
+        }
+    }
+
+    /// Returns each draining endpoint along with its target.
+    pub fn snapshot(&self) -> Vec<(String, SocketAddr)> {
+        self.0
+            .lock()
+            .expect("overrides poisoned")
+            .draining
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Returns true if the `addr` endpoint of `target` is draining.
+    pub fn is_draining(&self, target: &str, addr: SocketAddr) -> bool {
+        self.0
+            .lock()
+            .map(|inner| inner.draining.contains(&(target.to_owned(), addr)))
+            .unwrap_or(false)
+    }
+
+    /// Returns a task that is notified whenever overrides change.
+    pub(crate) fn watch(&self) -> Arc<AtomicTask> {
+        let task = Arc::new(AtomicTask::new());
+        if let Ok(mut inner) = self.0.lock() {
+            inner.watchers.push(Arc::downgrade(&task));
+        }
+        task
+    }
+
+    /// Returns the current version of the overrides and the draining
+    /// endpoints of `target`.
+    pub(crate) fn draining(&self, target: &str) -> (u64, IndexSet<SocketAddr>) {
+        let inner = self.0.lock().expect("overrides poisoned");
+        let addrs = inner
+            .draining
+            .iter()
+            .filter(|(t, _)| t == target)
+            .map(|(_, addr)| *addr)
+            .collect();
+        (inner.version, addrs)
+    }
+}
+
+impl Default for Overrides {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+// === impl Discovered ===
+
+impl Drop for Discovered {
+    fn drop(&mut self) {
+        if let Ok(mut inner) = self.overrides.0.lock() {
+            let remaining = match inner.discovered.get_mut(&self.key) {
+                Some(count) => {
+                    *count -= 1;
+                    *count
+                }
+                None => return,
+            };
+            if remaining == 0 {
+                // The endpoint is no longer discovered, so its override is
+                // forgotten.
+                inner.discovered.swap_remove(&self.key);
+                if inner.draining.remove(&self.key) {
+                    inner.version += 1;
+                }
+            }
+        }
+    }
+}
+
+// === impl Inner ===
+
+impl Inner {
+    /// Notifies watchers that overrides have changed.
+    fn changed(&mut self) {
+        self.version += 1;
+        self.watchers.retain(|w| match w.upgrade() {
+            Some(task) => {
+                task.notify();
+                true
+            }
+            None => false,
+        });
+    }
+}
+
+// === impl SetError ===
+
+impl fmt::Display for SetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SetError::NotDiscovered => write!(f, "endpoint is not discovered"),
+            SetError::CapacityExceeded(max) => {
+                write!(f, "at most {} endpoints may be draining", max)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SetError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drain::Gate;
+    use crate::from_resolve::FromResolve;
+    use futures::{future, Async, Future, Poll};
+    use linkerd2_error::Error;
+    use linkerd2_proxy_core::resolve::{Resolution, Update};
+    use std::collections::VecDeque;
+    use tower::discover::{Change, Discover};
+
+    const TARGET: &str = "web.example.com:8080";
+
+    struct MockResolve(Option<MockResolution>);
+
+    struct MockResolution(VecDeque<Update<()>>);
+
+    impl tower::Service<&'static str> for MockResolve {
+        type Response = MockResolution;
+        type Error = Error;
+        type Future = future::FutureResult<MockResolution, Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: &'static str) -> Self::Future {
+            future::ok(self.0.take().expect("resolved more than once"))
+        }
+    }
+
+    impl Resolution for MockResolution {
+        type Endpoint = ();
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<Update<()>, Self::Error> {
+            Ok(self
+                .0
+                .pop_front()
+                .map(Async::Ready)
+                .unwrap_or(Async::NotReady))
+        }
+    }
+
+    fn addrs(addrs: &[SocketAddr]) -> IndexSet<SocketAddr> {
+        addrs.iter().cloned().collect()
+    }
+
+    /// Applies all pending changes to `balanced`, the gates of the endpoints
+    /// in the balancer.
+    fn poll_changes<D>(discover: &mut D, balanced: &mut IndexMap<SocketAddr, Gate>)
+    where
+        D: Discover<Key = SocketAddr, Service = crate::drain::Target<()>>,
+        D::Error: fmt::Debug,
+    {
+        while let Async::Ready(change) = discover.poll().expect("discover must not fail") {
+            match change {
+                Change::Insert(addr, target) => {
+                    balanced.insert(addr, target.gate);
+                }
+                Change::Remove(addr) => {
+                    balanced.remove(&addr);
+                }
+            }
+        }
+    }
+
+    /// Returns the endpoints that would receive requests.
+    fn ready(balanced: &IndexMap<SocketAddr, Gate>) -> IndexSet<SocketAddr> {
+        balanced
+            .iter()
+            .filter(|(_, gate)| !gate.is_draining())
+            .map(|(addr, _)| *addr)
+            .collect()
+    }
+
+    #[test]
+    fn draining_endpoints_receive_no_requests_until_restored() {
+        let addr0 = SocketAddr::from(([10, 1, 1, 1], 8080));
+        let addr1 = SocketAddr::from(([10, 1, 1, 2], 8080));
+        let overrides = Overrides::default();
+
+        let resolution = MockResolution(vec![Update::Add(vec![(addr0, ()), (addr1, ())])].into());
+        let mut make_discover =
+            FromResolve::new(MockResolve(Some(resolution))).with_overrides(Some(overrides.clone()));
+
+        future::lazy(move || {
+            let mut discover = tower::Service::call(&mut make_discover, TARGET)
+                .wait()
+                .expect("resolution must succeed");
+            let mut balanced = IndexMap::new();
+
+            poll_changes(&mut discover, &mut balanced);
+            assert_eq!(ready(&balanced), addrs(&[addr0, addr1]));
+
+            overrides
+                .set(TARGET, addr1, State::Draining)
+                .expect("endpoint must be drained");
+            poll_changes(&mut discover, &mut balanced);
+            assert_eq!(
+                balanced.keys().cloned().collect::<IndexSet<_>>(),
+                addrs(&[addr0, addr1]),
+                "the draining endpoint must be kept in the balancer"
+            );
+            assert_eq!(
+                ready(&balanced),
+                addrs(&[addr0]),
+                "all requests must be sent to the active endpoint"
+            );
+            assert_eq!(overrides.snapshot(), vec![(TARGET.to_owned(), addr1)]);
+
+            overrides
+                .set(TARGET, addr1, State::Active)
+                .expect("endpoint must be restored");
+            poll_changes(&mut discover, &mut balanced);
+            assert_eq!(ready(&balanced), addrs(&[addr0, addr1]));
+            assert!(overrides.snapshot().is_empty());
+
+            Ok::<_, ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+
+    #[test]
+    fn overrides_are_forgotten_when_endpoints_are_removed() {
+        let addr0 = SocketAddr::from(([10, 1, 1, 1], 8080));
+        let overrides = Overrides::default();
+
+        let updates = vec![
+            Update::Add(vec![(addr0, ())]),
+            Update::Remove(vec![addr0]),
+            Update::Add(vec![(addr0, ())]),
+        ];
+        let mut make_discover = FromResolve::new(MockResolve(Some(MockResolution(updates.into()))))
+            .with_overrides(Some(overrides.clone()));
+
+        future::lazy(move || {
+            let mut discover = tower::Service::call(&mut make_discover, TARGET)
+                .wait()
+                .expect("resolution must succeed");
+            let mut balanced = IndexMap::new();
+
+            // Only apply the first update.
+            match discover.poll().expect("discover must not fail") {
+                Async::Ready(Change::Insert(addr, target)) => {
+                    balanced.insert(addr, target.gate);
+                }
+                _ => panic!("expected insert"),
+            }
+            overrides
+                .set(TARGET, addr0, State::Draining)
+                .expect("endpoint must be drained");
+
+            poll_changes(&mut discover, &mut balanced);
+            assert!(overrides.snapshot().is_empty());
+            assert_eq!(
+                ready(&balanced),
+                addrs(&[addr0]),
+                "a rediscovered endpoint must not be draining"
+            );
+
+            drop(discover);
+            assert_eq!(
+                overrides.set(TARGET, addr0, State::Draining),
+                Err(SetError::NotDiscovered),
+                "endpoints are forgotten when discovery is dropped"
+            );
+
+            Ok::<_, ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+
+    #[test]
+    fn only_discovered_endpoints_may_be_drained() {
+        let overrides = Overrides::default();
+        let addr0 = SocketAddr::from(([10, 1, 1, 1], 8080));
+
+        assert_eq!(
+            overrides.set(TARGET, addr0, State::Draining),
+            Err(SetError::NotDiscovered)
+        );
+        assert!(overrides.snapshot().is_empty());
+
+        // An endpoint stays discovered while any resolution discovers it.
+        let discovered0 = overrides.discover(TARGET, addr0);
+        let discovered1 = overrides.discover(TARGET, addr0);
+        assert!(overrides.set(TARGET, addr0, State::Draining).is_ok());
+        drop(discovered0);
+        assert!(overrides.is_draining(TARGET, addr0));
+        drop(discovered1);
+        assert!(!overrides.is_draining(TARGET, addr0));
+    }
+
+    #[test]
+    fn registry_is_bounded() {
+        let overrides = Overrides::new(1);
+        let addr0 = SocketAddr::from(([10, 1, 1, 1], 8080));
+        let addr1 = SocketAddr::from(([10, 1, 1, 2], 8080));
+        let _discovered0 = overrides.discover(TARGET, addr0);
+        let _discovered1 = overrides.discover(TARGET, addr1);
+
+        assert!(overrides.set(TARGET, addr0, State::Draining).is_ok());
+        assert!(overrides.set(TARGET, addr0, State::Draining).is_ok());
+        assert_eq!(
+            overrides.set(TARGET, addr1, State::Draining),
+            Err(SetError::CapacityExceeded(1))
+        );
+        assert!(overrides.set(TARGET, addr0, State::Active).is_ok());
+        assert!(overrides.set(TARGET, addr1, State::Draining).is_ok());
+    }
+}
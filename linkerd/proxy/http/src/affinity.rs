@@ -0,0 +1,135 @@
+//! Pins requests to the endpoint named by an affinity cookie.
+//!
+//! Routes that honor an affinity cookie mark each of their requests with the
+//! cookie's name. When a backend sets that cookie to its own address, the
+//! client echoes it on subsequent requests, so that they may be dispatched
+//! to the same endpoint rather than to one chosen by the load balancer.
+
+use futures::{try_ready, Future, Poll};
+use http::header::COOKIE;
+use linkerd2_stack::layer;
+use std::{net::SocketAddr, sync::Arc};
+use tracing::trace;
+
+/// Implemented by route targets whose requests may be pinned by a cookie.
+pub trait HasAffinityCookie {
+    fn affinity_cookie(&self) -> Option<&str>;
+}
+
+/// A request extension naming the cookie that may pin the request to an
+/// endpoint.
+#[derive(Clone, Debug)]
+pub struct Cookie(Arc<str>);
+
+/// Wraps an HTTP `Service` so that requests on targets that honor an
+/// affinity cookie are marked with a `Cookie` extension.
+#[derive(Clone, Debug)]
+pub struct Make<M>(M);
+
+pub struct MakeFuture<F> {
+    inner: F,
+    cookie: Option<Cookie>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Service<S> {
+    inner: S,
+    cookie: Option<Cookie>,
+}
+
+pub fn layer<M>() -> impl tower::layer::Layer<M, Service = Make<M>> + Copy {
+    layer::mk(Make)
+}
+
+/// Returns the endpoint address named by the request's affinity cookie, if
+/// its route honors one and the request carries it.
+///
+/// The cookie's value must be a socket address, i.e. `IP:PORT`.
+pub fn pinned_addr<B>(req: &http::Request<B>) -> Option<SocketAddr> {
+    let name = &req.extensions().get::<Cookie>()?.0;
+    let value = req
+        .headers()
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| {
+            let mut parts = pair.trim().splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(n), Some(v)) if n == &**name => Some(v.trim().trim_matches('"')),
+                _ => None,
+            }
+        })
+        .next()?;
+
+    match value.parse() {
+        Ok(addr) => Some(addr),
+        Err(_) => {
+            trace!(cookie = %name, %value, "ignoring invalid affinity cookie");
+            None
+        }
+    }
+}
+
+// === impl Make ===
+
+impl<T, M> tower::Service<T> for Make<M>
+where
+    T: HasAffinityCookie,
+    M: tower::Service<T>,
+{
+    type Response = Service<M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), M::Error> {
+        self.0.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let cookie = target.affinity_cookie().map(|name| Cookie(name.into()));
+        MakeFuture {
+            cookie,
+            inner: self.0.call(target),
+        }
+    }
+}
+
+// === impl MakeFuture ===
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = Service<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        let svc = Service {
+            inner,
+            cookie: self.cookie.clone(),
+        };
+        Ok(svc.into())
+    }
+}
+
+// === impl Service ===
+
+impl<S, B> tower::Service<http::Request<B>> for Service<S>
+where
+    S: tower::Service<http::Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), S::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut req: http::Request<B>) -> Self::Future {
+        if let Some(ref cookie) = self.cookie {
+            req.extensions_mut().insert(cookie.clone());
+        }
+
+        self.inner.call(req)
+    }
+}
@@ -0,0 +1,239 @@
+//! Steers retries away from the endpoint that just failed.
+//!
+//! Each balanced endpoint annotates its responses with a `Handle`. When a
+//! response is retried, the retry policy uses the handle to mark the
+//! endpoint as avoided, which makes it appear maximally loaded until the
+//! balancer dispatches its next request. Because the balancer compares two
+//! endpoints' loads before dispatching, that request--usually the
+//! retry--is sent to another endpoint whenever more than one is available.
+
+use futures::{try_ready, Async, Future, Poll};
+use http;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use tower_discover::Change;
+use tower_load::Load;
+
+/// Wraps each of a `Discover`'s endpoints so that they may be avoided.
+#[derive(Debug)]
+pub struct Discover<D> {
+    inner: D,
+    dispatches: Arc<AtomicUsize>,
+}
+
+#[derive(Debug)]
+pub struct Endpoint<S> {
+    inner: S,
+    handle: Handle,
+}
+
+/// A response extension that allows the endpoint that produced a response to
+/// be avoided.
+#[derive(Clone, Debug)]
+pub struct Handle {
+    /// The number of requests dispatched by the balancer.
+    dispatches: Arc<AtomicUsize>,
+    /// The endpoint is avoided while `dispatches` is less than this value.
+    avoid_until: Arc<AtomicUsize>,
+}
+
+pub struct ResponseFuture<F> {
+    inner: F,
+    handle: Handle,
+}
+
+// === impl Discover ===
+
+impl<D> Discover<D> {
+    pub fn new(inner: D) -> Self {
+        Self {
+            inner,
+            dispatches: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl<D: tower_discover::Discover> tower_discover::Discover for Discover<D> {
+    type Key = D::Key;
+    type Service = Endpoint<D::Service>;
+    type Error = D::Error;
+
+    fn poll(&mut self) -> Poll<Change<Self::Key, Self::Service>, Self::Error> {
+        let change = match try_ready!(self.inner.poll()) {
+            Change::Insert(key, inner) => {
+                let handle = Handle {
+                    dispatches: self.dispatches.clone(),
+                    avoid_until: Arc::new(AtomicUsize::new(0)),
+                };
+                Change::Insert(key, Endpoint { inner, handle })
+            }
+            Change::Remove(key) => Change::Remove(key),
+        };
+        Ok(Async::Ready(change))
+    }
+}
+
+// === impl Endpoint ===
+
+impl<S: Load> Load for Endpoint<S> {
+    type Metric = (bool, S::Metric);
+
+    fn load(&self) -> Self::Metric {
+        (self.handle.is_avoided(), self.inner.load())
+    }
+}
+
+impl<S, A, B> tower::Service<http::Request<A>> for Endpoint<S>
+where
+    S: tower::Service<http::Request<A>, Response = http::Response<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: http::Request<A>) -> Self::Future {
+        self.handle.dispatches.fetch_add(1, Ordering::AcqRel);
+        ResponseFuture {
+            inner: self.inner.call(req),
+            handle: self.handle.clone(),
+        }
+    }
+}
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+{
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut rsp = try_ready!(self.inner.poll());
+        rsp.extensions_mut().insert(self.handle.clone());
+        Ok(Async::Ready(rsp))
+    }
+}
+
+// === impl Handle ===
+
+impl Handle {
+    /// Avoids the endpoint until the balancer dispatches another request.
+    pub fn avoid(&self) {
+        let next = self.dispatches.load(Ordering::Acquire) + 1;
+        self.avoid_until.store(next, Ordering::Release);
+    }
+
+    fn is_avoided(&self) -> bool {
+        self.dispatches.load(Ordering::Acquire) < self.avoid_until.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use rand::{rngs::SmallRng, SeedableRng};
+    use std::collections::VecDeque;
+    use tower::Service;
+    use tower_balance::p2c::Balance;
+
+    /// An endpoint that responds with its own key.
+    struct Mock(usize);
+
+    struct MockDiscover(VecDeque<Change<usize, Mock>>);
+
+    impl tower::Service<http::Request<()>> for Mock {
+        type Response = http::Response<usize>;
+        type Error = crate::Error;
+        type Future = future::FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: http::Request<()>) -> Self::Future {
+            future::ok(http::Response::new(self.0))
+        }
+    }
+
+    impl Load for Mock {
+        type Metric = usize;
+
+        fn load(&self) -> usize {
+            0
+        }
+    }
+
+    impl tower_discover::Discover for MockDiscover {
+        type Key = usize;
+        type Service = Mock;
+        type Error = crate::Error;
+
+        fn poll(&mut self) -> Poll<Change<usize, Mock>, Self::Error> {
+            Ok(self
+                .0
+                .pop_front()
+                .map(Async::Ready)
+                .unwrap_or(Async::NotReady))
+        }
+    }
+
+    fn dispatch<S>(balance: &mut S) -> http::Response<usize>
+    where
+        S: tower::Service<http::Request<()>, Response = http::Response<usize>>,
+        S::Error: std::fmt::Debug,
+    {
+        future::poll_fn(|| balance.poll_ready())
+            .wait()
+            .expect("balancer must become ready");
+        balance
+            .call(http::Request::new(()))
+            .wait()
+            .expect("request must succeed")
+    }
+
+    #[test]
+    fn retries_avoid_the_failed_endpoint() {
+        let discover =
+            MockDiscover(vec![Change::Insert(0, Mock(0)), Change::Insert(1, Mock(1))].into());
+        let mut balance = Balance::new(Discover::new(discover), SmallRng::from_entropy());
+
+        for _ in 0..10 {
+            let failed = dispatch(&mut balance);
+            failed
+                .extensions()
+                .get::<Handle>()
+                .expect("responses must have an avoid handle")
+                .avoid();
+
+            let retried = dispatch(&mut balance);
+            assert_ne!(
+                failed.body(),
+                retried.body(),
+                "the retry must be sent to another endpoint"
+            );
+        }
+    }
+
+    #[test]
+    fn avoidance_ends_after_the_next_dispatch() {
+        let dispatches = Arc::new(AtomicUsize::new(0));
+        let handle = Handle {
+            dispatches: dispatches.clone(),
+            avoid_until: Arc::new(AtomicUsize::new(0)),
+        };
+        assert!(!handle.is_avoided());
+
+        handle.avoid();
+        assert!(handle.is_avoided());
+
+        dispatches.fetch_add(1, Ordering::AcqRel);
+        assert!(!handle.is_avoided());
+    }
+}
@@ -1,51 +1,106 @@
-use crate::Error;
+use crate::{avoid, dry_run, min_ready, Error};
 use futures::{try_ready, Async, Future, Poll};
 use http;
 use hyper::body::Payload;
 pub use hyper_balance::{PendingUntilFirstData, PendingUntilFirstDataBody};
-use rand::{rngs::SmallRng, SeedableRng};
+use indexmap::IndexMap;
+use linkerd2_addr::{Addr, NameAddr};
+use rand::rngs::SmallRng;
+use std::sync::Arc;
 use std::{marker::PhantomData, time::Duration};
 pub use tower_balance::p2c::Balance;
 use tower_discover::Discover;
 pub use tower_load::{Load, PeakEwmaDiscover};
+use tracing::debug;
+
+type Balanced<D, A> = min_ready::Service<
+    Balance<
+        dry_run::balance::Discover<
+            avoid::Discover<PeakEwmaDiscover<min_ready::Discover<D>, PendingUntilFirstData>>,
+        >,
+        http::Request<A>,
+    >,
+>;
+
+/// Parameterizes the peak-EWMA estimate of each endpoint's load.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Ewma {
+    /// The RTT assumed for an endpoint before any of its responses are seen.
+    pub default_rtt: Duration,
+    /// The time over which an endpoint's observed RTTs decay.
+    pub decay: Duration,
+}
 
 /// Configures a stack to resolve `T` typed targets to balance requests over
 /// `M`-typed endpoint stacks.
 #[derive(Debug)]
 pub struct Layer<A, B> {
-    decay: Duration,
-    default_rtt: Duration,
+    ewma: Ewma,
+    ewma_overrides: Arc<IndexMap<NameAddr, Ewma>>,
     rng: SmallRng,
+    min_ready: Option<min_ready::Config>,
+    dry_run: Option<dry_run::Metrics>,
     _marker: PhantomData<fn(A) -> B>,
 }
 
 /// Resolves `T` typed targets to balance requests over `M`-typed endpoint stacks.
 #[derive(Debug)]
 pub struct MakeSvc<M, A, B> {
-    decay: Duration,
-    default_rtt: Duration,
+    ewma: Ewma,
+    ewma_overrides: Arc<IndexMap<NameAddr, Ewma>>,
     inner: M,
     rng: SmallRng,
+    min_ready: Option<min_ready::Config>,
+    dry_run: Option<dry_run::Metrics>,
     _marker: PhantomData<fn(A) -> B>,
 }
 
 // === impl Layer ===
 
-pub fn layer<A, B>(default_rtt: Duration, decay: Duration) -> Layer<A, B> {
+/// Balances requests with the power of two choices, sampling endpoints with
+/// `rng`.
+pub fn layer<A, B>(default_rtt: Duration, decay: Duration, rng: SmallRng) -> Layer<A, B> {
     Layer {
-        decay,
-        default_rtt,
-        rng: SmallRng::from_entropy(),
+        ewma: Ewma { default_rtt, decay },
+        ewma_overrides: Arc::new(IndexMap::new()),
+        rng,
+        min_ready: None,
+        dry_run: None,
         _marker: PhantomData,
     }
 }
 
+impl<A, B> Layer<A, B> {
+    /// Holds each balancer until enough of its endpoints are ready, if
+    /// `min_ready` is set.
+    pub fn with_min_ready(self, min_ready: Option<min_ready::Config>) -> Self {
+        Self { min_ready, ..self }
+    }
+
+    /// Compares each balancer's choices with least-request's, if `dry_run`
+    /// is set.
+    pub fn with_dry_run(self, dry_run: Option<dry_run::Metrics>) -> Self {
+        Self { dry_run, ..self }
+    }
+
+    /// Balances each concrete destination in `ewma_overrides` with its own
+    /// EWMA parameters, rather than the defaults.
+    pub fn with_ewma_overrides(self, ewma_overrides: IndexMap<NameAddr, Ewma>) -> Self {
+        Self {
+            ewma_overrides: Arc::new(ewma_overrides),
+            ..self
+        }
+    }
+}
+
 impl<A, B> Clone for Layer<A, B> {
     fn clone(&self) -> Self {
         Self {
-            decay: self.decay,
-            default_rtt: self.default_rtt,
+            ewma: self.ewma,
+            ewma_overrides: self.ewma_overrides.clone(),
             rng: self.rng.clone(),
+            min_ready: self.min_ready,
+            dry_run: self.dry_run.clone(),
             _marker: PhantomData,
         }
     }
@@ -60,10 +115,12 @@ where
 
     fn layer(&self, inner: M) -> Self::Service {
         MakeSvc {
-            decay: self.decay,
-            default_rtt: self.default_rtt,
+            ewma: self.ewma,
+            ewma_overrides: self.ewma_overrides.clone(),
             inner,
             rng: self.rng.clone(),
+            min_ready: self.min_ready,
+            dry_run: self.dry_run.clone(),
             _marker: PhantomData,
         }
     }
@@ -74,17 +131,34 @@ where
 impl<M: Clone, A, B> Clone for MakeSvc<M, A, B> {
     fn clone(&self) -> Self {
         MakeSvc {
-            decay: self.decay,
-            default_rtt: self.default_rtt,
+            ewma: self.ewma,
+            ewma_overrides: self.ewma_overrides.clone(),
             inner: self.inner.clone(),
             rng: self.rng.clone(),
+            min_ready: self.min_ready,
+            dry_run: self.dry_run.clone(),
             _marker: PhantomData,
         }
     }
 }
 
+impl<M, A, B> MakeSvc<M, A, B> {
+    /// Returns the EWMA parameters for `target`'s balancer.
+    fn ewma(&self, target: &Addr) -> Ewma {
+        let ewma = target
+            .name_addr()
+            .and_then(|dst| self.ewma_overrides.get(dst));
+        if let Some(ewma) = ewma {
+            debug!(?ewma, "overriding EWMA parameters");
+            return *ewma;
+        }
+        self.ewma
+    }
+}
+
 impl<T, M, A, B> tower::Service<T> for MakeSvc<M, A, B>
 where
+    T: AsRef<Addr>,
     M: tower::Service<T>,
     M::Response: Discover,
     <M::Response as Discover>::Service:
@@ -92,10 +166,9 @@ where
     <<M::Response as Discover>::Service as tower::Service<http::Request<A>>>::Error: Into<Error>,
     A: Payload,
     B: Payload,
-    Balance<PeakEwmaDiscover<M::Response, PendingUntilFirstData>, http::Request<A>>:
-        tower::Service<http::Request<A>>,
+    Balanced<M::Response, A>: tower::Service<http::Request<A>>,
 {
-    type Response = Balance<PeakEwmaDiscover<M::Response, PendingUntilFirstData>, http::Request<A>>;
+    type Response = Balanced<M::Response, A>;
     type Error = M::Error;
     type Future = MakeSvc<M::Future, A, B>;
 
@@ -104,13 +177,16 @@ where
     }
 
     fn call(&mut self, target: T) -> Self::Future {
+        let ewma = self.ewma(target.as_ref());
         let inner = self.inner.call(target);
 
         MakeSvc {
-            decay: self.decay,
-            default_rtt: self.default_rtt,
+            ewma,
+            ewma_overrides: self.ewma_overrides.clone(),
             inner,
             rng: self.rng.clone(),
+            min_ready: self.min_ready,
+            dry_run: self.dry_run.clone(),
             _marker: PhantomData,
         }
     }
@@ -124,17 +200,255 @@ where
     <<F::Item as Discover>::Service as tower::Service<http::Request<A>>>::Error: Into<Error>,
     A: Payload,
     B: Payload,
-    Balance<PeakEwmaDiscover<F::Item, PendingUntilFirstData>, http::Request<A>>:
-        tower::Service<http::Request<A>>,
+    Balanced<F::Item, A>: tower::Service<http::Request<A>>,
 {
-    type Item = Balance<PeakEwmaDiscover<F::Item, PendingUntilFirstData>, http::Request<A>>;
+    type Item = Balanced<F::Item, A>;
     type Error = F::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        let discover = try_ready!(self.inner.poll());
+        let discover = min_ready::Discover::new(try_ready!(self.inner.poll()));
+        let hold = self
+            .min_ready
+            .and_then(|min_ready| min_ready.hold(&discover));
         let instrument = PendingUntilFirstData::default();
-        let loaded = PeakEwmaDiscover::new(discover, self.default_rtt, self.decay, instrument);
-        let balance = Balance::new(loaded, self.rng.clone());
-        Ok(Async::Ready(balance))
+        let Ewma { default_rtt, decay } = self.ewma;
+        let loaded = PeakEwmaDiscover::new(discover, default_rtt, decay, instrument);
+        let discover =
+            dry_run::balance::Discover::new(avoid::Discover::new(loaded), self.dry_run.clone());
+        let balance = Balance::new(discover, self.rng.clone());
+        Ok(Async::Ready(min_ready::Service::new(balance, hold)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use futures::sync::oneshot;
+    use hyper::Body;
+    use rand::SeedableRng;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+    use tower::layer::Layer as _;
+    use tower::Service as _;
+    use tower_discover::Change;
+
+    struct Target(Addr);
+
+    /// Never discovers any endpoints.
+    struct NoEndpoints;
+
+    struct Endpoint;
+
+    #[derive(Clone)]
+    struct MakeDiscover;
+
+    impl AsRef<Addr> for Target {
+        fn as_ref(&self) -> &Addr {
+            &self.0
+        }
+    }
+
+    impl Discover for NoEndpoints {
+        type Key = usize;
+        type Service = Endpoint;
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<Change<usize, Endpoint>, Self::Error> {
+            Ok(Async::NotReady)
+        }
+    }
+
+    impl tower::Service<http::Request<Body>> for Endpoint {
+        type Response = http::Response<Body>;
+        type Error = Error;
+        type Future = future::FutureResult<Self::Response, Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: http::Request<Body>) -> Self::Future {
+            future::ok(http::Response::new(Body::empty()))
+        }
+    }
+
+    impl tower::Service<Target> for MakeDiscover {
+        type Response = NoEndpoints;
+        type Error = Error;
+        type Future = future::FutureResult<NoEndpoints, Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: Target) -> Self::Future {
+            future::ok(NoEndpoints)
+        }
+    }
+
+    /// Each request that an endpoint has received, along with the endpoint's
+    /// key and a sender that completes the request.
+    type Calls = Arc<Mutex<Vec<(usize, oneshot::Sender<http::Response<Body>>)>>>;
+
+    /// Discovers the endpoint changes that a test pushes.
+    #[derive(Clone, Default)]
+    struct Changes(Arc<Mutex<VecDeque<Change<usize, PendingEndpoint>>>>);
+
+    /// Holds each request until the test completes it.
+    struct PendingEndpoint(usize, Calls);
+
+    impl Discover for Changes {
+        type Key = usize;
+        type Service = PendingEndpoint;
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<Change<usize, PendingEndpoint>, Self::Error> {
+            let change = self.0.lock().unwrap().pop_front();
+            Ok(change.map(Async::Ready).unwrap_or(Async::NotReady))
+        }
+    }
+
+    impl tower::Service<Target> for Changes {
+        type Response = Changes;
+        type Error = Error;
+        type Future = future::FutureResult<Changes, Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: Target) -> Self::Future {
+            future::ok(self.clone())
+        }
+    }
+
+    impl tower::Service<http::Request<Body>> for PendingEndpoint {
+        type Response = http::Response<Body>;
+        type Error = Error;
+        type Future = Box<dyn Future<Item = Self::Response, Error = Error> + Send>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: http::Request<Body>) -> Self::Future {
+            let (tx, rx) = oneshot::channel();
+            self.1.lock().unwrap().push((self.0, tx));
+            Box::new(rx.map_err(Into::into))
+        }
+    }
+
+    fn ewma_for(make: &mut MakeSvc<MakeDiscover, Body, Body>, dst: &str) -> Ewma {
+        let addr = Addr::from_str(dst).expect("address must be valid");
+        make.call(Target(addr)).ewma
+    }
+
+    #[test]
+    fn overridden_destinations_use_their_ewma() {
+        let default = Ewma {
+            default_rtt: Duration::from_millis(30),
+            decay: Duration::from_secs(10),
+        };
+        let slow = Ewma {
+            default_rtt: Duration::from_secs(1),
+            decay: Duration::from_secs(60),
+        };
+        let mut overrides = IndexMap::new();
+        overrides.insert(
+            NameAddr::from_str("slow.ns.svc.cluster.local:8080").expect("name must be valid"),
+            slow,
+        );
+
+        let mut make = layer::<Body, Body>(
+            default.default_rtt,
+            default.decay,
+            SmallRng::seed_from_u64(0),
+        )
+        .with_ewma_overrides(overrides)
+        .layer(MakeDiscover);
+
+        assert_eq!(ewma_for(&mut make, "slow.ns.svc.cluster.local:8080"), slow);
+        assert_eq!(
+            ewma_for(&mut make, "slow.ns.svc.cluster.local:9090"),
+            default,
+            "overrides apply to a single port"
+        );
+        assert_eq!(
+            ewma_for(&mut make, "web.ns.svc.cluster.local:8080"),
+            default
+        );
+        assert_eq!(ewma_for(&mut make, "10.1.1.1:8080"), default);
+    }
+
+    #[test]
+    fn drained_endpoints_complete_in_flight_requests() {
+        let changes = Changes::default();
+        let calls = Calls::default();
+        for key in 0..2 {
+            let endpoint = PendingEndpoint(key, calls.clone());
+            changes
+                .0
+                .lock()
+                .unwrap()
+                .push_back(Change::Insert(key, endpoint));
+        }
+
+        let mut make = layer::<Body, Body>(
+            Duration::from_millis(30),
+            Duration::from_secs(10),
+            SmallRng::seed_from_u64(0),
+        )
+        .layer(changes.clone());
+        let addr = Addr::from_str("web.ns.svc.cluster.local:8080").expect("address must be valid");
+        let mut balance = make
+            .call(Target(addr))
+            .wait()
+            .expect("balancer must be built");
+
+        future::lazy(move || {
+            let send = |balance: &mut Balanced<Changes, Body>| {
+                assert!(balance
+                    .poll_ready()
+                    .expect("balancer must not fail")
+                    .is_ready());
+                balance.call(http::Request::new(Body::empty()))
+            };
+
+            // Requests are balanced over both endpoints until one of them is
+            // dispatched to the endpoint that's drained.
+            let mut in_flight = Vec::new();
+            while !calls.lock().unwrap().iter().any(|(key, _)| *key == 0) {
+                in_flight.push(send(&mut balance));
+                assert!(in_flight.len() < 100, "endpoint 0 must receive a request");
+            }
+
+            changes.0.lock().unwrap().push_back(Change::Remove(0));
+            let (drained, active): (Vec<_>, Vec<_>) = calls
+                .lock()
+                .unwrap()
+                .drain(..)
+                .zip(in_flight)
+                .partition(|((key, _), _)| *key == 0);
+
+            for _ in 0..10 {
+                let _ = send(&mut balance);
+            }
+            assert!(
+                calls.lock().unwrap().iter().all(|(key, _)| *key == 1),
+                "the drained endpoint must not receive new requests"
+            );
+
+            // Requests that were in flight on the drained endpoint complete.
+            for ((_, tx), rsp) in drained.into_iter().chain(active) {
+                tx.send(http::Response::new(Body::empty()))
+                    .expect("request must still be in flight");
+                assert!(rsp.wait().is_ok(), "in-flight request must complete");
+            }
+
+            Ok::<_, ()>(())
+        })
+        .wait()
+        .unwrap();
     }
 }
@@ -8,13 +8,27 @@
 //!
 //! DNS TTLs are honored and the most recent value is added to each request's
 //! extensions.
+//!
+//! Refinements are shared by all services built by a layer. When a service is
+//! rebuilt for a name that was refined previously, the last-known refinement
+//! is served immediately while it is revalidated in the background.
+//!
+//! Refinements may be restored from a prior process, in which case they are
+//! used until they expire as if they had been refined by this one.
+//!
+//! At most `DEFAULT_MAX_REFINEMENTS` refinements are retained; once this
+//! capacity is reached, the least-recently-used refinement is evicted.
 
 use futures::{try_ready, Async, Future, Poll, Stream};
 use http;
 use linkerd2_addr::{Addr, NameAddr};
 use linkerd2_dns as dns;
-use linkerd2_error::Never;
-use std::time::Duration;
+use linkerd2_error::{contract, Error, Never};
+use linkerd2_metrics::{metrics, Counter, FmtMetrics};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio;
 use tokio::sync::{mpsc, oneshot};
 use tokio_timer::{clock, Delay, Timeout};
@@ -25,22 +39,52 @@ use tracing_futures::Instrument;
 /// response with no TTL).
 const DNS_ERROR_TTL: Duration = Duration::from_secs(3);
 
-#[derive(Debug, Clone)]
-pub struct Layer {
-    resolver: dns::Resolver,
-    timeout: Duration,
+const DEFAULT_MAX_REFINEMENTS: usize = 10_000;
+
+metrics! {
+    dns_canonicalize_revalidate_total: Counter {
+        "Total count of canonicalized names that were refined again in the background"
+    },
+    dns_canonicalize_name_change_total: Counter {
+        "Total count of canonicalized names that changed when revalidated"
+    }
+}
+
+/// Refines a name to its canonical, fully-qualified form.
+pub trait Refine {
+    type Future: Future<Item = dns::Refine, Error = dns::ResolveError>;
+
+    fn refine(&self, name: &dns::Name) -> Self::Future;
+}
+
+/// Notified with the previous canonical name when a name's refinement changes.
+#[derive(Clone)]
+pub struct Invalidate(Arc<dyn Fn(&NameAddr) + Send + Sync>);
+
+/// Counts background revalidations and name changes.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics(Arc<Mutex<Counts>>);
+
+#[derive(Debug, Default)]
+struct Counts {
+    revalidations: Counter,
+    name_changes: Counter,
+}
+
+#[derive(Clone, Debug)]
+pub struct Layer<R = dns::Resolver> {
+    config: Config<R>,
 }
 
 #[derive(Clone, Debug)]
-pub struct Stack<M> {
-    resolver: dns::Resolver,
+pub struct Stack<M, R = dns::Resolver> {
+    config: Config<R>,
     inner: M,
-    timeout: Duration,
 }
 
-pub struct MakeFuture<F> {
+pub struct MakeFuture<F, R = dns::Resolver> {
     inner: F,
-    task: Option<(NameAddr, dns::Resolver, Duration)>,
+    task: Option<(NameAddr, Config<R>)>,
 }
 
 pub struct Service<S> {
@@ -51,12 +95,41 @@ pub struct Service<S> {
     _tx_stop: oneshot::Sender<Never>,
 }
 
-struct Task {
+#[derive(Clone, Debug)]
+struct Config<R> {
+    resolver: R,
+    timeout: Duration,
+    freshness: Option<Duration>,
+    refinements: Refinements,
+    invalidate: Option<Invalidate>,
+    metrics: Metrics,
+}
+
+/// The most recent refinement of each original name.
+#[derive(Clone, Debug)]
+pub struct Refinements(Arc<Mutex<Lru>>);
+
+#[derive(Debug)]
+struct Lru {
+    capacity: usize,
+    by_name: HashMap<NameAddr, (Refined, u64)>,
+    /// Incremented each time a refinement is used, so that the
+    /// least-recently-used refinement may be evicted.
+    uses: u64,
+}
+
+#[derive(Clone, Debug)]
+struct Refined {
+    name: NameAddr,
+    /// The time after which the name should be revalidated.
+    fresh_until: Instant,
+}
+
+struct Task<R: Refine> {
     original: NameAddr,
     resolved: Cache,
-    resolver: dns::Resolver,
-    state: State,
-    timeout: Duration,
+    config: Config<R>,
+    state: State<R::Future>,
     tx: mpsc::Sender<NameAddr>,
     rx_stop: oneshot::Receiver<Never>,
 }
@@ -75,44 +148,89 @@ enum Cache {
     Resolved(NameAddr),
 }
 
-enum State {
+enum State<F> {
     Init,
-    Pending(Timeout<dns::RefineFuture>),
+    Pending(Timeout<F>),
     ValidUntil(Delay),
 }
 
 // === Layer ===
 
-// FIXME the resolver should be abstracted to a trait so that this can be tested
-// without a real DNS service.
 pub fn layer(resolver: dns::Resolver, timeout: Duration) -> Layer {
-    Layer { resolver, timeout }
+    Layer::new(resolver, timeout)
 }
 
-impl<M> tower::layer::Layer<M> for Layer
+impl<R: Refine + Clone> Layer<R> {
+    pub fn new(resolver: R, timeout: Duration) -> Self {
+        Self {
+            config: Config {
+                resolver,
+                timeout,
+                freshness: None,
+                refinements: Refinements::default(),
+                invalidate: None,
+                metrics: Metrics::default(),
+            },
+        }
+    }
+
+    /// Bounds how long a refinement is used before it is revalidated, even if
+    /// its DNS TTL has not yet elapsed.
+    ///
+    /// Stale refinements continue to be used while they are revalidated.
+    pub fn with_freshness(mut self, freshness: Option<Duration>) -> Self {
+        self.config.freshness = freshness;
+        self
+    }
+
+    /// Configures a callback that is notified with the previous canonical name
+    /// when revalidation produces a different name.
+    pub fn with_invalidate<F>(mut self, invalidate: F) -> Self
+    where
+        F: Fn(&NameAddr) + Send + Sync + 'static,
+    {
+        self.config.invalidate = Some(Invalidate(Arc::new(invalidate)));
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.config.metrics = metrics;
+        self
+    }
+
+    /// Shares `refinements` with the layer's services, so that they may be
+    /// pre-populated or inspected.
+    pub fn with_refinements(mut self, refinements: Refinements) -> Self {
+        self.config.refinements = refinements;
+        self
+    }
+}
+
+impl<M, R> tower::layer::Layer<M> for Layer<R>
 where
     M: tower::Service<Addr> + Clone,
+    R: Refine + Clone,
 {
-    type Service = Stack<M>;
+    type Service = Stack<M, R>;
 
     fn layer(&self, inner: M) -> Self::Service {
         Stack {
             inner,
-            resolver: self.resolver.clone(),
-            timeout: self.timeout,
+            config: self.config.clone(),
         }
     }
 }
 
 // === impl Stack ===
 
-impl<M> tower::Service<Addr> for Stack<M>
+impl<M, R> tower::Service<Addr> for Stack<M, R>
 where
     M: tower::Service<Addr>,
+    R: Refine + Clone,
 {
     type Response = tower::util::Either<Service<M::Response>, M::Response>;
     type Error = M::Error;
-    type Future = MakeFuture<M::Future>;
+    type Future = MakeFuture<M::Future, R>;
 
     fn poll_ready(&mut self) -> Poll<(), Self::Error> {
         self.inner.poll_ready()
@@ -120,7 +238,7 @@ where
 
     fn call(&mut self, addr: Addr) -> Self::Future {
         let task = match addr {
-            Addr::Name(ref na) => Some((na.clone(), self.resolver.clone(), self.timeout)),
+            Addr::Name(ref na) => Some((na.clone(), self.config.clone())),
             Addr::Socket(_) => None,
         };
 
@@ -131,23 +249,30 @@ where
 
 // === impl MakeFuture ===
 
-impl<F> Future for MakeFuture<F>
+impl<F, R> Future for MakeFuture<F, R>
 where
     F: Future,
+    R: Refine + Send + 'static,
+    R::Future: Send + 'static,
 {
     type Item = tower::util::Either<Service<F::Item>, F::Item>;
     type Error = F::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         let inner = try_ready!(self.inner.poll());
-        let svc = if let Some((na, resolver, timeout)) = self.task.take() {
+        let svc = if let Some((na, config)) = self.task.take() {
             let (tx, rx) = mpsc::channel(1);
             let (_tx_stop, rx_stop) = oneshot::channel();
 
-            tokio::spawn(Task::new(na, resolver, timeout, tx, rx_stop).in_current_span());
+            // If the name has been refined before, the service may use the
+            // last-known refinement immediately, even if it is stale.
+            let cached = config.refinements.get(&na);
+            let canonicalized = cached.as_ref().map(|r| r.name.clone().into());
+
+            tokio::spawn(Task::new(na, config, cached, tx, rx_stop).in_current_span());
 
             tower::util::Either::A(Service {
-                canonicalized: None,
+                canonicalized,
                 inner,
                 rx,
                 _tx_stop,
@@ -162,27 +287,40 @@ where
 
 // === impl Task ===
 
-impl Task {
+impl<R: Refine> Task<R> {
     fn new(
         original: NameAddr,
-        resolver: dns::Resolver,
-        timeout: Duration,
+        config: Config<R>,
+        cached: Option<Refined>,
         tx: mpsc::Sender<NameAddr>,
         rx_stop: oneshot::Receiver<Never>,
     ) -> Self {
+        let (resolved, state) = match cached {
+            None => (Cache::AwaitingInitial, State::Init),
+            Some(Refined { name, fresh_until }) => {
+                // The service already has this name, so it only needs to be
+                // notified if the name changes.
+                let state = if fresh_until > clock::now() {
+                    State::ValidUntil(Delay::new(fresh_until))
+                } else {
+                    State::Init
+                };
+                (Cache::Resolved(name), state)
+            }
+        };
+
         Self {
             original,
-            resolved: Cache::AwaitingInitial,
-            resolver,
-            state: State::Init,
-            timeout,
+            resolved,
+            config,
+            state,
             tx,
             rx_stop,
         }
     }
 }
 
-impl Future for Task {
+impl<R: Refine> Future for Task<R> {
     type Item = ();
     type Error = ();
 
@@ -200,8 +338,11 @@ impl Future for Task {
             self.state = match self.state {
                 State::Init => {
                     trace!("task init; name={:?}", self.original);
-                    let f = self.resolver.refine(self.original.name());
-                    State::Pending(Timeout::new(f, self.timeout))
+                    if self.resolved.get().is_some() {
+                        self.config.metrics.revalidate();
+                    }
+                    let f = self.config.resolver.refine(self.original.name());
+                    State::Pending(Timeout::new(f, self.config.timeout))
                 }
                 State::Pending(ref mut fut) => {
                     // Only poll the resolution for updates when the receiver is
@@ -236,10 +377,30 @@ impl Future for Task {
                                 self.tx
                                     .try_send(resolved.clone())
                                     .expect("tx failed despite being ready");
-                                self.resolved = Cache::Resolved(resolved);
+
+                                if let Cache::Resolved(ref prior) = self.resolved {
+                                    debug!(
+                                        "canonical name changed; name={} prior={} refined={}",
+                                        self.original, prior, resolved
+                                    );
+                                    self.config.metrics.name_change();
+                                    if let Some(Invalidate(ref invalidate)) = self.config.invalidate
+                                    {
+                                        invalidate(prior);
+                                    }
+                                }
+                                self.resolved = Cache::Resolved(resolved.clone());
                             }
 
-                            State::ValidUntil(Delay::new(refine.valid_until))
+                            let fresh_until = self.config.fresh_until(refine.valid_until);
+                            self.config.refinements.insert(
+                                self.original.clone(),
+                                Refined {
+                                    name: resolved,
+                                    fresh_until,
+                                },
+                            );
+                            State::ValidUntil(Delay::new(fresh_until))
                         }
                         Err(e) => {
                             trace!("task error; name={:?} err={:?}", self.original, e);
@@ -308,18 +469,168 @@ impl Cache {
     }
 }
 
+// === impl Config ===
+
+impl<R> Config<R> {
+    /// Determines when a refinement that is valid until `valid_until` should
+    /// be revalidated.
+    fn fresh_until(&self, valid_until: Instant) -> Instant {
+        match self.freshness {
+            Some(freshness) => ::std::cmp::min(valid_until, clock::now() + freshness),
+            None => valid_until,
+        }
+    }
+}
+
+// === impl Refinements ===
+
+impl Default for Refinements {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_MAX_REFINEMENTS)
+    }
+}
+
+impl Refinements {
+    /// Retains at most `capacity` refinements.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Refinements(Arc::new(Mutex::new(Lru {
+            capacity,
+            by_name: HashMap::new(),
+            uses: 0,
+        })))
+    }
+
+    /// Returns each original name's refinement, along with the time until
+    /// which the refinement is fresh.
+    pub fn snapshot(&self) -> Vec<(NameAddr, NameAddr, Instant)> {
+        match self.0.lock() {
+            Ok(lru) => lru
+                .by_name
+                .iter()
+                .map(|(original, (r, _))| (original.clone(), r.name.clone(), r.fresh_until))
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Adds refinements that were made elsewhere (i.e. by a prior process).
+    ///
+    /// Refinements that have already been made are not replaced.
+    pub fn restore(&self, refinements: impl IntoIterator<Item = (NameAddr, NameAddr, Instant)>) {
+        if let Ok(mut lru) = self.0.lock() {
+            for (original, name, fresh_until) in refinements {
+                if !lru.by_name.contains_key(&original) {
+                    lru.insert(original, Refined { name, fresh_until });
+                }
+            }
+        }
+    }
+
+    fn get(&self, original: &NameAddr) -> Option<Refined> {
+        let mut lru = self.0.lock().ok()?;
+        lru.uses += 1;
+        let uses = lru.uses;
+        let (refined, used) = lru.by_name.get_mut(original)?;
+        *used = uses;
+        Some(refined.clone())
+    }
+
+    fn insert(&self, original: NameAddr, refined: Refined) {
+        if let Ok(mut lru) = self.0.lock() {
+            lru.insert(original, refined);
+        }
+    }
+}
+
+// === impl Lru ===
+
+impl Lru {
+    fn insert(&mut self, original: NameAddr, refined: Refined) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if !self.by_name.contains_key(&original) && self.by_name.len() >= self.capacity {
+            let lru = self
+                .by_name
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(name, _)| name.clone());
+            if let Some(name) = lru {
+                trace!("evicting refinement; name={}", name);
+                self.by_name.remove(&name);
+            }
+        }
+
+        self.uses += 1;
+        self.by_name.insert(original, (refined, self.uses));
+    }
+}
+
+// === impl Refine ===
+
+impl Refine for dns::Resolver {
+    type Future = dns::RefineFuture;
+
+    fn refine(&self, name: &dns::Name) -> Self::Future {
+        dns::Resolver::refine(self, name)
+    }
+}
+
+// === impl Invalidate ===
+
+impl fmt::Debug for Invalidate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Invalidate").finish()
+    }
+}
+
+// === impl Metrics ===
+
+impl Metrics {
+    fn revalidate(&self) {
+        if let Ok(mut counts) = self.0.lock() {
+            counts.revalidations.incr();
+        }
+    }
+
+    fn name_change(&self) {
+        if let Ok(mut counts) = self.0.lock() {
+            counts.name_changes.incr();
+        }
+    }
+}
+
+impl FmtMetrics for Metrics {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let counts = match self.0.lock() {
+            Err(_) => return Ok(()),
+            Ok(lock) => lock,
+        };
+
+        dns_canonicalize_revalidate_total.fmt_help(f)?;
+        dns_canonicalize_revalidate_total.fmt_metric(f, counts.revalidations)?;
+
+        dns_canonicalize_name_change_total.fmt_help(f)?;
+        dns_canonicalize_name_change_total.fmt_metric(f, counts.name_changes)?;
+
+        Ok(())
+    }
+}
+
 // === impl Service ===
 
 impl<S, B> tower::Service<http::Request<B>> for Service<S>
 where
     S: tower::Service<http::Request<B>>,
+    S::Error: Into<Error>,
 {
     type Response = S::Response;
-    type Error = S::Error;
-    type Future = S::Future;
+    type Error = Error;
+    type Future = contract::ResponseFuture<S::Future>;
 
     fn poll_ready(&mut self) -> Poll<(), Self::Error> {
-        try_ready!(self.inner.poll_ready());
+        try_ready!(self.inner.poll_ready().map_err(Into::into));
 
         while let Ok(Async::Ready(Some(addr))) = self.rx.poll() {
             debug!("refined: {}", addr);
@@ -333,12 +644,16 @@ where
     }
 
     fn call(&mut self, mut req: http::Request<B>) -> Self::Future {
-        let addr = self
-            .canonicalized
-            .clone()
-            .expect("called before canonicalized address");
-        req.extensions_mut().insert(addr);
-        self.inner.call(req)
+        let addr = contract!(
+            self.canonicalized.clone(),
+            "canonicalize::Service::call",
+            "called before canonicalized address"
+        );
+        addr.map(|addr| {
+            req.extensions_mut().insert(addr);
+            self.inner.call(req)
+        })
+        .into()
     }
 }
 
@@ -347,3 +662,293 @@ impl<S> Drop for Service<S> {
         trace!("dropping service; name={:?}", self.canonicalized);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use std::convert::TryFrom;
+    use tokio::runtime::current_thread::Runtime;
+    use tower::Service as _;
+
+    const REFINE_LATENCY: Duration = Duration::from_millis(100);
+    const FRESHNESS: Duration = Duration::from_millis(50);
+
+    /// Refines all names to the current answer after `REFINE_LATENCY`.
+    #[derive(Clone)]
+    struct MockRefine(Arc<Mutex<dns::Name>>);
+
+    impl Refine for MockRefine {
+        type Future = Box<dyn Future<Item = dns::Refine, Error = dns::ResolveError> + Send>;
+
+        fn refine(&self, _: &dns::Name) -> Self::Future {
+            let answer = self.0.clone();
+            let f = Delay::new(clock::now() + REFINE_LATENCY).then(move |_| {
+                let name = answer.lock().unwrap().clone();
+                let valid_until = clock::now() + Duration::from_secs(60 * 60);
+                Ok::<_, dns::ResolveError>(dns::Refine { name, valid_until })
+            });
+            Box::new(f)
+        }
+    }
+
+    /// Refines each name to itself, counting refinements.
+    #[derive(Clone, Default)]
+    struct CountRefine(Arc<Mutex<usize>>);
+
+    impl Refine for CountRefine {
+        type Future = future::FutureResult<dns::Refine, dns::ResolveError>;
+
+        fn refine(&self, name: &dns::Name) -> Self::Future {
+            *self.0.lock().unwrap() += 1;
+            let valid_until = clock::now() + Duration::from_secs(60 * 60);
+            future::ok(dns::Refine {
+                name: name.clone(),
+                valid_until,
+            })
+        }
+    }
+
+    fn name(s: &str) -> dns::Name {
+        dns::Name::try_from(s.as_bytes()).unwrap()
+    }
+
+    fn addr(s: &str) -> Addr {
+        Addr::from_str(s).unwrap()
+    }
+
+    /// Builds services that respond with the `Addr` set on each request.
+    #[derive(Clone)]
+    struct MakeEchoAddr;
+
+    struct EchoAddr;
+
+    impl tower::Service<Addr> for MakeEchoAddr {
+        type Response = EchoAddr;
+        type Error = Never;
+        type Future = future::FutureResult<EchoAddr, Never>;
+
+        fn poll_ready(&mut self) -> Poll<(), Never> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: Addr) -> Self::Future {
+            future::ok(EchoAddr)
+        }
+    }
+
+    impl tower::Service<http::Request<()>> for EchoAddr {
+        type Response = Addr;
+        type Error = Never;
+        type Future = future::FutureResult<Addr, Never>;
+
+        fn poll_ready(&mut self) -> Poll<(), Never> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, req: http::Request<()>) -> Self::Future {
+            let addr = req.extensions().get::<Addr>().cloned();
+            future::ok(addr.expect("request must have an addr"))
+        }
+    }
+
+    fn is_ready(rt: &mut Runtime, svc: &mut Service<EchoAddr>) -> bool {
+        rt.block_on(future::lazy(|| {
+            tower::Service::<http::Request<()>>::poll_ready(svc)
+        }))
+        .expect("service must not fail")
+        .is_ready()
+    }
+
+    fn send(rt: &mut Runtime, svc: &mut Service<EchoAddr>) -> Addr {
+        rt.block_on(future::poll_fn(|| {
+            tower::Service::<http::Request<()>>::poll_ready(svc)
+        }))
+        .expect("service must not fail");
+        rt.block_on(tower::Service::call(svc, http::Request::new(())))
+            .expect("request must not fail")
+    }
+
+    #[test]
+    fn serves_stale_while_revalidating() {
+        let mut rt = Runtime::new().unwrap();
+
+        let answer = Arc::new(Mutex::new(name("web.ns1.svc.cluster.local")));
+        let invalidated = Arc::new(Mutex::new(Vec::new()));
+        let metrics = Metrics::default();
+        let layer = Layer::new(MockRefine(answer.clone()), Duration::from_secs(1))
+            .with_freshness(Some(FRESHNESS))
+            .with_metrics(metrics.clone())
+            .with_invalidate({
+                let invalidated = invalidated.clone();
+                move |prior: &NameAddr| invalidated.lock().unwrap().push(prior.clone())
+            });
+        let mut stack = tower::layer::Layer::layer(&layer, MakeEchoAddr);
+
+        let mut svc = match rt.block_on(stack.call(addr("web:8080"))).unwrap() {
+            tower::util::Either::A(svc) => svc,
+            tower::util::Either::B(_) => panic!("names must be canonicalized"),
+        };
+        assert_eq!(
+            send(&mut rt, &mut svc),
+            addr("web.ns1.svc.cluster.local:8080")
+        );
+
+        // Change the answer and wait for the refinement to become stale, so
+        // that it is being revalidated.
+        *answer.lock().unwrap() = name("web.ns2.svc.cluster.local");
+        rt.block_on(tokio_timer::sleep(FRESHNESS * 2)).unwrap();
+        assert!(
+            is_ready(&mut rt, &mut svc),
+            "service must not wait for revalidation"
+        );
+        assert_eq!(
+            send(&mut rt, &mut svc),
+            addr("web.ns1.svc.cluster.local:8080")
+        );
+
+        // Once revalidation completes, the new name is used.
+        rt.block_on(tokio_timer::sleep(REFINE_LATENCY * 2)).unwrap();
+        assert_eq!(
+            send(&mut rt, &mut svc),
+            addr("web.ns2.svc.cluster.local:8080")
+        );
+
+        assert_eq!(
+            *invalidated.lock().unwrap(),
+            vec![NameAddr::from_str("web.ns1.svc.cluster.local:8080").unwrap()],
+            "the prior name must be invalidated"
+        );
+        let counts = metrics.0.lock().unwrap();
+        assert!(counts.revalidations.value() >= 1);
+        assert_eq!(counts.name_changes.value(), 1);
+    }
+
+    #[test]
+    fn rebuilt_service_uses_prior_refinement() {
+        let mut rt = Runtime::new().unwrap();
+
+        let answer = Arc::new(Mutex::new(name("web.ns1.svc.cluster.local")));
+        let layer = Layer::new(MockRefine(answer), Duration::from_secs(1));
+        let mut stack = tower::layer::Layer::layer(&layer, MakeEchoAddr);
+
+        let mut svc = match rt.block_on(stack.call(addr("web:8080"))).unwrap() {
+            tower::util::Either::A(svc) => svc,
+            tower::util::Either::B(_) => panic!("names must be canonicalized"),
+        };
+        assert!(
+            !is_ready(&mut rt, &mut svc),
+            "a new name must be refined before it is used"
+        );
+        assert_eq!(
+            send(&mut rt, &mut svc),
+            addr("web.ns1.svc.cluster.local:8080")
+        );
+        drop(svc);
+
+        let mut svc = match rt.block_on(stack.call(addr("web:8080"))).unwrap() {
+            tower::util::Either::A(svc) => svc,
+            tower::util::Either::B(_) => panic!("names must be canonicalized"),
+        };
+        assert!(
+            is_ready(&mut rt, &mut svc),
+            "a previously-refined name must be used immediately"
+        );
+        assert_eq!(
+            send(&mut rt, &mut svc),
+            addr("web.ns1.svc.cluster.local:8080")
+        );
+    }
+
+    #[test]
+    fn calling_before_ready_fails_request() {
+        let mut rt = Runtime::new().unwrap();
+
+        let answer = Arc::new(Mutex::new(name("web.ns1.svc.cluster.local")));
+        let layer = Layer::new(MockRefine(answer), Duration::from_secs(1));
+        let mut stack = tower::layer::Layer::layer(&layer, MakeEchoAddr);
+
+        let mut svc = match rt.block_on(stack.call(addr("web:8080"))).unwrap() {
+            tower::util::Either::A(svc) => svc,
+            tower::util::Either::B(_) => panic!("names must be canonicalized"),
+        };
+        let err = rt
+            .block_on(tower::Service::call(&mut svc, http::Request::new(())))
+            .expect_err("request must fail");
+        let violation = err
+            .downcast_ref::<contract::InvariantViolation>()
+            .expect("error must be an invariant violation");
+        assert_eq!(violation.site(), "canonicalize::Service::call");
+
+        // The service remains usable once it becomes ready.
+        assert_eq!(
+            send(&mut rt, &mut svc),
+            addr("web.ns1.svc.cluster.local:8080")
+        );
+    }
+
+    #[test]
+    fn restored_refinements_skip_initial_refine() {
+        let mut rt = Runtime::new().unwrap();
+
+        let refinements = Refinements::default();
+        refinements.restore(vec![(
+            NameAddr::from_str("web:8080").unwrap(),
+            NameAddr::from_str("web.ns1.svc.cluster.local:8080").unwrap(),
+            clock::now() + Duration::from_secs(60),
+        )]);
+        let refine = CountRefine::default();
+        let layer =
+            Layer::new(refine.clone(), Duration::from_secs(1)).with_refinements(refinements);
+        let mut stack = tower::layer::Layer::layer(&layer, MakeEchoAddr);
+
+        let mut svc = match rt.block_on(stack.call(addr("web:8080"))).unwrap() {
+            tower::util::Either::A(svc) => svc,
+            tower::util::Either::B(_) => panic!("names must be canonicalized"),
+        };
+        assert!(
+            is_ready(&mut rt, &mut svc),
+            "a restored refinement must be used immediately"
+        );
+        assert_eq!(
+            send(&mut rt, &mut svc),
+            addr("web.ns1.svc.cluster.local:8080")
+        );
+        assert_eq!(
+            *refine.0.lock().unwrap(),
+            0,
+            "a restored refinement must not be refined until it expires"
+        );
+    }
+
+    #[test]
+    fn least_recently_used_refinements_are_evicted() {
+        let refinements = Refinements::with_capacity(2);
+        let fresh_until = clock::now() + Duration::from_secs(60);
+        let refined = |n: &str| Refined {
+            name: NameAddr::from_str(&format!("{}.ns1.svc.cluster.local:8080", n)).unwrap(),
+            fresh_until,
+        };
+        let original = |n: &str| NameAddr::from_str(&format!("{}:8080", n)).unwrap();
+
+        refinements.insert(original("web"), refined("web"));
+        refinements.insert(original("db"), refined("db"));
+        assert!(refinements.get(&original("web")).is_some());
+
+        // `db` was used least recently, so it's evicted to make room.
+        refinements.insert(original("api"), refined("api"));
+        assert_eq!(refinements.snapshot().len(), 2);
+        assert!(refinements.get(&original("db")).is_none());
+        assert!(refinements.get(&original("web")).is_some());
+        assert!(refinements.get(&original("api")).is_some());
+
+        // Updating a retained refinement does not evict another.
+        refinements.insert(original("web"), refined("web"));
+        assert!(refinements.get(&original("api")).is_some());
+
+        // Restored refinements are bounded as well.
+        refinements.restore(vec![(original("db"), refined("db").name, fresh_until)]);
+        assert_eq!(refinements.snapshot().len(), 2);
+        assert!(refinements.get(&original("db")).is_some());
+    }
+}
@@ -0,0 +1,712 @@
+//! Verifies the integrity of message bodies between proxies.
+//!
+//! When enabled for a target, the outbound proxy computes a CRC32C checksum
+//! of each upgraded request's body as it is streamed and appends it as an
+//! `l5d-body-checksum` trailer. That the body is checksummed is signaled by
+//! the `body-checksum` flag of the request's `l5d-orig-proto` header, so that
+//! the inbound proxy verifies the checksum before it reports the end of the
+//! body. The inbound proxy checksums its response in turn, and the outbound
+//! proxy verifies it. Proxies that don't know the flag ignore it and don't
+//! checksum their responses.
+//!
+//! A body that fails verification fails with a `ChecksumFailed` error, which
+//! is counted. Checksum trailers are removed from verified bodies.
+
+use crate::orig_proto;
+use crc::crc32::{self, Hasher32};
+use futures::{try_ready, Async, Future, Poll};
+use http::header::{HeaderMap, HeaderValue};
+use hyper::body::Payload;
+use indexmap::IndexMap;
+use linkerd2_error::Error;
+use linkerd2_metrics::{metrics, Counter, FmtLabels, FmtMetrics};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tracing::{debug, trace};
+
+pub const L5D_BODY_CHECKSUM: &str = "l5d-body-checksum";
+
+metrics! {
+    body_checksum_failures_total: Counter {
+        "Total count of message bodies that failed checksum verification"
+    }
+}
+
+/// A message's body did not match its checksum.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ChecksumFailed {
+    message: Message,
+    reason: Reason,
+}
+
+/// Counts the bodies that failed verification.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics(Arc<Mutex<IndexMap<ChecksumFailed, Counter>>>);
+
+#[derive(Clone, Debug)]
+pub struct Layer<P> {
+    enabled: P,
+    metrics: Metrics,
+}
+
+#[derive(Clone, Debug)]
+pub struct Stack<P, M> {
+    inner: M,
+    enabled: P,
+    metrics: Metrics,
+}
+
+pub struct MakeFuture<F> {
+    inner: F,
+    enabled: bool,
+    metrics: Metrics,
+}
+
+#[derive(Clone, Debug)]
+pub struct Service<S> {
+    inner: S,
+    enabled: bool,
+    metrics: Metrics,
+}
+
+pub struct ResponseFuture<F> {
+    inner: F,
+    response: ResponseCheck,
+    metrics: Metrics,
+}
+
+pub struct Body<B> {
+    inner: B,
+    check: Check,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum Message {
+    Request,
+    Response,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum Reason {
+    Missing,
+    Mismatch,
+}
+
+/// How a request's response is checksummed.
+#[derive(Copy, Clone, Debug)]
+enum ResponseCheck {
+    Passthrough,
+    /// The request was verified, so its response is checksummed in turn.
+    Append,
+    /// The request was checksummed, so its response is verified if the peer
+    /// checksummed it in turn.
+    Verify,
+}
+
+enum Check {
+    Passthrough,
+    Append(crc32::Digest),
+    Verify {
+        digest: crc32::Digest,
+        data_complete: bool,
+        message: Message,
+        metrics: Metrics,
+    },
+    /// The body's data was verified. Its remaining trailers, if any, have yet
+    /// to be returned.
+    Verified(Option<HeaderMap>),
+}
+
+/// Checksums the bodies of upgraded requests to the targets for which
+/// `enabled` returns true, and verifies the bodies of those that peers have
+/// checksummed.
+///
+/// This layer must be inside of `orig_proto::Upgrade` and outside of
+/// `orig_proto::Downgrade`, so that it sees each message's `l5d-orig-proto`
+/// header.
+pub fn layer<T, P>(enabled: P, metrics: Metrics) -> Layer<P>
+where
+    P: Fn(&T) -> bool + Clone,
+{
+    Layer { enabled, metrics }
+}
+
+// === impl ChecksumFailed ===
+
+impl fmt::Display for ChecksumFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self.message {
+            Message::Request => "request",
+            Message::Response => "response",
+        };
+        match self.reason {
+            Reason::Missing => write!(f, "{} body checksum is missing", message),
+            Reason::Mismatch => write!(f, "{} body does not match its checksum", message),
+        }
+    }
+}
+
+impl std::error::Error for ChecksumFailed {}
+
+impl FmtLabels for ChecksumFailed {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self.message {
+            Message::Request => "request",
+            Message::Response => "response",
+        };
+        let reason = match self.reason {
+            Reason::Missing => "missing",
+            Reason::Mismatch => "mismatch",
+        };
+        write!(f, "message=\"{}\",reason=\"{}\"", message, reason)
+    }
+}
+
+// === impl Layer ===
+
+impl<P: Clone, M> tower::layer::Layer<M> for Layer<P> {
+    type Service = Stack<P, M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        Stack {
+            inner,
+            enabled: self.enabled.clone(),
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+// === impl Stack ===
+
+impl<T, P, M> tower::Service<T> for Stack<P, M>
+where
+    P: Fn(&T) -> bool,
+    M: tower::Service<T>,
+{
+    type Response = Service<M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let enabled = (self.enabled)(&target);
+        MakeFuture {
+            inner: self.inner.call(target),
+            enabled,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+// === impl MakeFuture ===
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = Service<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        Ok(Service {
+            inner,
+            enabled: self.enabled,
+            metrics: self.metrics.clone(),
+        }
+        .into())
+    }
+}
+
+// === impl Service ===
+
+impl<S, A, B> tower::Service<http::Request<A>> for Service<S>
+where
+    S: tower::Service<http::Request<Body<A>>, Response = http::Response<B>>,
+    A: Payload,
+    A::Data: AsRef<[u8]>,
+    B: Payload,
+    B::Data: AsRef<[u8]>,
+{
+    type Response = http::Response<Body<B>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut req: http::Request<A>) -> Self::Future {
+        let (req, response) = if req.version() == http::Version::HTTP_2
+            && orig_proto::has_body_checksum(req.headers())
+        {
+            trace!("verifying request body checksum");
+            let metrics = self.metrics.clone();
+            let req = req.map(move |b| Body::verify(b, Message::Request, metrics));
+            (req, ResponseCheck::Append)
+        } else if self.enabled && orig_proto::set_body_checksum(req.headers_mut()) {
+            trace!("appending request body checksum");
+            (req.map(Body::append), ResponseCheck::Verify)
+        } else {
+            (req.map(Body::passthrough), ResponseCheck::Passthrough)
+        };
+
+        ResponseFuture {
+            inner: self.inner.call(req),
+            response,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+    B: Payload,
+    B::Data: AsRef<[u8]>,
+{
+    type Item = http::Response<Body<B>>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let rsp = try_ready!(self.inner.poll());
+        let (mut head, body) = rsp.into_parts();
+        let body = match self.response {
+            ResponseCheck::Append if orig_proto::set_body_checksum(&mut head.headers) => {
+                trace!("appending response body checksum");
+                Body::append(body)
+            }
+            ResponseCheck::Verify if orig_proto::has_body_checksum(&head.headers) => {
+                trace!("verifying response body checksum");
+                Body::verify(body, Message::Response, self.metrics.clone())
+            }
+            _ => Body::passthrough(body),
+        };
+        Ok(http::Response::from_parts(head, body).into())
+    }
+}
+
+// === impl Body ===
+
+impl<B: Payload> Body<B> {
+    fn passthrough(inner: B) -> Self {
+        Self {
+            inner,
+            check: Check::Passthrough,
+        }
+    }
+
+    /// Appends a checksum to a body, unless it is empty. Empty bodies are sent
+    /// without any frames, so they can't have trailers.
+    fn append(inner: B) -> Self {
+        if inner.is_end_stream() {
+            return Self::passthrough(inner);
+        }
+        Self {
+            inner,
+            check: Check::Append(crc32::Digest::new(crc32::CASTAGNOLI)),
+        }
+    }
+
+    /// Verifies a body's checksum, unless it is empty.
+    fn verify(inner: B, message: Message, metrics: Metrics) -> Self {
+        if inner.is_end_stream() {
+            return Self::passthrough(inner);
+        }
+        Self {
+            inner,
+            check: Check::Verify {
+                digest: crc32::Digest::new(crc32::CASTAGNOLI),
+                data_complete: false,
+                message,
+                metrics,
+            },
+        }
+    }
+
+    /// Reads the body's trailers and checks its checksum, so that the end of
+    /// its data isn't reported until it has been verified.
+    fn poll_verify(&mut self) -> Poll<(), Error> {
+        let mut trailers = try_ready!(self.inner.poll_trailers().map_err(Into::into));
+        let checksum = trailers.as_mut().and_then(|t| t.remove(L5D_BODY_CHECKSUM));
+
+        let failed = match self.check {
+            Check::Verify {
+                ref digest,
+                message,
+                ref metrics,
+                ..
+            } => {
+                let reason = match checksum {
+                    None => Some(Reason::Missing),
+                    Some(ref v) if parse_checksum(v) != Some(digest.sum32()) => {
+                        Some(Reason::Mismatch)
+                    }
+                    Some(_) => None,
+                };
+                reason.map(|reason| {
+                    let failed = ChecksumFailed { message, reason };
+                    metrics.incr(&failed);
+                    failed
+                })
+            }
+            _ => unreachable!("body must be verifying"),
+        };
+
+        self.check = Check::Verified(trailers.filter(|t| !t.is_empty()));
+        match failed {
+            Some(failed) => {
+                debug!("{}", failed);
+                Err(failed.into())
+            }
+            None => Ok(Async::Ready(())),
+        }
+    }
+}
+
+impl<B> Payload for Body<B>
+where
+    B: Payload,
+    B::Data: AsRef<[u8]>,
+{
+    type Data = B::Data;
+    type Error = Error;
+
+    fn is_end_stream(&self) -> bool {
+        match self.check {
+            Check::Passthrough => self.inner.is_end_stream(),
+            // The checksum has yet to be appended or verified.
+            Check::Append(_) | Check::Verify { .. } => false,
+            Check::Verified(ref trailers) => trailers.is_none(),
+        }
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        match self.check {
+            Check::Verified(_) => return Ok(Async::Ready(None)),
+            Check::Verify {
+                data_complete: true,
+                ..
+            } => {
+                try_ready!(self.poll_verify());
+                return Ok(Async::Ready(None));
+            }
+            _ => {}
+        }
+
+        let data = try_ready!(self.inner.poll_data().map_err(Into::into));
+        match data {
+            Some(data) => {
+                match self.check {
+                    Check::Append(ref mut digest) | Check::Verify { ref mut digest, .. } => {
+                        digest.write(data.as_ref())
+                    }
+                    _ => {}
+                }
+                Ok(Async::Ready(Some(data)))
+            }
+            None => {
+                if let Check::Verify {
+                    ref mut data_complete,
+                    ..
+                } = self.check
+                {
+                    *data_complete = true;
+                    try_ready!(self.poll_verify());
+                }
+                Ok(Async::Ready(None))
+            }
+        }
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Self::Error> {
+        if let Check::Verify { .. } = self.check {
+            try_ready!(self.poll_verify());
+        }
+        if let Check::Verified(ref mut trailers) = self.check {
+            return Ok(Async::Ready(trailers.take()));
+        }
+
+        let mut trailers = try_ready!(self.inner.poll_trailers().map_err(Into::into));
+        if let Check::Append(ref digest) = self.check {
+            let checksum = format!("{:08x}", digest.sum32());
+            trailers.get_or_insert_with(HeaderMap::new).insert(
+                L5D_BODY_CHECKSUM,
+                HeaderValue::from_str(&checksum).expect("checksum must be a valid header"),
+            );
+            self.check = Check::Passthrough;
+        }
+        Ok(Async::Ready(trailers))
+    }
+}
+
+impl<B> http_body::Body for Body<B>
+where
+    B: Payload,
+    B::Data: AsRef<[u8]>,
+{
+    type Data = B::Data;
+    type Error = Error;
+
+    fn is_end_stream(&self) -> bool {
+        Payload::is_end_stream(self)
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        Payload::poll_data(self)
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Self::Error> {
+        Payload::poll_trailers(self)
+    }
+}
+
+impl<B: Payload + Default> Default for Body<B> {
+    fn default() -> Self {
+        Self::passthrough(B::default())
+    }
+}
+
+fn parse_checksum(value: &HeaderValue) -> Option<u32> {
+    let value = value.to_str().ok()?;
+    u32::from_str_radix(value, 16).ok()
+}
+
+// === impl Metrics ===
+
+impl Metrics {
+    fn incr(&self, failed: &ChecksumFailed) {
+        if let Ok(mut failures) = self.0.lock() {
+            failures
+                .entry(*failed)
+                .or_insert_with(Counter::default)
+                .incr();
+        }
+    }
+}
+
+impl FmtMetrics for Metrics {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let failures = match self.0.lock() {
+            Ok(failures) => failures,
+            Err(_) => return Ok(()),
+        };
+        if failures.is_empty() {
+            return Ok(());
+        }
+
+        body_checksum_failures_total.fmt_help(f)?;
+        body_checksum_failures_total.fmt_scopes(f, failures.iter(), |c| c)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use std::collections::VecDeque;
+    use tower::{service_fn, Service as _};
+
+    #[derive(Default)]
+    struct TestBody {
+        data: VecDeque<hyper::Chunk>,
+        trailers: Option<HeaderMap>,
+    }
+
+    /// The request body and trailers received by the application.
+    type Received = Arc<Mutex<Option<(Vec<u8>, Option<HeaderMap>)>>>;
+
+    impl TestBody {
+        fn new<D: AsRef<[u8]>>(data: &[D], trailers: Option<HeaderMap>) -> Self {
+            Self {
+                data: data
+                    .iter()
+                    .map(|d| hyper::Chunk::from(d.as_ref().to_vec()))
+                    .collect(),
+                trailers,
+            }
+        }
+    }
+
+    impl Payload for TestBody {
+        type Data = hyper::Chunk;
+        type Error = Error;
+
+        fn is_end_stream(&self) -> bool {
+            self.data.is_empty() && self.trailers.is_none()
+        }
+
+        fn poll_data(&mut self) -> Poll<Option<hyper::Chunk>, Error> {
+            Ok(Async::Ready(self.data.pop_front()))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Error> {
+            Ok(Async::Ready(self.trailers.take()))
+        }
+    }
+
+    /// Reads a body that is always ready.
+    fn read<B: Payload>(mut body: B) -> Result<(Vec<u8>, Option<HeaderMap>), B::Error>
+    where
+        B::Data: AsRef<[u8]>,
+    {
+        let mut data = Vec::new();
+        loop {
+            match body.poll_data()? {
+                Async::Ready(Some(chunk)) => data.extend_from_slice(chunk.as_ref()),
+                Async::Ready(None) => break,
+                Async::NotReady => panic!("body must be ready"),
+            }
+        }
+        match body.poll_trailers()? {
+            Async::Ready(trailers) => Ok((data, trailers)),
+            Async::NotReady => panic!("trailers must be ready"),
+        }
+    }
+
+    /// Returns a request as it's upgraded by the outbound proxy.
+    fn request(data: &[&str]) -> http::Request<TestBody> {
+        http::Request::builder()
+            .version(http::Version::HTTP_2)
+            .uri("http://web.ns.svc.cluster.local/")
+            .header(orig_proto::L5D_ORIG_PROTO, "HTTP/1.1")
+            .body(TestBody::new(data, None))
+            .unwrap()
+    }
+
+    /// Sends a request through an outbound proxy's layer, which appends
+    /// checksums if `enabled`, to an inbound proxy's layer, whose application
+    /// records the request that it receives and responds with `rsp_data`.
+    fn roundtrip(
+        enabled: bool,
+        req: http::Request<TestBody>,
+        rsp_data: &'static [&'static str],
+        metrics: &Metrics,
+    ) -> (Received, http::HeaderMap, Result<Vec<u8>, Error>) {
+        let received = Received::default();
+        let app = {
+            let received = received.clone();
+            service_fn(move |req: http::Request<Body<Body<TestBody>>>| {
+                // The inbound proxy's `Downgrade` removes the request's
+                // orig-proto header and adds one to the response.
+                assert_eq!(orig_proto::has_body_checksum(req.headers()), enabled);
+                *received.lock().unwrap() = Some(read(req.into_body()).expect("request body"));
+                let rsp = http::Response::builder()
+                    .version(http::Version::HTTP_2)
+                    .header(orig_proto::L5D_ORIG_PROTO, "HTTP/1.1")
+                    .body(TestBody::new(rsp_data, None))
+                    .unwrap();
+                future::ok::<_, Error>(rsp)
+            })
+        };
+        let inbound = Service {
+            inner: app,
+            enabled: false,
+            metrics: metrics.clone(),
+        };
+        let mut outbound = Service {
+            inner: inbound,
+            enabled,
+            metrics: metrics.clone(),
+        };
+
+        let rsp = outbound.call(req).wait().expect("response");
+        let (head, body) = rsp.into_parts();
+        let body = read(body).map(|(data, trailers)| {
+            assert!(trailers.is_none(), "checksum trailers must be removed");
+            data
+        });
+        (received, head.headers, body)
+    }
+
+    #[test]
+    fn roundtrip_is_verified() {
+        let metrics = Metrics::default();
+        let req = request(&["hello", " ", "world"]);
+        let (received, headers, rsp) = roundtrip(true, req, &["hi", " there"], &metrics);
+
+        let (data, trailers) = received.lock().unwrap().take().unwrap();
+        assert_eq!(data, b"hello world");
+        assert!(trailers.is_none(), "checksum trailers must be removed");
+
+        assert!(orig_proto::has_body_checksum(&headers));
+        assert_eq!(rsp.expect("response body must be verified"), b"hi there");
+        assert_eq!(metrics.as_display().to_string(), "");
+    }
+
+    #[test]
+    fn disabled_adds_no_trailers() {
+        let metrics = Metrics::default();
+        let req = request(&["hello"]);
+        let (received, headers, rsp) = roundtrip(false, req, &["hi"], &metrics);
+
+        let (data, trailers) = received.lock().unwrap().take().unwrap();
+        assert_eq!(data, b"hello");
+        assert!(trailers.is_none());
+
+        assert!(!orig_proto::has_body_checksum(&headers));
+        assert_eq!(rsp.expect("response body"), b"hi");
+    }
+
+    #[test]
+    fn empty_bodies_are_not_checksummed() {
+        let metrics = Metrics::default();
+        let req = request(&[]);
+        let (received, _, rsp) = roundtrip(true, req, &[], &metrics);
+
+        let (data, trailers) = received.lock().unwrap().take().unwrap();
+        assert!(data.is_empty() && trailers.is_none());
+        assert!(rsp.expect("response body").is_empty());
+    }
+
+    #[test]
+    fn corruption_is_detected() {
+        let metrics = Metrics::default();
+        let (mut data, trailers) = read(Body::append(TestBody::new(&["hello"], None))).unwrap();
+        assert!(trailers.as_ref().unwrap().contains_key(L5D_BODY_CHECKSUM));
+
+        data[1] ^= 0x20;
+        let corrupt = TestBody::new(&[&data], trailers);
+        let error = read(Body::verify(corrupt, Message::Request, metrics.clone()))
+            .expect_err("corrupt body must fail");
+        let failed = error
+            .downcast_ref::<ChecksumFailed>()
+            .expect("checksum error");
+        assert_eq!(failed.reason, Reason::Mismatch);
+        assert!(metrics
+            .as_display()
+            .to_string()
+            .contains("body_checksum_failures_total{message=\"request\",reason=\"mismatch\"} 1\n"));
+    }
+
+    #[test]
+    fn missing_checksums_fail() {
+        let metrics = Metrics::default();
+        let body = TestBody::new(&["hello"], None);
+        let error = read(Body::verify(body, Message::Response, metrics))
+            .expect_err("unchecksummed body must fail");
+        let failed = error
+            .downcast_ref::<ChecksumFailed>()
+            .expect("checksum error");
+        assert_eq!(failed.reason, Reason::Missing);
+    }
+
+    #[test]
+    fn other_trailers_are_preserved() {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static("0"));
+        let appended = read(Body::append(TestBody::new(&["hello"], Some(trailers)))).unwrap();
+
+        let body = TestBody::new(&[appended.0], appended.1);
+        let (data, trailers) = read(Body::verify(body, Message::Request, Metrics::default()))
+            .expect("body must be verified");
+        assert_eq!(data, b"hello");
+        let trailers = trailers.expect("trailers must be preserved");
+        assert_eq!(trailers.len(), 1);
+        assert_eq!(trailers.get("grpc-status").unwrap(), "0");
+    }
+}
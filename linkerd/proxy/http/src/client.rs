@@ -5,7 +5,7 @@ use super::{
     settings::{HasSettings, Settings},
 };
 use futures::{try_ready, Async, Future, Poll};
-use http;
+use http::{self, header::HeaderValue};
 use hyper;
 use linkerd2_error::Error;
 use linkerd2_proxy_transport::connect;
@@ -42,7 +42,10 @@ where
     C::Connection: Send + 'static,
     C::Error: Into<Error>,
 {
-    Http1(Option<HyperClient<C, T, B>>),
+    Http1 {
+        client: Option<HyperClient<C, T, B>>,
+        wants_trailers: bool,
+    },
     Http2(::tower_util::Oneshot<h2::Connect<C, B>, T>),
 }
 
@@ -52,7 +55,12 @@ where
     B: hyper::body::Payload + 'static,
     C: tower::MakeConnection<T> + 'static,
 {
-    Http1(HyperClient<C, T, B>),
+    Http1 {
+        client: HyperClient<C, T, B>,
+        /// Whether requests accepted trailers before their `TE` header was
+        /// stripped, so that it must be restored.
+        wants_trailers: bool,
+    },
     Http2(h2::Connection<B>),
 }
 
@@ -135,6 +143,8 @@ where
                 keep_alive,
                 wants_h1_upgrade: _,
                 was_absolute_form,
+                is_http10: _,
+                wants_trailers,
             } => {
                 let exec = tokio::executor::DefaultExecutor::current()
                     .instrument(info_span!("http1", %peer_addr));
@@ -145,7 +155,10 @@ where
                     // header, instead always just passing whatever we received.
                     .set_host(false)
                     .build(HyperConnect::new(connect, config, was_absolute_form));
-                ClientNewServiceFuture::Http1(Some(h1))
+                ClientNewServiceFuture::Http1 {
+                    client: Some(h1),
+                    wants_trailers,
+                }
             }
             Settings::Http2 => {
                 let h2 = h2::Connect::new(connect, self.h2_settings.clone()).oneshot(config);
@@ -187,9 +200,13 @@ where
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         let svc = match *self {
-            ClientNewServiceFuture::Http1(ref mut h1) => {
-                ClientService::Http1(h1.take().expect("poll more than once"))
-            }
+            ClientNewServiceFuture::Http1 {
+                ref mut client,
+                wants_trailers,
+            } => ClientService::Http1 {
+                client: client.take().expect("poll more than once"),
+                wants_trailers,
+            },
             ClientNewServiceFuture::Http2(ref mut h2) => {
                 let svc = try_ready!(h2.poll());
                 ClientService::Http2(svc)
@@ -216,7 +233,7 @@ where
 
     fn poll_ready(&mut self) -> Poll<(), Self::Error> {
         match *self {
-            ClientService::Http1(_) => Ok(Async::Ready(())),
+            ClientService::Http1 { .. } => Ok(Async::Ready(())),
             ClientService::Http2(ref mut h2) => h2.poll_ready().map_err(Into::into),
         }
     }
@@ -230,7 +247,14 @@ where
             req.headers()
         );
         match *self {
-            ClientService::Http1(ref h1) => {
+            ClientService::Http1 {
+                ref client,
+                wants_trailers,
+            } => {
+                if wants_trailers {
+                    req.headers_mut()
+                        .insert(http::header::TE, HeaderValue::from_static("trailers"));
+                }
                 let upgrade = req.extensions_mut().remove::<Http11Upgrade>();
                 let is_http_connect = if upgrade.is_some() {
                     req.method() == &http::Method::CONNECT
@@ -238,7 +262,7 @@ where
                     false
                 };
                 ClientServiceFuture::Http1 {
-                    future: h1.request(req),
+                    future: client.request(req),
                     upgrade,
                     is_http_connect,
                 }
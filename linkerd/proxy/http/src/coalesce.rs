@@ -0,0 +1,879 @@
+//! Coalesces concurrent identical requests on a route into a single request.
+//!
+//! When a route allows it, a bodiless request with a safe method (e.g. `GET`)
+//! that is identical to a request already in flight is not dispatched.
+//! Instead, it joins the in-flight request: that request's response is
+//! buffered and each request that joined it is served a copy. Requests are
+//! identical when they have the same method, URI, and values for each of
+//! `KEY_HEADERS` and, if clients are isolated, are from the same client.
+//!
+//! Requests that ask to bypass caches are never coalesced. If a response's
+//! body is larger than `max_body_bytes`, it is streamed rather than shared,
+//! and later requests with the same key are no longer coalesced. Requests
+//! that joined a request that was not shared--because its response was too
+//! large or because it failed--are dispatched on their own.
+
+use bytes::{Bytes, BytesMut};
+use futures::{sync::oneshot, try_ready, Async, Future, Poll};
+use http::{self, header};
+use hyper::body::Payload;
+use linkerd2_error::{contract, Error, InvariantViolation};
+use std::collections::{hash_map::DefaultHasher, HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::{fmt, mem};
+use tracing::{debug, trace};
+
+/// The request headers that distinguish otherwise identical requests.
+const KEY_HEADERS: [&str; 6] = [
+    "host",
+    "accept",
+    "accept-encoding",
+    "accept-language",
+    "authorization",
+    "cookie",
+];
+
+/// Bounds the number of keys that are remembered as having responses too
+/// large to coalesce.
+const MAX_UNCOALESCED_KEYS: usize = 1_000;
+
+/// Implement on targets to determine if a route allows coalescing.
+pub trait CanCoalesce {
+    fn can_coalesce(&self) -> bool;
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Config {
+    /// Responses with larger bodies are not shared.
+    pub max_body_bytes: usize,
+    /// If true, responses are only shared among requests from the same
+    /// client.
+    pub isolate_clients: bool,
+}
+
+/// Coalesces requests on routes that allow it.
+///
+/// `client` identifies the client that sent a request from its extensions.
+/// When clients are isolated, requests from unidentified clients are not
+/// coalesced.
+pub fn layer<C>(config: Config, client: C) -> Layer<C> {
+    Layer { config, client }
+}
+
+#[derive(Clone, Debug)]
+pub struct Layer<C> {
+    config: Config,
+    client: C,
+}
+
+#[derive(Clone, Debug)]
+pub struct Stack<M, C> {
+    inner: M,
+    config: Config,
+    client: C,
+}
+
+pub struct MakeFuture<F, C> {
+    inner: F,
+    coalesce: Option<Coalesce<C>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Service<S, C> {
+    inner: S,
+    coalesce: Option<Coalesce<C>>,
+}
+
+pub enum ResponseFuture<S, A, B>
+where
+    S: tower::Service<http::Request<A>>,
+{
+    Passthrough(S::Future),
+    Lead(Leader<S::Future, B>),
+    Follow(Follower<S, A>),
+}
+
+/// Dispatches a request and shares its response with the requests that
+/// joined it.
+pub struct Leader<F, B> {
+    /// Unset once the request's outcome has been published.
+    key: Option<Key>,
+    registry: Registry,
+    max_body_bytes: usize,
+    state: Leading<F, B>,
+}
+
+/// Waits for the response of the request it joined, or dispatches its own
+/// request if that response is not shared.
+pub struct Follower<S, A>
+where
+    S: tower::Service<http::Request<A>>,
+{
+    inner: S,
+    request: Option<http::Request<A>>,
+    state: Following<S::Future>,
+}
+
+/// The body of a response that may have been shared.
+pub enum Body<B> {
+    /// The response was not coalesced.
+    Inner(B),
+    /// The response was buffered.
+    Buffered {
+        data: Option<Bytes>,
+        trailers: Option<http::HeaderMap>,
+    },
+    /// The response was too large to buffer, so the rest of it is streamed.
+    Partial { buffered: Option<Bytes>, rest: B },
+}
+
+#[derive(Clone, Debug)]
+struct Coalesce<C> {
+    config: Config,
+    client: C,
+    registry: Registry,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Key {
+    method: http::Method,
+    uri: String,
+    headers: u64,
+    client: Option<String>,
+}
+
+/// Tracks the requests that are in flight on a route.
+#[derive(Clone, Default)]
+struct Registry(Arc<Mutex<Inner>>);
+
+#[derive(Default)]
+struct Inner {
+    /// Notifies the requests that joined each in-flight request.
+    in_flight: HashMap<Key, Vec<oneshot::Sender<Arc<Buffered>>>>,
+    /// Keys whose responses were too large to buffer.
+    uncoalesced: HashSet<Key>,
+    /// The keys in `uncoalesced`, oldest first, so that the oldest key is
+    /// forgotten once `MAX_UNCOALESCED_KEYS` is reached.
+    uncoalesced_order: VecDeque<Key>,
+}
+
+enum Join {
+    Lead,
+    Follow(oneshot::Receiver<Arc<Buffered>>),
+    Bypass,
+}
+
+/// A response that is shared with each request that joined its request.
+#[derive(Debug)]
+struct Buffered {
+    status: http::StatusCode,
+    version: http::Version,
+    headers: http::HeaderMap,
+    data: Bytes,
+    trailers: Option<http::HeaderMap>,
+}
+
+enum Leading<F, B> {
+    Pending(F),
+    Buffering {
+        head: http::response::Parts,
+        body: B,
+        data: BytesMut,
+        eos: bool,
+    },
+    Done,
+}
+
+enum Following<F> {
+    Waiting(oneshot::Receiver<Arc<Buffered>>),
+    Dispatching,
+    Dispatched(F),
+}
+
+// === impl Layer ===
+
+impl<M, C: Clone> tower::layer::Layer<M> for Layer<C> {
+    type Service = Stack<M, C>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        Stack {
+            inner,
+            config: self.config,
+            client: self.client.clone(),
+        }
+    }
+}
+
+// === impl Stack ===
+
+impl<T, M, C> tower::Service<T> for Stack<M, C>
+where
+    T: CanCoalesce,
+    M: tower::Service<T>,
+    C: Clone,
+{
+    type Response = Service<M::Response, C>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future, C>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let coalesce = if target.can_coalesce() {
+            Some(Coalesce {
+                config: self.config,
+                client: self.client.clone(),
+                registry: Registry::default(),
+            })
+        } else {
+            None
+        };
+        let inner = self.inner.call(target);
+
+        MakeFuture { inner, coalesce }
+    }
+}
+
+impl<F: Future, C> Future for MakeFuture<F, C> {
+    type Item = Service<F::Item, C>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        let coalesce = self.coalesce.take();
+        Ok(Service { inner, coalesce }.into())
+    }
+}
+
+// === impl Service ===
+
+impl<S, C, A, B> tower::Service<http::Request<A>> for Service<S, C>
+where
+    S: tower::Service<http::Request<A>, Response = http::Response<B>> + Clone,
+    S::Error: Into<Error>,
+    C: Fn(&http::Extensions) -> Option<String>,
+    A: Payload,
+    B: Payload,
+    B::Data: From<Bytes>,
+{
+    type Response = http::Response<Body<B>>;
+    type Error = Error;
+    type Future = ResponseFuture<S, A, B>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, req: http::Request<A>) -> Self::Future {
+        let (coalesce, key) = match self.coalesce {
+            Some(ref coalesce) => match coalesce.key(&req) {
+                Some(key) => (coalesce, key),
+                None => return ResponseFuture::Passthrough(self.inner.call(req)),
+            },
+            None => return ResponseFuture::Passthrough(self.inner.call(req)),
+        };
+
+        match coalesce.registry.join(&key) {
+            Join::Lead => {
+                trace!(?key, "dispatching coalescable request");
+                ResponseFuture::Lead(Leader {
+                    key: Some(key),
+                    registry: coalesce.registry.clone(),
+                    max_body_bytes: coalesce.config.max_body_bytes,
+                    state: Leading::Pending(self.inner.call(req)),
+                })
+            }
+            Join::Follow(rx) => {
+                trace!(?key, "joining in-flight request");
+                ResponseFuture::Follow(Follower {
+                    inner: self.inner.clone(),
+                    request: Some(req),
+                    state: Following::Waiting(rx),
+                })
+            }
+            Join::Bypass => ResponseFuture::Passthrough(self.inner.call(req)),
+        }
+    }
+}
+
+impl<C> Coalesce<C>
+where
+    C: Fn(&http::Extensions) -> Option<String>,
+{
+    /// Returns the key of a request that may be coalesced.
+    fn key<A: Payload>(&self, req: &http::Request<A>) -> Option<Key> {
+        if !req.method().is_safe() || !req.body().is_end_stream() || bypasses_cache(req.headers()) {
+            return None;
+        }
+
+        let client = if self.config.isolate_clients {
+            Some((self.client)(req.extensions())?)
+        } else {
+            None
+        };
+
+        let mut hasher = DefaultHasher::new();
+        for name in KEY_HEADERS.iter() {
+            for value in req.headers().get_all(*name).iter() {
+                name.hash(&mut hasher);
+                value.as_bytes().hash(&mut hasher);
+            }
+        }
+
+        Some(Key {
+            method: req.method().clone(),
+            uri: req.uri().to_string(),
+            headers: hasher.finish(),
+            client,
+        })
+    }
+}
+
+/// Returns true if a request asks not to be served a cached response.
+fn bypasses_cache(headers: &http::HeaderMap) -> bool {
+    let cache_control = headers.get_all(header::CACHE_CONTROL);
+    let pragma = headers.get_all(header::PRAGMA);
+    cache_control
+        .iter()
+        .chain(pragma.iter())
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|directive| {
+            directive.eq_ignore_ascii_case("no-cache") || directive.eq_ignore_ascii_case("no-store")
+        })
+}
+
+// === impl ResponseFuture ===
+
+impl<S, A, B> Future for ResponseFuture<S, A, B>
+where
+    S: tower::Service<http::Request<A>, Response = http::Response<B>>,
+    S::Error: Into<Error>,
+    B: Payload,
+    B::Data: From<Bytes>,
+{
+    type Item = http::Response<Body<B>>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match *self {
+            ResponseFuture::Passthrough(ref mut f) => {
+                let rsp = try_ready!(f.poll().map_err(Into::into));
+                Ok(rsp.map(Body::Inner).into())
+            }
+            ResponseFuture::Lead(ref mut f) => f.poll(),
+            ResponseFuture::Follow(ref mut f) => f.poll(),
+        }
+    }
+}
+
+// === impl Leader ===
+
+impl<F, B> Leader<F, B>
+where
+    F: Future<Item = http::Response<B>>,
+    F::Error: Into<Error>,
+    B: Payload,
+    B::Data: From<Bytes>,
+{
+    fn poll_response(&mut self) -> Poll<http::Response<Body<B>>, Error> {
+        loop {
+            match self.state {
+                Leading::Pending(ref mut f) => {
+                    let (head, body) = try_ready!(f.poll().map_err(Into::into)).into_parts();
+                    self.state = Leading::Buffering {
+                        head,
+                        body,
+                        data: BytesMut::new(),
+                        eos: false,
+                    };
+                }
+                Leading::Buffering {
+                    ref mut body,
+                    ref mut data,
+                    ref mut eos,
+                    ..
+                } => {
+                    while !*eos {
+                        match try_ready!(body.poll_data().map_err(Into::into)) {
+                            Some(chunk) => {
+                                data.extend_from_slice(chunk.as_ref());
+                                if data.len() > self.max_body_bytes {
+                                    return Ok(self.stream().into());
+                                }
+                            }
+                            None => *eos = true,
+                        }
+                    }
+                    let trailers = try_ready!(body.poll_trailers().map_err(Into::into));
+                    return Ok(self.share(trailers).into());
+                }
+                Leading::Done => {
+                    let site = "coalesce::Leader::poll";
+                    return Err(
+                        InvariantViolation::new(site, "polled after complete".into()).into(),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Shares the buffered response with the requests that joined it.
+    fn share(&mut self, trailers: Option<http::HeaderMap>) -> http::Response<Body<B>> {
+        let (head, data) = match mem::replace(&mut self.state, Leading::Done) {
+            Leading::Buffering { head, data, .. } => (head, data.freeze()),
+            _ => unreachable!("response must be buffered"),
+        };
+
+        if let Some(key) = self.key.take() {
+            let buffered = Buffered {
+                status: head.status,
+                version: head.version,
+                headers: head.headers.clone(),
+                data: data.clone(),
+                trailers: trailers.clone(),
+            };
+            self.registry.publish(&key, Arc::new(buffered));
+        }
+
+        http::Response::from_parts(head, Body::buffered(data, trailers))
+    }
+
+    /// Streams a response that is too large to buffer, so that the requests
+    /// that joined it are dispatched on their own.
+    fn stream(&mut self) -> http::Response<Body<B>> {
+        if let Some(key) = self.key.take() {
+            debug!(?key, "response is too large to coalesce");
+            self.registry.uncoalesce(key);
+        }
+
+        match mem::replace(&mut self.state, Leading::Done) {
+            Leading::Buffering {
+                head, body, data, ..
+            } => http::Response::from_parts(
+                head,
+                Body::Partial {
+                    buffered: Some(data.freeze()),
+                    rest: body,
+                },
+            ),
+            _ => unreachable!("response must be buffering"),
+        }
+    }
+}
+
+impl<F, B> Leader<F, B> {
+    fn abandon(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.registry.abandon(&key);
+        }
+    }
+}
+
+impl<F, B> Future for Leader<F, B>
+where
+    F: Future<Item = http::Response<B>>,
+    F::Error: Into<Error>,
+    B: Payload,
+    B::Data: From<Bytes>,
+{
+    type Item = http::Response<Body<B>>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let poll = self.poll_response();
+        if poll.is_err() {
+            self.abandon();
+        }
+        poll
+    }
+}
+
+impl<F, B> Drop for Leader<F, B> {
+    fn drop(&mut self) {
+        self.abandon();
+    }
+}
+
+// === impl Follower ===
+
+impl<S, A, B> Future for Follower<S, A>
+where
+    S: tower::Service<http::Request<A>, Response = http::Response<B>>,
+    S::Error: Into<Error>,
+    B: Payload,
+    B::Data: From<Bytes>,
+{
+    type Item = http::Response<Body<B>>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            self.state = match self.state {
+                Following::Waiting(ref mut rx) => match rx.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(buffered)) => return Ok(buffered.to_response().into()),
+                    Err(oneshot::Canceled) => {
+                        debug!("in-flight response was not shared");
+                        Following::Dispatching
+                    }
+                },
+                Following::Dispatching => {
+                    try_ready!(self.inner.poll_ready().map_err(Into::into));
+                    let req = contract!(
+                        self.request.take(),
+                        "coalesce::Follower::poll",
+                        "request must only be dispatched once"
+                    )?;
+                    Following::Dispatched(self.inner.call(req))
+                }
+                Following::Dispatched(ref mut f) => {
+                    let rsp = try_ready!(f.poll().map_err(Into::into));
+                    return Ok(rsp.map(Body::Inner).into());
+                }
+            };
+        }
+    }
+}
+
+// === impl Registry ===
+
+impl Registry {
+    /// Joins the in-flight request with the same key, if there is one, or
+    /// registers a new in-flight request.
+    fn join(&self, key: &Key) -> Join {
+        let mut inner = match self.0.lock() {
+            Ok(inner) => inner,
+            Err(_) => return Join::Bypass,
+        };
+        if inner.uncoalesced.contains(key) {
+            return Join::Bypass;
+        }
+        if let Some(waiters) = inner.in_flight.get_mut(key) {
+            let (tx, rx) = oneshot::channel();
+            waiters.push(tx);
+            return Join::Follow(rx);
+        }
+        inner.in_flight.insert(key.clone(), Vec::new());
+        Join::Lead
+    }
+
+    fn publish(&self, key: &Key, rsp: Arc<Buffered>) {
+        if let Ok(mut inner) = self.0.lock() {
+            for tx in inner.in_flight.remove(key).into_iter().flatten() {
+                let _ = tx.send(rsp.clone());
+            }
+        }
+    }
+
+    /// Forgets an in-flight request, so that the requests that joined it are
+    /// dispatched on their own.
+    fn abandon(&self, key: &Key) {
+        if let Ok(mut inner) = self.0.lock() {
+            inner.in_flight.remove(key);
+        }
+    }
+
+    /// Abandons an in-flight request and stops coalescing requests with its
+    /// key.
+    fn uncoalesce(&self, key: Key) {
+        if let Ok(mut inner) = self.0.lock() {
+            inner.in_flight.remove(&key);
+            if !inner.uncoalesced.insert(key.clone()) {
+                return;
+            }
+            inner.uncoalesced_order.push_back(key);
+            if inner.uncoalesced_order.len() > MAX_UNCOALESCED_KEYS {
+                if let Some(oldest) = inner.uncoalesced_order.pop_front() {
+                    inner.uncoalesced.remove(&oldest);
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Registry").finish()
+    }
+}
+
+// === impl Buffered ===
+
+impl Buffered {
+    fn to_response<B>(&self) -> http::Response<Body<B>> {
+        let mut rsp = http::Response::new(Body::buffered(self.data.clone(), self.trailers.clone()));
+        *rsp.status_mut() = self.status;
+        *rsp.version_mut() = self.version;
+        *rsp.headers_mut() = self.headers.clone();
+        rsp
+    }
+}
+
+// === impl Body ===
+
+impl<B> Body<B> {
+    fn buffered(data: Bytes, trailers: Option<http::HeaderMap>) -> Self {
+        let data = if data.is_empty() { None } else { Some(data) };
+        Body::Buffered { data, trailers }
+    }
+}
+
+impl<B> Payload for Body<B>
+where
+    B: Payload,
+    B::Data: From<Bytes>,
+{
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn is_end_stream(&self) -> bool {
+        match *self {
+            Body::Inner(ref body) => body.is_end_stream(),
+            Body::Buffered {
+                ref data,
+                ref trailers,
+            } => data.is_none() && trailers.is_none(),
+            Body::Partial {
+                ref buffered,
+                ref rest,
+            } => buffered.is_none() && rest.is_end_stream(),
+        }
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        match *self {
+            Body::Inner(ref mut body) => body.poll_data(),
+            Body::Buffered { ref mut data, .. } => Ok(data.take().map(Into::into).into()),
+            Body::Partial {
+                ref mut buffered,
+                ref mut rest,
+            } => match buffered.take() {
+                Some(data) => Ok(Some(data.into()).into()),
+                None => rest.poll_data(),
+            },
+        }
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        match *self {
+            Body::Inner(ref mut body) => body.poll_trailers(),
+            Body::Buffered {
+                ref mut trailers, ..
+            } => Ok(trailers.take().into()),
+            Body::Partial { ref mut rest, .. } => rest.poll_trailers(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const CONFIG: Config = Config {
+        max_body_bytes: 1024,
+        isolate_clients: false,
+    };
+
+    #[derive(Clone)]
+    struct Client(&'static str);
+
+    fn client(extensions: &http::Extensions) -> Option<String> {
+        extensions.get::<Client>().map(|c| c.0.to_owned())
+    }
+
+    /// Serves `body` to each request, counting the requests it serves.
+    #[derive(Clone)]
+    struct Upstream {
+        body: &'static str,
+        requests: Arc<AtomicUsize>,
+    }
+
+    impl tower::Service<http::Request<hyper::Body>> for Upstream {
+        type Response = http::Response<hyper::Body>;
+        type Error = Error;
+        type Future = future::FutureResult<Self::Response, Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: http::Request<hyper::Body>) -> Self::Future {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            let rsp = http::Response::builder()
+                .header("x-upstream", "1")
+                .body(hyper::Body::from(self.body))
+                .unwrap();
+            future::ok(rsp)
+        }
+    }
+
+    fn upstream(body: &'static str) -> (Upstream, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let svc = Upstream {
+            body,
+            requests: requests.clone(),
+        };
+        (svc, requests)
+    }
+
+    fn get(path: &str) -> http::Request<hyper::Body> {
+        http::Request::get(path)
+            .header("host", "web.example.com")
+            .body(hyper::Body::empty())
+            .unwrap()
+    }
+
+    fn read(mut body: Body<hyper::Body>) -> Bytes {
+        let mut buf = BytesMut::new();
+        future::poll_fn(|| {
+            while let Some(chunk) = try_ready!(body.poll_data()) {
+                buf.extend_from_slice(chunk.as_ref());
+            }
+            Ok::<_, hyper::Error>(Async::Ready(()))
+        })
+        .wait()
+        .expect("body must be read");
+        buf.freeze()
+    }
+
+    fn coalesced(
+        inner: Upstream,
+        config: Config,
+    ) -> Service<Upstream, fn(&http::Extensions) -> Option<String>> {
+        Service {
+            inner,
+            coalesce: Some(Coalesce {
+                config,
+                client,
+                registry: Registry::default(),
+            }),
+        }
+    }
+
+    #[test]
+    fn concurrent_identical_requests_are_coalesced() {
+        let (inner, requests) = upstream("hello world");
+        let mut svc = coalesced(inner, CONFIG);
+
+        let rsps = (0..10)
+            .map(|_| svc.call(get("/config")))
+            .collect::<Vec<_>>();
+        let rsps = future::join_all(rsps)
+            .wait()
+            .expect("requests must succeed");
+
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert_eq!(rsps.len(), 10);
+        for rsp in rsps.into_iter() {
+            assert_eq!(rsp.headers().get("x-upstream").unwrap(), "1");
+            assert_eq!(read(rsp.into_body()), "hello world");
+        }
+    }
+
+    #[test]
+    fn large_responses_are_not_coalesced() {
+        let (inner, requests) = upstream("hello world");
+        let mut svc = coalesced(
+            inner,
+            Config {
+                max_body_bytes: 4,
+                ..CONFIG
+            },
+        );
+
+        let rsps = (0..3).map(|_| svc.call(get("/config"))).collect::<Vec<_>>();
+        let rsps = future::join_all(rsps)
+            .wait()
+            .expect("requests must succeed");
+        assert_eq!(
+            requests.load(Ordering::SeqCst),
+            3,
+            "requests that joined must be dispatched"
+        );
+        for rsp in rsps.into_iter() {
+            assert_eq!(read(rsp.into_body()), "hello world");
+        }
+
+        match svc.call(get("/config")) {
+            ResponseFuture::Passthrough(_) => {}
+            _ => panic!("requests must no longer be coalesced"),
+        }
+    }
+
+    #[test]
+    fn oldest_uncoalesced_keys_are_forgotten() {
+        let key = |n: usize| Key {
+            method: http::Method::GET,
+            uri: format!("/config/{}", n),
+            headers: 0,
+            client: None,
+        };
+        let registry = Registry::default();
+
+        for n in 0..=MAX_UNCOALESCED_KEYS {
+            registry.uncoalesce(key(n));
+        }
+        // Re-adding a remembered key does not displace another.
+        registry.uncoalesce(key(1));
+
+        match registry.join(&key(0)) {
+            Join::Lead => {}
+            _ => panic!("the oldest key must be forgotten"),
+        }
+        for n in 1..=MAX_UNCOALESCED_KEYS {
+            match registry.join(&key(n)) {
+                Join::Bypass => {}
+                _ => panic!("key {} must not be coalesced", n),
+            }
+        }
+    }
+
+    #[test]
+    fn only_cacheable_requests_from_the_same_client_are_coalesced() {
+        let (inner, requests) = upstream("hello world");
+        let mut svc = coalesced(
+            inner,
+            Config {
+                isolate_clients: true,
+                ..CONFIG
+            },
+        );
+
+        let mut reqs = Vec::new();
+        for name in &["a", "a", "b"] {
+            let mut req = get("/config");
+            req.extensions_mut().insert(Client(*name));
+            reqs.push(req);
+        }
+        let mut no_cache = get("/config");
+        no_cache.extensions_mut().insert(Client("a"));
+        no_cache.headers_mut().insert(
+            header::CACHE_CONTROL,
+            "max-age=0, no-cache".parse().unwrap(),
+        );
+        reqs.push(no_cache);
+        let mut post = get("/config");
+        post.extensions_mut().insert(Client("a"));
+        *post.method_mut() = http::Method::POST;
+        reqs.push(post);
+        // Requests from unidentified clients are not coalesced.
+        reqs.push(get("/config"));
+
+        let rsps = reqs
+            .into_iter()
+            .map(|req| svc.call(req))
+            .collect::<Vec<_>>();
+        future::join_all(rsps)
+            .wait()
+            .expect("requests must succeed");
+
+        assert_eq!(requests.load(Ordering::SeqCst), 5);
+    }
+}
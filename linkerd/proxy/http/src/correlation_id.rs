@@ -0,0 +1,300 @@
+//! Ensures that each request carries a correlation ID.
+//!
+//! When a request lacks the configured header, a random ID is generated and
+//! set on it. A request that already carries the header keeps its ID. Either
+//! way, the ID is also set on the response (unless the response already has
+//! one), so that clients can correlate a response with the logs of each system
+//! that handled its request.
+//!
+//! If the layer is configured to echo IDs, each request's ID is also recorded
+//! in its `Outcome` (and so in its access log and trace span annotations),
+//! and the request is served within a `correlation` span that carries the ID,
+//! so that it appears in each log emitted on the request's behalf.
+
+use crate::outcome::Outcome;
+use futures::{try_ready, Future, Poll};
+use http::header::{HeaderName, HeaderValue};
+use tracing::{info_span, trace};
+
+#[derive(Clone, Debug)]
+pub struct Layer {
+    header: Option<HeaderName>,
+    echo: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Stack<M> {
+    inner: M,
+    header: Option<HeaderName>,
+    echo: bool,
+}
+
+pub struct MakeFuture<F> {
+    inner: F,
+    header: Option<HeaderName>,
+    echo: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Service<S> {
+    inner: S,
+    header: Option<HeaderName>,
+    echo: bool,
+}
+
+pub struct ResponseFuture<F> {
+    inner: F,
+    id: Option<(HeaderName, HeaderValue)>,
+    span: Option<tracing::Span>,
+}
+
+/// Returns a layer that sets correlation IDs in `header`. If no header is
+/// configured, requests and responses are unchanged.
+pub fn layer(header: Option<HeaderName>) -> Layer {
+    Layer {
+        header,
+        echo: false,
+    }
+}
+
+/// Generates a random 128-bit ID, formatted as 32 hex digits.
+fn generate() -> HeaderValue {
+    let id = format!("{:032x}", rand::random::<u128>());
+    HeaderValue::from_str(&id).expect("hex digits must be a valid header value")
+}
+
+// === impl Layer ===
+
+impl Layer {
+    /// If set, each request's ID is recorded in its outcome and on the span
+    /// within which it is served.
+    pub fn with_echo(self, echo: bool) -> Self {
+        Self { echo, ..self }
+    }
+}
+
+impl<M> tower::layer::Layer<M> for Layer {
+    type Service = Stack<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        Stack {
+            inner,
+            header: self.header.clone(),
+            echo: self.echo,
+        }
+    }
+}
+
+// === impl Stack ===
+
+impl<T, M> tower::Service<T> for Stack<M>
+where
+    M: tower::Service<T>,
+{
+    type Response = Service<M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        MakeFuture {
+            inner: self.inner.call(target),
+            header: self.header.clone(),
+            echo: self.echo,
+        }
+    }
+}
+
+// === impl MakeFuture ===
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = Service<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        Ok(Service {
+            inner,
+            header: self.header.clone(),
+            echo: self.echo,
+        }
+        .into())
+    }
+}
+
+// === impl Service ===
+
+impl<S, A, B> tower::Service<http::Request<A>> for Service<S>
+where
+    S: tower::Service<http::Request<A>, Response = http::Response<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut req: http::Request<A>) -> Self::Future {
+        let id = self.header.as_ref().map(|header| {
+            let value = req
+                .headers_mut()
+                .entry(header)
+                .expect("header name must be valid")
+                .or_insert_with(|| {
+                    let id = generate();
+                    trace!(?id, "generated correlation ID");
+                    id
+                })
+                .clone();
+            (header.clone(), value)
+        });
+
+        let span = match id {
+            Some((_, ref value)) if self.echo => {
+                let id = String::from_utf8_lossy(value.as_bytes()).into_owned();
+                if let Some(outcome) = req.extensions().get::<Outcome>() {
+                    outcome.set_correlation_id(id.clone());
+                }
+                Some(info_span!("correlation", id = %id))
+            }
+            _ => None,
+        };
+
+        let inner = {
+            let _enter = span.as_ref().map(tracing::Span::enter);
+            self.inner.call(req)
+        };
+        ResponseFuture { inner, id, span }
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+{
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut rsp = {
+            let _enter = self.span.as_ref().map(tracing::Span::enter);
+            try_ready!(self.inner.poll())
+        };
+        if let Some((header, value)) = self.id.take() {
+            rsp.headers_mut()
+                .entry(header)
+                .expect("header name must be valid")
+                .or_insert(value);
+        }
+        Ok(rsp.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::outcome;
+    use futures::{future, sync::mpsc, Stream};
+    use linkerd2_trace_context as trace_context;
+    use tower::{layer::Layer as _, Service as _};
+
+    const HEADER: &str = "x-request-id";
+
+    /// Serves `req` with a service that echoes its correlation ID in
+    /// `x-echo`.
+    fn call(req: http::Request<()>) -> http::Response<()> {
+        let inner = tower::service_fn(|req: http::Request<()>| {
+            let mut rsp = http::Response::new(());
+            if let Some(id) = req.headers().get(HEADER) {
+                rsp.headers_mut().insert("x-echo", id.clone());
+            }
+            future::ok::<_, ()>(rsp)
+        });
+        let mut svc = Service {
+            inner,
+            header: Some(HeaderName::from_static(HEADER)),
+            echo: false,
+        };
+        svc.call(req).wait().unwrap()
+    }
+
+    #[test]
+    fn generates_missing_ids() {
+        let rsp = call(http::Request::new(()));
+
+        let id = rsp.headers().get(HEADER).expect("response must have an ID");
+        assert_eq!(id.len(), 32);
+        assert_eq!(
+            rsp.headers().get("x-echo"),
+            Some(id),
+            "request must have the same ID"
+        );
+
+        let other = call(http::Request::new(()));
+        assert_ne!(other.headers().get(HEADER), Some(id), "IDs must be unique");
+    }
+
+    #[test]
+    fn preserves_existing_ids() {
+        let req = http::Request::builder()
+            .header(HEADER, "abc123")
+            .body(())
+            .unwrap();
+        let rsp = call(req);
+
+        assert_eq!(rsp.headers()[HEADER], "abc123");
+        assert_eq!(rsp.headers()["x-echo"], "abc123");
+    }
+
+    #[test]
+    fn echoes_ids_in_outcome_and_span() {
+        let (spans_tx, spans_rx) = mpsc::unbounded();
+        let make = tower::service_fn(|()| {
+            let svc = tower::service_fn(|_: http::Request<()>| {
+                future::ok::<_, ()>(http::Response::new(()))
+            });
+            future::ok::<_, ()>(svc)
+        });
+        let make = layer(Some(HeaderName::from_static(HEADER)))
+            .with_echo(true)
+            .layer(make);
+        let make = outcome::layer().layer(make);
+        let mut make = trace_context::layer(Some(spans_tx)).layer(make);
+        let mut svc = make.call(()).wait().unwrap();
+
+        let outcome = outcome::Outcome::default();
+        let mut req = http::Request::builder()
+            .header("x-b3-traceid", "0123456789abcdef0123456789abcdef")
+            .header("x-b3-spanid", "0123456789abcdef")
+            .header("x-b3-sampled", "1")
+            .body(())
+            .unwrap();
+        req.extensions_mut().insert(outcome.clone());
+        let rsp = svc.call(req).wait().unwrap();
+        let id = rsp.headers()[HEADER].to_str().unwrap().to_owned();
+        // The outcome is final once the response body is dropped.
+        drop(rsp);
+
+        let record = outcome.get().expect("outcome must be final");
+        assert_eq!(record.correlation_id.as_ref(), Some(&id));
+
+        let span = spans_rx
+            .wait()
+            .next()
+            .expect("span must be emitted")
+            .unwrap();
+        let annotation = span
+            .annotations
+            .iter()
+            .find(|a| a.description == "outcome")
+            .expect("span must be annotated with the outcome");
+        assert_eq!(annotation.attributes.get("correlation_id"), Some(&id));
+    }
+}
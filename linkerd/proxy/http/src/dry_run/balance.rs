@@ -0,0 +1,333 @@
+//! Compares the balancer's choice of endpoint with least-request's.
+//!
+//! Least-request balancing would have dispatched each request to whichever
+//! ready endpoint has the fewest requests in flight, where a request is in
+//! flight until its response is received. The two agree if the endpoint that
+//! was chosen had no more requests in flight than any other ready endpoint.
+
+use super::{Metrics, Policy};
+use futures::{try_ready, Async, Future, Poll};
+use indexmap::IndexMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use tower_discover::Change;
+use tower_load::Load;
+
+/// Wraps each of a `Discover`'s endpoints so that the balancer's choices may
+/// be compared.
+pub struct Discover<D: tower_discover::Discover> {
+    inner: D,
+    endpoints: Option<Endpoints<D::Key>>,
+}
+
+pub struct Endpoint<S, K: Hash + Eq> {
+    inner: S,
+    tracked: Option<Tracked<K>>,
+}
+
+pub struct ResponseFuture<F, K: Hash + Eq> {
+    inner: F,
+    in_flight: Option<InFlight<K>>,
+}
+
+#[derive(Clone)]
+struct Endpoints<K: Hash + Eq> {
+    counts: Arc<Mutex<IndexMap<K, Counts>>>,
+    metrics: Metrics,
+}
+
+#[derive(Default)]
+struct Counts {
+    ready: bool,
+    in_flight: usize,
+}
+
+struct Tracked<K: Hash + Eq> {
+    key: K,
+    endpoints: Endpoints<K>,
+}
+
+/// Counts a request as in flight until it's dropped.
+struct InFlight<K: Hash + Eq> {
+    key: K,
+    endpoints: Endpoints<K>,
+}
+
+// === impl Discover ===
+
+impl<D> Discover<D>
+where
+    D: tower_discover::Discover,
+    D::Key: Hash + Eq,
+{
+    /// Compares the balancer's choices if `metrics` is set.
+    pub fn new(inner: D, metrics: Option<Metrics>) -> Self {
+        let endpoints = metrics.map(|metrics| Endpoints {
+            counts: Arc::new(Mutex::new(IndexMap::default())),
+            metrics,
+        });
+        Self { inner, endpoints }
+    }
+}
+
+impl<D> tower_discover::Discover for Discover<D>
+where
+    D: tower_discover::Discover,
+    D::Key: Hash + Eq + Clone,
+{
+    type Key = D::Key;
+    type Service = Endpoint<D::Service, D::Key>;
+    type Error = D::Error;
+
+    fn poll(&mut self) -> Poll<Change<Self::Key, Self::Service>, Self::Error> {
+        let change = match try_ready!(self.inner.poll()) {
+            Change::Insert(key, inner) => {
+                let tracked = self.endpoints.as_ref().map(|endpoints| {
+                    endpoints.insert(key.clone());
+                    Tracked {
+                        key: key.clone(),
+                        endpoints: endpoints.clone(),
+                    }
+                });
+                Change::Insert(key, Endpoint { inner, tracked })
+            }
+            Change::Remove(key) => {
+                if let Some(ref endpoints) = self.endpoints {
+                    endpoints.remove(&key);
+                }
+                Change::Remove(key)
+            }
+        };
+        Ok(Async::Ready(change))
+    }
+}
+
+// === impl Endpoints ===
+
+impl<K: Hash + Eq> Endpoints<K> {
+    fn insert(&self, key: K) {
+        if let Ok(mut counts) = self.counts.lock() {
+            counts.insert(key, Counts::default());
+        }
+    }
+
+    fn remove(&self, key: &K) {
+        if let Ok(mut counts) = self.counts.lock() {
+            counts.remove(key);
+        }
+    }
+
+    fn set_ready(&self, key: &K, ready: bool) {
+        if let Ok(mut counts) = self.counts.lock() {
+            if let Some(c) = counts.get_mut(key) {
+                c.ready = ready;
+            }
+        }
+    }
+
+    /// Records that a request was dispatched to `key`'s endpoint.
+    fn dispatch(&self, key: &K) {
+        let mut counts = match self.counts.lock() {
+            Ok(counts) => counts,
+            Err(_) => return,
+        };
+
+        let chosen = match counts.get(key) {
+            Some(c) => c.in_flight,
+            None => return,
+        };
+        let least = counts
+            .values()
+            .filter(|c| c.ready)
+            .map(|c| c.in_flight)
+            .min()
+            .unwrap_or(chosen);
+        self.metrics.record(Policy::Balancer, chosen <= least);
+
+        if let Some(c) = counts.get_mut(key) {
+            c.in_flight += 1;
+            // The endpoint must become ready again before it's dispatched to.
+            c.ready = false;
+        }
+    }
+
+    fn release(&self, key: &K) {
+        if let Ok(mut counts) = self.counts.lock() {
+            if let Some(c) = counts.get_mut(key) {
+                c.in_flight = c.in_flight.saturating_sub(1);
+            }
+        }
+    }
+}
+
+// === impl Endpoint ===
+
+impl<S: Load, K: Hash + Eq> Load for Endpoint<S, K> {
+    type Metric = S::Metric;
+
+    fn load(&self) -> Self::Metric {
+        self.inner.load()
+    }
+}
+
+impl<S, K, Req> tower::Service<Req> for Endpoint<S, K>
+where
+    S: tower::Service<Req>,
+    K: Hash + Eq + Clone,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future, K>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        let poll = self.inner.poll_ready();
+        if let Some(Tracked {
+            ref key,
+            ref endpoints,
+        }) = self.tracked
+        {
+            let ready = match poll {
+                Ok(Async::Ready(())) => true,
+                _ => false,
+            };
+            endpoints.set_ready(key, ready);
+        }
+        poll
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        let in_flight = self.tracked.as_ref().map(|Tracked { key, endpoints }| {
+            endpoints.dispatch(key);
+            InFlight {
+                key: key.clone(),
+                endpoints: endpoints.clone(),
+            }
+        });
+        ResponseFuture {
+            inner: self.inner.call(req),
+            in_flight,
+        }
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl<F: Future, K: Hash + Eq> Future for ResponseFuture<F, K> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let rsp = try_ready!(self.inner.poll().map_err(|e| {
+            self.in_flight = None;
+            e
+        }));
+        self.in_flight = None;
+        Ok(Async::Ready(rsp))
+    }
+}
+
+// === impl InFlight ===
+
+impl<K: Hash + Eq> Drop for InFlight<K> {
+    fn drop(&mut self) {
+        self.endpoints.release(&self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use rand::{rngs::SmallRng, SeedableRng};
+    use std::collections::VecDeque;
+    use tower::Service;
+    use tower_balance::p2c::Balance;
+
+    /// An endpoint with a fixed load, whose responses never complete.
+    struct Mock(usize);
+
+    struct MockDiscover(VecDeque<Change<usize, Mock>>);
+
+    impl tower::Service<()> for Mock {
+        type Response = ();
+        type Error = crate::Error;
+        type Future = future::Empty<(), crate::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, (): ()) -> Self::Future {
+            future::empty()
+        }
+    }
+
+    impl Load for Mock {
+        type Metric = usize;
+
+        fn load(&self) -> usize {
+            self.0
+        }
+    }
+
+    impl tower_discover::Discover for MockDiscover {
+        type Key = usize;
+        type Service = Mock;
+        type Error = crate::Error;
+
+        fn poll(&mut self) -> Poll<Change<usize, Mock>, Self::Error> {
+            Ok(self
+                .0
+                .pop_front()
+                .map(Async::Ready)
+                .unwrap_or(Async::NotReady))
+        }
+    }
+
+    fn dispatch<S: tower::Service<()>>(balance: &mut S) -> S::Future
+    where
+        S::Error: std::fmt::Debug,
+    {
+        future::poll_fn(|| balance.poll_ready())
+            .wait()
+            .expect("balancer must become ready");
+        balance.call(())
+    }
+
+    #[test]
+    fn counts_divergence_from_least_request() {
+        let metrics = Metrics::default();
+        // The balancer always prefers the endpoint with less load, even while
+        // it has more requests in flight.
+        let discover =
+            MockDiscover(vec![Change::Insert(0, Mock(0)), Change::Insert(1, Mock(100))].into());
+        let mut balance = Balance::new(
+            Discover::new(discover, Some(metrics.clone())),
+            SmallRng::seed_from_u64(0),
+        );
+
+        // Both endpoints are idle, so least-request agrees with the first
+        // choice but not with those that follow.
+        let first = dispatch(&mut balance);
+        assert_eq!(metrics.count(Policy::Balancer, true), 1);
+        let second = dispatch(&mut balance);
+        let third = dispatch(&mut balance);
+        assert_eq!(metrics.count(Policy::Balancer, false), 2);
+
+        // Once the in-flight requests complete, the endpoints are idle again.
+        drop((first, second, third));
+        let _fourth = dispatch(&mut balance);
+        assert_eq!(metrics.count(Policy::Balancer, true), 2);
+        assert_eq!(metrics.count(Policy::Balancer, false), 2);
+    }
+
+    #[test]
+    fn endpoints_are_not_tracked_without_metrics() {
+        let discover = MockDiscover(vec![Change::Insert(0, Mock(0))].into());
+        let mut discover = Discover::new(discover, None);
+        match tower_discover::Discover::poll(&mut discover) {
+            Ok(Async::Ready(Change::Insert(0, endpoint))) => assert!(endpoint.tracked.is_none()),
+            _ => panic!("endpoint must be inserted"),
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! Evaluates alternative policies alongside the policies in effect, without
+//! acting on them, so that a policy can be assessed before it's enabled.
+//!
+//! Each dry-run policy compares the decision it would have made with the
+//! decision that was made, and counts whether the two agree. Policies that
+//! aren't configured aren't evaluated.
+
+use indexmap::IndexMap;
+use linkerd2_metrics::{metrics, Counter, FmtLabels, FmtMetrics};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+pub mod balance;
+pub mod retry;
+
+metrics! {
+    policy_divergence_total: Counter {
+        "Total count of decisions, by whether a dry-run policy agreed with the policy in effect"
+    }
+}
+
+/// A policy that may be evaluated in a dry run.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Policy {
+    /// Least-request endpoint selection, compared with the balancer's.
+    Balancer,
+    /// An alternative retry condition, compared with each route's.
+    Retry,
+}
+
+/// Counts dry-run decisions, by policy and agreement.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics(Arc<Mutex<IndexMap<Decision, Counter>>>);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+struct Decision {
+    policy: Policy,
+    agree: bool,
+}
+
+// === impl Metrics ===
+
+impl Metrics {
+    fn record(&self, policy: Policy, agree: bool) {
+        if let Ok(mut decisions) = self.0.lock() {
+            decisions
+                .entry(Decision { policy, agree })
+                .or_insert_with(Counter::default)
+                .incr();
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn count(&self, policy: Policy, agree: bool) -> u64 {
+        self.0
+            .lock()
+            .unwrap()
+            .get(&Decision { policy, agree })
+            .map(|c| (*c).into())
+            .unwrap_or(0)
+    }
+}
+
+impl FmtMetrics for Metrics {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let decisions = match self.0.lock() {
+            Ok(decisions) => decisions,
+            Err(_) => return Ok(()),
+        };
+        if decisions.is_empty() {
+            return Ok(());
+        }
+
+        policy_divergence_total.fmt_help(f)?;
+        policy_divergence_total.fmt_scopes(f, decisions.iter(), |c| c)?;
+
+        Ok(())
+    }
+}
+
+// === impl Decision ===
+
+impl FmtLabels for Decision {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.policy {
+            Policy::Balancer => write!(f, "policy=\"balancer\"")?,
+            Policy::Retry => write!(f, "policy=\"retry\"")?,
+        }
+        write!(f, ",agree=\"{}\"", self.agree)
+    }
+}
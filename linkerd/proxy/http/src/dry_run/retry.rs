@@ -0,0 +1,116 @@
+//! Compares each route's retry decisions with an alternative retry condition.
+
+use super::{Metrics, Policy};
+use http::{Response, StatusCode};
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+/// Retries responses whose status is in any of a set of ranges.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Condition(Vec<RangeInclusive<u16>>);
+
+/// Indicates that a retry condition could not be parsed.
+#[derive(Debug, PartialEq, Eq)]
+pub struct InvalidCondition(());
+
+/// Records whether a `Condition` agrees with the retry decisions that were
+/// made.
+#[derive(Clone, Debug)]
+pub struct DryRun {
+    condition: Condition,
+    metrics: Metrics,
+}
+
+// === impl Condition ===
+
+impl Condition {
+    pub fn retries<B>(&self, rsp: &Response<B>) -> bool {
+        let status = rsp.status().as_u16();
+        self.0.iter().any(|r| r.contains(&status))
+    }
+}
+
+/// Parses a comma-separated list of statuses and inclusive status ranges,
+/// e.g. `500-599,429`.
+impl FromStr for Condition {
+    type Err = InvalidCondition;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        fn parse_status(s: &str) -> Result<u16, InvalidCondition> {
+            StatusCode::from_bytes(s.trim().as_bytes())
+                .map(|s| s.as_u16())
+                .map_err(|_| InvalidCondition(()))
+        }
+
+        let mut ranges = Vec::new();
+        for range in s.split(',') {
+            let mut bounds = range.splitn(2, '-');
+            let min = parse_status(bounds.next().unwrap_or(""))?;
+            let max = match bounds.next() {
+                Some(max) => parse_status(max)?,
+                None => min,
+            };
+            if max < min {
+                return Err(InvalidCondition(()));
+            }
+            ranges.push(min..=max);
+        }
+        Ok(Condition(ranges))
+    }
+}
+
+impl fmt::Display for InvalidCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid retry condition")
+    }
+}
+
+impl std::error::Error for InvalidCondition {}
+
+// === impl DryRun ===
+
+impl DryRun {
+    pub fn new(condition: Condition, metrics: Metrics) -> Self {
+        Self { condition, metrics }
+    }
+
+    /// Records whether the condition would have retried `rsp`, given whether
+    /// the route's policy would have.
+    ///
+    /// Retries that were prevented only by the budget count as retries, since
+    /// the budget applies regardless of the condition.
+    pub(crate) fn compare<B>(&self, rsp: &Response<B>, retried: bool) {
+        let agree = self.condition.retries(rsp) == retried;
+        self.metrics.record(Policy::Retry, agree);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(status: u16) -> Response<()> {
+        let mut rsp = Response::new(());
+        *rsp.status_mut() = StatusCode::from_u16(status).unwrap();
+        rsp
+    }
+
+    #[test]
+    fn parses_statuses_and_ranges() {
+        let condition = "500-599, 429".parse::<Condition>().unwrap();
+        assert!(condition.retries(&response(503)));
+        assert!(condition.retries(&response(429)));
+        assert!(!condition.retries(&response(404)));
+        assert!(!condition.retries(&response(200)));
+
+        for invalid in &["", "5xx", "599-500", "500-", "1000"] {
+            assert_eq!(
+                invalid.parse::<Condition>(),
+                Err(InvalidCondition(())),
+                "{:?} must not parse",
+                invalid
+            );
+        }
+    }
+}
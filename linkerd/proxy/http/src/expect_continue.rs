@@ -0,0 +1,174 @@
+//! Answers `Expect: 100-continue` on behalf of upstreams.
+//!
+//! A client that sends `Expect: 100-continue` withholds its request body
+//! until the server answers with `100 Continue` (or a final response). The
+//! proxy's server sends `100 Continue` as soon as the request body is first
+//! read, so the expectation is answered as soon as the request is
+//! dispatched, regardless of the upstream. This layer makes that explicit by
+//! removing the `Expect` header, so that upstreams never see an expectation
+//! that has already been answered.
+//!
+//! The expectation can't be forwarded instead: hyper's clients discard the
+//! upstream's `100 Continue` and surface only final responses, so the proxy
+//! has no signal on which to release the body other than the final response
+//! or a timeout. Holding every such request's body until a timeout elapses
+//! would delay each of them by the full timeout, so the expectation is always
+//! answered locally. (Informational responses are likewise never relayed; see
+//! `Server` in `linkerd2-app-core`.)
+
+use futures::{try_ready, Future, Poll};
+use http::header::EXPECT;
+use tracing::trace;
+
+#[derive(Clone, Debug, Default)]
+pub struct Layer(());
+
+#[derive(Clone, Debug)]
+pub struct Stack<M> {
+    inner: M,
+}
+
+pub struct MakeFuture<F> {
+    inner: F,
+}
+
+#[derive(Clone, Debug)]
+pub struct Service<S> {
+    inner: S,
+}
+
+pub fn layer() -> Layer {
+    Layer(())
+}
+
+/// Returns true if the request expects `100 Continue` before it sends its
+/// body.
+fn expects_continue<B>(req: &http::Request<B>) -> bool {
+    req.version() == http::Version::HTTP_11
+        && req
+            .headers()
+            .get(EXPECT)
+            .map(|v| v.as_bytes().eq_ignore_ascii_case(b"100-continue"))
+            .unwrap_or(false)
+}
+
+// === impl Layer ===
+
+impl<M> tower::layer::Layer<M> for Layer {
+    type Service = Stack<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        Stack { inner }
+    }
+}
+
+// === impl Stack ===
+
+impl<T, M> tower::Service<T> for Stack<M>
+where
+    M: tower::Service<T>,
+{
+    type Response = Service<M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        MakeFuture {
+            inner: self.inner.call(target),
+        }
+    }
+}
+
+// === impl MakeFuture ===
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = Service<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        Ok(Service { inner }.into())
+    }
+}
+
+// === impl Service ===
+
+impl<S, B> tower::Service<http::Request<B>> for Service<S>
+where
+    S: tower::Service<http::Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut req: http::Request<B>) -> Self::Future {
+        if expects_continue(&req) {
+            trace!("answering expectation locally");
+            req.headers_mut().remove(EXPECT);
+        }
+
+        self.inner.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::sync::oneshot;
+    use tower::Service as _;
+
+    fn request(expect: bool, version: http::Version) -> http::Request<()> {
+        let mut req = http::Request::builder();
+        req.method("POST")
+            .uri("http://example.com/")
+            .version(version);
+        if expect {
+            req.header(EXPECT, "100-continue");
+        }
+        req.body(()).unwrap()
+    }
+
+    /// Returns a service that hands each request to the test.
+    fn service() -> (
+        Service<impl tower::Service<http::Request<()>, Response = (), Error = ()>>,
+        oneshot::Receiver<http::Request<()>>,
+    ) {
+        let (tx, rx) = oneshot::channel();
+        let mut tx = Some(tx);
+        let inner = tower::service_fn(move |req| {
+            let _ = tx.take().expect("called once").send(req);
+            Ok::<_, ()>(())
+        });
+        (Service { inner }, rx)
+    }
+
+    #[test]
+    fn expectation_is_answered_locally() {
+        let (mut svc, req_rx) = service();
+
+        svc.call(request(true, http::Version::HTTP_11))
+            .wait()
+            .unwrap();
+        let req = req_rx.wait().expect("request must be dispatched");
+        assert!(!req.headers().contains_key(EXPECT));
+    }
+
+    #[test]
+    fn http10_requests_are_unchanged() {
+        let (mut svc, req_rx) = service();
+
+        svc.call(request(true, http::Version::HTTP_10))
+            .wait()
+            .unwrap();
+        let req = req_rx.wait().expect("request must be dispatched");
+        assert_eq!(req.headers()[EXPECT], "100-continue");
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{upgrade::Http11Upgrade, HasH2Reason};
+use crate::{h1, upgrade::Http11Upgrade, HasH2Reason};
 use futures::{try_ready, Async, Future, Poll};
 use http;
 use hyper::client::connect as hyper_connect;
@@ -21,6 +21,15 @@ pub struct HyperServerSvc<S> {
     service: S,
 }
 
+/// Frames responses to HTTP/1.0 requests so that their clients can parse
+/// them.
+pub struct HyperServerFuture<F> {
+    inner: F,
+    /// Set for HTTP/1.0 requests, indicating whether the request was a
+    /// `HEAD` request.
+    http10_head: Option<bool>,
+}
+
 /// Glue for any `tokio_connect::Connect` to implement `hyper::client::Connect`.
 #[derive(Debug, Clone)]
 pub struct HyperConnect<C, T> {
@@ -134,17 +143,40 @@ where
     type ReqBody = hyper::Body;
     type ResBody = B;
     type Error = S::Error;
-    type Future = S::Future;
+    type Future = HyperServerFuture<S::Future>;
 
     fn poll_ready(&mut self) -> Poll<(), Self::Error> {
         self.service.poll_ready()
     }
 
     fn call(&mut self, req: http::Request<Self::ReqBody>) -> Self::Future {
-        self.service.call(req.map(|b| HttpBody {
+        let http10_head = if req.version() == http::Version::HTTP_10 {
+            Some(*req.method() == http::Method::HEAD)
+        } else {
+            None
+        };
+        let inner = self.service.call(req.map(|b| HttpBody {
             body: Some(b),
             upgrade: None,
-        }))
+        }));
+        HyperServerFuture { inner, http10_head }
+    }
+}
+
+impl<F, B> Future for HyperServerFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+    B: Payload,
+{
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut rsp = try_ready!(self.inner.poll());
+        if let Some(head_request) = self.http10_head {
+            h1::set_http10_framing(&mut rsp, head_request);
+        }
+        Ok(Async::Ready(rsp))
     }
 }
 
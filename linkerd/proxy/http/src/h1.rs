@@ -1,10 +1,25 @@
 use super::upgrade::HttpConnect;
 use http;
-use http::header::{CONNECTION, HOST, UPGRADE};
+use http::header::{
+    HeaderValue, CONNECTION, CONTENT_LENGTH, HOST, PROXY_AUTHENTICATE, PROXY_AUTHORIZATION, TE,
+    TRAILER, TRANSFER_ENCODING, UPGRADE,
+};
 use http::uri::{Authority, Parts, Scheme, Uri};
+use hyper::body::Payload;
 use std::mem;
 use tracing::{debug, trace};
 
+/// Set in `http::Request::extensions` when an HTTP/1.0 request asked for its
+/// connection to be kept alive, since the `Connection` header that asked is
+/// stripped.
+#[derive(Copy, Clone, Debug)]
+pub struct KeepAlive(());
+
+/// Set in `http::Request::extensions` when a request's `TE` header accepted
+/// trailers, since the `TE` header is stripped.
+#[derive(Copy, Clone, Debug)]
+pub struct AcceptsTrailers(());
+
 /// Tries to make sure the `Uri` of the request is in a form needed by
 /// hyper's Client.
 pub fn normalize_our_view_of_uri<B>(req: &mut http::Request<B>) {
@@ -59,6 +74,20 @@ pub fn set_authority(uri: &mut http::Uri, auth: Authority) {
     *uri = new;
 }
 
+/// Replaces the scheme of an absolute-form URI.
+pub fn set_scheme(uri: &mut http::Uri, scheme: Scheme) {
+    debug_assert!(is_absolute_form(uri), "only absolute URIs have a scheme");
+    let mut parts = Parts::from(mem::replace(uri, Uri::default()));
+    parts.scheme = Some(scheme);
+    *uri = Uri::from_parts(parts).expect("absolute uri");
+}
+
+/// Removes hop-by-hop headers, as described by RFC 7230 §6.1.
+///
+/// These headers describe a single connection, so they must not be forwarded
+/// by a proxy. This includes the `Connection` header, each header that it
+/// nominates, and the headers that are hop-by-hop regardless of whether
+/// they're nominated.
 pub fn strip_connection_headers(headers: &mut http::HeaderMap) {
     if let Some(val) = headers.remove(CONNECTION) {
         if let Ok(conn_header) = val.to_str() {
@@ -77,10 +106,102 @@ pub fn strip_connection_headers(headers: &mut http::HeaderMap) {
     // Additionally, strip these "connection-level" headers always, since
     // they are otherwise illegal if upgraded to HTTP2.
     headers.remove(UPGRADE);
+    headers.remove(TE);
+    headers.remove(TRAILER);
+    if headers.remove(TRANSFER_ENCODING).is_some() {
+        // A message with both a transfer-encoding and a content-length is
+        // framed by its transfer-encoding, so its content-length must not be
+        // forwarded.
+        headers.remove(CONTENT_LENGTH);
+    }
+    headers.remove(PROXY_AUTHENTICATE);
+    headers.remove(PROXY_AUTHORIZATION);
     headers.remove("proxy-connection");
     headers.remove("keep-alive");
 }
 
+/// Strips a request's connection headers, recording whether an HTTP/1.0
+/// request asked for its connection to be kept alive and whether the request
+/// accepted trailers.
+pub fn strip_request_connection_headers<B>(req: &mut http::Request<B>) {
+    if wants_keep_alive(req) {
+        req.extensions_mut().insert(KeepAlive(()));
+    }
+    if wants_trailers(req) {
+        req.extensions_mut().insert(AcceptsTrailers(()));
+    }
+    strip_connection_headers(req.headers_mut());
+}
+
+/// Checks requests to determine if they accept trailers in their responses,
+/// either because their `TE` header includes `trailers` or because it did
+/// before it was stripped.
+pub fn wants_trailers<B>(req: &http::Request<B>) -> bool {
+    if req.extensions().get::<AcceptsTrailers>().is_some() {
+        return true;
+    }
+
+    req.headers()
+        .get_all(TE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|opt| {
+            let coding = opt.split(';').next().unwrap_or_default();
+            coding.trim().eq_ignore_ascii_case("trailers")
+        })
+}
+
+/// Frames a response to an HTTP/1.0 request so that the client can parse it.
+///
+/// HTTP/1.0 has no chunked transfer-encoding, so the response is framed by
+/// its content-length if it's known and by closing the connection otherwise.
+pub fn set_http10_framing<B: Payload>(rsp: &mut http::Response<B>, head_request: bool) {
+    *rsp.version_mut() = http::Version::HTTP_10;
+
+    let has_body = !head_request
+        && !rsp.status().is_informational()
+        && rsp.status() != http::StatusCode::NO_CONTENT
+        && rsp.status() != http::StatusCode::NOT_MODIFIED;
+    if !has_body {
+        return;
+    }
+
+    if rsp.headers_mut().remove(TRANSFER_ENCODING).is_some() {
+        rsp.headers_mut().remove(CONTENT_LENGTH);
+    }
+    if rsp.headers().contains_key(CONTENT_LENGTH) {
+        return;
+    }
+
+    match rsp.body().content_length() {
+        Some(len) => {
+            trace!(len, "framing HTTP/1.0 response by content-length");
+            rsp.headers_mut().insert(CONTENT_LENGTH, len.into());
+        }
+        None => {
+            trace!("framing HTTP/1.0 response by closing the connection");
+            rsp.headers_mut()
+                .insert(CONNECTION, HeaderValue::from_static("close"));
+        }
+    }
+}
+
+/// Checks HTTP/1.0 requests to determine if they want their connection to be
+/// kept alive. HTTP/1.1 connections are kept alive unless they're closed.
+fn wants_keep_alive<B>(req: &http::Request<B>) -> bool {
+    if req.version() != http::Version::HTTP_10 {
+        return false;
+    }
+
+    req.headers()
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|opt| opt.trim().eq_ignore_ascii_case("keep-alive"))
+}
+
 /// Checks requests to determine if they want to perform an HTTP upgrade.
 pub fn wants_upgrade<B>(req: &http::Request<B>) -> bool {
     // HTTP upgrades were added in 1.1, not 1.0.
@@ -182,3 +303,51 @@ pub fn is_bad_request<B>(req: &http::Request<B>) -> bool {
 
     false
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn http10_response(body: hyper::Body) -> http::Response<hyper::Body> {
+        let mut rsp = http::Response::new(body);
+        rsp.headers_mut()
+            .insert(TRANSFER_ENCODING, HeaderValue::from_static("chunked"));
+        set_http10_framing(&mut rsp, false);
+        rsp
+    }
+
+    #[test]
+    fn http10_response_is_framed_by_content_length() {
+        let rsp = http10_response(hyper::Body::from("hello"));
+        assert_eq!(rsp.version(), http::Version::HTTP_10);
+        assert!(!rsp.headers().contains_key(TRANSFER_ENCODING));
+        assert_eq!(rsp.headers()[CONTENT_LENGTH], "5");
+    }
+
+    #[test]
+    fn http10_response_of_unknown_length_closes_connection() {
+        let (_tx, body) = hyper::Body::channel();
+        let rsp = http10_response(body);
+        assert_eq!(rsp.version(), http::Version::HTTP_10);
+        assert!(!rsp.headers().contains_key(TRANSFER_ENCODING));
+        assert!(!rsp.headers().contains_key(CONTENT_LENGTH));
+        assert_eq!(rsp.headers()[CONNECTION], "close");
+    }
+
+    #[test]
+    fn trailers_are_wanted_after_te_is_stripped() {
+        let mut req = http::Request::builder()
+            .header(TE, "deflate, trailers;q=1")
+            .body(())
+            .unwrap();
+        strip_request_connection_headers(&mut req);
+        assert!(!req.headers().contains_key(TE));
+        assert!(wants_trailers(&req));
+
+        let req = http::Request::builder()
+            .header(TE, "deflate")
+            .body(())
+            .unwrap();
+        assert!(!wants_trailers(&req));
+    }
+}
@@ -1,5 +1,5 @@
 use super::Body;
-use futures::{try_ready, Future, Poll};
+use futures::{task::AtomicTask, try_ready, Async, Future, Poll};
 use http;
 use hyper::{
     body::Payload,
@@ -9,15 +9,37 @@ use linkerd2_error::Error;
 use linkerd2_proxy_transport::connect;
 use std::marker::PhantomData;
 use std::net::SocketAddr;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use std::time::{Duration, Instant};
 use tokio::executor::{DefaultExecutor, Executor};
 use tokio::io::{AsyncRead, AsyncWrite};
-use tracing::{debug, info_span};
+use tokio_timer::{clock, Delay};
+use tracing::{debug, info_span, trace};
 use tracing_futures::Instrument;
 
 #[derive(Copy, Clone, Debug, Default)]
 pub struct Settings {
     pub initial_stream_window_size: Option<u32>,
     pub initial_connection_window_size: Option<u32>,
+    /// If set, client connections limit their concurrent streams while they
+    /// warm up. Servers ignore this.
+    pub warmup: Option<Warmup>,
+}
+
+/// Limits the number of concurrent streams on a newly established client
+/// connection, so that a cold server isn't sent a burst of requests as soon
+/// as the connection is ready.
+///
+/// Like TCP slow start, the limit begins at `initial_streams` and doubles
+/// each tenth of `window`. Once `window` has elapsed, the connection is only
+/// limited by the server's `SETTINGS_MAX_CONCURRENT_STREAMS`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Warmup {
+    pub initial_streams: usize,
+    pub window: Duration,
 }
 
 #[derive(Debug)]
@@ -30,6 +52,7 @@ pub struct Connect<C, B> {
 #[derive(Debug)]
 pub struct Connection<B> {
     tx: SendRequest<B>,
+    warmup: Option<Ramp>,
 }
 
 pub struct ConnectFuture<F: Future, B> {
@@ -45,8 +68,34 @@ enum ConnectState<F: Future, B> {
 
 pub struct ResponseFuture {
     inner: conn::ResponseFuture,
+    stream: Option<Stream>,
 }
 
+/// Enforces a `Warmup` on a connection.
+#[derive(Debug)]
+struct Ramp {
+    warmup: Warmup,
+    established: Instant,
+    streams: Arc<Streams>,
+    /// Fires when the limit is next raised.
+    next_step: Option<Delay>,
+}
+
+#[derive(Debug, Default)]
+struct Streams {
+    active: AtomicUsize,
+    /// Notified as streams complete.
+    task: AtomicTask,
+}
+
+/// Counts as an active stream until it's dropped, once the stream's response
+/// headers are received.
+#[derive(Debug)]
+struct Stream(Arc<Streams>);
+
+/// The number of times the limit is doubled over a warmup window.
+const WARMUP_STEPS: u32 = 10;
+
 // ===== impl Connect =====
 
 impl<C, B> Connect<C, B> {
@@ -118,7 +167,8 @@ where
                         .spawn(Box::new(conn.map_err(|error| debug!(%error, "failed"))))
                         .map_err(Error::from)?;
 
-                    return Ok(Connection { tx }.into());
+                    let warmup = self.h2_settings.warmup.map(Ramp::new);
+                    return Ok(Connection { tx, warmup }.into());
                 }
             };
 
@@ -148,6 +198,11 @@ where
     type Future = ResponseFuture;
 
     fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        if let Some(ref mut warmup) = self.warmup {
+            if warmup.poll_ready().is_not_ready() {
+                return Ok(Async::NotReady);
+            }
+        }
         self.tx.poll_ready().map_err(From::from)
     }
 
@@ -166,12 +221,93 @@ where
             *req.version_mut() = http::Version::HTTP_11;
         }
 
+        let stream = match self.warmup {
+            Some(ref warmup) if !warmup.is_complete() => Some(warmup.stream()),
+            _ => {
+                // Once the connection has warmed up, streams no longer need
+                // to be tracked.
+                self.warmup = None;
+                None
+            }
+        };
+
         ResponseFuture {
             inner: self.tx.send_request(req),
+            stream,
         }
     }
 }
 
+// ===== impl Ramp =====
+
+impl Ramp {
+    fn new(warmup: Warmup) -> Self {
+        Self {
+            warmup,
+            established: clock::now(),
+            streams: Arc::new(Streams::default()),
+            next_step: None,
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        clock::now() - self.established >= self.warmup.window
+    }
+
+    /// Returns the current limit and the time at which it's next raised, or
+    /// `None` once the connection has warmed up.
+    fn limit(&self, now: Instant) -> Option<(usize, Instant)> {
+        let elapsed = now - self.established;
+        if elapsed >= self.warmup.window {
+            return None;
+        }
+
+        let step_width = self.warmup.window / WARMUP_STEPS;
+        let step = (elapsed.as_nanos() / step_width.as_nanos().max(1)) as u32;
+        let limit = self.warmup.initial_streams.max(1).saturating_mul(1 << step);
+        Some((limit, self.established + step_width * (step + 1)))
+    }
+
+    fn poll_ready(&mut self) -> Async<()> {
+        let (limit, next_step) = match self.limit(clock::now()) {
+            Some(limit) => limit,
+            None => return Async::Ready(()),
+        };
+
+        // Register for notification before checking the number of active
+        // streams so that a stream completing in between isn't missed.
+        self.streams.task.register();
+        let active = self.streams.active.load(Ordering::Acquire);
+        if active < limit {
+            return Async::Ready(());
+        }
+
+        trace!(active, limit, "warming up");
+        let delay = self.next_step.get_or_insert_with(|| Delay::new(next_step));
+        delay.reset(next_step);
+        match delay.poll() {
+            Ok(Async::NotReady) => Async::NotReady,
+            // The limit has been raised (or the timer has shut down, in
+            // which case the connection can't be held back).
+            Ok(Async::Ready(())) | Err(_) => Async::Ready(()),
+        }
+    }
+
+    fn stream(&self) -> Stream {
+        self.streams.active.fetch_add(1, Ordering::AcqRel);
+        Stream(self.streams.clone())
+    }
+}
+
+// ===== impl Stream =====
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::AcqRel);
+        self.0.task.notify();
+    }
+}
+
 // ===== impl ResponseFuture =====
 
 impl Future for ResponseFuture {
@@ -180,6 +316,7 @@ impl Future for ResponseFuture {
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         let res = try_ready!(self.inner.poll());
+        drop(self.stream.take());
         let res = res.map(|body| Body {
             body: Some(body),
             upgrade: None,
@@ -187,3 +324,59 @@ impl Future for ResponseFuture {
         Ok(res.into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+
+    fn warmup() -> Warmup {
+        Warmup {
+            initial_streams: 2,
+            window: Duration::from_millis(100),
+        }
+    }
+
+    #[test]
+    fn limit_doubles_until_window_elapses() {
+        let ramp = Ramp::new(warmup());
+        let limit_at = |ms| {
+            ramp.limit(ramp.established + Duration::from_millis(ms))
+                .map(|(limit, _)| limit)
+        };
+
+        assert_eq!(limit_at(0), Some(2));
+        assert_eq!(limit_at(9), Some(2));
+        assert_eq!(limit_at(10), Some(4));
+        assert_eq!(limit_at(55), Some(64));
+        assert_eq!(limit_at(99), Some(1024));
+        assert_eq!(limit_at(100), None, "limit must be lifted after the window");
+    }
+
+    #[test]
+    fn new_connection_limits_streams_then_relaxes() {
+        tokio::run(future::lazy(|| {
+            let mut ramp = Ramp::new(warmup());
+
+            let first = ramp.stream();
+            assert!(ramp.poll_ready().is_ready());
+            let second = ramp.stream();
+            assert!(
+                ramp.poll_ready().is_not_ready(),
+                "streams must be limited initially"
+            );
+
+            drop(first);
+            assert!(
+                ramp.poll_ready().is_ready(),
+                "a completed stream must free capacity"
+            );
+            let third = ramp.stream();
+            assert!(ramp.poll_ready().is_not_ready());
+
+            // Becomes ready once the limit is raised, without any streams
+            // completing.
+            future::poll_fn(move || Ok(ramp.poll_ready())).map(move |()| drop((second, third)))
+        }));
+    }
+}
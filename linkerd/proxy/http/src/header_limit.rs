@@ -0,0 +1,291 @@
+//! Limits the size of the response headers that upstreams may send.
+//!
+//! A header's size is measured as HTTP/2 measures it for
+//! `SETTINGS_MAX_HEADER_LIST_SIZE` (RFC 7540 §6.5.2): the length of its name
+//! and value, plus 32 bytes of overhead. The same measure applies to HTTP/1
+//! responses, so that a limit means the same thing for either protocol.
+//!
+//! Responses whose headers exceed the limit either fail, so that the client
+//! receives an error response, or have the headers that don't fit removed,
+//! depending on the limit's `Policy`.
+
+use futures::{try_ready, Future, Poll};
+use http::header::HeaderMap;
+use linkerd2_error::Error;
+use std::{error, fmt};
+use tracing::{debug, warn};
+
+/// The per-header overhead that HTTP/2 adds to each header's size.
+const HEADER_OVERHEAD: usize = 32;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Limit {
+    pub max_bytes: usize,
+    pub policy: Policy,
+}
+
+/// Determines what happens to responses whose headers exceed a `Limit`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// The response fails with `HeadersTooLarge`.
+    Reject,
+    /// Headers are retained, in order, until the next would exceed the
+    /// limit; it and every header after it are removed.
+    Truncate,
+}
+
+/// A response was rejected because its headers exceeded the limit.
+#[derive(Debug)]
+pub struct HeadersTooLarge {
+    size: usize,
+    max_bytes: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Layer {
+    limit: Option<Limit>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Stack<M> {
+    inner: M,
+    limit: Option<Limit>,
+}
+
+pub struct MakeFuture<F> {
+    inner: F,
+    limit: Option<Limit>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Service<S> {
+    inner: S,
+    limit: Option<Limit>,
+}
+
+pub struct ResponseFuture<F> {
+    inner: F,
+    limit: Option<Limit>,
+}
+
+/// Enforces `limit` on responses, if one is set.
+pub fn layer(limit: Option<Limit>) -> Layer {
+    Layer { limit }
+}
+
+/// Returns the size of `headers`, as HTTP/2 measures it.
+pub fn size(headers: &HeaderMap) -> usize {
+    headers.iter().map(|(n, v)| header_size(n, v)).sum()
+}
+
+fn header_size(name: &http::header::HeaderName, value: &http::HeaderValue) -> usize {
+    name.as_str().len() + value.len() + HEADER_OVERHEAD
+}
+
+// === impl Limit ===
+
+impl Limit {
+    /// Applies the limit to `headers`.
+    ///
+    /// Fails if the headers exceed the limit and the policy is `Reject`.
+    pub fn enforce(&self, headers: &mut HeaderMap) -> Result<(), HeadersTooLarge> {
+        let size = size(headers);
+        if size <= self.max_bytes {
+            return Ok(());
+        }
+
+        match self.policy {
+            Policy::Reject => Err(HeadersTooLarge {
+                size,
+                max_bytes: self.max_bytes,
+            }),
+            Policy::Truncate => {
+                let mut kept = HeaderMap::with_capacity(headers.len());
+                let mut kept_size = 0;
+                for (name, value) in headers.iter() {
+                    let sz = header_size(name, value);
+                    if kept_size + sz > self.max_bytes {
+                        break;
+                    }
+                    kept_size += sz;
+                    kept.append(name.clone(), value.clone());
+                }
+                warn!(
+                    size,
+                    max_bytes = self.max_bytes,
+                    removed = headers.len() - kept.len(),
+                    "truncated response headers"
+                );
+                *headers = kept;
+                Ok(())
+            }
+        }
+    }
+}
+
+// === impl HeadersTooLarge ===
+
+impl fmt::Display for HeadersTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "response headers of {} bytes exceed the limit of {} bytes",
+            self.size, self.max_bytes
+        )
+    }
+}
+
+impl error::Error for HeadersTooLarge {}
+
+// === impl Layer ===
+
+impl<M> tower::layer::Layer<M> for Layer {
+    type Service = Stack<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        Stack {
+            inner,
+            limit: self.limit,
+        }
+    }
+}
+
+// === impl Stack ===
+
+impl<T, M> tower::Service<T> for Stack<M>
+where
+    M: tower::Service<T>,
+{
+    type Response = Service<M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        MakeFuture {
+            inner: self.inner.call(target),
+            limit: self.limit,
+        }
+    }
+}
+
+// === impl MakeFuture ===
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = Service<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        Ok(Service {
+            inner,
+            limit: self.limit,
+        }
+        .into())
+    }
+}
+
+// === impl Service ===
+
+impl<S, Req, B> tower::Service<Req> for Service<S>
+where
+    S: tower::Service<Req, Response = http::Response<B>>,
+    S::Error: Into<Error>,
+{
+    type Response = S::Response;
+    type Error = Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        ResponseFuture {
+            inner: self.inner.call(req),
+            limit: self.limit,
+        }
+    }
+}
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+    F::Error: Into<Error>,
+{
+    type Item = F::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut rsp = try_ready!(self.inner.poll().map_err(Into::into));
+        if let Some(ref limit) = self.limit {
+            if let Err(e) = limit.enforce(rsp.headers_mut()) {
+                debug!(%e, "rejecting response");
+                return Err(e.into());
+            }
+        }
+        Ok(rsp.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+
+    fn upstream(
+        header_bytes: usize,
+    ) -> impl tower::Service<(), Response = http::Response<()>, Error = Error> {
+        tower::service_fn(move |()| {
+            let rsp = http::Response::builder()
+                .header("x-small", "1")
+                .header("x-large", "a".repeat(header_bytes).as_str())
+                .body(())
+                .unwrap();
+            future::ok::<_, Error>(rsp)
+        })
+    }
+
+    fn service<S>(inner: S, policy: Policy) -> Service<S> {
+        Service {
+            inner,
+            limit: Some(Limit {
+                max_bytes: 1024,
+                policy,
+            }),
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_headers() {
+        use tower::Service as _;
+
+        let mut svc = service(upstream(2048), Policy::Reject);
+        let err = svc.call(()).wait().expect_err("response must be rejected");
+        assert!(err.is::<HeadersTooLarge>(), "unexpected error: {}", err);
+    }
+
+    #[test]
+    fn passes_headers_under_the_limit() {
+        use tower::Service as _;
+
+        let mut svc = service(upstream(512), Policy::Reject);
+        let rsp = svc.call(()).wait().expect("response must pass");
+        assert_eq!(rsp.headers()["x-small"], "1");
+        assert_eq!(rsp.headers()["x-large"].len(), 512);
+    }
+
+    #[test]
+    fn truncates_oversized_headers() {
+        use tower::Service as _;
+
+        let mut svc = service(upstream(2048), Policy::Truncate);
+        let rsp = svc.call(()).wait().expect("response must pass");
+        assert_eq!(rsp.headers()["x-small"], "1");
+        assert!(!rsp.headers().contains_key("x-large"));
+        assert!(size(rsp.headers()) <= 1024);
+    }
+}
@@ -6,24 +6,39 @@ use linkerd2_error::Error;
 use linkerd2_identity as identity;
 
 pub mod add_header;
+pub mod affinity;
+pub mod avoid;
 pub mod balance;
 pub mod boxed;
 pub mod canonicalize;
+pub mod checksum;
 pub mod client;
+pub mod coalesce;
+pub mod correlation_id;
+pub mod dry_run;
+pub mod expect_continue;
 pub mod glue;
 pub mod grpc;
 pub mod h1;
 pub mod h2;
+pub mod header_limit;
 pub mod header_from_target;
 pub mod insert;
 pub mod metrics;
+pub mod min_ready;
+pub mod normalize_headers;
 pub mod normalize_uri;
 pub mod orig_proto;
+pub mod outcome;
+pub mod pool;
 pub mod profiles;
+pub mod response_reset;
 pub mod retry;
+pub mod sanitize_response;
 pub mod settings;
 pub mod strip_header;
 pub mod timeout;
+pub mod transform;
 pub mod upgrade;
 mod version;
 
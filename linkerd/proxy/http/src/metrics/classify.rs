@@ -42,6 +42,9 @@ pub trait ClassifyEos {
     /// Because trailers indicate an EOS, a classification must be returned.
     fn eos(self, trailers: Option<&http::HeaderMap>) -> Self::Class;
 
+    /// Update the classifier with the length of a chunk of body data.
+    fn data(&mut self, _len: usize) {}
+
     /// Update the classifier with an underlying error.
     ///
     /// Because errors indicate an end-of-stream, a classification must be
@@ -1,6 +1,6 @@
 use http;
 use indexmap::IndexMap;
-use linkerd2_metrics::{latency, Counter, FmtLabels, Histogram};
+use linkerd2_metrics::{latency, size, Counter, FmtLabels, Histogram};
 use std::hash::Hash;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
@@ -8,19 +8,30 @@ use tokio_timer::clock;
 
 pub mod classify;
 pub mod handle_time;
+mod rate;
 mod report;
 mod service;
 
-pub use self::{report::Report, service::layer};
+use self::rate::Rate;
+pub use self::{
+    report::{Report, Summary},
+    service::layer,
+};
 
 pub type SharedRegistry<T, C> = Arc<Mutex<Registry<T, C>>>;
 
-pub fn new<T, C>(retain_idle: Duration) -> (SharedRegistry<T, C>, Report<T, C>)
+/// Creates a registry of HTTP metrics and a report that formats them.
+///
+/// Request rates are estimated over the trailing `rate_window`.
+pub fn new<T, C>(
+    retain_idle: Duration,
+    rate_window: Duration,
+) -> (SharedRegistry<T, C>, Report<T, C>)
 where
     T: FmtLabels + Clone + Hash + Eq,
     C: FmtLabels + Hash + Eq,
 {
-    let registry = Arc::new(Mutex::new(Registry::default()));
+    let registry = Arc::new(Mutex::new(Registry::new(rate_window)));
     (registry.clone(), Report::new(retain_idle, registry))
 }
 
@@ -31,6 +42,9 @@ where
     C: Hash + Eq,
 {
     by_target: IndexMap<T, Arc<Mutex<RequestMetrics<C>>>>,
+    rate_window: Duration,
+    /// The index of the next target to be checked for eviction.
+    retain_cursor: usize,
 }
 
 pub trait Scoped<T> {
@@ -40,6 +54,10 @@ pub trait Scoped<T> {
 
 pub trait Stats {
     fn incr_retry_skipped_budget(&self);
+
+    /// Records that a request is being dispatched for the `attempt`th time,
+    /// where the original request is the first attempt.
+    fn incr_attempt(&self, attempt: usize);
 }
 
 #[derive(Debug)]
@@ -49,7 +67,16 @@ where
 {
     last_update: Instant,
     total: Counter,
+    rate: Rate,
+    request_size: Histogram<size::Bytes>,
+    response_size: Histogram<size::Bytes>,
     by_retry_skipped: IndexMap<RetrySkipped, Counter>,
+    /// Counts attempts by ordinal. Only retryable targets record attempts.
+    by_attempt: IndexMap<Attempt, Counter>,
+    /// The rates of original requests and of all attempts, the ratio of which
+    /// is the factor by which retries amplify the target's load.
+    original_rate: Rate,
+    attempt_rate: Rate,
     by_status: IndexMap<Option<http::StatusCode>, StatusMetrics<C>>,
 }
 
@@ -67,34 +94,102 @@ pub struct ClassMetrics {
     total: Counter,
 }
 
-#[derive(Debug, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 enum RetrySkipped {
     Budget,
 }
 
-impl<T, C> Default for Registry<T, C>
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum Attempt {
+    First,
+    Second,
+    ThirdOrLater,
+}
+
+impl<T, C> Registry<T, C>
 where
     T: Hash + Eq,
     C: Hash + Eq,
 {
-    fn default() -> Self {
+    fn new(rate_window: Duration) -> Self {
         Self {
             by_target: IndexMap::default(),
+            rate_window,
+            retain_cursor: 0,
         }
     }
-}
 
-impl<T, C> Registry<T, C>
-where
-    T: Hash + Eq,
-    C: Hash + Eq,
-{
-    /// Retains metrics for all targets that (1) no longer have an active
+    /// Returns the number of targets for which metrics are held.
+    pub fn target_count(&self) -> usize {
+        self.by_target.len()
+    }
+
+    /// Evicts metrics for all targets that (1) no longer have an active
     /// reference to the `RequestMetrics` structure and (2) have not been updated since `epoch`.
-    fn retain_since(&mut self, epoch: Instant) {
-        self.by_target.retain(|_, m| {
-            Arc::strong_count(&m) > 1 || m.lock().map(|m| m.last_update >= epoch).unwrap_or(false)
-        })
+    ///
+    /// At most `max` targets are checked, resuming where the previous call
+    /// left off, so that callers may bound how long the registry is locked.
+    /// Returns true once every target has been checked in the current pass.
+    fn retain_since(&mut self, epoch: Instant, max: usize) -> bool {
+        let mut checked = 0;
+        while checked < max && self.retain_cursor < self.by_target.len() {
+            let retain = {
+                let (_, m) = self
+                    .by_target
+                    .get_index(self.retain_cursor)
+                    .expect("retain cursor must be in bounds");
+                Arc::strong_count(m) > 1
+                    || m.lock().map(|m| m.last_update >= epoch).unwrap_or(false)
+            };
+            if retain {
+                self.retain_cursor += 1;
+            } else {
+                // The last target is moved into the evicted target's place,
+                // so it's checked next.
+                self.by_target.swap_remove_index(self.retain_cursor);
+            }
+            checked += 1;
+        }
+
+        if self.retain_cursor < self.by_target.len() {
+            return false;
+        }
+        self.retain_cursor = 0;
+        true
+    }
+
+    /// Returns the number of targets that have yet to be checked for
+    /// eviction in the current pass.
+    fn retain_backlog(&self) -> usize {
+        self.by_target.len().saturating_sub(self.retain_cursor)
+    }
+
+    /// Returns the metrics for `target`, creating them if necessary.
+    fn get_or_insert(&mut self, target: T) -> Arc<Mutex<RequestMetrics<C>>> {
+        let rate_window = self.rate_window;
+        self.by_target
+            .entry(target)
+            .or_insert_with(|| Arc::new(Mutex::new(RequestMetrics::new(rate_window))))
+            .clone()
+    }
+
+    /// Returns a new registry in which the metrics of all targets that
+    /// `summarize` maps to the same key are merged.
+    fn summarize<K, F>(&self, summarize: F, now: Instant) -> Registry<K, C>
+    where
+        K: Hash + Eq,
+        F: Fn(&T) -> K,
+        C: Clone,
+    {
+        let mut summary = Registry::new(self.rate_window);
+        for (target, metrics) in &self.by_target {
+            if let Ok(mut metrics) = metrics.lock() {
+                let merged = summary.get_or_insert(summarize(target));
+                let mut merged = merged.lock().expect("summary metrics lock");
+                merged.merge(&mut *metrics, now);
+            }
+        }
+        summary
     }
 }
 
@@ -108,10 +203,7 @@ where
     fn scoped(&self, target: T) -> Self::Scope {
         self.lock()
             .expect("metrics Registry lock")
-            .by_target
-            .entry(target)
-            .or_insert_with(|| Arc::new(Mutex::new(RequestMetrics::default())))
-            .clone()
+            .get_or_insert(target)
     }
 }
 
@@ -119,24 +211,117 @@ impl<C> RequestMetrics<C>
 where
     C: Hash + Eq,
 {
+    fn new(rate_window: Duration) -> Self {
+        let now = clock::now();
+        Self {
+            last_update: now,
+            total: Counter::default(),
+            rate: Rate::new(rate_window, now),
+            request_size: Histogram::default(),
+            response_size: Histogram::default(),
+            by_retry_skipped: IndexMap::default(),
+            by_attempt: IndexMap::default(),
+            original_rate: Rate::new(rate_window, now),
+            attempt_rate: Rate::new(rate_window, now),
+            by_status: IndexMap::default(),
+        }
+    }
+
+    fn incr_request(&mut self, now: Instant) {
+        self.last_update = now;
+        self.total.incr();
+        self.rate.incr(now);
+    }
+
+    fn record_request_size(&mut self, bytes: u64, now: Instant) {
+        self.last_update = now;
+        self.request_size.add(bytes);
+    }
+
+    fn record_response_size(&mut self, bytes: u64, now: Instant) {
+        self.last_update = now;
+        self.response_size.add(bytes);
+    }
+
     fn incr_retry_skipped(&mut self, reason: RetrySkipped) {
         self.by_retry_skipped
             .entry(reason)
             .or_insert_with(Counter::default)
             .incr();
     }
-}
 
-impl<C> Default for RequestMetrics<C>
-where
-    C: Hash + Eq,
-{
-    fn default() -> Self {
-        Self {
-            last_update: clock::now(),
-            total: Counter::default(),
-            by_retry_skipped: IndexMap::default(),
-            by_status: IndexMap::default(),
+    fn incr_attempt(&mut self, attempt: usize, now: Instant) {
+        self.last_update = now;
+        let attempt = match attempt {
+            0 | 1 => Attempt::First,
+            2 => Attempt::Second,
+            _ => Attempt::ThirdOrLater,
+        };
+        if attempt == Attempt::First {
+            self.original_rate.incr(now);
+        }
+        self.attempt_rate.incr(now);
+        self.by_attempt
+            .entry(attempt)
+            .or_insert_with(Counter::default)
+            .incr();
+    }
+
+    /// Returns the ratio of attempts to original requests over the rate
+    /// window, if the target records attempts.
+    fn attempt_amplification(&mut self, now: Instant) -> Option<f64> {
+        if self.by_attempt.is_empty() {
+            return None;
+        }
+        let originals = self.original_rate.events(now);
+        let attempts = self.attempt_rate.events(now);
+        if originals > 0 {
+            Some(attempts as f64 / originals as f64)
+        } else {
+            Some(0.0)
+        }
+    }
+
+    /// Adds all of the metrics recorded by `other` into these metrics.
+    fn merge(&mut self, other: &mut Self, now: Instant)
+    where
+        C: Clone,
+    {
+        self.last_update = self.last_update.max(other.last_update);
+        self.total += other.total;
+        self.rate.merge(&mut other.rate, now);
+        self.request_size.merge(&other.request_size);
+        self.response_size.merge(&other.response_size);
+
+        for (reason, count) in &other.by_retry_skipped {
+            *self
+                .by_retry_skipped
+                .entry(*reason)
+                .or_insert_with(Counter::default) += *count;
+        }
+
+        self.original_rate.merge(&mut other.original_rate, now);
+        self.attempt_rate.merge(&mut other.attempt_rate, now);
+        for (attempt, count) in &other.by_attempt {
+            *self
+                .by_attempt
+                .entry(*attempt)
+                .or_insert_with(Counter::default) += *count;
+        }
+
+        for (status, other) in &other.by_status {
+            let metrics = self
+                .by_status
+                .entry(*status)
+                .or_insert_with(StatusMetrics::default);
+            metrics.latency.merge(&other.latency);
+            for (class, other) in &other.by_class {
+                metrics
+                    .by_class
+                    .entry(class.clone())
+                    .or_insert_with(ClassMetrics::default)
+                    .total += other.total;
+            }
         }
     }
 }
@@ -151,6 +336,12 @@ where
             metrics.incr_retry_skipped(RetrySkipped::Budget);
         }
     }
+
+    fn incr_attempt(&self, attempt: usize) {
+        if let Ok(mut metrics) = self.lock() {
+            metrics.incr_attempt(attempt, clock::now());
+        }
+    }
 }
 
 impl<C> Default for StatusMetrics<C>
@@ -199,19 +390,16 @@ mod tests {
         }
 
         let retain_idle_for = Duration::from_secs(1);
-        let (r, report) = super::new::<Target, Class>(retain_idle_for);
+        let rate_window = Duration::from_secs(10);
+        let (r, report) = super::new::<Target, Class>(retain_idle_for, rate_window);
         let mut registry = r.lock().unwrap();
 
         let before_update = clock::now();
-        let metrics = registry
-            .by_target
-            .entry(Target(123))
-            .or_insert_with(|| Default::default())
-            .clone();
+        let metrics = registry.get_or_insert(Target(123));
         assert_eq!(registry.by_target.len(), 1, "target should be registered");
         let after_update = clock::now();
 
-        registry.retain_since(after_update);
+        registry.retain_since(after_update, usize::max_value());
         assert_eq!(
             registry.by_target.len(),
             1,
@@ -219,14 +407,14 @@ mod tests {
         );
 
         drop(metrics);
-        registry.retain_since(before_update);
+        registry.retain_since(before_update, usize::max_value());
         assert_eq!(
             registry.by_target.len(),
             1,
             "target should not be evicted by availability alone"
         );
 
-        registry.retain_since(after_update);
+        registry.retain_since(after_update, usize::max_value());
         assert_eq!(
             registry.by_target.len(),
             0,
@@ -235,4 +423,84 @@ mod tests {
 
         drop((registry, report));
     }
+
+    #[test]
+    fn summary_merges_targets() {
+        use crate::metrics::{FmtLabels, FmtMetrics};
+        use std::fmt;
+        use std::time::Duration;
+        use tokio_timer::clock;
+
+        #[derive(Clone, Debug, Hash, Eq, PartialEq)]
+        struct Endpoint {
+            dst: &'static str,
+            addr: usize,
+        }
+        impl FmtLabels for Endpoint {
+            fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "dst=\"{}\",addr=\"{}\"", self.dst, self.addr)
+            }
+        }
+
+        #[derive(Clone, Debug, Hash, Eq, PartialEq)]
+        struct Dst(&'static str);
+        impl FmtLabels for Dst {
+            fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "dst=\"{}\"", self.0)
+            }
+        }
+
+        #[derive(Clone, Debug, Hash, Eq, PartialEq)]
+        struct Class;
+        impl FmtLabels for Class {
+            fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "class=\"ok\"")
+            }
+        }
+
+        let (r, report) =
+            super::new::<Endpoint, Class>(Duration::from_secs(60), Duration::from_secs(10));
+        let summary = report.clone().summarize(|ep: &Endpoint| Dst(ep.dst));
+
+        let now = clock::now();
+        for addr in 1..=3 {
+            let metrics = r
+                .lock()
+                .unwrap()
+                .get_or_insert(Endpoint { dst: "web", addr });
+            let mut metrics = metrics.lock().unwrap();
+            for _ in 0..addr {
+                metrics.incr_request(now);
+                let status = metrics
+                    .by_status
+                    .entry(Some(http::StatusCode::OK))
+                    .or_insert_with(super::StatusMetrics::default);
+                status.latency.add(Duration::from_millis(5));
+                status
+                    .by_class
+                    .entry(Class)
+                    .or_insert_with(super::ClassMetrics::default)
+                    .total
+                    .incr();
+            }
+        }
+
+        let full = report.as_display().to_string();
+        assert!(full.contains("request_total{dst=\"web\",addr=\"1\"} 1\n"));
+        assert!(full.contains("request_total{dst=\"web\",addr=\"3\"} 3\n"));
+
+        let summary = summary.as_display().to_string();
+        let request_totals = summary
+            .lines()
+            .filter(|l| l.starts_with("request_total{"))
+            .collect::<Vec<_>>();
+        assert_eq!(request_totals, vec!["request_total{dst=\"web\"} 6"]);
+        assert!(
+            summary.contains("response_total{dst=\"web\",status_code=\"200\",class=\"ok\"} 6\n")
+        );
+        assert!(summary.contains("response_latency_ms_count{dst=\"web\",status_code=\"200\"} 6\n"));
+
+        // The registry still holds each endpoint's metrics.
+        assert_eq!(r.lock().unwrap().target_count(), 3);
+    }
 }
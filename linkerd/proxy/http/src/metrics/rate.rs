@@ -0,0 +1,170 @@
+use std::time::{Duration, Instant};
+
+/// The number of buckets that a window is divided into.
+const BUCKETS: u32 = 10;
+
+/// Estimates the rate of events per second over a sliding window.
+///
+/// The window is divided into a fixed number of buckets so that events are
+/// forgotten a bucket at a time as they age out of the window, rather than
+/// all at once.
+#[derive(Debug)]
+pub struct Rate {
+    bucket_width: Duration,
+    counts: [u64; BUCKETS as usize],
+    /// The index of the bucket that counts the most recent events.
+    head: usize,
+    /// The time at which the head bucket started.
+    head_start: Instant,
+    /// The time at which the estimator was created, so that the rate isn't
+    /// underestimated before a full window has elapsed.
+    created_at: Instant,
+}
+
+impl Rate {
+    pub fn new(window: Duration, now: Instant) -> Self {
+        Self {
+            bucket_width: (window / BUCKETS).max(Duration::from_millis(1)),
+            counts: [0; BUCKETS as usize],
+            head: 0,
+            head_start: now,
+            created_at: now,
+        }
+    }
+
+    /// Records an event at `now`.
+    pub fn incr(&mut self, now: Instant) {
+        self.advance(now);
+        self.counts[self.head] = self.counts[self.head].saturating_add(1);
+    }
+
+    /// Returns the number of events per second over the window ending at
+    /// `now`.
+    pub fn per_second(&mut self, now: Instant) -> f64 {
+        let events = self.events(now);
+
+        // The head bucket has only been counting since `head_start`, so the
+        // window actually covered is slightly shorter than the full window.
+        let covered = self.bucket_width * (BUCKETS - 1) + since(now, self.head_start);
+        let span = covered
+            .min(since(now, self.created_at))
+            .max(self.bucket_width);
+        events as f64 / span.as_secs_f64()
+    }
+
+    /// Returns the number of events recorded within the window ending at
+    /// `now`.
+    pub fn events(&mut self, now: Instant) -> u64 {
+        self.advance(now);
+        self.counts.iter().sum()
+    }
+
+    /// Adds the events recorded by `other` into this estimator, so that its
+    /// rate becomes the sum of both rates at `now`.
+    ///
+    /// Both estimators must have been created with the same window.
+    pub fn merge(&mut self, other: &mut Rate, now: Instant) {
+        debug_assert_eq!(self.bucket_width, other.bucket_width);
+        self.advance(now);
+        other.advance(now);
+
+        // Buckets are aligned by age: each estimator's head bucket counts its
+        // most recent events, regardless of exactly when that bucket started.
+        let len = self.counts.len();
+        for age in 0..len {
+            let i = (self.head + len - age) % len;
+            let j = (other.head + len - age) % len;
+            self.counts[i] = self.counts[i].saturating_add(other.counts[j]);
+        }
+        self.created_at = self.created_at.min(other.created_at);
+    }
+
+    /// Moves the head to the bucket containing `now`, clearing the buckets
+    /// that have aged out of the window.
+    fn advance(&mut self, now: Instant) {
+        if since(now, self.head_start) >= self.bucket_width * BUCKETS {
+            // Nothing has been recorded within the window.
+            self.counts = [0; BUCKETS as usize];
+            self.head_start = now;
+            return;
+        }
+
+        while since(now, self.head_start) >= self.bucket_width {
+            self.head = (self.head + 1) % self.counts.len();
+            self.counts[self.head] = 0;
+            self.head_start += self.bucket_width;
+        }
+    }
+}
+
+fn since(now: Instant, earlier: Instant) -> Duration {
+    now.checked_duration_since(earlier).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(10);
+
+    /// Records 10 events per second for 30 seconds.
+    fn steady(rate: &mut Rate, start: Instant) -> Instant {
+        let interval = Duration::from_millis(100);
+        let mut now = start;
+        for _ in 0..300 {
+            now += interval;
+            rate.incr(now);
+        }
+        now
+    }
+
+    #[test]
+    fn steady_stream() {
+        let start = Instant::now();
+        let mut rate = Rate::new(WINDOW, start);
+        let end = steady(&mut rate, start);
+
+        let rps = rate.per_second(end);
+        assert!((rps - 10.0).abs() < 0.5, "rate={}", rps);
+    }
+
+    #[test]
+    fn steady_stream_before_window_elapses() {
+        let start = Instant::now();
+        let mut rate = Rate::new(Duration::from_secs(60), start);
+        let end = steady(&mut rate, start);
+
+        let rps = rate.per_second(end);
+        assert!((rps - 10.0).abs() < 0.5, "rate={}", rps);
+    }
+
+    #[test]
+    fn decays_when_traffic_stops() {
+        let start = Instant::now();
+        let mut rate = Rate::new(WINDOW, start);
+        let end = steady(&mut rate, start);
+
+        let mut prior = rate.per_second(end);
+        for secs in 1..WINDOW.as_secs() {
+            let rps = rate.per_second(end + Duration::from_secs(secs));
+            assert!(rps < prior, "rate must decay: {} >= {}", rps, prior);
+            prior = rps;
+        }
+
+        assert_eq!(rate.per_second(end + WINDOW), 0.0);
+        assert_eq!(rate.per_second(end + WINDOW * 2), 0.0);
+    }
+
+    #[test]
+    fn merged_rates_sum() {
+        let start = Instant::now();
+        let mut a = Rate::new(WINDOW, start);
+        let mut b = Rate::new(WINDOW, start);
+        steady(&mut a, start);
+        let end = steady(&mut b, start);
+
+        a.merge(&mut b, end);
+        let rps = a.per_second(end);
+        assert!((rps - 20.0).abs() < 1.0, "rate={}", rps);
+    }
+}
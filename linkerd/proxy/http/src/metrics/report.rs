@@ -1,13 +1,25 @@
-use super::{ClassMetrics, Registry, RequestMetrics, RetrySkipped, StatusMetrics};
+use super::{Attempt, ClassMetrics, Registry, RequestMetrics, RetrySkipped, StatusMetrics};
 use http;
-use linkerd2_metrics::{latency, Counter, FmtLabels, FmtMetric, FmtMetrics, Histogram, Metric};
+use linkerd2_metrics::{
+    latency, size, Counter, FmtLabels, FmtMetric, FmtMetrics, Histogram, Metric,
+};
 use std::fmt;
 use std::hash::Hash;
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 use tokio_timer::clock;
 use tracing::trace;
 
+/// The maximum number of targets that are checked for eviction, or copied for
+/// formatting, each time the registry is locked.
+const LOCKED_BATCH: usize = 1_000;
+
+/// The maximum number of targets that each scrape checks for eviction. Any
+/// remaining targets are checked by later scrapes and are reported as the
+/// registry's maintenance backlog.
+const MAX_RETAIN_PER_SCRAPE: usize = 10 * LOCKED_BATCH;
+
 /// Reports HTTP metrics for prometheus.
 #[derive(Clone, Debug)]
 pub struct Report<T, C>
@@ -21,14 +33,52 @@ where
     retain_idle: Duration,
 }
 
+/// Reports HTTP metrics for prometheus, merging the metrics of all targets
+/// that map to the same summary key.
+///
+/// Targets are summarized each time metrics are formatted, so the registry
+/// only ever holds metrics for the original targets.
+#[derive(Clone)]
+pub struct Summary<T, C, F>
+where
+    T: FmtLabels + Hash + Eq,
+    C: FmtLabels + Hash + Eq,
+{
+    report: Report<T, C>,
+    summarize: F,
+}
+
 struct Status(http::StatusCode);
 
+/// A locked registry.
+///
+/// In tests, the most targets that are visited while a registry is held
+/// locked is recorded so that scrapes can be shown to hold it only briefly.
+struct Locked<'a, T, C>
+where
+    T: Hash + Eq,
+    C: Hash + Eq,
+{
+    registry: MutexGuard<'a, Registry<T, C>>,
+    #[cfg(test)]
+    visited: usize,
+}
+
+/// A floating-point gauge, e.g. of events per second.
+struct Gauge(f64);
+
 #[derive(Clone, Debug)]
 struct Scope {
     request_total_key: String,
+    request_rate_key: String,
+    request_size_bytes_key: String,
+    response_size_bytes_key: String,
     response_total_key: String,
     response_latency_ms_key: String,
     retry_skipped_total_key: String,
+    attempt_total_key: String,
+    attempt_amplification_key: String,
+    maintenance_backlog_key: String,
 }
 
 // ===== impl Report =====
@@ -58,21 +108,61 @@ where
             ..self
         }
     }
+
+    /// Returns a report of the same metrics in which all targets that
+    /// `summarize` maps to the same key are reported as a single target.
+    pub fn summarize<K, F>(self, summarize: F) -> Summary<T, C, F>
+    where
+        K: FmtLabels + Hash + Eq,
+        F: Fn(&T) -> K,
+    {
+        Summary {
+            report: self,
+            summarize,
+        }
+    }
 }
 
 impl<T, C> FmtMetrics for Report<T, C>
 where
-    T: FmtLabels + Hash + Eq,
+    T: FmtLabels + Clone + Hash + Eq,
     C: FmtLabels + Hash + Eq,
 {
     fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         trace!("fmt_metrics({})", self.prefix);
-        let mut registry = match self.registry.lock() {
-            Err(_) => return Ok(()),
-            Ok(r) => r,
+        let now = clock::now();
+        let backlog = match self.retain_idle(now) {
+            Some(backlog) => backlog,
+            None => return Ok(()),
         };
 
-        let now = clock::now();
+        // The targets are formatted from a snapshot so that the registry
+        // isn't locked while the metrics are written.
+        let registry = match self.snapshot() {
+            Some(registry) => registry,
+            None => return Ok(()),
+        };
+        trace!(
+            "fmt_metrics({}): by_target={} backlog={}",
+            self.prefix,
+            registry.by_target.len(),
+            backlog
+        );
+        self.scope.fmt_registry(f, &registry, backlog, now)
+    }
+}
+
+impl<T, C> Report<T, C>
+where
+    T: FmtLabels + Hash + Eq,
+    C: FmtLabels + Hash + Eq,
+{
+    /// Evicts the metrics of idle targets, locking the registry for at most
+    /// `LOCKED_BATCH` targets at a time.
+    ///
+    /// Returns the number of targets that are left to be checked by later
+    /// scrapes, or `None` if the registry's lock is poisoned.
+    fn retain_idle(&self, now: Instant) -> Option<usize> {
         let since = now - self.retain_idle;
         trace!(
             "fmt_metrics({}): retain_since: now={:?} since={:?}",
@@ -80,31 +170,155 @@ where
             now,
             since
         );
-        registry.retain_since(since);
+        let mut checked = 0;
+        loop {
+            let mut registry = self.lock()?;
+            let backlog = registry.retain_backlog();
+            if registry.retain_since(since, LOCKED_BATCH) {
+                registry.visited(backlog);
+                return Some(0);
+            }
+            // Each target that's checked is either retained or evicted, so
+            // the backlog shrinks by one for each.
+            let remaining = registry.retain_backlog();
+            registry.visited(backlog - remaining);
+            checked += LOCKED_BATCH;
+            if checked >= MAX_RETAIN_PER_SCRAPE {
+                return Some(registry.retain_backlog());
+            }
+        }
+    }
+
+    /// Copies the registry's targets, and handles to their metrics, locking
+    /// the registry for at most `LOCKED_BATCH` targets at a time.
+    ///
+    /// Targets that are evicted concurrently (i.e. by another scrape) may be
+    /// omitted from the snapshot.
+    fn snapshot(&self) -> Option<Registry<T, C>>
+    where
+        T: Clone,
+    {
+        let mut snapshot: Option<Registry<T, C>> = None;
+        let mut start = 0;
+        loop {
+            let mut registry = self.lock()?;
+            let snapshot = snapshot.get_or_insert_with(|| Registry::new(registry.rate_window));
+            let end = registry.by_target.len().min(start + LOCKED_BATCH);
+            for i in start..end {
+                let (target, metrics) = registry
+                    .by_target
+                    .get_index(i)
+                    .expect("index must be in bounds");
+                // An eviction between batches may move a target that has
+                // already been copied.
+                snapshot
+                    .by_target
+                    .entry(target.clone())
+                    .or_insert_with(|| metrics.clone());
+            }
+            registry.visited(end.saturating_sub(start));
+            if end == registry.by_target.len() {
+                break;
+            }
+            start = end;
+        }
+        snapshot
+    }
+
+    fn lock(&self) -> Option<Locked<'_, T, C>> {
+        let registry = self.registry.lock().ok()?;
+        Some(Locked {
+            registry,
+            #[cfg(test)]
+            visited: 0,
+        })
+    }
+}
+
+impl<T, C, K, F> FmtMetrics for Summary<T, C, F>
+where
+    T: FmtLabels + Clone + Hash + Eq,
+    C: FmtLabels + Hash + Eq + Clone,
+    K: FmtLabels + Hash + Eq,
+    F: Fn(&T) -> K,
+{
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let now = clock::now();
+        let backlog = match self.report.retain_idle(now) {
+            Some(backlog) => backlog,
+            None => return Ok(()),
+        };
+        let summary = match self.report.snapshot() {
+            Some(registry) => registry.summarize(&self.summarize, now),
+            None => return Ok(()),
+        };
 
-        let registry = registry;
         trace!(
-            "fmt_metrics({}): by_target={}",
-            self.prefix,
-            registry.by_target.len()
+            "fmt_metrics({}): summarized {} targets",
+            self.report.prefix,
+            summary.by_target.len()
         );
-        if registry.by_target.is_empty() {
-            return Ok(());
-        }
+        self.report.scope.fmt_registry(f, &summary, backlog, now)
+    }
+}
 
-        self.scope.request_total().fmt_help(f)?;
-        registry.fmt_by_target(f, self.scope.request_total(), |s| &s.total)?;
+// === impl Locked ===
 
-        self.scope.response_latency_ms().fmt_help(f)?;
-        registry.fmt_by_status(f, self.scope.response_latency_ms(), |s| &s.latency)?;
+impl<'a, T, C> Locked<'a, T, C>
+where
+    T: Hash + Eq,
+    C: Hash + Eq,
+{
+    /// Records that `n` targets were visited while the registry was locked.
+    #[cfg(test)]
+    fn visited(&mut self, n: usize) {
+        self.visited += n;
+    }
 
-        self.scope.response_total().fmt_help(f)?;
-        registry.fmt_by_class(f, self.scope.response_total(), |s| &s.total)?;
+    #[cfg(not(test))]
+    fn visited(&mut self, _: usize) {}
+}
 
-        self.scope.retry_skipped_total().fmt_help(f)?;
-        registry.fmt_by_retry(f, self.scope.retry_skipped_total())?;
+impl<'a, T, C> Deref for Locked<'a, T, C>
+where
+    T: Hash + Eq,
+    C: Hash + Eq,
+{
+    type Target = Registry<T, C>;
 
-        Ok(())
+    fn deref(&self) -> &Self::Target {
+        &*self.registry
+    }
+}
+
+impl<'a, T, C> DerefMut for Locked<'a, T, C>
+where
+    T: Hash + Eq,
+    C: Hash + Eq,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut *self.registry
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    static MAX_VISITED: std::cell::Cell<usize> = std::cell::Cell::new(0);
+}
+
+#[cfg(test)]
+impl<'a, T, C> Drop for Locked<'a, T, C>
+where
+    T: Hash + Eq,
+    C: Hash + Eq,
+{
+    fn drop(&mut self) {
+        let visited = self.visited;
+        MAX_VISITED.with(|max| {
+            if visited > max.get() {
+                max.set(visited);
+            }
+        });
     }
 }
 
@@ -123,9 +337,62 @@ where
         M: FmtMetric,
         F: Fn(&RequestMetrics<C>) -> &M,
     {
+        let name = metric.sample_name(f);
         for (tgt, tm) in &self.by_target {
             if let Ok(m) = tm.lock() {
-                get_metric(&*m).fmt_metric_labeled(f, metric.name, tgt)?;
+                get_metric(&*m).fmt_metric_labeled(f, &name, tgt)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn fmt_request_rate(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        metric: Metric<'_, Gauge>,
+        now: Instant,
+    ) -> fmt::Result {
+        let name = metric.sample_name(f);
+        for (tgt, tm) in &self.by_target {
+            if let Ok(mut tm) = tm.lock() {
+                let rate = Gauge(tm.rate.per_second(now));
+                rate.fmt_metric_labeled(f, &name, tgt)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn fmt_attempt_amplification(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        metric: Metric<'_, Gauge>,
+        now: Instant,
+    ) -> fmt::Result {
+        let name = metric.sample_name(f);
+        for (tgt, tm) in &self.by_target {
+            if let Ok(mut tm) = tm.lock() {
+                if let Some(amplification) = tm.attempt_amplification(now) {
+                    Gauge(amplification).fmt_metric_labeled(f, &name, tgt)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn fmt_by_attempt<M>(&self, f: &mut fmt::Formatter<'_>, metric: Metric<'_, M>) -> fmt::Result
+    where
+        M: FmtMetric,
+    {
+        let name = metric.sample_name(f);
+        for (tgt, tm) in &self.by_target {
+            if let Ok(tm) = tm.lock() {
+                for (attempt, m) in &tm.by_attempt {
+                    let labels = (tgt, attempt);
+                    m.fmt_metric_labeled(f, &name, labels)?;
+                }
             }
         }
 
@@ -136,11 +403,12 @@ where
     where
         M: FmtMetric,
     {
+        let name = metric.sample_name(f);
         for (tgt, tm) in &self.by_target {
             if let Ok(tm) = tm.lock() {
                 for (retry, m) in &tm.by_retry_skipped {
                     let labels = (tgt, retry);
-                    m.fmt_metric_labeled(f, metric.name, labels)?;
+                    m.fmt_metric_labeled(f, &name, labels)?;
                 }
             }
         }
@@ -158,12 +426,13 @@ where
         M: FmtMetric,
         F: Fn(&StatusMetrics<C>) -> &M,
     {
+        let name = metric.sample_name(f);
         for (tgt, tm) in &self.by_target {
             if let Ok(tm) = tm.lock() {
                 for (status, m) in &tm.by_status {
                     let status = status.as_ref().map(|s| Status(*s));
                     let labels = (tgt, status);
-                    get_metric(&*m).fmt_metric_labeled(f, metric.name, labels)?;
+                    get_metric(&*m).fmt_metric_labeled(f, &name, labels)?;
                 }
             }
         }
@@ -181,13 +450,14 @@ where
         M: FmtMetric,
         F: Fn(&ClassMetrics) -> &M,
     {
+        let name = metric.sample_name(f);
         for (tgt, tm) in &self.by_target {
             if let Ok(tm) = tm.lock() {
                 for (status, sm) in &tm.by_status {
                     for (cls, m) in &sm.by_class {
                         let status = status.as_ref().map(|s| Status(*s));
                         let labels = (tgt, (status, cls));
-                        get_metric(&*m).fmt_metric_labeled(f, metric.name, labels)?;
+                        get_metric(&*m).fmt_metric_labeled(f, &name, labels)?;
                     }
                 }
             }
@@ -203,14 +473,69 @@ impl Default for Scope {
     fn default() -> Self {
         Self {
             request_total_key: "request_total".to_owned(),
+            request_rate_key: "request_rate".to_owned(),
+            request_size_bytes_key: "request_size_bytes".to_owned(),
+            response_size_bytes_key: "response_size_bytes".to_owned(),
             response_total_key: "response_total".to_owned(),
             response_latency_ms_key: "response_latency_ms".to_owned(),
             retry_skipped_total_key: "retry_skipped_total".to_owned(),
+            attempt_total_key: "attempt_total".to_owned(),
+            attempt_amplification_key: "attempt_amplification".to_owned(),
+            maintenance_backlog_key: "metrics_maintenance_backlog".to_owned(),
         }
     }
 }
 
 impl Scope {
+    fn fmt_registry<T, C>(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        registry: &Registry<T, C>,
+        backlog: usize,
+        now: Instant,
+    ) -> fmt::Result
+    where
+        T: FmtLabels + Hash + Eq,
+        C: FmtLabels + Hash + Eq,
+    {
+        if registry.by_target.is_empty() {
+            return Ok(());
+        }
+
+        self.request_total().fmt_help(f)?;
+        registry.fmt_by_target(f, self.request_total(), |s| &s.total)?;
+
+        self.request_rate().fmt_help(f)?;
+        registry.fmt_request_rate(f, self.request_rate(), now)?;
+
+        self.request_size_bytes().fmt_help(f)?;
+        registry.fmt_by_target(f, self.request_size_bytes(), |s| &s.request_size)?;
+
+        self.response_size_bytes().fmt_help(f)?;
+        registry.fmt_by_target(f, self.response_size_bytes(), |s| &s.response_size)?;
+
+        self.response_latency_ms().fmt_help(f)?;
+        registry.fmt_by_status(f, self.response_latency_ms(), |s| &s.latency)?;
+
+        self.response_total().fmt_help(f)?;
+        registry.fmt_by_class(f, self.response_total(), |s| &s.total)?;
+
+        self.retry_skipped_total().fmt_help(f)?;
+        registry.fmt_by_retry(f, self.retry_skipped_total())?;
+
+        self.attempt_total().fmt_help(f)?;
+        registry.fmt_by_attempt(f, self.attempt_total())?;
+
+        self.attempt_amplification().fmt_help(f)?;
+        registry.fmt_attempt_amplification(f, self.attempt_amplification(), now)?;
+
+        self.maintenance_backlog().fmt_help(f)?;
+        self.maintenance_backlog()
+            .fmt_metric(f, Gauge(backlog as f64))?;
+
+        Ok(())
+    }
+
     fn prefixed(prefix: &'static str) -> Self {
         if prefix.is_empty() {
             return Self::default();
@@ -218,9 +543,15 @@ impl Scope {
 
         Self {
             request_total_key: format!("{}_request_total", prefix),
+            request_rate_key: format!("{}_request_rate", prefix),
+            request_size_bytes_key: format!("{}_request_size_bytes", prefix),
+            response_size_bytes_key: format!("{}_response_size_bytes", prefix),
             response_total_key: format!("{}_response_total", prefix),
             response_latency_ms_key: format!("{}_response_latency_ms", prefix),
             retry_skipped_total_key: format!("{}_retry_skipped_total", prefix),
+            attempt_total_key: format!("{}_attempt_total", prefix),
+            attempt_amplification_key: format!("{}_attempt_amplification", prefix),
+            maintenance_backlog_key: format!("{}_metrics_maintenance_backlog", prefix),
         }
     }
 
@@ -228,6 +559,21 @@ impl Scope {
         Metric::new(&self.request_total_key, &Self::REQUEST_TOTAL_HELP)
     }
 
+    fn request_rate(&self) -> Metric<'_, Gauge> {
+        Metric::new(&self.request_rate_key, &Self::REQUEST_RATE_HELP)
+    }
+
+    fn request_size_bytes(&self) -> Metric<'_, Histogram<size::Bytes>> {
+        Metric::new(&self.request_size_bytes_key, &Self::REQUEST_SIZE_BYTES_HELP)
+    }
+
+    fn response_size_bytes(&self) -> Metric<'_, Histogram<size::Bytes>> {
+        Metric::new(
+            &self.response_size_bytes_key,
+            &Self::RESPONSE_SIZE_BYTES_HELP,
+        )
+    }
+
     fn response_total(&self) -> Metric<'_, Counter> {
         Metric::new(&self.response_total_key, &Self::RESPONSE_TOTAL_HELP)
     }
@@ -246,8 +592,33 @@ impl Scope {
         )
     }
 
+    fn attempt_total(&self) -> Metric<'_, Counter> {
+        Metric::new(&self.attempt_total_key, &Self::ATTEMPT_TOTAL_HELP)
+    }
+
+    fn attempt_amplification(&self) -> Metric<'_, Gauge> {
+        Metric::new(
+            &self.attempt_amplification_key,
+            &Self::ATTEMPT_AMPLIFICATION_HELP,
+        )
+    }
+
+    fn maintenance_backlog(&self) -> Metric<'_, Gauge> {
+        Metric::new(
+            &self.maintenance_backlog_key,
+            &Self::MAINTENANCE_BACKLOG_HELP,
+        )
+    }
+
     const REQUEST_TOTAL_HELP: &'static str = "Total count of HTTP requests.";
 
+    const REQUEST_RATE_HELP: &'static str =
+        "Rate of HTTP requests per second over a recent sliding window.";
+
+    const REQUEST_SIZE_BYTES_HELP: &'static str = "Sizes of HTTP request bodies in bytes.";
+
+    const RESPONSE_SIZE_BYTES_HELP: &'static str = "Sizes of HTTP response bodies in bytes.";
+
     const RESPONSE_TOTAL_HELP: &'static str = "Total count of HTTP responses.";
 
     const RESPONSE_LATENCY_MS_HELP: &'static str =
@@ -256,6 +627,38 @@ impl Scope {
 
     const RETRY_SKIPPED_TOTAL_HELP: &'static str =
         "Total count of retryable HTTP responses that were not retried.";
+
+    const ATTEMPT_TOTAL_HELP: &'static str =
+        "Total count of attempts to serve retryable HTTP requests, by attempt.";
+
+    const ATTEMPT_AMPLIFICATION_HELP: &'static str =
+        "Ratio of attempts to original HTTP requests over a recent sliding window.";
+
+    const MAINTENANCE_BACKLOG_HELP: &'static str =
+        "Number of targets that have yet to be checked for eviction by scrapes.";
+}
+
+impl FmtMetric for Gauge {
+    const KIND: &'static str = "gauge";
+
+    fn fmt_metric<N: fmt::Display>(&self, f: &mut fmt::Formatter<'_>, name: N) -> fmt::Result {
+        writeln!(f, "{} {}", name, self.0)
+    }
+
+    fn fmt_metric_labeled<N, L>(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        name: N,
+        labels: L,
+    ) -> fmt::Result
+    where
+        N: fmt::Display,
+        L: FmtLabels,
+    {
+        write!(f, "{}{{", name)?;
+        labels.fmt_labels(f)?;
+        writeln!(f, "}} {}", self.0)
+    }
 }
 
 impl FmtLabels for Status {
@@ -275,3 +678,111 @@ impl FmtLabels for RetrySkipped {
         )
     }
 }
+
+impl FmtLabels for Attempt {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "attempt=\"{}\"",
+            match self {
+                Attempt::First => "first",
+                Attempt::Second => "second",
+                Attempt::ThirdOrLater => "third+",
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LOCKED_BATCH, MAX_VISITED};
+    use crate::metrics::{FmtLabels, FmtMetrics};
+    use std::fmt;
+    use std::time::Duration;
+    use tokio_timer::clock;
+
+    const SERIES: usize = 50_000;
+
+    #[derive(Clone, Debug, Hash, Eq, PartialEq)]
+    struct Target(usize);
+    impl FmtLabels for Target {
+        fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "n=\"{}\"", self.0)
+        }
+    }
+
+    #[derive(Clone, Debug, Hash, Eq, PartialEq)]
+    struct Class;
+    impl FmtLabels for Class {
+        fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "class=\"ok\"")
+        }
+    }
+
+    fn populate(registry: &super::super::SharedRegistry<Target, Class>) {
+        let now = clock::now();
+        let mut registry = registry.lock().unwrap();
+        for n in 0..SERIES {
+            let metrics = registry.get_or_insert(Target(n));
+            metrics.lock().unwrap().incr_request(now);
+        }
+    }
+
+    fn max_visited() -> usize {
+        MAX_VISITED.with(|max| max.get())
+    }
+
+    #[test]
+    fn scrapes_lock_registry_briefly() {
+        let (registry, report) =
+            super::super::new::<Target, Class>(Duration::from_secs(60), Duration::from_secs(10));
+        let summary = report.clone().summarize(|_: &Target| Target(0));
+        populate(&registry);
+
+        let full = report.as_display().to_string();
+        let request_totals = full
+            .lines()
+            .filter(|l| l.starts_with("request_total{"))
+            .count();
+        assert_eq!(request_totals, SERIES, "all targets must be formatted");
+        // Each scrape checks a bounded number of the targets for eviction.
+        let backlog = SERIES - super::MAX_RETAIN_PER_SCRAPE;
+        assert!(full.contains(&format!("metrics_maintenance_backlog {}\n", backlog)));
+
+        let summary = summary.as_display().to_string();
+        assert!(summary.contains(&format!("request_total{{n=\"0\"}} {}\n", SERIES)));
+
+        assert_eq!(
+            max_visited(),
+            LOCKED_BATCH,
+            "registry must be locked for at most {} targets at a time",
+            LOCKED_BATCH
+        );
+    }
+
+    #[test]
+    fn eviction_backlog_is_reported() {
+        // All targets are idle as soon as their handles are dropped.
+        let (registry, report) =
+            super::super::new::<Target, Class>(Duration::from_secs(0), Duration::from_secs(10));
+        populate(&registry);
+
+        let mut remaining = SERIES;
+        while remaining > 0 {
+            let scrape = report.as_display().to_string();
+            remaining -= super::MAX_RETAIN_PER_SCRAPE.min(remaining);
+            assert_eq!(registry.lock().unwrap().target_count(), remaining);
+            if remaining > 0 {
+                let backlog = format!("metrics_maintenance_backlog {}\n", remaining);
+                assert!(scrape.contains(&backlog), "expected {:?}", backlog);
+            }
+        }
+
+        assert_eq!(
+            max_visited(),
+            LOCKED_BATCH,
+            "registry must be locked for at most {} targets at a time",
+            LOCKED_BATCH
+        );
+    }
+}
@@ -1,10 +1,13 @@
+use super::super::outcome::{self, Outcome};
 use super::super::retry::TryClone;
 use super::classify::{ClassifyEos, ClassifyResponse};
 use super::{ClassMetrics, Registry, RequestMetrics, StatusMetrics};
+use bytes::Buf;
 use futures::{try_ready, Async, Future, Poll};
 use http;
 use hyper::body::Payload;
 use linkerd2_error::Error;
+use linkerd2_metrics::FmtLabels;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::marker::PhantomData;
@@ -22,6 +25,7 @@ where
     C::Class: Hash + Eq,
 {
     registry: Arc<Mutex<Registry<K, C::Class>>>,
+    outcome: bool,
     _p: PhantomData<fn() -> C>,
 }
 
@@ -34,6 +38,7 @@ where
     C::Class: Hash + Eq,
 {
     registry: Arc<Mutex<Registry<K, C::Class>>>,
+    outcome: bool,
     inner: M,
     _p: PhantomData<fn() -> C>,
 }
@@ -44,6 +49,7 @@ where
     C::Class: Hash + Eq,
 {
     metrics: Option<Arc<Mutex<RequestMetrics<C::Class>>>>,
+    outcome: bool,
     inner: F,
     _p: PhantomData<fn() -> C>,
 }
@@ -56,6 +62,7 @@ where
     C::Class: Hash + Eq,
 {
     metrics: Option<Arc<Mutex<RequestMetrics<C::Class>>>>,
+    outcome: bool,
     inner: S,
     _p: PhantomData<fn() -> C>,
 }
@@ -67,6 +74,7 @@ where
 {
     classify: Option<C>,
     metrics: Option<Arc<Mutex<RequestMetrics<C::Class>>>>,
+    outcome: Option<Outcome>,
     stream_open_at: Instant,
     inner: F,
 }
@@ -78,6 +86,9 @@ where
     C: Hash + Eq,
 {
     metrics: Option<Arc<Mutex<RequestMetrics<C>>>>,
+    /// Records the body's size once it has been fully read.
+    size_metrics: Option<Arc<Mutex<RequestMetrics<C>>>>,
+    bytes: u64,
     inner: B,
 }
 
@@ -91,8 +102,12 @@ where
     status: http::StatusCode,
     classify: Option<C>,
     metrics: Option<Arc<Mutex<RequestMetrics<C::Class>>>>,
+    /// Records the response's class in the request's outcome, formatting it
+    /// with the given function.
+    outcome: Option<(Outcome, fn(&C::Class) -> String)>,
     stream_open_at: Instant,
     latency_recorded: bool,
+    bytes: u64,
     inner: B,
 }
 
@@ -106,10 +121,26 @@ where
 {
     Layer {
         registry,
+        outcome: false,
         _p: PhantomData,
     }
 }
 
+impl<K, C> Layer<K, C>
+where
+    K: Hash + Eq,
+    C: ClassifyResponse,
+    C::Class: Hash + Eq,
+{
+    /// If set, each response's class is recorded in its request's `Outcome`.
+    ///
+    /// Only one metrics layer in a stack should record the outcome: the
+    /// route's, which observes the response after any retries.
+    pub fn with_outcome(self, outcome: bool) -> Self {
+        Self { outcome, ..self }
+    }
+}
+
 impl<K, C> Clone for Layer<K, C>
 where
     K: Hash + Eq,
@@ -119,6 +150,7 @@ where
     fn clone(&self) -> Self {
         Self {
             registry: self.registry.clone(),
+            outcome: self.outcome,
             _p: PhantomData,
         }
     }
@@ -136,6 +168,7 @@ where
         MakeSvc {
             inner,
             registry: self.registry.clone(),
+            outcome: self.outcome,
             _p: PhantomData,
         }
     }
@@ -154,6 +187,7 @@ where
         Self {
             inner: self.inner.clone(),
             registry: self.registry.clone(),
+            outcome: self.outcome,
             _p: PhantomData,
         }
     }
@@ -178,12 +212,7 @@ where
     fn call(&mut self, target: T) -> Self::Future {
         trace!("make: target={:?}", target);
         let metrics = match self.registry.lock() {
-            Ok(mut r) => Some(
-                r.by_target
-                    .entry(target.clone().into())
-                    .or_insert_with(|| Arc::new(Mutex::new(RequestMetrics::default())))
-                    .clone(),
-            ),
+            Ok(mut r) => Some(r.get_or_insert(target.clone().into())),
             Err(_) => None,
         };
         trace!("make: metrics={}", metrics.is_some());
@@ -192,6 +221,7 @@ where
 
         MakeFuture {
             metrics,
+            outcome: self.outcome,
             inner,
             _p: PhantomData,
         }
@@ -214,6 +244,7 @@ where
         Ok(Service {
             inner,
             metrics: self.metrics.clone(),
+            outcome: self.outcome,
             _p: PhantomData,
         }
         .into())
@@ -232,6 +263,7 @@ where
         Self {
             inner: self.inner.clone(),
             metrics: self.metrics.clone(),
+            outcome: self.outcome,
             _p: PhantomData,
         }
     }
@@ -244,7 +276,7 @@ where
     A: Payload,
     B: Payload,
     C: ClassifyResponse + Clone + Default + Send + Sync + 'static,
-    C::Class: Hash + Eq + Send + Sync,
+    C::Class: Hash + Eq + FmtLabels + Send + Sync,
 {
     type Response = http::Response<ResponseBody<B, C::ClassifyEos>>;
     type Error = Error;
@@ -256,31 +288,41 @@ where
 
     fn call(&mut self, req: http::Request<A>) -> Self::Future {
         let mut req_metrics = self.metrics.clone();
+        let mut size_metrics = self.metrics.clone();
 
         if req.body().is_end_stream() {
             if let Some(lock) = req_metrics.take() {
                 let now = clock::now();
                 if let Ok(mut metrics) = lock.lock() {
-                    (*metrics).last_update = now;
-                    (*metrics).total.incr();
+                    metrics.incr_request(now);
+                    metrics.record_request_size(0, now);
                 }
             }
+            size_metrics = None;
         }
 
         let req = {
             let (head, inner) = req.into_parts();
             let body = RequestBody {
                 metrics: req_metrics,
+                size_metrics,
+                bytes: 0,
                 inner,
             };
             http::Request::from_parts(head, body)
         };
 
         let classify = req.extensions().get::<C>().cloned().unwrap_or_default();
+        let outcome = if self.outcome {
+            req.extensions().get::<Outcome>().cloned()
+        } else {
+            None
+        };
 
         ResponseFuture {
             classify: Some(classify),
             metrics: self.metrics.clone(),
+            outcome,
             stream_open_at: clock::now(),
             inner: self.inner.call(req),
         }
@@ -293,7 +335,7 @@ where
     F::Error: Into<Error>,
     B: Payload,
     C: ClassifyResponse + Send + Sync + 'static,
-    C::Class: Hash + Eq + Send + Sync,
+    C::Class: Hash + Eq + FmtLabels + Send + Sync,
 {
     type Item = http::Response<ResponseBody<B, C::ClassifyEos>>;
     type Error = Error;
@@ -307,6 +349,7 @@ where
 
         let classify = self.classify.take();
         let metrics = self.metrics.take();
+        let outcome = self.outcome.take();
         match rsp {
             Ok(rsp) => {
                 let classify = classify.map(|c| c.start(&rsp));
@@ -315,17 +358,25 @@ where
                     status: head.status,
                     classify,
                     metrics,
+                    outcome: outcome.map(|o| {
+                        let labels: fn(&C::Class) -> String = outcome::class_labels;
+                        (o, labels)
+                    }),
                     stream_open_at: self.stream_open_at,
                     latency_recorded: false,
+                    bytes: 0,
                     inner,
                 };
                 Ok(http::Response::from_parts(head, body).into())
             }
             Err(e) => {
                 let e = e.into();
-                if let Some(lock) = metrics {
-                    if let Some(classify) = classify {
-                        let class = classify.error(&e);
+                if let Some(classify) = classify {
+                    let class = classify.error(&e);
+                    if let Some(outcome) = outcome {
+                        outcome.set_class(outcome::class_labels(&class));
+                    }
+                    if let Some(lock) = metrics {
                         measure_class(&lock, class, None);
                     }
                 }
@@ -353,11 +404,17 @@ where
         if let Some(lock) = self.metrics.take() {
             let now = clock::now();
             if let Ok(mut metrics) = lock.lock() {
-                (*metrics).last_update = now;
-                (*metrics).total.incr();
+                metrics.incr_request(now);
             }
         }
 
+        if let Some(ref data) = frame {
+            self.bytes += data.remaining() as u64;
+        }
+        if frame.is_none() || self.inner.is_end_stream() {
+            self.record_size();
+        }
+
         Ok(Async::Ready(frame))
     }
 
@@ -396,10 +453,26 @@ where
         self.inner.try_clone().map(|inner| RequestBody {
             inner,
             metrics: self.metrics.clone(),
+            size_metrics: self.size_metrics.clone(),
+            bytes: self.bytes,
         })
     }
 }
 
+impl<B, C> RequestBody<B, C>
+where
+    B: Payload,
+    C: Hash + Eq,
+{
+    fn record_size(&mut self) {
+        if let Some(lock) = self.size_metrics.take() {
+            if let Ok(mut metrics) = lock.lock() {
+                metrics.record_request_size(self.bytes, clock::now());
+            }
+        }
+    }
+}
+
 impl<B, C> Default for ResponseBody<B, C>
 where
     B: Payload + Default,
@@ -413,7 +486,9 @@ where
             stream_open_at: clock::now(),
             classify: None,
             metrics: None,
+            outcome: None,
             latency_recorded: false,
+            bytes: 0,
         }
     }
 }
@@ -448,8 +523,16 @@ where
         self.latency_recorded = true;
     }
 
+    /// Records the response's class (in the request's outcome, too, if it's
+    /// recorded here) and, since the stream has ended, the size of its body.
     fn record_class(&mut self, class: C::Class) {
+        if let Some((outcome, labels)) = self.outcome.take() {
+            outcome.set_class(labels(&class));
+        }
         if let Some(lock) = self.metrics.take() {
+            if let Ok(mut metrics) = lock.lock() {
+                metrics.record_response_size(self.bytes, clock::now());
+            }
             measure_class(&lock, class, Some(self.status));
         }
     }
@@ -511,6 +594,14 @@ where
             self.record_latency();
         }
 
+        if let Some(ref data) = frame {
+            self.bytes += data.remaining() as u64;
+        }
+
+        if let (Some(c), Some(data)) = (self.classify.as_mut(), frame.as_ref()) {
+            c.data(data.remaining());
+        }
+
         Ok(Async::Ready(frame))
     }
 
@@ -566,3 +657,108 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linkerd2_metrics::{FmtLabels, FmtMetrics};
+    use std::fmt;
+    use std::time::Duration;
+
+    #[derive(Clone, Debug, Hash, Eq, PartialEq)]
+    struct Target;
+    impl FmtLabels for Target {
+        fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "tgt=\"test\"")
+        }
+    }
+
+    #[derive(Clone, Debug, Hash, Eq, PartialEq)]
+    struct Class;
+    impl FmtLabels for Class {
+        fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "class=\"ok\"")
+        }
+    }
+
+    struct Eos;
+    impl ClassifyEos for Eos {
+        type Class = Class;
+
+        fn eos(self, _: Option<&http::HeaderMap>) -> Class {
+            Class
+        }
+
+        fn error(self, _: &Error) -> Class {
+            Class
+        }
+    }
+
+    fn drain<B: Payload>(mut body: B) {
+        loop {
+            match body.poll_data() {
+                Ok(Async::Ready(Some(_))) => {}
+                Ok(_) => return,
+                Err(_) => panic!("body must not fail"),
+            }
+        }
+    }
+
+    #[test]
+    fn records_body_sizes() {
+        let (registry, report) =
+            super::super::new::<Target, Class>(Duration::from_secs(60), Duration::from_secs(10));
+        let metrics = registry.lock().unwrap().get_or_insert(Target);
+
+        for &size in &[10, 100, 1_000, 100_000] {
+            drain(RequestBody {
+                metrics: None,
+                size_metrics: Some(metrics.clone()),
+                bytes: 0,
+                inner: hyper::Body::from(vec![0u8; size]),
+            });
+            drain(ResponseBody {
+                status: http::StatusCode::OK,
+                classify: Some(Eos),
+                metrics: Some(metrics.clone()),
+                outcome: None,
+                stream_open_at: clock::now(),
+                latency_recorded: false,
+                bytes: 0,
+                inner: hyper::Body::from(vec![0u8; size * 2]),
+            });
+        }
+
+        let report = report.as_display().to_string();
+        for (le, count) in &[
+            ("64", 1),
+            ("256", 2),
+            ("1024", 3),
+            ("4096", 3),
+            ("262144", 4),
+        ] {
+            let bucket = format!(
+                "request_size_bytes_bucket{{tgt=\"test\",le=\"{}\"}} {}\n",
+                le, count
+            );
+            assert!(report.contains(&bucket), "missing {}", bucket);
+        }
+        assert!(report.contains("request_size_bytes_sum{tgt=\"test\"} 101110\n"));
+
+        for (le, count) in &[
+            ("64", 1),
+            ("256", 2),
+            ("1024", 2),
+            ("4096", 3),
+            ("65536", 3),
+        ] {
+            let bucket = format!(
+                "response_size_bytes_bucket{{tgt=\"test\",le=\"{}\"}} {}\n",
+                le, count
+            );
+            assert!(report.contains(&bucket), "missing {}", bucket);
+        }
+        assert!(report.contains("response_size_bytes_count{tgt=\"test\"} 4\n"));
+        assert!(report.contains("response_size_bytes_sum{tgt=\"test\"} 202220\n"));
+    }
+}
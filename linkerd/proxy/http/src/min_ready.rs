@@ -0,0 +1,334 @@
+//! Holds a balancer until a minimum number of its endpoints are ready.
+//!
+//! A balancer serves requests as soon as any one of its endpoints is ready,
+//! so, while a destination's endpoints are slowly becoming ready (e.g. during
+//! a rollout), the first of them may receive all of its traffic. A balancer
+//! wrapped in `Service` stays unready until `min_endpoints` of its endpoints
+//! have become ready or until `timeout` elapses. After that, it serves
+//! whatever endpoints are available, even if fewer become ready later.
+
+use futures::{try_ready, Async, Future, Poll};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use std::time::Duration;
+use tokio_timer::{clock, Delay};
+use tower_discover::Change;
+use tracing::debug;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Config {
+    /// The number of endpoints that must be ready before the balancer serves.
+    pub min_endpoints: usize,
+    /// The maximum time that the balancer is held.
+    pub timeout: Duration,
+}
+
+/// Counts how many of a `Discover`'s endpoints are ready.
+#[derive(Debug)]
+pub struct Discover<D> {
+    inner: D,
+    ready: Arc<AtomicUsize>,
+}
+
+#[derive(Debug)]
+pub struct Endpoint<S> {
+    inner: S,
+    ready: Arc<AtomicUsize>,
+    /// True once the endpoint has been counted as ready.
+    counted: bool,
+}
+
+/// Determines when a held balancer is released.
+#[derive(Debug)]
+pub struct Hold {
+    min_endpoints: usize,
+    ready: Arc<AtomicUsize>,
+    timeout: Delay,
+}
+
+#[derive(Debug)]
+pub struct Service<S> {
+    inner: S,
+    hold: Option<Hold>,
+}
+
+// === impl Config ===
+
+impl Config {
+    /// Returns a `Hold` on the endpoints of `discover`, unless the balancer
+    /// would be ready with a single endpoint anyway.
+    pub fn hold<D>(&self, discover: &Discover<D>) -> Option<Hold> {
+        if self.min_endpoints <= 1 {
+            return None;
+        }
+
+        Some(Hold {
+            min_endpoints: self.min_endpoints,
+            ready: discover.ready.clone(),
+            timeout: Delay::new(clock::now() + self.timeout),
+        })
+    }
+}
+
+// === impl Discover ===
+
+impl<D> Discover<D> {
+    pub fn new(inner: D) -> Self {
+        Self {
+            inner,
+            ready: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl<D: tower_discover::Discover> tower_discover::Discover for Discover<D> {
+    type Key = D::Key;
+    type Service = Endpoint<D::Service>;
+    type Error = D::Error;
+
+    fn poll(&mut self) -> Poll<Change<Self::Key, Self::Service>, Self::Error> {
+        let change = match try_ready!(self.inner.poll()) {
+            Change::Insert(key, inner) => {
+                let endpoint = Endpoint {
+                    inner,
+                    ready: self.ready.clone(),
+                    counted: false,
+                };
+                Change::Insert(key, endpoint)
+            }
+            Change::Remove(key) => Change::Remove(key),
+        };
+        Ok(Async::Ready(change))
+    }
+}
+
+// === impl Endpoint ===
+
+impl<S, Req> tower::Service<Req> for Endpoint<S>
+where
+    S: tower::Service<Req>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        try_ready!(self.inner.poll_ready());
+        if !self.counted {
+            self.counted = true;
+            self.ready.fetch_add(1, Ordering::AcqRel);
+        }
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        self.inner.call(req)
+    }
+}
+
+impl<S> Drop for Endpoint<S> {
+    fn drop(&mut self) {
+        if self.counted {
+            self.ready.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
+
+// === impl Hold ===
+
+impl Hold {
+    fn poll_release(&mut self) -> Async<()> {
+        let ready = self.ready.load(Ordering::Acquire);
+        if ready >= self.min_endpoints {
+            debug!(%ready, "enough endpoints are ready");
+            return Async::Ready(());
+        }
+
+        match self.timeout.poll() {
+            Ok(Async::NotReady) => Async::NotReady,
+            // A failed timer is treated as elapsed.
+            Ok(Async::Ready(())) | Err(_) => {
+                debug!(
+                    %ready,
+                    min = %self.min_endpoints,
+                    "serving before enough endpoints are ready"
+                );
+                Async::Ready(())
+            }
+        }
+    }
+}
+
+// === impl Service ===
+
+impl<S> Service<S> {
+    pub fn new(inner: S, hold: Option<Hold>) -> Self {
+        Self { inner, hold }
+    }
+}
+
+impl<S, Req> tower::Service<Req> for Service<S>
+where
+    S: tower::Service<Req>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        // The inner balancer is polled even while it is held, so that it
+        // discovers its endpoints and polls them for readiness.
+        try_ready!(self.inner.poll_ready());
+
+        if let Some(ref mut hold) = self.hold {
+            if hold.poll_release().is_not_ready() {
+                return Ok(Async::NotReady);
+            }
+            self.hold = None;
+        }
+
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        debug_assert!(self.hold.is_none(), "called before ready");
+        self.inner.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use std::collections::VecDeque;
+    use tokio::runtime::current_thread::Runtime;
+    use tower::Service as _;
+    use tower_discover::Discover as _;
+
+    /// An endpoint that becomes ready when `ready` is set.
+    struct Mock {
+        ready: bool,
+    }
+
+    struct MockDiscover(VecDeque<Change<usize, Mock>>);
+
+    impl tower::Service<()> for Mock {
+        type Response = ();
+        type Error = ();
+        type Future = future::FutureResult<(), ()>;
+
+        fn poll_ready(&mut self) -> Poll<(), ()> {
+            if self.ready {
+                Ok(Async::Ready(()))
+            } else {
+                Ok(Async::NotReady)
+            }
+        }
+
+        fn call(&mut self, _: ()) -> Self::Future {
+            future::ok(())
+        }
+    }
+
+    impl tower_discover::Discover for MockDiscover {
+        type Key = usize;
+        type Service = Mock;
+        type Error = ();
+
+        fn poll(&mut self) -> Poll<Change<usize, Mock>, ()> {
+            Ok(self
+                .0
+                .pop_front()
+                .map(Async::Ready)
+                .unwrap_or(Async::NotReady))
+        }
+    }
+
+    /// Takes all of the endpoints that `discover` has discovered.
+    fn endpoints(discover: &mut Discover<MockDiscover>) -> Vec<Endpoint<Mock>> {
+        let mut endpoints = Vec::new();
+        while let Ok(Async::Ready(Change::Insert(_, endpoint))) = discover.poll() {
+            endpoints.push(endpoint);
+        }
+        endpoints
+    }
+
+    /// Discovers `n` unready endpoints.
+    fn discover(n: usize) -> Discover<MockDiscover> {
+        let changes = (0..n)
+            .map(|i| Change::Insert(i, Mock { ready: false }))
+            .collect();
+        Discover::new(MockDiscover(changes))
+    }
+
+    fn mark_ready(endpoint: &mut Endpoint<Mock>) {
+        endpoint.inner.ready = true;
+        assert!(endpoint.poll_ready().unwrap().is_ready());
+    }
+
+    #[test]
+    fn holds_until_min_endpoints_are_ready() {
+        let config = Config {
+            min_endpoints: 2,
+            timeout: Duration::from_secs(60),
+        };
+
+        Runtime::new()
+            .unwrap()
+            .block_on(future::lazy(move || {
+                let mut discover = discover(3);
+                let hold = config.hold(&discover);
+                let mut endpoints = endpoints(&mut discover);
+                let mut svc = Service::new(tower::service_fn(|()| future::ok::<(), ()>(())), hold);
+
+                assert!(svc.poll_ready().unwrap().is_not_ready());
+                mark_ready(&mut endpoints[0]);
+                assert!(svc.poll_ready().unwrap().is_not_ready());
+                mark_ready(&mut endpoints[1]);
+                assert!(svc.poll_ready().unwrap().is_ready());
+
+                // Once released, the balancer isn't held again.
+                endpoints.truncate(0);
+                assert!(svc.poll_ready().unwrap().is_ready());
+                Ok::<(), ()>(())
+            }))
+            .unwrap();
+    }
+
+    #[test]
+    fn removed_endpoints_are_not_counted() {
+        let mut discover = discover(2);
+        let mut endpoints = endpoints(&mut discover);
+        mark_ready(&mut endpoints[0]);
+        mark_ready(&mut endpoints[1]);
+        assert_eq!(discover.ready.load(Ordering::Acquire), 2);
+
+        drop(endpoints.pop());
+        assert_eq!(discover.ready.load(Ordering::Acquire), 1);
+    }
+
+    #[test]
+    fn releases_after_timeout() {
+        let config = Config {
+            min_endpoints: 2,
+            timeout: Duration::from_millis(10),
+        };
+
+        Runtime::new()
+            .unwrap()
+            .block_on(future::lazy(move || {
+                let mut discover = discover(1);
+                let hold = config.hold(&discover);
+                let mut endpoints = endpoints(&mut discover);
+                mark_ready(&mut endpoints[0]);
+
+                let mut svc = Service::new(tower::service_fn(|()| future::ok::<(), ()>(())), hold);
+                assert!(svc.poll_ready().unwrap().is_not_ready());
+
+                future::poll_fn(move || svc.poll_ready())
+            }))
+            .expect("the balancer must be released once the timeout elapses");
+    }
+}
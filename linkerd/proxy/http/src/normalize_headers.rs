@@ -0,0 +1,250 @@
+//! Collapses duplicate request headers that backends may mishandle.
+//!
+//! Some legacy clients repeat headers that their backends expect to appear at
+//! most once. Each configured header that appears more than once is collapsed
+//! into a single value according to its `Collapse` policy.
+//!
+//! Header names are matched without regard to case: HTTP/2 requires names to
+//! be lowercase and the `http` crate represents every name in lowercase, so an
+//! HTTP/1 client's `X-Request-Id` and `x-request-id` are the same header.
+//!
+//! Headers whose repetition carries meaning, like `Set-Cookie`, are never
+//! collapsed, even if configured.
+
+use futures::{try_ready, Future, Poll};
+use http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use indexmap::IndexMap;
+use std::sync::Arc;
+use tracing::debug;
+
+/// Describes how the values of a repeated header are collapsed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Collapse {
+    /// Keeps the first value.
+    First,
+    /// Keeps the last value.
+    Last,
+    /// Joins the values into a single comma-separated list.
+    Join,
+}
+
+pub type Policy = Arc<IndexMap<HeaderName, Collapse>>;
+
+#[derive(Clone, Debug)]
+pub struct Layer {
+    policy: Policy,
+}
+
+#[derive(Clone, Debug)]
+pub struct Stack<M> {
+    inner: M,
+    policy: Policy,
+}
+
+pub struct MakeFuture<F> {
+    inner: F,
+    policy: Policy,
+}
+
+#[derive(Clone, Debug)]
+pub struct Service<S> {
+    inner: S,
+    policy: Policy,
+}
+
+pub fn layer(policy: Policy) -> Layer {
+    Layer { policy }
+}
+
+/// Collapses each header in `policy` that appears more than once in
+/// `headers`.
+pub fn normalize(headers: &mut HeaderMap, policy: &IndexMap<HeaderName, Collapse>) {
+    for (name, collapse) in policy.iter() {
+        if is_exempt(name) {
+            continue;
+        }
+
+        let value = {
+            let values = headers.get_all(name).iter().collect::<Vec<_>>();
+            if values.len() < 2 {
+                continue;
+            }
+            debug!(header = %name, values = values.len(), ?collapse, "collapsing");
+
+            match collapse {
+                Collapse::First => values[0].clone(),
+                Collapse::Last => values[values.len() - 1].clone(),
+                Collapse::Join => match join(name, &values) {
+                    Some(value) => value,
+                    None => continue,
+                },
+            }
+        };
+        headers.insert(name.clone(), value);
+    }
+}
+
+/// Returns true for headers that may legitimately be repeated.
+fn is_exempt(name: &HeaderName) -> bool {
+    *name == header::SET_COOKIE
+}
+
+fn join(name: &HeaderName, values: &[&HeaderValue]) -> Option<HeaderValue> {
+    // Cookies are split into crumbs over HTTP/2 and must be rejoined as a
+    // single cookie-string (RFC 7540 §8.1.2.5).
+    let sep: &[u8] = if *name == header::COOKIE {
+        b"; "
+    } else {
+        b", "
+    };
+
+    let mut joined = Vec::new();
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            joined.extend_from_slice(sep);
+        }
+        joined.extend_from_slice(value.as_bytes());
+    }
+    HeaderValue::from_bytes(&joined).ok()
+}
+
+// === impl Layer ===
+
+impl<M> tower::layer::Layer<M> for Layer {
+    type Service = Stack<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        Stack {
+            inner,
+            policy: self.policy.clone(),
+        }
+    }
+}
+
+// === impl Stack ===
+
+impl<T, M> tower::Service<T> for Stack<M>
+where
+    M: tower::Service<T>,
+{
+    type Response = Service<M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        MakeFuture {
+            inner: self.inner.call(target),
+            policy: self.policy.clone(),
+        }
+    }
+}
+
+// === impl MakeFuture ===
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = Service<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        Ok(Service {
+            inner,
+            policy: self.policy.clone(),
+        }
+        .into())
+    }
+}
+
+// === impl Service ===
+
+impl<S, B> tower::Service<http::Request<B>> for Service<S>
+where
+    S: tower::Service<http::Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut req: http::Request<B>) -> Self::Future {
+        normalize(req.headers_mut(), &self.policy);
+        self.inner.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(entries: &[(&str, Collapse)]) -> IndexMap<HeaderName, Collapse> {
+        entries
+            .iter()
+            .map(|(name, collapse)| (HeaderName::from_bytes(name.as_bytes()).unwrap(), *collapse))
+            .collect()
+    }
+
+    fn headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &[
+            ("x-first", "a"),
+            ("x-first", "b"),
+            ("x-last", "a"),
+            ("x-last", "b"),
+            ("x-join", "a"),
+            ("x-join", "b"),
+            ("cookie", "a=1"),
+            ("cookie", "b=2"),
+            ("set-cookie", "a=1"),
+            ("set-cookie", "b=2"),
+            ("x-other", "a"),
+            ("x-other", "b"),
+        ] {
+            headers.append(*name, HeaderValue::from_static(*value));
+        }
+        headers
+    }
+
+    fn values<'a>(headers: &'a HeaderMap, name: &str) -> Vec<&'a str> {
+        headers
+            .get_all(name)
+            .iter()
+            .map(|v| v.to_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn collapses_configured_duplicates() {
+        let mut headers = headers();
+        normalize(
+            &mut headers,
+            &policy(&[
+                // Configured names match regardless of case.
+                ("X-First", Collapse::First),
+                ("x-last", Collapse::Last),
+                ("x-join", Collapse::Join),
+                ("cookie", Collapse::Join),
+            ]),
+        );
+
+        assert_eq!(values(&headers, "x-first"), vec!["a"]);
+        assert_eq!(values(&headers, "x-last"), vec!["b"]);
+        assert_eq!(values(&headers, "x-join"), vec!["a, b"]);
+        assert_eq!(values(&headers, "cookie"), vec!["a=1; b=2"]);
+        assert_eq!(values(&headers, "x-other"), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn preserves_exempt_headers() {
+        let mut headers = headers();
+        normalize(&mut headers, &policy(&[("Set-Cookie", Collapse::Join)]));
+
+        assert_eq!(values(&headers, "set-cookie"), vec!["a=1", "b=2"]);
+    }
+}
@@ -8,6 +8,17 @@ pub trait ShouldNormalizeUri {
     fn should_normalize_uri(&self) -> Option<Authority>;
 }
 
+/// Implemented by route targets on which requests keep their original scheme
+/// when their URIs are normalized.
+pub trait ShouldPreserveScheme {
+    fn should_preserve_scheme(&self) -> bool;
+}
+
+/// A request extension that prevents `NormalizeUri` from replacing the
+/// request's scheme.
+#[derive(Copy, Clone, Debug)]
+pub struct PreserveScheme(());
+
 #[derive(Clone, Debug)]
 pub struct MakeNormalizeUri<N> {
     inner: N,
@@ -93,7 +104,7 @@ where
                 request.version() != http::Version::HTTP_2,
                 "normalize_uri must only be applied to HTTP/1"
             );
-            h1::set_authority(request.uri_mut(), authority.clone());
+            normalize(&mut request, authority);
         } else {
             trace!("Not normalizing URI");
         }
@@ -101,3 +112,141 @@ where
         self.inner.call(request)
     }
 }
+
+fn normalize<B>(request: &mut http::Request<B>, authority: &Authority) {
+    let scheme = request
+        .extensions()
+        .get::<PreserveScheme>()
+        .and_then(|_| request.uri().scheme_part().cloned());
+
+    h1::set_authority(request.uri_mut(), authority.clone());
+
+    if let Some(scheme) = scheme {
+        trace!(%scheme, "Preserving scheme");
+        h1::set_scheme(request.uri_mut(), scheme);
+    }
+}
+
+pub mod preserve_scheme {
+    use super::*;
+
+    /// Wraps an HTTP `Service` so that requests on targets that preserve
+    /// their scheme are marked with the `PreserveScheme` extension.
+    #[derive(Clone, Debug)]
+    pub struct Make<M>(M);
+
+    pub struct MakeFuture<F> {
+        inner: F,
+        preserve: bool,
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct Service<S> {
+        inner: S,
+        preserve: bool,
+    }
+
+    // === impl Layer ===
+
+    pub fn layer<M>() -> impl tower::layer::Layer<M, Service = Make<M>> + Copy {
+        layer::mk(Make)
+    }
+
+    // === impl Make ===
+
+    impl<T, M> tower::Service<T> for Make<M>
+    where
+        T: ShouldPreserveScheme,
+        M: tower::Service<T>,
+    {
+        type Response = Service<M::Response>;
+        type Error = M::Error;
+        type Future = MakeFuture<M::Future>;
+
+        fn poll_ready(&mut self) -> Poll<(), M::Error> {
+            self.0.poll_ready()
+        }
+
+        fn call(&mut self, target: T) -> Self::Future {
+            let preserve = target.should_preserve_scheme();
+            MakeFuture {
+                preserve,
+                inner: self.0.call(target),
+            }
+        }
+    }
+
+    // === impl MakeFuture ===
+
+    impl<F: Future> Future for MakeFuture<F> {
+        type Item = Service<F::Item>;
+        type Error = F::Error;
+
+        fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+            let inner = try_ready!(self.inner.poll());
+            let svc = Service {
+                inner,
+                preserve: self.preserve,
+            };
+            Ok(svc.into())
+        }
+    }
+
+    // === impl Service ===
+
+    impl<S, B> tower::Service<http::Request<B>> for Service<S>
+    where
+        S: tower::Service<http::Request<B>>,
+    {
+        type Response = S::Response;
+        type Error = S::Error;
+        type Future = S::Future;
+
+        fn poll_ready(&mut self) -> Poll<(), S::Error> {
+            self.inner.poll_ready()
+        }
+
+        fn call(&mut self, mut request: http::Request<B>) -> Self::Future {
+            if self.preserve {
+                request.extensions_mut().insert(PreserveScheme(()));
+            }
+
+            self.inner.call(request)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(uri: &str) -> http::Request<()> {
+        http::Request::builder().uri(uri).body(()).unwrap()
+    }
+
+    #[test]
+    fn normalizes_scheme() {
+        let authority = "web.example.com:8080".parse::<Authority>().unwrap();
+        let mut req = request("https://web.example.com/path?query");
+        normalize(&mut req, &authority);
+
+        assert_eq!(req.uri(), "http://web.example.com:8080/path?query");
+    }
+
+    #[test]
+    fn preserves_scheme() {
+        let authority = "web.example.com:8080".parse::<Authority>().unwrap();
+        let mut req = request("https://web.example.com/path?query");
+        req.extensions_mut().insert(PreserveScheme(()));
+        normalize(&mut req, &authority);
+
+        assert_eq!(req.uri(), "https://web.example.com:8080/path?query");
+
+        // Origin-form requests have no scheme to preserve.
+        let mut req = request("/path");
+        req.extensions_mut().insert(PreserveScheme(()));
+        normalize(&mut req, &authority);
+
+        assert_eq!(req.uri(), "http://web.example.com:8080/path");
+    }
+}
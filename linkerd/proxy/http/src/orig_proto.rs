@@ -1,8 +1,11 @@
 use super::h1;
 use futures::{future, Future, Poll};
 use http;
-use http::header::{HeaderValue, TRANSFER_ENCODING};
-use tracing::{debug, warn};
+use http::header::{
+    HeaderMap, HeaderValue, CONNECTION, CONTENT_LENGTH, EXPECT, TE, TRANSFER_ENCODING,
+};
+use std::fmt;
+use tracing::{debug, trace, warn};
 
 pub const L5D_ORIG_PROTO: &str = "l5d-orig-proto";
 
@@ -19,6 +22,44 @@ pub struct Downgrade<S> {
     inner: S,
 }
 
+/// The value of an `l5d-orig-proto` header.
+///
+/// It's encoded as the original HTTP version, followed by a `;`-separated list
+/// of flags describing the original request, e.g. `HTTP/1.0; absolute-form;
+/// keep-alive`. Flags that aren't known are ignored, so that new flags may be
+/// added without breaking older proxies.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct OrigProto {
+    version: http::Version,
+    /// Whether the request's URI was in absolute-form.
+    pub absolute_form: bool,
+    /// Whether an HTTP/1.0 request asked for its connection to be kept alive.
+    pub keep_alive: bool,
+    /// Whether the request was a `HEAD` request, whose response has no body
+    /// regardless of its framing headers.
+    pub head_request: bool,
+    /// Whether the request expected a `100 Continue` response before sending
+    /// its body.
+    pub expect_continue: bool,
+    /// Whether the request's `TE` header accepted trailers.
+    pub trailers: bool,
+    /// Whether the message's body is followed by a checksum trailer (see
+    /// `checksum`). Unlike other flags, it describes the message between
+    /// proxies, rather than the original request.
+    pub body_checksum: bool,
+}
+
+/// An `l5d-orig-proto` header value could not be parsed.
+#[derive(Debug)]
+pub struct InvalidOrigProto(());
+
+const ABSOLUTE_FORM: &str = "absolute-form";
+const KEEP_ALIVE: &str = "keep-alive";
+const HEAD_REQUEST: &str = "head-request";
+const EXPECT_CONTINUE: &str = "expect-continue";
+const TRAILERS: &str = "trailers";
+const BODY_CHECKSUM: &str = "body-checksum";
+
 // ==== impl Upgrade =====
 
 impl<S> Upgrade<S> {
@@ -50,48 +91,39 @@ where
 
         debug!("upgrading {:?} to HTTP2 with orig-proto", req.version());
 
-        // absolute-form is far less common, origin-form is the usual,
-        // so only encode the extra information if it's different than
-        // the normal.
-        let was_absolute_form = h1::is_absolute_form(req.uri());
-        if !was_absolute_form {
+        let orig_proto = OrigProto::from_request(&req)
+            .unwrap_or_else(|| unreachable!("bad orig-proto version: {:?}", req.version()));
+
+        if !orig_proto.absolute_form {
             // Since the version is going to set to HTTP_2, the NormalizeUri
             // middleware won't normalize the URI automatically, so it
             // needs to be done now.
             h1::normalize_our_view_of_uri(&mut req);
         }
 
-        let val = match (req.version(), was_absolute_form) {
-            (http::Version::HTTP_11, false) => "HTTP/1.1",
-            (http::Version::HTTP_11, true) => "HTTP/1.1; absolute-form",
-            (http::Version::HTTP_10, false) => "HTTP/1.0",
-            (http::Version::HTTP_10, true) => "HTTP/1.0; absolute-form",
-            (v, _) => unreachable!("bad orig-proto version: {:?}", v),
-        };
+        // The expectation is mediated by this proxy's server (see
+        // `expect_continue`), so it is only restored when the request is
+        // downgraded.
+        if orig_proto.expect_continue {
+            req.headers_mut().remove(EXPECT);
+        }
+
         req.headers_mut()
-            .insert(L5D_ORIG_PROTO, HeaderValue::from_static(val));
+            .insert(L5D_ORIG_PROTO, orig_proto.to_header_value());
 
-        // transfer-encoding is illegal in HTTP2
-        req.headers_mut().remove(TRANSFER_ENCODING);
+        strip_framing(req.headers_mut());
 
         *req.version_mut() = http::Version::HTTP_2;
 
         self.inner.call(req).map(|mut res| {
             debug_assert_eq!(res.version(), http::Version::HTTP_2);
-            let version = if let Some(orig_proto) = res.headers_mut().remove(L5D_ORIG_PROTO) {
+            if let Some(orig_proto) = res.headers_mut().remove(L5D_ORIG_PROTO) {
                 debug!("downgrading {} response: {:?}", L5D_ORIG_PROTO, orig_proto);
-                if orig_proto == "HTTP/1.1" {
-                    http::Version::HTTP_11
-                } else if orig_proto == "HTTP/1.0" {
-                    http::Version::HTTP_10
-                } else {
-                    warn!("unknown {} header value: {:?}", L5D_ORIG_PROTO, orig_proto);
-                    res.version()
+                match OrigProto::parse(orig_proto.as_bytes()) {
+                    Ok(OrigProto { version, .. }) => *res.version_mut() = version,
+                    Err(_) => warn!("invalid {} header value: {:?}", L5D_ORIG_PROTO, orig_proto),
                 }
-            } else {
-                res.version()
-            };
-            *res.version_mut() = version;
+            }
             res
         })
     }
@@ -127,20 +159,23 @@ where
             if let Some(orig_proto) = req.headers_mut().remove(L5D_ORIG_PROTO) {
                 debug!("translating HTTP2 to orig-proto: {:?}", orig_proto);
 
-                let val: &[u8] = orig_proto.as_bytes();
-
-                if val.starts_with(b"HTTP/1.1") {
-                    *req.version_mut() = http::Version::HTTP_11;
-                } else if val.starts_with(b"HTTP/1.0") {
-                    *req.version_mut() = http::Version::HTTP_10;
-                } else {
-                    warn!("unknown {} header value: {:?}", L5D_ORIG_PROTO, orig_proto,);
+                match OrigProto::parse(orig_proto.as_bytes()) {
+                    Ok(ref p) if p.head_request != (*req.method() == http::Method::HEAD) => {
+                        warn!(
+                            "{} header doesn't match {} request: {:?}",
+                            L5D_ORIG_PROTO,
+                            req.method(),
+                            orig_proto
+                        );
+                    }
+                    Ok(p) => {
+                        p.downgrade(&mut req);
+                        upgrade_response = true;
+                    }
+                    Err(_) => {
+                        warn!("invalid {} header value: {:?}", L5D_ORIG_PROTO, orig_proto);
+                    }
                 }
-
-                if !was_absolute_form(val) {
-                    h1::set_origin_form(req.uri_mut());
-                }
-                upgrade_response = true;
             }
         }
 
@@ -148,19 +183,20 @@ where
 
         if upgrade_response {
             fut.map(|mut res| {
-                let orig_proto = if res.version() == http::Version::HTTP_11 {
-                    "HTTP/1.1"
-                } else if res.version() == http::Version::HTTP_10 {
-                    "HTTP/1.0"
-                } else {
-                    return res;
+                let orig_proto = match OrigProto::new(res.version()) {
+                    Some(orig_proto) => orig_proto,
+                    None => return res,
                 };
 
                 res.headers_mut()
-                    .insert(L5D_ORIG_PROTO, HeaderValue::from_static(orig_proto));
+                    .insert(L5D_ORIG_PROTO, orig_proto.to_header_value());
 
-                // transfer-encoding is illegal in HTTP2
-                res.headers_mut().remove(TRANSFER_ENCODING);
+                // Informational and `204 No Content` responses never have a
+                // body, so they must not have a content-length.
+                if res.status().is_informational() || res.status() == http::StatusCode::NO_CONTENT {
+                    res.headers_mut().remove(CONTENT_LENGTH);
+                }
+                strip_framing(res.headers_mut());
 
                 *res.version_mut() = http::Version::HTTP_2;
                 res
@@ -171,6 +207,593 @@ where
     }
 }
 
-fn was_absolute_form(val: &[u8]) -> bool {
-    val.len() >= "HTTP/1.1; absolute-form".len() && &val[10..23] == b"absolute-form"
+/// Removes HTTP/1 framing headers, since transfer-encoding is illegal in
+/// HTTP2. A message that has both a transfer-encoding and a content-length is
+/// framed by its transfer-encoding, so its content-length is removed as well.
+fn strip_framing(headers: &mut HeaderMap) {
+    if headers.remove(TRANSFER_ENCODING).is_some() {
+        headers.remove(CONTENT_LENGTH);
+    }
+}
+
+// ===== impl OrigProto =====
+
+impl OrigProto {
+    /// Returns a value without flags, if `version` is HTTP/1.
+    pub fn new(version: http::Version) -> Option<Self> {
+        if version != http::Version::HTTP_11 && version != http::Version::HTTP_10 {
+            return None;
+        }
+
+        Some(Self {
+            version,
+            absolute_form: false,
+            keep_alive: false,
+            head_request: false,
+            expect_continue: false,
+            trailers: false,
+            body_checksum: false,
+        })
+    }
+
+    /// Describes an HTTP/1 request, after its connection headers have been
+    /// stripped.
+    pub fn from_request<B>(req: &http::Request<B>) -> Option<Self> {
+        let mut orig_proto = Self::new(req.version())?;
+        orig_proto.absolute_form = h1::is_absolute_form(req.uri());
+        orig_proto.keep_alive = req.version() == http::Version::HTTP_10
+            && req.extensions().get::<h1::KeepAlive>().is_some();
+        orig_proto.head_request = *req.method() == http::Method::HEAD;
+        orig_proto.expect_continue = req
+            .headers()
+            .get(EXPECT)
+            .map(|v| v.as_bytes().eq_ignore_ascii_case(b"100-continue"))
+            .unwrap_or(false);
+        orig_proto.trailers = h1::wants_trailers(req);
+        Some(orig_proto)
+    }
+
+    /// Parses an `l5d-orig-proto` header value.
+    ///
+    /// The version must be exactly `HTTP/1.1` or `HTTP/1.0`, and each flag
+    /// must be a token, optionally with a `=`-separated token value. Known
+    /// flags may not have values.
+    pub fn parse(value: &[u8]) -> Result<Self, InvalidOrigProto> {
+        let mut parts = value.split(|b| *b == b';');
+        let version = match parts.next() {
+            Some(b"HTTP/1.1") => http::Version::HTTP_11,
+            Some(b"HTTP/1.0") => http::Version::HTTP_10,
+            _ => return Err(InvalidOrigProto(())),
+        };
+        let mut orig_proto = Self::new(version).expect("version must be HTTP/1");
+
+        for flag in parts.map(trim) {
+            if flag == ABSOLUTE_FORM.as_bytes() {
+                orig_proto.absolute_form = true;
+            } else if flag == KEEP_ALIVE.as_bytes() {
+                orig_proto.keep_alive = true;
+            } else if flag == HEAD_REQUEST.as_bytes() {
+                orig_proto.head_request = true;
+            } else if flag == EXPECT_CONTINUE.as_bytes() {
+                orig_proto.expect_continue = true;
+            } else if flag == TRAILERS.as_bytes() {
+                orig_proto.trailers = true;
+            } else if flag == BODY_CHECKSUM.as_bytes() {
+                orig_proto.body_checksum = true;
+            } else if is_unknown_flag(flag) {
+                trace!(flag = ?String::from_utf8_lossy(flag), "ignoring unknown flag");
+            } else {
+                return Err(InvalidOrigProto(()));
+            }
+        }
+
+        Ok(orig_proto)
+    }
+
+    pub fn version(&self) -> http::Version {
+        self.version
+    }
+
+    fn to_header_value(&self) -> HeaderValue {
+        HeaderValue::from_str(&self.to_string()).expect("orig-proto values must be valid headers")
+    }
+
+    /// Restores the request that this value describes.
+    fn downgrade<B>(&self, req: &mut http::Request<B>) {
+        *req.version_mut() = self.version;
+
+        if !self.absolute_form {
+            h1::set_origin_form(req.uri_mut());
+        }
+
+        if self.keep_alive && self.version == http::Version::HTTP_10 {
+            req.headers_mut()
+                .insert(CONNECTION, HeaderValue::from_static(KEEP_ALIVE));
+        }
+
+        if self.expect_continue {
+            req.headers_mut()
+                .insert(EXPECT, HeaderValue::from_static("100-continue"));
+        }
+
+        if self.trailers {
+            req.headers_mut()
+                .insert(TE, HeaderValue::from_static(TRAILERS));
+        }
+    }
+}
+
+impl fmt::Display for OrigProto {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.version == http::Version::HTTP_10 {
+            write!(f, "HTTP/1.0")?;
+        } else {
+            write!(f, "HTTP/1.1")?;
+        }
+
+        // Older proxies only recognize absolute-form when it's the first
+        // flag, so it must precede all others.
+        let flags = [
+            (self.absolute_form, ABSOLUTE_FORM),
+            (self.keep_alive, KEEP_ALIVE),
+            (self.head_request, HEAD_REQUEST),
+            (self.expect_continue, EXPECT_CONTINUE),
+            (self.trailers, TRAILERS),
+            (self.body_checksum, BODY_CHECKSUM),
+        ];
+        for (_, flag) in flags.iter().filter(|(set, _)| *set) {
+            write!(f, "; {}", flag)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns true if a message's `l5d-orig-proto` header has the
+/// `body-checksum` flag.
+pub(crate) fn has_body_checksum(headers: &HeaderMap) -> bool {
+    headers
+        .get(L5D_ORIG_PROTO)
+        .and_then(|v| OrigProto::parse(v.as_bytes()).ok())
+        .map(|p| p.body_checksum)
+        .unwrap_or(false)
+}
+
+/// Sets the `body-checksum` flag on a message's `l5d-orig-proto` header.
+/// Returns false if the message has no valid header to set it on.
+pub(crate) fn set_body_checksum(headers: &mut HeaderMap) -> bool {
+    let orig_proto = headers
+        .get(L5D_ORIG_PROTO)
+        .and_then(|v| OrigProto::parse(v.as_bytes()).ok());
+    match orig_proto {
+        Some(mut orig_proto) => {
+            orig_proto.body_checksum = true;
+            headers.insert(L5D_ORIG_PROTO, orig_proto.to_header_value());
+            true
+        }
+        None => false,
+    }
+}
+
+/// Trims optional whitespace from a flag.
+fn trim(s: &[u8]) -> &[u8] {
+    let is_ows = |b: &u8| *b == b' ' || *b == b'\t';
+    let start = s.iter().position(|b| !is_ows(b)).unwrap_or(s.len());
+    let end = s
+        .iter()
+        .rposition(|b| !is_ows(b))
+        .map(|i| i + 1)
+        .unwrap_or(start);
+    &s[start..end]
+}
+
+fn is_unknown_flag(flag: &[u8]) -> bool {
+    let mut parts = flag.splitn(2, |b| *b == b'=');
+    let name = parts.next().unwrap_or_default();
+    let is_known = name == ABSOLUTE_FORM.as_bytes()
+        || name == KEEP_ALIVE.as_bytes()
+        || name == HEAD_REQUEST.as_bytes()
+        || name == EXPECT_CONTINUE.as_bytes()
+        || name == TRAILERS.as_bytes()
+        || name == BODY_CHECKSUM.as_bytes();
+    !is_known && is_token(name) && parts.next().map(is_token).unwrap_or(true)
+}
+
+fn is_token(s: &[u8]) -> bool {
+    !s.is_empty()
+        && s.iter()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(b))
+}
+
+// ===== impl InvalidOrigProto =====
+
+impl fmt::Display for InvalidOrigProto {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid {} header", L5D_ORIG_PROTO)
+    }
+}
+
+impl std::error::Error for InvalidOrigProto {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use http::header::HOST;
+    use quickcheck::{quickcheck, Arbitrary, Gen};
+    use tower::{service_fn, Service};
+
+    /// The parts of a request, as it was received by the downgrading side's
+    /// inner service.
+    struct Received(http::Method, http::Version, http::Uri, http::HeaderMap);
+
+    /// Upgrades a request and downgrades it again, returning the request as
+    /// it was received by the downgrading side's inner service and the
+    /// response as it was returned by the upgrading side.
+    fn roundtrip_with(
+        mut req: http::Request<()>,
+        rsp: http::Response<()>,
+    ) -> (Received, http::Response<()>) {
+        let (status, headers) = (rsp.status(), rsp.headers().clone());
+        let inner = service_fn(move |req: http::Request<()>| {
+            let mut rsp = http::Response::new(());
+            *rsp.status_mut() = status;
+            *rsp.headers_mut() = headers.clone();
+            *rsp.version_mut() = req.version();
+            rsp.extensions_mut().insert(Received(
+                req.method().clone(),
+                req.version(),
+                req.uri().clone(),
+                req.headers().clone(),
+            ));
+            future::ok::<_, ()>(rsp)
+        });
+        let mut svc = Upgrade::new(Downgrade::new(inner));
+
+        // The upgrading side's server strips connection headers before the
+        // request is routed.
+        h1::strip_request_connection_headers(&mut req);
+
+        let mut rsp = svc.call(req).wait().expect("must succeed");
+        let received = rsp.extensions_mut().remove::<Received>().unwrap();
+        (received, rsp)
+    }
+
+    fn roundtrip(req: http::Request<()>) -> (http::Version, http::Uri) {
+        let (Received(_, _, uri, _), rsp) = roundtrip_with(req, http::Response::new(()));
+        (rsp.version(), uri)
+    }
+
+    #[test]
+    fn preserves_absolute_form() {
+        let req = http::Request::builder()
+            .uri("http://web.ns.svc.cluster.local/users?id=1")
+            .body(())
+            .unwrap();
+        let (version, uri) = roundtrip(req);
+        assert_eq!(version, http::Version::HTTP_11);
+        assert_eq!(uri, "http://web.ns.svc.cluster.local/users?id=1");
+    }
+
+    #[test]
+    fn preserves_origin_form() {
+        let req = http::Request::builder()
+            .version(http::Version::HTTP_10)
+            .uri("/users?id=1")
+            .header(HOST, "web.ns.svc.cluster.local")
+            .body(())
+            .unwrap();
+        let (version, uri) = roundtrip(req);
+        assert_eq!(version, http::Version::HTTP_10);
+        assert_eq!(uri, "/users?id=1");
+    }
+
+    #[test]
+    fn preserves_trailers_expectation() {
+        for version in &[http::Version::HTTP_10, http::Version::HTTP_11] {
+            let req = http::Request::builder()
+                .version(*version)
+                .uri("/")
+                .header(HOST, "web.ns.svc.cluster.local")
+                .header(TE, "trailers")
+                .body(())
+                .unwrap();
+            let (Received(_, _, _, headers), _) = roundtrip_with(req, http::Response::new(()));
+            assert_eq!(headers.get(TE).unwrap(), "trailers", "{:?}", version);
+
+            let req = http::Request::builder()
+                .version(*version)
+                .uri("/")
+                .header(HOST, "web.ns.svc.cluster.local")
+                .body(())
+                .unwrap();
+            let (Received(_, _, _, headers), _) = roundtrip_with(req, http::Response::new(()));
+            assert!(headers.get(TE).is_none(), "{:?}", version);
+        }
+    }
+
+    /// How a message's body is delimited, as far as its headers say. Bodies
+    /// that aren't length-delimited are framed by the HTTP/1 codec.
+    #[derive(Debug, PartialEq)]
+    enum Framing {
+        Length(HeaderValue),
+        Codec,
+    }
+
+    #[derive(Copy, Clone, Debug)]
+    enum Body {
+        Empty,
+        Length,
+        Chunked,
+        LengthAndChunked,
+    }
+
+    #[derive(Copy, Clone, Debug)]
+    enum Options {
+        Default,
+        KeepAlive,
+        Close,
+        ExpectContinue,
+    }
+
+    const BODIES: &[Body] = &[
+        Body::Empty,
+        Body::Length,
+        Body::Chunked,
+        Body::LengthAndChunked,
+    ];
+
+    impl Body {
+        fn set(self, headers: &mut http::HeaderMap) {
+            if let Body::Length | Body::LengthAndChunked = self {
+                headers.insert(CONTENT_LENGTH, HeaderValue::from_static("5"));
+            }
+            if let Body::Chunked | Body::LengthAndChunked = self {
+                headers.insert(TRANSFER_ENCODING, HeaderValue::from_static("chunked"));
+            }
+        }
+
+        fn framing(self) -> Framing {
+            match self {
+                Body::Length => Framing::Length(HeaderValue::from_static("5")),
+                Body::Empty | Body::Chunked | Body::LengthAndChunked => Framing::Codec,
+            }
+        }
+    }
+
+    fn framing(headers: &http::HeaderMap) -> Framing {
+        assert!(
+            headers.get(TRANSFER_ENCODING).is_none(),
+            "transfer-encoding must not be forwarded"
+        );
+        match headers.get(CONTENT_LENGTH) {
+            Some(len) => Framing::Length(len.clone()),
+            None => Framing::Codec,
+        }
+    }
+
+    #[test]
+    fn requests_roundtrip() {
+        let methods = [
+            http::Method::GET,
+            http::Method::HEAD,
+            http::Method::POST,
+            http::Method::PUT,
+            http::Method::DELETE,
+            http::Method::OPTIONS,
+        ];
+        let options = [
+            Options::Default,
+            Options::KeepAlive,
+            Options::Close,
+            Options::ExpectContinue,
+        ];
+        let versions = [http::Version::HTTP_10, http::Version::HTTP_11];
+        let uris = ["/users?id=1", "http://web.ns.svc.cluster.local/users?id=1"];
+
+        for method in methods.iter() {
+            for body in BODIES {
+                for opts in options.iter() {
+                    for version in versions.iter() {
+                        for uri in uris.iter() {
+                            let case = (method, body, opts, version, uri);
+                            let mut req = http::Request::builder()
+                                .method(method.clone())
+                                .version(*version)
+                                .uri(*uri)
+                                .header(HOST, "web.ns.svc.cluster.local")
+                                .body(())
+                                .unwrap();
+                            body.set(req.headers_mut());
+                            match opts {
+                                Options::Default => {}
+                                Options::KeepAlive => {
+                                    req.headers_mut()
+                                        .insert(CONNECTION, HeaderValue::from_static("keep-alive"));
+                                }
+                                Options::Close => {
+                                    req.headers_mut()
+                                        .insert(CONNECTION, HeaderValue::from_static("close"));
+                                }
+                                Options::ExpectContinue => {
+                                    req.headers_mut()
+                                        .insert(EXPECT, HeaderValue::from_static("100-continue"));
+                                }
+                            }
+
+                            let (Received(m, v, u, headers), rsp) =
+                                roundtrip_with(req, http::Response::new(()));
+                            assert_eq!(&m, method, "{:?}", case);
+                            assert_eq!(&v, version, "{:?}", case);
+                            assert_eq!(&u, uri, "{:?}", case);
+                            assert_eq!(rsp.version(), *version, "{:?}", case);
+                            assert_eq!(framing(&headers), body.framing(), "{:?}", case);
+                            assert_eq!(
+                                headers.get(HOST).unwrap(),
+                                "web.ns.svc.cluster.local",
+                                "{:?}",
+                                case
+                            );
+                            assert!(headers.get(L5D_ORIG_PROTO).is_none(), "{:?}", case);
+
+                            // Only HTTP/1.0 connections need to ask to be
+                            // kept alive; closing is a property of this hop.
+                            let keep_alive = match (opts, version) {
+                                (Options::KeepAlive, &http::Version::HTTP_10) => true,
+                                _ => false,
+                            };
+                            assert_eq!(headers.get(CONNECTION).is_some(), keep_alive, "{:?}", case);
+                            let expect = match opts {
+                                Options::ExpectContinue => true,
+                                _ => false,
+                            };
+                            assert_eq!(
+                                headers.get(EXPECT).map(|v| v == "100-continue"),
+                                if expect { Some(true) } else { None },
+                                "{:?}",
+                                case
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn responses_roundtrip() {
+        let methods = [http::Method::GET, http::Method::HEAD];
+        let statuses = [
+            http::StatusCode::OK,
+            http::StatusCode::NO_CONTENT,
+            http::StatusCode::NOT_MODIFIED,
+            http::StatusCode::from_u16(103).unwrap(),
+        ];
+        let versions = [http::Version::HTTP_10, http::Version::HTTP_11];
+
+        for method in methods.iter() {
+            for status in statuses.iter() {
+                for body in BODIES {
+                    for version in versions.iter() {
+                        let case = (method, status, body, version);
+                        let req = http::Request::builder()
+                            .method(method.clone())
+                            .version(*version)
+                            .uri("/")
+                            .header(HOST, "web.ns.svc.cluster.local")
+                            .body(())
+                            .unwrap();
+                        let mut rsp = http::Response::new(());
+                        *rsp.status_mut() = *status;
+                        body.set(rsp.headers_mut());
+
+                        let (_, rsp) = roundtrip_with(req, rsp);
+                        assert_eq!(rsp.version(), *version, "{:?}", case);
+                        assert_eq!(rsp.status(), *status, "{:?}", case);
+                        assert!(rsp.headers().get(L5D_ORIG_PROTO).is_none(), "{:?}", case);
+
+                        // HEAD responses keep the content-length of the
+                        // body that they omit, unless they can't have one.
+                        let expected = if status.is_informational()
+                            || *status == http::StatusCode::NO_CONTENT
+                        {
+                            Framing::Codec
+                        } else {
+                            body.framing()
+                        };
+                        assert_eq!(framing(rsp.headers()), expected, "{:?}", case);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn parses_legacy_values() {
+        let p = OrigProto::parse(b"HTTP/1.1").unwrap();
+        assert_eq!(p, OrigProto::new(http::Version::HTTP_11).unwrap());
+
+        let p = OrigProto::parse(b"HTTP/1.0; absolute-form").unwrap();
+        assert_eq!(p.version(), http::Version::HTTP_10);
+        assert!(p.absolute_form);
+    }
+
+    #[test]
+    fn ignores_unknown_flags() {
+        let p =
+            OrigProto::parse(b"HTTP/1.1; absolute-form; future; opt=val; head-request").unwrap();
+        assert!(p.absolute_form && p.head_request);
+        assert!(!p.keep_alive && !p.expect_continue);
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        for v in &[
+            &b""[..],
+            b"HTTP/2",
+            b"HTTP/1.1 ",
+            b"http/1.1",
+            b"HTTP/1.1;",
+            b"HTTP/1.1; ; keep-alive",
+            b"HTTP/1.1; keep alive",
+            b"HTTP/1.1; keep-alive=yes",
+            b"HTTP/1.1; opt=",
+            b"HTTP/1.1; opt=a=b",
+        ] {
+            assert!(
+                OrigProto::parse(v).is_err(),
+                "{:?} must be invalid",
+                String::from_utf8_lossy(v)
+            );
+        }
+    }
+
+    impl Arbitrary for OrigProto {
+        fn arbitrary<G: Gen>(g: &mut G) -> Self {
+            let version = if bool::arbitrary(g) {
+                http::Version::HTTP_11
+            } else {
+                http::Version::HTTP_10
+            };
+            let mut orig_proto = OrigProto::new(version).unwrap();
+            orig_proto.absolute_form = bool::arbitrary(g);
+            orig_proto.keep_alive = bool::arbitrary(g);
+            orig_proto.head_request = bool::arbitrary(g);
+            orig_proto.expect_continue = bool::arbitrary(g);
+            orig_proto.trailers = bool::arbitrary(g);
+            orig_proto.body_checksum = bool::arbitrary(g);
+            orig_proto
+        }
+    }
+
+    quickcheck! {
+        fn parse_does_not_panic(value: Vec<u8>) -> bool {
+            let _ = OrigProto::parse(&value);
+            true
+        }
+
+        fn parse_inverts_display(orig_proto: OrigProto) -> bool {
+            let value = orig_proto.to_header_value();
+            OrigProto::parse(value.as_bytes()).ok() == Some(orig_proto)
+        }
+
+        fn parse_ignores_unknown_flags(orig_proto: OrigProto, flags: Vec<String>) -> bool {
+            let mut value = orig_proto.to_string();
+            for flag in flags.iter().filter(|f| is_unknown_flag(f.as_bytes())) {
+                value.push_str("; ");
+                value.push_str(flag);
+            }
+            OrigProto::parse(value.as_bytes()).ok() == Some(orig_proto)
+        }
+
+        fn display_is_compatible_with_legacy_parsing(orig_proto: OrigProto) -> bool {
+            // Older proxies check the version prefix, and for absolute-form
+            // at a fixed offset.
+            let value = orig_proto.to_header_value();
+            let val = value.as_bytes();
+            let absolute_form = val.len() >= "HTTP/1.1; absolute-form".len()
+                && &val[10..23] == b"absolute-form";
+            val.starts_with(b"HTTP/1.") && absolute_form == orig_proto.absolute_form
+        }
+    }
 }
@@ -0,0 +1,552 @@
+//! A per-request record of the decisions made on a request's behalf.
+//!
+//! The outcome `layer` inserts an `Outcome` handle into each request's
+//! extensions. Inner layers fill in the record as they make decisions, and it
+//! is finalized once the response completes, i.e. when its body ends or is
+//! dropped. Consumers read the finalized record rather than deriving their
+//! own view of the request, so they can't disagree with one another.
+//!
+//! Each field has exactly one writer:
+//!
+//! - `status` is written by the outcome layer, from the response it returns;
+//! - `class` is written by the metrics layer configured `with_outcome`, i.e.
+//!   the route's, which classifies the response after all retries;
+//! - `attempts` is written by the `retry` layer;
+//! - `endpoint` and `route_labels` are written by `record` layers on the
+//!   endpoint and route stacks, respectively;
+//! - `error` is written by the layer that synthesizes a response from an
+//!   error;
+//! - `correlation_id` is written by the `correlation_id` layer, if it's
+//!   configured to echo IDs.
+//!
+//! Once the record is final, it's annotated on the request's span (if the
+//! request is being traced), and it's logged at the `debug` level, as an
+//! access log.
+
+use futures::{try_ready, Async, Future, Poll};
+use http;
+use hyper::body::Payload;
+use indexmap::IndexMap;
+use linkerd2_metrics::FmtLabels;
+use linkerd2_trace_context as trace_context;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tracing::debug;
+
+/// A handle to a request's outcome, shared via its extensions.
+#[derive(Clone, Debug, Default)]
+pub struct Outcome(Arc<Mutex<State>>);
+
+/// What became of a request.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    /// The status of the response returned to the client.
+    pub status: Option<http::StatusCode>,
+    /// The response's class, formatted as its metric labels.
+    pub class: Option<String>,
+    /// The class of the error from which the response was synthesized.
+    pub error: Option<&'static str>,
+    /// The number of times the request was dispatched, including retries.
+    pub attempts: usize,
+    /// The endpoint that served the last attempt.
+    pub endpoint: Option<SocketAddr>,
+    /// A hash of the labels of the route that served the request.
+    pub route_labels: Option<u64>,
+    /// The request's correlation ID.
+    pub correlation_id: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    record: Record,
+    is_final: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Layer(());
+
+#[derive(Clone, Debug)]
+pub struct Stack<M> {
+    inner: M,
+}
+
+pub struct MakeFuture<F> {
+    inner: F,
+}
+
+#[derive(Clone, Debug)]
+pub struct Service<S> {
+    inner: S,
+}
+
+pub struct ResponseFuture<F> {
+    inner: F,
+    finalize: Option<Finalize>,
+}
+
+#[derive(Debug)]
+pub struct ResponseBody<B> {
+    // Dropped before the record is finalized, so that inner bodies may
+    // complete the record as they're dropped.
+    inner: Option<B>,
+    finalize: Option<Finalize>,
+}
+
+/// Finalizes an outcome and notifies its consumers.
+#[derive(Debug)]
+struct Finalize {
+    outcome: Outcome,
+    status: Option<http::StatusCode>,
+    annotations: Option<trace_context::Annotations>,
+    // Holds the request's span until it has been annotated.
+    _deferral: Option<trace_context::Deferral>,
+}
+
+/// Formats a class as its metric labels.
+struct Labels<'a, C>(&'a C);
+
+/// Inserts an `Outcome` into each request's extensions (unless one is already
+/// present) and finalizes it when the response completes.
+pub fn layer() -> Layer {
+    Layer(())
+}
+
+pub(crate) fn class_labels<C: FmtLabels>(class: &C) -> String {
+    Labels(class).to_string()
+}
+
+// === impl Outcome ===
+
+impl Outcome {
+    /// Returns the record, once it is final.
+    pub fn get(&self) -> Option<Record> {
+        let state = self.0.lock().ok()?;
+        if state.is_final {
+            Some(state.record.clone())
+        } else {
+            None
+        }
+    }
+
+    pub fn set_class(&self, class: String) {
+        self.update(|r| r.class = Some(class));
+    }
+
+    pub fn set_error(&self, error: &'static str) {
+        self.update(|r| r.error = Some(error));
+    }
+
+    pub fn set_attempts(&self, attempts: usize) {
+        self.update(|r| r.attempts = attempts);
+    }
+
+    pub fn set_endpoint(&self, endpoint: SocketAddr) {
+        self.update(|r| r.endpoint = Some(endpoint));
+    }
+
+    pub fn set_route_labels(&self, labels: &IndexMap<String, String>) {
+        let mut hasher = DefaultHasher::new();
+        for (k, v) in labels.iter() {
+            k.hash(&mut hasher);
+            v.hash(&mut hasher);
+        }
+        let hash = hasher.finish();
+        self.update(|r| r.route_labels = Some(hash));
+    }
+
+    pub fn set_correlation_id(&self, id: String) {
+        self.update(|r| r.correlation_id = Some(id));
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Record)) {
+        if let Ok(mut state) = self.0.lock() {
+            if state.is_final {
+                debug!("ignoring update to a final outcome");
+                return;
+            }
+            f(&mut state.record);
+        }
+    }
+
+    fn finalize(&self, status: Option<http::StatusCode>) -> Option<Record> {
+        let mut state = self.0.lock().ok()?;
+        if state.is_final {
+            return None;
+        }
+        state.record.status = status;
+        state.is_final = true;
+        Some(state.record.clone())
+    }
+}
+
+// === impl Record ===
+
+impl Default for Record {
+    fn default() -> Self {
+        Self {
+            status: None,
+            class: None,
+            error: None,
+            attempts: 1,
+            endpoint: None,
+            route_labels: None,
+            correlation_id: None,
+        }
+    }
+}
+
+// === impl Layer ===
+
+impl<M> tower::layer::Layer<M> for Layer {
+    type Service = Stack<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        Stack { inner }
+    }
+}
+
+// === impl Stack ===
+
+impl<T, M> tower::Service<T> for Stack<M>
+where
+    M: tower::Service<T>,
+{
+    type Response = Service<M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        MakeFuture {
+            inner: self.inner.call(target),
+        }
+    }
+}
+
+// === impl MakeFuture ===
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = Service<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        Ok(Service { inner }.into())
+    }
+}
+
+// === impl Service ===
+
+impl<S, A, B> tower::Service<http::Request<A>> for Service<S>
+where
+    S: tower::Service<http::Request<A>, Response = http::Response<B>>,
+{
+    type Response = http::Response<ResponseBody<B>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut req: http::Request<A>) -> Self::Future {
+        let outcome = match req.extensions().get::<Outcome>() {
+            Some(outcome) => outcome.clone(),
+            None => {
+                let outcome = Outcome::default();
+                req.extensions_mut().insert(outcome.clone());
+                outcome
+            }
+        };
+        let annotations = req
+            .extensions()
+            .get::<trace_context::Annotations>()
+            .cloned();
+        let finalize = Finalize {
+            outcome,
+            status: None,
+            _deferral: annotations.as_ref().map(trace_context::Annotations::defer),
+            annotations,
+        };
+
+        ResponseFuture {
+            inner: self.inner.call(req),
+            finalize: Some(finalize),
+        }
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+{
+    type Item = http::Response<ResponseBody<B>>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let rsp = match self.inner.poll() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(rsp)) => rsp,
+            Err(e) => {
+                if let Some(finalize) = self.finalize.take() {
+                    finalize.finalize();
+                }
+                return Err(e);
+            }
+        };
+
+        let mut finalize = self.finalize.take().expect("polled after complete");
+        finalize.status = Some(rsp.status());
+        let (head, inner) = rsp.into_parts();
+        let body = ResponseBody {
+            inner: Some(inner),
+            finalize: Some(finalize),
+        };
+        Ok(http::Response::from_parts(head, body).into())
+    }
+}
+
+// === impl ResponseBody ===
+
+impl<B> ResponseBody<B> {
+    fn inner(&mut self) -> &mut B {
+        self.inner.as_mut().expect("body polled after drop")
+    }
+
+    fn finalize(&mut self) {
+        if let Some(finalize) = self.finalize.take() {
+            finalize.finalize();
+        }
+    }
+}
+
+impl<B: Default> Default for ResponseBody<B> {
+    fn default() -> Self {
+        Self {
+            inner: Some(B::default()),
+            finalize: None,
+        }
+    }
+}
+
+impl<B: Payload> Payload for ResponseBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner
+            .as_ref()
+            .map(Payload::is_end_stream)
+            .unwrap_or(true)
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        self.inner().poll_data().map_err(|e| {
+            self.finalize();
+            e
+        })
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        let trls = self.inner().poll_trailers();
+        if let Ok(Async::NotReady) = trls {
+            return trls;
+        }
+        // The stream has ended, so inner layers have recorded their view of it.
+        self.finalize();
+        trls
+    }
+}
+
+impl<B: Payload> http_body::Body for ResponseBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn is_end_stream(&self) -> bool {
+        Payload::is_end_stream(self)
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        Payload::poll_data(self)
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        Payload::poll_trailers(self)
+    }
+}
+
+impl<B> Drop for ResponseBody<B> {
+    fn drop(&mut self) {
+        // Inner bodies may classify the response as they're dropped.
+        drop(self.inner.take());
+        self.finalize();
+    }
+}
+
+// === impl Finalize ===
+
+impl Finalize {
+    fn finalize(self) {
+        let record = match self.outcome.finalize(self.status) {
+            Some(record) => record,
+            None => return,
+        };
+
+        if let Some(annotations) = self.annotations.as_ref() {
+            let mut attributes = vec![("attempts", record.attempts.to_string())];
+            if let Some(ref class) = record.class {
+                attributes.push(("class", class.clone()));
+            }
+            if let Some(error) = record.error {
+                attributes.push(("error.class", error.to_owned()));
+            }
+            if let Some(endpoint) = record.endpoint {
+                attributes.push(("endpoint", endpoint.to_string()));
+            }
+            if let Some(ref id) = record.correlation_id {
+                attributes.push(("correlation_id", id.clone()));
+            }
+            annotations.annotate("outcome", &attributes);
+        }
+
+        debug!(
+            status = ?record.status,
+            class = ?record.class,
+            error = ?record.error,
+            attempts = record.attempts as u64,
+            endpoint = ?record.endpoint,
+            route_labels = ?record.route_labels,
+            correlation_id = ?record.correlation_id,
+            "request complete"
+        );
+        // The span is emitted as `_deferral` is dropped.
+    }
+}
+
+// === impl Labels ===
+
+impl<'a, C: FmtLabels> fmt::Display for Labels<'a, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt_labels(f)
+    }
+}
+
+/// Records target-specific parts of an outcome, e.g. the endpoint that served
+/// a request.
+pub mod record {
+    use super::Outcome;
+    use futures::{try_ready, Future, Poll};
+    use http;
+
+    /// Applies `record` to the target and `Outcome` of each request.
+    pub fn layer<T, F>(record: F) -> Layer<F>
+    where
+        F: Fn(&T, &Outcome) + Clone,
+    {
+        Layer { record }
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct Layer<F> {
+        record: F,
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct Stack<M, F> {
+        inner: M,
+        record: F,
+    }
+
+    pub struct MakeFuture<T, F, R> {
+        inner: F,
+        target: Option<T>,
+        record: Option<R>,
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct Service<T, S, F> {
+        inner: S,
+        target: T,
+        record: F,
+    }
+
+    impl<M, F: Clone> tower::layer::Layer<M> for Layer<F> {
+        type Service = Stack<M, F>;
+
+        fn layer(&self, inner: M) -> Self::Service {
+            Stack {
+                inner,
+                record: self.record.clone(),
+            }
+        }
+    }
+
+    impl<T, M, F> tower::Service<T> for Stack<M, F>
+    where
+        T: Clone,
+        M: tower::Service<T>,
+        F: Fn(&T, &Outcome) + Clone,
+    {
+        type Response = Service<T, M::Response, F>;
+        type Error = M::Error;
+        type Future = MakeFuture<T, M::Future, F>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            self.inner.poll_ready()
+        }
+
+        fn call(&mut self, target: T) -> Self::Future {
+            MakeFuture {
+                target: Some(target.clone()),
+                record: Some(self.record.clone()),
+                inner: self.inner.call(target),
+            }
+        }
+    }
+
+    impl<T, F: Future, R> Future for MakeFuture<T, F, R> {
+        type Item = Service<T, F::Item, R>;
+        type Error = F::Error;
+
+        fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+            let inner = try_ready!(self.inner.poll());
+            let target = self.target.take().expect("polled after complete");
+            let record = self.record.take().expect("polled after complete");
+            Ok(Service {
+                inner,
+                target,
+                record,
+            }
+            .into())
+        }
+    }
+
+    impl<T, S, F, B> tower::Service<http::Request<B>> for Service<T, S, F>
+    where
+        S: tower::Service<http::Request<B>>,
+        F: Fn(&T, &Outcome),
+    {
+        type Response = S::Response;
+        type Error = S::Error;
+        type Future = S::Future;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            self.inner.poll_ready()
+        }
+
+        fn call(&mut self, req: http::Request<B>) -> Self::Future {
+            if let Some(outcome) = req.extensions().get::<Outcome>() {
+                (self.record)(&self.target, outcome);
+            }
+            self.inner.call(req)
+        }
+    }
+}
@@ -0,0 +1,620 @@
+//! Reports the state of each endpoint's pool of client connections.
+//!
+//! The connections in an endpoint's pool are counted as the connector beneath
+//! its HTTP client establishes and closes them. Requests are counted while
+//! they're in flight on the client, from when they're dispatched until their
+//! response bodies complete. Each in-flight request occupies a connection (or,
+//! for HTTP/2, shares the endpoint's only connection), so connections are
+//! reported as active while requests are in flight and idle otherwise.
+
+use futures::{try_ready, Async, Future, Poll};
+use hyper::body::Payload;
+use indexmap::IndexMap;
+use linkerd2_metrics::{metrics, Counter, FmtLabels, FmtMetrics, Gauge};
+use std::fmt;
+use std::hash::Hash;
+use std::io;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+metrics! {
+    endpoint_pool_connections: Gauge {
+        "Number of connections in each endpoint's client pool"
+    },
+    endpoint_pool_active_connections: Gauge {
+        "Number of pooled connections that are serving requests"
+    },
+    endpoint_pool_idle_connections: Gauge {
+        "Number of pooled connections that are not serving requests"
+    },
+    endpoint_pool_connections_created_total: Counter {
+        "Total count of connections added to each endpoint's client pool"
+    },
+    endpoint_pool_connections_closed_total: Counter {
+        "Total count of connections closed in each endpoint's client pool"
+    }
+}
+
+/// Tracks each endpoint's pool, by `K`-typed labels.
+#[derive(Debug)]
+pub struct Registry<K: Hash + Eq>(Arc<Mutex<IndexMap<K, Pool>>>);
+
+type Pool = Arc<Mutex<Counts>>;
+
+#[derive(Debug, Default)]
+struct Counts {
+    open: u64,
+    in_flight: u64,
+    created: Counter,
+    closed: Counter,
+}
+
+/// Counts the connections established by each endpoint's connector.
+#[derive(Debug)]
+pub struct ConnectLayer<K: Hash + Eq> {
+    registry: Registry<K>,
+}
+
+#[derive(Debug)]
+pub struct Connect<M, K: Hash + Eq> {
+    inner: M,
+    registry: Registry<K>,
+}
+
+pub struct ConnectFuture<F> {
+    inner: F,
+    pool: Option<Pool>,
+}
+
+/// A pooled connection, which is counted until it's dropped.
+#[derive(Debug)]
+pub struct Connection<C> {
+    inner: C,
+    pool: Pool,
+}
+
+/// Counts the requests in flight on each endpoint's client.
+#[derive(Debug)]
+pub struct Layer<K: Hash + Eq> {
+    registry: Registry<K>,
+}
+
+#[derive(Debug)]
+pub struct Stack<M, K: Hash + Eq> {
+    inner: M,
+    registry: Registry<K>,
+}
+
+pub struct MakeFuture<F> {
+    inner: F,
+    pool: Option<Pool>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Service<S> {
+    inner: S,
+    pool: Pool,
+}
+
+pub struct ResponseFuture<F> {
+    inner: F,
+    in_flight: Option<InFlight>,
+}
+
+/// A response body, whose request is in flight until the body completes.
+#[derive(Debug, Default)]
+pub struct Body<B> {
+    inner: B,
+    in_flight: Option<InFlight>,
+}
+
+#[derive(Debug)]
+struct InFlight(Pool);
+
+pub fn connect_layer<K: Hash + Eq>(registry: Registry<K>) -> ConnectLayer<K> {
+    ConnectLayer { registry }
+}
+
+pub fn layer<K: Hash + Eq>(registry: Registry<K>) -> Layer<K> {
+    Layer { registry }
+}
+
+// === impl Registry ===
+
+impl<K: Hash + Eq> Registry<K> {
+    fn pool(&self, labels: K) -> Option<Pool> {
+        let mut pools = self.0.lock().ok()?;
+        Some(pools.entry(labels).or_insert_with(Pool::default).clone())
+    }
+}
+
+impl<K: Hash + Eq> Clone for Registry<K> {
+    fn clone(&self) -> Self {
+        Registry(self.0.clone())
+    }
+}
+
+impl<K: Hash + Eq> Default for Registry<K> {
+    fn default() -> Self {
+        Registry(Arc::new(Mutex::new(IndexMap::default())))
+    }
+}
+
+impl<K: FmtLabels + Hash + Eq> FmtMetrics for Registry<K> {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pools = match self.0.lock() {
+            Ok(pools) => pools,
+            Err(_) => return Ok(()),
+        };
+        if pools.is_empty() {
+            return Ok(());
+        }
+
+        // Snapshot each pool so that gauges are consistent with each other.
+        let snapshots = pools
+            .iter()
+            .filter_map(|(labels, pool)| {
+                let counts = pool.lock().ok()?;
+                Some((labels, Snapshot::from(&*counts)))
+            })
+            .collect::<Vec<_>>();
+
+        endpoint_pool_connections.fmt_help(f)?;
+        endpoint_pool_connections
+            .fmt_scopes(f, snapshots.iter().map(|(l, s)| (*l, s)), |s| &s.open)?;
+
+        endpoint_pool_active_connections.fmt_help(f)?;
+        endpoint_pool_active_connections.fmt_scopes(
+            f,
+            snapshots.iter().map(|(l, s)| (*l, s)),
+            |s| &s.active,
+        )?;
+
+        endpoint_pool_idle_connections.fmt_help(f)?;
+        endpoint_pool_idle_connections.fmt_scopes(
+            f,
+            snapshots.iter().map(|(l, s)| (*l, s)),
+            |s| &s.idle,
+        )?;
+
+        endpoint_pool_connections_created_total.fmt_help(f)?;
+        endpoint_pool_connections_created_total.fmt_scopes(
+            f,
+            snapshots.iter().map(|(l, s)| (*l, s)),
+            |s| &s.created,
+        )?;
+
+        endpoint_pool_connections_closed_total.fmt_help(f)?;
+        endpoint_pool_connections_closed_total.fmt_scopes(
+            f,
+            snapshots.iter().map(|(l, s)| (*l, s)),
+            |s| &s.closed,
+        )?;
+
+        Ok(())
+    }
+}
+
+struct Snapshot {
+    open: Gauge,
+    active: Gauge,
+    idle: Gauge,
+    created: Counter,
+    closed: Counter,
+}
+
+impl<'a> From<&'a Counts> for Snapshot {
+    fn from(counts: &'a Counts) -> Self {
+        // Requests that are waiting for a connection don't make more
+        // connections active than there are.
+        let active = counts.in_flight.min(counts.open);
+        Snapshot {
+            open: counts.open.into(),
+            active: active.into(),
+            idle: (counts.open - active).into(),
+            created: counts.created,
+            closed: counts.closed,
+        }
+    }
+}
+
+// === impl ConnectLayer ===
+
+impl<M, K: Hash + Eq> tower::layer::Layer<M> for ConnectLayer<K> {
+    type Service = Connect<M, K>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        Connect {
+            inner,
+            registry: self.registry.clone(),
+        }
+    }
+}
+
+impl<K: Hash + Eq> Clone for ConnectLayer<K> {
+    fn clone(&self) -> Self {
+        Self {
+            registry: self.registry.clone(),
+        }
+    }
+}
+
+// === impl Connect ===
+
+impl<T, M, K> tower::Service<T> for Connect<M, K>
+where
+    T: Clone + Into<K>,
+    K: Hash + Eq,
+    M: tower::Service<T>,
+{
+    type Response = Connection<M::Response>;
+    type Error = M::Error;
+    type Future = ConnectFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        ConnectFuture {
+            pool: self.registry.pool(target.clone().into()),
+            inner: self.inner.call(target),
+        }
+    }
+}
+
+impl<M: Clone, K: Hash + Eq> Clone for Connect<M, K> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            registry: self.registry.clone(),
+        }
+    }
+}
+
+// === impl ConnectFuture ===
+
+impl<F: Future> Future for ConnectFuture<F> {
+    type Item = Connection<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        let pool = self.pool.take().unwrap_or_default();
+        if let Ok(mut counts) = pool.lock() {
+            counts.open += 1;
+            counts.created.incr();
+        }
+        Ok(Connection { inner, pool }.into())
+    }
+}
+
+// === impl Connection ===
+
+impl<C: io::Read> io::Read for Connection<C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<C: io::Write> io::Write for Connection<C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<C: AsyncRead> AsyncRead for Connection<C> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.inner.prepare_uninitialized_buffer(buf)
+    }
+}
+
+impl<C: AsyncWrite> AsyncWrite for Connection<C> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.inner.shutdown()
+    }
+}
+
+impl<C> Drop for Connection<C> {
+    fn drop(&mut self) {
+        if let Ok(mut counts) = self.pool.lock() {
+            counts.open = counts.open.saturating_sub(1);
+            counts.closed.incr();
+        }
+    }
+}
+
+// === impl Layer ===
+
+impl<M, K: Hash + Eq> tower::layer::Layer<M> for Layer<K> {
+    type Service = Stack<M, K>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        Stack {
+            inner,
+            registry: self.registry.clone(),
+        }
+    }
+}
+
+impl<K: Hash + Eq> Clone for Layer<K> {
+    fn clone(&self) -> Self {
+        Self {
+            registry: self.registry.clone(),
+        }
+    }
+}
+
+// === impl Stack ===
+
+impl<T, M, K> tower::Service<T> for Stack<M, K>
+where
+    T: Clone + Into<K>,
+    K: Hash + Eq,
+    M: tower::Service<T>,
+{
+    type Response = Service<M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        MakeFuture {
+            pool: self.registry.pool(target.clone().into()),
+            inner: self.inner.call(target),
+        }
+    }
+}
+
+impl<M: Clone, K: Hash + Eq> Clone for Stack<M, K> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            registry: self.registry.clone(),
+        }
+    }
+}
+
+// === impl MakeFuture ===
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = Service<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        let pool = self.pool.take().unwrap_or_default();
+        Ok(Service { inner, pool }.into())
+    }
+}
+
+// === impl Service ===
+
+impl<S, A, B> tower::Service<http::Request<A>> for Service<S>
+where
+    S: tower::Service<http::Request<A>, Response = http::Response<B>>,
+{
+    type Response = http::Response<Body<B>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: http::Request<A>) -> Self::Future {
+        let in_flight = InFlight::new(self.pool.clone());
+        ResponseFuture {
+            inner: self.inner.call(req),
+            in_flight: Some(in_flight),
+        }
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+{
+    type Item = http::Response<Body<B>>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let rsp = try_ready!(self.inner.poll());
+        let in_flight = self.in_flight.take();
+        Ok(rsp.map(|inner| Body { inner, in_flight }).into())
+    }
+}
+
+// === impl Body ===
+
+impl<B: Payload> Body<B> {
+    fn complete_if_end_stream(&mut self) {
+        if self.inner.is_end_stream() {
+            self.in_flight = None;
+        }
+    }
+}
+
+impl<B: Payload> Payload for Body<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        let frame = try_ready!(self.inner.poll_data());
+        if frame.is_none() {
+            self.in_flight = None;
+        } else {
+            self.complete_if_end_stream();
+        }
+        Ok(Async::Ready(frame))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        let trailers = try_ready!(self.inner.poll_trailers());
+        self.in_flight = None;
+        Ok(Async::Ready(trailers))
+    }
+}
+
+impl<B: Payload> http_body::Body for Body<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn is_end_stream(&self) -> bool {
+        Payload::is_end_stream(self)
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        Payload::poll_data(self)
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        Payload::poll_trailers(self)
+    }
+}
+
+// === impl InFlight ===
+
+impl InFlight {
+    fn new(pool: Pool) -> Self {
+        if let Ok(mut counts) = pool.lock() {
+            counts.in_flight += 1;
+        }
+        InFlight(pool)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if let Ok(mut counts) = self.0.lock() {
+            counts.in_flight = counts.in_flight.saturating_sub(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use tower::layer::Layer as _;
+    use tower::Service as _;
+
+    /// Establishes in-memory connections.
+    #[derive(Clone)]
+    struct MockConnect;
+
+    /// Makes services that respond to every request with an empty body.
+    struct MockClient;
+
+    impl tower::Service<&'static str> for MockConnect {
+        type Response = io::Cursor<Vec<u8>>;
+        type Error = io::Error;
+        type Future = future::FutureResult<Self::Response, io::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), io::Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: &'static str) -> Self::Future {
+            future::ok(io::Cursor::new(Vec::new()))
+        }
+    }
+
+    impl tower::Service<&'static str> for MockClient {
+        type Response = MockClient;
+        type Error = ();
+        type Future = future::FutureResult<MockClient, ()>;
+
+        fn poll_ready(&mut self) -> Poll<(), ()> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: &'static str) -> Self::Future {
+            future::ok(MockClient)
+        }
+    }
+
+    impl tower::Service<http::Request<()>> for MockClient {
+        type Response = http::Response<hyper::Body>;
+        type Error = ();
+        type Future = future::FutureResult<Self::Response, ()>;
+
+        fn poll_ready(&mut self) -> Poll<(), ()> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: http::Request<()>) -> Self::Future {
+            future::ok(http::Response::new(hyper::Body::from("hello")))
+        }
+    }
+
+    /// Returns the endpoint's (open, active, idle, created, closed) counts.
+    fn counts(registry: &Registry<&'static str>) -> (u64, u64, u64, u64, u64) {
+        let pool = registry.pool("web").unwrap();
+        let counts = pool.lock().unwrap();
+        let s = Snapshot::from(&*counts);
+        (
+            s.open.into(),
+            s.active.into(),
+            s.idle.into(),
+            s.created.into(),
+            s.closed.into(),
+        )
+    }
+
+    #[test]
+    fn connections_are_counted_until_closed() {
+        let registry = Registry::default();
+        let mut connect = connect_layer(registry.clone()).layer(MockConnect);
+
+        let conn0 = connect.call("web").wait().unwrap();
+        let conn1 = connect.call("web").wait().unwrap();
+        assert_eq!(counts(&registry), (2, 0, 2, 2, 0));
+
+        drop(conn0);
+        assert_eq!(counts(&registry), (1, 0, 1, 2, 1));
+        drop(conn1);
+        assert_eq!(counts(&registry), (0, 0, 0, 2, 2));
+    }
+
+    #[test]
+    fn connections_are_active_while_requests_are_in_flight() {
+        let registry = Registry::default();
+        let mut connect = connect_layer(registry.clone()).layer(MockConnect);
+        let mut client = layer(registry.clone())
+            .layer(MockClient)
+            .call("web")
+            .wait()
+            .unwrap();
+        let _conn = connect.call("web").wait().unwrap();
+        assert_eq!(counts(&registry), (1, 0, 1, 1, 0));
+
+        let rsp = client.call(http::Request::new(())).wait().unwrap();
+        assert_eq!(
+            counts(&registry),
+            (1, 1, 0, 1, 0),
+            "the connection must be active until the body completes"
+        );
+
+        let mut body = rsp.into_body();
+        while let Async::Ready(Some(_)) = Payload::poll_data(&mut body).unwrap() {}
+        assert_eq!(
+            counts(&registry),
+            (1, 0, 1, 1, 0),
+            "the connection must be idle once the body completes"
+        );
+    }
+}
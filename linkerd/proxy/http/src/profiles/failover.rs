@@ -0,0 +1,351 @@
+//! Fails requests over to a route's backup destination.
+//!
+//! When the primary destination's balancer is exhausted--for instance, because
+//! none of its endpoints became available before the request's dispatch
+//! deadline--the request is dispatched once more with a `Backup` marker so that
+//! the concrete router sends it to the route's backup destination instead.
+//!
+//! Errors raised after the request was dispatched to an endpoint (e.g.
+//! timeouts and resets) are returned as-is: the request may already have been
+//! processed upstream, so it must not be replayed.
+//!
+//! Backups are configured per route, so each of a destination's routes may
+//! fail over to a different backup, or not at all. Only requests whose bodies
+//! can be cloned may fail over.
+
+use crate::retry::TryClone;
+use futures::{try_ready, Future, Poll};
+use http;
+use linkerd2_addr::NameAddr;
+use linkerd2_error::{contract, Error};
+use tracing::debug;
+
+/// Implement on targets to determine if a route has a backup destination.
+pub trait HasBackup {
+    fn backup(&self) -> Option<NameAddr>;
+}
+
+/// Marks a request that must be routed to a backup destination.
+#[derive(Clone, Debug)]
+pub struct Backup(pub(super) NameAddr);
+
+/// Fails requests over to the target's backup destination, if it has one.
+///
+/// `extension` is called to produce an extension that is inserted into each
+/// request as it fails over, e.g. a new dispatch deadline. `exhausted`
+/// determines whether an error indicates that the primary balancer could not
+/// dispatch the request at all; requests that fail with any other error do
+/// not fail over.
+pub fn layer<X, P>(extension: X, exhausted: P) -> Layer<X, P> {
+    Layer {
+        extension,
+        exhausted,
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Layer<X, P> {
+    extension: X,
+    exhausted: P,
+}
+
+#[derive(Clone, Debug)]
+pub struct Stack<M, X, P> {
+    inner: M,
+    extension: X,
+    exhausted: P,
+}
+
+pub struct MakeFuture<F, X, P> {
+    inner: F,
+    backup: Option<NameAddr>,
+    extension: X,
+    exhausted: P,
+}
+
+#[derive(Clone, Debug)]
+pub struct Service<S, X, P> {
+    inner: S,
+    backup: NameAddr,
+    extension: X,
+    exhausted: P,
+}
+
+pub struct ResponseFuture<S, X, P, A>
+where
+    S: tower::Service<http::Request<A>>,
+{
+    inner: S,
+    extension: X,
+    exhausted: P,
+    backup: Option<http::Request<A>>,
+    state: State<S::Future>,
+}
+
+enum State<F> {
+    /// Waiting for the primary destination's response.
+    Primary(F),
+    /// Waiting for the service to become ready to dispatch to the backup.
+    Waiting,
+    /// Waiting for the backup destination's response.
+    Backup(F),
+}
+
+// === impl Layer ===
+
+impl<M, X: Clone, P: Clone> tower::layer::Layer<M> for Layer<X, P> {
+    type Service = Stack<M, X, P>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        Stack {
+            inner,
+            extension: self.extension.clone(),
+            exhausted: self.exhausted.clone(),
+        }
+    }
+}
+
+// === impl Stack ===
+
+impl<T, M, X, P> tower::Service<T> for Stack<M, X, P>
+where
+    T: HasBackup,
+    M: tower::Service<T>,
+    X: Clone,
+    P: Clone,
+{
+    type Response = tower::util::Either<Service<M::Response, X, P>, M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future, X, P>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let backup = target.backup();
+        let inner = self.inner.call(target);
+
+        MakeFuture {
+            inner,
+            backup,
+            extension: self.extension.clone(),
+            exhausted: self.exhausted.clone(),
+        }
+    }
+}
+
+impl<F: Future, X: Clone, P: Clone> Future for MakeFuture<F, X, P> {
+    type Item = tower::util::Either<Service<F::Item, X, P>, F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+
+        let svc = match self.backup.take() {
+            Some(backup) => tower::util::Either::A(Service {
+                inner,
+                backup,
+                extension: self.extension.clone(),
+                exhausted: self.exhausted.clone(),
+            }),
+            None => tower::util::Either::B(inner),
+        };
+        Ok(svc.into())
+    }
+}
+
+// === impl Service ===
+
+impl<S, X, E, P, A> tower::Service<http::Request<A>> for Service<S, X, P>
+where
+    S: tower::Service<http::Request<A>> + Clone,
+    S::Error: Into<Error>,
+    X: Fn() -> E + Clone,
+    E: Send + Sync + 'static,
+    P: Fn(&Error) -> bool + Clone,
+    A: TryClone,
+{
+    type Response = S::Response;
+    type Error = Error;
+    type Future = ResponseFuture<S, X, P, A>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, req: http::Request<A>) -> Self::Future {
+        let backup = req.try_clone().map(|mut backup| {
+            backup.extensions_mut().insert(Backup(self.backup.clone()));
+            backup
+        });
+
+        ResponseFuture {
+            state: State::Primary(self.inner.call(req)),
+            inner: self.inner.clone(),
+            extension: self.extension.clone(),
+            exhausted: self.exhausted.clone(),
+            backup,
+        }
+    }
+}
+
+impl<S, X, E, P, A> Future for ResponseFuture<S, X, P, A>
+where
+    S: tower::Service<http::Request<A>>,
+    S::Error: Into<Error>,
+    X: Fn() -> E,
+    E: Send + Sync + 'static,
+    P: Fn(&Error) -> bool,
+{
+    type Item = S::Response;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            self.state = match self.state {
+                State::Primary(ref mut f) => match f.poll() {
+                    Ok(rsp) => return Ok(rsp),
+                    Err(error) => {
+                        let error = error.into();
+                        if self.backup.is_none() {
+                            debug!("request cannot fail over: {}", error);
+                            return Err(error);
+                        }
+                        if !(self.exhausted)(&error) {
+                            debug!("request may have been dispatched: {}", error);
+                            return Err(error);
+                        }
+                        debug!("failing over: {}", error);
+                        State::Waiting
+                    }
+                },
+                State::Waiting => {
+                    try_ready!(self.inner.poll_ready().map_err(Into::into));
+                    let mut req = contract!(
+                        self.backup.take(),
+                        "failover::ResponseFuture::poll",
+                        "backup must only be taken once"
+                    )?;
+                    req.extensions_mut().insert((self.extension)());
+                    State::Backup(self.inner.call(req))
+                }
+                State::Backup(ref mut f) => return f.poll().map_err(Into::into),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Debug)]
+    struct Body;
+
+    /// The primary balancer had no available endpoints.
+    #[derive(Debug)]
+    struct Unavailable;
+
+    impl std::fmt::Display for Unavailable {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "no endpoints available")
+        }
+    }
+
+    impl std::error::Error for Unavailable {}
+
+    impl TryClone for Body {
+        fn try_clone(&self) -> Option<Self> {
+            Some(Body)
+        }
+    }
+
+    /// Fails all requests to the primary destination unless it is healthy,
+    /// and records the destination of each request.
+    #[derive(Clone)]
+    struct Mock {
+        primary: Primary,
+        dispatched: Arc<Mutex<Vec<Option<NameAddr>>>>,
+    }
+
+    #[derive(Copy, Clone)]
+    enum Primary {
+        Healthy,
+        Unavailable,
+        /// Fails requests after they've been dispatched to an endpoint.
+        TimesOut,
+    }
+
+    impl tower::Service<http::Request<Body>> for Mock {
+        type Response = http::Response<()>;
+        type Error = Error;
+        type Future = future::FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, req: http::Request<Body>) -> Self::Future {
+            let backup = req.extensions().get::<Backup>().map(|b| b.0.clone());
+            self.dispatched.lock().unwrap().push(backup.clone());
+            match (backup, self.primary) {
+                (None, Primary::Unavailable) => future::err(Unavailable.into()),
+                (None, Primary::TimesOut) => future::err("response timed out".into()),
+                _ => future::ok(http::Response::new(())),
+            }
+        }
+    }
+
+    type Dispatched = Arc<Mutex<Vec<Option<NameAddr>>>>;
+
+    fn service(primary: Primary) -> (Service<Mock, fn(), fn(&Error) -> bool>, Dispatched) {
+        let dispatched = Arc::new(Mutex::new(Vec::new()));
+        let inner = Mock {
+            primary,
+            dispatched: dispatched.clone(),
+        };
+        let svc = Service {
+            inner,
+            backup: NameAddr::from_str("web.backup.example.com:8080").unwrap(),
+            extension: (|| ()) as fn(),
+            exhausted: (|e: &Error| e.is::<Unavailable>()) as fn(&Error) -> bool,
+        };
+        (svc, dispatched)
+    }
+
+    #[test]
+    fn total_primary_failure_uses_backup() {
+        let (mut svc, dispatched) = service(Primary::Unavailable);
+        tower::Service::call(&mut svc, http::Request::new(Body))
+            .wait()
+            .expect("request must fail over to the backup");
+
+        let backup = NameAddr::from_str("web.backup.example.com:8080").unwrap();
+        assert_eq!(*dispatched.lock().unwrap(), vec![None, Some(backup)]);
+    }
+
+    #[test]
+    fn healthy_primary_never_uses_backup() {
+        let (mut svc, dispatched) = service(Primary::Healthy);
+        for _ in 0..3 {
+            tower::Service::call(&mut svc, http::Request::new(Body))
+                .wait()
+                .expect("request must succeed");
+        }
+
+        assert_eq!(*dispatched.lock().unwrap(), vec![None, None, None]);
+    }
+
+    #[test]
+    fn dispatched_failures_are_not_replayed() {
+        let (mut svc, dispatched) = service(Primary::TimesOut);
+        tower::Service::call(&mut svc, http::Request::new(Body))
+            .wait()
+            .expect_err("request must fail without failing over");
+
+        assert_eq!(*dispatched.lock().unwrap(), vec![None]);
+    }
+}
@@ -1,4 +1,5 @@
 use super::retry::Budget;
+use super::transform::Transform;
 use futures::Stream;
 use http;
 use indexmap::IndexMap;
@@ -12,6 +13,8 @@ use std::ops::Deref;
 use std::sync::Arc;
 use std::time::Duration;
 
+pub mod failover;
+pub mod negative_cache;
 pub mod recognize;
 /// A stack module that produces a Service that routes requests through alternate
 /// middleware configurations
@@ -25,6 +28,9 @@ pub mod recognize;
 /// The concrete dst router uses the concrete dst as the target for the
 /// underlying stack.
 pub mod router;
+pub mod unmatched;
+
+pub use self::unmatched::Unmatched;
 
 #[derive(Clone, Debug)]
 pub struct WeightedAddr {
@@ -36,6 +42,9 @@ pub struct WeightedAddr {
 pub struct Routes {
     pub routes: Vec<(RequestMatch, Route)>,
     pub dst_overrides: Vec<WeightedAddr>,
+    /// A hash of the profile's content, if it was received from the control
+    /// plane.
+    pub hash: Option<u64>,
 }
 
 /// Watches a destination's Routes.
@@ -73,6 +82,22 @@ pub struct Route {
     response_classes: ResponseClasses,
     retries: Option<Retries>,
     timeout: Option<Duration>,
+    default: Option<DefaultRoute>,
+    backup: Option<NameAddr>,
+    coalesce: bool,
+    preserve_scheme: bool,
+    affinity_cookie: Option<String>,
+    transform: Option<Transform>,
+}
+
+/// Describes why a request was routed to a default route rather than to one
+/// of its profile's routes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DefaultRoute {
+    /// The destination has no profile routes.
+    NoProfile,
+    /// The destination's profile has routes, but none of them matched.
+    Unmatched,
 }
 
 #[derive(Clone, Debug)]
@@ -91,7 +116,10 @@ pub struct ResponseClass {
 }
 
 #[derive(Clone, Default)]
-pub struct ResponseClasses(Arc<Vec<ResponseClass>>);
+pub struct ResponseClasses {
+    classes: Arc<Vec<ResponseClass>>,
+    empty_is_failure: bool,
+}
 
 #[derive(Clone, Debug)]
 pub enum ResponseMatch {
@@ -127,9 +155,27 @@ impl Route {
 
         Self {
             labels,
-            response_classes: ResponseClasses(response_classes.into()),
+            response_classes: ResponseClasses {
+                classes: response_classes.into(),
+                empty_is_failure: false,
+            },
             retries: None,
             timeout: None,
+            default: None,
+            backup: None,
+            coalesce: false,
+            preserve_scheme: false,
+            affinity_cookie: None,
+            transform: None,
+        }
+    }
+
+    /// Creates a route that handles requests that are not matched by a
+    /// profile route.
+    pub fn new_default(default: DefaultRoute) -> Self {
+        Self {
+            default: Some(default),
+            ..Self::default()
         }
     }
 
@@ -149,6 +195,42 @@ impl Route {
         self.timeout
     }
 
+    /// Returns the reason this route was used as a default, if it is not a
+    /// profile route.
+    pub fn default_route(&self) -> Option<DefaultRoute> {
+        self.default
+    }
+
+    /// Returns the destination that requests fail over to when this route's
+    /// primary destination fails.
+    pub fn backup(&self) -> Option<&NameAddr> {
+        self.backup.as_ref()
+    }
+
+    /// Returns true if concurrent identical requests on this route may be
+    /// coalesced into a single request.
+    pub fn coalesce(&self) -> bool {
+        self.coalesce
+    }
+
+    /// Returns true if requests on this route keep their original scheme
+    /// when their URIs are normalized.
+    pub fn preserve_scheme(&self) -> bool {
+        self.preserve_scheme
+    }
+
+    /// Returns the name of the cookie that pins requests on this route to the
+    /// endpoint it names, if the route honors one.
+    pub fn affinity_cookie(&self) -> Option<&str> {
+        self.affinity_cookie.as_ref().map(String::as_str)
+    }
+
+    /// Returns the transformation applied to requests and responses on this
+    /// route.
+    pub fn transform(&self) -> Option<&Transform> {
+        self.transform.as_ref()
+    }
+
     pub fn set_retries(&mut self, budget: Arc<Budget>) {
         self.retries = Some(Retries { budget });
     }
@@ -156,6 +238,32 @@ impl Route {
     pub fn set_timeout(&mut self, timeout: Duration) {
         self.timeout = Some(timeout);
     }
+
+    pub fn set_backup(&mut self, backup: NameAddr) {
+        self.backup = Some(backup);
+    }
+
+    pub fn set_coalesce(&mut self) {
+        self.coalesce = true;
+    }
+
+    pub fn set_preserve_scheme(&mut self) {
+        self.preserve_scheme = true;
+    }
+
+    pub fn set_affinity_cookie(&mut self, name: String) {
+        self.affinity_cookie = Some(name);
+    }
+
+    pub fn set_transform(&mut self, transform: Transform) {
+        self.transform = Some(transform);
+    }
+
+    /// Classifies successful responses on this route as failures if they
+    /// have an empty body.
+    pub fn set_empty_is_failure(&mut self) {
+        self.response_classes.empty_is_failure = true;
+    }
 }
 
 // === impl RequestMatch ===
@@ -190,17 +298,26 @@ impl ResponseClass {
 
 // === impl ResponseClasses ===
 
+impl ResponseClasses {
+    /// Returns true if successful responses with an empty body should be
+    /// classified as failures.
+    pub fn empty_is_failure(&self) -> bool {
+        self.empty_is_failure
+    }
+}
+
 impl Deref for ResponseClasses {
     type Target = [ResponseClass];
 
     fn deref(&self) -> &Self::Target {
-        &*self.0
+        &*self.classes
     }
 }
 
 impl PartialEq for ResponseClasses {
     fn eq(&self, other: &Self) -> bool {
-        Arc::ptr_eq(&self.0, &other.0)
+        Arc::ptr_eq(&self.classes, &other.classes)
+            && self.empty_is_failure == other.empty_is_failure
     }
 }
 
@@ -208,13 +325,17 @@ impl Eq for ResponseClasses {}
 
 impl Hash for ResponseClasses {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        state.write_usize(Arc::as_ref(&self.0) as *const _ as usize);
+        state.write_usize(Arc::as_ref(&self.classes) as *const _ as usize);
+        self.empty_is_failure.hash(state);
     }
 }
 
 impl fmt::Debug for ResponseClasses {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        self.0.fmt(f)
+        f.debug_struct("ResponseClasses")
+            .field("classes", &self.classes)
+            .field("empty_is_failure", &self.empty_is_failure)
+            .finish()
     }
 }
 
@@ -0,0 +1,250 @@
+//! Remembers destinations that have no profile.
+//!
+//! When the control plane answers a lookup with an empty profile, the
+//! destination is recorded for a TTL. If the destination's routes are watched
+//! again before the TTL elapses (e.g. because its router was rebuilt), the
+//! default routes are used without a lookup until the entry expires, at which
+//! point the destination is looked up again.
+
+use super::Routes;
+use futures::{Async, Future, Poll, Stream};
+use linkerd2_addr::NameAddr;
+use linkerd2_error::Never;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_timer::{clock, Delay};
+use tracing::{debug, trace};
+
+/// The destinations known to have no profile, along with the time at which
+/// each should be looked up again.
+#[derive(Clone, Debug, Default)]
+pub struct NegativeCache(Arc<Mutex<HashMap<NameAddr, Instant>>>);
+
+/// Wraps a `GetRoutes` so that destinations with no profile are not looked up
+/// again until their negative cache entries expire.
+#[derive(Clone, Debug)]
+pub struct GetRoutes<G> {
+    inner: G,
+    cache: NegativeCache,
+    ttl: Option<Duration>,
+}
+
+pub struct RouteStream<G: super::GetRoutes> {
+    dst: NameAddr,
+    inner: G,
+    cache: NegativeCache,
+    ttl: Option<Duration>,
+    state: State<G::Stream>,
+}
+
+enum State<S> {
+    /// Waiting for the destination's negative cache entry to expire. Until
+    /// then, the router uses its default routes.
+    Deferred(Delay),
+    Watching(S),
+    /// The destination's routes are no longer discoverable.
+    Unwatched,
+}
+
+// === impl NegativeCache ===
+
+impl NegativeCache {
+    /// Returns each destination that has no profile, along with the time at
+    /// which it should be looked up again.
+    pub fn snapshot(&self) -> Vec<(NameAddr, Instant)> {
+        match self.0.lock() {
+            Ok(cache) => cache
+                .iter()
+                .map(|(dst, until)| (dst.clone(), *until))
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Adds entries that were recorded elsewhere (i.e. by a prior process).
+    pub fn restore(&self, entries: impl IntoIterator<Item = (NameAddr, Instant)>) {
+        if let Ok(mut cache) = self.0.lock() {
+            for (dst, until) in entries {
+                cache.entry(dst).or_insert(until);
+            }
+        }
+    }
+
+    /// Returns the time until which `dst` is known to have no profile.
+    fn get(&self, dst: &NameAddr) -> Option<Instant> {
+        let cache = self.0.lock().ok()?;
+        let until = *cache.get(dst)?;
+        if until > clock::now() {
+            Some(until)
+        } else {
+            None
+        }
+    }
+
+    fn insert(&self, dst: &NameAddr, until: Instant) {
+        if let Ok(mut cache) = self.0.lock() {
+            cache.insert(dst.clone(), until);
+        }
+    }
+
+    fn remove(&self, dst: &NameAddr) {
+        if let Ok(mut cache) = self.0.lock() {
+            cache.remove(dst);
+        }
+    }
+}
+
+// === impl GetRoutes ===
+
+impl<G> GetRoutes<G> {
+    /// Records destinations that have no profile in `cache` for `ttl`. If no
+    /// TTL is configured, lookups are never skipped.
+    pub fn new(inner: G, cache: NegativeCache, ttl: Option<Duration>) -> Self {
+        Self { inner, cache, ttl }
+    }
+}
+
+impl<G> super::GetRoutes for GetRoutes<G>
+where
+    G: super::GetRoutes + Clone,
+{
+    type Stream = RouteStream<G>;
+
+    fn get_routes(&self, dst: &NameAddr) -> Option<Self::Stream> {
+        let state = match self.ttl.and_then(|_| self.cache.get(dst)) {
+            Some(until) => {
+                debug!("destination has no profile; deferring lookup");
+                State::Deferred(Delay::new(until))
+            }
+            None => State::Watching(self.inner.get_routes(dst)?),
+        };
+
+        Some(RouteStream {
+            dst: dst.clone(),
+            inner: self.inner.clone(),
+            cache: self.cache.clone(),
+            ttl: self.ttl,
+            state,
+        })
+    }
+}
+
+// === impl RouteStream ===
+
+impl<G: super::GetRoutes> Stream for RouteStream<G> {
+    type Item = Routes;
+    type Error = Never;
+
+    fn poll(&mut self) -> Poll<Option<Routes>, Never> {
+        loop {
+            self.state = match self.state {
+                State::Deferred(ref mut delay) => {
+                    match delay.poll().expect("timer must not fail") {
+                        Async::NotReady => return Ok(Async::NotReady),
+                        Async::Ready(()) => {}
+                    }
+                    trace!("negative cache entry expired");
+                    match self.inner.get_routes(&self.dst) {
+                        Some(stream) => State::Watching(stream),
+                        None => State::Unwatched,
+                    }
+                }
+                State::Watching(ref mut stream) => {
+                    let routes = match stream.poll()? {
+                        Async::Ready(Some(routes)) => routes,
+                        poll => return Ok(poll),
+                    };
+                    // Only profiles that were received from the control plane
+                    // have a hash.
+                    if let (Some(ttl), Some(_)) = (self.ttl, routes.hash) {
+                        if routes.routes.is_empty() && routes.dst_overrides.is_empty() {
+                            self.cache.insert(&self.dst, clock::now() + ttl);
+                        } else {
+                            self.cache.remove(&self.dst);
+                        }
+                    }
+                    return Ok(Async::Ready(Some(routes)));
+                }
+                State::Unwatched => return Ok(Async::NotReady),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{future, stream};
+    use tokio::runtime::current_thread::Runtime;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    /// Answers every lookup with an empty profile, counting lookups.
+    #[derive(Clone, Default)]
+    struct CountGetRoutes(Arc<Mutex<usize>>);
+
+    impl super::super::GetRoutes for CountGetRoutes {
+        type Stream = stream::IterOk<std::vec::IntoIter<Routes>, Never>;
+
+        fn get_routes(&self, _: &NameAddr) -> Option<Self::Stream> {
+            *self.0.lock().unwrap() += 1;
+            let empty = Routes {
+                hash: Some(1),
+                ..Routes::default()
+            };
+            Some(stream::iter_ok(vec![empty]))
+        }
+    }
+
+    fn lookups(get: &CountGetRoutes) -> usize {
+        *get.0.lock().unwrap()
+    }
+
+    #[test]
+    fn restored_entries_skip_initial_lookups() {
+        use super::super::GetRoutes as _;
+
+        let mut rt = Runtime::new().unwrap();
+        let dst = NameAddr::from_str("web.ns.svc.cluster.local:8080").unwrap();
+
+        let inner = CountGetRoutes::default();
+        let cache = NegativeCache::default();
+        let get = GetRoutes::new(inner.clone(), cache.clone(), Some(TTL));
+        let mut rx = get.get_routes(&dst).expect("routes must be watched");
+        rt.block_on(future::poll_fn(|| rx.poll())).unwrap();
+        assert_eq!(lookups(&inner), 1);
+        let snapshot = cache.snapshot();
+        assert_eq!(snapshot.len(), 1, "an empty profile must be cached");
+
+        // A new process restores the cache.
+        let inner = CountGetRoutes::default();
+        let cache = NegativeCache::default();
+        cache.restore(snapshot);
+        let get = GetRoutes::new(inner.clone(), cache, Some(TTL));
+        let mut rx = get.get_routes(&dst).expect("routes must be watched");
+        assert!(rt
+            .block_on(future::lazy(|| rx.poll()))
+            .unwrap()
+            .is_not_ready());
+        assert_eq!(
+            lookups(&inner),
+            0,
+            "a cached destination must not be looked up until its entry expires"
+        );
+    }
+
+    #[test]
+    fn lookups_are_not_skipped_without_a_ttl() {
+        use super::super::GetRoutes as _;
+
+        let dst = NameAddr::from_str("web.ns.svc.cluster.local:8080").unwrap();
+        let inner = CountGetRoutes::default();
+        let cache = NegativeCache::default();
+        cache.restore(vec![(dst.clone(), clock::now() + TTL)]);
+
+        let get = GetRoutes::new(inner.clone(), cache, None);
+        let _rx = get.get_routes(&dst).expect("routes must be watched");
+        assert_eq!(lookups(&inner), 1);
+    }
+}
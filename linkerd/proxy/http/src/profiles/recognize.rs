@@ -1,8 +1,12 @@
-use super::{RequestMatch, Route, WeightedAddr, WithAddr, WithRoute};
+use super::failover::Backup;
+use super::{RequestMatch, Route, Unmatched, WeightedAddr, WithAddr, WithRoute};
 use http;
+use linkerd2_addr::NameAddr;
 use linkerd2_router as rt;
 use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::SmallRng;
 use std::hash::Hash;
+use std::sync::{Arc, Mutex};
 use tracing::trace;
 
 #[derive(Clone)]
@@ -10,8 +14,17 @@ pub struct RouteRecognize<T> {
     target: T,
     routes: Vec<(RequestMatch, Route)>,
     default_route: Route,
+    unmatched: Option<(NameAddr, Unmatched)>,
 }
 
+/// Records the backend that a traffic split chose for a request.
+///
+/// Layers above the concrete router may insert this into a request's
+/// extensions to learn which of the split's backends the request was
+/// dispatched to. It is left unset when no split is active.
+#[derive(Clone, Debug, Default)]
+pub struct SplitBackend(Arc<Mutex<Option<NameAddr>>>);
+
 #[derive(Clone)]
 pub struct ConcreteDstRecognize<T> {
     target: T,
@@ -19,6 +32,7 @@ pub struct ConcreteDstRecognize<T> {
     // A weighted index of the `dst_overrides` weights.  This must only be
     // None if `dst_overrides` is empty.
     distribution: Option<WeightedIndex<u32>>,
+    rng: Arc<Mutex<SmallRng>>,
 }
 
 impl<T> RouteRecognize<T> {
@@ -27,6 +41,15 @@ impl<T> RouteRecognize<T> {
             target,
             routes,
             default_route,
+            unmatched: None,
+        }
+    }
+
+    /// Records requests to `dst` that match none of `routes` in `unmatched`.
+    pub fn with_unmatched(self, dst: NameAddr, unmatched: Unmatched) -> Self {
+        Self {
+            unmatched: Some((dst, unmatched)),
+            ..self
         }
     }
 }
@@ -47,17 +70,25 @@ where
         }
 
         trace!("using default route");
+        if !self.routes.is_empty() {
+            if let Some((ref dst, ref unmatched)) = self.unmatched {
+                unmatched.record(dst, req);
+            }
+        }
         Some(self.target.clone().with_route(self.default_route.clone()))
     }
 }
 
 impl<T> ConcreteDstRecognize<T> {
-    pub fn new(target: T, dst_overrides: Vec<WeightedAddr>) -> Self {
+    /// Splits requests over `dst_overrides` by weight, choosing a
+    /// destination for each request with `rng`.
+    pub fn new(target: T, dst_overrides: Vec<WeightedAddr>, rng: SmallRng) -> Self {
         let distribution = Self::make_dist(&dst_overrides);
         ConcreteDstRecognize {
             target,
             dst_overrides,
             distribution,
+            rng: Arc::new(Mutex::new(rng)),
         }
     }
 
@@ -78,15 +109,178 @@ where
 {
     type Target = T;
 
-    fn recognize(&self, _req: &http::Request<Body>) -> Option<Self::Target> {
+    fn recognize(&self, req: &http::Request<Body>) -> Option<Self::Target> {
+        if let Some(Backup(ref backup)) = req.extensions().get::<Backup>() {
+            trace!(%backup, "failing over");
+            return Some(self.target.clone().with_addr(backup.clone()));
+        }
+
         match self.distribution {
             Some(ref distribution) => {
-                let mut rng = rand::thread_rng();
-                let idx = distribution.sample(&mut rng);
+                let mut rng = self.rng.lock().expect("split rng lock");
+                let idx = distribution.sample(&mut *rng);
                 let addr = self.dst_overrides[idx].addr.clone();
+                if let Some(backend) = req.extensions().get::<SplitBackend>() {
+                    backend.set(addr.clone());
+                }
                 Some(self.target.clone().with_addr(addr))
             }
             None => Some(self.target.clone()),
         }
     }
 }
+
+// === impl SplitBackend ===
+
+impl SplitBackend {
+    pub fn get(&self) -> Option<NameAddr> {
+        self.0.lock().ok()?.clone()
+    }
+
+    fn set(&self, addr: NameAddr) {
+        if let Ok(mut backend) = self.0.lock() {
+            *backend = Some(addr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profiles::DefaultRoute;
+    use linkerd2_metrics::FmtMetrics;
+    use linkerd2_router::Recognize;
+    use rand::SeedableRng;
+    use regex::Regex;
+
+    #[derive(Clone)]
+    struct Target;
+
+    impl WithRoute for Target {
+        type Output = Route;
+
+        fn with_route(self, route: Route) -> Route {
+            route
+        }
+    }
+
+    fn req(path: &str) -> http::Request<()> {
+        http::Request::builder().uri(path).body(()).unwrap()
+    }
+
+    #[test]
+    fn unmatched_requests_use_default_route_and_are_counted() {
+        let books = Route::new(
+            vec![("route".to_string(), "books".to_string())].into_iter(),
+            vec![],
+        );
+        let routes = vec![(RequestMatch::Path(Regex::new("^/books$").unwrap()), books)];
+        let dst = NameAddr::from_str("web.example.com:8080").unwrap();
+        let unmatched = Unmatched::default();
+        let recognize =
+            RouteRecognize::new(Target, routes, Route::new_default(DefaultRoute::Unmatched))
+                .with_unmatched(dst, unmatched.clone());
+
+        let matched = recognize.recognize(&req("/books")).unwrap();
+        assert_eq!(matched.default_route(), None);
+        assert_eq!(
+            matched.labels().get("route").map(String::as_str),
+            Some("books")
+        );
+        assert!(unmatched.as_display().to_string().is_empty());
+
+        let default = recognize.recognize(&req("/authors")).unwrap();
+        assert_eq!(default.default_route(), Some(DefaultRoute::Unmatched));
+        recognize.recognize(&req("/authors/1")).unwrap();
+        assert!(unmatched
+            .as_display()
+            .to_string()
+            .contains("route_unmatched_total{dst=\"web.example.com:8080\"} 2"));
+    }
+
+    #[test]
+    fn requests_without_profile_routes_are_not_counted() {
+        let dst = NameAddr::from_str("web.example.com:8080").unwrap();
+        let unmatched = Unmatched::default();
+        let recognize =
+            RouteRecognize::new(Target, vec![], Route::new_default(DefaultRoute::NoProfile))
+                .with_unmatched(dst, unmatched.clone());
+
+        let default = recognize.recognize(&req("/authors")).unwrap();
+        assert_eq!(default.default_route(), Some(DefaultRoute::NoProfile));
+        assert!(unmatched.as_display().to_string().is_empty());
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+    struct Concrete(NameAddr);
+
+    impl WithAddr for Concrete {
+        fn with_addr(self, addr: NameAddr) -> Self {
+            Concrete(addr)
+        }
+    }
+
+    #[test]
+    fn seeded_splits_are_reproducible() {
+        let logical = NameAddr::from_str("web.example.com:8080").unwrap();
+        let primary = NameAddr::from_str("web-v1.example.com:8080").unwrap();
+        let canary = NameAddr::from_str("web-v2.example.com:8080").unwrap();
+        let split = |seed: u64| {
+            let dsts = vec![
+                WeightedAddr {
+                    addr: primary.clone(),
+                    weight: 70,
+                },
+                WeightedAddr {
+                    addr: canary.clone(),
+                    weight: 30,
+                },
+            ];
+            let recognize = ConcreteDstRecognize::new(
+                Concrete(logical.clone()),
+                dsts,
+                SmallRng::seed_from_u64(seed),
+            );
+            (0..100)
+                .map(|_| recognize.recognize(&req("/")).unwrap().0)
+                .collect::<Vec<_>>()
+        };
+
+        let first = split(7);
+        assert!(first.contains(&primary));
+        assert!(first.contains(&canary));
+        assert_eq!(first, split(7), "a seeded split must be reproducible");
+    }
+
+    #[test]
+    fn split_backends_are_recorded() {
+        let logical = NameAddr::from_str("web.example.com:8080").unwrap();
+        let canary = NameAddr::from_str("web-v2.example.com:8080").unwrap();
+        let recognize = |dsts| {
+            ConcreteDstRecognize::new(Concrete(logical.clone()), dsts, SmallRng::seed_from_u64(7))
+        };
+        let recognize_backend = |recognize: &ConcreteDstRecognize<Concrete>| {
+            let backend = SplitBackend::default();
+            let mut req = req("/");
+            req.extensions_mut().insert(backend.clone());
+            let Concrete(dst) = recognize.recognize(&req).unwrap();
+            (dst, backend.get())
+        };
+
+        let split = recognize(vec![WeightedAddr {
+            addr: canary.clone(),
+            weight: 100,
+        }]);
+        assert_eq!(
+            recognize_backend(&split),
+            (canary.clone(), Some(canary.clone()))
+        );
+
+        let unsplit = recognize(vec![]);
+        assert_eq!(
+            recognize_backend(&unsplit),
+            (logical.clone(), None),
+            "backends must only be recorded when a split is active"
+        );
+    }
+}
@@ -1,12 +1,19 @@
 use super::recognize::{ConcreteDstRecognize, RouteRecognize};
-use super::{CanGetDestination, GetRoutes, Route, Routes, WeightedAddr, WithAddr, WithRoute};
+use super::{
+    CanGetDestination, DefaultRoute, GetRoutes, Route, Routes, Unmatched, WeightedAddr, WithAddr,
+    WithRoute,
+};
+use crate::transform::Transform;
 use futures::{Async, Poll, Stream};
 use http;
-use indexmap::IndexMap;
+use indexmap::{IndexMap, IndexSet};
+use linkerd2_addr::NameAddr;
 use linkerd2_error::{Error, Never};
 use linkerd2_router as rt;
 use linkerd2_stack::Shared;
+use rand::{rngs::SmallRng, SeedableRng};
 use std::hash::Hash;
+use std::sync::Arc;
 use tracing::{debug, error};
 
 // A router which routes based on the `dst_overrides` of the profile or, if
@@ -29,7 +36,16 @@ where
     Layer {
         get_routes,
         route_layer,
-        default_route: Route::default(),
+        default_route: Route::new_default(DefaultRoute::Unmatched),
+        no_profile_route: Route::new_default(DefaultRoute::NoProfile),
+        unmatched: None,
+        backups: None,
+        empty_failures: None,
+        coalesced: None,
+        preserved_schemes: None,
+        affinity_cookies: None,
+        transforms: None,
+        rng: SmallRng::from_entropy(),
         _p: ::std::marker::PhantomData,
     }
 }
@@ -38,9 +54,18 @@ where
 pub struct Layer<G, Inner, RouteLayer, RouteBody, InnerBody> {
     get_routes: G,
     route_layer: RouteLayer,
-    /// This is saved into a field so that the same `Arc`s are used and
-    /// cloned, instead of calling `Route::default()` every time.
+    /// These are saved into fields so that the same `Arc`s are used and
+    /// cloned, instead of building new default routes every time.
     default_route: Route,
+    no_profile_route: Route,
+    unmatched: Option<Unmatched>,
+    backups: Option<Arc<IndexMap<NameAddr, IndexMap<String, NameAddr>>>>,
+    empty_failures: Option<Arc<IndexMap<NameAddr, IndexSet<String>>>>,
+    coalesced: Option<Arc<IndexMap<NameAddr, IndexSet<String>>>>,
+    preserved_schemes: Option<Arc<IndexMap<NameAddr, IndexSet<String>>>>,
+    affinity_cookies: Option<Arc<IndexMap<NameAddr, IndexMap<String, String>>>>,
+    transforms: Option<Arc<IndexMap<NameAddr, IndexMap<String, Transform>>>>,
+    rng: SmallRng,
     _p: ::std::marker::PhantomData<fn() -> (Inner, RouteBody, InnerBody)>,
 }
 
@@ -50,6 +75,15 @@ pub struct MakeSvc<G, Inner, RouteLayer, RouteBody, InnerBody> {
     get_routes: G,
     route_layer: RouteLayer,
     default_route: Route,
+    no_profile_route: Route,
+    unmatched: Option<Unmatched>,
+    backups: Option<Arc<IndexMap<NameAddr, IndexMap<String, NameAddr>>>>,
+    empty_failures: Option<Arc<IndexMap<NameAddr, IndexSet<String>>>>,
+    coalesced: Option<Arc<IndexMap<NameAddr, IndexSet<String>>>>,
+    preserved_schemes: Option<Arc<IndexMap<NameAddr, IndexSet<String>>>>,
+    affinity_cookies: Option<Arc<IndexMap<NameAddr, IndexMap<String, String>>>>,
+    transforms: Option<Arc<IndexMap<NameAddr, IndexMap<String, Transform>>>>,
+    rng: SmallRng,
     _p: ::std::marker::PhantomData<fn(RouteBody, InnerBody)>,
 }
 
@@ -89,6 +123,115 @@ where
     concrete_router: Option<ConcreteRouter<Target, Inner::Value, InnerBody>>,
     router: RouteRouter<Target, Target::Output, RouteMake::Value, RouteBody>,
     default_route: Route,
+    no_profile_route: Route,
+    dst: Option<NameAddr>,
+    unmatched: Option<Unmatched>,
+    /// The backup destinations of this destination's routes, by route name.
+    backups: Option<IndexMap<String, NameAddr>>,
+    /// The names of this destination's routes on which empty responses are
+    /// failures.
+    empty_failures: Option<IndexSet<String>>,
+    /// The names of this destination's routes on which requests may be
+    /// coalesced.
+    coalesced: Option<IndexSet<String>>,
+    /// The names of this destination's routes on which requests keep their
+    /// original scheme.
+    preserved_schemes: Option<IndexSet<String>>,
+    /// The names of the affinity cookies honored on this destination's
+    /// routes, by route name.
+    affinity_cookies: Option<IndexMap<String, String>>,
+    /// The transformations applied on this destination's routes, by route
+    /// name.
+    transforms: Option<IndexMap<String, Transform>>,
+    /// Seeds the RNG of each concrete router that splits this destination's
+    /// traffic.
+    rng: SmallRng,
+}
+
+impl<G, Inner, RouteLayer, RouteBody, InnerBody> Layer<G, Inner, RouteLayer, RouteBody, InnerBody> {
+    /// Records requests that match none of their profile's routes in
+    /// `unmatched`.
+    pub fn with_unmatched(self, unmatched: Unmatched) -> Self {
+        Self {
+            unmatched: Some(unmatched),
+            ..self
+        }
+    }
+
+    /// Sets a backup destination on the named routes of each destination in
+    /// `backups`, so that requests on those routes may fail over to it.
+    pub fn with_backups(
+        self,
+        backups: Arc<IndexMap<NameAddr, IndexMap<String, NameAddr>>>,
+    ) -> Self {
+        Self {
+            backups: Some(backups),
+            ..self
+        }
+    }
+
+    /// Classifies empty responses as failures on the named routes of each
+    /// destination in `empty_failures`.
+    pub fn with_empty_failures(
+        self,
+        empty_failures: Arc<IndexMap<NameAddr, IndexSet<String>>>,
+    ) -> Self {
+        Self {
+            empty_failures: Some(empty_failures),
+            ..self
+        }
+    }
+
+    /// Allows concurrent identical requests to be coalesced on the named
+    /// routes of each destination in `coalesced`.
+    pub fn with_coalesced(self, coalesced: Arc<IndexMap<NameAddr, IndexSet<String>>>) -> Self {
+        Self {
+            coalesced: Some(coalesced),
+            ..self
+        }
+    }
+
+    /// Preserves the original scheme of requests on the named routes of each
+    /// destination in `preserved_schemes`.
+    pub fn with_preserved_schemes(
+        self,
+        preserved_schemes: Arc<IndexMap<NameAddr, IndexSet<String>>>,
+    ) -> Self {
+        Self {
+            preserved_schemes: Some(preserved_schemes),
+            ..self
+        }
+    }
+
+    /// Pins requests on the named routes of each destination in
+    /// `affinity_cookies` to the endpoint named by the route's cookie.
+    pub fn with_affinity_cookies(
+        self,
+        affinity_cookies: Arc<IndexMap<NameAddr, IndexMap<String, String>>>,
+    ) -> Self {
+        Self {
+            affinity_cookies: Some(affinity_cookies),
+            ..self
+        }
+    }
+
+    /// Transforms requests and responses on the named routes of each
+    /// destination in `transforms`.
+    pub fn with_transforms(
+        self,
+        transforms: Arc<IndexMap<NameAddr, IndexMap<String, Transform>>>,
+    ) -> Self {
+        Self {
+            transforms: Some(transforms),
+            ..self
+        }
+    }
+
+    /// Seeds the RNGs that split each destination's traffic over its
+    /// `dst_overrides` from `rng`, rather than from entropy.
+    pub fn with_rng(self, rng: SmallRng) -> Self {
+        Self { rng, ..self }
+    }
 }
 
 impl<G, Inner, RouteLayer, RouteBody, InnerBody> tower::layer::Layer<Inner>
@@ -105,6 +248,15 @@ where
             get_routes: self.get_routes.clone(),
             route_layer: self.route_layer.clone(),
             default_route: self.default_route.clone(),
+            no_profile_route: self.no_profile_route.clone(),
+            unmatched: self.unmatched.clone(),
+            backups: self.backups.clone(),
+            empty_failures: self.empty_failures.clone(),
+            coalesced: self.coalesced.clone(),
+            preserved_schemes: self.preserved_schemes.clone(),
+            affinity_cookies: self.affinity_cookies.clone(),
+            transforms: self.transforms.clone(),
+            rng: self.rng.clone(),
             _p: ::std::marker::PhantomData,
         }
     }
@@ -121,6 +273,15 @@ where
             get_routes: self.get_routes.clone(),
             route_layer: self.route_layer.clone(),
             default_route: self.default_route.clone(),
+            no_profile_route: self.no_profile_route.clone(),
+            unmatched: self.unmatched.clone(),
+            backups: self.backups.clone(),
+            empty_failures: self.empty_failures.clone(),
+            coalesced: self.coalesced.clone(),
+            preserved_schemes: self.preserved_schemes.clone(),
+            affinity_cookies: self.affinity_cookies.clone(),
+            transforms: self.transforms.clone(),
+            rng: self.rng.clone(),
             _p: ::std::marker::PhantomData,
         }
     }
@@ -151,34 +312,58 @@ where
     }
 
     fn call(&mut self, target: Target) -> Self::Future {
+        let dst = target.get_destination().cloned();
+        let backups = dst
+            .as_ref()
+            .and_then(|dst| self.backups.as_ref()?.get(dst).cloned());
+        let empty_failures = dst
+            .as_ref()
+            .and_then(|dst| self.empty_failures.as_ref()?.get(dst).cloned());
+        let coalesced = dst
+            .as_ref()
+            .and_then(|dst| self.coalesced.as_ref()?.get(dst).cloned());
+        let preserved_schemes = dst
+            .as_ref()
+            .and_then(|dst| self.preserved_schemes.as_ref()?.get(dst).cloned());
+        let affinity_cookies = dst
+            .as_ref()
+            .and_then(|dst| self.affinity_cookies.as_ref()?.get(dst).cloned());
+        let transforms = dst
+            .as_ref()
+            .and_then(|dst| self.transforms.as_ref()?.get(dst).cloned());
+        let default_route = self.default_route.clone();
+        let no_profile_route = self.no_profile_route.clone();
+        let mut rng = fork(&mut self.rng);
+
         let concrete_router = {
-            // Initially there are no dst_overrides, so build a concrete router
-            // with only the default target.
+            // Initially there are no dst_overrides or routes (and so no
+            // backups), so build a concrete router with only the default
+            // target.
             let mut make = IndexMap::with_capacity(1);
             make.insert(target.clone(), self.inner.make(&target));
 
-            let rec = ConcreteDstRecognize::new(target.clone(), Vec::new());
+            let rec = ConcreteDstRecognize::new(target.clone(), Vec::new(), fork(&mut rng));
             rt::Router::new_fixed(rec, make)
         };
 
         let concrete_stack = self.route_layer.layer(Shared::new(concrete_router.clone()));
 
         // Initially there are no routes, so build a route router with only
-        // the default route.
+        // the no-profile default route.
         let router = {
-            let default_route = target.clone().with_route(self.default_route.clone());
+            let default_route = target.clone().with_route(no_profile_route.clone());
             let stack = rt::Make::make(&concrete_stack, &default_route);
 
             let mut make = IndexMap::with_capacity(1);
             make.insert(default_route.clone(), stack);
 
-            let recognize = RouteRecognize::new(target.clone(), vec![], self.default_route.clone());
+            let recognize = RouteRecognize::new(target.clone(), vec![], no_profile_route.clone());
             rt::Router::new_fixed(recognize, make)
         };
 
         // Initiate a stream to get route and dst_override updates for this
         // destination.
-        let route_stream = match target.get_destination() {
+        let route_stream = match dst {
             Some(ref dst) => self.get_routes.get_routes(&dst),
             None => {
                 debug!("no destination for routes");
@@ -193,7 +378,17 @@ where
             route_stream,
             router,
             concrete_router: Some(concrete_router),
-            default_route: self.default_route.clone(),
+            default_route,
+            no_profile_route,
+            dst,
+            unmatched: self.unmatched.clone(),
+            backups,
+            empty_failures,
+            coalesced,
+            preserved_schemes,
+            affinity_cookies,
+            transforms,
+            rng,
         })
     }
 }
@@ -211,6 +406,15 @@ where
             get_routes: self.get_routes.clone(),
             route_layer: self.route_layer.clone(),
             default_route: self.default_route.clone(),
+            no_profile_route: self.no_profile_route.clone(),
+            unmatched: self.unmatched.clone(),
+            backups: self.backups.clone(),
+            empty_failures: self.empty_failures.clone(),
+            coalesced: self.coalesced.clone(),
+            preserved_schemes: self.preserved_schemes.clone(),
+            affinity_cookies: self.affinity_cookies.clone(),
+            transforms: self.transforms.clone(),
+            rng: self.rng.clone(),
             _p: ::std::marker::PhantomData,
         }
     }
@@ -232,6 +436,12 @@ where
     Inner::Value: tower::Service<http::Request<InnerBody>> + Clone,
 {
     fn update_routes(&mut self, routes: Routes) {
+        debug!(
+            dst = ?self.dst,
+            profile.hash = ?routes.hash,
+            "updating routes"
+        );
+
         // We must build a new concrete router with a service for each
         // dst_override.  These services are created eagerly.  If a service
         // was present in the previous concrete router, we reuse that
@@ -259,8 +469,24 @@ where
             make.insert(target, service);
         }
 
+        // Requests that fail over are routed to their route's backup
+        // destination.
+        for backup in self.backups.iter().flat_map(|b| b.values()) {
+            let target = self.target.clone().with_addr(backup.clone());
+            if !make.contains_key(&target) {
+                let service = old_make
+                    .remove(&target)
+                    .unwrap_or_else(|| self.inner.make(&target));
+                make.insert(target, service);
+            }
+        }
+
         let concrete_router = rt::Router::new_fixed(
-            ConcreteDstRecognize::new(self.target.clone(), routes.dst_overrides),
+            ConcreteDstRecognize::new(
+                self.target.clone(),
+                routes.dst_overrides,
+                fork(&mut self.rng),
+            ),
             make,
         );
 
@@ -271,29 +497,47 @@ where
 
         let stack = self.route_layer.layer(Shared::new(concrete_router));
 
-        let default_route = self.target.clone().with_route(self.default_route.clone());
+        // Requests to destinations whose profiles have no routes are
+        // distinguished from requests that match none of a profile's routes.
+        let default = if routes.routes.is_empty() {
+            self.no_profile_route.clone()
+        } else {
+            self.default_route.clone()
+        };
+        let default_route = self.target.clone().with_route(default.clone());
 
         // Create a new fixed router router; we can eagerly make the
         // services and never expire the routes from the profile router
         // cache.
-        let capacity = routes.routes.len() + 1;
+        let routes = routes
+            .routes
+            .into_iter()
+            .map(|(condition, route)| {
+                let route = with_backup(route, self.backups.as_ref());
+                let route = with_empty_failure(route, self.empty_failures.as_ref());
+                let route = with_coalesce(route, self.coalesced.as_ref());
+                let route = with_preserve_scheme(route, self.preserved_schemes.as_ref());
+                let route = with_affinity_cookie(route, self.affinity_cookies.as_ref());
+                let route = with_transform(route, self.transforms.as_ref());
+                (condition, route)
+            })
+            .collect::<Vec<_>>();
+
+        let capacity = routes.len() + 1;
         let mut make = IndexMap::with_capacity(capacity);
         make.insert(default_route.clone(), stack.make(&default_route));
 
-        for (_, route) in &routes.routes {
+        for (_, route) in &routes {
             let route = self.target.clone().with_route(route.clone());
             let service = stack.make(&route);
             make.insert(route, service);
         }
 
-        let router = rt::Router::new_fixed(
-            RouteRecognize::new(
-                self.target.clone(),
-                routes.routes,
-                self.default_route.clone(),
-            ),
-            make,
-        );
+        let mut recognize = RouteRecognize::new(self.target.clone(), routes, default);
+        if let (Some(dst), Some(unmatched)) = (self.dst.as_ref(), self.unmatched.as_ref()) {
+            recognize = recognize.with_unmatched(dst.clone(), unmatched.clone());
+        }
+        let router = rt::Router::new_fixed(recognize, make);
 
         self.router = router;
     }
@@ -342,3 +586,131 @@ where
         self.router.call(req)
     }
 }
+
+fn with_backup(mut route: Route, backups: Option<&IndexMap<String, NameAddr>>) -> Route {
+    let backup = match (backups, route.labels().get("route")) {
+        (Some(backups), Some(name)) => backups.get(name).cloned(),
+        _ => None,
+    };
+    if let Some(backup) = backup {
+        route.set_backup(backup);
+    }
+    route
+}
+
+fn with_empty_failure(mut route: Route, names: Option<&IndexSet<String>>) -> Route {
+    if is_named(&route, names) {
+        route.set_empty_is_failure();
+    }
+    route
+}
+
+fn with_coalesce(mut route: Route, names: Option<&IndexSet<String>>) -> Route {
+    if is_named(&route, names) {
+        route.set_coalesce();
+    }
+    route
+}
+
+fn with_preserve_scheme(mut route: Route, names: Option<&IndexSet<String>>) -> Route {
+    if is_named(&route, names) {
+        route.set_preserve_scheme();
+    }
+    route
+}
+
+fn with_affinity_cookie(mut route: Route, cookies: Option<&IndexMap<String, String>>) -> Route {
+    let cookie = match (cookies, route.labels().get("route")) {
+        (Some(cookies), Some(name)) => cookies.get(name).cloned(),
+        _ => None,
+    };
+    if let Some(cookie) = cookie {
+        route.set_affinity_cookie(cookie);
+    }
+    route
+}
+
+fn with_transform(mut route: Route, transforms: Option<&IndexMap<String, Transform>>) -> Route {
+    let transform = match (transforms, route.labels().get("route")) {
+        (Some(transforms), Some(name)) => transforms.get(name).cloned(),
+        _ => None,
+    };
+    if let Some(transform) = transform {
+        route.set_transform(transform);
+    }
+    route
+}
+
+fn is_named(route: &Route, names: Option<&IndexSet<String>>) -> bool {
+    match (names, route.labels().get("route")) {
+        (Some(names), Some(name)) => names.contains(name),
+        _ => false,
+    }
+}
+
+/// Derives a new RNG from `rng`, so that every RNG derived from a seeded RNG
+/// is itself deterministic.
+fn fork(rng: &mut SmallRng) -> SmallRng {
+    SmallRng::from_rng(rng).expect("SmallRng must not fail to seed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform::{HeaderOp, Op};
+    use http::header::{HeaderName, HeaderValue};
+
+    fn route(name: &str) -> Route {
+        Route::new(
+            vec![("route".to_string(), name.to_string())].into_iter(),
+            vec![],
+        )
+    }
+
+    #[test]
+    fn transforms_apply_to_their_route_only() {
+        let hsts = Transform::new(vec![Op::Response(HeaderOp::Set(
+            HeaderName::from_static("strict-transport-security"),
+            HeaderValue::from_static("max-age=31536000"),
+        ))])
+        .unwrap();
+        let mut transforms = IndexMap::new();
+        transforms.insert("GET /login".to_string(), hsts);
+
+        let login = with_transform(route("GET /login"), Some(&transforms));
+        let mut rsp = http::Response::new(());
+        login
+            .transform()
+            .expect("the named route must be transformed")
+            .apply_response(&mut rsp);
+        assert_eq!(
+            rsp.headers()["strict-transport-security"],
+            "max-age=31536000"
+        );
+
+        let books = with_transform(route("GET /books"), Some(&transforms));
+        assert!(books.transform().is_none());
+
+        let default = with_transform(
+            Route::new_default(DefaultRoute::Unmatched),
+            Some(&transforms),
+        );
+        assert!(default.transform().is_none());
+    }
+
+    #[test]
+    fn backups_apply_to_their_route_only() {
+        let backup = NameAddr::from_str("books.backup.svc.cluster.local:8080").unwrap();
+        let mut backups = IndexMap::new();
+        backups.insert("GET /books".to_string(), backup.clone());
+
+        let books = with_backup(route("GET /books"), Some(&backups));
+        assert_eq!(books.backup(), Some(&backup));
+
+        let login = with_backup(route("GET /login"), Some(&backups));
+        assert!(login.backup().is_none());
+
+        let default = with_backup(Route::new_default(DefaultRoute::Unmatched), Some(&backups));
+        assert!(default.backup().is_none());
+    }
+}
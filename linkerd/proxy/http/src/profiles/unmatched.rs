@@ -0,0 +1,186 @@
+//! Tracks requests that match none of their destination profile's routes.
+//!
+//! Such requests are served by the default route, which can hide gaps in a
+//! profile's route definitions. Unmatched requests are counted per
+//! destination and, optionally, logged at most once per interval per
+//! destination so that profile authors can see what failed to match.
+//!
+//! Destinations that have not had an unmatched request within the configured
+//! idle period are evicted when metrics are scraped.
+
+use http;
+use indexmap::IndexMap;
+use linkerd2_addr::NameAddr;
+use linkerd2_metrics::{metrics, Counter, FmtLabels, FmtMetrics};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_timer::clock;
+use tracing::warn;
+
+metrics! {
+    route_unmatched_total: Counter {
+        "Total count of requests that matched none of their destination profile's routes"
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Unmatched {
+    by_dst: Arc<Mutex<IndexMap<NameAddr, Dst>>>,
+    warn_interval: Option<Duration>,
+    retain_idle: Option<Duration>,
+}
+
+#[derive(Debug)]
+struct Dst {
+    requests: Counter,
+    last_warned: Option<Instant>,
+    last_update: Instant,
+}
+
+struct DstLabel<'a>(&'a NameAddr);
+
+// === impl Unmatched ===
+
+impl Unmatched {
+    /// Logs a warning describing an unmatched request at most once per
+    /// `interval` for each destination.
+    pub fn with_warn_interval(self, warn_interval: Option<Duration>) -> Self {
+        Self {
+            warn_interval,
+            ..self
+        }
+    }
+
+    /// Evicts destinations that have had no unmatched requests for
+    /// `retain_idle`.
+    pub fn with_retain_idle(self, retain_idle: Duration) -> Self {
+        Self {
+            retain_idle: Some(retain_idle),
+            ..self
+        }
+    }
+
+    pub(super) fn record<B>(&self, dst: &NameAddr, req: &http::Request<B>) {
+        let mut by_dst = match self.by_dst.lock() {
+            Ok(by_dst) => by_dst,
+            Err(_) => return,
+        };
+        let now = clock::now();
+        let entry = by_dst.entry(dst.clone()).or_insert_with(|| Dst {
+            requests: Counter::default(),
+            last_warned: None,
+            last_update: now,
+        });
+        entry.requests.incr();
+        entry.last_update = now;
+
+        if let Some(interval) = self.warn_interval {
+            let should_warn = entry
+                .last_warned
+                .map(|at| now - at >= interval)
+                .unwrap_or(true);
+            if should_warn {
+                entry.last_warned = Some(now);
+                warn!(
+                    %dst,
+                    method = %req.method(),
+                    path = %req.uri().path(),
+                    "request did not match any route in the destination's profile",
+                );
+            }
+        }
+    }
+
+    /// Evicts destinations that have not been updated since `epoch`.
+    fn retain_since(by_dst: &mut IndexMap<NameAddr, Dst>, epoch: Instant) {
+        by_dst.retain(|_, d| d.last_update >= epoch);
+    }
+}
+
+impl FmtMetrics for Unmatched {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut by_dst = match self.by_dst.lock() {
+            Ok(by_dst) => by_dst,
+            Err(_) => return Ok(()),
+        };
+        if let Some(retain_idle) = self.retain_idle {
+            Self::retain_since(&mut by_dst, clock::now() - retain_idle);
+        }
+        if by_dst.is_empty() {
+            return Ok(());
+        }
+
+        route_unmatched_total.fmt_help(f)?;
+        route_unmatched_total.fmt_scopes(
+            f,
+            by_dst.iter().map(|(dst, d)| (DstLabel(dst), d)),
+            |d| &d.requests,
+        )?;
+
+        Ok(())
+    }
+}
+
+impl<'a> FmtLabels for DstLabel<'a> {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "dst=\"{}\"", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn dst(name: &str) -> NameAddr {
+        NameAddr::from_str(name).unwrap()
+    }
+
+    /// Marks `dst` as having been updated `idle` ago.
+    fn idle(unmatched: &Unmatched, dst: &NameAddr, idle: Duration) {
+        let mut by_dst = unmatched.by_dst.lock().unwrap();
+        by_dst
+            .get_mut(dst)
+            .expect("dst must be recorded")
+            .last_update -= idle;
+    }
+
+    #[test]
+    fn idle_destinations_are_evicted() {
+        let unmatched = Unmatched::default();
+        let web = dst("web.ns.svc.cluster.local:8080");
+        let api = dst("api.ns.svc.cluster.local:8080");
+
+        unmatched.record(&web, &http::Request::new(()));
+        unmatched.record(&api, &http::Request::new(()));
+        idle(&unmatched, &web, Duration::from_secs(60));
+
+        let mut by_dst = unmatched.by_dst.lock().unwrap();
+        Unmatched::retain_since(&mut by_dst, clock::now() - Duration::from_secs(30));
+        assert!(
+            !by_dst.contains_key(&web),
+            "idle destination must be evicted"
+        );
+        assert!(
+            by_dst.contains_key(&api),
+            "recently-updated destination must be retained"
+        );
+    }
+
+    #[test]
+    fn scrapes_evict_idle_destinations() {
+        let unmatched = Unmatched::default().with_retain_idle(Duration::from_secs(30));
+        let web = dst("web.ns.svc.cluster.local:8080");
+
+        unmatched.record(&web, &http::Request::new(()));
+        assert!(unmatched
+            .as_display()
+            .to_string()
+            .contains("route_unmatched_total{dst=\"web.ns.svc.cluster.local:8080\"} 1"));
+
+        idle(&unmatched, &web, Duration::from_secs(60));
+        assert_eq!(unmatched.as_display().to_string(), "");
+        assert!(unmatched.by_dst.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,550 @@
+//! Distinguishes responses that are reset after their headers are received.
+//!
+//! When an upstream resets its connection after it has sent a response's
+//! headers but before it has completed the response's body, the request can
+//! no longer fail with an error response. Instead, the body fails with a
+//! `ResetMidResponse` error, so that it is logged as such, and the reset is
+//! counted.
+//!
+//! Optionally, responses to idempotent requests on retryable routes are
+//! buffered, up to a limit, before they are returned. When such a response is
+//! reset while it is buffered, the response future fails with a
+//! `ResetMidResponse` so that the request may be retried. Responses that
+//! exceed the limit are streamed and can't be retried.
+
+use crate::retry::CanRetry;
+use bytes::{Buf, Bytes, BytesMut};
+use futures::{try_ready, Async, Future, Poll};
+use http::{self, Method};
+use hyper::body::Payload;
+use linkerd2_error::Error;
+use linkerd2_metrics::{metrics, Counter, FmtMetrics};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tracing::debug;
+
+metrics! {
+    response_reset_total: Counter {
+        "Total count of responses whose bodies were reset after their headers were received"
+    },
+    response_reset_retryable_total: Counter {
+        "Total count of responses that were reset before they were returned, so that their requests could be retried"
+    }
+}
+
+/// Fails a response whose body was reset by its upstream.
+#[derive(Debug)]
+pub struct ResetMidResponse(Error);
+
+/// Counts reset responses.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics(Arc<Mutex<Counts>>);
+
+#[derive(Debug, Default)]
+struct Counts {
+    resets: Counter,
+    retryable: Counter,
+}
+
+#[derive(Clone, Debug)]
+pub struct Layer {
+    max_buffer_bytes: Option<usize>,
+    metrics: Metrics,
+}
+
+#[derive(Clone, Debug)]
+pub struct Stack<M> {
+    inner: M,
+    max_buffer_bytes: Option<usize>,
+    metrics: Metrics,
+}
+
+pub struct MakeFuture<F> {
+    inner: F,
+    max_buffer_bytes: Option<usize>,
+    metrics: Metrics,
+}
+
+#[derive(Clone, Debug)]
+pub struct Service<S> {
+    inner: S,
+    max_buffer_bytes: Option<usize>,
+    metrics: Metrics,
+}
+
+pub struct ResponseFuture<F, B> {
+    state: State<F, B>,
+    metrics: Metrics,
+}
+
+enum State<F, B> {
+    Pending {
+        future: F,
+        max_buffer_bytes: Option<usize>,
+    },
+    Buffering {
+        head: http::response::Parts,
+        body: B,
+        data: BytesMut,
+        max_buffer_bytes: usize,
+    },
+    Done,
+}
+
+pub struct Body<B> {
+    /// Data that was buffered before the response was returned.
+    buffered: Option<Bytes>,
+    rest: Rest<B>,
+    metrics: Metrics,
+}
+
+enum Rest<B> {
+    Streaming(B),
+    /// The body's data was buffered entirely.
+    Trailers(Option<http::HeaderMap>),
+}
+
+/// Distinguishes responses that are reset mid-response. If `max_buffer_bytes`
+/// is set, responses to idempotent requests on retryable routes are buffered
+/// up to that size so that they may be retried if they're reset.
+pub fn layer(max_buffer_bytes: Option<usize>, metrics: Metrics) -> Layer {
+    Layer {
+        max_buffer_bytes,
+        metrics,
+    }
+}
+
+// === impl ResetMidResponse ===
+
+impl ResetMidResponse {
+    pub(crate) fn new(cause: impl Into<Error>) -> Self {
+        ResetMidResponse(cause.into())
+    }
+}
+
+impl fmt::Display for ResetMidResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "response reset by upstream: {}", self.0)
+    }
+}
+
+impl std::error::Error for ResetMidResponse {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&*self.0)
+    }
+}
+
+// === impl Layer ===
+
+impl<M> tower::layer::Layer<M> for Layer {
+    type Service = Stack<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        Stack {
+            inner,
+            max_buffer_bytes: self.max_buffer_bytes,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+// === impl Stack ===
+
+impl<T, M> tower::Service<T> for Stack<M>
+where
+    T: CanRetry,
+    M: tower::Service<T>,
+{
+    type Response = Service<M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        // Responses needn't be buffered if they can't be retried.
+        let max_buffer_bytes = self
+            .max_buffer_bytes
+            .filter(|_| target.can_retry().is_some());
+        MakeFuture {
+            inner: self.inner.call(target),
+            max_buffer_bytes,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+// === impl MakeFuture ===
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = Service<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        Ok(Service {
+            inner,
+            max_buffer_bytes: self.max_buffer_bytes,
+            metrics: self.metrics.clone(),
+        }
+        .into())
+    }
+}
+
+// === impl Service ===
+
+impl<S, A, B> tower::Service<http::Request<A>> for Service<S>
+where
+    S: tower::Service<http::Request<A>, Response = http::Response<B>>,
+    S::Error: Into<Error>,
+    B: Payload,
+    B::Data: From<Bytes>,
+{
+    type Response = http::Response<Body<B>>;
+    type Error = Error;
+    type Future = ResponseFuture<S::Future, B>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, req: http::Request<A>) -> Self::Future {
+        let max_buffer_bytes = self
+            .max_buffer_bytes
+            .filter(|_| is_idempotent(req.method()));
+        ResponseFuture {
+            state: State::Pending {
+                future: self.inner.call(req),
+                max_buffer_bytes,
+            },
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    match *method {
+        Method::GET
+        | Method::HEAD
+        | Method::OPTIONS
+        | Method::TRACE
+        | Method::PUT
+        | Method::DELETE => true,
+        _ => false,
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl<F, B> ResponseFuture<F, B>
+where
+    B: Payload,
+{
+    /// Fails a response that was reset while it was buffered.
+    fn reset(&mut self, error: B::Error) -> Error {
+        debug!("response reset before it was returned");
+        self.metrics.reset(true);
+        self.state = State::Done;
+        ResetMidResponse(error.into()).into()
+    }
+
+    fn respond(&mut self, trailers: Option<Option<http::HeaderMap>>) -> http::Response<Body<B>> {
+        match std::mem::replace(&mut self.state, State::Done) {
+            State::Buffering {
+                head, body, data, ..
+            } => {
+                let data = data.freeze();
+                let rest = match trailers {
+                    Some(trailers) => Rest::Trailers(trailers),
+                    None => Rest::Streaming(body),
+                };
+                http::Response::from_parts(
+                    head,
+                    Body {
+                        buffered: if data.is_empty() { None } else { Some(data) },
+                        rest,
+                        metrics: self.metrics.clone(),
+                    },
+                )
+            }
+            _ => unreachable!("response must be buffering"),
+        }
+    }
+}
+
+impl<F, B> Future for ResponseFuture<F, B>
+where
+    F: Future<Item = http::Response<B>>,
+    F::Error: Into<Error>,
+    B: Payload,
+    B::Data: From<Bytes>,
+{
+    type Item = http::Response<Body<B>>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            match self.state {
+                State::Pending {
+                    ref mut future,
+                    max_buffer_bytes,
+                } => {
+                    let rsp = try_ready!(future.poll().map_err(Into::into));
+                    let max_buffer_bytes = match max_buffer_bytes {
+                        Some(max) => max,
+                        None => {
+                            self.state = State::Done;
+                            let metrics = self.metrics.clone();
+                            return Ok(rsp
+                                .map(move |body| Body {
+                                    buffered: None,
+                                    rest: Rest::Streaming(body),
+                                    metrics,
+                                })
+                                .into());
+                        }
+                    };
+                    let (head, body) = rsp.into_parts();
+                    self.state = State::Buffering {
+                        head,
+                        body,
+                        data: BytesMut::new(),
+                        max_buffer_bytes,
+                    };
+                }
+                State::Buffering {
+                    ref mut body,
+                    ref mut data,
+                    max_buffer_bytes,
+                    ..
+                } => {
+                    loop {
+                        let chunk = match body.poll_data() {
+                            Ok(Async::NotReady) => return Ok(Async::NotReady),
+                            Ok(Async::Ready(chunk)) => chunk,
+                            Err(e) => return Err(self.reset(e)),
+                        };
+                        match chunk {
+                            Some(chunk) => {
+                                data.extend_from_slice(chunk.bytes());
+                                if data.len() > max_buffer_bytes {
+                                    debug!("response is too large to retry");
+                                    return Ok(self.respond(None).into());
+                                }
+                            }
+                            None => break,
+                        }
+                    }
+                    let trailers = match body.poll_trailers() {
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Ok(Async::Ready(trailers)) => trailers,
+                        Err(e) => return Err(self.reset(e)),
+                    };
+                    return Ok(self.respond(Some(trailers)).into());
+                }
+                State::Done => panic!("polled after complete"),
+            }
+        }
+    }
+}
+
+// === impl Body ===
+
+impl<B: Payload> Body<B> {
+    fn reset(&self, error: B::Error) -> Error {
+        debug!("response reset after it was returned");
+        self.metrics.reset(false);
+        ResetMidResponse(error.into()).into()
+    }
+}
+
+impl<B> Payload for Body<B>
+where
+    B: Payload,
+    B::Data: From<Bytes>,
+{
+    type Data = B::Data;
+    type Error = Error;
+
+    fn is_end_stream(&self) -> bool {
+        self.buffered.is_none()
+            && match self.rest {
+                Rest::Streaming(ref body) => body.is_end_stream(),
+                Rest::Trailers(ref trailers) => trailers.is_none(),
+            }
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        if let Some(data) = self.buffered.take() {
+            return Ok(Async::Ready(Some(data.into())));
+        }
+        match self.rest {
+            Rest::Streaming(ref mut body) => match body.poll_data() {
+                Ok(poll) => Ok(poll),
+                Err(e) => Err(self.reset(e)),
+            },
+            Rest::Trailers(_) => Ok(Async::Ready(None)),
+        }
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        match self.rest {
+            Rest::Streaming(ref mut body) => match body.poll_trailers() {
+                Ok(poll) => Ok(poll),
+                Err(e) => Err(self.reset(e)),
+            },
+            Rest::Trailers(ref mut trailers) => Ok(Async::Ready(trailers.take())),
+        }
+    }
+}
+
+// === impl Metrics ===
+
+impl Metrics {
+    fn reset(&self, retryable: bool) {
+        if let Ok(mut counts) = self.0.lock() {
+            counts.resets.incr();
+            if retryable {
+                counts.retryable.incr();
+            }
+        }
+    }
+}
+
+impl FmtMetrics for Metrics {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let counts = match self.0.lock() {
+            Err(_) => return Ok(()),
+            Ok(lock) => lock,
+        };
+
+        response_reset_total.fmt_help(f)?;
+        response_reset_total.fmt_metric(f, counts.resets)?;
+
+        response_reset_retryable_total.fmt_help(f)?;
+        response_reset_retryable_total.fmt_metric(f, counts.retryable)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use std::io;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Serves responses whose bodies are reset after their first chunk.
+    #[derive(Clone, Default)]
+    struct ResetUpstream(Arc<AtomicUsize>);
+
+    struct ResetBody(Option<&'static str>);
+
+    impl tower::Service<http::Request<()>> for ResetUpstream {
+        type Response = http::Response<ResetBody>;
+        type Error = Error;
+        type Future = future::FutureResult<Self::Response, Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: http::Request<()>) -> Self::Future {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            future::ok(http::Response::new(ResetBody(Some("partial"))))
+        }
+    }
+
+    impl Payload for ResetBody {
+        type Data = hyper::Chunk;
+        type Error = io::Error;
+
+        fn poll_data(&mut self) -> Poll<Option<hyper::Chunk>, io::Error> {
+            match self.0.take() {
+                Some(data) => Ok(Async::Ready(Some(data.into()))),
+                None => Err(io::Error::new(
+                    io::ErrorKind::ConnectionReset,
+                    "connection reset",
+                )),
+            }
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, io::Error> {
+            Ok(Async::Ready(None))
+        }
+    }
+
+    fn service(metrics: &Metrics) -> Service<ResetUpstream> {
+        Service {
+            inner: ResetUpstream::default(),
+            max_buffer_bytes: Some(1024),
+            metrics: metrics.clone(),
+        }
+    }
+
+    fn counts(metrics: &Metrics) -> (u64, u64) {
+        let counts = metrics.0.lock().unwrap();
+        (counts.resets.into(), counts.retryable.into())
+    }
+
+    #[test]
+    fn idempotent_requests_fail_before_the_response_is_returned() {
+        use tower::Service as _;
+
+        let metrics = Metrics::default();
+        let req = http::Request::get("/").body(()).unwrap();
+        let err = service(&metrics)
+            .call(req)
+            .wait()
+            .err()
+            .expect("the response must fail");
+        assert!(err.is::<ResetMidResponse>());
+        assert_eq!(counts(&metrics), (1, 1));
+    }
+
+    #[test]
+    fn other_requests_fail_while_the_body_is_read() {
+        use tower::Service as _;
+
+        let metrics = Metrics::default();
+        let req = http::Request::post("/").body(()).unwrap();
+        let mut body = service(&metrics)
+            .call(req)
+            .wait()
+            .expect("the response must be returned")
+            .into_body();
+
+        let chunk = body.poll_data().expect("the first chunk must be read");
+        assert!(chunk.is_ready());
+        let err = body.poll_data().err().expect("the body must fail");
+        assert!(err.is::<ResetMidResponse>(), "must be a distinct error");
+        assert_eq!(counts(&metrics), (1, 0));
+    }
+
+    #[test]
+    fn large_responses_are_streamed() {
+        use tower::Service as _;
+
+        let metrics = Metrics::default();
+        let mut svc = Service {
+            max_buffer_bytes: Some(4),
+            ..service(&metrics)
+        };
+        let req = http::Request::get("/").body(()).unwrap();
+        let mut body = svc
+            .call(req)
+            .wait()
+            .expect("the response must be returned")
+            .into_body();
+
+        match body.poll_data() {
+            Ok(Async::Ready(Some(chunk))) => assert_eq!(chunk.as_ref(), b"partial"),
+            _ => panic!("the buffered data must be read"),
+        }
+        assert!(body.poll_data().is_err());
+        assert_eq!(counts(&metrics), (1, 0));
+    }
+}
@@ -1,7 +1,14 @@
+use crate::avoid;
+use crate::dry_run;
 use crate::metrics::{handle_time, Scoped, Stats};
+use crate::outcome::Outcome;
+use crate::response_reset::ResetMidResponse;
+use crate::timeout;
 use futures::{future, try_ready, Future, Poll};
 use http::{Request, Response};
+use linkerd2_error::Error;
 use linkerd2_proxy_transport::tls;
+use linkerd2_trace_context as trace_context;
 use std::marker::PhantomData;
 use tower::retry as tower_retry;
 pub use tower::retry::budget::Budget;
@@ -14,6 +21,9 @@ pub trait CanRetry {
 
 pub trait Retry: Sized {
     fn retry<B1, B2>(&self, req: &Request<B1>, res: &Response<B2>) -> Result<(), NoRetry>;
+    /// Determines whether a request whose response was reset before it was
+    /// returned may be retried.
+    fn retry_reset<B>(&self, req: &Request<B>) -> Result<(), NoRetry>;
     fn clone_request<B: TryClone>(&self, req: &Request<B>) -> Option<Request<B>>;
 }
 
@@ -28,12 +38,14 @@ pub trait TryClone: Sized {
 
 pub struct Layer<S, K, A, B> {
     registry: S,
+    dry_run: Option<dry_run::retry::DryRun>,
     _p: PhantomData<(K, fn(A) -> B)>,
 }
 
 pub struct Stack<M, S, K, A, B> {
     inner: M,
     registry: S,
+    dry_run: Option<dry_run::retry::DryRun>,
     _p: PhantomData<(K, fn(A) -> B)>,
 }
 
@@ -44,22 +56,38 @@ pub struct MakeFuture<F, R, S> {
 
 pub type Service<R, Svc, St> = tower_retry::Retry<Policy<R, St>, Svc>;
 
+/// Tracks the number of the request attempt to which the policy applies.
 #[derive(Clone)]
-pub struct Policy<R, S>(R, S);
+pub struct Policy<R, S>(R, S, usize, Option<dry_run::retry::DryRun>);
+
+/// Set in `http::Request::extensions` to prevent the request from being
+/// retried, even if its route is retryable.
+#[derive(Copy, Clone, Debug)]
+pub struct Disabled;
 
 // === impl Layer ===
 
 pub fn layer<S, K, A, B>(registry: S) -> Layer<S, K, A, B> {
     Layer {
         registry,
+        dry_run: None,
         _p: PhantomData,
     }
 }
 
+impl<S, K, A, B> Layer<S, K, A, B> {
+    /// Compares each retry decision with an alternative condition, if
+    /// `dry_run` is set.
+    pub fn with_dry_run(self, dry_run: Option<dry_run::retry::DryRun>) -> Self {
+        Self { dry_run, ..self }
+    }
+}
+
 impl<S: Clone, K, A, B> Clone for Layer<S, K, A, B> {
     fn clone(&self) -> Self {
         Layer {
             registry: self.registry.clone(),
+            dry_run: self.dry_run.clone(),
             _p: PhantomData,
         }
     }
@@ -77,6 +105,7 @@ where
         Stack {
             inner,
             registry: self.registry.clone(),
+            dry_run: self.dry_run.clone(),
             _p: PhantomData,
         }
     }
@@ -89,6 +118,7 @@ impl<M: Clone, S: Clone, K, A, B> Clone for Stack<M, S, K, A, B> {
         Stack {
             inner: self.inner.clone(),
             registry: self.registry.clone(),
+            dry_run: self.dry_run.clone(),
             _p: PhantomData,
         }
     }
@@ -117,7 +147,7 @@ where
         let policy = if let Some(retries) = target.can_retry() {
             trace!("stack is retryable");
             let stats = self.registry.scoped(target.clone().into());
-            Some(Policy(retries, stats))
+            Some(Policy(retries, stats, 1, self.dry_run.clone()))
         } else {
             None
         };
@@ -148,7 +178,7 @@ where
 
 // === impl Policy ===
 
-impl<R, S, A, B, E> tower_retry::Policy<Request<A>, Response<B>, E> for Policy<R, S>
+impl<R, S, A, B> tower_retry::Policy<Request<A>, Response<B>, Error> for Policy<R, S>
 where
     R: Retry + Clone,
     S: Stats + Clone,
@@ -156,12 +186,75 @@ where
 {
     type Future = future::FutureResult<Self, ()>;
 
-    fn retry(&self, req: &Request<A>, result: Result<&Response<B>, &E>) -> Option<Self::Future> {
+    fn retry(
+        &self,
+        req: &Request<A>,
+        result: Result<&Response<B>, &Error>,
+    ) -> Option<Self::Future> {
         match result {
-            Ok(res) => match self.0.retry(req, res) {
+            Ok(res) => {
+                let decision = self.0.retry(req, res);
+                if let Some(ref dry_run) = self.3 {
+                    let retried = match decision {
+                        Err(NoRetry::Success) => false,
+                        Ok(()) | Err(NoRetry::Budget) => true,
+                    };
+                    dry_run.compare(res, retried);
+                }
+                match decision {
+                    Ok(()) => {
+                        let attempt = self.2 + 1;
+                        trace!(attempt, "retrying request");
+                        if let Some(outcome) = req.extensions().get::<Outcome>() {
+                            outcome.set_attempts(attempt);
+                        }
+                        trace_context::annotate(
+                            req,
+                            "retry",
+                            &[
+                                ("attempt", attempt.to_string()),
+                                ("http.status_code", res.status().as_str().to_owned()),
+                            ],
+                        );
+                        // Prefer that the retry is balanced to another endpoint.
+                        if let Some(endpoint) = res.extensions().get::<avoid::Handle>() {
+                            endpoint.avoid();
+                        }
+                        Some(future::ok(Policy(
+                            self.0.clone(),
+                            self.1.clone(),
+                            attempt,
+                            self.3.clone(),
+                        )))
+                    }
+                    Err(NoRetry::Budget) => {
+                        self.1.incr_retry_skipped_budget();
+                        None
+                    }
+                    Err(NoRetry::Success) => None,
+                }
+            }
+            Err(err) if err.is::<ResetMidResponse>() => match self.0.retry_reset(req) {
                 Ok(()) => {
-                    trace!("retrying request");
-                    Some(future::ok(self.clone()))
+                    let attempt = self.2 + 1;
+                    trace!(attempt, "retrying reset response");
+                    if let Some(outcome) = req.extensions().get::<Outcome>() {
+                        outcome.set_attempts(attempt);
+                    }
+                    trace_context::annotate(
+                        req,
+                        "retry",
+                        &[
+                            ("attempt", attempt.to_string()),
+                            ("error", "reset_mid_response".to_owned()),
+                        ],
+                    );
+                    Some(future::ok(Policy(
+                        self.0.clone(),
+                        self.1.clone(),
+                        attempt,
+                        self.3.clone(),
+                    )))
                 }
                 Err(NoRetry::Budget) => {
                     self.1.incr_retry_skipped_budget();
@@ -177,6 +270,16 @@ where
     }
 
     fn clone_request(&self, req: &Request<A>) -> Option<Request<A>> {
+        // The request is cloned just before each attempt is dispatched, so
+        // that it may be retried, so each attempt is counted here.
+        self.1.incr_attempt(self.2);
+
+        // Requests that are never retried needn't be cloned.
+        if req.extensions().get::<Disabled>().is_some() {
+            trace!("retries disabled for request");
+            return None;
+        }
+
         if let Some(clone) = self.0.clone_request(req) {
             trace!("cloning request");
             Some(clone)
@@ -206,9 +309,430 @@ impl<B: TryClone> TryClone for Request<B> {
                 clone.extensions_mut().insert(ext.clone());
             }
 
+            // Retries share the original request's timeout.
+            if let Some(ext) = self.extensions().get::<timeout::Deadline>() {
+                clone.extensions_mut().insert(*ext);
+            }
+
+            // Retries annotate the span of the original request.
+            if let Some(ext) = self.extensions().get::<trace_context::Annotations>() {
+                clone.extensions_mut().insert(ext.clone());
+            }
+
+            // Retries contribute to the original request's outcome.
+            if let Some(ext) = self.extensions().get::<Outcome>() {
+                clone.extensions_mut().insert(ext.clone());
+            }
+
             Some(clone)
         } else {
             None
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::classify::{ClassifyEos, ClassifyResponse};
+    use crate::{metrics, outcome};
+    use futures::{sync::mpsc, Async, Stream};
+    use http::StatusCode;
+    use hyper::body::Payload;
+    use linkerd2_metrics::{FmtLabels, FmtMetrics};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use std::{fmt, io, time::Duration};
+    use tower::layer::Layer as _;
+    use tower::Service as _;
+
+    /// Retries server errors.
+    #[derive(Clone)]
+    struct RetryServerErrors;
+
+    #[derive(Clone)]
+    struct NoStats;
+
+    struct Body;
+
+    /// Classifies server errors as failures.
+    #[derive(Clone, Default)]
+    struct ClassifyStatus;
+
+    struct ClassifyStatusEos(StatusCode);
+
+    #[derive(Clone, Debug, Hash, PartialEq, Eq)]
+    enum Class {
+        Success,
+        Failure,
+    }
+
+    #[derive(Clone, Debug, Hash, PartialEq, Eq)]
+    struct Route;
+
+    /// Fails requests with a 500 (or, if `reset`, a reset response) until the
+    /// given number of attempts is made.
+    #[derive(Clone)]
+    struct Flaky {
+        attempts: Arc<AtomicUsize>,
+        succeed_on: usize,
+        reset: bool,
+    }
+
+    struct MakeRetry(Flaky);
+
+    impl Retry for RetryServerErrors {
+        fn retry<B1, B2>(&self, _: &Request<B1>, res: &Response<B2>) -> Result<(), NoRetry> {
+            if res.status().is_server_error() {
+                Ok(())
+            } else {
+                Err(NoRetry::Success)
+            }
+        }
+
+        fn retry_reset<B>(&self, _: &Request<B>) -> Result<(), NoRetry> {
+            Ok(())
+        }
+
+        fn clone_request<B: TryClone>(&self, req: &Request<B>) -> Option<Request<B>> {
+            req.try_clone()
+        }
+    }
+
+    impl Stats for NoStats {
+        fn incr_retry_skipped_budget(&self) {}
+
+        fn incr_attempt(&self, _: usize) {}
+    }
+
+    impl TryClone for Body {
+        fn try_clone(&self) -> Option<Self> {
+            Some(Body)
+        }
+    }
+
+    impl Payload for Body {
+        type Data = io::Cursor<Vec<u8>>;
+        type Error = crate::Error;
+
+        fn is_end_stream(&self) -> bool {
+            true
+        }
+
+        fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+            Ok(Async::Ready(None))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+            Ok(Async::Ready(None))
+        }
+    }
+
+    impl ClassifyResponse for ClassifyStatus {
+        type Class = Class;
+        type ClassifyEos = ClassifyStatusEos;
+
+        fn start<B>(self, rsp: &Response<B>) -> Self::ClassifyEos {
+            ClassifyStatusEos(rsp.status())
+        }
+
+        fn error(self, _: &Error) -> Self::Class {
+            Class::Failure
+        }
+    }
+
+    impl ClassifyEos for ClassifyStatusEos {
+        type Class = Class;
+
+        fn eos(self, _: Option<&http::HeaderMap>) -> Self::Class {
+            if self.0.is_server_error() {
+                Class::Failure
+            } else {
+                Class::Success
+            }
+        }
+
+        fn error(self, _: &Error) -> Self::Class {
+            Class::Failure
+        }
+    }
+
+    impl FmtLabels for Class {
+        fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Class::Success => write!(f, "classification=\"success\""),
+                Class::Failure => write!(f, "classification=\"failure\""),
+            }
+        }
+    }
+
+    impl FmtLabels for Route {
+        fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "route=\"test\"")
+        }
+    }
+
+    impl<B> tower::Service<Request<B>> for Flaky {
+        type Response = Response<hyper::Body>;
+        type Error = crate::Error;
+        type Future = future::FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: Request<B>) -> Self::Future {
+            let attempt = self.attempts.fetch_add(1, Ordering::SeqCst) + 1;
+            if self.reset && attempt < self.succeed_on {
+                return future::err(ResetMidResponse::new("connection reset").into());
+            }
+            let status = if attempt < self.succeed_on {
+                StatusCode::INTERNAL_SERVER_ERROR
+            } else {
+                StatusCode::OK
+            };
+            let mut rsp = Response::new(hyper::Body::empty());
+            *rsp.status_mut() = status;
+            future::ok(rsp)
+        }
+    }
+
+    impl tower::Service<()> for MakeRetry {
+        type Response = Service<RetryServerErrors, Flaky, NoStats>;
+        type Error = crate::Error;
+        type Future = future::FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, (): ()) -> Self::Future {
+            let policy = Policy(RetryServerErrors, NoStats, 1, None);
+            future::ok(tower_retry::Retry::new(policy, self.0.clone()))
+        }
+    }
+
+    #[test]
+    fn retries_annotate_the_span() {
+        let (spans, rx) = mpsc::unbounded();
+        let flaky = Flaky {
+            attempts: Arc::new(AtomicUsize::new(0)),
+            succeed_on: 3,
+            reset: false,
+        };
+        let mut make = trace_context::layer(Some(spans)).layer(MakeRetry(flaky.clone()));
+        let mut svc = make.call(()).wait().expect("service must be made");
+
+        let req = Request::builder()
+            .header("x-b3-traceid", "0af7651916cd43dd8448eb211c80319c")
+            .header("x-b3-spanid", "b7ad6b7169203331")
+            .header("x-b3-sampled", "1")
+            .body(Body)
+            .unwrap();
+        let rsp = svc.call(req).wait().expect("request must succeed");
+        assert_eq!(rsp.status(), StatusCode::OK);
+        assert_eq!(flaky.attempts.load(Ordering::SeqCst), 3);
+
+        drop((make, svc));
+        let spans = rx.collect().wait().expect("spans must be collected");
+        assert_eq!(spans.len(), 1);
+        let annotations = &spans[0].annotations;
+        assert_eq!(annotations.len(), 2, "one annotation per retry");
+        for (annotation, attempt) in annotations.iter().zip(&["2", "3"]) {
+            assert_eq!(annotation.description, "retry");
+            assert_eq!(annotation.attributes["attempt"], *attempt);
+            assert_eq!(annotation.attributes["http.status_code"], "500");
+        }
+    }
+
+    #[test]
+    fn disabled_requests_are_not_retried() {
+        let flaky = Flaky {
+            attempts: Arc::new(AtomicUsize::new(0)),
+            succeed_on: 3,
+            reset: false,
+        };
+        let mut svc = MakeRetry(flaky.clone())
+            .call(())
+            .wait()
+            .expect("service must be made");
+
+        let mut req = Request::new(Body);
+        req.extensions_mut().insert(Disabled);
+        let rsp = svc.call(req).wait().expect("request must complete");
+        assert_eq!(rsp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(flaky.attempts.load(Ordering::SeqCst), 1);
+
+        let rsp = svc
+            .call(Request::new(Body))
+            .wait()
+            .expect("request must complete");
+        assert_eq!(
+            rsp.status(),
+            StatusCode::OK,
+            "other requests must be retried"
+        );
+        assert_eq!(flaky.attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn reset_responses_are_retried() {
+        let flaky = Flaky {
+            attempts: Arc::new(AtomicUsize::new(0)),
+            succeed_on: 2,
+            reset: true,
+        };
+        let mut svc = MakeRetry(flaky.clone())
+            .call(())
+            .wait()
+            .expect("service must be made");
+
+        let rsp = svc
+            .call(Request::get("/").body(Body).unwrap())
+            .wait()
+            .expect("request must be retried");
+        assert_eq!(rsp.status(), StatusCode::OK);
+        assert_eq!(flaky.attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn dry_run_counts_divergent_retries() {
+        let metrics = dry_run::Metrics::default();
+        // Unlike the route, the dry run retries only unavailable responses.
+        let condition = "503".parse().expect("condition must parse");
+        let dry_run = dry_run::retry::DryRun::new(condition, metrics.clone());
+        let flaky = Flaky {
+            attempts: Arc::new(AtomicUsize::new(0)),
+            succeed_on: 3,
+            reset: false,
+        };
+        let policy = Policy(RetryServerErrors, NoStats, 1, Some(dry_run));
+        let mut svc = tower_retry::Retry::new(policy, flaky.clone());
+
+        let rsp = svc
+            .call(Request::new(Body))
+            .wait()
+            .expect("request must complete");
+        assert_eq!(rsp.status(), StatusCode::OK);
+        assert_eq!(
+            flaky.attempts.load(Ordering::SeqCst),
+            3,
+            "the dry run must not change how requests are retried"
+        );
+
+        // Both 500s were retried, though the dry run wouldn't have retried
+        // them; neither would have retried the 200.
+        assert_eq!(metrics.count(dry_run::Policy::Retry, false), 2);
+        assert_eq!(metrics.count(dry_run::Policy::Retry, true), 1);
+        assert_eq!(metrics.count(dry_run::Policy::Balancer, true), 0);
+    }
+
+    #[test]
+    fn retried_outcomes_are_consistent() {
+        let (spans, rx) = mpsc::unbounded();
+        let (registry, report) =
+            metrics::new::<Route, Class>(Duration::from_secs(60), Duration::from_secs(10));
+        let flaky = Flaky {
+            attempts: Arc::new(AtomicUsize::new(0)),
+            succeed_on: 3,
+            reset: false,
+        };
+
+        // The route's metrics classify responses after they're retried.
+        let route = tower::service_fn(move |_: Route| {
+            let policy = Policy(RetryServerErrors, NoStats, 1, None);
+            future::ok::<_, Error>(tower_retry::Retry::new(policy, flaky.clone()))
+        });
+        let route = metrics::layer::<_, ClassifyStatus>(registry)
+            .with_outcome(true)
+            .layer(route);
+        let mut make = trace_context::layer(Some(spans)).layer(outcome::layer().layer(route));
+        let mut svc = make.call(Route).wait().expect("service must be made");
+
+        let outcome = Outcome::default();
+        let mut req = Request::builder()
+            .header("x-b3-traceid", "0af7651916cd43dd8448eb211c80319c")
+            .header("x-b3-spanid", "b7ad6b7169203331")
+            .header("x-b3-sampled", "1")
+            .body(Body)
+            .unwrap();
+        req.extensions_mut().insert(outcome.clone());
+        let rsp = svc.call(req).wait().expect("request must succeed");
+        assert_eq!(rsp.status(), StatusCode::OK);
+        assert!(outcome.get().is_none(), "the response hasn't completed");
+        drop(rsp);
+
+        let record = outcome.get().expect("outcome must be final");
+        assert_eq!(record.status, Some(StatusCode::OK));
+        assert_eq!(record.attempts, 3);
+        let class = record.class.expect("response must be classified");
+        assert_eq!(class, "classification=\"success\"");
+
+        // The route's metrics observe only the final class.
+        let report = report.as_display().to_string();
+        assert!(report.contains(&format!(
+            "response_total{{route=\"test\",status_code=\"200\",{}}} 1\n",
+            class
+        )));
+        assert!(!report.contains("classification=\"failure\""));
+
+        // The span is annotated with the same outcome.
+        drop((make, svc));
+        let spans = rx.collect().wait().expect("spans must be collected");
+        assert_eq!(spans.len(), 1);
+        let annotations = &spans[0].annotations;
+        let descriptions = annotations
+            .iter()
+            .map(|a| a.description.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(descriptions, vec!["retry", "retry", "outcome"]);
+        assert_eq!(annotations[1].attributes["attempt"], "3");
+        assert_eq!(annotations[2].attributes["attempts"], "3");
+        assert_eq!(annotations[2].attributes["class"], class);
+    }
+
+    /// Serves `requests` requests on a route whose endpoint fails the first
+    /// `failures` attempts of each, returning the route's metrics report.
+    fn attempt_metrics(requests: usize, failures: usize) -> String {
+        let (registry, report) =
+            metrics::new::<Route, Class>(Duration::from_secs(60), Duration::from_secs(10));
+        let stats = registry.scoped(Route);
+        for _ in 0..requests {
+            let flaky = Flaky {
+                attempts: Arc::new(AtomicUsize::new(0)),
+                succeed_on: failures + 1,
+                reset: false,
+            };
+            let policy = Policy(RetryServerErrors, stats.clone(), 1, None);
+            let rsp = tower_retry::Retry::new(policy, flaky)
+                .call(Request::new(Body))
+                .wait()
+                .expect("request must complete");
+            assert_eq!(rsp.status(), StatusCode::OK);
+        }
+        report.as_display().to_string()
+    }
+
+    #[test]
+    fn retries_amplify_attempts() {
+        let report = attempt_metrics(10, 1);
+        assert!(report.contains("attempt_total{route=\"test\",attempt=\"first\"} 10\n"));
+        assert!(report.contains("attempt_total{route=\"test\",attempt=\"second\"} 10\n"));
+        assert!(!report.contains("attempt=\"third+\""));
+        assert!(report.contains("attempt_amplification{route=\"test\"} 2\n"));
+
+        let report = attempt_metrics(10, 2);
+        assert!(report.contains("attempt_total{route=\"test\",attempt=\"third+\"} 10\n"));
+        assert!(report.contains("attempt_amplification{route=\"test\"} 3\n"));
+    }
+
+    #[test]
+    fn unretried_attempts_are_not_amplified() {
+        let report = attempt_metrics(10, 0);
+        assert!(report.contains("attempt_total{route=\"test\",attempt=\"first\"} 10\n"));
+        assert!(!report.contains("attempt=\"second\""));
+        assert!(report.contains("attempt_amplification{route=\"test\"} 1\n"));
+    }
+}
@@ -0,0 +1,206 @@
+//! Strips hop-by-hop headers from the responses that a proxy serves.
+//!
+//! Responses may have been translated between protocols on their way back to
+//! the proxy's client (e.g. from HTTP/2 to HTTP/1), so headers that described
+//! an upstream connection must not be forwarded to the client's connection.
+//! As described in RFC 7230 §6.1, this includes the `Connection` header and
+//! every header that it nominates.
+//!
+//! Successful HTTP upgrades are left untouched, since their `Connection` and
+//! `Upgrade` headers complete the upgrade.
+
+use super::h1;
+use futures::{try_ready, Future, Poll};
+use http::header::HeaderName;
+use indexmap::IndexSet;
+use std::sync::Arc;
+
+#[derive(Clone, Debug)]
+pub struct Layer {
+    strip: Arc<IndexSet<HeaderName>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Stack<M> {
+    inner: M,
+    strip: Arc<IndexSet<HeaderName>>,
+}
+
+pub struct MakeFuture<F> {
+    inner: F,
+    strip: Arc<IndexSet<HeaderName>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Service<S> {
+    inner: S,
+    strip: Arc<IndexSet<HeaderName>>,
+}
+
+pub struct ResponseFuture<F> {
+    inner: F,
+    strip: Arc<IndexSet<HeaderName>>,
+}
+
+/// Strips hop-by-hop headers, as well as each of the headers in `strip`, from
+/// responses.
+pub fn layer(strip: Arc<IndexSet<HeaderName>>) -> Layer {
+    Layer { strip }
+}
+
+/// Removes hop-by-hop headers and each of the headers in `strip` from `rsp`,
+/// unless it completes an HTTP upgrade.
+pub fn sanitize<B>(rsp: &mut http::Response<B>, strip: &IndexSet<HeaderName>) {
+    if h1::is_upgrade(rsp) {
+        return;
+    }
+
+    let headers = rsp.headers_mut();
+    h1::strip_connection_headers(headers);
+    for name in strip.iter() {
+        headers.remove(name);
+    }
+}
+
+// === impl Layer ===
+
+impl<M> tower::layer::Layer<M> for Layer {
+    type Service = Stack<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        Stack {
+            inner,
+            strip: self.strip.clone(),
+        }
+    }
+}
+
+// === impl Stack ===
+
+impl<T, M> tower::Service<T> for Stack<M>
+where
+    M: tower::Service<T>,
+{
+    type Response = Service<M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        MakeFuture {
+            inner: self.inner.call(target),
+            strip: self.strip.clone(),
+        }
+    }
+}
+
+// === impl MakeFuture ===
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = Service<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        Ok(Service {
+            inner,
+            strip: self.strip.clone(),
+        }
+        .into())
+    }
+}
+
+// === impl Service ===
+
+impl<S, Req, B> tower::Service<Req> for Service<S>
+where
+    S: tower::Service<Req, Response = http::Response<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        ResponseFuture {
+            inner: self.inner.call(req),
+            strip: self.strip.clone(),
+        }
+    }
+}
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+{
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut rsp = try_ready!(self.inner.poll());
+        sanitize(&mut rsp, &self.strip);
+        Ok(rsp.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::header;
+
+    fn response(status: http::StatusCode) -> http::Response<()> {
+        http::Response::builder()
+            .status(status)
+            .version(http::Version::HTTP_11)
+            .header(header::CONNECTION, "upgrade, x-hop")
+            .header("keep-alive", "timeout=5")
+            .header(header::UPGRADE, "websocket")
+            .header(header::TRANSFER_ENCODING, "chunked")
+            .header("x-hop", "1")
+            .header("x-debug", "1")
+            .header("x-normal", "1")
+            .body(())
+            .unwrap()
+    }
+
+    #[test]
+    fn strips_hop_by_hop_and_custom_headers() {
+        let strip = Some(HeaderName::from_static("x-debug"))
+            .into_iter()
+            .collect();
+        let mut rsp = response(http::StatusCode::OK);
+        sanitize(&mut rsp, &strip);
+
+        for name in &[
+            "connection",
+            "keep-alive",
+            "upgrade",
+            "transfer-encoding",
+            "x-hop",
+            "x-debug",
+        ] {
+            assert!(
+                !rsp.headers().contains_key(*name),
+                "{} must be stripped",
+                name
+            );
+        }
+        assert_eq!(rsp.headers()["x-normal"], "1");
+    }
+
+    #[test]
+    fn preserves_upgrades() {
+        let strip = IndexSet::new();
+        let mut rsp = response(http::StatusCode::SWITCHING_PROTOCOLS);
+        sanitize(&mut rsp, &strip);
+
+        assert_eq!(rsp.headers()[header::CONNECTION], "upgrade, x-hop");
+        assert_eq!(rsp.headers()[header::UPGRADE], "websocket");
+    }
+}
@@ -15,6 +15,14 @@ pub enum Settings {
         /// absolute URIs be bound to separate service stacks. It is also
         /// used to determine what URI normalization will be necessary.
         was_absolute_form: bool,
+        /// Whether the request was HTTP/1.0 rather than HTTP/1.1.
+        ///
+        /// HTTP/1.0 clients can't parse chunked responses, so their
+        /// responses must be framed by a content-length or by closing the
+        /// connection.
+        is_http10: bool,
+        /// Whether the request's `TE` header accepted trailers.
+        wants_trailers: bool,
     },
     Http2,
 
@@ -53,6 +61,8 @@ impl Settings {
             keep_alive: !is_missing_authority,
             wants_h1_upgrade,
             was_absolute_form: super::h1::is_absolute_form(req.uri()),
+            is_http10: req.version() == http::Version::HTTP_10,
+            wants_trailers: super::h1::wants_trailers(req),
         }
     }
 
@@ -66,6 +76,22 @@ impl Settings {
         }
     }
 
+    /// Returns true if the request was HTTP/1.0.
+    pub fn is_http10(&self) -> bool {
+        match self {
+            Settings::Http1 { is_http10, .. } => *is_http10,
+            Settings::Http2 | Settings::NotHttp => false,
+        }
+    }
+
+    /// Returns true if an HTTP/1 request accepted trailers in its response.
+    pub fn wants_trailers(&self) -> bool {
+        match self {
+            Settings::Http1 { wants_trailers, .. } => *wants_trailers,
+            Settings::Http2 | Settings::NotHttp => false,
+        }
+    }
+
     pub fn is_http2(&self) -> bool {
         match self {
             Settings::Http2 => true,
@@ -2,7 +2,8 @@ use futures::{future, try_ready, Future, Poll};
 use http::{Request, Response, StatusCode};
 use linkerd2_error::Error;
 use linkerd2_timeout::{error, Timeout};
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use tokio_timer::{self as timer, clock};
 use tracing::{debug, error};
 
 /// Implement on targets to determine if a service has a timeout.
@@ -35,13 +36,27 @@ pub struct MakeFuture<F> {
 }
 
 #[derive(Clone, Debug)]
-pub struct Service<S>(Timeout<S>);
+pub struct Service<S> {
+    inner: S,
+    timeout: Duration,
+}
 
 /// A marker set in `http::Response::extensions` that *this* process triggered
 /// the request timeout.
 #[derive(Debug)]
 pub struct ProxyTimedOut(());
 
+/// Set in `http::Request::extensions` with the time at which the request's
+/// timeout fires, so that lower layers can tell when a request was abandoned
+/// because it timed out.
+#[derive(Copy, Clone, Debug)]
+pub struct Deadline(Instant);
+
+/// Set in `http::Request::extensions` to replace the timeout of the request's
+/// route, if the route has one.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Override(pub Duration);
+
 impl<M> tower::layer::Layer<M> for Layer {
     type Service = Stack<M>;
 
@@ -79,7 +94,7 @@ impl<F: Future> Future for MakeFuture<F> {
         let inner = try_ready!(self.inner.poll());
 
         let svc = if let Some(timeout) = self.timeout {
-            tower::util::Either::A(Service(Timeout::new(inner, timeout)))
+            tower::util::Either::A(Service { inner, timeout })
         } else {
             tower::util::Either::B(inner)
         };
@@ -96,17 +111,27 @@ where
     type Response = S::Response;
     type Error = Error;
     type Future = future::OrElse<
-        <Timeout<S> as tower::Service<Request<B1>>>::Future,
+        Timeout<timer::Timeout<S::Future>>,
         Result<Response<B2>, Error>,
         fn(Error) -> Result<Response<B2>, Error>,
     >;
 
     fn poll_ready(&mut self) -> Poll<(), Self::Error> {
-        self.0.poll_ready()
+        self.inner.poll_ready().map_err(Into::into)
     }
 
-    fn call(&mut self, req: Request<B1>) -> Self::Future {
-        self.0.call(req).or_else(|err| {
+    fn call(&mut self, mut req: Request<B1>) -> Self::Future {
+        let timeout = match req.extensions().get::<Override>() {
+            Some(&Override(timeout)) => {
+                debug!(?timeout, route.timeout = ?self.timeout, "overriding route timeout");
+                timeout
+            }
+            None => self.timeout,
+        };
+        req.extensions_mut()
+            .insert(Deadline(clock::now() + timeout));
+        let inner = timer::Timeout::new(self.inner.call(req), timeout);
+        Timeout::new(inner, timeout).or_else(|err| {
             if let Some(err) = err.downcast_ref::<error::Timedout>() {
                 debug!("request timed out after {:?}", err.duration());
                 let mut res = Response::default();
@@ -126,3 +151,45 @@ where
         })
     }
 }
+
+// === impl Deadline ===
+
+impl Deadline {
+    /// Returns true if the request's timeout has fired.
+    pub fn is_expired(&self) -> bool {
+        clock::now() >= self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::runtime::current_thread::Runtime;
+    use tower::Service as _;
+
+    /// Serves each request after `delay`.
+    fn serve(req: Request<()>, delay: Duration, timeout: Duration) -> Result<Response<()>, Error> {
+        let inner = tower::service_fn(move |_: Request<()>| {
+            timer::Delay::new(clock::now() + delay).map(|()| Response::new(()))
+        });
+        let mut svc = Service { inner, timeout };
+        Runtime::new()
+            .unwrap()
+            .block_on(future::lazy(move || svc.call(req)))
+    }
+
+    #[test]
+    fn override_extends_route_timeout() {
+        let delay = Duration::from_millis(50);
+        let route_timeout = Duration::from_millis(10);
+
+        let rsp = serve(Request::new(()), delay, route_timeout).unwrap();
+        assert_eq!(rsp.status(), StatusCode::GATEWAY_TIMEOUT);
+
+        let mut req = Request::new(());
+        req.extensions_mut()
+            .insert(Override(Duration::from_secs(10)));
+        let rsp = serve(req, delay, route_timeout).unwrap();
+        assert_eq!(rsp.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,609 @@
+//! Applies declarative transformations to the requests and responses on a
+//! route.
+//!
+//! A route's `Transform` is an ordered list of operations, each of which adds,
+//! sets, removes, or renames a request or response header, assigns the request
+//! to an experiment bucket, or rewrites the request's path. Operations are
+//! applied in order, so, e.g., a header added by one operation may be renamed
+//! by a later one.
+//!
+//! Transformations can't execute arbitrary code, and they are bounded: a
+//! route has at most `MAX_OPS` operations and each path pattern's compiled
+//! size is limited to `MAX_PATTERN_BYTES`.
+
+use futures::{try_ready, Future, Poll};
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use http::uri::{PathAndQuery, Uri};
+use rand::Rng;
+use regex::{Regex, RegexBuilder};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use tracing::debug;
+
+/// The maximum number of operations in a route's transformation.
+pub const MAX_OPS: usize = 16;
+
+/// Bounds the compiled size of a path rewrite's pattern.
+const MAX_PATTERN_BYTES: usize = 1 << 16;
+
+/// Implement on targets to determine how a route's requests and responses
+/// are transformed.
+pub trait HasTransform {
+    fn transform(&self) -> Option<Transform>;
+}
+
+#[derive(Clone, Debug)]
+pub enum Op {
+    Request(HeaderOp),
+    Response(HeaderOp),
+    RewritePath(Rewrite),
+    Bucket(Bucket),
+}
+
+#[derive(Clone, Debug)]
+pub enum HeaderOp {
+    /// Appends a value to a header.
+    Add(HeaderName, HeaderValue),
+    /// Replaces all of a header's values with a value.
+    Set(HeaderName, HeaderValue),
+    /// Removes all of a header's values.
+    Remove(HeaderName),
+    /// Moves all of a header's values to another header.
+    Rename(HeaderName, HeaderName),
+}
+
+/// Rewrites the first match of a pattern in a request's path.
+///
+/// The replacement may refer to the pattern's capture groups, as in `$1` or
+/// `${name}`. The request's query is preserved.
+#[derive(Clone, Debug)]
+pub struct Rewrite {
+    pattern: Regex,
+    replacement: String,
+}
+
+/// Sets a request header to the name of the experiment bucket to which the
+/// request is assigned, so that downstream services may branch on it.
+///
+/// Each request is assigned to a bucket at random, in proportion to the
+/// buckets' weights. If a key header is configured, requests with the same
+/// key are assigned to the same bucket instead; requests without the key are
+/// assigned at random.
+#[derive(Clone, Debug)]
+pub struct Bucket {
+    header: HeaderName,
+    key: Option<HeaderName>,
+    buckets: Arc<Vec<(HeaderValue, u32)>>,
+    total_weight: u64,
+}
+
+#[derive(Clone)]
+pub struct Transform(Arc<Vec<Op>>);
+
+#[derive(Debug)]
+pub struct TooManyOps(usize);
+
+/// Indicates that experiment buckets have no total weight.
+#[derive(Debug)]
+pub struct NoBuckets(());
+
+pub fn layer() -> Layer {
+    Layer
+}
+
+#[derive(Clone, Debug)]
+pub struct Layer;
+
+#[derive(Clone, Debug)]
+pub struct Stack<M> {
+    inner: M,
+}
+
+pub struct MakeFuture<F> {
+    inner: F,
+    transform: Option<Transform>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Service<S> {
+    inner: S,
+    transform: Transform,
+}
+
+pub struct ResponseFuture<F> {
+    inner: F,
+    transform: Transform,
+}
+
+// === impl HeaderOp ===
+
+impl HeaderOp {
+    fn apply(&self, headers: &mut HeaderMap) {
+        match self {
+            HeaderOp::Add(name, value) => {
+                headers.append(name.clone(), value.clone());
+            }
+            HeaderOp::Set(name, value) => {
+                headers.insert(name.clone(), value.clone());
+            }
+            HeaderOp::Remove(name) => {
+                headers.remove(name);
+            }
+            HeaderOp::Rename(from, to) => {
+                let values = headers.get_all(from).iter().cloned().collect::<Vec<_>>();
+                headers.remove(from);
+                for value in values {
+                    headers.append(to.clone(), value);
+                }
+            }
+        }
+    }
+}
+
+// === impl Rewrite ===
+
+impl Rewrite {
+    pub fn new(pattern: &str, replacement: impl Into<String>) -> Result<Self, regex::Error> {
+        let pattern = RegexBuilder::new(pattern)
+            .size_limit(MAX_PATTERN_BYTES)
+            .build()?;
+        Ok(Self {
+            pattern,
+            replacement: replacement.into(),
+        })
+    }
+
+    /// Returns the rewritten URI, or `None` if the path does not match or the
+    /// rewritten path is invalid.
+    fn apply(&self, uri: &Uri) -> Option<Uri> {
+        let path = uri.path();
+        if !self.pattern.is_match(path) {
+            return None;
+        }
+
+        let rewritten = self.pattern.replace(path, self.replacement.as_str());
+        if !rewritten.starts_with('/') {
+            debug!(%path, %rewritten, "rewritten path is not absolute");
+            return None;
+        }
+        let path_and_query = match uri.query() {
+            Some(query) => format!("{}?{}", rewritten, query),
+            None => rewritten.into_owned(),
+        };
+        let path_and_query = match path_and_query.parse::<PathAndQuery>() {
+            Ok(pq) => pq,
+            Err(error) => {
+                debug!(%path, %error, "rewritten path is invalid");
+                return None;
+            }
+        };
+
+        let mut parts = uri.clone().into_parts();
+        parts.path_and_query = Some(path_and_query);
+        Uri::from_parts(parts).ok()
+    }
+}
+
+// === impl Bucket ===
+
+impl Bucket {
+    pub fn new(
+        header: HeaderName,
+        key: Option<HeaderName>,
+        buckets: Vec<(HeaderValue, u32)>,
+    ) -> Result<Self, NoBuckets> {
+        let total_weight = buckets.iter().map(|&(_, w)| u64::from(w)).sum();
+        if total_weight == 0 {
+            return Err(NoBuckets(()));
+        }
+        Ok(Self {
+            header,
+            key,
+            buckets: Arc::new(buckets),
+            total_weight,
+        })
+    }
+
+    fn apply<R: Rng>(&self, headers: &mut HeaderMap, rng: &mut R) {
+        let bucket = self.assign(headers, rng).clone();
+        headers.insert(self.header.clone(), bucket);
+    }
+
+    fn assign<R: Rng>(&self, headers: &HeaderMap, rng: &mut R) -> &HeaderValue {
+        let key = self
+            .key
+            .as_ref()
+            .and_then(|k| headers.get(k))
+            .map(|v| v.as_bytes());
+        let mut point = match key {
+            Some(key) => fnv1a(key) % self.total_weight,
+            None => rng.gen_range(0, self.total_weight),
+        };
+
+        for (bucket, weight) in self.buckets.iter() {
+            let weight = u64::from(*weight);
+            if point < weight {
+                return bucket;
+            }
+            point -= weight;
+        }
+        unreachable!("point must be less than the total weight");
+    }
+}
+
+/// Hashes experiment keys so that they're assigned to the same bucket by
+/// every proxy, regardless of its version.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+    bytes.iter().fold(OFFSET_BASIS, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(PRIME)
+    })
+}
+
+// === impl Transform ===
+
+impl Transform {
+    pub fn new(ops: Vec<Op>) -> Result<Self, TooManyOps> {
+        if ops.len() > MAX_OPS {
+            return Err(TooManyOps(ops.len()));
+        }
+        Ok(Transform(Arc::new(ops)))
+    }
+
+    /// Returns true if the transformation has no operations.
+    pub fn is_noop(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn apply_request<B>(&self, req: &mut http::Request<B>) {
+        for op in self.0.iter() {
+            match op {
+                Op::Request(op) => op.apply(req.headers_mut()),
+                Op::RewritePath(rewrite) => {
+                    if let Some(uri) = rewrite.apply(req.uri()) {
+                        debug!(from = %req.uri(), to = %uri, "rewriting");
+                        *req.uri_mut() = uri;
+                    }
+                }
+                Op::Bucket(bucket) => bucket.apply(req.headers_mut(), &mut rand::thread_rng()),
+                Op::Response(_) => {}
+            }
+        }
+    }
+
+    pub fn apply_response<B>(&self, rsp: &mut http::Response<B>) {
+        for op in self.0.iter() {
+            if let Op::Response(op) = op {
+                op.apply(rsp.headers_mut());
+            }
+        }
+    }
+}
+
+impl PartialEq for Transform {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Transform {}
+
+impl Hash for Transform {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(Arc::as_ref(&self.0) as *const _ as usize);
+    }
+}
+
+impl fmt::Debug for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.0.iter()).finish()
+    }
+}
+
+// === impl TooManyOps ===
+
+impl fmt::Display for TooManyOps {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "a route may have at most {} transformations; found {}",
+            MAX_OPS, self.0
+        )
+    }
+}
+
+impl std::error::Error for TooManyOps {}
+
+// === impl NoBuckets ===
+
+impl fmt::Display for NoBuckets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "experiment buckets must have a nonzero total weight")
+    }
+}
+
+impl std::error::Error for NoBuckets {}
+
+// === impl Layer ===
+
+impl<M> tower::layer::Layer<M> for Layer {
+    type Service = Stack<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        Stack { inner }
+    }
+}
+
+// === impl Stack ===
+
+impl<T, M> tower::Service<T> for Stack<M>
+where
+    T: HasTransform,
+    M: tower::Service<T>,
+{
+    type Response = tower::util::Either<Service<M::Response>, M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let transform = target.transform().filter(|t| !t.is_noop());
+        let inner = self.inner.call(target);
+        MakeFuture { inner, transform }
+    }
+}
+
+// === impl MakeFuture ===
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = tower::util::Either<Service<F::Item>, F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+
+        let svc = match self.transform.take() {
+            Some(transform) => tower::util::Either::A(Service { inner, transform }),
+            None => tower::util::Either::B(inner),
+        };
+        Ok(svc.into())
+    }
+}
+
+// === impl Service ===
+
+impl<S, A, B> tower::Service<http::Request<A>> for Service<S>
+where
+    S: tower::Service<http::Request<A>, Response = http::Response<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut req: http::Request<A>) -> Self::Future {
+        self.transform.apply_request(&mut req);
+        ResponseFuture {
+            inner: self.inner.call(req),
+            transform: self.transform.clone(),
+        }
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+{
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut rsp = try_ready!(self.inner.poll());
+        self.transform.apply_response(&mut rsp);
+        Ok(rsp.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indexmap::IndexMap;
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    fn name(s: &'static str) -> HeaderName {
+        HeaderName::from_static(s)
+    }
+
+    fn request(uri: &str) -> http::Request<()> {
+        http::Request::builder()
+            .uri(uri)
+            .header("x-old", "a")
+            .header("x-old", "b")
+            .body(())
+            .unwrap()
+    }
+
+    #[test]
+    fn renames_headers() {
+        let transform = Transform::new(vec![
+            Op::Request(HeaderOp::Rename(name("x-old"), name("x-new"))),
+            Op::Response(HeaderOp::Rename(name("x-old"), name("x-new"))),
+        ])
+        .unwrap();
+
+        let mut req = request("/users");
+        transform.apply_request(&mut req);
+        assert!(req.headers().get("x-old").is_none());
+        let values = req.headers().get_all("x-new").iter().collect::<Vec<_>>();
+        assert_eq!(values, vec!["a", "b"]);
+
+        let mut rsp = http::Response::builder()
+            .header("x-old", "c")
+            .body(())
+            .unwrap();
+        transform.apply_response(&mut rsp);
+        assert!(rsp.headers().get("x-old").is_none());
+        assert_eq!(rsp.headers()["x-new"], "c");
+    }
+
+    #[test]
+    fn injects_response_headers() {
+        let transform = Transform::new(vec![
+            Op::Response(HeaderOp::Add(name("x-old"), HeaderValue::from_static("d"))),
+            Op::Response(HeaderOp::Set(
+                name("cache-control"),
+                HeaderValue::from_static("no-store"),
+            )),
+        ])
+        .unwrap();
+
+        let mut rsp = http::Response::builder()
+            .header("x-old", "c")
+            .header("cache-control", "max-age=60")
+            .header("cache-control", "public")
+            .body(())
+            .unwrap();
+        transform.apply_response(&mut rsp);
+        let values = rsp.headers().get_all("x-old").iter().collect::<Vec<_>>();
+        assert_eq!(values, vec!["c", "d"], "added values must be appended");
+        let values = rsp
+            .headers()
+            .get_all("cache-control")
+            .iter()
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec!["no-store"], "set values must overwrite");
+
+        // Requests are unchanged by response operations.
+        let mut req = request("/users");
+        transform.apply_request(&mut req);
+        assert!(req.headers().get("cache-control").is_none());
+    }
+
+    #[test]
+    fn rewrites_path_with_capture_group() {
+        let transform = Transform::new(vec![Op::RewritePath(
+            Rewrite::new("^/v1/users/([0-9]+)$", "/v2/accounts/$1").unwrap(),
+        )])
+        .unwrap();
+
+        let mut req = request("http://web.ns.svc.cluster.local/v1/users/42?verbose=true");
+        transform.apply_request(&mut req);
+        assert_eq!(
+            req.uri(),
+            "http://web.ns.svc.cluster.local/v2/accounts/42?verbose=true"
+        );
+
+        // Paths that don't match the pattern are unchanged.
+        let mut req = request("/v1/users/me");
+        transform.apply_request(&mut req);
+        assert_eq!(req.uri(), "/v1/users/me");
+    }
+
+    #[test]
+    fn noop_route_is_unchanged() {
+        let transform = Transform::new(Vec::new()).unwrap();
+        assert!(transform.is_noop());
+
+        let mut req = request("/users?verbose=true");
+        transform.apply_request(&mut req);
+        assert_eq!(req.uri(), "/users?verbose=true");
+        let values = req.headers().get_all("x-old").iter().collect::<Vec<_>>();
+        assert_eq!(values, vec!["a", "b"]);
+    }
+
+    fn buckets(key: Option<HeaderName>) -> Bucket {
+        Bucket::new(
+            name("x-experiment"),
+            key,
+            vec![
+                (HeaderValue::from_static("control"), 70),
+                (HeaderValue::from_static("disabled"), 0),
+                (HeaderValue::from_static("treatment"), 30),
+            ],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn buckets_respect_weights() {
+        let bucket = buckets(None);
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut counts = IndexMap::<String, usize>::new();
+        for _ in 0..10_000 {
+            let mut headers = HeaderMap::new();
+            bucket.apply(&mut headers, &mut rng);
+            let assigned = headers["x-experiment"].to_str().unwrap().to_owned();
+            *counts.entry(assigned).or_insert(0) += 1;
+        }
+
+        let control = counts.get("control").cloned().unwrap_or(0);
+        let treatment = counts.get("treatment").cloned().unwrap_or(0);
+        assert!(6_700 < control && control < 7_300, "control: {}", control);
+        assert!(
+            2_700 < treatment && treatment < 3_300,
+            "treatment: {}",
+            treatment
+        );
+        assert!(
+            counts.get("disabled").is_none(),
+            "buckets without weight must not be assigned"
+        );
+    }
+
+    #[test]
+    fn keyed_buckets_are_stable() {
+        let bucket = buckets(Some(name("x-user-id")));
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut assigned = IndexMap::new();
+        for user in 0..100 {
+            for _ in 0..10 {
+                let mut req = request("/users");
+                req.headers_mut()
+                    .insert("x-user-id", user.to_string().parse().unwrap());
+                req.headers_mut()
+                    .insert("x-experiment", HeaderValue::from_static("spoofed"));
+                bucket.apply(req.headers_mut(), &mut rng);
+                let b = req.headers()["x-experiment"].clone();
+                assert_eq!(
+                    assigned.entry(user).or_insert_with(|| b.clone()),
+                    &b,
+                    "user {} must always be assigned the same bucket",
+                    user
+                );
+            }
+        }
+        assert!(
+            assigned.values().any(|b| b == "control")
+                && assigned.values().any(|b| b == "treatment"),
+            "keys must be spread over buckets"
+        );
+
+        // Keys are hashed independently of the process, so assignments are
+        // stable across proxies.
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn buckets_require_weight() {
+        let buckets = vec![(HeaderValue::from_static("control"), 0)];
+        assert!(Bucket::new(name("x-experiment"), None, buckets).is_err());
+        assert!(Bucket::new(name("x-experiment"), None, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn limits_ops() {
+        let ops = vec![Op::Request(HeaderOp::Remove(name("x-old"))); MAX_OPS + 1];
+        assert!(Transform::new(ops).is_err());
+    }
+}
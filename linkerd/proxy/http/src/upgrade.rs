@@ -208,7 +208,7 @@ where
 
             Some(halves.server)
         } else {
-            h1::strip_connection_headers(req.headers_mut());
+            h1::strip_request_connection_headers(&mut req);
             None
         };
 
@@ -1,5 +1,5 @@
 use crate::{Crt, CrtKey, Csr, Key, Name, TokenSource, TrustAnchors};
-use futures::{try_ready, Async, Future, Poll};
+use futures::{try_ready, Async, Future, Poll, Stream};
 use linkerd2_error::Never;
 use linkerd2_proxy_api::identity as api;
 use linkerd2_proxy_transport::tls;
@@ -36,6 +36,14 @@ pub struct Local {
 #[derive(Debug)]
 pub struct AwaitCrt(Option<Local>);
 
+/// Yields each time the local certificate is replaced by a new one.
+///
+/// The first certificate to be provisioned is not a rotation.
+pub struct Rotations {
+    crt_key: watch::Receiver<Option<CrtKey>>,
+    current: Option<Arc<tls::client::Config>>,
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct LostDaemon;
 
@@ -106,9 +114,32 @@ impl Local {
         &self.name
     }
 
+    /// Indicates whether a certificate has been provisioned for this identity.
+    pub fn is_certified(&self) -> bool {
+        self.crt_key.get_ref().is_some()
+    }
+
+    /// Returns the time after which the current certificate is no longer
+    /// valid, if one has been provisioned.
+    pub fn crt_expiry(&self) -> Option<SystemTime> {
+        self.crt_key.get_ref().as_ref().map(CrtKey::expiry)
+    }
+
     pub fn await_crt(self) -> AwaitCrt {
         AwaitCrt(Some(self))
     }
+
+    /// Returns a stream of the current certificate's rotations.
+    pub fn rotations(&self) -> Rotations {
+        Rotations {
+            current: self
+                .crt_key
+                .get_ref()
+                .as_ref()
+                .map(CrtKey::tls_client_config),
+            crt_key: self.crt_key.clone(),
+        }
+    }
 }
 
 impl tls::client::HasConfig for Local {
@@ -249,6 +280,49 @@ where
     }
 }
 
+// === impl LostDaemon ===
+
+impl std::fmt::Display for LostDaemon {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "identity daemon lost")
+    }
+}
+
+impl std::error::Error for LostDaemon {}
+
+// === impl Rotations ===
+
+impl Stream for Rotations {
+    type Item = ();
+    type Error = Never;
+
+    fn poll(&mut self) -> Poll<Option<()>, Never> {
+        loop {
+            let config = match self.crt_key.poll_ref() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(Some(crt_key))) => crt_key.as_ref().map(CrtKey::tls_client_config),
+                Err(_) | Ok(Async::Ready(None)) => return Ok(Async::Ready(None)),
+            };
+
+            // The watch may yield a certificate that has already been
+            // observed, i.e. when the stream is first polled.
+            let config = match config {
+                Some(config) => config,
+                None => continue,
+            };
+            let rotated = match self.current {
+                Some(ref current) if Arc::ptr_eq(current, &config) => continue,
+                Some(_) => true,
+                None => false,
+            };
+            self.current = Some(config);
+            if rotated {
+                return Ok(Async::Ready(Some(())));
+            }
+        }
+    }
+}
+
 // === impl AwaitCrt ===
 
 impl Future for AwaitCrt {
@@ -274,3 +348,97 @@ impl Future for AwaitCrt {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use linkerd2_identity::test_util::FOO_NS1;
+
+    fn local() -> (Local, CrtKeySender) {
+        let (s, w) = watch::channel(None);
+        let local = Local {
+            name: Name::from_hostname(FOO_NS1.name.as_bytes()).unwrap(),
+            trust_anchors: FOO_NS1.trust_anchors(),
+            crt_key: w,
+        };
+        (local, s)
+    }
+
+    #[test]
+    fn rotated_crt_is_used_for_new_handshakes() {
+        let (local, mut crt_key) = local();
+        assert_eq!(local.crt_expiry(), None);
+
+        let first = FOO_NS1.validate().expect("valid crt");
+        crt_key.broadcast(Some(first.clone())).unwrap();
+        assert_eq!(local.crt_expiry(), Some(first.expiry()));
+        assert!(Arc::ptr_eq(
+            &tls::accept::HasConfig::tls_server_config(&local),
+            &first.tls_server_config()
+        ));
+
+        // Each handshake reads the current configuration, so the new
+        // certificate is presented as soon as it's provisioned.
+        let second = FOO_NS1.validate().expect("valid crt");
+        crt_key.broadcast(Some(second.clone())).unwrap();
+        assert_eq!(local.crt_expiry(), Some(second.expiry()));
+        assert!(Arc::ptr_eq(
+            &tls::accept::HasConfig::tls_server_config(&local),
+            &second.tls_server_config()
+        ));
+        assert!(Arc::ptr_eq(
+            &tls::client::HasConfig::tls_client_config(&local),
+            &second.tls_client_config()
+        ));
+    }
+
+    #[test]
+    fn rotations_exclude_the_first_crt() {
+        let (local, mut crt_key) = local();
+        let mut rotations = local.rotations();
+        future::lazy(move || {
+            assert!(rotations.poll().unwrap().is_not_ready());
+
+            crt_key
+                .broadcast(Some(FOO_NS1.validate().expect("valid crt")))
+                .unwrap();
+            assert!(
+                rotations.poll().unwrap().is_not_ready(),
+                "the first certificate must not be a rotation"
+            );
+
+            crt_key
+                .broadcast(Some(FOO_NS1.validate().expect("valid crt")))
+                .unwrap();
+            assert_eq!(rotations.poll().unwrap(), Async::Ready(Some(())));
+            assert!(rotations.poll().unwrap().is_not_ready());
+
+            drop(crt_key);
+            assert_eq!(rotations.poll().unwrap(), Async::Ready(None));
+            Ok::<_, ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+
+    #[test]
+    fn rotations_begin_at_the_current_crt() {
+        let (local, mut crt_key) = local();
+        crt_key
+            .broadcast(Some(FOO_NS1.validate().expect("valid crt")))
+            .unwrap();
+        let mut rotations = local.rotations();
+        future::lazy(move || {
+            assert!(rotations.poll().unwrap().is_not_ready());
+
+            crt_key
+                .broadcast(Some(FOO_NS1.validate().expect("valid crt")))
+                .unwrap();
+            assert_eq!(rotations.poll().unwrap(), Async::Ready(Some(())));
+            Ok::<_, ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+}
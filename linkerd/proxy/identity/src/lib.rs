@@ -2,5 +2,5 @@
 
 pub mod certify;
 
-pub use self::certify::{AwaitCrt, CrtKeySender, Local};
+pub use self::certify::{AwaitCrt, CrtKeySender, Local, Rotations};
 pub use linkerd2_identity::{Crt, CrtKey, Csr, InvalidName, Key, Name, TokenSource, TrustAnchors};
@@ -100,20 +100,38 @@ where
     type Error = R::Error;
 
     fn poll(&mut self) -> Poll<resolve::Update<M::Out>, Self::Error> {
-        let update = match try_ready!(self.resolution.poll()) {
-            resolve::Update::Add(eps) => resolve::Update::Add(
-                eps.into_iter()
-                    .map(|(a, ep)| {
-                        let ep = self.map.map_endpoint(&self.target, a, ep);
-                        (a, ep)
-                    })
-                    .collect(),
-            ),
+        let update = try_ready!(self.resolution.poll());
+        Ok(self.map_update(update).into())
+    }
+
+    fn poll_tagged(&mut self) -> Poll<resolve::Tagged<M::Out>, Self::Error> {
+        let resolve::Tagged { generation, update } = try_ready!(self.resolution.poll_tagged());
+        let update = self.map_update(update);
+        Ok(resolve::Tagged { generation, update }.into())
+    }
+}
+
+impl<T, M, R> Resolution<T, M, R>
+where
+    R: resolve::Resolution,
+    M: MapEndpoint<T, R::Endpoint>,
+{
+    fn map_update(&self, update: resolve::Update<R::Endpoint>) -> resolve::Update<M::Out> {
+        let map = |eps: Vec<(SocketAddr, R::Endpoint)>| {
+            eps.into_iter()
+                .map(|(a, ep)| {
+                    let ep = self.map.map_endpoint(&self.target, a, ep);
+                    (a, ep)
+                })
+                .collect()
+        };
+        match update {
+            resolve::Update::Add(eps) => resolve::Update::Add(map(eps)),
+            resolve::Update::Reset(eps) => resolve::Update::Reset(map(eps)),
             resolve::Update::Remove(addrs) => resolve::Update::Remove(addrs),
             resolve::Update::DoesNotExist => resolve::Update::DoesNotExist,
             resolve::Update::Empty => resolve::Update::Empty,
-        };
-        Ok(update.into())
+        }
     }
 }
 
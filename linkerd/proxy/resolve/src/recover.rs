@@ -1,10 +1,8 @@
 //! A middleware that recovers a resolution after some failures.
 
 use futures::{try_ready, Async, Future, Poll, Stream};
-use indexmap::IndexMap;
 use linkerd2_error::{Error, Recover};
-use linkerd2_proxy_core::resolve::{self, Resolution as _, Update};
-use std::net::SocketAddr;
+use linkerd2_proxy_core::resolve::{self, Resolution as _, Tagged, Update};
 
 #[derive(Clone, Debug)]
 pub struct Resolve<E, R> {
@@ -16,10 +14,15 @@ pub struct ResolveFuture<T, E: Recover, R: resolve::Resolve<T>> {
     inner: Option<Inner<T, E, R>>,
 }
 
+/// A resolution that is re-established when it fails.
+///
+/// Each connection begins a new generation, and its first update is
+/// advertised as a snapshot of the complete endpoint set, so that consumers
+/// can reconcile their state against it and discard any updates from prior
+/// connections.
 pub struct Resolution<T, E: Recover, R: resolve::Resolve<T>> {
     inner: Inner<T, E, R>,
-    cache: IndexMap<SocketAddr, R::Endpoint>,
-    reconcile: Option<Update<R::Endpoint>>,
+    generation: u64,
 }
 
 struct Inner<T, E: Recover, R: resolve::Resolve<T>> {
@@ -29,11 +32,6 @@ struct Inner<T, E: Recover, R: resolve::Resolve<T>> {
     state: State<R::Future, R::Resolution, E::Backoff>,
 }
 
-// #[derive(Debug)]
-// struct Cache<T> {
-//     active: IndexMap<SocketAddr, T>,
-// }
-
 enum State<F, R: resolve::Resolution, B> {
     Disconnected {
         backoff: Option<B>,
@@ -127,9 +125,7 @@ where
 
         Ok(Async::Ready(Resolution {
             inner: self.inner.take().expect("polled after complete"),
-            cache: IndexMap::default(),
-            //cache: Cache::default(),
-            reconcile: None,
+            generation: 0,
         }))
     }
 }
@@ -147,14 +143,12 @@ where
     type Error = Error;
 
     fn poll(&mut self) -> Poll<Update<Self::Endpoint>, Self::Error> {
-        loop {
-            // If a reconciliation update is buffered (i.e. after
-            // reconcile_after_reconnect), process it immediately.
-            if let Some(update) = self.reconcile.take() {
-                self.update_active(&update);
-                return Ok(update.into());
-            }
+        let Tagged { update, .. } = try_ready!(self.poll_tagged());
+        Ok(update.into())
+    }
 
+    fn poll_tagged(&mut self) -> Poll<Tagged<Self::Endpoint>, Self::Error> {
+        loop {
             if let State::Connected {
                 ref mut resolution,
                 ref mut initial,
@@ -162,28 +156,29 @@ where
             {
                 // XXX Due to linkerd/linkerd2#3362, errors can't be discovered
                 // eagerly, so we must potentially read the first update to be
-                // sure it didn't fail. If that's the case, then reconcile the
-                // cache against the initial update.
+                // sure it didn't fail. The initial update begins a new
+                // generation, describing the complete state of the
+                // resolution.
                 if let Some(initial) = initial.take() {
-                    // The initial state afer a reconnect may be identitical to
-                    // the prior state, and so there may be no updates to
-                    // advertise.
-                    if let Some((update, reconcile)) = reconcile_after_connect(&self.cache, initial)
-                    {
-                        self.reconcile = reconcile;
-                        self.update_active(&update);
-                        return Ok(update.into());
+                    self.generation += 1;
+                    return Ok(Tagged {
+                        generation: self.generation,
+                        update: snapshot(initial),
                     }
+                    .into());
                 }
 
-                // Process the resolution stream, updating the cache.
+                // Process the resolution stream.
                 //
                 // Attempt recovery/backoff if the resolution fails.
                 match resolve::Resolution::poll(resolution) {
                     Ok(Async::NotReady) => return Ok(Async::NotReady),
                     Ok(Async::Ready(update)) => {
-                        self.update_active(&update);
-                        return Ok(update.into());
+                        return Ok(Tagged {
+                            generation: self.generation,
+                            update,
+                        }
+                        .into());
                     }
                     Err(e) => {
                         self.inner.state = State::Recover {
@@ -199,30 +194,6 @@ where
     }
 }
 
-impl<T, E, R> Resolution<T, E, R>
-where
-    T: Clone,
-    R: resolve::Resolve<T>,
-    R::Endpoint: Clone + PartialEq,
-    E: Recover,
-{
-    fn update_active(&mut self, update: &Update<R::Endpoint>) {
-        match update {
-            Update::Add(ref endpoints) => {
-                self.cache.extend(endpoints.clone());
-            }
-            Update::Remove(ref addrs) => {
-                for addr in addrs.iter() {
-                    self.cache.remove(addr);
-                }
-            }
-            Update::DoesNotExist | Update::Empty => {
-                self.cache.drain(..);
-            }
-        }
-    }
-}
-
 // === impl Inner ===
 
 impl<T, E, R> Inner<T, E, R>
@@ -323,64 +294,26 @@ where
     }
 }
 
-/// Computes the updates needed after a connection is (re-)established.
+/// Converts the first update after a connection is (re-)established into a
+/// snapshot of the complete endpoint set.
 // Raw fn for easier testing.
-fn reconcile_after_connect<E: PartialEq>(
-    cache: &IndexMap<SocketAddr, E>,
-    initial: Update<E>,
-) -> Option<(Update<E>, Option<Update<E>>)> {
+fn snapshot<E>(initial: Update<E>) -> Update<E> {
     match initial {
-        // When the first update after a disconnect is an Add, it should
-        // contain the new state of the replica set.
-        Update::Add(endpoints) => {
-            let mut new_eps = endpoints.into_iter().collect::<IndexMap<_, _>>();
-            let mut rm_addrs = Vec::with_capacity(cache.len());
-            for (addr, endpoint) in cache.iter() {
-                match new_eps.get(addr) {
-                    // If the endpoint is in the active set and not in
-                    // the new set, it needs to be removed.
-                    None => {
-                        rm_addrs.push(*addr);
-                    }
-                    // If the endpoint is already in the active set,
-                    // remove it from the new set (to avoid rebuilding
-                    // services unnecessarily).
-                    Some(ep) => {
-                        // The endpoints must be identitical, though.
-                        if *ep == *endpoint {
-                            new_eps.remove(addr);
-                        }
-                    }
-                }
-            }
-            let add = if new_eps.is_empty() {
-                None
-            } else {
-                Some(Update::Add(new_eps.into_iter().collect()))
-            };
-            let rm = if rm_addrs.is_empty() {
-                None
-            } else {
-                Some(Update::Remove(rm_addrs))
-            };
-            // Advertise adds before removes so that we don't unnecessarily
-            // empty out a consumer.
-            match add {
-                Some(add) => Some((add, rm)),
-                None => rm.map(|rm| (rm, None)),
-            }
-        }
+        // When the first update after a connect is an Add, it contains the
+        // new state of the replica set.
+        Update::Add(endpoints) | Update::Reset(endpoints) => Update::Reset(endpoints),
         // It would be exceptionally odd to get a remove, specifically,
-        // immediately after a reconnect, but it seems appropriate to
-        // handle it as Empty.
-        Update::Remove(..) | Update::Empty => Some((Update::Empty, None)),
-        Update::DoesNotExist => Some((Update::DoesNotExist, None)),
+        // immediately after a connect, but it seems appropriate to handle it
+        // as Empty.
+        Update::Remove(..) | Update::Empty => Update::Empty,
+        Update::DoesNotExist => Update::DoesNotExist,
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::net::SocketAddr;
 
     pub fn addr0() -> SocketAddr {
         ([198, 51, 100, 1], 8080).into()
@@ -391,98 +324,26 @@ mod tests {
     }
 
     #[test]
-    fn reconcile_after_initial_connect() {
-        let cache = IndexMap::default();
-        let add = Update::Add(vec![(addr0(), 0), (addr1(), 0)]);
+    fn initial_update_is_a_snapshot() {
+        let eps = vec![(addr0(), 0), (addr1(), 0)];
         assert_eq!(
-            reconcile_after_connect(&cache, add.clone()),
-            Some((add, None)),
-            "Adds should be passed through initially"
+            snapshot(Update::Add(eps.clone())),
+            Update::Reset(eps),
+            "Adds should replace all endpoints"
         );
         assert_eq!(
-            reconcile_after_connect(&cache, Update::Remove(vec![addr0(), addr1()])),
-            Some((Update::Empty, None)),
-            "Removes should be treated as empty"
-        );
-        assert_eq!(
-            reconcile_after_connect(&cache, Update::Empty),
-            Some((Update::Empty, None)),
-            "Empties should be passed through"
-        );
-        assert_eq!(
-            reconcile_after_connect(&cache, Update::DoesNotExist),
-            Some((Update::DoesNotExist, None)),
-            "DNEs should be passed through"
-        );
-    }
-
-    #[test]
-    fn reconcile_after_reconnect_dedupes() {
-        let mut cache = IndexMap::new();
-        cache.insert(addr0(), 0);
-
-        assert_eq!(
-            reconcile_after_connect(&cache, Update::Add(vec![(addr0(), 0), (addr1(), 0)])),
-            Some((Update::Add(vec![(addr1(), 0)]), None)),
-        );
-    }
-
-    #[test]
-    fn reconcile_after_reconnect_updates() {
-        let mut cache = IndexMap::new();
-        cache.insert(addr0(), 0);
-
-        assert_eq!(
-            reconcile_after_connect(&cache, Update::Add(vec![(addr0(), 1), (addr1(), 0)])),
-            Some((Update::Add(vec![(addr0(), 1), (addr1(), 0)]), None)),
-        );
-    }
-
-    #[test]
-    fn reconcile_after_reconnect_removes() {
-        let mut cache = IndexMap::new();
-        cache.insert(addr0(), 0);
-        cache.insert(addr1(), 0);
-
-        assert_eq!(
-            reconcile_after_connect(&cache, Update::Add(vec![(addr0(), 0)])),
-            Some((Update::Remove(vec![addr1()]), None))
-        );
-    }
-
-    #[test]
-    fn reconcile_after_reconnect_adds_and_removes() {
-        let mut cache = IndexMap::new();
-        cache.insert(addr0(), 0);
-        cache.insert(addr1(), 0);
-
-        assert_eq!(
-            reconcile_after_connect(&cache, Update::Add(vec![(addr0(), 1)])),
-            Some((
-                Update::Add(vec![(addr0(), 1)]),
-                Some(Update::Remove(vec![addr1()]))
-            ))
-        );
-    }
-
-    #[test]
-    fn reconcile_after_reconnect_passthru() {
-        let mut cache = IndexMap::default();
-        cache.insert(addr0(), 0);
-
-        assert_eq!(
-            reconcile_after_connect(&cache, Update::Remove(vec![addr1()])),
-            Some((Update::Empty, None)),
+            snapshot(Update::Remove(vec![addr0(), addr1()])),
+            Update::<()>::Empty,
             "Removes should be treated as empty"
         );
         assert_eq!(
-            reconcile_after_connect(&cache, Update::Empty),
-            Some((Update::Empty, None)),
+            snapshot(Update::<()>::Empty),
+            Update::Empty,
             "Empties should be passed through"
         );
         assert_eq!(
-            reconcile_after_connect(&cache, Update::DoesNotExist),
-            Some((Update::DoesNotExist, None)),
+            snapshot(Update::<()>::DoesNotExist),
+            Update::DoesNotExist,
             "DNEs should be passed through"
         );
     }
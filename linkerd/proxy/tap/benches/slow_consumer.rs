@@ -0,0 +1,199 @@
+//! Compares the latency of a tapped service with no tap subscriber, with a
+//! subscriber that reads its events as they are emitted, and with a
+//! subscriber that never reads its events.
+//!
+//! A stalled subscriber must not hold up tapped requests, so this fails if
+//! requests are more than `MAX_STALLED_SLOWDOWN` times slower with a stalled
+//! subscriber than with no subscriber at all.
+//!
+//! Run with `cargo bench -p linkerd2-proxy-tap`.
+
+#![deny(warnings, rust_2018_idioms)]
+
+use futures::{future, Future, Stream};
+use hyper::Body;
+use indexmap::IndexMap;
+use linkerd2_conditional::Conditional;
+use linkerd2_error::Error;
+use linkerd2_identity as identity;
+use linkerd2_proxy_api::tap::{self as pb, server::Tap as _};
+use linkerd2_proxy_tap as tap;
+use linkerd2_proxy_transport::tls::ReasonForNoIdentity;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tower::{layer::Layer as _, Service as _};
+
+const REQUESTS: u32 = 100_000;
+
+const MAX_STALLED_SLOWDOWN: f64 = 5.0;
+
+#[derive(Copy, Clone, Debug)]
+enum Subscriber {
+    None,
+    Fast,
+    Stalled,
+}
+
+#[derive(Clone, Debug)]
+struct Target(SocketAddr);
+
+#[derive(Clone, Debug)]
+struct MakeSvc;
+
+#[derive(Clone, Debug)]
+struct Svc;
+
+fn main() {
+    let baseline = measure(Subscriber::None);
+    let fast = measure(Subscriber::Fast);
+    let stalled = measure(Subscriber::Stalled);
+
+    println!("no subscriber:      {:?}/request", baseline);
+    println!("fast subscriber:    {:?}/request", fast);
+    println!("stalled subscriber: {:?}/request", stalled);
+
+    let slowdown = stalled.as_nanos() as f64 / baseline.as_nanos() as f64;
+    assert!(
+        slowdown <= MAX_STALLED_SLOWDOWN,
+        "a stalled subscriber slowed requests by {:.1}x",
+        slowdown
+    );
+}
+
+/// Returns the mean latency of requests through a tapped service.
+fn measure(subscriber: Subscriber) -> Duration {
+    let mut rt = tokio::runtime::Runtime::new().expect("runtime must start");
+    let (layer, mut server, daemon) = tap::new();
+    rt.spawn(daemon.map_err(|_| ()));
+
+    // Register the service before subscribing so that it is notified of the
+    // tap before the subscription completes.
+    let mut svc = layer
+        .layer(MakeSvc)
+        .call(Target(([10, 0, 0, 1], 8080).into()))
+        .wait()
+        .expect("service must be built");
+
+    // Holds the stalled subscriber's stream, which is never read.
+    let _stalled = match subscriber {
+        Subscriber::None => None,
+        Subscriber::Fast => {
+            let events = subscribe(&mut server);
+            rt.spawn(events.for_each(|_| Ok(())).map_err(|_| ()));
+            None
+        }
+        Subscriber::Stalled => Some(subscribe(&mut server)),
+    };
+
+    let start = Instant::now();
+    for _ in 0..REQUESTS {
+        future::poll_fn(|| svc.poll_ready())
+            .wait()
+            .expect("service must become ready");
+        let req = http::Request::get("http://bench.test.svc.cluster.local/")
+            .body(Body::empty())
+            .expect("request must be valid");
+        svc.call(req).wait().expect("request must succeed");
+    }
+    start.elapsed() / REQUESTS
+}
+
+/// Taps all requests, including their headers.
+fn subscribe(server: &mut tap::Server) -> <tap::Server as pb::server::Tap>::ObserveStream {
+    use pb::observe_request::{extract, r#match, Extract, Match};
+
+    let req = pb::ObserveRequest {
+        limit: REQUESTS,
+        r#match: Some(Match {
+            r#match: Some(r#match::Match::Http(r#match::Http {
+                r#match: Some(r#match::http::Match::Path(r#match::http::StringMatch {
+                    r#match: Some(r#match::http::string_match::Match::Prefix("/".to_string())),
+                })),
+            })),
+        }),
+        extract: Some(Extract {
+            extract: Some(extract::Extract::Http(extract::Http {
+                extract: Some(extract::http::Extract::Headers(
+                    extract::http::Headers::default(),
+                )),
+            })),
+        }),
+    };
+
+    server
+        .observe(tower_grpc::Request::new(req))
+        .wait()
+        .expect("tap must subscribe")
+        .into_inner()
+}
+
+// === impl Target ===
+
+impl tap::Inspect for Target {
+    fn src_addr<B>(&self, _: &http::Request<B>) -> Option<SocketAddr> {
+        Some(([10, 0, 0, 2], 40000).into())
+    }
+
+    fn src_tls<'a, B>(
+        &self,
+        _: &'a http::Request<B>,
+    ) -> Conditional<&'a identity::Name, ReasonForNoIdentity> {
+        Conditional::None(ReasonForNoIdentity::Disabled)
+    }
+
+    fn dst_addr<B>(&self, _: &http::Request<B>) -> Option<SocketAddr> {
+        Some(self.0)
+    }
+
+    fn dst_labels<B>(&self, _: &http::Request<B>) -> Option<&IndexMap<String, String>> {
+        None
+    }
+
+    fn dst_tls<B>(
+        &self,
+        _: &http::Request<B>,
+    ) -> Conditional<&identity::Name, ReasonForNoIdentity> {
+        Conditional::None(ReasonForNoIdentity::Disabled)
+    }
+
+    fn route_labels<B>(&self, _: &http::Request<B>) -> Option<Arc<IndexMap<String, String>>> {
+        None
+    }
+
+    fn is_outbound<B>(&self, _: &http::Request<B>) -> bool {
+        false
+    }
+}
+
+// === impl MakeSvc ===
+
+impl tower::Service<Target> for MakeSvc {
+    type Response = Svc;
+    type Error = Error;
+    type Future = future::FutureResult<Svc, Error>;
+
+    fn poll_ready(&mut self) -> futures::Poll<(), Self::Error> {
+        Ok(().into())
+    }
+
+    fn call(&mut self, _: Target) -> Self::Future {
+        future::ok(Svc)
+    }
+}
+
+// === impl Svc ===
+
+impl<B> tower::Service<http::Request<B>> for Svc {
+    type Response = http::Response<Body>;
+    type Error = Error;
+    type Future = future::FutureResult<Self::Response, Error>;
+
+    fn poll_ready(&mut self) -> futures::Poll<(), Self::Error> {
+        Ok(().into())
+    }
+
+    fn call(&mut self, _: http::Request<B>) -> Self::Future {
+        future::ok(http::Response::new(Body::empty()))
+    }
+}
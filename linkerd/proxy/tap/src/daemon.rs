@@ -2,11 +2,16 @@ use super::iface::Tap;
 use futures::sync::{mpsc, oneshot};
 use futures::{try_ready, Async, Future, Poll, Stream};
 use linkerd2_error::Never;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
 use tracing::{debug, trace, warn};
 
 pub fn new<T>() -> (Daemon<T>, Register<T>, Subscribe<T>) {
     let (svc_tx, svc_rx) = mpsc::channel(super::REGISTER_CHANNEL_CAPACITY);
     let (tap_tx, tap_rx) = mpsc::channel(super::TAP_CAPACITY);
+    let active = Arc::new(AtomicUsize::new(0));
 
     let daemon = Daemon {
         svc_rx,
@@ -14,9 +19,11 @@ pub fn new<T>() -> (Daemon<T>, Register<T>, Subscribe<T>) {
 
         tap_rx,
         taps: Vec::default(),
+        active: active.clone(),
     };
 
-    (daemon, Register(svc_tx), Subscribe(tap_tx))
+    let register = Register { svc_tx, active };
+    (daemon, register, Subscribe(tap_tx))
 }
 
 /// A background task that connects a tap server and proxy services.
@@ -32,10 +39,27 @@ pub struct Daemon<T> {
 
     tap_rx: mpsc::Receiver<(T, oneshot::Sender<()>)>,
     taps: Vec<T>,
+
+    /// The number of active taps, shared with all registered services so
+    /// that they need not poll for taps while there are none.
+    ///
+    /// This is only updated when the daemon is polled, so it may remain
+    /// non-zero for a time after the last tap completes.
+    active: Arc<AtomicUsize>,
 }
 
 #[derive(Debug)]
-pub struct Register<T>(mpsc::Sender<mpsc::Sender<T>>);
+pub struct Register<T> {
+    svc_tx: mpsc::Sender<mpsc::Sender<T>>,
+    active: Arc<AtomicUsize>,
+}
+
+/// The taps advertised to a single registered service.
+#[derive(Debug)]
+pub struct Taps<T> {
+    rx: mpsc::Receiver<T>,
+    active: Arc<AtomicUsize>,
+}
 
 #[derive(Debug)]
 pub struct Subscribe<T>(mpsc::Sender<(T, oneshot::Sender<()>)>);
@@ -61,6 +85,7 @@ impl<T: Tap> Future for Daemon<T> {
         // been dropped).
         let tap_count = self.taps.len();
         self.taps.retain(|t| t.can_tap_more());
+        self.active.store(self.taps.len(), Ordering::Relaxed);
         trace!("retained {} of {} taps", self.taps.len(), tap_count);
 
         // Drop services that are no longer active.
@@ -126,6 +151,7 @@ impl<T: Tap> Future for Daemon<T> {
             }
 
             self.taps.push(tap);
+            self.active.store(self.taps.len(), Ordering::Relaxed);
             let _ = ack.send(());
             trace!("tap subscribed");
         }
@@ -136,20 +162,41 @@ impl<T: Tap> Future for Daemon<T> {
 
 impl<T: Tap> Clone for Register<T> {
     fn clone(&self) -> Self {
-        Register(self.0.clone())
+        Register {
+            svc_tx: self.svc_tx.clone(),
+            active: self.active.clone(),
+        }
     }
 }
 
 impl<T: Tap> super::iface::Register for Register<T> {
     type Tap = T;
-    type Taps = mpsc::Receiver<T>;
+    type Taps = Taps<T>;
 
     fn register(&mut self) -> Self::Taps {
         let (tx, rx) = mpsc::channel(super::TAP_CAPACITY);
-        if let Err(_) = self.0.try_send(tx) {
+        if let Err(_) = self.svc_tx.try_send(tx) {
             debug!("failed to register service");
         }
-        rx
+        Taps {
+            rx,
+            active: self.active.clone(),
+        }
+    }
+}
+
+impl<T> Stream for Taps<T> {
+    type Item = T;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<T>, ()> {
+        self.rx.poll()
+    }
+}
+
+impl<T> super::iface::Taps for Taps<T> {
+    fn is_idle(&self) -> bool {
+        self.active.load(Ordering::Relaxed) == 0
     }
 }
 
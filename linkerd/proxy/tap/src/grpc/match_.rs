@@ -93,6 +93,17 @@ impl Match {
             Match::Http(ref http) => http.matches(req, inspect),
         }
     }
+
+    /// Returns `true` if this match only matches requests with a given route
+    /// label.
+    pub fn is_route_scoped(&self) -> bool {
+        match self {
+            Match::RouteLabel(_) => true,
+            Match::All(ref ms) => ms.iter().any(Match::is_route_scoped),
+            Match::Any(ref ms) => !ms.is_empty() && ms.iter().all(Match::is_route_scoped),
+            _ => false,
+        }
+    }
 }
 
 impl Match {
@@ -4,17 +4,29 @@ use bytes::Buf;
 use futures::sync::mpsc;
 use futures::{future, Async, Future, Poll, Stream};
 use hyper::body::Payload;
+use indexmap::IndexMap;
 use linkerd2_conditional::Conditional;
+use linkerd2_identity as identity;
 use linkerd2_proxy_api::{http_types, pb_duration, tap as api};
 use linkerd2_proxy_http::HasH2Reason;
+use linkerd2_proxy_transport::tls::ReasonForNoIdentity;
 use std::convert::TryFrom;
 use std::iter;
+use std::net::SocketAddr;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Weak};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use tokio_timer::clock;
 use tower_grpc::{self as grpc, Response};
-use tracing::{debug, trace, warn};
+use tracing::{debug, info, trace, warn};
+
+/// The request metadata key that scopes a tap to a sample of a route's
+/// requests.
+///
+/// Its value, `N`, causes only one of every `N` requests that match the tap
+/// to be tapped. The tap's match must select a route label, so that the
+/// sample describes a single route.
+const ROUTE_SAMPLE_METADATA: &str = "l5d-tap-route-sample";
 
 #[derive(Clone, Debug)]
 pub struct Server<T> {
@@ -25,14 +37,16 @@ pub struct Server<T> {
 #[derive(Debug)]
 pub struct ResponseFuture<F> {
     subscribe: F,
-    events_rx: Option<mpsc::Receiver<api::TapEvent>>,
+    events_rx: Option<mpsc::Receiver<Event>>,
     shared: Option<Arc<Shared>>,
 }
 
 #[derive(Debug)]
 pub struct ResponseStream {
-    events_rx: mpsc::Receiver<api::TapEvent>,
+    base_id: u32,
+    events_rx: mpsc::Receiver<Event>,
     shared: Option<Arc<Shared>>,
+    dropped: Arc<AtomicUsize>,
 }
 
 #[derive(Debug)]
@@ -41,14 +55,26 @@ struct Shared {
     count: AtomicUsize,
     limit: usize,
     match_: Match,
+    sample: Option<Sample>,
     extract: ExtractKind,
-    events_tx: mpsc::Sender<api::TapEvent>,
+    events_tx: mpsc::Sender<Event>,
+    dropped: Arc<AtomicUsize>,
+}
+
+/// Selects one of every `every` requests that match a route-scoped tap.
+#[derive(Debug)]
+struct Sample {
+    every: usize,
+    matched: AtomicUsize,
 }
 
 #[derive(Clone, Debug)]
 struct TapTx {
     id: api::tap_event::http::StreamId,
-    tx: mpsc::Sender<api::TapEvent>,
+    base: Arc<BaseEvent>,
+    tx: mpsc::Sender<Event>,
+    /// Counts the events that could not be sent on this tap's subscription.
+    dropped: Arc<AtomicUsize>,
 }
 
 #[derive(Clone, Debug)]
@@ -58,7 +84,6 @@ pub struct Tap {
 
 #[derive(Debug)]
 pub struct TapResponse {
-    base_event: api::TapEvent,
     request_init_at: Instant,
     /// Should headers be extracted?
     extract_headers: bool,
@@ -67,13 +92,11 @@ pub struct TapResponse {
 
 #[derive(Debug)]
 pub struct TapRequestPayload {
-    base_event: api::TapEvent,
     tap: TapTx,
 }
 
 #[derive(Debug)]
 pub struct TapResponsePayload {
-    base_event: api::TapEvent,
     request_init_at: Instant,
     response_init_at: Instant,
     response_bytes: usize,
@@ -84,6 +107,56 @@ pub struct TapResponsePayload {
     grpc_status: Option<u32>,
 }
 
+/// A tap event, as it is recorded by a tapped request.
+///
+/// Events hold the request's data as-is. They are only converted to protobuf
+/// as the subscription's response stream is read, so that this work is done
+/// on behalf of the tap's consumer rather than on the tapped request's task.
+#[derive(Debug)]
+struct Event {
+    id: api::tap_event::http::StreamId,
+    base: Arc<BaseEvent>,
+    kind: EventKind,
+}
+
+#[derive(Debug)]
+enum EventKind {
+    RequestInit {
+        method: http::Method,
+        uri: http::Uri,
+        version: http::Version,
+        authority: String,
+        headers: Option<http::HeaderMap>,
+    },
+    ResponseInit {
+        since_request_init: Duration,
+        status: http::StatusCode,
+        version: http::Version,
+        headers: Option<http::HeaderMap>,
+    },
+    ResponseEnd {
+        since_request_init: Duration,
+        since_response_init: Option<Duration>,
+        response_bytes: u64,
+        end: Option<api::eos::End>,
+        trailers: Option<http::HeaderMap>,
+    },
+}
+
+/// The metadata common to all of a tapped request's events.
+#[derive(Debug)]
+struct BaseEvent {
+    is_outbound: bool,
+    source: Option<SocketAddr>,
+    source_tls: Conditional<identity::Name, ReasonForNoIdentity>,
+    destination: Option<SocketAddr>,
+    destination_meta: Option<(
+        IndexMap<String, String>,
+        Conditional<identity::Name, ReasonForNoIdentity>,
+    )>,
+    route_labels: Option<Arc<IndexMap<String, String>>>,
+}
+
 /// Indicates what tap data should be extracted from traffic.
 ///
 /// This is constructed from the protobuf `Extract` message, and represents the
@@ -118,6 +191,19 @@ where
     >;
 
     fn observe(&mut self, req: grpc::Request<api::ObserveRequest>) -> Self::ObserveFuture {
+        let sample_every = match req.metadata().get(ROUTE_SAMPLE_METADATA) {
+            None => None,
+            Some(v) => match v.to_str().ok().and_then(|v| v.parse::<usize>().ok()) {
+                Some(every) if every > 0 => Some(every),
+                _ => {
+                    let err = Self::invalid_arg(format!(
+                        "{} must be a positive integer",
+                        ROUTE_SAMPLE_METADATA
+                    ));
+                    return future::Either::A(future::err(err));
+                }
+            },
+        };
         let req = req.into_inner();
 
         let limit = req.limit as usize;
@@ -141,6 +227,17 @@ where
             }
         };
 
+        let sample = match sample_every {
+            Some(_) if !match_.is_route_scoped() => {
+                let err = Self::invalid_arg(format!(
+                    "{} requires a route label match",
+                    ROUTE_SAMPLE_METADATA
+                ));
+                return future::Either::A(future::err(err));
+            }
+            every => every.map(Sample::new),
+        };
+
         let extract = req
             .extract
             .and_then(|ex| ExtractKind::try_from(ex).ok())
@@ -154,7 +251,7 @@ where
         // Wrapping is okay. This is realy just to disambiguate events within a
         // single tap session (i.e. that may consist of several tap requests).
         let base_id = self.base_id.fetch_add(1, Ordering::Relaxed) as u32;
-        debug!(id = ?base_id, r#match = ?match_, ?extract, "tap;");
+        debug!(id = ?base_id, r#match = ?match_, ?sample, ?extract, "tap;");
 
         // The events channel is used to emit tap events to the response stream.
         //
@@ -162,6 +259,9 @@ where
         // requests. Each tapped request's sender is dropped when the response
         // completes, so the event stream closes gracefully when all tapped
         // requests are completed without additional coordination.
+        //
+        // Tapped requests never wait for capacity on this channel: when the
+        // consumer falls behind, events are dropped and counted instead.
         let (events_tx, events_rx) =
             mpsc::channel(super::super::PER_RESPONSE_EVENT_BUFFER_CAPACITY);
 
@@ -170,8 +270,10 @@ where
             count: AtomicUsize::new(0),
             limit,
             match_,
+            sample,
             extract,
             events_tx,
+            dropped: Arc::new(AtomicUsize::new(0)),
         });
 
         let tap = Tap {
@@ -206,8 +308,11 @@ impl<F: Future<Item = ()>> Future for ResponseFuture<F> {
             }
         }
 
+        let shared = self.shared.take().expect("shared must be set");
         let rsp = ResponseStream {
-            shared: self.shared.take(),
+            base_id: shared.base_id,
+            dropped: shared.dropped.clone(),
+            shared: Some(shared),
             events_rx: self.events_rx.take().expect("events_rx must be set"),
         };
 
@@ -217,6 +322,14 @@ impl<F: Future<Item = ()>> Future for ResponseFuture<F> {
 
 // === impl ResponseStream ===
 
+impl ResponseStream {
+    /// Returns the number of events that have been dropped because this
+    /// stream was not read quickly enough.
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
 impl Stream for ResponseStream {
     type Item = api::TapEvent;
     type Error = grpc::Status;
@@ -237,7 +350,21 @@ impl Stream for ResponseStream {
 
         // Read events from taps. The receiver can't actually error, but we need
         // to satisfy the type signature, so we coerce errors into EOS.
-        self.events_rx.poll().or_else(|_| Ok(None.into()))
+        let event = match self.events_rx.poll() {
+            Ok(Async::Ready(event)) => event,
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(()) => None,
+        };
+        Ok(event.map(Event::into_pb).into())
+    }
+}
+
+impl Drop for ResponseStream {
+    fn drop(&mut self) {
+        let dropped = self.dropped();
+        if dropped > 0 {
+            info!(id = ?self.base_id, dropped = dropped, "tap consumer could not keep up; events dropped");
+        }
     }
 }
 
@@ -247,6 +374,31 @@ impl Shared {
     fn is_under_limit(&self) -> bool {
         self.count.load(Ordering::Relaxed) < self.limit
     }
+
+    /// Returns `true` if a request that matches this tap should be tapped.
+    fn matches<B, I: Inspect>(&self, req: &http::Request<B>, inspect: &I) -> bool {
+        if !self.match_.matches(req, inspect) {
+            return false;
+        }
+
+        self.sample.as_ref().map(Sample::select).unwrap_or(true)
+    }
+}
+
+// === impl Sample ===
+
+impl Sample {
+    fn new(every: usize) -> Self {
+        Self {
+            every,
+            matched: AtomicUsize::new(0),
+        }
+    }
+
+    /// Counts a matching request, returning `true` if it is sampled.
+    fn select(&self) -> bool {
+        self.matched.fetch_add(1, Ordering::Relaxed) % self.every == 0
+    }
 }
 
 // === impl Tap ===
@@ -273,7 +425,7 @@ impl iface::Tap for Tap {
         I: Inspect,
     {
         let shared = self.shared.upgrade()?;
-        if !shared.match_.matches(req, inspect) {
+        if !shared.matches(req, inspect) {
             return None;
         }
 
@@ -295,81 +447,36 @@ impl iface::Tap for Tap {
                 return None;
             }
         };
-        let mut events_tx = shared.events_tx.clone();
 
         let request_init_at = clock::now();
 
-        let base_event = base_event(req, inspect);
-
-        let authority = inspect.authority(req).unwrap_or_default();
-
-        let headers = if extract_headers {
-            let headers = if req.version() == http::Version::HTTP_2 {
-                // If the request is HTTP/2, add the pseudo-header fields to the
-                // headers.
-                let pseudos = vec![
-                    http_types::headers::Header {
-                        name: ":method".to_owned(),
-                        value: req.method().as_str().as_bytes().into(),
-                    },
-                    http_types::headers::Header {
-                        name: ":scheme".to_owned(),
-                        value: req
-                            .uri()
-                            .scheme_str()
-                            .map(|s| s.as_bytes().into())
-                            .unwrap_or_default(),
-                    },
-                    http_types::headers::Header {
-                        name: ":authority".to_owned(),
-                        value: authority.as_bytes().into(),
-                    },
-                    http_types::headers::Header {
-                        name: ":path".to_owned(),
-                        value: req
-                            .uri()
-                            .path_and_query()
-                            .map(|p| p.as_str().as_bytes().into())
-                            .unwrap_or_default(),
-                    },
-                ];
-                headers_to_pb(pseudos, req.headers())
-            } else {
-                headers_to_pb(iter::empty(), req.headers())
-            };
-            Some(headers)
-        } else {
-            None
+        let mut tap = TapTx {
+            id,
+            base: Arc::new(BaseEvent::new(req, inspect)),
+            tx: shared.events_tx.clone(),
+            dropped: shared.dropped.clone(),
         };
 
-        let init = api::tap_event::http::RequestInit {
-            id: Some(id.clone()),
-            method: Some(req.method().into()),
-            scheme: req.uri().scheme_part().map(http_types::Scheme::from),
-            authority,
-            path: req.uri().path().into(),
-            headers,
-        };
-
-        let event = api::TapEvent {
-            event: Some(api::tap_event::Event::Http(api::tap_event::Http {
-                event: Some(api::tap_event::http::Event::RequestInit(init)),
-            })),
-            ..base_event.clone()
+        let init = EventKind::RequestInit {
+            method: req.method().clone(),
+            uri: req.uri().clone(),
+            version: req.version(),
+            authority: inspect.authority(req).unwrap_or_default(),
+            headers: if extract_headers {
+                Some(req.headers().clone())
+            } else {
+                None
+            },
         };
 
-        // If try_send fails, just return `None`...
-        events_tx.try_send(event).ok()?;
-
-        let tap = TapTx { id, tx: events_tx };
+        // If the event can't be sent, don't tap the rest of the request.
+        if !tap.send(init) {
+            return None;
+        }
 
-        let req = TapRequestPayload {
-            tap: tap.clone(),
-            base_event: base_event.clone(),
-        };
+        let req = TapRequestPayload { tap: tap.clone() };
         let rsp = TapResponse {
             tap,
-            base_event,
             request_init_at,
             extract_headers,
         };
@@ -377,6 +484,28 @@ impl iface::Tap for Tap {
     }
 }
 
+// === impl TapTx ===
+
+impl TapTx {
+    /// Sends an event to the tap's subscription without waiting for capacity,
+    /// so that a slow consumer cannot delay the tapped request.
+    ///
+    /// Returns `false` if the event was dropped.
+    fn send(&mut self, kind: EventKind) -> bool {
+        let event = Event {
+            id: self.id.clone(),
+            base: self.base.clone(),
+            kind,
+        };
+        if self.tx.try_send(event).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            trace!("dropped tap event");
+            return false;
+        }
+        true
+    }
+}
+
 // === impl TapResponse ===
 
 impl iface::TapResponse for TapResponse {
@@ -385,38 +514,18 @@ impl iface::TapResponse for TapResponse {
     fn tap<B: Payload>(mut self, rsp: &http::Response<B>) -> TapResponsePayload {
         let response_init_at = clock::now();
 
-        let headers = if self.extract_headers {
-            let headers = if rsp.version() == http::Version::HTTP_2 {
-                let pseudos = iter::once(http_types::headers::Header {
-                    name: ":status".to_owned(),
-                    value: rsp.status().as_str().as_bytes().into(),
-                });
-                headers_to_pb(pseudos, rsp.headers())
+        self.tap.send(EventKind::ResponseInit {
+            since_request_init: response_init_at - self.request_init_at,
+            status: rsp.status(),
+            version: rsp.version(),
+            headers: if self.extract_headers {
+                Some(rsp.headers().clone())
             } else {
-                headers_to_pb(iter::empty(), rsp.headers())
-            };
-            Some(headers)
-        } else {
-            None
-        };
-
-        let init = api::tap_event::http::Event::ResponseInit(api::tap_event::http::ResponseInit {
-            id: Some(self.tap.id.clone()),
-            since_request_init: Some(pb_duration(response_init_at - self.request_init_at)),
-            http_status: rsp.status().as_u16().into(),
-            headers,
+                None
+            },
         });
 
-        let event = api::TapEvent {
-            event: Some(api::tap_event::Event::Http(api::tap_event::Http {
-                event: Some(init),
-            })),
-            ..self.base_event.clone()
-        };
-        let _ = self.tap.tx.try_send(event);
-
         TapResponsePayload {
-            base_event: self.base_event,
             request_init_at: self.request_init_at,
             response_init_at,
             response_bytes: 0,
@@ -433,24 +542,13 @@ impl iface::TapResponse for TapResponse {
     fn fail<E: HasH2Reason>(mut self, err: &E) {
         let response_end_at = clock::now();
         let reason = err.h2_reason();
-        let end = api::tap_event::http::Event::ResponseEnd(api::tap_event::http::ResponseEnd {
-            id: Some(self.tap.id.clone()),
-            since_request_init: Some(pb_duration(response_end_at - self.request_init_at)),
+        self.tap.send(EventKind::ResponseEnd {
+            since_request_init: response_end_at - self.request_init_at,
             since_response_init: None,
             response_bytes: 0,
-            eos: Some(api::Eos {
-                end: reason.map(|r| api::eos::End::ResetErrorCode(r.into())),
-            }),
+            end: reason.map(|r| api::eos::End::ResetErrorCode(r.into())),
             trailers: None,
         });
-
-        let event = api::TapEvent {
-            event: Some(api::tap_event::Event::Http(api::tap_event::Http {
-                event: Some(end),
-            })),
-            ..self.base_event
-        };
-        let _ = self.tap.tx.try_send(event);
     }
 }
 
@@ -495,26 +593,193 @@ impl TapResponsePayload {
     fn send(mut self, end: Option<api::eos::End>, trls: Option<&http::HeaderMap>) {
         let response_end_at = clock::now();
         let trailers = if self.extract_headers {
-            trls.map(|trls| headers_to_pb(iter::empty(), trls))
+            trls.cloned()
         } else {
             None
         };
-        let end = api::tap_event::http::ResponseEnd {
-            id: Some(self.tap.id),
-            since_request_init: Some(pb_duration(response_end_at - self.request_init_at)),
-            since_response_init: Some(pb_duration(response_end_at - self.response_init_at)),
+        self.tap.send(EventKind::ResponseEnd {
+            since_request_init: response_end_at - self.request_init_at,
+            since_response_init: Some(response_end_at - self.response_init_at),
             response_bytes: self.response_bytes as u64,
-            eos: Some(api::Eos { end }),
+            end,
             trailers,
+        });
+    }
+}
+
+// === impl Event ===
+
+impl Event {
+    fn into_pb(self) -> api::TapEvent {
+        let event = match self.kind {
+            EventKind::RequestInit {
+                method,
+                uri,
+                version,
+                authority,
+                headers,
+            } => {
+                let headers = headers.map(|headers| {
+                    if version == http::Version::HTTP_2 {
+                        // If the request is HTTP/2, add the pseudo-header
+                        // fields to the headers.
+                        let pseudos = vec![
+                            http_types::headers::Header {
+                                name: ":method".to_owned(),
+                                value: method.as_str().as_bytes().into(),
+                            },
+                            http_types::headers::Header {
+                                name: ":scheme".to_owned(),
+                                value: uri
+                                    .scheme_str()
+                                    .map(|s| s.as_bytes().into())
+                                    .unwrap_or_default(),
+                            },
+                            http_types::headers::Header {
+                                name: ":authority".to_owned(),
+                                value: authority.as_bytes().into(),
+                            },
+                            http_types::headers::Header {
+                                name: ":path".to_owned(),
+                                value: uri
+                                    .path_and_query()
+                                    .map(|p| p.as_str().as_bytes().into())
+                                    .unwrap_or_default(),
+                            },
+                        ];
+                        headers_to_pb(pseudos, &headers)
+                    } else {
+                        headers_to_pb(iter::empty(), &headers)
+                    }
+                });
+
+                api::tap_event::http::Event::RequestInit(api::tap_event::http::RequestInit {
+                    id: Some(self.id),
+                    method: Some((&method).into()),
+                    scheme: uri.scheme_part().map(http_types::Scheme::from),
+                    authority,
+                    path: uri.path().into(),
+                    headers,
+                })
+            }
+
+            EventKind::ResponseInit {
+                since_request_init,
+                status,
+                version,
+                headers,
+            } => {
+                let headers = headers.map(|headers| {
+                    if version == http::Version::HTTP_2 {
+                        let pseudos = iter::once(http_types::headers::Header {
+                            name: ":status".to_owned(),
+                            value: status.as_str().as_bytes().into(),
+                        });
+                        headers_to_pb(pseudos, &headers)
+                    } else {
+                        headers_to_pb(iter::empty(), &headers)
+                    }
+                });
+
+                api::tap_event::http::Event::ResponseInit(api::tap_event::http::ResponseInit {
+                    id: Some(self.id),
+                    since_request_init: Some(pb_duration(since_request_init)),
+                    http_status: status.as_u16().into(),
+                    headers,
+                })
+            }
+
+            EventKind::ResponseEnd {
+                since_request_init,
+                since_response_init,
+                response_bytes,
+                end,
+                trailers,
+            } => api::tap_event::http::Event::ResponseEnd(api::tap_event::http::ResponseEnd {
+                id: Some(self.id),
+                since_request_init: Some(pb_duration(since_request_init)),
+                since_response_init: since_response_init.map(pb_duration),
+                response_bytes,
+                eos: Some(api::Eos { end }),
+                trailers: trailers.map(|trls| headers_to_pb(iter::empty(), &trls)),
+            }),
         };
 
-        let event = api::TapEvent {
+        api::TapEvent {
             event: Some(api::tap_event::Event::Http(api::tap_event::Http {
-                event: Some(api::tap_event::http::Event::ResponseEnd(end)),
+                event: Some(event),
             })),
-            ..self.base_event
-        };
-        let _ = self.tap.tx.try_send(event);
+            ..self.base.to_pb()
+        }
+    }
+}
+
+// === impl BaseEvent ===
+
+impl BaseEvent {
+    fn new<B, I: Inspect>(req: &http::Request<B>, inspect: &I) -> Self {
+        Self {
+            is_outbound: inspect.is_outbound(req),
+            source: inspect.src_addr(req),
+            source_tls: inspect.src_tls(req).map(Clone::clone),
+            destination: inspect.dst_addr(req),
+            destination_meta: inspect
+                .dst_labels(req)
+                .map(|labels| (labels.clone(), inspect.dst_tls(req).map(Clone::clone))),
+            route_labels: inspect.route_labels(req),
+        }
+    }
+
+    // All of the events emitted from tap have a common set of metadata.
+    // Build this without an `event`, so that it can be used to build each
+    // HTTP event.
+    fn to_pb(&self) -> api::TapEvent {
+        api::TapEvent {
+            proxy_direction: if self.is_outbound {
+                api::tap_event::ProxyDirection::Outbound.into()
+            } else {
+                api::tap_event::ProxyDirection::Inbound.into()
+            },
+            source: self.source.as_ref().map(|a| a.into()),
+            source_meta: {
+                let mut m = api::tap_event::EndpointMeta::default();
+                match self.source_tls {
+                    Conditional::None(reason) => {
+                        m.labels.insert("tls".to_owned(), reason.to_string());
+                    }
+                    Conditional::Some(ref id) => {
+                        m.labels.insert("tls".to_owned(), "true".to_owned());
+                        m.labels
+                            .insert("client_id".to_owned(), id.as_ref().to_owned());
+                    }
+                }
+                Some(m)
+            },
+            destination: self.destination.as_ref().map(|a| a.into()),
+            destination_meta: self.destination_meta.as_ref().map(|(labels, tls)| {
+                let mut m = api::tap_event::EndpointMeta::default();
+                m.labels
+                    .extend(labels.iter().map(|(k, v)| (k.clone(), v.clone())));
+                match tls {
+                    Conditional::None(reason) => {
+                        m.labels.insert("tls".to_owned(), reason.to_string());
+                    }
+                    Conditional::Some(id) => {
+                        m.labels.insert("tls".to_owned(), "true".to_owned());
+                        m.labels
+                            .insert("server_id".to_owned(), id.as_ref().to_owned());
+                    }
+                }
+                m
+            }),
+            route_meta: self.route_labels.as_ref().map(|labels| {
+                let mut m = api::tap_event::RouteMeta::default();
+                m.labels
+                    .extend(labels.as_ref().iter().map(|(k, v)| (k.clone(), v.clone())));
+                m
+            }),
+            event: None,
+        }
     }
 }
 
@@ -542,58 +807,6 @@ impl Default for ExtractKind {
     }
 }
 
-// All of the events emitted from tap have a common set of metadata.
-// Build this once, without an `event`, so that it can be used to build
-// each HTTP event.
-fn base_event<B, I: Inspect>(req: &http::Request<B>, inspect: &I) -> api::TapEvent {
-    api::TapEvent {
-        proxy_direction: if inspect.is_outbound(req) {
-            api::tap_event::ProxyDirection::Outbound.into()
-        } else {
-            api::tap_event::ProxyDirection::Inbound.into()
-        },
-        source: inspect.src_addr(req).as_ref().map(|a| a.into()),
-        source_meta: {
-            let mut m = api::tap_event::EndpointMeta::default();
-            match inspect.src_tls(req) {
-                Conditional::None(reason) => {
-                    m.labels.insert("tls".to_owned(), reason.to_string());
-                }
-                Conditional::Some(id) => {
-                    m.labels.insert("tls".to_owned(), "true".to_owned());
-                    m.labels
-                        .insert("client_id".to_owned(), id.as_ref().to_owned());
-                }
-            }
-            Some(m)
-        },
-        destination: inspect.dst_addr(req).as_ref().map(|a| a.into()),
-        destination_meta: inspect.dst_labels(req).map(|labels| {
-            let mut m = api::tap_event::EndpointMeta::default();
-            m.labels
-                .extend(labels.iter().map(|(k, v)| (k.clone(), v.clone())));
-            match inspect.dst_tls(req) {
-                Conditional::None(reason) => {
-                    m.labels.insert("tls".to_owned(), reason.to_string());
-                }
-                Conditional::Some(id) => {
-                    m.labels.insert("tls".to_owned(), "true".to_owned());
-                    m.labels
-                        .insert("server_id".to_owned(), id.as_ref().to_owned());
-                }
-            }
-            m
-        }),
-        route_meta: inspect.route_labels(req).map(|labels| {
-            let mut m = api::tap_event::RouteMeta::default();
-            m.labels
-                .extend(labels.as_ref().iter().map(|(k, v)| (k.clone(), v.clone())));
-            m
-        }),
-        event: None,
-    }
-}
-
 fn headers_to_pb(
     pseudos: impl IntoIterator<Item = http_types::headers::Header>,
     headers: &http::HeaderMap,
@@ -612,3 +825,160 @@ fn headers_to_pb(
             .collect(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iface::Tap as _;
+
+    fn tap_tx(capacity: usize) -> (TapTx, mpsc::Receiver<Event>) {
+        let (tx, rx) = mpsc::channel(capacity);
+        let base = BaseEvent {
+            is_outbound: false,
+            source: None,
+            source_tls: Conditional::None(ReasonForNoIdentity::Disabled),
+            destination: None,
+            destination_meta: None,
+            route_labels: None,
+        };
+        let tap = TapTx {
+            id: api::tap_event::http::StreamId::default(),
+            base: Arc::new(base),
+            tx,
+            dropped: Arc::new(AtomicUsize::new(0)),
+        };
+        (tap, rx)
+    }
+
+    fn response_end() -> EventKind {
+        EventKind::ResponseEnd {
+            since_request_init: Duration::from_millis(1),
+            since_response_init: None,
+            response_bytes: 0,
+            end: None,
+            trailers: None,
+        }
+    }
+
+    #[test]
+    fn full_queue_drops_events() {
+        const CAPACITY: usize = 2;
+        let (mut tap, rx) = tap_tx(CAPACITY);
+
+        // A sender may always enqueue one event beyond the channel's capacity.
+        for _ in 0..CAPACITY + 1 {
+            assert!(tap.send(response_end()), "event must be enqueued");
+        }
+
+        // Nothing reads from the queue, so further events are dropped
+        // immediately rather than waiting for the consumer.
+        for _ in 0..3 {
+            assert!(!tap.send(response_end()), "event must be dropped");
+        }
+        assert_eq!(tap.dropped.load(Ordering::Relaxed), 3);
+
+        // The events that were enqueued are still delivered.
+        drop(tap);
+        let events = rx.collect().wait().expect("queue must not fail");
+        assert_eq!(events.len(), CAPACITY + 1);
+    }
+
+    /// A target whose requests are all routed to a single profile route.
+    struct Route(Arc<IndexMap<String, String>>);
+
+    impl Route {
+        fn new(name: &str) -> Self {
+            let labels = Some(("route".to_owned(), name.to_owned()))
+                .into_iter()
+                .collect();
+            Route(Arc::new(labels))
+        }
+    }
+
+    impl Inspect for Route {
+        fn src_addr<B>(&self, _: &http::Request<B>) -> Option<SocketAddr> {
+            None
+        }
+
+        fn src_tls<'a, B>(
+            &self,
+            _: &'a http::Request<B>,
+        ) -> Conditional<&'a identity::Name, ReasonForNoIdentity> {
+            Conditional::None(ReasonForNoIdentity::Disabled)
+        }
+
+        fn dst_addr<B>(&self, _: &http::Request<B>) -> Option<SocketAddr> {
+            None
+        }
+
+        fn dst_labels<B>(&self, _: &http::Request<B>) -> Option<&IndexMap<String, String>> {
+            None
+        }
+
+        fn dst_tls<B>(
+            &self,
+            _: &http::Request<B>,
+        ) -> Conditional<&identity::Name, ReasonForNoIdentity> {
+            Conditional::None(ReasonForNoIdentity::Disabled)
+        }
+
+        fn route_labels<B>(&self, _: &http::Request<B>) -> Option<Arc<IndexMap<String, String>>> {
+            Some(self.0.clone())
+        }
+
+        fn is_outbound<B>(&self, _: &http::Request<B>) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn route_sample_only_taps_matching_route() {
+        use api::observe_request::r#match;
+
+        let match_ = Match::try_new(Some(api::observe_request::Match {
+            r#match: Some(r#match::Match::RouteLabel(r#match::Label {
+                key: "route".into(),
+                value: "GET /books".into(),
+            })),
+        }))
+        .expect("match must be valid");
+        assert!(match_.is_route_scoped());
+
+        let (events_tx, events_rx) = mpsc::channel(10);
+        let shared = Arc::new(Shared {
+            base_id: 0,
+            count: AtomicUsize::new(0),
+            limit: 100,
+            match_,
+            sample: Some(Sample::new(2)),
+            extract: ExtractKind::default(),
+            events_tx,
+            dropped: Arc::new(AtomicUsize::new(0)),
+        });
+        let mut tap = Tap {
+            shared: Arc::downgrade(&shared),
+        };
+
+        let books = Route::new("GET /books");
+        let authors = Route::new("GET /authors");
+        let mut sampled = 0;
+        for _ in 0..6 {
+            let req = http::Request::new(hyper::Body::empty());
+            if tap.tap(&req, &books).is_some() {
+                sampled += 1;
+            }
+            assert!(
+                tap.tap(&req, &authors).is_none(),
+                "other routes must not be tapped"
+            );
+        }
+        assert_eq!(sampled, 3, "one of every 2 requests must be sampled");
+
+        drop((tap, shared));
+        let events = events_rx.collect().wait().expect("queue must not fail");
+        assert_eq!(events.len(), 3);
+        for event in &events {
+            assert_eq!(event.base.route_labels.as_ref(), Some(&books.0));
+        }
+    }
+}
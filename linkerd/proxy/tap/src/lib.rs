@@ -100,11 +100,20 @@ mod iface {
     /// Registers a stack to receive taps.
     pub trait Register {
         type Tap: Tap;
-        type Taps: Stream<Item = Self::Tap>;
+        type Taps: Taps<Item = Self::Tap>;
 
         fn register(&mut self) -> Self::Taps;
     }
 
+    /// A stream of the taps advertised to a registered stack.
+    pub trait Taps: Stream {
+        /// Returns `true` if no taps are active, in which case the stream
+        /// need not be polled.
+        ///
+        /// This is checked for every request, so it must be cheap.
+        fn is_idle(&self) -> bool;
+    }
+
     /// Advertises a Tap from a server to stacks.
     pub trait Subscribe<T: Tap> {
         type Future: Future<Item = (), Error = NoCapacity>;
@@ -1,4 +1,4 @@
-use super::iface::{Register, Tap, TapPayload, TapResponse};
+use super::iface::{Register, Tap, TapPayload, TapResponse, Taps};
 use super::Inspect;
 use bytes::IntoBuf;
 use futures::{try_ready, Async, Future, Poll, Stream};
@@ -105,12 +105,12 @@ where
 
 // === MakeFuture ===
 
-impl<F, Taps, I> Future for MakeFuture<F, Taps, I>
+impl<F, R, I> Future for MakeFuture<F, R, I>
 where
     F: Future,
-    Taps: Stream,
+    R: Stream,
 {
-    type Item = Service<I, Taps, Taps::Item, F::Item>;
+    type Item = Service<I, R, R::Item, F::Item>;
     type Error = F::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
@@ -131,7 +131,7 @@ where
 impl<I, R, S, T, A, B> tower::Service<http::Request<A>> for Service<I, R, T, S>
 where
     I: Inspect,
-    R: Stream<Item = T>,
+    R: Taps<Item = T>,
     T: Tap,
     T::TapRequestPayload: Send + 'static,
     T::TapResponsePayload: Send + 'static,
@@ -150,12 +150,18 @@ where
     type Future = ResponseFuture<S::Future, T::TapResponse>;
 
     fn poll_ready(&mut self) -> Poll<(), Self::Error> {
-        // Load new taps from the tap server.
-        while let Ok(Async::Ready(Some(t))) = self.tap_rx.poll() {
-            self.taps.push(t);
+        if self.tap_rx.is_idle() {
+            // Nothing is being tapped, so there's no need to poll for taps
+            // or to match requests against them.
+            self.taps.clear();
+        } else {
+            // Load new taps from the tap server.
+            while let Ok(Async::Ready(Some(t))) = self.tap_rx.poll() {
+                self.taps.push(t);
+            }
+            // Drop taps that have been canceled or completed.
+            self.taps.retain(|t| t.can_tap_more());
         }
-        // Drop taps that have been canceled or completed.
-        self.taps.retain(|t| t.can_tap_more());
 
         self.inner.poll_ready()
     }
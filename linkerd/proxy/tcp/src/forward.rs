@@ -1,26 +1,44 @@
-use futures::{try_ready, Future, Poll};
+use futures::{try_ready, Async, Future, Poll};
 use linkerd2_duplex::Duplex;
-use linkerd2_error::Error;
+use linkerd2_error::{contract, Error};
 use tokio::io::{AsyncRead, AsyncWrite};
 use tower::Service;
+use tracing::{debug, Span};
 
 pub fn forward<C>(connect: C) -> Forward<C> {
-    Forward { connect }
+    Forward::new(connect)
 }
 
 #[derive(Clone, Debug)]
 pub struct Forward<C> {
     connect: C,
+    buffer_size: usize,
 }
 
 pub enum ForwardFuture<I, F: Future> {
-    Connect { connect: F, io: Option<I> },
+    Connect {
+        connect: F,
+        io: Option<I>,
+        buffer_size: usize,
+    },
     Duplex(Duplex<I, F::Item>),
 }
 
 impl<C> Forward<C> {
     pub fn new(connect: C) -> Self {
-        Self { connect }
+        Self {
+            connect,
+            buffer_size: linkerd2_duplex::DEFAULT_BUFFER_SIZE,
+        }
+    }
+
+    /// Sets the size of the buffer used to copy data in each direction of a
+    /// forwarded connection.
+    pub fn with_buffer_size(self, buffer_size: usize) -> Self {
+        Self {
+            buffer_size,
+            ..self
+        }
     }
 }
 
@@ -43,6 +61,7 @@ where
         ForwardFuture::Connect {
             io: Some(io),
             connect: self.connect.call(meta),
+            buffer_size: self.buffer_size,
         }
     }
 }
@@ -63,13 +82,34 @@ where
                 ForwardFuture::Connect {
                     ref mut connect,
                     ref mut io,
+                    buffer_size,
                 } => {
                     let client_io = try_ready!(connect.poll().map_err(Into::into));
-                    let server_io = io.take().expect("illegal state");
-                    ForwardFuture::Duplex(Duplex::new(server_io, client_io))
+                    let server_io = contract!(
+                        io.take(),
+                        "tcp::ForwardFuture::poll",
+                        "polled after the connection was forwarded"
+                    )?;
+                    ForwardFuture::Duplex(
+                        Duplex::new(server_io, client_io).with_buffer_size(*buffer_size),
+                    )
                 }
                 ForwardFuture::Duplex(ref mut fut) => {
-                    return fut.poll().map_err(Into::into);
+                    let res = fut.poll();
+                    if let Ok(Async::NotReady) = res {
+                        return Ok(Async::NotReady);
+                    }
+
+                    // The connection has closed, so its byte counts are final.
+                    // They're recorded on the current span, if it declares
+                    // `bytes.sent` and `bytes.received` fields.
+                    let sent = fut.bytes_in_to_out();
+                    let received = fut.bytes_out_to_in();
+                    let span = Span::current();
+                    span.record("bytes.sent", &sent);
+                    span.record("bytes.received", &received);
+                    debug!(sent, received, "forward complete");
+                    return res.map_err(Into::into);
                 }
             }
         }
@@ -7,11 +7,13 @@ pub mod connect;
 pub use linkerd2_io as io;
 pub mod listen;
 pub mod metrics;
+pub mod skip_ports;
 pub mod tls;
 
 pub use self::{
     io::BoxedIo,
     listen::{Bind, Listen, NoOrigDstAddr, OrigDstAddr, SysOrigDstAddr},
+    skip_ports::SkipPorts,
 };
 
 // Misc.
@@ -1,4 +1,4 @@
-use super::Sensor;
+use super::{Sensor, SetCloseReason};
 use bytes::Buf;
 use futures::{try_ready, Async, Poll};
 use std::io;
@@ -18,6 +18,11 @@ impl<T> Io<T> {
         Self { io, sensor }
     }
 
+    /// Returns a handle that labels this transport's closure with a reason.
+    pub fn close_reason(&self) -> SetCloseReason {
+        self.sensor.close_reason.clone()
+    }
+
     /// Wraps an operation on the underlying transport with error telemetry.
     ///
     /// If the transport operation results in a non-recoverable error, record a
@@ -24,7 +24,15 @@ metrics! {
     tcp_write_bytes_total: Counter { "Total count of bytes written to peers" },
 
     tcp_close_total: Counter { "Total count of closed connections" },
-    tcp_connection_duration_ms: Histogram<latency::Ms> { "Connection lifetimes" }
+    tcp_connection_duration_ms: Histogram<latency::Ms> { "Connection lifetimes" },
+
+    tcp_connect_duration_ms: Histogram<latency::Ms> {
+        "Time taken to establish TCP connections, excluding any TLS handshake"
+    },
+    tls_handshake_duration_ms: Histogram<latency::Ms> {
+        "Time taken to complete TLS handshakes on established TCP connections"
+    },
+    tls_handshake_errors_total: Counter { "Total count of failed TLS handshakes" }
 }
 
 pub fn new<K: Eq + Hash + FmtLabels>() -> (Registry<K>, Report<K>) {
@@ -65,6 +73,54 @@ pub struct Connecting<F> {
     new_sensor: Option<NewSensor>,
 }
 
+/// Records how long each phase of a connection's establishment takes.
+///
+/// Implements `tls::client::Observe`.
+#[derive(Debug)]
+pub struct ObserveConnect<L, K: Eq + Hash + FmtLabels> {
+    label: L,
+    registry: Arc<Mutex<Inner<K>>>,
+}
+
+/// Records the phases of a single connection's establishment.
+#[derive(Debug)]
+pub struct ConnectObserver {
+    metrics: Arc<Mutex<Metrics>>,
+    started_at: Instant,
+    /// Set while a TLS handshake is in progress.
+    handshake_started_at: Option<Instant>,
+}
+
+/// Classifies why a TLS handshake failed.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum HandshakeError {
+    /// The handshake was abandoned before it completed, e.g. because the
+    /// connect timeout elapsed.
+    Timeout,
+    /// The peer's certificate could not be verified.
+    CertVerification,
+    /// The peer violated the TLS protocol or is incompatible with the client.
+    Protocol,
+    /// The connection failed during the handshake.
+    Io,
+}
+
+/// Describes why the proxy chose to close a connection.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum CloseReason {
+    /// The connection served its maximum number of requests.
+    MaxRequests,
+    /// The client reset too many streams on the connection.
+    StreamResets,
+    /// The client didn't use the connection before its idle timeout elapsed.
+    IdleTimeout,
+}
+
+/// Records why a transport is being closed, so that its closure is labeled
+/// accordingly.
+#[derive(Clone, Debug, Default)]
+pub struct SetCloseReason(Arc<Mutex<Option<CloseReason>>>);
+
 /// Stores a class of transport's metrics.
 ///
 /// TODO We should probaby use AtomicUsize for most of these counters so that
@@ -77,6 +133,18 @@ struct Metrics {
     read_bytes_total: Counter,
 
     by_eos: IndexMap<Eos, EosMetrics>,
+
+    /// Only set for connections that are established by the proxy, once one
+    /// has been observed.
+    connect: Option<ConnectMetrics>,
+}
+
+/// Holds metrics for the establishment of connections.
+#[derive(Debug, Default)]
+struct ConnectMetrics {
+    connect_duration: Histogram<latency::Ms>,
+    handshake_duration: Histogram<latency::Ms>,
+    handshake_errors: IndexMap<HandshakeError, Counter>,
 }
 
 /// Describes a classtransport end.
@@ -85,7 +153,10 @@ struct Metrics {
 ///
 /// Implements `FmtLabels`.
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
-struct Eos(Option<Errno>);
+struct Eos {
+    errno: Option<Errno>,
+    reason: Option<CloseReason>,
+}
 
 /// Holds metrics for a class of end-of-stream.
 #[derive(Debug, Default)]
@@ -99,6 +170,7 @@ struct EosMetrics {
 struct Sensor {
     metrics: Option<Arc<Mutex<Metrics>>>,
     opened_at: Instant,
+    close_reason: SetCloseReason,
 }
 
 /// Lazily builds instances of `Sensor`.
@@ -139,8 +211,9 @@ impl<K: Eq + Hash + FmtLabels> Inner<K> {
         F: Fn(&Metrics) -> &M,
         M: FmtMetric,
     {
+        let name = metric.sample_name(f);
         for (key, m) in self.iter() {
-            get_metric(&*m).fmt_metric_labeled(f, metric.name, key)?;
+            get_metric(&*m).fmt_metric_labeled(f, &name, key)?;
         }
 
         Ok(())
@@ -157,15 +230,59 @@ impl<K: Eq + Hash + FmtLabels> Inner<K> {
         F: Fn(&EosMetrics) -> &M,
         M: FmtMetric,
     {
+        let name = metric.sample_name(f);
         for (key, metrics) in self.iter() {
             for (eos, m) in (*metrics).by_eos.iter() {
-                get_metric(&*m).fmt_metric_labeled(f, metric.name, (key, eos))?;
+                get_metric(&*m).fmt_metric_labeled(f, &name, (key, eos))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Formats a metric across all instances of `ConnectMetrics` in the
+    /// registry.
+    fn fmt_connect_by<F, M>(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        metric: Metric<'_, M>,
+        get_metric: F,
+    ) -> fmt::Result
+    where
+        F: Fn(&ConnectMetrics) -> &M,
+        M: FmtMetric,
+    {
+        let name = metric.sample_name(f);
+        for (key, metrics) in self.iter() {
+            if let Some(ref m) = metrics.connect {
+                get_metric(m).fmt_metric_labeled(f, &name, key)?;
             }
         }
 
         Ok(())
     }
 
+    fn fmt_handshake_errors(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        metric: Metric<'_, Counter>,
+    ) -> fmt::Result {
+        let name = metric.sample_name(f);
+        for (key, metrics) in self.iter() {
+            if let Some(ref m) = metrics.connect {
+                for (error, c) in m.handshake_errors.iter() {
+                    c.fmt_metric_labeled(f, &name, (key, error))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn has_connects(&self) -> bool {
+        self.iter().any(|(_, m)| m.connect.is_some())
+    }
+
     fn get_or_default(&mut self, k: K) -> &Arc<Mutex<Metrics>> {
         self.0.entry(k).or_insert_with(|| Default::default())
     }
@@ -182,6 +299,15 @@ impl<K: Eq + Hash + FmtLabels> Registry<K> {
         LayerConnect::new(label, self.0.clone())
     }
 
+    /// Returns a `tls::client::Observe` that records the time taken to
+    /// connect separately from the time taken by the TLS handshake.
+    pub fn observe_connect<L>(&self, label: L) -> ObserveConnect<L, K> {
+        ObserveConnect {
+            label,
+            registry: self.0.clone(),
+        }
+    }
+
     pub fn wrap_server_transport<T: AsyncRead + AsyncWrite>(&self, labels: K, io: T) -> Io<T> {
         let metrics = self
             .0
@@ -301,8 +427,111 @@ where
     }
 }
 
+// === impl ObserveConnect ===
+
+impl<L: Clone, K: Eq + Hash + FmtLabels> Clone for ObserveConnect<L, K> {
+    fn clone(&self) -> Self {
+        Self {
+            label: self.label.clone(),
+            registry: self.registry.clone(),
+        }
+    }
+}
+
+impl<L, T, K> tls::client::Observe<T> for ObserveConnect<L, K>
+where
+    L: TransportLabels<T, Labels = K>,
+    K: Eq + Hash + FmtLabels,
+{
+    type Observer = ConnectObserver;
+
+    fn observe(&self, target: &T) -> Self::Observer {
+        let labels = self.label.transport_labels(target);
+        let metrics = self
+            .registry
+            .lock()
+            .expect("metrics registry poisoned")
+            .get_or_default(labels)
+            .clone();
+        ConnectObserver {
+            metrics,
+            started_at: Instant::now(),
+            handshake_started_at: None,
+        }
+    }
+}
+
+// === impl ConnectObserver ===
+
+impl ConnectObserver {
+    fn record<F: FnOnce(&mut ConnectMetrics)>(&self, f: F) {
+        if let Ok(mut m) = self.metrics.lock() {
+            f(m.connect.get_or_insert_with(ConnectMetrics::default))
+        }
+    }
+
+    fn record_handshake_error(&mut self, error: HandshakeError) {
+        self.handshake_started_at = None;
+        self.record(|m| {
+            m.handshake_errors
+                .entry(error)
+                .or_insert_with(Counter::default)
+                .incr()
+        });
+    }
+}
+
+impl tls::client::Observer for ConnectObserver {
+    fn connected(&mut self) {
+        let duration = self.started_at.elapsed();
+        self.record(|m| m.connect_duration.add(duration));
+    }
+
+    fn handshake_started(&mut self) {
+        self.handshake_started_at = Some(Instant::now());
+    }
+
+    fn handshake_succeeded(&mut self) {
+        if let Some(t0) = self.handshake_started_at.take() {
+            let duration = t0.elapsed();
+            self.record(|m| m.handshake_duration.add(duration));
+        }
+    }
+
+    fn handshake_failed(&mut self, error: &std::io::Error) {
+        let error = HandshakeError::classify(error);
+        debug!(?error, "TLS handshake failed");
+        self.record_handshake_error(error);
+    }
+}
+
+impl Drop for ConnectObserver {
+    fn drop(&mut self) {
+        // A handshake that is still in progress has been abandoned.
+        if self.handshake_started_at.is_some() {
+            self.record_handshake_error(HandshakeError::Timeout);
+        }
+    }
+}
+
 // ===== impl Report =====
 
+impl<K: Eq + Hash + FmtLabels> Report<K> {
+    /// Returns the number of currently-open connections across all keys
+    /// that satisfy `matches`.
+    pub fn open_connections<F>(&self, matches: F) -> u64
+    where
+        F: Fn(&K) -> bool,
+    {
+        let metrics = self.0.lock().expect("metrics registry poisoned");
+        metrics
+            .iter()
+            .filter(|(k, _)| matches(k))
+            .map(|(_, m)| -> u64 { m.open_connections.into() })
+            .sum()
+    }
+}
+
 impl<K: Eq + Hash + FmtLabels> FmtMetrics for Report<K> {
     fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let metrics = self.0.lock().expect("metrics registry poisoned");
@@ -328,6 +557,17 @@ impl<K: Eq + Hash + FmtLabels> FmtMetrics for Report<K> {
         tcp_connection_duration_ms.fmt_help(f)?;
         metrics.fmt_eos_by(f, tcp_connection_duration_ms, |e| &e.connection_duration)?;
 
+        if metrics.has_connects() {
+            tcp_connect_duration_ms.fmt_help(f)?;
+            metrics.fmt_connect_by(f, tcp_connect_duration_ms, |m| &m.connect_duration)?;
+
+            tls_handshake_duration_ms.fmt_help(f)?;
+            metrics.fmt_connect_by(f, tls_handshake_duration_ms, |m| &m.handshake_duration)?;
+
+            tls_handshake_errors_total.fmt_help(f)?;
+            metrics.fmt_handshake_errors(f, tls_handshake_errors_total)?;
+        }
+
         Ok(())
     }
 }
@@ -344,6 +584,7 @@ impl Sensor {
         Self {
             metrics: Some(metrics),
             opened_at: Instant::now(),
+            close_reason: SetCloseReason::default(),
         }
     }
 
@@ -370,7 +611,11 @@ impl Sensor {
             let mut m = m.lock().expect("metrics registry poisoned");
             m.open_connections.decr();
 
-            let class = m.by_eos.entry(Eos(eos)).or_insert_with(EosMetrics::default);
+            let eos = Eos {
+                errno: eos,
+                reason: self.close_reason.get(),
+            };
+            let class = m.by_eos.entry(eos).or_insert_with(EosMetrics::default);
             class.close_total.incr();
             class.connection_duration.add(duration);
         }
@@ -383,6 +628,21 @@ impl Drop for Sensor {
     }
 }
 
+// ===== impl SetCloseReason =====
+
+impl SetCloseReason {
+    pub fn set(&self, reason: CloseReason) {
+        if let Ok(mut r) = self.0.lock() {
+            *r = Some(reason);
+        }
+    }
+
+    /// Returns the reason that has been set, if any.
+    pub fn get(&self) -> Option<CloseReason> {
+        self.0.lock().ok().and_then(|r| *r)
+    }
+}
+
 // ===== impl NewSensor =====
 
 impl NewSensor {
@@ -395,9 +655,54 @@ impl NewSensor {
 
 impl FmtLabels for Eos {
     fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self.0 {
-            None => f.pad("errno=\"\""),
-            Some(errno) => write!(f, "errno=\"{}\"", errno),
+        match self.errno {
+            None => f.pad("errno=\"\"")?,
+            Some(errno) => write!(f, "errno=\"{}\"", errno)?,
+        }
+
+        match self.reason {
+            None => Ok(()),
+            Some(reason) => write!(f, ",close_reason=\"{}\"", reason),
+        }
+    }
+}
+
+// ===== impl HandshakeError =====
+
+impl HandshakeError {
+    fn classify(error: &std::io::Error) -> Self {
+        use rustls::TLSError;
+
+        match error.get_ref().and_then(|e| e.downcast_ref::<TLSError>()) {
+            Some(TLSError::WebPKIError(_)) | Some(TLSError::NoCertificatesPresented) => {
+                HandshakeError::CertVerification
+            }
+            Some(_) => HandshakeError::Protocol,
+            None => HandshakeError::Io,
+        }
+    }
+}
+
+impl FmtLabels for HandshakeError {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let error = match self {
+            HandshakeError::Timeout => "timeout",
+            HandshakeError::CertVerification => "cert_verification",
+            HandshakeError::Protocol => "protocol",
+            HandshakeError::Io => "io",
+        };
+        write!(f, "error=\"{}\"", error)
+    }
+}
+
+// ===== impl CloseReason =====
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CloseReason::MaxRequests => f.pad("max_requests"),
+            CloseReason::StreamResets => f.pad("stream_resets"),
+            CloseReason::IdleTimeout => f.pad("idle_timeout"),
         }
     }
 }
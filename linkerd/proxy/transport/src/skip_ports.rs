@@ -0,0 +1,54 @@
+use indexmap::IndexSet;
+use std::sync::{Arc, RwLock};
+
+/// The set of ports for which protocol detection is disabled.
+///
+/// The set is shared by all clones, so that it may be updated while the proxy
+/// is running. It is consulted as each connection is accepted, so updates
+/// apply only to new connections.
+#[derive(Clone, Debug, Default)]
+pub struct SkipPorts(Arc<RwLock<IndexSet<u16>>>);
+
+impl SkipPorts {
+    pub fn new(ports: IndexSet<u16>) -> Self {
+        SkipPorts(Arc::new(RwLock::new(ports)))
+    }
+
+    pub fn contains(&self, port: u16) -> bool {
+        self.0
+            .read()
+            .map(|ports| ports.contains(&port))
+            .unwrap_or(false)
+    }
+
+    /// Disables protocol detection for `port`, returning false if it was
+    /// already disabled.
+    pub fn insert(&self, port: u16) -> bool {
+        self.0
+            .write()
+            .map(|mut ports| ports.insert(port))
+            .unwrap_or(false)
+    }
+
+    /// Enables protocol detection for `port`, returning false if it was not
+    /// disabled.
+    pub fn remove(&self, port: u16) -> bool {
+        self.0
+            .write()
+            .map(|mut ports| ports.remove(&port))
+            .unwrap_or(false)
+    }
+
+    pub fn ports(&self) -> Vec<u16> {
+        self.0
+            .read()
+            .map(|ports| ports.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+impl From<IndexSet<u16>> for SkipPorts {
+    fn from(ports: IndexSet<u16>) -> Self {
+        Self::new(ports)
+    }
+}
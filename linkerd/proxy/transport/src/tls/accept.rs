@@ -1,9 +1,9 @@
 use super::{conditional_accept, ReasonForNoPeerName};
 use crate::io::{BoxedIo, PrefixedIo};
 use crate::listen::{self, Addrs};
+use crate::SkipPorts;
 use bytes::BytesMut;
 use futures::{try_ready, Future, Poll};
-use indexmap::IndexSet;
 use linkerd2_conditional::Conditional;
 use linkerd2_dns_name as dns;
 use linkerd2_error::Error;
@@ -11,6 +11,7 @@ use linkerd2_identity as identity;
 use linkerd2_proxy_core::listen::Accept;
 pub use rustls::ServerConfig as Config;
 use std::sync::Arc;
+use std::{error, fmt};
 use tokio::net::TcpStream;
 use tracing::{debug, trace};
 
@@ -34,12 +35,18 @@ pub struct Meta {
 
 pub type Connection = (Meta, BoxedIo);
 
+#[derive(Clone)]
 pub struct AcceptTls<A: Accept<Connection>, T> {
     accept: A,
     tls: super::Conditional<T>,
-    skip_ports: Arc<IndexSet<u16>>,
+    skip_ports: SkipPorts,
+    require_sni: bool,
 }
 
+/// A TLS connection was rejected because its ClientHello had no SNI.
+#[derive(Debug)]
+pub struct MissingSni(());
+
 pub enum AcceptFuture<A: Accept<Connection>> {
     TryTls(Option<TryTls<A>>),
     TerminateTls(
@@ -56,6 +63,7 @@ pub struct TryTls<A: Accept<Connection>> {
     config: Arc<Config>,
     peek_buf: BytesMut,
     socket: TcpStream,
+    require_sni: bool,
 }
 
 pub struct AcceptMeta<A: Accept<Connection>> {
@@ -73,13 +81,21 @@ impl<A: Accept<Connection>, T: HasConfig> AcceptTls<A, T> {
             accept,
             tls,
             skip_ports: Default::default(),
+            require_sni: false,
         }
     }
 
-    pub fn with_skip_ports(mut self, skip_ports: Arc<IndexSet<u16>>) -> Self {
+    pub fn with_skip_ports(mut self, skip_ports: SkipPorts) -> Self {
         self.skip_ports = skip_ports;
         self
     }
+
+    /// If set, TLS connections whose ClientHello has no SNI are rejected,
+    /// rather than being passed through without being terminated.
+    pub fn with_require_sni(mut self, require_sni: bool) -> Self {
+        self.require_sni = require_sni;
+        self
+    }
 }
 
 impl<A, T> tower::Service<listen::Connection> for AcceptTls<A, T>
@@ -114,7 +130,7 @@ where
 
             // Tls is enabled. Try to accept a Tls handshake.
             Conditional::Some(tls) => {
-                if self.skip_ports.contains(&target_addr.port()) {
+                if self.skip_ports.contains(target_addr.port()) {
                     debug!("skipping protocol detection");
                     let meta = Meta {
                         peer_identity: Conditional::None(
@@ -136,6 +152,7 @@ where
                         peek_buf: BytesMut::with_capacity(Self::PEEK_CAPACITY),
                         config: tls.tls_server_config(),
                         server_name: tls.tls_server_name(),
+                        require_sni: self.require_sni,
                     }))
                 }
             }
@@ -183,8 +200,15 @@ impl<A: Accept<Connection>> Future for AcceptFuture<A> {
                                 peek_buf,
                                 socket,
                                 meta: AcceptMeta { accept, addrs },
+                                require_sni,
                                 ..
                             } = try_tls.take().expect("polled after complete");
+                            if require_sni
+                                && conditional_accept::is_client_hello_without_sni(&peek_buf)
+                            {
+                                debug!("rejecting TLS connection without SNI");
+                                return Err(MissingSni(()).into());
+                            }
                             let meta = Meta {
                                 addrs,
                                 peer_identity: Conditional::None(
@@ -257,6 +281,24 @@ impl<A: Accept<Connection>> TryTls<A> {
     }
 }
 
+// === impl Meta ===
+
+impl super::HasPeerIdentity for Meta {
+    fn peer_identity(&self) -> super::PeerIdentity {
+        self.peer_identity.clone()
+    }
+}
+
+// === impl MissingSni ===
+
+impl fmt::Display for MissingSni {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TLS ClientHello has no SNI")
+    }
+}
+
+impl error::Error for MissingSni {}
+
 fn client_identity<S>(tls: &tokio_rustls::server::TlsStream<S>) -> Option<identity::Name> {
     use rustls::Session;
     use webpki::GeneralDNSNameRef;
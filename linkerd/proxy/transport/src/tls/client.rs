@@ -1,5 +1,6 @@
+use super::peer_name::UriSan;
 use crate::io::BoxedIo;
-use futures::{try_ready, Future, Poll};
+use futures::{try_ready, Async, Future, Poll};
 use linkerd2_conditional::Conditional;
 use linkerd2_identity as identity;
 pub use rustls::ClientConfig as Config;
@@ -12,41 +13,89 @@ pub trait HasConfig {
     fn tls_client_config(&self) -> Arc<Config>;
 }
 
+/// Observes how each connection is established, so that the time spent
+/// connecting may be distinguished from the time spent in the TLS handshake.
+pub trait Observe<T> {
+    type Observer: Observer;
+
+    fn observe(&self, target: &T) -> Self::Observer;
+}
+
+/// Observes the establishment of a single connection.
+///
+/// If the connection is abandoned before it is established (e.g. because a
+/// timeout elapsed), the observer is dropped without being notified.
+pub trait Observer {
+    /// Called once the TCP connection has been established.
+    fn connected(&mut self);
+
+    /// Called when a TLS handshake is initiated on the connection.
+    fn handshake_started(&mut self);
+
+    /// Called when the TLS handshake completes successfully.
+    fn handshake_succeeded(&mut self);
+
+    /// Called when the TLS handshake fails.
+    fn handshake_failed(&mut self, error: &io::Error);
+}
+
 #[derive(Clone, Debug)]
-pub struct Layer<L>(super::Conditional<L>);
+pub struct Layer<L, O = ()> {
+    local: super::Conditional<L>,
+    observe: O,
+}
 
 #[derive(Clone, Debug)]
-pub struct Connect<L, C> {
+pub struct Connect<L, C, O = ()> {
     local: super::Conditional<L>,
+    observe: O,
     inner: C,
 }
 
 pub type Connection = BoxedIo;
 
 /// A socket that is in the process of connecting.
-pub enum ConnectFuture<L, F: Future> {
+pub struct ConnectFuture<L, F: Future, O = ()> {
+    state: State<L, F>,
+    observer: O,
+}
+
+enum State<L, F: Future> {
     Init {
         future: F,
         tls: super::Conditional<(identity::Name, L)>,
+        uri_san: Option<UriSan>,
     },
     Handshake(tokio_rustls::Connect<F::Item>),
 }
 
 // === impl Layer ===
 
-pub fn layer<L: HasConfig + Clone>(l: super::Conditional<L>) -> Layer<L> {
-    Layer(l)
+pub fn layer<L: HasConfig + Clone>(local: super::Conditional<L>) -> Layer<L> {
+    Layer { local, observe: () }
+}
+
+impl<L> Layer<L> {
+    /// Notifies `observe` as each connection is established.
+    pub fn with_observe<O>(self, observe: O) -> Layer<L, O> {
+        Layer {
+            local: self.local,
+            observe,
+        }
+    }
 }
 
-impl<L, C> tower::layer::Layer<C> for Layer<L>
+impl<L, C, O> tower::layer::Layer<C> for Layer<L, O>
 where
     L: HasConfig + Clone,
+    O: Clone,
 {
-    type Service = Connect<L, C>;
+    type Service = Connect<L, C, O>;
 
     fn layer(&self, inner: C) -> Self::Service {
         Connect {
-            local: self.0.clone(),
+            local: self.local.clone(),
+            observe: self.observe.clone(),
             inner,
         }
     }
@@ -55,10 +104,11 @@ where
 // === impl Connect ===
 
 /// impl MakeConnection
-impl<L, C, Target> tower::Service<Target> for Connect<L, C>
+impl<L, C, O, Target> tower::Service<Target> for Connect<L, C, O>
 where
     Target: super::HasPeerIdentity,
     L: HasConfig + Clone,
+    O: Observe<Target>,
     C: tower::MakeConnection<Target, Connection = TcpStream>,
     C::Future: Send + 'static,
     C::Error: ::std::error::Error + Send + Sync + 'static,
@@ -66,7 +116,7 @@ where
 {
     type Response = Connection;
     type Error = C::Error;
-    type Future = ConnectFuture<L, C::Future>;
+    type Future = ConnectFuture<L, C::Future, O::Observer>;
 
     fn poll_ready(&mut self) -> Poll<(), Self::Error> {
         self.inner.poll_ready()
@@ -78,35 +128,61 @@ where
             .local
             .clone()
             .and_then(|l| peer_identity.map(|n| (n, l)));
-        ConnectFuture::Init {
-            future: self.inner.make_connection(target),
-            tls,
+        let uri_san = target.peer_uri_san();
+        let observer = self.observe.observe(&target);
+        ConnectFuture {
+            state: State::Init {
+                future: self.inner.make_connection(target),
+                tls,
+                uri_san,
+            },
+            observer,
         }
     }
 }
 
 // ===== impl ConnectFuture =====
 
-impl<L, F> Future for ConnectFuture<L, F>
+impl<L, F, O> Future for ConnectFuture<L, F, O>
 where
     L: HasConfig,
     F: Future<Item = TcpStream>,
     F::Error: From<io::Error>,
+    O: Observer,
 {
     type Item = Connection;
     type Error = F::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         loop {
-            *self = match self {
-                ConnectFuture::Init { future, tls } => {
+            self.state = match self.state {
+                State::Init {
+                    ref mut future,
+                    ref mut tls,
+                    ref uri_san,
+                } => {
                     let io = try_ready!(future.poll());
+                    self.observer.connected();
 
                     match tls {
                         Conditional::Some((peer_identity, local_tls)) => {
-                            trace!(peer.id = %peer_identity, "initiating TLS");
-                            ConnectFuture::Handshake(
-                                tokio_rustls::TlsConnector::from(local_tls.tls_client_config())
+                            let config = match uri_san {
+                                Some(uri_san) => {
+                                    trace!(
+                                        peer.id = %peer_identity,
+                                        peer.san = %uri_san,
+                                        "initiating TLS"
+                                    );
+                                    uri_san.client_config(&local_tls.tls_client_config())
+                                }
+                                None => {
+                                    trace!(peer.id = %peer_identity, "initiating TLS");
+                                    local_tls.tls_client_config()
+                                }
+                            };
+                            self.observer.handshake_started();
+                            State::Handshake(
+                                tokio_rustls::TlsConnector::from(config)
                                     .connect(peer_identity.as_dns_name_ref(), io),
                             )
                         }
@@ -116,16 +192,42 @@ where
                         }
                     }
                 }
-                ConnectFuture::Handshake(ref mut fut) => {
-                    let io = try_ready!(fut.poll());
-                    trace!("established TLS");
-                    return Ok(Connection::new(io).into());
-                }
+                State::Handshake(ref mut fut) => match fut.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(io)) => {
+                        trace!("established TLS");
+                        self.observer.handshake_succeeded();
+                        return Ok(Connection::new(io).into());
+                    }
+                    Err(e) => {
+                        trace!(error = %e, "TLS handshake failed");
+                        self.observer.handshake_failed(&e);
+                        return Err(e.into());
+                    }
+                },
             };
         }
     }
 }
 
+// === impl Observer ===
+
+impl<T> Observe<T> for () {
+    type Observer = ();
+
+    fn observe(&self, _: &T) -> Self::Observer {}
+}
+
+impl Observer for () {
+    fn connected(&mut self) {}
+
+    fn handshake_started(&mut self) {}
+
+    fn handshake_succeeded(&mut self) {}
+
+    fn handshake_failed(&mut self, _: &io::Error) {}
+}
+
 impl HasConfig for identity::CrtKey {
     fn tls_client_config(&self) -> Arc<Config> {
         identity::CrtKey::tls_client_config(self)
@@ -24,12 +24,7 @@ pub enum Match {
 /// record, which is what all reasonable implementations do. (If they were not
 /// to, they wouldn't interoperate with picky servers.)
 pub fn match_client_hello(input: &[u8], identity: &identity::Name) -> Match {
-    let r = untrusted::Input::from(input).read_all(untrusted::EndOfInput, |input| {
-        let r = extract_sni(input);
-        input.skip_to_end(); // Ignore anything after what we parsed.
-        r
-    });
-    match r {
+    match read_sni(input) {
         Ok(Some(sni)) => {
             let m = identity::Name::from_hostname(sni.as_slice_less_safe())
                 .map(|sni| {
@@ -57,6 +52,48 @@ pub fn match_client_hello(input: &[u8], identity: &identity::Name) -> Match {
     }
 }
 
+/// Determines whether the given `input` begins with a TLS record carrying a
+/// ClientHello, without regard to its SNI.
+///
+/// Only the record header and the handshake message's type and length are
+/// inspected, and they must match exactly, so that other binary protocols
+/// are not mistaken for TLS.
+pub fn is_client_hello(input: &[u8]) -> bool {
+    input.len() >= 7
+        // ContentType::handshake
+        && input[0] == 22
+        // legacy_record_version is 0x0301 or 0x0303.
+        && input[1] == 0x03
+        && (input[2] == 0x01 || input[2] == 0x03)
+        // HandshakeType::client_hello, with a length that fits in a `u16`.
+        && input[5] == 1
+        && input[6] == 0
+}
+
+/// Determines whether the given `input` is a complete ClientHello in which no
+/// SNI extension can be found.
+pub fn is_client_hello_without_sni(input: &[u8]) -> bool {
+    match read_sni(input) {
+        Ok(None) => is_client_hello(input),
+        Ok(Some(_)) | Err(untrusted::EndOfInput) => false,
+    }
+}
+
+/// Returns the SNI of the ClientHello at the start of `input`, if one can be
+/// parsed.
+pub fn client_hello_sni(input: &[u8]) -> Option<identity::Name> {
+    let sni = read_sni(input).ok()??;
+    identity::Name::from_hostname(sni.as_slice_less_safe()).ok()
+}
+
+fn read_sni(input: &[u8]) -> Result<Option<untrusted::Input<'_>>, untrusted::EndOfInput> {
+    untrusted::Input::from(input).read_all(untrusted::EndOfInput, |input| {
+        let r = extract_sni(input);
+        input.skip_to_end(); // Ignore anything after what we parsed.
+        r
+    })
+}
+
 /// The result is `Ok(Some(hostname))` if the SNI extension was found, `Ok(None)`
 /// if we affirmatively rejected the input before we found the SNI extension, or
 /// `Err(EndOfInput)` if we don't have enough input to continue.
@@ -237,6 +274,36 @@ mod tests {
         );
     }
 
+    #[test]
+    fn detects_client_hello() {
+        assert!(is_client_hello(VALID_EXAMPLE_COM));
+        assert_eq!(
+            client_hello_sni(VALID_EXAMPLE_COM),
+            identity::Name::from_hostname(b"example.com").ok()
+        );
+    }
+
+    #[test]
+    fn detects_client_hello_with_sni() {
+        assert!(!is_client_hello_without_sni(VALID_EXAMPLE_COM));
+        // Until the ClientHello is complete, its SNI may yet be found.
+        assert!(!is_client_hello_without_sni(&VALID_EXAMPLE_COM[..64]));
+        assert!(!is_client_hello_without_sni(
+            b"GET /TheProject.html HTTP/1.0\r\n\r\n"
+        ));
+    }
+
+    #[test]
+    fn does_not_detect_other_protocols() {
+        assert!(!is_client_hello(b"GET /TheProject.html HTTP/1.0\r\n\r\n"));
+        // A PostgreSQL SSLRequest.
+        assert!(!is_client_hello(&[0, 0, 0, 8, 4, 210, 22, 47]));
+        // A TLS alert record.
+        assert!(!is_client_hello(&[21, 3, 3, 0, 2, 2, 40]));
+        // Too short to tell.
+        assert!(!is_client_hello(&VALID_EXAMPLE_COM[..6]));
+    }
+
     fn check_all_prefixes(expected_match: Match, identity: &str, input: &[u8]) {
         assert!(expected_match == Match::Matched || expected_match == Match::NotMatched);
 
@@ -5,8 +5,11 @@ use std::fmt;
 pub mod accept;
 pub mod client;
 mod conditional_accept;
+pub mod originate;
+pub mod peer_name;
 
 pub use self::accept::AcceptTls;
+pub use self::conditional_accept::{client_hello_sni, is_client_hello};
 
 /// Describes whether or not a connection was secured with TLS and, if it was
 /// not, the reason why.
@@ -16,6 +19,12 @@ pub type PeerIdentity = Conditional<identity::Name>;
 
 pub trait HasPeerIdentity {
     fn peer_identity(&self) -> PeerIdentity;
+
+    /// Returns a URI SAN that the peer's certificate must present, in which
+    /// case its identity is only sent as the SNI.
+    fn peer_uri_san(&self) -> Option<peer_name::UriSan> {
+        None
+    }
 }
 
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
@@ -0,0 +1,283 @@
+//! Originates TLS to servers outside of the mesh.
+//!
+//! Mesh TLS authenticates a server by its Linkerd identity. When TLS is
+//! originated, the application's plaintext is instead sent over a TLS
+//! connection whose server certificate is verified against a set of trusted
+//! roots and the destination's DNS name, as a web client would.
+
+use super::client;
+use crate::io::BoxedIo;
+use futures::{try_ready, Future, Poll};
+use linkerd2_dns_name::{Name, Suffix};
+use linkerd2_error::Error;
+use linkerd2_identity as identity;
+use std::sync::Arc;
+use std::{error, fmt, io};
+use tracing::{debug, trace, warn};
+
+/// Implemented by targets to which TLS may be originated.
+pub trait HasOrigination {
+    fn tls_origination(&self) -> Option<Origination>;
+}
+
+/// Determines, by destination name, whether TLS is originated.
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    rules: Arc<Vec<Rule>>,
+}
+
+/// Originates TLS to destinations matching `suffix`.
+#[derive(Clone)]
+pub struct Rule {
+    suffix: Suffix,
+    client_config: Arc<client::Config>,
+    server_name: Option<identity::Name>,
+}
+
+/// Describes the TLS connection originated to a target.
+#[derive(Clone)]
+pub struct Origination {
+    server_name: identity::Name,
+    client_config: Arc<client::Config>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Layer(());
+
+#[derive(Clone, Debug)]
+pub struct Connect<C> {
+    inner: C,
+}
+
+pub enum ConnectFuture<F> {
+    Init {
+        future: F,
+        origination: Option<Origination>,
+    },
+    Handshake {
+        future: tokio_rustls::Connect<BoxedIo>,
+        server_name: identity::Name,
+    },
+}
+
+/// Indicates that a TLS connection could not be originated, e.g. because
+/// the server's certificate is not trusted.
+#[derive(Debug)]
+pub struct HandshakeFailed {
+    server_name: identity::Name,
+    source: io::Error,
+}
+
+pub fn layer() -> Layer {
+    Layer(())
+}
+
+// === impl Config ===
+
+impl Config {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        Self {
+            rules: Arc::new(rules),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Returns how TLS is originated to `dst`, using the first rule whose
+    /// suffix matches it.
+    pub fn origination(&self, dst: &Name) -> Option<Origination> {
+        let rule = self.rules.iter().find(|r| r.suffix.contains(dst))?;
+        let server_name = rule
+            .server_name
+            .clone()
+            .unwrap_or_else(|| dst.clone().into());
+        Some(Origination {
+            server_name,
+            client_config: rule.client_config.clone(),
+        })
+    }
+}
+
+// === impl Rule ===
+
+impl Rule {
+    /// Returns a rule that verifies servers' certificates against the roots
+    /// in `roots_pem`, or `None` if it contains no roots.
+    ///
+    /// If `server_name` is set, it is sent as the SNI and the certificate is
+    /// verified against it. Otherwise, the destination's name is used.
+    pub fn new(
+        suffix: Suffix,
+        roots_pem: &[u8],
+        server_name: Option<identity::Name>,
+    ) -> Option<Self> {
+        let mut roots = rustls::RootCertStore::empty();
+        let (added, skipped) = roots.add_pem_file(&mut io::Cursor::new(roots_pem)).ok()?;
+        if skipped != 0 {
+            warn!(%suffix, "skipped {} roots in CA bundle", skipped);
+        }
+        if added == 0 {
+            return None;
+        }
+
+        let mut client_config = client::Config::new();
+        client_config.root_store = roots;
+
+        Some(Self {
+            suffix,
+            client_config: Arc::new(client_config),
+            server_name,
+        })
+    }
+}
+
+impl fmt::Debug for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Rule")
+            .field("suffix", &self.suffix)
+            .field("server_name", &self.server_name)
+            .finish()
+    }
+}
+
+// === impl Origination ===
+
+impl Origination {
+    pub fn server_name(&self) -> &identity::Name {
+        &self.server_name
+    }
+}
+
+impl fmt::Debug for Origination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Origination")
+            .field("server_name", &self.server_name)
+            .finish()
+    }
+}
+
+/// Originations are equal if they use the same client configuration, so that
+/// targets that embed them may be compared.
+impl PartialEq for Origination {
+    fn eq(&self, other: &Self) -> bool {
+        self.server_name == other.server_name
+            && Arc::ptr_eq(&self.client_config, &other.client_config)
+    }
+}
+
+impl Eq for Origination {}
+
+// === impl Layer ===
+
+impl<C> tower::layer::Layer<C> for Layer {
+    type Service = Connect<C>;
+
+    fn layer(&self, inner: C) -> Self::Service {
+        Connect { inner }
+    }
+}
+
+// === impl Connect ===
+
+/// impl MakeConnection
+impl<C, Target> tower::Service<Target> for Connect<C>
+where
+    Target: HasOrigination,
+    C: tower::MakeConnection<Target, Connection = BoxedIo>,
+    C::Error: Into<Error>,
+{
+    type Response = BoxedIo;
+    type Error = Error;
+    type Future = ConnectFuture<C::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, target: Target) -> Self::Future {
+        let origination = target.tls_origination();
+        ConnectFuture::Init {
+            future: self.inner.make_connection(target),
+            origination,
+        }
+    }
+}
+
+// === impl ConnectFuture ===
+
+impl<F> Future for ConnectFuture<F>
+where
+    F: Future<Item = BoxedIo>,
+    F::Error: Into<Error>,
+{
+    type Item = BoxedIo;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            *self = match self {
+                ConnectFuture::Init {
+                    future,
+                    origination,
+                } => {
+                    let io = try_ready!(future.poll().map_err(Into::into));
+                    match origination.take() {
+                        Some(Origination {
+                            server_name,
+                            client_config,
+                        }) => {
+                            trace!(server.name = %server_name, "originating TLS");
+                            let future = tokio_rustls::TlsConnector::from(client_config)
+                                .connect(server_name.as_dns_name_ref(), io);
+                            ConnectFuture::Handshake {
+                                future,
+                                server_name,
+                            }
+                        }
+                        None => return Ok(io.into()),
+                    }
+                }
+                ConnectFuture::Handshake {
+                    future,
+                    server_name,
+                } => {
+                    let io = try_ready!(future.poll().map_err(|source| {
+                        debug!(server.name = %server_name, %source, "TLS origination failed");
+                        HandshakeFailed {
+                            server_name: server_name.clone(),
+                            source,
+                        }
+                    }));
+                    trace!(server.name = %server_name, "originated TLS");
+                    return Ok(BoxedIo::new(io).into());
+                }
+            };
+        }
+    }
+}
+
+// === impl HandshakeFailed ===
+
+impl HandshakeFailed {
+    pub fn server_name(&self) -> &identity::Name {
+        &self.server_name
+    }
+}
+
+impl fmt::Display for HandshakeFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to originate TLS to {}: {}",
+            self.server_name, self.source
+        )
+    }
+}
+
+impl error::Error for HandshakeFailed {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
@@ -0,0 +1,454 @@
+//! Maps peers to the names that their certificates are verified against.
+//!
+//! Mesh TLS sends a peer's identity as the SNI and verifies that the peer's
+//! certificate is valid for it as a DNS name. Workloads in an adjacent,
+//! SPIFFE-based mesh instead present certificates that identify them by a
+//! `spiffe://` URI SAN. For peers that match a configured rule, the expected
+//! URI is constructed from a template and the certificate is verified against
+//! it. Verification fails if the certificate doesn't present that URI.
+
+use super::client;
+use linkerd2_dns_name::{Name, Suffix};
+use linkerd2_identity as identity;
+use rustls::{RootCertStore, ServerCertVerified, ServerCertVerifier, TLSError};
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::time::SystemTime;
+use std::{error, fmt};
+use untrusted::{EndOfInput, Input, Reader};
+
+/// Determines, by destination authority or by identity, the name that a
+/// peer's certificate is verified against.
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    rules: Arc<Vec<Rule>>,
+}
+
+/// Maps the peers matching `matches` to names of the form `san`.
+#[derive(Clone, Debug)]
+pub struct Rule {
+    matches: Match,
+    san: Form,
+}
+
+/// Describes which peers a rule applies to.
+#[derive(Clone, Debug)]
+pub enum Match {
+    /// Matches peers whose destination authority has the suffix.
+    Authority(Suffix),
+    /// Matches peers whose discovered identity has the suffix.
+    Identity(Suffix),
+}
+
+/// The form of the SAN that a peer's certificate must present.
+#[derive(Clone, Debug)]
+pub enum Form {
+    /// The certificate must be valid for the matched name as a DNS name.
+    Dns,
+    /// The certificate must present the URI rendered from the template.
+    Uri(Template),
+}
+
+/// A URI template.
+///
+/// `{name}` is replaced by the matched name, and `{N}` by its `N`th label,
+/// counting from 1. E.g. `spiffe://cluster.local/ns/{2}/sa/{1}` renders
+/// `spiffe://cluster.local/ns/ns1/sa/foo` for `foo.ns1.serviceaccount...`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Template(Vec<Segment>);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Segment {
+    Literal(String),
+    Name,
+    Label(usize),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InvalidTemplate(());
+
+/// The name by which a peer is identified when TLS is established.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct PeerName {
+    name: identity::Name,
+    uri: Option<UriSan>,
+}
+
+/// A URI that a peer's certificate must present as a SAN.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct UriSan(Arc<str>);
+
+/// Verifies that a server's certificate chains to a trusted root and that it
+/// presents the expected URI SAN.
+struct Verifier(UriSan);
+
+static SUPPORTED_SIG_ALGS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+    &webpki::RSA_PKCS1_3072_8192_SHA384,
+];
+
+// === impl Config ===
+
+impl Config {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        Self {
+            rules: Arc::new(rules),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Returns the name by which a peer is identified, using the first rule
+    /// that matches its destination authority or its identity.
+    pub fn peer_name(
+        &self,
+        authority: Option<&Name>,
+        identity: Option<&identity::Name>,
+    ) -> Option<PeerName> {
+        self.rules
+            .iter()
+            .filter_map(|rule| rule.peer_name(authority, identity))
+            .next()
+    }
+}
+
+// === impl Rule ===
+
+impl Rule {
+    pub fn new(matches: Match, san: Form) -> Self {
+        Self { matches, san }
+    }
+
+    /// Returns the name by which a matching peer is identified. A rule whose
+    /// template can't be rendered for the matched name doesn't match.
+    fn peer_name(
+        &self,
+        authority: Option<&Name>,
+        identity: Option<&identity::Name>,
+    ) -> Option<PeerName> {
+        let name = match self.matches {
+            Match::Authority(ref suffix) => authority
+                .filter(|a| suffix.contains(a))
+                .map(|a| identity::Name::from(a.clone()))?,
+            Match::Identity(ref suffix) => identity
+                .filter(|id| {
+                    Name::try_from(id.as_ref().as_bytes())
+                        .map(|n| suffix.contains(&n))
+                        .unwrap_or(false)
+                })
+                .cloned()?,
+        };
+        let uri = match self.san {
+            Form::Dns => None,
+            Form::Uri(ref template) => Some(UriSan(template.render(name.as_ref())?.into())),
+        };
+        Some(PeerName { name, uri })
+    }
+}
+
+// === impl Template ===
+
+impl Template {
+    pub fn parse(s: &str) -> Result<Self, InvalidTemplate> {
+        let mut segments = Vec::new();
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            if rest[..start].contains('}') {
+                return Err(InvalidTemplate(()));
+            }
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_owned()));
+            }
+            let end = rest[start..].find('}').ok_or(InvalidTemplate(()))? + start;
+            let segment = match &rest[start + 1..end] {
+                "name" => Segment::Name,
+                label => match label.parse::<usize>() {
+                    Ok(n) if n > 0 => Segment::Label(n),
+                    _ => return Err(InvalidTemplate(())),
+                },
+            };
+            segments.push(segment);
+            rest = &rest[end + 1..];
+        }
+        if rest.contains('}') {
+            return Err(InvalidTemplate(()));
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_owned()));
+        }
+        if segments.is_empty() {
+            return Err(InvalidTemplate(()));
+        }
+        Ok(Template(segments))
+    }
+
+    /// Renders the template for `name`, unless it refers to a label that
+    /// `name` doesn't have.
+    fn render(&self, name: &str) -> Option<String> {
+        let labels = name.split('.').collect::<Vec<_>>();
+        let mut uri = String::new();
+        for segment in &self.0 {
+            match segment {
+                Segment::Literal(s) => uri.push_str(s),
+                Segment::Name => uri.push_str(name),
+                Segment::Label(n) => uri.push_str(labels.get(n - 1)?),
+            }
+        }
+        Some(uri)
+    }
+}
+
+impl fmt::Display for InvalidTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid URI template")
+    }
+}
+
+impl error::Error for InvalidTemplate {}
+
+// === impl PeerName ===
+
+impl PeerName {
+    /// The name sent as the SNI and, if no URI is expected, against which
+    /// the peer's certificate is verified.
+    pub fn name(&self) -> &identity::Name {
+        &self.name
+    }
+
+    pub fn uri(&self) -> Option<&UriSan> {
+        self.uri.as_ref()
+    }
+}
+
+// === impl UriSan ===
+
+impl UriSan {
+    /// Returns a copy of `config` that verifies servers' certificates against
+    /// this URI rather than the SNI name.
+    pub(super) fn client_config(&self, config: &client::Config) -> Arc<client::Config> {
+        let mut config = config.clone();
+        config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(Verifier(self.clone())));
+        Arc::new(config)
+    }
+}
+
+impl AsRef<str> for UriSan {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for UriSan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+// === impl Verifier ===
+
+impl ServerCertVerifier for Verifier {
+    fn verify_server_cert(
+        &self,
+        roots: &RootCertStore,
+        presented_certs: &[rustls::Certificate],
+        _: webpki::DNSNameRef<'_>,
+        _: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        let (leaf, intermediates) = presented_certs
+            .split_first()
+            .ok_or(TLSError::NoCertificatesPresented)?;
+        let cert = webpki::EndEntityCert::from(&leaf.0).map_err(TLSError::WebPKIError)?;
+        let intermediates = intermediates
+            .iter()
+            .map(|c| c.0.as_ref())
+            .collect::<Vec<_>>();
+        let anchors = roots
+            .roots
+            .iter()
+            .map(|r| r.to_trust_anchor())
+            .collect::<Vec<_>>();
+        let now = webpki::Time::try_from(SystemTime::now())
+            .map_err(|_| TLSError::FailedToGetCurrentTime)?;
+        cert.verify_is_valid_tls_server_cert(
+            SUPPORTED_SIG_ALGS,
+            &webpki::TLSServerTrustAnchors(&anchors),
+            &intermediates,
+            now,
+        )
+        .map_err(TLSError::WebPKIError)?;
+
+        // The certificate is trusted, so its SANs may be read without
+        // webpki's more careful parsing.
+        let expected = (self.0).0.as_bytes();
+        let sans =
+            uri_sans(&leaf.0).map_err(|EndOfInput| TLSError::WebPKIError(webpki::Error::BadDER))?;
+        if !sans.iter().any(|san| *san == expected) {
+            return Err(TLSError::WebPKIError(webpki::Error::CertNotValidForName));
+        }
+
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+const SEQUENCE: u8 = 0x30;
+const OCTET_STRING: u8 = 0x04;
+const OID: u8 = 0x06;
+/// The tag of a certificate's explicitly-tagged extensions, `[3]`.
+const EXTENSIONS: u8 = 0xa3;
+/// The tag of a `uniformResourceIdentifier` general name, `[6]`.
+const URI: u8 = 0x86;
+/// The OID of the subject alternative name extension, 2.5.29.17.
+const SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+
+/// Returns the URI SANs of a DER-encoded certificate.
+fn uri_sans(der: &[u8]) -> Result<Vec<&[u8]>, EndOfInput> {
+    let cert = expect(&mut Reader::new(Input::from(der)), SEQUENCE)?;
+    let tbs = expect(&mut Reader::new(cert), SEQUENCE)?;
+
+    let mut sans = Vec::new();
+    let mut tbs = Reader::new(tbs);
+    while !tbs.at_end() {
+        let (tag, value) = read_tlv(&mut tbs)?;
+        if tag != EXTENSIONS {
+            continue;
+        }
+
+        let mut extensions = Reader::new(expect(&mut Reader::new(value), SEQUENCE)?);
+        while !extensions.at_end() {
+            let mut extension = Reader::new(expect(&mut extensions, SEQUENCE)?);
+            if expect(&mut extension, OID)?.as_slice_less_safe() != SUBJECT_ALT_NAME {
+                continue;
+            }
+
+            // The extension's value may be preceded by its `critical` flag.
+            let mut value = read_tlv(&mut extension)?;
+            if value.0 != OCTET_STRING {
+                value = read_tlv(&mut extension)?;
+            }
+            let mut names = Reader::new(expect(&mut Reader::new(value.1), SEQUENCE)?);
+            while !names.at_end() {
+                let (tag, name) = read_tlv(&mut names)?;
+                if tag == URI {
+                    sans.push(name.as_slice_less_safe());
+                }
+            }
+        }
+    }
+    Ok(sans)
+}
+
+fn expect<'a>(r: &mut Reader<'a>, tag: u8) -> Result<Input<'a>, EndOfInput> {
+    match read_tlv(r)? {
+        (t, value) if t == tag => Ok(value),
+        _ => Err(EndOfInput),
+    }
+}
+
+fn read_tlv<'a>(r: &mut Reader<'a>) -> Result<(u8, Input<'a>), EndOfInput> {
+    let tag = r.read_byte()?;
+    let len = match r.read_byte()? {
+        n if n & 0x80 == 0 => usize::from(n),
+        n @ 0x81..=0x83 => {
+            let mut len = 0;
+            for _ in 0..(n & 0x7f) {
+                len = (len << 8) | usize::from(r.read_byte()?);
+            }
+            len
+        }
+        _ => return Err(EndOfInput),
+    };
+    let value = r.read_bytes(len)?;
+    Ok((tag, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates_render_labels() {
+        let template = Template::parse("spiffe://cluster.local/ns/{2}/sa/{1}").unwrap();
+        assert_eq!(
+            template
+                .render("foo.ns1.serviceaccount.identity.linkerd.cluster.local")
+                .as_ref()
+                .map(String::as_str),
+            Some("spiffe://cluster.local/ns/ns1/sa/foo")
+        );
+        assert_eq!(template.render("foo"), None, "labels must exist");
+
+        let template = Template::parse("spiffe://{name}").unwrap();
+        assert_eq!(
+            template.render("web.ns").as_ref().map(String::as_str),
+            Some("spiffe://web.ns")
+        );
+    }
+
+    #[test]
+    fn invalid_templates() {
+        for t in &[
+            "",
+            "spiffe://{",
+            "spiffe://{ns}",
+            "spiffe://{0}",
+            "spiffe://}",
+        ] {
+            assert_eq!(Template::parse(t), Err(InvalidTemplate(())), "{}", t);
+        }
+    }
+
+    #[test]
+    fn first_matching_rule_is_used() {
+        let suffix = |s: &str| Suffix::try_from(s).unwrap();
+        let template = Template::parse("spiffe://cluster.local/ns/{2}/sa/{1}").unwrap();
+        let config = Config::new(vec![
+            Rule::new(Match::Authority(suffix("legacy.test")), Form::Dns),
+            Rule::new(
+                Match::Identity(suffix("cluster.local")),
+                Form::Uri(template),
+            ),
+        ]);
+
+        let authority = Name::try_from("web.legacy.test".as_bytes()).unwrap();
+        let id = identity::Name::from_hostname(b"foo.ns1.serviceaccount.cluster.local").unwrap();
+
+        let dns = config.peer_name(Some(&authority), Some(&id)).unwrap();
+        assert_eq!(dns.name().as_ref(), "web.legacy.test");
+        assert_eq!(dns.uri(), None);
+
+        let uri = config.peer_name(None, Some(&id)).unwrap();
+        assert_eq!(uri.name(), &id, "the identity is sent as the SNI");
+        assert_eq!(
+            uri.uri().map(AsRef::as_ref),
+            Some("spiffe://cluster.local/ns/ns1/sa/foo")
+        );
+
+        let other = identity::Name::from_hostname(b"foo.ns1.example.com").unwrap();
+        assert_eq!(config.peer_name(None, Some(&other)), None);
+    }
+
+    #[test]
+    fn uri_sans_are_read() {
+        let der = include_bytes!("../../../../identity/src/testdata/foo-ns1-ca1-spiffe/crt.der");
+        assert_eq!(
+            uri_sans(der).unwrap(),
+            vec![&b"spiffe://cluster.local/ns/ns1/sa/foo"[..]]
+        );
+
+        let der = include_bytes!("../../../../identity/src/testdata/foo-ns1-ca1/crt.der");
+        assert!(uri_sans(der).unwrap().is_empty(), "DNS SANs are ignored");
+    }
+}
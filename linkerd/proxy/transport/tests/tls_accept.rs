@@ -74,6 +74,83 @@ fn proxy_to_proxy_tls_pass_through_when_identity_does_not_match() {
     assert_eq!(&server_result.result.unwrap()[..], START_OF_TLS);
 }
 
+#[test]
+fn proxy_to_proxy_tls_works_when_sni_is_required() {
+    let server_tls = test_util::FOO_NS1.validate().unwrap();
+    let client_tls = test_util::BAR_NS1.validate().unwrap();
+    let (client_result, server_result) = run_test_with(
+        Options {
+            require_sni: true,
+            ..Options::default()
+        },
+        Conditional::Some((client_tls, server_tls.tls_server_name())),
+        |conn| write_then_read(conn, PING),
+        Conditional::Some(server_tls),
+        |(_, conn)| read_then_write(conn, PING.len(), PONG),
+    );
+    let server_result = server_result.expect("server must accept the connection");
+    assert_eq!(client_result.is_tls(), true);
+    assert_eq!(&client_result.result.expect("pong")[..], PONG);
+    assert_eq!(server_result.is_tls(), true);
+    assert_eq!(&server_result.result.expect("ping")[..], PING);
+}
+
+#[test]
+fn proxy_to_proxy_tls_pass_through_without_sni() {
+    let server_tls = test_util::FOO_NS1.validate().unwrap();
+    let client_tls = test_util::BAR_NS1.validate().unwrap();
+    let (client_result, server_result) = run_test_with(
+        Options {
+            disable_client_sni: true,
+            ..Options::default()
+        },
+        Conditional::Some((client_tls, server_tls.tls_server_name())),
+        |conn| write_then_read(conn, PING),
+        Conditional::Some(server_tls),
+        |(_, conn)| read_then_write(conn, START_OF_TLS.len(), PONG),
+    );
+
+    // Without an SNI, the server can't tell that the connection is meant
+    // for it, so the TLS client hello is passed through.
+    let server_result = server_result.expect("server must accept the connection");
+    assert_eq!(client_result.is_tls(), false);
+    assert!(client_result.result.is_err());
+    assert_eq!(server_result.is_tls(), false);
+    assert_eq!(&server_result.result.unwrap()[..], START_OF_TLS);
+}
+
+#[test]
+fn proxy_to_proxy_tls_rejected_without_sni_when_sni_is_required() {
+    let server_tls = test_util::FOO_NS1.validate().unwrap();
+    let client_tls = test_util::BAR_NS1.validate().unwrap();
+    let (client_result, server_result) = run_test_with(
+        Options {
+            disable_client_sni: true,
+            require_sni: true,
+        },
+        Conditional::Some((client_tls, server_tls.tls_server_name())),
+        |conn| write_then_read(conn, PING),
+        Conditional::Some(server_tls),
+        |(_, conn)| read_then_write(conn, START_OF_TLS.len(), PONG),
+    );
+
+    assert!(
+        server_result.is_none(),
+        "server must not accept the connection"
+    );
+    assert_eq!(client_result.is_tls(), false);
+    assert!(client_result.result.is_err());
+}
+
+/// Varies how a test's connection is established.
+#[derive(Copy, Clone, Debug, Default)]
+struct Options {
+    /// Whether the client omits the SNI from its ClientHello.
+    disable_client_sni: bool,
+    /// Whether the server rejects TLS connections without an SNI.
+    require_sni: bool,
+}
+
 struct Transported<R> {
     /// The value of `Connection::peer_identity()` for the established connection.
     ///
@@ -102,6 +179,30 @@ fn run_test<C, CF, CR, S, SF, SR>(
     server_tls: tls::Conditional<CrtKey>,
     server: S,
 ) -> (Transported<CR>, Transported<SR>)
+where
+    // Client
+    C: FnOnce(ClientConnection) -> CF + Clone + Send + 'static,
+    CF: Future<Item = CR, Error = io::Error> + Send + 'static,
+    CR: Send + 'static,
+    // Server
+    S: Fn(ServerConnection) -> SF + Clone + Send + 'static,
+    SF: Future<Item = SR, Error = io::Error> + Send + 'static,
+    SR: Send + 'static,
+{
+    let (client_result, server_result) =
+        run_test_with(Options::default(), client_tls, client, server_tls, server);
+    (client_result, server_result.expect("server complete"))
+}
+
+/// Like `run_test`, except that the server's result is `None` if the server
+/// rejected the connection.
+fn run_test_with<C, CF, CR, S, SF, SR>(
+    options: Options,
+    client_tls: tls::Conditional<(CrtKey, Name)>,
+    client: C,
+    server_tls: tls::Conditional<CrtKey>,
+    server: S,
+) -> (Transported<CR>, Option<Transported<SR>>)
 where
     // Client
     C: FnOnce(ClientConnection) -> CF + Clone + Send + 'static,
@@ -122,7 +223,10 @@ where
 
     let (client_tls, client_target_name) = match client_tls {
         Conditional::Some((crtkey, name)) => (
-            Conditional::Some(ClientTls(crtkey)),
+            Conditional::Some(ClientTls {
+                crtkey,
+                disable_sni: options.disable_client_sni,
+            }),
             Conditional::Some(name),
         ),
         Conditional::None(reason) => (Conditional::None(reason.clone()), Conditional::None(reason)),
@@ -157,7 +261,8 @@ where
                     ok
                 })
             }),
-        );
+        )
+        .with_require_sni(options.require_sni);
         let server = Server::Init { listen, accept };
 
         (server, listen_addr, receiver)
@@ -207,7 +312,7 @@ where
     // XXX: This assumes that only one connection is accepted. TODO: allow the
     // caller to observe the results for every connection, once we have tests
     // that allow accepting multiple connections.
-    let server_result = server_result.try_recv().expect("server complete");
+    let server_result = server_result.try_recv().ok();
 
     (client_result, server_result)
 }
@@ -264,7 +369,10 @@ where
 struct Target(SocketAddr, Conditional<Name>);
 
 #[derive(Clone)]
-struct ClientTls(CrtKey);
+struct ClientTls {
+    crtkey: CrtKey,
+    disable_sni: bool,
+}
 
 impl<A: Accept<ServerConnection> + Clone> Future for Server<A> {
     type Item = ();
@@ -289,10 +397,11 @@ impl<A: Accept<ServerConnection> + Clone> Future for Server<A> {
                     };
                     Server::Serving(accept.accept(conn))
                 }
-                Server::Serving(ref mut fut) => match fut.poll() {
-                    Ok(ready) => return Ok(ready),
-                    Err(_) => panic!("connection failed"),
-                },
+                // A rejected connection has no result, so the server just
+                // completes and leaves the client to fail.
+                Server::Serving(ref mut fut) => {
+                    return Ok(fut.poll().unwrap_or(Async::Ready(())));
+                }
             }
         }
     }
@@ -312,6 +421,13 @@ impl tls::HasPeerIdentity for Target {
 
 impl tls::client::HasConfig for ClientTls {
     fn tls_client_config(&self) -> std::sync::Arc<tls::client::Config> {
-        self.0.tls_client_config()
+        let config = self.crtkey.tls_client_config();
+        if !self.disable_sni {
+            return config;
+        }
+
+        let mut config = (*config).clone();
+        config.enable_sni = false;
+        std::sync::Arc::new(config)
     }
 }
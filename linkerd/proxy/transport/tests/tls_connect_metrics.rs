@@ -0,0 +1,199 @@
+#![cfg(test)]
+
+use futures::{Future, Stream};
+use linkerd2_error::Error;
+use linkerd2_identity::{test_util, Name};
+use linkerd2_metrics::{FmtLabels, FmtMetrics};
+use linkerd2_proxy_transport::connect;
+use linkerd2_proxy_transport::metrics::{self, TransportLabels};
+use linkerd2_proxy_transport::tls::{self, Conditional};
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+use tokio::timer::{Delay, Timeout};
+use tokio::{io, prelude::*};
+use tower::{layer::Layer, Service, ServiceExt};
+
+const HANDSHAKE_DELAY: Duration = Duration::from_millis(300);
+
+#[test]
+fn handshake_delay_is_not_attributed_to_connect() {
+    let metrics = run_test(
+        test_util::FOO_NS1.crt().name().clone(),
+        |tcp| delay_then_accept_tls(tcp, HANDSHAKE_DELAY),
+        None,
+    )
+    .expect("connection must succeed");
+
+    // The server starts its delay once it accepts the connection, which may
+    // be slightly after the client considers itself connected.
+    let delay = HANDSHAKE_DELAY.as_millis() as u64;
+    assert_eq!(metric(&metrics, "tcp_connect_duration_ms_count"), Some(1));
+    assert!(metric(&metrics, "tcp_connect_duration_ms_sum").unwrap() < delay / 2);
+    assert_eq!(metric(&metrics, "tls_handshake_duration_ms_count"), Some(1));
+    assert!(metric(&metrics, "tls_handshake_duration_ms_sum").unwrap() >= delay / 2);
+    assert!(!metrics.contains("tls_handshake_errors_total{"));
+}
+
+#[test]
+fn handshake_timeouts_are_counted() {
+    let metrics = run_test(
+        test_util::FOO_NS1.crt().name().clone(),
+        |tcp| delay_then_accept_tls(tcp, Duration::from_secs(10)),
+        Some(Duration::from_millis(100)),
+    )
+    .expect_err("connection must time out");
+
+    assert_eq!(metric(&metrics, "tcp_connect_duration_ms_count"), Some(1));
+    assert_eq!(metric(&metrics, "tls_handshake_duration_ms_count"), Some(0));
+    assert_eq!(handshake_errors(&metrics, "timeout"), Some(1));
+}
+
+#[test]
+fn certificate_verification_failures_are_counted() {
+    // The server's certificate isn't valid for the name that the client
+    // expects.
+    let metrics = run_test(
+        test_util::BAR_NS1.crt().name().clone(),
+        |tcp| delay_then_accept_tls(tcp, Duration::from_secs(0)),
+        None,
+    )
+    .expect_err("connection must fail");
+
+    assert_eq!(metric(&metrics, "tls_handshake_duration_ms_count"), Some(0));
+    assert_eq!(handshake_errors(&metrics, "cert_verification"), Some(1));
+}
+
+#[test]
+fn protocol_failures_are_counted() {
+    // The server doesn't speak TLS. It holds the connection open until the
+    // client closes it, so that the client reads the response rather than a
+    // reset.
+    let metrics = run_test(
+        test_util::FOO_NS1.crt().name().clone(),
+        |tcp| {
+            io::write_all(tcp, &b"HTTP/1.1 400 Bad Request\r\n\r\n"[..])
+                .and_then(|(tcp, _)| io::read_to_end(tcp, Vec::new()))
+                .map(|_| ())
+        },
+        None,
+    )
+    .expect_err("connection must fail");
+
+    assert_eq!(metric(&metrics, "tls_handshake_duration_ms_count"), Some(0));
+    assert_eq!(handshake_errors(&metrics, "protocol"), Some(1));
+}
+
+#[derive(Clone)]
+struct Target(SocketAddr, Name);
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Labels;
+
+/// Accepts a single connection with `serve` and connects to it over mesh TLS,
+/// expecting the server to be identified by `server_name`. Returns the
+/// rendered transport metrics.
+fn run_test<S, F>(server_name: Name, serve: S, timeout: Option<Duration>) -> Result<String, String>
+where
+    S: FnOnce(TcpStream) -> F + Send + 'static,
+    F: Future<Item = (), Error = io::Error> + Send + 'static,
+{
+    let mut rt = Runtime::new().expect("runtime");
+    let (registry, report) = metrics::new::<Labels>();
+
+    let addr = "127.0.0.1:0".parse::<SocketAddr>().unwrap();
+    let listener = TcpListener::bind(&addr).expect("must bind");
+    let server_addr = listener.local_addr().expect("must have an address");
+    let server = listener
+        .incoming()
+        .into_future()
+        .map_err(|(e, _)| e)
+        .and_then(move |(tcp, _)| serve(tcp.expect("must accept")))
+        .then(|_| Ok::<(), ()>(()));
+    rt.spawn(server);
+
+    let client_tls = test_util::BAR_NS1.validate().expect("valid client cert");
+    let connect = tls::client::layer(Conditional::Some(client_tls))
+        .with_observe(registry.observe_connect(Labels))
+        .layer(connect::svc(None))
+        .ready()
+        .and_then(move |mut svc| svc.call(Target(server_addr, server_name)))
+        .and_then(|conn| io::shutdown(conn))
+        .map(|_| ())
+        .map_err(Error::from);
+    let result = match timeout {
+        Some(timeout) => {
+            rt.block_on(Timeout::new(connect, timeout).map_err(|_| Error::from("timeout")))
+        }
+        None => rt.block_on(connect),
+    };
+
+    let metrics = report.as_display().to_string();
+    match result {
+        Ok(()) => Ok(metrics),
+        Err(_) => Err(metrics),
+    }
+}
+
+/// Waits for `delay` before accepting TLS on `tcp`.
+fn delay_then_accept_tls(
+    tcp: TcpStream,
+    delay: Duration,
+) -> impl Future<Item = (), Error = io::Error> {
+    let server_tls = test_util::FOO_NS1.validate().expect("valid server cert");
+    let acceptor = tokio_rustls::TlsAcceptor::from(server_tls.tls_server_config());
+    Delay::new(Instant::now() + delay)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+        .and_then(move |()| acceptor.accept(tcp))
+        .and_then(|conn| io::read_to_end(conn, Vec::new()))
+        .map(|_| ())
+}
+
+/// Returns the value of the sample named `name`.
+fn metric(metrics: &str, name: &str) -> Option<u64> {
+    let prefix = format!("{}{{test=\"1\"}} ", name);
+    metrics
+        .lines()
+        .find(|l| l.starts_with(&prefix))
+        .and_then(|l| l[prefix.len()..].parse().ok())
+}
+
+/// Returns the number of handshakes that failed with `error`.
+fn handshake_errors(metrics: &str, error: &str) -> Option<u64> {
+    let prefix = format!(
+        "tls_handshake_errors_total{{test=\"1\",error=\"{}\"}} ",
+        error
+    );
+    metrics
+        .lines()
+        .find(|l| l.starts_with(&prefix))
+        .and_then(|l| l[prefix.len()..].parse().ok())
+}
+
+impl FmtLabels for Labels {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("test=\"1\"")
+    }
+}
+
+impl TransportLabels<Target> for Labels {
+    type Labels = Labels;
+
+    fn transport_labels(&self, _: &Target) -> Self::Labels {
+        Labels
+    }
+}
+
+impl connect::HasPeerAddr for Target {
+    fn peer_addr(&self) -> SocketAddr {
+        self.0
+    }
+}
+
+impl tls::HasPeerIdentity for Target {
+    fn peer_identity(&self) -> tls::PeerIdentity {
+        Conditional::Some(self.1.clone())
+    }
+}
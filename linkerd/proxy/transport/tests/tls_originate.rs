@@ -0,0 +1,99 @@
+#![cfg(test)]
+
+use futures::{Future, Stream};
+use linkerd2_dns_name::{Name, Suffix};
+use linkerd2_error::Error;
+use linkerd2_identity::{test_util, CrtKey};
+use linkerd2_proxy_transport::connect;
+use linkerd2_proxy_transport::tls::{self, originate, Conditional};
+use std::convert::TryFrom;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+use tokio::{io, prelude::*};
+use tower::{layer::Layer, Service, ServiceExt};
+
+#[test]
+fn originates_tls_to_servers_with_trusted_certificates() {
+    let origination = origination(test_util::FOO_NS1.trust_anchors);
+    let received = run_test(origination).expect("connection must succeed");
+    assert_eq!(&received[..], PONG);
+}
+
+#[test]
+fn fails_to_originate_tls_to_servers_with_untrusted_certificates() {
+    let origination = origination(include_bytes!("../../../identity/src/testdata/ca2.pem"));
+    let error = run_test(origination).expect_err("connection must fail");
+    let failed = error
+        .downcast_ref::<originate::HandshakeFailed>()
+        .expect("error must be HandshakeFailed");
+    assert_eq!(failed.server_name().as_ref(), test_util::FOO_NS1.name);
+}
+
+const PONG: &[u8] = b"pong";
+
+#[derive(Clone)]
+struct Target(SocketAddr, originate::Origination);
+
+/// Returns an origination to the test server whose certificate is verified
+/// against `roots_pem`.
+fn origination(roots_pem: &[u8]) -> originate::Origination {
+    let rule = originate::Rule::new(Suffix::Root, roots_pem, None).expect("roots must be valid");
+    let name = Name::try_from(test_util::FOO_NS1.name.as_bytes()).expect("name must be valid");
+    originate::Config::new(vec![rule])
+        .origination(&name)
+        .expect("root suffix must match")
+}
+
+/// Serves `PONG` over TLS to a single connection and returns what a client
+/// originating TLS to it reads.
+fn run_test(origination: originate::Origination) -> Result<Vec<u8>, Error> {
+    let mut rt = Runtime::new().expect("runtime");
+
+    let addr = "127.0.0.1:0".parse::<SocketAddr>().unwrap();
+    let listener = TcpListener::bind(&addr).expect("must bind");
+    let server_addr = listener.local_addr().expect("must have an address");
+    let server_tls = test_util::FOO_NS1.validate().expect("valid server cert");
+    let acceptor = tokio_rustls::TlsAcceptor::from(server_tls.tls_server_config());
+    let server = listener
+        .incoming()
+        .into_future()
+        .map_err(|(e, _)| e)
+        .and_then(move |(tcp, _)| acceptor.accept(tcp.expect("must accept")))
+        .and_then(|conn| io::write_all(conn, PONG))
+        .and_then(|(conn, _)| io::shutdown(conn))
+        // The handshake fails when the client rejects the certificate.
+        .then(|_| Ok::<(), ()>(()));
+    rt.spawn(server);
+
+    let no_identity: tls::Conditional<CrtKey> =
+        Conditional::None(tls::ReasonForNoIdentity::Disabled);
+    let client = originate::layer()
+        .layer(tls::client::layer(no_identity).layer(connect::svc(None)))
+        .ready()
+        .and_then(move |mut svc| svc.call(Target(server_addr, origination)))
+        .and_then(|conn| {
+            io::read_to_end(conn, Vec::new())
+                .map(|(_, received)| received)
+                .map_err(Error::from)
+        });
+    rt.block_on(client)
+}
+
+impl connect::HasPeerAddr for Target {
+    fn peer_addr(&self) -> SocketAddr {
+        self.0
+    }
+}
+
+impl tls::HasPeerIdentity for Target {
+    fn peer_identity(&self) -> tls::PeerIdentity {
+        Conditional::None(tls::ReasonForNoPeerName::NotProvidedByServiceDiscovery.into())
+    }
+}
+
+impl originate::HasOrigination for Target {
+    fn tls_origination(&self) -> Option<originate::Origination> {
+        Some(self.1.clone())
+    }
+}
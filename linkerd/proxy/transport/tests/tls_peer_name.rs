@@ -0,0 +1,121 @@
+#![cfg(test)]
+
+use futures::{Future, Stream};
+use linkerd2_dns_name::Suffix;
+use linkerd2_error::Error;
+use linkerd2_identity::{test_util, Name};
+use linkerd2_proxy_transport::connect;
+use linkerd2_proxy_transport::tls::{self, peer_name, Conditional};
+use std::convert::TryFrom;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+use tokio::{io, prelude::*};
+use tower::{layer::Layer, Service, ServiceExt};
+
+#[test]
+fn uri_sans_are_verified_when_mapped() {
+    let peer_name = map_identity("spiffe://cluster.local/ns/{2}/sa/{1}");
+    assert!(peer_name.uri().is_some());
+    let received = run_test(peer_name.name().clone(), peer_name.uri().cloned())
+        .expect("connection must succeed");
+    assert_eq!(&received[..], PONG);
+}
+
+#[test]
+fn uri_sans_are_not_verified_as_dns_names() {
+    // Without a mapping, the certificate must be valid for the identity as a
+    // DNS name, which it isn't.
+    let name = Name::from_hostname(test_util::FOO_NS1.name.as_bytes()).unwrap();
+    run_test(name, None).expect_err("connection must fail");
+}
+
+#[test]
+fn unexpected_uri_sans_are_rejected() {
+    let peer_name = map_identity("spiffe://cluster.local/ns/{2}/sa/bar");
+    run_test(peer_name.name().clone(), peer_name.uri().cloned()).expect_err("connection must fail");
+}
+
+const PONG: &[u8] = b"pong";
+
+/// The server's certificate presents `spiffe://cluster.local/ns/ns1/sa/foo`
+/// and no DNS names.
+const SPIFFE_CRT: &[u8] =
+    include_bytes!("../../../identity/src/testdata/foo-ns1-ca1-spiffe/crt.der");
+const SPIFFE_KEY: &[u8] =
+    include_bytes!("../../../identity/src/testdata/foo-ns1-ca1-spiffe/key.p8");
+
+#[derive(Clone)]
+struct Target(SocketAddr, Name, Option<peer_name::UriSan>);
+
+/// Maps the test server's identity to a URI SAN rendered from `template`.
+fn map_identity(template: &str) -> peer_name::PeerName {
+    let template = peer_name::Template::parse(template).expect("template must be valid");
+    let rule = peer_name::Rule::new(
+        peer_name::Match::Identity(Suffix::try_from("cluster.local").unwrap()),
+        peer_name::Form::Uri(template),
+    );
+    let id = Name::from_hostname(test_util::FOO_NS1.name.as_bytes()).unwrap();
+    peer_name::Config::new(vec![rule])
+        .peer_name(None, Some(&id))
+        .expect("identity must match")
+}
+
+/// Serves `PONG` over TLS, with the SPIFFE certificate, to a single
+/// connection and returns what a mesh TLS client, connecting to `name` and
+/// expecting `uri_san`, reads from it.
+fn run_test(name: Name, uri_san: Option<peer_name::UriSan>) -> Result<Vec<u8>, Error> {
+    let mut rt = Runtime::new().expect("runtime");
+
+    let addr = "127.0.0.1:0".parse::<SocketAddr>().unwrap();
+    let listener = TcpListener::bind(&addr).expect("must bind");
+    let server_addr = listener.local_addr().expect("must have an address");
+    let mut server_tls = rustls::ServerConfig::new(rustls::NoClientAuth::new());
+    server_tls
+        .set_single_cert(
+            vec![rustls::Certificate(SPIFFE_CRT.to_vec())],
+            rustls::PrivateKey(SPIFFE_KEY.to_vec()),
+        )
+        .expect("valid server cert");
+    let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(server_tls));
+    let server = listener
+        .incoming()
+        .into_future()
+        .map_err(|(e, _)| e)
+        .and_then(move |(tcp, _)| acceptor.accept(tcp.expect("must accept")))
+        .and_then(|conn| io::write_all(conn, PONG))
+        .and_then(|(conn, _)| io::shutdown(conn))
+        // The handshake fails when the client rejects the certificate.
+        .then(|_| Ok::<(), ()>(()));
+    rt.spawn(server);
+
+    let client_tls = test_util::BAR_NS1.validate().expect("valid client cert");
+    let client = tls::client::layer(Conditional::Some(client_tls))
+        .layer(connect::svc(None))
+        .ready()
+        .and_then(move |mut svc| svc.call(Target(server_addr, name, uri_san)))
+        .map_err(Error::from)
+        .and_then(|conn| {
+            io::read_to_end(conn, Vec::new())
+                .map(|(_, received)| received)
+                .map_err(Error::from)
+        });
+    rt.block_on(client)
+}
+
+impl connect::HasPeerAddr for Target {
+    fn peer_addr(&self) -> SocketAddr {
+        self.0
+    }
+}
+
+impl tls::HasPeerIdentity for Target {
+    fn peer_identity(&self) -> tls::PeerIdentity {
+        Conditional::Some(self.1.clone())
+    }
+
+    fn peer_uri_san(&self) -> Option<peer_name::UriSan> {
+        self.2.clone()
+    }
+}
@@ -1,23 +1,53 @@
-use super::Service;
+use super::{Budget, Retire, Service};
 use futures::{future, Poll};
 use linkerd2_error::{Error, Never, Recover};
+use std::time::Duration;
 
 #[derive(Clone, Debug)]
 pub struct Layer<R: Recover> {
     recover: R,
+    max_age: Option<Duration>,
+    budget: Option<Budget>,
+    retire: Option<Retire>,
 }
 
 #[derive(Clone, Debug)]
 pub struct MakeService<R, M> {
     recover: R,
     make_service: M,
+    max_age: Option<Duration>,
+    budget: Option<Budget>,
+    retire: Option<Retire>,
 }
 
 // === impl Layer ===
 
 impl<R: Recover + Clone> From<R> for Layer<R> {
     fn from(recover: R) -> Self {
-        Self { recover }
+        Self {
+            recover,
+            max_age: None,
+            budget: None,
+            retire: None,
+        }
+    }
+}
+
+impl<R: Recover + Clone> Layer<R> {
+    /// Configures each service to be rebuilt once it is older than `max_age`.
+    pub fn with_max_age(self, max_age: Option<Duration>) -> Self {
+        Self { max_age, ..self }
+    }
+
+    /// Configures each service to be marked unavailable for a cooldown once
+    /// it fails to connect more than `budget` allows in a row.
+    pub fn with_budget(self, budget: Option<Budget>) -> Self {
+        Self { budget, ..self }
+    }
+
+    /// Configures each service to be rebuilt once `retire` retires it.
+    pub fn with_retire(self, retire: Option<Retire>) -> Self {
+        Self { retire, ..self }
     }
 }
 
@@ -31,6 +61,9 @@ where
         MakeService {
             make_service,
             recover: self.recover.clone(),
+            max_age: self.max_age,
+            budget: self.budget,
+            retire: self.retire.clone(),
         }
     }
 }
@@ -53,10 +86,11 @@ where
     }
 
     fn call(&mut self, target: T) -> Self::Future {
-        future::ok(Service::new(
-            target,
-            self.make_service.clone(),
-            self.recover.clone(),
-        ))
+        future::ok(
+            Service::new(target, self.make_service.clone(), self.recover.clone())
+                .with_max_age(self.max_age)
+                .with_budget(self.budget)
+                .with_retire(self.retire.clone()),
+        )
     }
 }
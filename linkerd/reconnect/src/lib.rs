@@ -4,10 +4,12 @@
 use linkerd2_error::Recover;
 
 mod layer;
+mod retire;
 mod service;
 
 pub use self::layer::Layer;
-pub use self::service::Service;
+pub use self::retire::Retire;
+pub use self::service::{Budget, Service};
 
 pub fn layer<R: Recover + Clone>(recover: R) -> Layer<R> {
     recover.into()
@@ -0,0 +1,40 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+/// Retires all of the services that have been built with it, e.g. because
+/// the credentials with which they connect have changed.
+///
+/// A retired service is rebuilt when it's next polled, just as if it had
+/// exceeded its max age, so requests that have already been dispatched to it
+/// are not interrupted.
+#[derive(Clone, Debug, Default)]
+pub struct Retire(Arc<Shared>);
+
+#[derive(Debug, Default)]
+struct Shared {
+    generation: AtomicUsize,
+    retired: AtomicUsize,
+}
+
+impl Retire {
+    /// Retires every service that has been built so far.
+    pub fn retire_all(&self) {
+        self.0.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Returns the number of services that have been rebuilt because they
+    /// were retired.
+    pub fn retired(&self) -> usize {
+        self.0.retired.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn generation(&self) -> usize {
+        self.0.generation.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn record_retired(&self) {
+        self.0.retired.fetch_add(1, Ordering::SeqCst);
+    }
+}
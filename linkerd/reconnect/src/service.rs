@@ -1,7 +1,22 @@
+use crate::Retire;
 use futures::{future, try_ready, Async, Future, Poll, Stream};
 use linkerd2_error::{Error, Recover};
+use std::time::{Duration, Instant};
+use tokio_timer::{clock, Delay};
 use tracing;
 
+/// Limits the number of times in a row that a service may fail to be built
+/// before it is marked unavailable.
+///
+/// An unavailable service doesn't become ready, and no attempts are made to
+/// build it, until `cooldown` has elapsed. Then, it is retried with a fresh
+/// budget.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Budget {
+    pub max_retries: usize,
+    pub cooldown: Duration,
+}
+
 pub struct Service<T, R, M>
 where
     R: Recover,
@@ -10,6 +25,11 @@ where
     target: T,
     recover: R,
     make_service: M,
+    max_age: Option<Duration>,
+    budget: Option<Budget>,
+    retire: Option<Retire>,
+    /// The number of consecutive failures to build the inner service.
+    failures: usize,
     state: State<M::Future, R::Backoff>,
 }
 
@@ -21,12 +41,18 @@ enum State<F: Future, B> {
         future: F,
         backoff: Option<B>,
     },
-    Service(F::Item),
+    Service {
+        service: F::Item,
+        expires_at: Option<Instant>,
+        /// The `Retire` generation in which the service was built.
+        generation: usize,
+    },
     Recover {
         error: Option<Error>,
         backoff: Option<B>,
     },
     Backoff(Option<B>),
+    Unavailable(Delay),
 }
 
 // === impl Service ===
@@ -43,9 +69,44 @@ where
             target,
             recover,
             make_service,
+            max_age: None,
+            budget: None,
+            retire: None,
+            failures: 0,
             state: State::Disconnected { backoff: None },
         }
     }
+
+    /// Discards the inner service once it has been in use for longer than
+    /// `max_age`, so that a new one is built when the service is next polled.
+    ///
+    /// Requests that have already been dispatched to the old service are not
+    /// interrupted.
+    pub fn with_max_age(mut self, max_age: Option<Duration>) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Marks the service unavailable once it has exhausted `budget`'s
+    /// retries, rather than retrying indefinitely.
+    pub fn with_budget(mut self, budget: Option<Budget>) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Discards the inner service once `retire` retires it, so that a new
+    /// one is built when the service is next polled.
+    ///
+    /// As when a service exceeds its max age, requests that have already
+    /// been dispatched to the old service are not interrupted.
+    pub fn with_retire(mut self, retire: Option<Retire>) -> Self {
+        self.retire = retire;
+        self
+    }
+
+    fn generation(&self) -> usize {
+        self.retire.as_ref().map(Retire::generation).unwrap_or(0)
+    }
 }
 
 impl<Req, T, R, M, S> tower::Service<Req> for Service<T, R, M>
@@ -62,6 +123,27 @@ where
     type Future = future::MapErr<S::Future, fn(S::Error) -> Error>;
 
     fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        // An expired service is only torn down once per call so that a
+        // freshly-built service is always used at least once, even when the
+        // max age is very short.
+        if let State::Service {
+            expires_at,
+            generation,
+            ..
+        } = self.state
+        {
+            if expires_at.map(|at| clock::now() >= at).unwrap_or(false) {
+                tracing::debug!("Service exceeded its max age; reconnecting");
+                self.state = State::Disconnected { backoff: None };
+            } else if let Some(ref retire) = self.retire {
+                if retire.generation() != generation {
+                    tracing::debug!("Service was retired; reconnecting");
+                    retire.record_retired();
+                    self.state = State::Disconnected { backoff: None };
+                }
+            }
+        }
+
         loop {
             self.state = match self.state {
                 State::Disconnected { ref mut backoff } => {
@@ -80,12 +162,20 @@ where
                     ref mut backoff,
                 } => match future.poll() {
                     Ok(Async::NotReady) => return Ok(Async::NotReady),
-                    Ok(Async::Ready(service)) => State::Service(service),
+                    Ok(Async::Ready(service)) => {
+                        self.failures = 0;
+                        State::Service {
+                            service,
+                            expires_at: self.max_age.map(|age| clock::now() + age),
+                            generation: self.generation(),
+                        }
+                    }
                     Err(e) => {
                         // If the service cannot be built, try to recover using
                         // the existing backoff.
                         let error: Error = e.into();
-                        tracing::debug!(message="Failed to connect", %error);
+                        self.failures += 1;
+                        tracing::debug!(message="Failed to connect", %error, failures = self.failures);
                         State::Recover {
                             error: Some(error),
                             backoff: backoff.take(),
@@ -93,7 +183,9 @@ where
                     }
                 },
 
-                State::Service(ref mut service) => match service.poll_ready() {
+                State::Service {
+                    ref mut service, ..
+                } => match service.poll_ready() {
                     Ok(ready) => return Ok(ready),
                     Err(e) => {
                         // If the service fails, try to recover.
@@ -114,8 +206,30 @@ where
                     // prefer the existing backoff to the new one.
                     let error = error.take().expect("error must be set");
                     let new_backoff = self.recover.recover(error)?;
-                    tracing::debug!("Recovering");
-                    State::Backoff(Some(backoff.take().unwrap_or(new_backoff)))
+
+                    // If the service has failed to be built too many times
+                    // in a row, stop retrying until the cooldown elapses.
+                    match self.budget {
+                        Some(budget) if self.failures > budget.max_retries => {
+                            tracing::warn!(
+                                failures = self.failures,
+                                cooldown = ?budget.cooldown,
+                                "Exhausted connect retry budget; marking unavailable"
+                            );
+                            self.failures = 0;
+                            State::Unavailable(Delay::new(clock::now() + budget.cooldown))
+                        }
+                        _ => {
+                            tracing::debug!("Recovering");
+                            State::Backoff(Some(backoff.take().unwrap_or(new_backoff)))
+                        }
+                    }
+                }
+
+                State::Unavailable(ref mut cooldown) => {
+                    try_ready!(cooldown.poll().map_err(Into::<Error>::into));
+                    tracing::debug!("Connect retry cooldown elapsed");
+                    State::Disconnected { backoff: None }
                 }
 
                 State::Backoff(ref mut backoff) => {
@@ -136,10 +250,199 @@ where
 
     #[inline]
     fn call(&mut self, request: Req) -> Self::Future {
-        if let State::Service(ref mut service) = self.state {
+        if let State::Service {
+            ref mut service, ..
+        } = self.state
+        {
             return service.call(request).map_err(Into::into);
         }
 
         unreachable!("Called before ready");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linkerd2_error::{recover::Immediately, Never};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    /// Builds services that respond with the order in which they were built.
+    #[derive(Clone, Default)]
+    struct MakeSvc(Arc<AtomicUsize>);
+
+    struct Svc(usize);
+
+    impl tower::Service<()> for MakeSvc {
+        type Response = Svc;
+        type Error = Never;
+        type Future = future::FutureResult<Svc, Never>;
+
+        fn poll_ready(&mut self) -> Poll<(), Never> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, (): ()) -> Self::Future {
+            future::ok(Svc(self.0.fetch_add(1, Ordering::SeqCst)))
+        }
+    }
+
+    impl tower::Service<()> for Svc {
+        type Response = usize;
+        type Error = Never;
+        type Future = future::FutureResult<usize, Never>;
+
+        fn poll_ready(&mut self) -> Poll<(), Never> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, (): ()) -> Self::Future {
+            future::ok(self.0)
+        }
+    }
+
+    /// Fails to build a service until `failures` attempts have been made.
+    #[derive(Clone)]
+    struct FailingMakeSvc {
+        attempts: Arc<AtomicUsize>,
+        failures: usize,
+    }
+
+    impl tower::Service<()> for FailingMakeSvc {
+        type Response = Svc;
+        type Error = Error;
+        type Future = future::FutureResult<Svc, Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, (): ()) -> Self::Future {
+            let attempt = self.attempts.fetch_add(1, Ordering::SeqCst);
+            if attempt < self.failures {
+                future::err("connection refused".into())
+            } else {
+                future::ok(Svc(attempt))
+            }
+        }
+    }
+
+    fn send(svc: &mut Service<(), Immediately, MakeSvc>) -> usize {
+        let ready = tower::Service::<()>::poll_ready(svc).expect("service must not fail");
+        assert!(ready.is_ready(), "service must be ready");
+        tower::Service::<()>::call(svc, ())
+            .wait()
+            .expect("request must not fail")
+    }
+
+    #[test]
+    fn reuses_service_younger_than_max_age() {
+        let make = MakeSvc::default();
+        let mut svc = Service::new((), make.clone(), Immediately::new())
+            .with_max_age(Some(Duration::from_secs(60 * 60)));
+
+        assert_eq!(send(&mut svc), 0);
+        assert_eq!(send(&mut svc), 0);
+        assert_eq!(make.0.load(Ordering::SeqCst), 1, "service must be reused");
+    }
+
+    #[test]
+    fn rebuilds_service_older_than_max_age() {
+        let make = MakeSvc::default();
+        let mut svc = Service::new((), make.clone(), Immediately::new())
+            .with_max_age(Some(Duration::from_secs(0)));
+
+        assert_eq!(send(&mut svc), 0);
+        assert_eq!(send(&mut svc), 1, "expired service must be rebuilt");
+        assert_eq!(send(&mut svc), 2, "expired service must be rebuilt");
+    }
+
+    #[test]
+    fn never_expires_without_max_age() {
+        let make = MakeSvc::default();
+        let mut svc = Service::new((), make.clone(), Immediately::new());
+
+        assert_eq!(send(&mut svc), 0);
+        assert_eq!(send(&mut svc), 0);
+        assert_eq!(make.0.load(Ordering::SeqCst), 1, "service must be reused");
+    }
+
+    #[test]
+    fn rebuilds_retired_service() {
+        let make = MakeSvc::default();
+        let retire = Retire::default();
+        let mut svc =
+            Service::new((), make.clone(), Immediately::new()).with_retire(Some(retire.clone()));
+
+        assert_eq!(send(&mut svc), 0);
+
+        // A request that was dispatched before the service was retired
+        // completes on the old service.
+        let ready = tower::Service::<()>::poll_ready(&mut svc).expect("must not fail");
+        assert!(ready.is_ready());
+        let in_flight = tower::Service::<()>::call(&mut svc, ());
+        retire.retire_all();
+        assert_eq!(in_flight.wait().expect("request must not fail"), 0);
+
+        assert_eq!(send(&mut svc), 1, "retired service must be rebuilt");
+        assert_eq!(send(&mut svc), 1, "rebuilt service must be reused");
+        assert_eq!(retire.retired(), 1);
+    }
+
+    #[test]
+    fn unavailable_after_exhausting_budget_until_cooldown() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let make = FailingMakeSvc {
+            attempts: attempts.clone(),
+            failures: 3,
+        };
+        let cooldown = Duration::from_millis(100);
+        let mut svc = Service::new((), make, Immediately::new()).with_budget(Some(Budget {
+            max_retries: 2,
+            cooldown,
+        }));
+
+        tokio::runtime::current_thread::Runtime::new()
+            .unwrap()
+            .block_on(future::lazy(move || {
+                // The initial attempt and both retries fail, so the service
+                // is marked unavailable.
+                let ready = tower::Service::<()>::poll_ready(&mut svc).expect("must not fail");
+                assert!(ready.is_not_ready(), "service must be unavailable");
+                assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+                // No attempts are made during the cooldown.
+                let ready = tower::Service::<()>::poll_ready(&mut svc).expect("must not fail");
+                assert!(ready.is_not_ready(), "service must be unavailable");
+                assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+                // Once the cooldown elapses, the service is rebuilt.
+                let start = clock::now();
+                future::poll_fn(move || tower::Service::<()>::poll_ready(&mut svc)).map(move |()| {
+                    assert!(clock::now() - start >= cooldown / 2);
+                    assert_eq!(attempts.load(Ordering::SeqCst), 4);
+                })
+            }))
+            .expect("service must recover");
+    }
+
+    #[test]
+    fn retries_within_budget() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let make = FailingMakeSvc {
+            attempts: attempts.clone(),
+            failures: 2,
+        };
+        let mut svc = Service::new((), make, Immediately::new()).with_budget(Some(Budget {
+            max_retries: 2,
+            cooldown: Duration::from_secs(60),
+        }));
+
+        let ready = tower::Service::<()>::poll_ready(&mut svc).expect("must not fail");
+        assert!(ready.is_ready(), "service must be ready");
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+}
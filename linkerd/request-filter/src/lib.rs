@@ -13,6 +13,9 @@ pub trait RequestFilter<T> {
     fn filter(&self, request: T) -> Result<T, Self::Error>;
 }
 
+#[derive(Clone, Debug)]
+pub struct Layer<I>(I);
+
 #[derive(Clone, Debug)]
 pub struct Service<I, S> {
     filter: I,
@@ -25,6 +28,23 @@ pub enum ResponseFuture<F> {
     Rejected(Option<Error>),
 }
 
+pub fn layer<I>(filter: I) -> Layer<I> {
+    Layer(filter)
+}
+
+// === impl Layer ===
+
+impl<I: Clone, S> tower::layer::Layer<S> for Layer<I> {
+    type Service = Service<I, S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        Service {
+            filter: self.0.clone(),
+            service,
+        }
+    }
+}
+
 // === impl Service ===
 
 impl<I, S> Service<I, S> {
@@ -0,0 +1,104 @@
+//! Compares the tail latency of routing requests through a router whose cache
+//! has a single shard with that of a router whose cache is sharded, while many
+//! tasks route requests for a set of targets concurrently.
+//!
+//! Run with `cargo bench -p linkerd2-router`.
+
+#![deny(warnings, rust_2018_idioms)]
+
+use futures::{future, sync::oneshot, Future};
+use linkerd2_error::Error;
+use linkerd2_router::Router;
+use std::time::{Duration, Instant};
+use tower::Service as _;
+
+const TASKS: usize = 256;
+
+const REQUESTS_PER_TASK: usize = 2_000;
+
+const TARGETS: usize = 64;
+
+const SHARDS: usize = 16;
+
+#[derive(Clone, Debug)]
+struct Svc;
+
+fn main() {
+    let unsharded = measure(1);
+    let sharded = measure(SHARDS);
+
+    for (shards, latencies) in &[(1, unsharded), (SHARDS, sharded)] {
+        println!(
+            "{:>2} shard(s): p50={:?} p99={:?} max={:?}",
+            shards,
+            percentile(latencies, 0.5),
+            percentile(latencies, 0.99),
+            latencies.last().expect("requests must be measured"),
+        );
+    }
+}
+
+/// Returns the sorted latencies of all requests routed through a router with
+/// `shards` cache shards.
+fn measure(shards: usize) -> Vec<Duration> {
+    let mut rt = tokio::runtime::Runtime::new().expect("runtime must start");
+    let (router, purge) = Router::new_sharded(
+        |target: &usize| Some(*target),
+        |_: &usize| Svc,
+        TARGETS,
+        Duration::from_secs(60),
+        shards,
+    );
+    rt.spawn(purge.map_err(|n| match n {}));
+
+    let tasks = (0..TASKS)
+        .map(|task| {
+            let mut router = router.clone();
+            let requests = future::loop_fn(
+                (0, Vec::with_capacity(REQUESTS_PER_TASK)),
+                move |(i, mut latencies)| {
+                    let start = Instant::now();
+                    router.call((task + i) % TARGETS).map(move |()| {
+                        latencies.push(start.elapsed());
+                        if i + 1 == REQUESTS_PER_TASK {
+                            future::Loop::Break(latencies)
+                        } else {
+                            future::Loop::Continue((i + 1, latencies))
+                        }
+                    })
+                },
+            );
+            oneshot::spawn(requests, &rt.executor())
+        })
+        .collect::<Vec<_>>();
+
+    let mut latencies = rt
+        .block_on(future::join_all(tasks))
+        .expect("requests must succeed")
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    latencies.sort();
+    latencies
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let idx = ((sorted.len() - 1) as f64 * p) as usize;
+    sorted[idx]
+}
+
+// === impl Svc ===
+
+impl tower::Service<usize> for Svc {
+    type Response = ();
+    type Error = Error;
+    type Future = future::FutureResult<(), Error>;
+
+    fn poll_ready(&mut self) -> futures::Poll<(), Self::Error> {
+        Ok(().into())
+    }
+
+    fn call(&mut self, _: usize) -> Self::Future {
+        future::ok(())
+    }
+}
@@ -1,5 +1,9 @@
 use futures::{task, Async, Stream};
 use indexmap::IndexMap;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
 use std::{hash::Hash, time::Duration};
 use tokio_timer::{delay_queue, DelayQueue};
 use tracing::trace;
@@ -36,10 +40,18 @@ where
     /// Cache access is coordinated through `values`. This field represents
     /// the current state of the cache.
     values: IndexMap<K, Node<V>>,
+    /// Counts the values held in this cache and in any other shards that share
+    /// its capacity, so that it may be read without acquiring a lock.
+    size: Size,
 
     purge_task: Option<task::Task>,
 }
 
+/// A read-only view of the number of values held in a `Cache` (or in all of
+/// the shards that share it).
+#[derive(Clone, Debug, Default)]
+pub struct Size(Arc<AtomicUsize>);
+
 /// A handle to a cache value.
 struct Node<T> {
     dq_key: delay_queue::Key,
@@ -54,12 +66,20 @@ where
     V: Clone,
 {
     pub fn new(capacity: usize, expires: Duration) -> Self {
+        Self::shard(capacity, expires, Size::default(), 1)
+    }
+
+    /// Creates one of `shards` caches that together hold at most `capacity`
+    /// values, all of which are counted by `size`.
+    pub fn shard(capacity: usize, expires: Duration, size: Size, shards: usize) -> Self {
         assert!(capacity != 0);
+        assert!(shards != 0);
         Self {
             capacity,
             expires,
-            expirations: DelayQueue::with_capacity(capacity),
+            expirations: DelayQueue::with_capacity((capacity + shards - 1) / shards),
             values: IndexMap::default(),
+            size,
             purge_task: None,
         }
     }
@@ -68,8 +88,13 @@ where
         self.capacity
     }
 
+    /// Returns a handle that observes the number of values in the cache.
+    pub fn size(&self) -> Size {
+        self.size.clone()
+    }
+
     pub fn can_insert(&self) -> bool {
-        self.values.len() < self.capacity
+        self.size.get() < self.capacity
     }
 
     /// Attempts to access an item by key.
@@ -102,7 +127,59 @@ where
             purge.notify();
         }
 
-        self.values.insert(key, node).map(|n| n.value)
+        let prior = self.values.insert(key, node).map(|n| n.value);
+        if prior.is_none() {
+            self.size.add(1);
+        }
+        prior
+    }
+
+    /// Inserts a value for a key that is not yet cached, if the cache (and any
+    /// shards that share its capacity) has room for it.
+    ///
+    /// The value is only built once capacity has been reserved for it, so
+    /// concurrent inserts into other shards cannot exceed the capacity.
+    pub fn try_insert<F>(&mut self, key: K, make: F) -> Option<V>
+    where
+        F: FnOnce(&K) -> V,
+    {
+        debug_assert!(!self.values.contains_key(&key));
+        if !self.size.try_add(1, self.capacity) {
+            return None;
+        }
+
+        let value = make(&key);
+        trace!("inserting an item into the cache");
+        let dq_key = self.expirations.insert(key.clone(), self.expires);
+        if let Some(purge) = self.purge_task.take() {
+            purge.notify();
+        }
+        let node = Node {
+            dq_key,
+            value: value.clone(),
+        };
+        self.values.insert(key, node);
+        Some(value)
+    }
+
+    /// Removes all values whose keys match `evict`, regardless of whether
+    /// they have expired.
+    pub fn evict_where<F>(&mut self, evict: F)
+    where
+        F: Fn(&K) -> bool,
+    {
+        let expirations = &mut self.expirations;
+        let len = self.values.len();
+        self.values.retain(|key, node| {
+            if evict(key) {
+                trace!("evicting an item from the cache");
+                expirations.remove(&node.dq_key);
+                false
+            } else {
+                true
+            }
+        });
+        self.size.sub(len - self.values.len());
     }
 
     /// Evict expired values from the cache.
@@ -120,13 +197,48 @@ where
                 }
                 Ok(Async::Ready(Some(key))) => {
                     trace!("expiring an item from the cache");
-                    self.values.remove(key.get_ref());
+                    if self.values.remove(key.get_ref()).is_some() {
+                        self.size.sub(1);
+                    }
                 }
             }
         }
     }
 }
 
+// ===== impl Size =====
+
+impl Size {
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Acquire)
+    }
+
+    fn add(&self, n: usize) {
+        self.0.fetch_add(n, Ordering::AcqRel);
+    }
+
+    /// Adds `n` unless doing so would exceed `max`.
+    fn try_add(&self, n: usize, max: usize) -> bool {
+        let mut size = self.get();
+        loop {
+            if size + n > max {
+                return false;
+            }
+            match self
+                .0
+                .compare_exchange_weak(size, size + n, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => return true,
+                Err(actual) => size = actual,
+            }
+        }
+    }
+
+    fn sub(&self, n: usize) {
+        self.0.fetch_sub(n, Ordering::AcqRel);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -174,6 +286,53 @@ mod tests {
         }))
     }
 
+    #[test]
+    fn evict_where() {
+        current_thread::run(future::lazy(|| {
+            let mut cache = Cache::new(3, Duration::from_secs(60));
+            let size = cache.size();
+
+            cache.insert(1, 2);
+            cache.insert(2, 3);
+            cache.insert(3, 4);
+            assert_eq!(size.get(), 3);
+
+            cache.evict_where(|k| k % 2 == 1);
+            assert_eq!(cache.values.len(), 1);
+            assert_eq!(size.get(), 1);
+            assert!(cache.access(&1).is_none());
+            assert!(cache.access(&2).is_some());
+            assert!(cache.access(&3).is_none());
+            assert!(cache.can_insert());
+
+            Ok::<_, ()>(())
+        }))
+    }
+
+    #[test]
+    fn shards_share_capacity() {
+        current_thread::run(future::lazy(|| {
+            let size = Size::default();
+            let mut a = Cache::shard(2, Duration::from_secs(60), size.clone(), 2);
+            let mut b = Cache::shard(2, Duration::from_secs(60), size.clone(), 2);
+
+            assert_eq!(a.try_insert(1, |k| k * 10), Some(10));
+            assert_eq!(b.try_insert(2, |k| k * 10), Some(20));
+            assert_eq!(size.get(), 2);
+            assert!(!a.can_insert());
+            assert!(b
+                .try_insert(3, |_| panic!("value must not be built without capacity"))
+                .is_none());
+
+            a.evict_where(|_| true);
+            assert_eq!(size.get(), 1);
+            assert_eq!(b.try_insert(3, |k| k * 10), Some(30));
+            assert_eq!(size.get(), 2);
+
+            Ok::<_, ()>(())
+        }))
+    }
+
     #[test]
     fn insert_and_background_purge() {
         let mut rt = Runtime::new().unwrap();
@@ -181,7 +340,7 @@ mod tests {
         let mut lock = Lock::new(Cache::new(2, Duration::from_millis(10)));
 
         // Spawn a background purge task on the runtime
-        let (purge, _handle) = Purge::new(lock.clone());
+        let (purge, _handle) = Purge::new(vec![lock.clone()]);
         rt.spawn(purge.map_err(|n| match n {}));
 
         // Fill the cache
@@ -217,7 +376,7 @@ mod tests {
         let mut lock = Lock::new(Cache::new(2, Duration::from_millis(100)));
 
         // Spawn a background purge task on the runtime
-        let (purge, _handle) = Purge::new(lock.clone());
+        let (purge, _handle) = Purge::new(vec![lock.clone()]);
         rt.spawn(purge.map_err(|n| match n {}));
 
         // Insert into the cache
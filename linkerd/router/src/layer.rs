@@ -1,4 +1,4 @@
-use crate::{Recognize, Router};
+use crate::{CacheSize, Evict, LockWait, Recognize, Router};
 use futures::{Future, Poll};
 use linkerd2_error::{Error, Never};
 use std::marker::PhantomData;
@@ -10,6 +10,7 @@ use tracing_futures::Instrument;
 pub struct Config {
     capacity: usize,
     max_idle_age: Duration,
+    shards: usize,
 }
 
 /// A layer that that builds a routing service.
@@ -48,8 +49,15 @@ impl Config {
         Self {
             capacity,
             max_idle_age,
+            shards: 1,
         }
     }
+
+    /// Splits the router's cache into `shards` independently locked shards,
+    /// rounded up to a power of two.
+    pub fn with_shards(self, shards: usize) -> Self {
+        Self { shards, ..self }
+    }
 }
 
 // === impl Layer ===
@@ -105,11 +113,12 @@ where
     <Mk::Value as tower::Service<Req>>::Error: Into<Error>,
 {
     pub fn spawn(&self) -> Service<Req, Rec, Mk> {
-        let (inner, purge) = Router::new(
+        let (inner, purge) = Router::new_sharded(
             self.recognize.clone(),
             self.inner.clone(),
             self.config.capacity,
             self.config.max_idle_age,
+            self.config.shards,
         );
         tokio::spawn(
             purge
@@ -157,6 +166,31 @@ where
 }
 // === impl Service ===
 
+impl<Req, Rec, Mk> Service<Req, Rec, Mk>
+where
+    Rec: Recognize<Req>,
+    Mk: super::Make<Rec::Target>,
+    Mk::Value: tower::Service<Req>,
+{
+    /// Returns a handle that can be used to eagerly remove routes from the
+    /// router's cache.
+    pub fn evict_handle(&self) -> Evict<Rec::Target> {
+        self.inner.evict_handle()
+    }
+
+    /// Returns a handle that observes the number of routes in the router's
+    /// cache.
+    pub fn cache_size(&self) -> CacheSize {
+        self.inner.cache_size()
+    }
+
+    /// Returns a handle that observes the time requests spend waiting to
+    /// acquire the router's cache lock.
+    pub fn lock_wait(&self) -> LockWait {
+        self.inner.lock_wait()
+    }
+}
+
 impl<Req, Rec, Mk> tower::Service<Req> for Service<Req, Rec, Mk>
 where
     Rec: Recognize<Req> + Send + Sync + 'static,
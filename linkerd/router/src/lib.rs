@@ -3,16 +3,23 @@
 mod cache;
 pub mod error;
 pub mod layer;
+mod lock_wait;
 mod purge;
 
 use self::cache::Cache;
+pub use self::cache::Size as CacheSize;
 pub use self::layer::{Config, Layer};
-pub use self::purge::Purge;
+pub use self::lock_wait::LockWait;
+pub use self::purge::{Evict, Purge};
 use futures::{Async, Future, Poll};
 use indexmap::IndexMap;
-use std::hash::Hash;
-use std::time::Duration;
+use linkerd2_error::contract;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::lock::Lock;
+use tokio_timer::clock;
 pub use tower_load_shed::LoadShed;
 use tracing::{debug, trace};
 
@@ -24,6 +31,8 @@ where
     Mk::Value: tower::Service<Req>,
 {
     inner: Inner<Req, Rec, Mk>,
+    evict: Evict<Rec::Target>,
+    size: CacheSize,
     _hangup: purge::Handle,
 }
 
@@ -80,7 +89,10 @@ where
 {
     recognize: Rec,
     make: Mk,
-    cache: Lock<Cache<Rec::Target, LoadShed<Mk::Value>>>,
+    /// The cache is split into a power-of-two number of shards, each with its
+    /// own lock, so that requests for different targets contend less.
+    shards: Arc<Vec<Lock<Cache<Rec::Target, LoadShed<Mk::Value>>>>>,
+    lock_wait: LockWait,
 }
 
 enum State<Req, Rec, Mk>
@@ -95,6 +107,9 @@ where
         target: Option<Rec::Target>,
         make: Option<Mk>,
         cache: Lock<Cache<Rec::Target, LoadShed<Mk::Value>>>,
+        lock_wait: LockWait,
+        /// Set when the cache's lock is first found to be held elsewhere.
+        waiting_since: Option<Instant>,
     },
     Call(Option<Req>, Option<LoadShed<Mk::Value>>),
     Respond(<LoadShed<Mk::Value> as tower::Service<Req>>::Future),
@@ -147,14 +162,36 @@ where
         capacity: usize,
         max_idle_age: Duration,
     ) -> (Self, Purge<Rec::Target, LoadShed<Mk::Value>>) {
-        let cache = Lock::new(Cache::new(capacity, max_idle_age));
-        let (purge, _hangup) = Purge::new(cache.clone());
+        Self::new_sharded(recognize, make, capacity, max_idle_age, 1)
+    }
+
+    /// Creates a router whose cache is split into `shards` independently
+    /// locked shards, rounded up to a power of two.
+    ///
+    /// Targets are assigned to shards by their hash. All shards share the
+    /// router's `capacity` and are purged by the same background task.
+    pub fn new_sharded(
+        recognize: Rec,
+        make: Mk,
+        capacity: usize,
+        max_idle_age: Duration,
+        shards: usize,
+    ) -> (Self, Purge<Rec::Target, LoadShed<Mk::Value>>) {
+        let shards = shards.max(1).next_power_of_two();
+        let size = CacheSize::default();
+        let caches = (0..shards)
+            .map(|_| Lock::new(Cache::shard(capacity, max_idle_age, size.clone(), shards)))
+            .collect::<Vec<_>>();
+        let (purge, _hangup) = Purge::new(caches.clone());
         let router = Self {
+            evict: purge.evict_handle(),
+            size,
             _hangup,
             inner: Inner {
                 recognize,
                 make,
-                cache,
+                shards: Arc::new(caches),
+                lock_wait: LockWait::default(),
             },
         };
 
@@ -162,6 +199,31 @@ where
     }
 }
 
+impl<Req, Rec, Mk> Router<Req, Rec, Mk>
+where
+    Rec: Recognize<Req>,
+    Mk: Make<Rec::Target>,
+    Mk::Value: tower::Service<Req>,
+{
+    /// Returns a handle that can be used to eagerly remove routes from the
+    /// router's cache.
+    pub fn evict_handle(&self) -> Evict<Rec::Target> {
+        self.evict.clone()
+    }
+
+    /// Returns a handle that observes the number of routes in the router's
+    /// cache.
+    pub fn cache_size(&self) -> CacheSize {
+        self.size.clone()
+    }
+
+    /// Returns a handle that observes the time requests spend waiting to
+    /// acquire the router's cache lock.
+    pub fn lock_wait(&self) -> LockWait {
+        self.inner.lock_wait.clone()
+    }
+}
+
 impl<Req, Rec, Svc> Router<Req, Rec, FixedMake<Rec::Target, Svc>>
 where
     Rec: Recognize<Req>,
@@ -219,11 +281,13 @@ where
             None => return ResponseFuture::not_recognized(),
         };
 
+        let cache = self.inner.shard(&target);
         ResponseFuture::new(
             request,
             target,
             self.inner.make.clone(),
-            self.inner.cache.clone(),
+            cache,
+            self.inner.lock_wait.clone(),
         )
     }
 }
@@ -237,6 +301,8 @@ where
     fn clone(&self) -> Self {
         Router {
             inner: self.inner.clone(),
+            evict: self.evict.clone(),
+            size: self.size.clone(),
             _hangup: self._hangup.clone(),
         }
     }
@@ -256,6 +322,7 @@ where
         target: Rec::Target,
         make: Mk,
         cache: Lock<Cache<Rec::Target, LoadShed<Mk::Value>>>,
+        lock_wait: LockWait,
     ) -> Self {
         ResponseFuture {
             state: State::Acquire {
@@ -263,6 +330,8 @@ where
                 target: Some(target),
                 make: Some(make),
                 cache: cache,
+                lock_wait,
+                waiting_since: None,
             },
         }
     }
@@ -290,6 +359,7 @@ where
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         use tower::Service;
+        const SITE: &str = "router::ResponseFuture::poll";
 
         loop {
             self.state = match self.state {
@@ -298,15 +368,25 @@ where
                     ref mut target,
                     ref mut make,
                     ref mut cache,
+                    ref lock_wait,
+                    ref mut waiting_since,
                 } => {
-                    // Aquire the lock for the router cache
+                    // Aquire the lock for the target's shard of the router cache
                     let mut cache = match cache.poll_lock() {
                         Async::Ready(aquired) => aquired,
-                        Async::NotReady => return Ok(Async::NotReady),
+                        Async::NotReady => {
+                            waiting_since.get_or_insert_with(clock::now);
+                            return Ok(Async::NotReady);
+                        }
                     };
+                    lock_wait.record(
+                        waiting_since
+                            .map(|t0| clock::now() - t0)
+                            .unwrap_or_default(),
+                    );
 
-                    let request = request.take().expect("polled after ready");
-                    let target = target.take().expect("polled after ready");
+                    let request = contract!(request.take(), SITE, "polled after ready")?;
+                    let target = contract!(target.take(), SITE, "polled after ready")?;
 
                     // If the target is already cached, route the request to
                     // the service; otherwise, try to insert it
@@ -316,34 +396,36 @@ where
                     } else {
                         debug!("target not cached");
 
-                        // Ensure that there is capacity for a new slot
-                        if !cache.can_insert() {
-                            debug!("not enough capacity to insert target into cache");
-                            return Err(error::NoCapacity(cache.capacity()).into());
+                        // Make a new service for the target, if there is
+                        // capacity for a new slot
+                        let make = contract!(make.take(), SITE, "polled after ready")?;
+                        match cache.try_insert(target, |t| LoadShed::new(make.make(t))) {
+                            Some(service) => {
+                                debug!("inserted new target into cache");
+                                State::Call(Some(request), Some(service))
+                            }
+                            None => {
+                                debug!("not enough capacity to insert target into cache");
+                                return Err(error::NoCapacity(cache.capacity()).into());
+                            }
                         }
-
-                        // Make a new service for the target
-                        let make = make.take().expect("polled after ready");
-                        let service = LoadShed::new(make.make(&target));
-
-                        debug!("inserting new target into cache");
-                        cache.insert(target, service.clone());
-                        State::Call(Some(request), Some(service))
                     }
                 }
                 State::Call(ref mut request, ref mut service) => {
-                    let mut service = service.take().expect("polled after ready");
-
-                    assert!(
+                    let mut service = contract!(service.take(), SITE, "polled after ready")?;
+                    contract!(
                         service.poll_ready()?.is_ready(),
+                        SITE,
                         "load shedding services must always be ready"
-                    );
+                    )?;
 
-                    let request = request.take().expect("polled after ready");
+                    let request = contract!(request.take(), SITE, "polled after ready")?;
                     State::Respond(service.call(request))
                 }
                 State::Respond(ref mut fut) => return fut.poll().map_err(Into::into),
-                State::Error(ref mut err) => return Err(err.take().expect("polled after ready")),
+                State::Error(ref mut err) => {
+                    return Err(contract!(err.take(), SITE, "polled after failure")?);
+                }
             }
         }
     }
@@ -351,6 +433,21 @@ where
 
 // ===== impl Inner =====
 
+impl<Req, Rec, Mk> Inner<Req, Rec, Mk>
+where
+    Rec: Recognize<Req>,
+    Mk: Make<Rec::Target>,
+    Mk::Value: tower::Service<Req>,
+{
+    fn shard(&self, target: &Rec::Target) -> Lock<Cache<Rec::Target, LoadShed<Mk::Value>>> {
+        let mut hasher = DefaultHasher::new();
+        target.hash(&mut hasher);
+        // The number of shards is a power of two.
+        let idx = hasher.finish() as usize & (self.shards.len() - 1);
+        self.shards[idx].clone()
+    }
+}
+
 impl<Req, Rec, Mk> Clone for Inner<Req, Rec, Mk>
 where
     Rec: Recognize<Req> + Clone,
@@ -361,7 +458,8 @@ where
         Inner {
             recognize: self.recognize.clone(),
             make: self.make.clone(),
-            cache: self.cache.clone(),
+            shards: self.shards.clone(),
+            lock_wait: self.lock_wait.clone(),
         }
     }
 }
@@ -561,6 +659,95 @@ mod tests {
         assert_eq!(rsp, 4);
     }
 
+    #[test]
+    fn evicted_services_rebuilt() {
+        use tokio::runtime::current_thread::Runtime;
+
+        let mut rt = Runtime::new().unwrap();
+        let (mut router, purge) = Router::new(Recognize, Recognize, 2, Duration::from_secs(60));
+        rt.spawn(purge.map_err(|n| match n {}));
+
+        assert_eq!(router.call_ok(2), 2);
+        assert_eq!(router.call_ok(2), 4);
+        assert_eq!(router.call_ok(3), 3);
+
+        router.evict_handle().evict(|n: &usize| *n == 2);
+        rt.block_on(tokio_timer::sleep(Duration::from_millis(10)))
+            .unwrap();
+
+        assert_eq!(router.call_ok(2), 2, "evicted service must be rebuilt");
+        assert_eq!(router.call_ok(3), 9, "other services must be retained");
+    }
+
+    #[test]
+    fn sharded_cache_limited_by_capacity() {
+        let (mut router, _cache_bg) =
+            Router::new_sharded(Recognize, Recognize, 2, Duration::from_secs(60), 4);
+        assert_eq!(router.inner.shards.len(), 4);
+
+        assert_eq!(router.call_ok(2), 2);
+        assert_eq!(router.call_ok(3), 3);
+        let err = router.call_err(4);
+        assert_eq!(
+            err.downcast_ref::<error::NoCapacity>()
+                .expect("error should be NoCapacity")
+                .0,
+            2
+        );
+        assert_eq!(router.cache_size().get(), 2);
+    }
+
+    #[test]
+    fn sharded_evictions_apply_to_all_shards() {
+        use tokio::runtime::current_thread::Runtime;
+
+        let mut rt = Runtime::new().unwrap();
+        let (mut router, purge) =
+            Router::new_sharded(Recognize, Recognize, 16, Duration::from_secs(60), 4);
+        rt.spawn(purge.map_err(|n| match n {}));
+
+        for n in 2..10 {
+            assert_eq!(router.call_ok(n), n);
+        }
+        assert_eq!(router.cache_size().get(), 8);
+
+        router.evict_handle().evict(|n: &usize| n % 2 == 0);
+        rt.block_on(tokio_timer::sleep(Duration::from_millis(10)))
+            .unwrap();
+        assert_eq!(router.cache_size().get(), 4);
+
+        for n in 2..10 {
+            if n % 2 == 0 {
+                assert_eq!(router.call_ok(n), n, "evicted service must be rebuilt");
+            } else {
+                assert_eq!(router.call_ok(n), n * n, "other services must be retained");
+            }
+        }
+    }
+
+    #[test]
+    fn sharded_idle_services_purged() {
+        use tokio::runtime::current_thread::Runtime;
+
+        let mut rt = Runtime::new().unwrap();
+        let (mut router, purge) =
+            Router::new_sharded(Recognize, Recognize, 16, Duration::from_millis(10), 4);
+        rt.spawn(purge.map_err(|n| match n {}));
+
+        rt.block_on(futures::future::lazy(|| {
+            for n in 2..10 {
+                assert_eq!(router.call_ok(n), n);
+            }
+            Ok::<_, ()>(())
+        }))
+        .unwrap();
+        assert_eq!(router.cache_size().get(), 8);
+
+        rt.block_on(tokio_timer::sleep(Duration::from_millis(100)))
+            .unwrap();
+        assert_eq!(router.cache_size().get(), 0, "idle services must be purged");
+    }
+
     #[test]
     fn poll_ready_is_called_first() {
         let (mut router, _cache_bg) = Router::new(
@@ -591,4 +778,52 @@ mod tests {
         let err = router.call_err(2);
         assert!(err.downcast_ref::<Overloaded>().is_some(), "Not overloaded",);
     }
+
+    #[test]
+    fn contended_cache_access_records_lock_wait() {
+        use futures::{future, Async};
+        use linkerd2_metrics::FmtMetric;
+        use std::fmt;
+        use tokio::runtime::current_thread::Runtime;
+
+        struct Report(super::LockWait);
+        impl fmt::Display for Report {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.fmt_metric(f, "lock_wait_us")
+            }
+        }
+
+        let mut rt = Runtime::new().unwrap();
+        let (mut router, _cache_bg) = Router::new(Recognize, Recognize, 1, Duration::from_secs(60));
+        let lock_wait = router.lock_wait();
+
+        rt.block_on(future::lazy(|| {
+            let mut cache = router.inner.shards[0].clone();
+            let held = match cache.poll_lock() {
+                Async::Ready(held) => held,
+                _ => panic!("cache lock should be Ready"),
+            };
+
+            let mut rsp = router.call(Request::from(2));
+            assert!(
+                rsp.poll().expect("call").is_not_ready(),
+                "call must wait for the cache lock"
+            );
+            std::thread::sleep(Duration::from_millis(1));
+            drop(held);
+            assert_eq!(rsp.poll().expect("call"), Async::Ready(2));
+
+            Ok::<_, ()>(())
+        }))
+        .unwrap();
+
+        let report = Report(lock_wait).to_string();
+        assert!(report.contains("lock_wait_us_count 1\n"), "{}", report);
+        let sum = report
+            .lines()
+            .find(|l| l.starts_with("lock_wait_us_sum "))
+            .and_then(|l| l["lock_wait_us_sum ".len()..].parse::<u64>().ok())
+            .expect("histogram must have a sum");
+        assert!(sum >= 1_000, "lock wait must be recorded: {}", report);
+    }
 }
@@ -0,0 +1,46 @@
+use linkerd2_metrics::{latency, FmtLabels, FmtMetric, Histogram};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A histogram of the time requests spend waiting to acquire a router's
+/// cache lock.
+#[derive(Clone, Debug, Default)]
+pub struct LockWait(Arc<Mutex<Histogram<latency::Us>>>);
+
+// ===== impl LockWait =====
+
+impl LockWait {
+    pub(crate) fn record(&self, wait: Duration) {
+        if let Ok(mut hist) = self.0.lock() {
+            hist.add(wait);
+        }
+    }
+}
+
+impl FmtMetric for LockWait {
+    const KIND: &'static str = <Histogram<latency::Us> as FmtMetric>::KIND;
+
+    fn fmt_metric<N: fmt::Display>(&self, f: &mut fmt::Formatter<'_>, name: N) -> fmt::Result {
+        if let Ok(hist) = self.0.lock() {
+            hist.fmt_metric(f, name)?;
+        }
+        Ok(())
+    }
+
+    fn fmt_metric_labeled<N, L>(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        name: N,
+        labels: L,
+    ) -> fmt::Result
+    where
+        N: fmt::Display,
+        L: FmtLabels,
+    {
+        if let Ok(hist) = self.0.lock() {
+            hist.fmt_metric_labeled(f, name, labels)?;
+        }
+        Ok(())
+    }
+}
@@ -2,15 +2,27 @@ use super::Cache;
 use futures::{Async, Future, Poll, Stream};
 use linkerd2_error::Never;
 use std::hash::Hash;
+use std::sync::Arc;
 use tokio::sync::lock::Lock;
 use tokio::sync::mpsc;
+use tracing::trace;
 
 /// A background future that eagerly removes expired cache values.
 ///
 /// If the cache is dropped, this future will complete.
 pub struct Purge<K: Clone + Eq + Hash, V> {
-    cache: Lock<Cache<K, V>>,
+    shards: Vec<Shard<K, V>>,
     hangup: mpsc::Receiver<Never>,
+    evictions: mpsc::UnboundedReceiver<EvictFn<K>>,
+    evict_tx: mpsc::UnboundedSender<EvictFn<K>>,
+}
+
+/// Each shard of the cache is locked (and so purged) independently.
+struct Shard<K: Clone + Eq + Hash, V> {
+    cache: Lock<Cache<K, V>>,
+    /// Evictions that have been requested but that have not yet been applied
+    /// because the shard's lock was unavailable.
+    pending: Vec<EvictFn<K>>,
 }
 
 /// Ensures that `Purge` runs until all handles are dropped.
@@ -18,15 +30,41 @@ pub struct Purge<K: Clone + Eq + Hash, V> {
 #[must_use = "handle must be held until purge should complete"]
 pub struct Handle(mpsc::Sender<Never>);
 
+/// Eagerly evicts values from a router's cache.
+///
+/// Evictions are applied asynchronously by the `Purge` task, so a value may
+/// still be accessed for a short time after it is evicted.
+pub struct Evict<K>(mpsc::UnboundedSender<EvictFn<K>>);
+
+type EvictFn<K> = Arc<dyn Fn(&K) -> bool + Send + Sync>;
+
 // ===== impl Purge =====
 
 impl<K, V> Purge<K, V>
 where
     K: Clone + Eq + Hash,
 {
-    pub(crate) fn new(cache: Lock<Cache<K, V>>) -> (Self, Handle) {
+    pub(crate) fn new(caches: Vec<Lock<Cache<K, V>>>) -> (Self, Handle) {
         let (tx, hangup) = mpsc::channel(1);
-        (Purge { cache, hangup }, Handle(tx))
+        let (evict_tx, evictions) = mpsc::unbounded_channel();
+        let shards = caches
+            .into_iter()
+            .map(|cache| Shard {
+                cache,
+                pending: Vec::new(),
+            })
+            .collect();
+        let purge = Purge {
+            shards,
+            hangup,
+            evictions,
+            evict_tx,
+        };
+        (purge, Handle(tx))
+    }
+
+    pub(crate) fn evict_handle(&self) -> Evict<K> {
+        Evict(self.evict_tx.clone())
     }
 }
 
@@ -46,19 +84,50 @@ where
             Err(_) => unreachable!("purge hangup handle must not error"),
         };
 
-        if let Async::Ready(mut cache) = self.cache.poll_lock() {
-            cache.purge();
+        // Evictions are buffered until each shard's lock is acquired.
+        while let Ok(Async::Ready(Some(evict))) = self.evictions.poll() {
+            for shard in &mut self.shards {
+                shard.pending.push(evict.clone());
+            }
+        }
+
+        for shard in &mut self.shards {
+            if let Async::Ready(mut cache) = shard.cache.poll_lock() {
+                for evict in shard.pending.drain(..) {
+                    cache.evict_where(|key| evict(key));
+                }
+                cache.purge();
+            }
         }
 
         Ok(Async::NotReady)
     }
 }
 
+// ===== impl Evict =====
+
+impl<K> Evict<K> {
+    /// Evicts all cached values with targets that match `evict`.
+    pub fn evict<F>(&self, evict: F)
+    where
+        F: Fn(&K) -> bool + Send + Sync + 'static,
+    {
+        if self.0.clone().try_send(Arc::new(evict)).is_err() {
+            trace!("purge task has completed; nothing to evict");
+        }
+    }
+}
+
+impl<K> Clone for Evict<K> {
+    fn clone(&self) -> Self {
+        Evict(self.0.clone())
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
     use futures::future;
-    use std::sync::Arc;
     use std::time::{Duration, Instant};
 
     const UNUSED: Duration = Duration::from_secs(12345);
@@ -66,7 +135,8 @@ pub mod tests {
     #[test]
     fn completes_on_handle_drop() {
         tokio::run(future::lazy(|| {
-            let (purge, purge_handle) = Purge::new(Lock::new(Cache::<usize, ()>::new(2, UNUSED)));
+            let (purge, purge_handle) =
+                Purge::new(vec![Lock::new(Cache::<usize, ()>::new(2, UNUSED))]);
 
             let polls = Arc::new(());
             let polls_handle = Arc::downgrade(&polls);
@@ -0,0 +1,116 @@
+use futures::{try_ready, Async, Future, Poll};
+use std::sync::Arc;
+
+/// A layer that annotates the span of every request it serves.
+///
+/// This is useful for recording that a request was handled by a particular
+/// stack, e.g. a fallback, when the decision to use that stack was made
+/// without access to the request.
+#[derive(Clone, Debug)]
+pub struct Layer {
+    annotation: Arc<Annotation>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Stack<M> {
+    inner: M,
+    annotation: Arc<Annotation>,
+}
+
+pub struct MakeFuture<F> {
+    inner: F,
+    annotation: Option<Arc<Annotation>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Service<S> {
+    inner: S,
+    annotation: Arc<Annotation>,
+}
+
+#[derive(Debug)]
+struct Annotation {
+    description: &'static str,
+    attributes: Vec<(&'static str, String)>,
+}
+
+pub fn layer(description: &'static str, attributes: Vec<(&'static str, String)>) -> Layer {
+    Layer {
+        annotation: Arc::new(Annotation {
+            description,
+            attributes,
+        }),
+    }
+}
+
+// === impl Layer ===
+
+impl<M> tower::layer::Layer<M> for Layer {
+    type Service = Stack<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        Stack {
+            inner,
+            annotation: self.annotation.clone(),
+        }
+    }
+}
+
+// === impl Stack ===
+
+impl<T, M> tower::Service<T> for Stack<M>
+where
+    M: tower::Service<T>,
+{
+    type Response = Service<M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), M::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        MakeFuture {
+            inner: self.inner.call(target),
+            annotation: Some(self.annotation.clone()),
+        }
+    }
+}
+
+// === impl MakeFuture ===
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = Service<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        let annotation = self.annotation.take().expect("polled after complete");
+        Ok(Async::Ready(Service { inner, annotation }))
+    }
+}
+
+// === impl Service ===
+
+impl<S, B> tower::Service<http::Request<B>> for Service<S>
+where
+    S: tower::Service<http::Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), S::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        super::annotate(
+            &request,
+            self.annotation.description,
+            &self.annotation.attributes,
+        );
+        self.inner.call(request)
+    }
+}
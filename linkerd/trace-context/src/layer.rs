@@ -1,11 +1,11 @@
-use super::{propagation, Span, SpanSink};
+use super::{propagation, Annotations, Span, SpanSink};
 use futures::{try_ready, Async, Future, Poll};
 use std::collections::HashMap;
 use std::time::SystemTime;
 use tracing::{trace, warn};
 
 pub struct ResponseFuture<F, S> {
-    trace: Option<(Span, S)>,
+    trace: Option<(Span, Annotations, S)>,
     inner: F,
 }
 
@@ -39,7 +39,9 @@ pub struct Service<Svc, S> {
 /// random span id setting it into the `traceparent` header before forwarding
 /// the request.  If the sampled bit of the header was set, we emit metadata
 /// about the span to the given SpanSink when the span is complete, i.e. when
-/// we receive the response.
+/// we receive the response. While a sampled span is in flight, an
+/// `Annotations` handle in the request's extensions collects annotations from
+/// inner layers, which may defer the span's emission past the response.
 pub fn layer<S>(sink: Option<S>) -> Layer<S> {
     Layer { sink }
 }
@@ -103,7 +105,7 @@ impl<F: Future, S> Future for MakeFuture<F, S> {
 impl<Svc, B1, B2, S> tower::Service<http::Request<B1>> for Service<Svc, S>
 where
     Svc: tower::Service<http::Request<B1>, Response = http::Response<B2>>,
-    S: SpanSink + Clone,
+    S: SpanSink + Clone + Send + 'static,
 {
     type Response = Svc::Response;
     type Error = Svc::Error;
@@ -125,7 +127,7 @@ where
         };
 
         let trace_context = propagation::unpack_trace_context(&request);
-        let mut span = None;
+        let mut trace = None;
 
         if let Some(context) = trace_context {
             trace!(message = "got trace context", ?context);
@@ -140,7 +142,9 @@ where
                     .map(|pq| pq.as_str().to_owned());
                 let mut labels = HashMap::new();
                 request_labels(&mut labels, &request);
-                span = Some(Span {
+                let annotations = Annotations::default();
+                request.extensions_mut().insert(annotations.clone());
+                let span = Span {
                     trace_id: context.trace_id,
                     span_id,
                     parent_id: context.parent_id,
@@ -149,16 +153,15 @@ where
                     // End time will be updated when the span completes.
                     end: SystemTime::UNIX_EPOCH,
                     labels,
-                });
+                    annotations: Vec::new(),
+                };
+                trace = Some((span, annotations, sink));
             }
         }
 
         let f = self.inner.call(request);
 
-        ResponseFuture {
-            trace: span.map(|span| (span, sink)),
-            inner: f,
-        }
+        ResponseFuture { trace, inner: f }
     }
 }
 
@@ -167,20 +170,25 @@ where
 impl<F, S, B2> Future for ResponseFuture<F, S>
 where
     F: Future<Item = http::Response<B2>>,
-    S: SpanSink,
+    S: SpanSink + Send + 'static,
 {
     type Item = F::Item;
     type Error = F::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         let inner = try_ready!(self.inner.poll());
-        if let Some((mut span, mut sink)) = self.trace.take() {
-            span.end = SystemTime::now();
+        if let Some((mut span, annotations, mut sink)) = self.trace.take() {
             response_labels(&mut span.labels, &inner);
-            trace!(message = "emitting span", ?span);
-            if let Err(error) = sink.try_send(span) {
-                warn!(message = "span dropped", %error);
-            }
+            // If an inner layer has deferred the span, it is emitted once
+            // that layer is done annotating it.
+            annotations.complete(Box::new(move |annotations| {
+                span.end = SystemTime::now();
+                span.annotations = annotations;
+                trace!(message = "emitting span", ?span);
+                if let Err(error) = sink.try_send(span) {
+                    warn!(message = "span dropped", %error);
+                }
+            }));
         }
         Ok(Async::Ready(inner))
     }
@@ -7,8 +7,10 @@ use rand::Rng;
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt;
+use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
 
+pub mod annotate;
 pub mod layer;
 mod propagation;
 
@@ -34,6 +36,36 @@ pub struct Span {
     pub start: SystemTime,
     pub end: SystemTime,
     pub labels: HashMap<String, String>,
+    pub annotations: Vec<Annotation>,
+}
+
+/// A time-stamped description of something that happened during a span.
+#[derive(Debug)]
+pub struct Annotation {
+    pub time: SystemTime,
+    pub description: String,
+    pub attributes: HashMap<String, String>,
+}
+
+/// A handle to the annotations of a span under construction.
+///
+/// When a sampled span is started, a handle is inserted into the request's
+/// extensions so that inner layers may describe the decisions they make on
+/// the request's behalf.
+#[derive(Clone, Debug, Default)]
+pub struct Annotations(Arc<Mutex<Pending>>);
+
+/// Delays a span's emission until it is dropped, so that the span may be
+/// annotated after its response has been returned (e.g. once the response
+/// body completes).
+#[derive(Debug)]
+pub struct Deferral(Annotations);
+
+#[derive(Default)]
+struct Pending {
+    annotations: Vec<Annotation>,
+    deferrals: usize,
+    emit: Option<Box<dyn FnOnce(Vec<Annotation>) + Send>>,
 }
 
 pub trait SpanSink {
@@ -50,6 +82,89 @@ where
     }
 }
 
+/// Annotates the span of `req`, if it is being sampled.
+pub fn annotate<B>(req: &http::Request<B>, description: &str, attributes: &[(&str, String)]) {
+    if let Some(annotations) = req.extensions().get::<Annotations>() {
+        annotations.annotate(description, attributes);
+    }
+}
+
+// === impl Annotations ===
+
+impl Annotations {
+    pub fn annotate(&self, description: &str, attributes: &[(&str, String)]) {
+        let annotation = Annotation {
+            time: SystemTime::now(),
+            description: description.to_owned(),
+            attributes: attributes
+                .iter()
+                .map(|(k, v)| ((*k).to_owned(), v.clone()))
+                .collect(),
+        };
+        if let Ok(mut pending) = self.0.lock() {
+            pending.annotations.push(annotation);
+        }
+    }
+
+    /// Defers the span's emission until the returned `Deferral` is dropped.
+    pub fn defer(&self) -> Deferral {
+        if let Ok(mut pending) = self.0.lock() {
+            pending.deferrals += 1;
+        }
+        Deferral(self.clone())
+    }
+
+    /// Emits the span's annotations with `emit`, once no deferrals remain.
+    fn complete(&self, emit: Box<dyn FnOnce(Vec<Annotation>) + Send>) {
+        let annotations = match self.0.lock() {
+            Ok(mut pending) => {
+                if pending.deferrals > 0 {
+                    pending.emit = Some(emit);
+                    return;
+                }
+                pending.annotations.drain(..).collect()
+            }
+            Err(_) => Vec::new(),
+        };
+        emit(annotations);
+    }
+}
+
+// === impl Deferral ===
+
+impl Drop for Deferral {
+    fn drop(&mut self) {
+        let ready = match (self.0).0.lock() {
+            Ok(mut pending) => {
+                pending.deferrals -= 1;
+                if pending.deferrals == 0 {
+                    pending
+                        .emit
+                        .take()
+                        .map(|emit| (emit, pending.annotations.drain(..).collect()))
+                } else {
+                    None
+                }
+            }
+            Err(_) => None,
+        };
+        // The span is emitted without holding the lock.
+        if let Some((emit, annotations)) = ready {
+            emit(annotations);
+        }
+    }
+}
+
+impl fmt::Debug for Pending {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pending")
+            .field("annotations", &self.annotations)
+            .field("deferrals", &self.deferrals)
+            .field("emit", &self.emit.is_some())
+            .finish()
+    }
+}
+
 // === impl Id ===
 
 impl Id {